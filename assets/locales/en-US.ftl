@@ -0,0 +1,282 @@
+## Main menu
+
+menu-title = Unnamed factory
+menu-new-game = New game
+menu-quit = Quit
+menu-back = Back
+menu-start = Start
+menu-seed = Seed
+menu-random-seed = Random
+menu-world-size = World size
+menu-terrain = Terrain
+menu-generating = Generating the world...
+world-size-small = Small
+world-size-medium = Medium
+world-size-large = Large
+world-preset-continent = Continent
+world-preset-archipelago = Archipelago
+world-preset-highlands = Highlands
+
+## Pause menu and settings
+
+pause-title = Paused
+pause-resume = Resume
+pause-settings = Settings
+pause-saves = Saves
+pause-export-gltf = Export to glTF
+pause-quit-to-menu = Quit to menu
+settings-title = Settings
+settings-language = Language
+settings-wireframe = Wireframe
+settings-taa = Anti-aliasing (TAA)
+settings-fog = Fog
+settings-toon = Stylized shading
+settings-wasd-panning = Pan with WASD
+settings-edge-pan = Pan at the edge of the screen
+settings-low-memory-terrain = Low memory terrain (next world)
+settings-palette = Color palette
+settings-hatch-patterns = Hatch patterns
+palette-default = Default
+palette-red-green = Red-green colorblind
+palette-blue-yellow = Blue-yellow colorblind
+settings-master-volume = Master volume
+settings-music-volume = Music volume
+settings-effects-volume = Effects volume
+settings-ui-volume = Interface volume
+settings-ambient-volume = Ambient volume
+settings-ui-scale = UI scale
+settings-on = On
+settings-off = Off
+settings-decrease = { "-" }
+settings-increase = { "+" }
+settings-back = Back
+controls-header = Controls : click a binding, then press a key or a mouse button
+controls-press-key = Press a key...
+controls-unbound = Unbound
+controls-conflicts = { $action } (conflicts with { $actions })
+action-camera-forward = Camera forward
+action-camera-back = Camera back
+action-camera-left = Camera left
+action-camera-right = Camera right
+action-orbit-camera = Orbit camera (hold)
+action-toggle-wireframe = Toggle wireframe
+action-toggle-bounding-boxes = Toggle bounding boxes
+action-cycle-snapping = Cycle snapping
+action-toggle-planning = Toggle planning mode
+action-pause-sim = Pause the sim
+action-speed-up = Speed up the sim
+action-slow-down = Slow down the sim
+action-reset-sim = Reset the sim
+action-toggle-sim-screen = Toggle sim screen
+action-quicksave = Quicksave
+action-quickload = Quickload
+action-toggle-script-stats = Toggle script stats
+action-save-replay = Save replay
+action-toggle-replay-viewer = Toggle replay viewer
+action-export-stats = Export stats
+action-toggle-controls = Toggle controls page
+action-pause-menu = Pause menu
+action-toggle-diagnostics = Toggle diagnostics
+action-place = Place or select a building
+action-build-list-up = Previous building of the list
+action-build-list-down = Next building of the list
+action-toggle-grid = Toggle the terrain grid
+action-toggle-contours = Toggle height contours
+action-cycle-heatmap = Cycle the heatmaps
+action-rotate-camera-left = Rotate the camera left
+action-rotate-camera-right = Rotate the camera right
+action-toggle-photo-mode = Toggle photo mode
+action-screenshot = Take a screenshot
+action-toggle-research = Toggle research screen
+action-toggle-road-tool = Toggle the road tool
+action-toggle-canal-tool = Toggle the canal tool
+action-toggle-console = Toggle the console
+action-commit-plan = Build the plan
+action-discard-plan = Discard the plan
+action-save-blueprint = Save the plan as a blueprint
+action-load-blueprint = Place the saved blueprint
+action-previous-bookmark = Previous replay bookmark
+action-next-bookmark = Next replay bookmark
+action-keep-placing = Keep the building selected after placing it (hold)
+action-fly-fast = Fly faster in photo mode (hold)
+action-hotbar-slot-1 = Hotbar slot 1
+action-hotbar-slot-2 = Hotbar slot 2
+action-hotbar-slot-3 = Hotbar slot 3
+action-hotbar-slot-4 = Hotbar slot 4
+action-hotbar-slot-5 = Hotbar slot 5
+action-hotbar-slot-6 = Hotbar slot 6
+action-hotbar-slot-7 = Hotbar slot 7
+action-hotbar-slot-8 = Hotbar slot 8
+action-hotbar-slot-9 = Hotbar slot 9
+action-hotbar-slot-10 = Hotbar slot 10
+
+## Saves
+
+saves-title = Saves
+saves-new = New save
+saves-load = Load
+saves-overwrite = Overwrite
+saves-delete = Delete
+saves-back = Back
+saves-empty = No saves yet
+saves-details = Seed { $seed }, played { $time }
+saves-confirm-overwrite = Overwrite the save of { $date } ?
+saves-confirm-delete = Delete the save of { $date } ? It can't be undone.
+saves-yes = Yes
+saves-no = No
+
+## Build menu
+
+build-search = Search...
+build-category-all = All
+build-list-item = Item { $name }
+build-list-locked = { $name } (needs { $tech })
+building-type-zone = Zone
+building-type-single = Building
+building-type-tool = Tool
+building-type-sign = Sign
+building-cost = Cost : { $cost }
+building-cost-free = Free
+# Default categories of the build menu, named after the category itself
+Zones = Zones
+Buildings = Buildings
+Tools = Tools
+Decoration = Decoration
+
+## Building context menu
+
+context-move = Move
+context-copy = Copy
+context-enable = Enable
+context-disable = Disable
+context-info = Info
+context-follow = Follow
+context-delete = Delete
+context-close = Close
+info-position = Position : { $position }
+info-id = Id : { $id }
+info-disabled = Disabled
+info-sign-text = Text of the sign, click to edit
+
+## Scenarios and scripts
+
+scenario-ticks-left = Ticks left : { $ticks }
+scenario-victory = Victory
+scenario-defeat = Defeat
+scenario-continue = Continue playing
+scenario-restart = Restart
+scenario-time-up = Time is up
+script-errors-retry = Retry after fix
+graph-hint = Click a stat to graph it
+graph-label = { $name } over the last { $ticks } ticks ({ $low } - { $high })
+console-stats-exported = Stats exported to { $path }
+console-export-failed = Export failed : { $error }
+
+## Notifications
+
+toast-game-saved = Game saved
+toast-game-loaded = Game loaded
+toast-save-deleted = Save deleted
+toast-area-occupied = Can't place a building here : the area is occupied
+toast-needs-coast = This building must be on the coast, near the mouth of a river
+toast-needs-river = This building must be across a river
+toast-needs-riverside = This building must be on land, by a river
+toast-spawn-area-occupied = Can't spawn a building at { $position } : the area is occupied
+toast-blueprint-missing = Some buildings of the blueprint are missing : { $buildings }
+toast-building-built = { $name } built
+toast-building-deleted = { $name } deleted
+toast-building-destroyed = { $name } was destroyed
+toast-building-enabled = { $name } enabled
+toast-building-disabled = { $name } disabled
+toast-following = Following { $name }, pan to stop
+toast-screenshot-saved = Screenshot saved to { $path }
+toast-objective-completed = Objective completed : { $objective }
+toast-script-error = Error in { $script } : { $message }
+toast-ship-docked = A ship traded at the harbor, balance : { $balance } credits
+toast-tech-unlocked = Researched { $tech }
+toast-tech-locked = Can't build this yet : { $tech } must be researched
+toast-road-tool-on = Road tool : click the ends of the road, bridges are built over the water and tunnels under the mountains
+toast-road-tool-off = Road tool off
+toast-canal-tool-on = Canal tool : click the ends of the canal, one of them in a river, a lake or another canal
+toast-canal-tool-off = Canal tool off
+rejection-unknown-building = Unknown building { $name }
+rejection-occupied = The area is occupied
+rejection-protected = The area is protected
+rejection-locked = { $tech } must be researched first
+rejection-already-researched = { $tech } is already researched
+rejection-unknown-tech = Unknown technology { $tech }
+rejection-road-too-long = The road is too long ({ $length } / { $max })
+rejection-road-in-water = Roads must start and end on land
+rejection-bridge-too-long = The bridge is too long ({ $length } / { $max })
+rejection-tunnel-too-long = The tunnel is too long ({ $length } / { $max })
+rejection-canal-too-long = The canal is too long ({ $length } / { $max })
+rejection-canal-not-connected = Canals must start or end in a river, a lake or another canal
+rejection-not-enough-resources = Not enough { $resource } ({ $available } / { $needed })
+rejection-invalid-terraform = The terrain can't be changed this way
+
+## Top bar
+
+top-bar-clock = Day { $day }, { $time }
+stat-resource-money = Credits
+stat-resource-food = Food
+stat-resource-material = Materials
+stat-aggregates-population = Population
+stat-stat-science = Science
+
+## Research
+
+research-title = Research
+research-rate = Labs : { $rate } points per tick
+research-done = Researched
+research-progress = Researching : { $progress }%
+research-cost = { $cost } points, click to research
+research-requires = Needs { $techs }
+research-unlocks = Unlocks { $buildings }
+
+## Heatmaps
+
+heatmap-none = Heatmap hidden
+heatmap-pollution = Heatmap : pollution
+heatmap-land-value = Heatmap : land value
+heatmap-power-coverage = Heatmap : power coverage
+
+## Photo mode
+
+photo-hint = F12 : screenshot, F11 : leave, camera keys and right mouse : fly
+photo-fov = Field of view
+photo-exposure = Exposure
+
+## Start location
+
+start-title = Where to start
+start-hint = The best spots are flat land by a river, with fertile lowland and the sea around
+start-best = Start at the best spot
+start-spot = { $rank } : { $flatness } % flat, { $water }
+start-water-both = by a river and the sea
+start-water-river = by a river
+start-water-coast = by the sea
+start-water-none = away from the water
+
+## Editor
+
+menu-editor = Editor
+editor-title = Editor
+editor-name = Name
+editor-raise = Raise
+editor-lower = Lower
+editor-flatten = Flatten
+editor-smooth = Smooth
+editor-erase = Erase deposits
+editor-radius = Radius { $radius }
+editor-starting-resources = Starting resources
+editor-objectives = Objectives
+editor-add-population = + Population
+editor-add-stock = + { $resource }
+editor-export = Export the scenario
+deposit-stone = Stone
+deposit-iron = Iron
+deposit-clay = Clay
+toast-scenario-exported = Scenario exported to { $path }
+toast-scenario-no-objectives = The scenario needs an objective
+toast-gltf-exported = Scene exported to { $path }
+toast-gltf-failed = Export failed : { $error }
@@ -0,0 +1,282 @@
+## Menu principal
+
+menu-title = Unnamed factory
+menu-new-game = Nouvelle partie
+menu-quit = Quitter
+menu-back = Retour
+menu-start = Commencer
+menu-seed = Graine
+menu-random-seed = Aléatoire
+menu-world-size = Taille du monde
+menu-terrain = Terrain
+menu-generating = Génération du monde...
+world-size-small = Petit
+world-size-medium = Moyen
+world-size-large = Grand
+world-preset-continent = Continent
+world-preset-archipelago = Archipel
+world-preset-highlands = Hautes terres
+
+## Menu pause et paramètres
+
+pause-title = Pause
+pause-resume = Reprendre
+pause-settings = Paramètres
+pause-saves = Sauvegardes
+pause-export-gltf = Exporter en glTF
+pause-quit-to-menu = Retour au menu
+settings-title = Paramètres
+settings-language = Langue
+settings-wireframe = Fil de fer
+settings-taa = Anticrénelage (TAA)
+settings-fog = Brouillard
+settings-toon = Rendu stylisé
+settings-wasd-panning = Déplacement avec ZQSD
+settings-edge-pan = Déplacement au bord de l'écran
+settings-low-memory-terrain = Terrain économe en mémoire (prochain monde)
+settings-palette = Palette de couleurs
+settings-hatch-patterns = Motifs hachurés
+palette-default = Par défaut
+palette-red-green = Daltonisme rouge-vert
+palette-blue-yellow = Daltonisme bleu-jaune
+settings-master-volume = Volume général
+settings-music-volume = Volume de la musique
+settings-effects-volume = Volume des effets
+settings-ui-volume = Volume de l'interface
+settings-ambient-volume = Volume de l'ambiance
+settings-ui-scale = Taille de l'interface
+settings-on = Oui
+settings-off = Non
+settings-decrease = { "-" }
+settings-increase = { "+" }
+settings-back = Retour
+controls-header = Contrôles : cliquez sur un raccourci, puis appuyez sur une touche ou un bouton de la souris
+controls-press-key = Appuyez sur une touche...
+controls-unbound = Aucun raccourci
+controls-conflicts = { $action } (en conflit avec { $actions })
+action-camera-forward = Caméra vers l'avant
+action-camera-back = Caméra vers l'arrière
+action-camera-left = Caméra à gauche
+action-camera-right = Caméra à droite
+action-orbit-camera = Tourner la caméra (maintenir)
+action-toggle-wireframe = Afficher le fil de fer
+action-toggle-bounding-boxes = Afficher les boîtes englobantes
+action-cycle-snapping = Changer l'aimantation
+action-toggle-planning = Mode planification
+action-pause-sim = Mettre la simulation en pause
+action-speed-up = Accélérer la simulation
+action-slow-down = Ralentir la simulation
+action-reset-sim = Réinitialiser la simulation
+action-toggle-sim-screen = Écran de la simulation
+action-quicksave = Sauvegarde rapide
+action-quickload = Chargement rapide
+action-toggle-script-stats = Statistiques des scripts
+action-save-replay = Enregistrer le replay
+action-toggle-replay-viewer = Lecteur de replay
+action-export-stats = Exporter les statistiques
+action-toggle-controls = Page des contrôles
+action-pause-menu = Menu pause
+action-toggle-diagnostics = Diagnostics
+action-place = Placer ou sélectionner un bâtiment
+action-build-list-up = Bâtiment précédent de la liste
+action-build-list-down = Bâtiment suivant de la liste
+action-toggle-grid = Grille du terrain
+action-toggle-contours = Courbes de niveau
+action-cycle-heatmap = Changer de carte de chaleur
+action-rotate-camera-left = Tourner la caméra à gauche
+action-rotate-camera-right = Tourner la caméra à droite
+action-toggle-photo-mode = Mode photo
+action-screenshot = Capture d'écran
+action-toggle-research = Écran de recherche
+action-toggle-road-tool = Outil route
+action-toggle-canal-tool = Outil canal
+action-toggle-console = Console
+action-commit-plan = Construire le plan
+action-discard-plan = Abandonner le plan
+action-save-blueprint = Enregistrer le plan comme modèle
+action-load-blueprint = Placer le modèle enregistré
+action-previous-bookmark = Signet précédent du replay
+action-next-bookmark = Signet suivant du replay
+action-keep-placing = Garder le bâtiment après l'avoir placé (maintenir)
+action-fly-fast = Voler plus vite en mode photo (maintenir)
+action-hotbar-slot-1 = Emplacement 1 de la barre rapide
+action-hotbar-slot-2 = Emplacement 2 de la barre rapide
+action-hotbar-slot-3 = Emplacement 3 de la barre rapide
+action-hotbar-slot-4 = Emplacement 4 de la barre rapide
+action-hotbar-slot-5 = Emplacement 5 de la barre rapide
+action-hotbar-slot-6 = Emplacement 6 de la barre rapide
+action-hotbar-slot-7 = Emplacement 7 de la barre rapide
+action-hotbar-slot-8 = Emplacement 8 de la barre rapide
+action-hotbar-slot-9 = Emplacement 9 de la barre rapide
+action-hotbar-slot-10 = Emplacement 10 de la barre rapide
+
+## Sauvegardes
+
+saves-title = Sauvegardes
+saves-new = Nouvelle sauvegarde
+saves-load = Charger
+saves-overwrite = Écraser
+saves-delete = Supprimer
+saves-back = Retour
+saves-empty = Aucune sauvegarde
+saves-details = Graine { $seed }, { $time } de jeu
+saves-confirm-overwrite = Écraser la sauvegarde du { $date } ?
+saves-confirm-delete = Supprimer la sauvegarde du { $date } ? C'est définitif.
+saves-yes = Oui
+saves-no = Non
+
+## Menu de construction
+
+build-search = Rechercher...
+build-category-all = Tout
+build-list-item = { $name }
+build-list-locked = { $name } (nécessite { $tech })
+building-type-zone = Zone
+building-type-single = Bâtiment
+building-type-tool = Outil
+building-type-sign = Panneau
+building-cost = Coût : { $cost }
+building-cost-free = Gratuit
+# Catégories par défaut du menu de construction, nommées d'après la catégorie elle-même
+Zones = Zones
+Buildings = Bâtiments
+Tools = Outils
+Decoration = Décoration
+
+## Menu contextuel des bâtiments
+
+context-move = Déplacer
+context-copy = Copier
+context-enable = Activer
+context-disable = Désactiver
+context-info = Infos
+context-follow = Suivre
+context-delete = Supprimer
+context-close = Fermer
+info-position = Position : { $position }
+info-id = Id : { $id }
+info-disabled = Désactivé
+info-sign-text = Texte du panneau, cliquez pour le modifier
+
+## Scénarios et scripts
+
+scenario-ticks-left = Ticks restants : { $ticks }
+scenario-victory = Victoire
+scenario-defeat = Défaite
+scenario-continue = Continuer à jouer
+scenario-restart = Recommencer
+scenario-time-up = Le temps est écoulé
+script-errors-retry = Réessayer après correction
+graph-hint = Cliquez sur une statistique pour l'afficher en graphique
+graph-label = { $name } sur les { $ticks } derniers ticks ({ $low } - { $high })
+console-stats-exported = Statistiques exportées dans { $path }
+console-export-failed = Échec de l'export : { $error }
+
+## Notifications
+
+toast-game-saved = Partie sauvegardée
+toast-game-loaded = Partie chargée
+toast-save-deleted = Sauvegarde supprimée
+toast-area-occupied = Impossible de construire ici : l'emplacement est occupé
+toast-needs-coast = Ce bâtiment doit être sur la côte, près de l'embouchure d'un fleuve
+toast-needs-river = Ce bâtiment doit être construit en travers d'une rivière
+toast-needs-riverside = Ce bâtiment doit être construit sur la terre ferme, au bord d'une rivière
+toast-spawn-area-occupied = Impossible de construire en { $position } : l'emplacement est occupé
+toast-blueprint-missing = Des bâtiments du plan sont introuvables : { $buildings }
+toast-building-built = { $name } construit
+toast-building-deleted = { $name } supprimé
+toast-building-destroyed = { $name } a été détruit
+toast-building-enabled = { $name } activé
+toast-building-disabled = { $name } désactivé
+toast-following = Caméra sur { $name }, déplacez-la pour arrêter
+toast-screenshot-saved = Capture d'écran enregistrée dans { $path }
+toast-objective-completed = Objectif atteint : { $objective }
+toast-script-error = Erreur dans { $script } : { $message }
+toast-ship-docked = Un navire a commercé au port, solde : { $balance } crédits
+toast-tech-unlocked = { $tech } découvert
+toast-tech-locked = Pas encore constructible : { $tech } doit être étudié
+toast-road-tool-on = Outil route : cliquez sur les extrémités de la route, des ponts sont construits au-dessus de l'eau et des tunnels sous les montagnes
+toast-road-tool-off = Outil route désactivé
+toast-canal-tool-on = Outil canal : cliquez sur les extrémités du canal, l'une d'elles dans une rivière, un lac ou un autre canal
+toast-canal-tool-off = Outil canal désactivé
+rejection-unknown-building = Bâtiment inconnu : { $name }
+rejection-occupied = L'emplacement est occupé
+rejection-protected = L'emplacement est protégé
+rejection-locked = { $tech } doit d'abord être étudié
+rejection-already-researched = { $tech } est déjà découvert
+rejection-unknown-tech = Technologie inconnue : { $tech }
+rejection-road-too-long = La route est trop longue ({ $length } / { $max })
+rejection-road-in-water = Les routes doivent commencer et finir sur la terre ferme
+rejection-bridge-too-long = Le pont est trop long ({ $length } / { $max })
+rejection-tunnel-too-long = Le tunnel est trop long ({ $length } / { $max })
+rejection-canal-too-long = Le canal est trop long ({ $length } / { $max })
+rejection-canal-not-connected = Les canaux doivent commencer ou finir dans une rivière, un lac ou un autre canal
+rejection-not-enough-resources = Pas assez de { $resource } ({ $available } / { $needed })
+rejection-invalid-terraform = Le terrain ne peut pas être modifié ainsi
+
+## Barre du haut
+
+top-bar-clock = Jour { $day }, { $time }
+stat-resource-money = Crédits
+stat-resource-food = Nourriture
+stat-resource-material = Matériaux
+stat-aggregates-population = Population
+stat-stat-science = Science
+
+## Recherche
+
+research-title = Recherche
+research-rate = Laboratoires : { $rate } points par tick
+research-done = Découvert
+research-progress = En cours : { $progress } %
+research-cost = { $cost } points, cliquez pour étudier
+research-requires = Nécessite { $techs }
+research-unlocks = Débloque { $buildings }
+
+## Cartes de chaleur
+
+heatmap-none = Carte de chaleur masquée
+heatmap-pollution = Carte de chaleur : pollution
+heatmap-land-value = Carte de chaleur : valeur foncière
+heatmap-power-coverage = Carte de chaleur : couverture électrique
+
+## Mode photo
+
+photo-hint = F12 : capture d'écran, F11 : quitter, touches de la caméra et clic droit : voler
+photo-fov = Champ de vision
+photo-exposure = Exposition
+
+## Lieu de départ
+
+start-title = Où commencer
+start-hint = Les meilleurs endroits sont des terres plates près d'une rivière, avec des plaines fertiles et la mer autour
+start-best = Commencer au meilleur endroit
+start-spot = { $rank } : plat à { $flatness } %, { $water }
+start-water-both = près d'une rivière et de la mer
+start-water-river = près d'une rivière
+start-water-coast = près de la mer
+start-water-none = loin de l'eau
+
+## Éditeur
+
+menu-editor = Éditeur
+editor-title = Éditeur
+editor-name = Nom
+editor-raise = Monter
+editor-lower = Creuser
+editor-flatten = Aplanir
+editor-smooth = Lisser
+editor-erase = Effacer les gisements
+editor-radius = Rayon { $radius }
+editor-starting-resources = Ressources de départ
+editor-objectives = Objectifs
+editor-add-population = + Population
+editor-add-stock = + { $resource }
+editor-export = Exporter le scénario
+deposit-stone = Pierre
+deposit-iron = Fer
+deposit-clay = Argile
+toast-scenario-exported = Scénario exporté dans { $path }
+toast-scenario-no-objectives = Le scénario a besoin d'un objectif
+toast-gltf-exported = Scène exportée dans { $path }
+toast-gltf-failed = Échec de l'export : { $error }
@@ -26,7 +26,30 @@
 #import bevy_pbr::decal::forward::get_forward_decal_info
 #endif
 
-@group(2) @binding(101) var highlight_color: vec4<f32>;
+@group(2) @binding(100) var<uniform> highlight_color: vec4<f32>;
+// x: pulse speed (0: steady), y: sharpness of the rim
+@group(2) @binding(101) var<uniform> highlight_params: vec4<f32>;
+// x: stylized shading on, y: number of light bands, z: rim strength, w: rim sharpness
+@group(2) @binding(102) var<uniform> toon: vec4<f32>;
+// tint of the darkest band, a: its strength
+@group(2) @binding(103) var<uniform> shadow_color: vec4<f32>;
+@group(2) @binding(104) var<uniform> rim_color: vec4<f32>;
+
+// Stylized shading of the lit color : the light is cut in bands, the darkest one tinted,
+// and a rim of light outlines the shapes
+fn toon_shading(light: vec3<f32>, normal: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let luminance = max(dot(light, vec3<f32>(0.2126, 0.7152, 0.0722)), 0.0001);
+    let bands = max(toon.y, 1.0);
+    let banded = ceil(luminance * bands) / bands;
+    var color = light * (banded / luminance);
+    if luminance <= 1.0 / bands {
+        color = color * mix(vec3<f32>(1.0), shadow_color.rgb, shadow_color.a);
+    }
+    let eye = normalize(view_bindings::view.world_position.xyz - world_position);
+    let rim = pow(1.0 - max(dot(eye, normal), 0.0), max(toon.w, 0.01));
+    // no rim light in the dark
+    return color + rim_color.rgb * rim * toon.z * min(banded, 1.0);
+}
 
 @fragment
 fn fragment(
@@ -70,6 +93,16 @@ fn fragment(
     } else {
         out.color = pbr_input.material.base_color;
     }
+    if toon.x > 0.0 {
+        out.color = vec4<f32>(toon_shading(out.color.rgb, pbr_input.N, in.world_position.xyz), out.color.a);
+    }
+
+    // rim glow, stronger where the surface faces away from the camera
+    let eye = normalize(view_bindings::view.world_position.xyz - in.world_position.xyz);
+    let rim = pow(1.0 - abs(dot(eye, pbr_input.N)), max(highlight_params.y, 0.01));
+    let pulse = 0.75 + 0.25 * sin(view_bindings::globals.time * highlight_params.x);
+    let glow = highlight_color.rgb * highlight_color.a * (rim + 0.15) * pulse;
+    out.color = vec4<f32>(out.color.rgb + glow, out.color.a);
 
     // apply in-shader post processing (fog, alpha-premultiply, and also tonemapping, debanding if the camera is non-hdr)
     // note this does not include fullscreen postprocessing effects like bloom.
@@ -77,9 +110,8 @@ fn fragment(
 #endif
 
 #ifdef FORWARD_DECAL
-        out.color.a = min(forward_decal_info.alpha, out.color.a);
+    out.color.a = min(forward_decal_info.alpha, out.color.a);
 #endif
 
-        out.color = mix(out.color, highlight_color, 0.5)
-        return out;
-}
\ No newline at end of file
+    return out;
+}
@@ -32,6 +32,202 @@
 @group(2) @binding(102) var<uniform> mountain_color: vec4<f32>;
 @group(2) @binding(103) var<uniform> snow_color: vec4<f32>;
 @group(2) @binding(104) var<uniform> sand_color: vec4<f32>;
+// x: hue strength, y: brightness strength, z: scale in world units, w: world seed
+@group(2) @binding(105) var<uniform> macro_variation: vec4<f32>;
+// x: shore, y: end of the sand, z: start of the rock, w: start of the snow
+@group(2) @binding(106) var<uniform> heights: vec4<f32>;
+// x: slope from which the ground is rock, y: width of the transitions,
+// z: width of the transition to the rock, w: size of the textures (0: flat colors)
+@group(2) @binding(107) var<uniform> layers: vec4<f32>;
+// layers : grass, rock, sand, snow
+@group(2) @binding(108) var splat_textures: texture_2d_array<f32>;
+@group(2) @binding(109) var splat_sampler: sampler;
+// x: grid spacing (0: no grid), y: squares between major lines, z: height between contours (0: none)
+@group(2) @binding(110) var<uniform> overlay: vec4<f32>;
+// rg: direction of the water, b: its speed
+@group(2) @binding(111) var flow_map: texture_2d<f32>;
+@group(2) @binding(112) var flow_sampler: sampler;
+// x: ripple speed, y: ripple size, z: ripple strength, w: hydro amount of a river
+@group(2) @binding(113) var<uniform> river: vec4<f32>;
+// x: size of a chunk in world units, y: in cells
+@group(2) @binding(114) var<uniform> chunk: vec4<f32>;
+// values of the shown heatmap layer
+@group(2) @binding(115) var heatmap: texture_2d<f32>;
+@group(2) @binding(116) var heatmap_sampler: sampler;
+// how much the terrain has been explored
+@group(2) @binding(117) var explored: texture_2d<f32>;
+@group(2) @binding(118) var explored_sampler: sampler;
+// rgb: color of the unexplored terrain, a: how much it fades to it
+@group(2) @binding(119) var<uniform> fog_of_war: vec4<f32>;
+// x: stylized shading on, y: number of light bands, z: rim strength, w: rim sharpness
+@group(2) @binding(120) var<uniform> toon: vec4<f32>;
+// tint of the darkest band, a: its strength
+@group(2) @binding(121) var<uniform> shadow_color: vec4<f32>;
+@group(2) @binding(122) var<uniform> rim_color: vec4<f32>;
+// x: ramp of the heatmap (0: rainbow, 1: for red-green colorblindness, 2: for blue-yellow),
+// y: hatch over the heatmap
+@group(2) @binding(123) var<uniform> palette: vec4<f32>;
+
+fn hash2(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(127.1, 311.7))) * 43758.5453);
+}
+
+fn value_noise(p: vec2<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+    let a = hash2(i);
+    let b = hash2(i + vec2<f32>(1.0, 0.0));
+    let c = hash2(i + vec2<f32>(0.0, 1.0));
+    let d = hash2(i + vec2<f32>(1.0, 1.0));
+    return mix(mix(a, b, u.x), mix(c, d, u.x), u.y);
+}
+
+// Low frequency noise in [-1, 1], used to break the tiling of large areas
+fn macro_noise(world_xz: vec2<f32>, channel: f32) -> f32 {
+    var p = world_xz / max(macro_variation.z, 1.0) + vec2<f32>(macro_variation.w * 17.0, channel * 31.0);
+    var value = 0.0;
+    var amplitude = 0.5;
+    for (var i = 0; i < 3; i++) {
+        value += amplitude * value_noise(p);
+        p *= 2.03;
+        amplitude *= 0.5;
+    }
+    return value / 0.875 * 2.0 - 1.0;
+}
+
+// Rotate the hue of a color around the grey axis
+fn hue_shift(color: vec3<f32>, angle: f32) -> vec3<f32> {
+    let k = vec3<f32>(0.57735, 0.57735, 0.57735);
+    let cos_a = cos(angle);
+    return color * cos_a + cross(k, color) * sin(angle) + k * dot(k, color) * (1.0 - cos_a);
+}
+
+// Steepness of the ground, 1 on cliffs
+fn cliff_factor(normal: vec3<f32>) -> f32 {
+    let slope = 1.0 - abs(normal.y);
+    return smoothstep(layers.x - 0.05, layers.x + 0.05, slope);
+}
+
+// Flat colors by height, with rock on the steep slopes
+fn flat_color(normal: vec3<f32>, height: f32) -> vec4<f32> {
+    let blend = max(layers.y, 0.0001);
+    var color = mix(ocean_color, sand_color, smoothstep(heights.x - blend, heights.x + blend, height));
+    color = mix(color, grass_color, smoothstep(heights.y - blend, heights.y + blend, height));
+    color = mix(color, mountain_color, smoothstep(heights.z - max(layers.z, 0.0001), heights.z, height));
+    color = mix(color, snow_color, step(heights.w, height));
+    // no cliffs under water
+    let cliff = cliff_factor(normal) * step(heights.x, height) * (1.0 - step(heights.w, height));
+    return mix(color, mountain_color, cliff);
+}
+
+fn sample_layer(uv: vec2<f32>, layer: i32) -> vec4<f32> {
+    return textureSample(splat_textures, splat_sampler, uv, layer);
+}
+
+// Project the texture along the three axes, so that it doesn't stretch on cliffs
+fn triplanar(world_position: vec3<f32>, normal: vec3<f32>, layer: i32) -> vec4<f32> {
+    let p = world_position / max(layers.w, 0.01);
+    var weights = pow(abs(normal), vec3<f32>(4.0));
+    weights /= weights.x + weights.y + weights.z;
+    return sample_layer(p.zy, layer) * weights.x
+        + sample_layer(p.xz, layer) * weights.y
+        + sample_layer(p.xy, layer) * weights.z;
+}
+
+// Blend the terrain textures by height, with rock on the steep slopes
+fn splat(world_position: vec3<f32>, normal: vec3<f32>, height: f32) -> vec4<f32> {
+    let blend = max(layers.y, 0.0001);
+    let uv = world_position.xz / max(layers.w, 0.01);
+    let sand = 1.0 - smoothstep(heights.y - blend, heights.y + blend, height);
+    let snow = smoothstep(heights.w - blend, heights.w + blend, height);
+    let high_rock = smoothstep(heights.z - max(layers.z, 0.0001), heights.z, height) * (1.0 - snow);
+    let cliff = cliff_factor(normal);
+    let grass = max(1.0 - sand - snow - high_rock, 0.0);
+    var color = sample_layer(uv, 0) * grass
+        + sample_layer(uv, 2) * sand
+        + sample_layer(uv, 3) * snow;
+    let rock = max(high_rock, cliff);
+    color = mix(color / max(grass + sand + snow, 0.0001), triplanar(world_position, normal, 1), rock);
+    return color;
+}
+
+// Blue for low values, through green and yellow, to red for high ones
+fn heat_color(value: f32) -> vec3<f32> {
+    let v = clamp(value, 0.0, 1.0);
+    if palette.x > 1.5 {
+        // from dark purple to white through red
+        return mix(
+            mix(vec3<f32>(0.1, 0.0, 0.15), vec3<f32>(0.85, 0.15, 0.1), clamp(v * 2.0, 0.0, 1.0)),
+            vec3<f32>(1.0, 0.95, 0.9),
+            clamp(v * 2.0 - 1.0, 0.0, 1.0),
+        );
+    }
+    if palette.x > 0.5 {
+        // from dark blue to yellow, growing in brightness
+        return mix(vec3<f32>(0.0, 0.13, 0.3), vec3<f32>(1.0, 0.9, 0.2), v);
+    }
+    return clamp(vec3<f32>(
+        1.5 - abs(v - 1.0) * 3.0,
+        1.5 - abs(v - 0.5) * 3.0,
+        1.5 - abs(v) * 3.0,
+    ), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Diagonal lines, thicker where the value is higher, to read the heatmap without colors
+fn heat_hatch(pos: vec2<f32>, value: f32) -> f32 {
+    let stripe = fract((pos.x + pos.y) / 4.0);
+    return select(0.0, 1.0, stripe < clamp(value, 0.0, 1.0) * 0.5);
+}
+
+// Stylized shading of the lit color : the light is cut in bands, the darkest one tinted,
+// and a rim of light outlines the shapes
+fn toon_shading(light: vec3<f32>, normal: vec3<f32>, world_position: vec3<f32>) -> vec3<f32> {
+    let luminance = max(dot(light, vec3<f32>(0.2126, 0.7152, 0.0722)), 0.0001);
+    let bands = max(toon.y, 1.0);
+    let banded = ceil(luminance * bands) / bands;
+    var color = light * (banded / luminance);
+    if luminance <= 1.0 / bands {
+        color = color * mix(vec3<f32>(1.0), shadow_color.rgb, shadow_color.a);
+    }
+    let eye = normalize(view_bindings::view.world_position.xyz - world_position);
+    let rim = pow(1.0 - max(dot(eye, normal), 0.0), max(toon.w, 0.01));
+    // no rim light in the dark
+    return color + rim_color.rgb * rim * toon.z * min(banded, 1.0);
+}
+
+// Position of a point in the data textures of its chunk
+fn chunk_uv(world_xz: vec2<f32>) -> vec2<f32> {
+    let origin = floor(world_xz / chunk.x) * chunk.x;
+    let cell = (world_xz - origin) / chunk.x * (chunk.y - 1.0);
+    return (cell + 0.5) / chunk.y;
+}
+
+// Ripples carried by the flow. Two layers scroll with half a period between them,
+// so that one hides the other jumping back.
+fn river_ripples(world_xz: vec2<f32>, flow: vec4<f32>) -> f32 {
+    let direction = flow.xy * 2.0 - 1.0;
+    let t = view_bindings::globals.time * river.x;
+    let first = fract(t);
+    let second = fract(t + 0.5);
+    let p = world_xz / max(river.y, 0.01);
+    let a = value_noise(p - direction * flow.z * first * 4.0);
+    let b = value_noise(p - direction * flow.z * second * 4.0 + vec2<f32>(0.37, 0.71));
+    return mix(a, b, abs(first - 0.5) * 2.0);
+}
+
+// 1 on the lines of a grid of the given spacing, fading over a pixel
+fn grid_lines(p: vec2<f32>, spacing: f32) -> f32 {
+    let q = p / max(spacing, 0.0001);
+    let distance = abs(fract(q - 0.5) - 0.5) / max(fwidth(q), vec2<f32>(0.0001));
+    return 1.0 - min(min(distance.x, distance.y), 1.0);
+}
+
+fn contour_lines(height: f32, spacing: f32) -> f32 {
+    let q = height / max(spacing, 0.0001);
+    let distance = abs(fract(q - 0.5) - 0.5) / max(fwidth(q), 0.0001);
+    return 1.0 - min(distance, 1.0);
+}
 
 @fragment
 fn fragment(
@@ -71,55 +267,77 @@ fn fragment(
     let hydro = (abs(in.uv.y) - 0.94) / 100.;
     let mix_hydro = exp(-(1./hydro));
 
-    if height < 0.34 {
-        texture = ocean_color;
-    }
-    else if height < 0.345 {
-        texture = mix(ocean_color, sand_color, (height - 0.34)/ 0.005);
-    }
-    else if height < 0.34 {
-        texture = sand_color;
-    }
-    else if height < 0.35 {
-        texture = mix(sand_color, grass_color, (height - 0.345)/0.005);
-    }
-    else if height < 0.42 {
-        texture = grass_color;
+    let normal = normalize(in.world_normal);
+    texture = flat_color(normal, height);
+
+    // sampled outside of the branch, as textures need uniform control flow
+    let splatted = splat(in.world_position.xyz, normal, height);
+    if layers.w > 0.0 && height >= heights.x {
+        texture = splatted;
     }
-    else if height < 0.47 {
-        texture = mix(grass_color, mountain_color, (height - 0.42)/0.05);
+
+    // texture = mix(texture, ocean_color, mix_hydro);
+
+    // sheen moving along the rivers
+    let flow = textureSample(flow_map, flow_sampler, chunk_uv(in.world_position.xz));
+    let river_mask = smoothstep(river.w, river.w * 4.0, in.uv.y);
+    let ripples = river_ripples(in.world_position.xz, flow);
+    texture = vec4<f32>(texture.rgb + vec3<f32>(ripples * river.z * river_mask), texture.a);
+
+    // heatmap of gameplay data
+    let heat = textureSample(heatmap, heatmap_sampler, chunk_uv(in.world_position.xz)).r;
+    if overlay.w > 0.0 {
+        texture = vec4<f32>(mix(texture.rgb, heat_color(heat), overlay.w), texture.a);
+        if palette.y > 0.0 {
+            let hatch = heat_hatch(in.world_position.xz, heat) * overlay.w;
+            texture = vec4<f32>(mix(texture.rgb, vec3<f32>(0.05), hatch * 0.6), texture.a);
+        }
     }
-    else if height < 0.55 {
-        texture = mountain_color;
+
+    // macro variation
+    let hue_noise = macro_noise(in.world_position.xz, 0.0);
+    let brightness_noise = macro_noise(in.world_position.xz, 1.0);
+    texture = vec4<f32>(
+        hue_shift(texture.rgb, hue_noise * macro_variation.x) * (1.0 + brightness_noise * macro_variation.y),
+        texture.a
+    );
+
+    // grid and contour overlays, computed outside of branches for the derivatives
+    let minor = grid_lines(in.world_position.xz, overlay.x);
+    let major = grid_lines(in.world_position.xz, overlay.x * max(overlay.y, 1.0));
+    let contour = contour_lines(in.world_position.y, overlay.z);
+    if overlay.x > 0.0 {
+        texture = vec4<f32>(mix(texture.rgb, vec3<f32>(0.0), minor * 0.3), texture.a);
+        if overlay.y > 1.0 {
+            texture = vec4<f32>(mix(texture.rgb, vec3<f32>(1.0), major * 0.5), texture.a);
+        }
     }
-    else {
-        texture = snow_color;
+    if overlay.z > 0.0 {
+        texture = vec4<f32>(mix(texture.rgb, vec3<f32>(0.25, 0.12, 0.05), contour * 0.6), texture.a);
     }
 
-    // texture = mix(texture, ocean_color, mix_hydro);
-
     texture = apply_decal_base_color(
         in.world_position.xyz,
         in.position.xy,
         texture
     );
 
+    // unexplored terrain, desaturated and faded
+    let explored_amount = textureSample(explored, explored_sampler, chunk_uv(in.world_position.xz)).r;
+    let fog = (1.0 - explored_amount) * fog_of_war.a;
+    let gray = vec3<f32>(dot(texture.rgb, vec3<f32>(0.2126, 0.7152, 0.0722)));
+    texture = vec4<f32>(mix(texture.rgb, mix(gray, fog_of_war.rgb, 0.7), fog), texture.a);
+
 
     pbr_input.material.base_color = vec4<f32>(1.0, 1.0, 1.0, 1.0);
 
     out.color = apply_pbr_lighting(pbr_input);
 
-    // Source for cel shading: https://www.youtube.com/watch?v=mnxs6CR6Zrk]
-    // sample mask at the current fragment's intensity as u to get the cutoff
-    //let uv = vec2<f32>(out.color.r, 0.0);
-    //let quantization = textureSample(mask, mask_sampler, uv);
-    //out.color = mix(shadow_color, highlight_color, quantization);
-
-    // apply rim highlights. Inspired by Breath of the Wild: https://www.youtube.com/watch?v=By7qcgaqGI4
-    //let eye = normalize(view_bindings::view.world_position.xyz - in.world_position.xyz);
-    //let rim = 1.0 - abs(dot(eye, in.world_normal));
-    //let rim_factor = rim * rim * rim * rim;
-    //out.color = mix(out.color, rim_color, rim_factor);
+    // cel shading, from https://www.youtube.com/watch?v=mnxs6CR6Zrk
+    // and rim highlights, inspired by Breath of the Wild: https://www.youtube.com/watch?v=By7qcgaqGI4
+    if toon.x > 0.0 {
+        out.color = vec4<f32>(toon_shading(out.color.rgb, pbr_input.N, in.world_position.xyz), out.color.a);
+    }
 
     // Reapply texture
     out.color = out.color * texture;
@@ -0,0 +1,44 @@
+// Outline of the highlighted and selected parts : the mesh grown along its normals, with
+// only its back faces drawn, in a flat color
+
+#import bevy_pbr::{
+    mesh_functions,
+    mesh_view_bindings::view,
+    view_transformations::position_world_to_clip,
+}
+
+// rgb: color of the outline
+@group(2) @binding(0) var<uniform> color: vec4<f32>;
+// x: width of the outline, as a share of the distance to the camera
+@group(2) @binding(1) var<uniform> params: vec4<f32>;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+    let world_position = mesh_functions::mesh_position_local_to_world(
+        world_from_local,
+        vec4<f32>(vertex.position, 1.0),
+    );
+    let normal = mesh_functions::mesh_normal_local_to_world(vertex.normal, vertex.instance_index);
+    // as wide on the screen however far the part is
+    let distance = length(view.world_position - world_position.xyz);
+    let grown = world_position.xyz + normal * params.x * distance;
+    var out: VertexOutput;
+    out.position = position_world_to_clip(grown);
+    return out;
+}
+
+@fragment
+fn fragment() -> @location(0) vec4<f32> {
+    return vec4<f32>(color.rgb, 1.0);
+}
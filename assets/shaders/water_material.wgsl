@@ -0,0 +1,110 @@
+// Animated water : two scrolling layers of normals, a color going from shallow to deep
+// with the depth of the water behind, read from the depth prepass, and foam on the shores.
+
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::alpha_discard,
+    mesh_view_bindings::globals,
+    view_transformations::depth_ndc_to_view_z,
+}
+
+#ifdef DEPTH_PREPASS
+#import bevy_pbr::prepass_utils::prepass_depth
+#endif
+
+#ifdef PREPASS_PIPELINE
+#import bevy_pbr::{
+    prepass_io::{VertexOutput, FragmentOutput},
+    pbr_deferred_functions::deferred_output,
+}
+#else
+#import bevy_pbr::{
+    forward_io::{VertexOutput, FragmentOutput},
+    pbr_functions,
+    pbr_functions::{apply_pbr_lighting, main_pass_post_lighting_processing},
+}
+#endif
+
+@group(2) @binding(100) var<uniform> shallow_color: vec4<f32>;
+@group(2) @binding(101) var<uniform> deep_color: vec4<f32>;
+@group(2) @binding(102) var<uniform> foam_color: vec4<f32>;
+// x: scale of the waves in world units, y: strength of the normals,
+// z: width of the foam, w: depth at which the water gets the deep color
+@group(2) @binding(103) var<uniform> waves: vec4<f32>;
+// xy: scrolling of the first layer of normals, zw: of the second, in world units per second
+@group(2) @binding(104) var<uniform> scroll: vec4<f32>;
+@group(2) @binding(105) var<uniform> has_normal_map: u32;
+@group(2) @binding(106) var normal_map: texture_2d<f32>;
+@group(2) @binding(107) var normal_map_sampler: sampler;
+
+// Slope of a sum of sine waves, used when there is no normal map
+fn procedural_slope(p: vec2<f32>, t: f32) -> vec2<f32> {
+    var directions = array<vec2<f32>, 3>(
+        vec2<f32>(1.0, 0.3),
+        vec2<f32>(-0.4, 1.0),
+        vec2<f32>(0.7, -0.8),
+    );
+    var slope = vec2<f32>(0.0);
+    for (var i = 0; i < 3; i++) {
+        let direction = normalize(directions[i]);
+        let frequency = 1.0 + f32(i) * 0.7;
+        slope += direction * cos(dot(p, direction) * frequency * 6.283 + t * (1.0 + f32(i) * 0.3)) / frequency;
+    }
+    return slope * 0.5;
+}
+
+fn sample_slope(uv: vec2<f32>) -> vec2<f32> {
+    return textureSample(normal_map, normal_map_sampler, uv).xy * 2.0 - 1.0;
+}
+
+// Slope of the water surface at a point, from the two scrolling layers
+fn water_slope(world_xz: vec2<f32>) -> vec2<f32> {
+    let t = globals.time;
+    let scale = max(waves.x, 0.01);
+    let first = (world_xz + scroll.xy * t) / scale;
+    let second = (world_xz + scroll.zw * t) / (scale * 1.7);
+    if has_normal_map != 0u {
+        return (sample_slope(first) + sample_slope(second)) * 0.5;
+    }
+    return (procedural_slope(first, t) + procedural_slope(second, t * 0.8)) * 0.5;
+}
+
+@fragment
+fn fragment(
+    in: VertexOutput,
+    @builtin(front_facing) is_front: bool,
+) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+    let slope = water_slope(in.world_position.xz) * waves.y;
+    let normal = normalize(vec3<f32>(-slope.x, 1.0, -slope.y));
+    pbr_input.N = normal;
+    pbr_input.world_normal = normal;
+
+    // thickness of the water between its surface and what is behind it
+    var depth = waves.w;
+#ifdef DEPTH_PREPASS
+#ifndef PREPASS_PIPELINE
+    let behind = depth_ndc_to_view_z(prepass_depth(in.position, 0u));
+    let surface = depth_ndc_to_view_z(in.position.z);
+    depth = max(surface - behind, 0.0);
+#endif
+#endif
+
+    var color = mix(shallow_color, deep_color, clamp(depth / max(waves.w, 0.001), 0.0, 1.0));
+    // the foam line moves a bit with the waves
+    let foam_width = waves.z * (1.0 + 0.3 * slope.x);
+    let foam = 1.0 - smoothstep(0.0, max(foam_width, 0.001), depth);
+    color = mix(color, foam_color, foam * foam_color.a);
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, color * pbr_input.material.base_color);
+
+#ifdef PREPASS_PIPELINE
+    let out = deferred_output(in, pbr_input);
+#else
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+#endif
+
+    return out;
+}
@@ -0,0 +1,53 @@
+//! Worldgen and chunk meshing benchmarks. Run with `cargo bench --bench worldgen`.
+use bevy::math::I64Vec2;
+use criterion::{Criterion, criterion_group, criterion_main};
+use unnamed_factory::{
+    map::{Chunk, Map},
+    mapgen::WorldGen,
+};
+
+/// Chunks generated and meshed, in a square around the origin
+const CHUNKS_SIDE: i64 = 4;
+
+fn continent(c: &mut Criterion) {
+    let mut group = c.benchmark_group("worldgen");
+    group.sample_size(10);
+    group.bench_function("continent", |b| {
+        b.iter(|| Map::new(WorldGen::default(), false))
+    });
+    group.bench_function("continent_lazy", |b| {
+        b.iter(|| Map::new(WorldGen::default(), true))
+    });
+    group.finish();
+}
+
+fn chunks(c: &mut Criterion) {
+    let map = Map::new(WorldGen::default(), false);
+    let positions: Vec<I64Vec2> = (0..CHUNKS_SIDE)
+        .flat_map(|x| (0..CHUNKS_SIDE).map(move |z| I64Vec2::new(x, z)))
+        .collect();
+    let mut group = c.benchmark_group("chunks");
+    group.sample_size(10);
+    group.bench_function("generate", |b| {
+        b.iter(|| {
+            for pos in &positions {
+                Chunk::new_and_generate(pos, &map.continent);
+            }
+        })
+    });
+    let generated: Vec<Chunk> = positions
+        .iter()
+        .map(|pos| Chunk::new_and_generate(pos, &map.continent))
+        .collect();
+    group.bench_function("mesh", |b| {
+        b.iter(|| {
+            for chunk in &generated {
+                chunk.make_mesh();
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, continent, chunks);
+criterion_main!(benches);
@@ -0,0 +1,266 @@
+use bevy::{audio::Volume, prelude::*};
+
+use crate::{
+    CameraTarget,
+    build::{BuildingPlaced, BuildingRemoved},
+    map::Map,
+    menu::GameState,
+    pause_menu::Settings,
+    player_commands::CommandRejected,
+};
+
+/// Normalized height from which the wind starts to be heard, and at which it is the loudest
+const WIND_HEIGHTS: (f32, f32) = (0.42, 0.55);
+/// Loudness of the wind with the camera zoomed out the most, anywhere
+const ZOOMED_OUT_WIND: f32 = 0.5;
+/// Camera distance at which the wind is `ZOOMED_OUT_WIND` loud
+const ZOOMED_OUT_DISTANCE: f32 = 100.;
+/// Distance from the camera target up to which water is heard, in world units
+const WATER_HEARING_RADIUS: f32 = 15.;
+/// Distance between the points searched for water, in world units
+const WATER_SEARCH_STEP: f32 = 2.5;
+/// Normalized height under which the ground is sea
+const SEA_HEIGHT: f32 = 0.34;
+/// Hydro amount from which the ground is a river, as in the map material
+const RIVER_AMOUNT: f32 = 20.;
+/// Change of the loudness of the ambient loops per second, so that they fade
+const AMBIENT_FADE_SPEED: f32 = 0.5;
+
+/// Sound effects and ambient sounds. Effects are played on placing and demolishing
+/// buildings, on invalid actions and on pressing buttons. Ambient loops fade in and out with
+/// the surroundings of the camera : wind over the mountains and when zoomed out, water near
+/// the sea and the rivers.
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PlaySound>()
+            .add_systems(Startup, load_sounds)
+            .add_systems(OnEnter(GameState::InGame), spawn_ambient)
+            .add_systems(
+                Update,
+                (
+                    (sound_on_buildings, sound_on_rejections, sound_on_clicks).before(play_sounds),
+                    play_sounds,
+                    update_ambient.run_if(in_state(GameState::InGame)),
+                ),
+            );
+    }
+}
+
+/// Volume setting a sound follows
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SoundCategory {
+    Effects,
+    Ui,
+    Ambient,
+    Music,
+}
+
+impl SoundCategory {
+    /// Volume of the category, master volume included
+    pub fn volume(self, settings: &Settings) -> f32 {
+        let volume = match self {
+            SoundCategory::Effects => settings.effects_volume,
+            SoundCategory::Ui => settings.ui_volume,
+            SoundCategory::Ambient => settings.ambient_volume,
+            SoundCategory::Music => settings.music_volume,
+        };
+        settings.master_volume * volume
+    }
+}
+
+/// A sound effect played once
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Sound {
+    Place,
+    Demolish,
+    /// An action that can't be done
+    Invalid,
+    Click,
+}
+
+impl Sound {
+    fn category(self) -> SoundCategory {
+        match self {
+            Sound::Click => SoundCategory::Ui,
+            _ => SoundCategory::Effects,
+        }
+    }
+}
+
+/// Request to play a sound effect
+#[derive(Event, Clone, Copy, Debug)]
+pub struct PlaySound(pub Sound);
+
+#[derive(Resource)]
+struct Sounds {
+    place: Handle<AudioSource>,
+    demolish: Handle<AudioSource>,
+    invalid: Handle<AudioSource>,
+    click: Handle<AudioSource>,
+    wind: Handle<AudioSource>,
+    water: Handle<AudioSource>,
+}
+
+impl Sounds {
+    fn effect(&self, sound: Sound) -> Handle<AudioSource> {
+        match sound {
+            Sound::Place => self.place.clone(),
+            Sound::Demolish => self.demolish.clone(),
+            Sound::Invalid => self.invalid.clone(),
+            Sound::Click => self.click.clone(),
+        }
+    }
+}
+
+/// An ambient loop, with how loud it currently is, between 0 and 1
+#[derive(Component)]
+struct Ambient {
+    kind: AmbientKind,
+    level: f32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum AmbientKind {
+    Wind,
+    Water,
+}
+
+fn load_sounds(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Sounds {
+        place: asset_server.load("sounds/place.wav"),
+        demolish: asset_server.load("sounds/demolish.wav"),
+        invalid: asset_server.load("sounds/invalid.wav"),
+        click: asset_server.load("sounds/click.wav"),
+        wind: asset_server.load("sounds/wind.wav"),
+        water: asset_server.load("sounds/water.wav"),
+    });
+}
+
+fn sound_on_buildings(
+    mut placed: EventReader<BuildingPlaced>,
+    mut removed: EventReader<BuildingRemoved>,
+    mut sounds: EventWriter<PlaySound>,
+) {
+    // the buildings restored from a save are placed silently
+    if placed.read().any(|placed| !placed.restored) {
+        sounds.write(PlaySound(Sound::Place));
+    }
+    if removed.read().count() > 0 {
+        sounds.write(PlaySound(Sound::Demolish));
+    }
+}
+
+fn sound_on_rejections(
+    mut rejected: EventReader<CommandRejected>,
+    mut sounds: EventWriter<PlaySound>,
+) {
+    if rejected.read().count() > 0 {
+        sounds.write(PlaySound(Sound::Invalid));
+    }
+}
+
+fn sound_on_clicks(
+    buttons: Query<&Interaction, (Changed<Interaction>, With<Button>)>,
+    mut sounds: EventWriter<PlaySound>,
+) {
+    if buttons.iter().any(|i| *i == Interaction::Pressed) {
+        sounds.write(PlaySound(Sound::Click));
+    }
+}
+
+/// Play the requested sound effects, each once a frame at most
+fn play_sounds(
+    mut commands: Commands,
+    mut requests: EventReader<PlaySound>,
+    sounds: Res<Sounds>,
+    settings: Res<Settings>,
+    mut played: Local<Vec<Sound>>,
+) {
+    played.clear();
+    for PlaySound(sound) in requests.read() {
+        if played.contains(sound) {
+            continue;
+        }
+        played.push(*sound);
+        let category = sound.category();
+        commands.spawn((
+            Name::new("sound effect"),
+            AudioPlayer(sounds.effect(*sound)),
+            PlaybackSettings::DESPAWN.with_volume(Volume::Linear(category.volume(&settings))),
+            category,
+        ));
+    }
+}
+
+/// Start the ambient loops, silent until the camera is somewhere they are heard
+fn spawn_ambient(mut commands: Commands, sounds: Res<Sounds>) {
+    for (kind, handle) in [
+        (AmbientKind::Wind, &sounds.wind),
+        (AmbientKind::Water, &sounds.water),
+    ] {
+        commands.spawn((
+            Name::new("ambient sound"),
+            AudioPlayer(handle.clone()),
+            PlaybackSettings::LOOP.with_volume(Volume::Linear(0.)),
+            Ambient { kind, level: 0. },
+            SoundCategory::Ambient,
+            StateScoped(GameState::InGame),
+        ));
+    }
+}
+
+/// How loud the wind is around the camera target, between 0 and 1
+fn wind_level(map: &Map, target: &CameraTarget) -> f32 {
+    let (x, y) = map.continent.from_world(&target.pos);
+    let height = map.continent.height(x, y);
+    let mountains = ((height - WIND_HEIGHTS.0) / (WIND_HEIGHTS.1 - WIND_HEIGHTS.0)).clamp(0., 1.);
+    let zoomed_out = (target.distance / ZOOMED_OUT_DISTANCE).clamp(0., 1.) * ZOOMED_OUT_WIND;
+    mountains.max(zoomed_out)
+}
+
+/// How loud the water is around the camera target, from the distance to the nearest sea or
+/// river, between 0 and 1
+fn water_level(map: &Map, target: &CameraTarget) -> f32 {
+    let steps = (WATER_HEARING_RADIUS / WATER_SEARCH_STEP) as i32;
+    let mut nearest = WATER_HEARING_RADIUS;
+    for i in -steps..=steps {
+        for j in -steps..=steps {
+            let offset = Vec2::new(i as f32, j as f32) * WATER_SEARCH_STEP;
+            let distance = offset.length();
+            if distance >= nearest {
+                continue;
+            }
+            let (x, y) = map
+                .continent
+                .from_world(&(target.pos + Vec3::new(offset.x, 0., offset.y)));
+            if map.continent.height(x, y) < SEA_HEIGHT
+                || map.continent.get_hydro(x, y).amount >= RIVER_AMOUNT
+            {
+                nearest = distance;
+            }
+        }
+    }
+    1. - nearest / WATER_HEARING_RADIUS
+}
+
+/// Fade the ambient loops towards how loud they are around the camera
+fn update_ambient(
+    map: Res<Map>,
+    camera: Single<&CameraTarget, With<Camera>>,
+    settings: Res<Settings>,
+    time: Res<Time>,
+    mut ambients: Query<(&mut Ambient, &mut AudioSink)>,
+) {
+    let volume = SoundCategory::Ambient.volume(&settings);
+    for (mut ambient, mut sink) in &mut ambients {
+        let target = match ambient.kind {
+            AmbientKind::Wind => wind_level(&map, &camera),
+            AmbientKind::Water => water_level(&map, &camera),
+        };
+        let step = AMBIENT_FADE_SPEED * time.delta_secs();
+        ambient.level += (target - ambient.level).clamp(-step, step);
+        sink.set_volume(Volume::Linear(ambient.level * volume));
+    }
+}
@@ -0,0 +1,505 @@
+use bevy::{picking::hover::HoverMap, prelude::*};
+
+use crate::{
+    audio::{PlaySound, Sound},
+    build::{SelectedBuild, cast_to_terrain},
+    canals::CanalTool,
+    input_map::{Action, Actions},
+    localization::Localization,
+    map::{GRID_SQUARE_SIZE, IsGround, Map, PatchOp},
+    menu::GameState,
+    player_commands::{IncomingCommand, PlayerCommand, Rejection},
+    pollution::RIVER_AMOUNT,
+    replication::TerrainOp,
+    roads::{DECK_HALF_WIDTH, RoadEdge, RoadGraph, RoadKind},
+    sim::Sim,
+    toasts::Toasts,
+};
+
+/// Longest bridge, in world units
+pub const MAX_BRIDGE_LENGTH: f32 = 40.;
+/// Longest tunnel, in world units
+pub const MAX_TUNNEL_LENGTH: f32 = 80.;
+/// Longest road built at once, in world units
+pub const MAX_ROAD_LENGTH: f32 = 200.;
+/// Resource the roads are paid with
+pub const COST_RESOURCE: &str = "material";
+/// Cost of the roads per world unit
+const ROAD_COST: f32 = 0.2;
+/// Cost of the bridges per world unit, growing with the length of the bridge
+const BRIDGE_COST: f32 = 2.;
+/// Cost of the tunnels per world unit
+const TUNNEL_COST: f32 = 3.;
+/// Height of the terrain above the straight line between the ends of a road from which the
+/// road goes through a tunnel
+const TUNNEL_DEPTH: f32 = 3.;
+/// Distance between the points of a road checked for water
+const SAMPLE_STEP: f32 = GRID_SQUARE_SIZE;
+/// Ends of the road closer than this to a road are joined to it
+const SNAP_DISTANCE: f32 = 2.;
+/// Height of the decks above the banks
+const DECK_CLEARANCE: f32 = 0.2;
+const DECK_THICKNESS: f32 = 0.3;
+const PILLAR_SPACING: f32 = 6.;
+const PILLAR_RADIUS: f32 = 0.3;
+/// Height of the opening of the tunnel portals
+const PORTAL_HEIGHT: f32 = 2.5;
+const PORTAL_THICKNESS: f32 = 0.6;
+/// Distance between the terrain flattenings along the roads on land
+const FLATTEN_STEP: f32 = 2.;
+const FLATTEN_RADIUS: f32 = 1.5;
+
+/// Road tool (N) : roads are drawn between two clicked points, joined to the roads they
+/// start or end on. On land the terrain under them is flattened. Where they cross a river or
+/// the sea, a bridge deck on pillars carries them instead, without touching the terrain, so
+/// vehicles can cross the water. Bridges are limited to `MAX_BRIDGE_LENGTH`, and cost more
+/// per unit the longer they are. Where the terrain rises `TUNNEL_DEPTH` above the straight
+/// line between the ends of the road, it goes through a tunnel with a portal at both ends,
+/// leaving the mountain untouched.
+pub struct BridgePlugin;
+
+impl Plugin for BridgePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<BuildRoad>()
+            .insert_resource(RoadTool::default())
+            .add_systems(Startup, setup_bridge_assets)
+            .add_systems(OnExit(GameState::InGame), reset_road_tool)
+            .add_systems(
+                Update,
+                (
+                    toggle_road_tool,
+                    use_road_tool.after(toggle_road_tool),
+                    build_roads,
+                )
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// State of the road tool
+#[derive(Resource, Default)]
+pub struct RoadTool {
+    pub active: bool,
+    /// First end of the road being drawn
+    pub start: Option<Vec2>,
+}
+
+/// A road to build, validated and paid for
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BuildRoad {
+    pub from: Vec2,
+    pub to: Vec2,
+}
+
+/// Straight part of a road, on land, on a bridge or in a tunnel
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RoadSpan {
+    pub from: Vec2,
+    pub to: Vec2,
+    pub kind: RoadKind,
+}
+
+impl RoadSpan {
+    pub fn length(&self) -> f32 {
+        self.from.distance(self.to)
+    }
+
+    pub fn cost(&self) -> f64 {
+        let length = self.length();
+        let cost = match self.kind {
+            RoadKind::Bridge => length * BRIDGE_COST * (1. + length / MAX_BRIDGE_LENGTH),
+            RoadKind::Tunnel => length * TUNNEL_COST,
+            RoadKind::Road | RoadKind::Driveway => length * ROAD_COST,
+        };
+        cost as f64
+    }
+
+    /// Points where the terrain is flattened under a span on land
+    pub fn flatten_points(&self) -> impl Iterator<Item = Vec2> {
+        let steps = (self.length() / FLATTEN_STEP).ceil().max(1.) as usize;
+        let (from, to) = (self.from, self.to);
+        (0..=steps).map(move |i| from.lerp(to, i as f32 / steps as f32))
+    }
+}
+
+/// Whether a point is in the sea or in a river
+pub fn is_water(map: &Map, pos: Vec2) -> bool {
+    let pos = Vec3::new(pos.x, 0., pos.y);
+    let (x, y) = map.continent.from_world(&pos);
+    map.continent.is_sea(pos) || map.continent.get_hydro(x, y).amount >= RIVER_AMOUNT
+}
+
+/// Split a straight road into its spans on land, its bridges over the water and its
+/// tunnels under the mountains. The bridges and the tunnels start and end on the last
+/// points on the surface.
+pub fn plan_road(map: &Map, from: Vec2, to: Vec2) -> Vec<RoadSpan> {
+    let height = |p: Vec2| map.get_height(Vec3::new(p.x, 0., p.y));
+    let (h_from, h_to) = (height(from), height(to));
+    let steps = (from.distance(to) / SAMPLE_STEP).ceil().max(1.) as usize;
+    let points: Vec<(Vec2, RoadKind)> = (0..=steps)
+        .map(|i| {
+            let t = i as f32 / steps as f32;
+            let p = from.lerp(to, t);
+            let kind = if is_water(map, p) {
+                RoadKind::Bridge
+            } else if height(p) > h_from.lerp(h_to, t) + TUNNEL_DEPTH {
+                RoadKind::Tunnel
+            } else {
+                RoadKind::Road
+            };
+            (p, kind)
+        })
+        .collect();
+    let mut spans: Vec<RoadSpan> = Vec::new();
+    for pair in points.windows(2) {
+        let [(a, kind_a), (b, kind_b)] = [pair[0], pair[1]];
+        // a step touching the water is bridged, the bridges win over the tunnels
+        let kind = match (kind_a, kind_b) {
+            (RoadKind::Bridge, _) | (_, RoadKind::Bridge) => RoadKind::Bridge,
+            (RoadKind::Tunnel, _) | (_, RoadKind::Tunnel) => RoadKind::Tunnel,
+            _ => RoadKind::Road,
+        };
+        match spans.last_mut() {
+            Some(span) if span.kind == kind => span.to = b,
+            _ => spans.push(RoadSpan {
+                from: a,
+                to: b,
+                kind,
+            }),
+        }
+    }
+    spans
+}
+
+/// Check that a road can be built, returning its spans
+pub fn check_road(map: &Map, from: Vec2, to: Vec2) -> Result<Vec<RoadSpan>, Rejection> {
+    let length = from.distance(to);
+    if length > MAX_ROAD_LENGTH {
+        return Err(Rejection::RoadTooLong {
+            length,
+            max: MAX_ROAD_LENGTH,
+        });
+    }
+    if is_water(map, from) || is_water(map, to) {
+        return Err(Rejection::RoadInWater);
+    }
+    let spans = plan_road(map, from, to);
+    for span in &spans {
+        let length = span.length();
+        match span.kind {
+            RoadKind::Bridge if length > MAX_BRIDGE_LENGTH => {
+                return Err(Rejection::BridgeTooLong {
+                    length,
+                    max: MAX_BRIDGE_LENGTH,
+                });
+            }
+            RoadKind::Tunnel if length > MAX_TUNNEL_LENGTH => {
+                return Err(Rejection::TunnelTooLong {
+                    length,
+                    max: MAX_TUNNEL_LENGTH,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(spans)
+}
+
+pub fn road_cost(spans: &[RoadSpan]) -> f64 {
+    spans.iter().map(RoadSpan::cost).sum()
+}
+
+/// Meshes and materials shared by the bridges and the tunnel portals
+#[derive(Resource)]
+struct BridgeAssets {
+    /// Unit cube, scaled to the decks and the parts of the portals
+    block: Handle<Mesh>,
+    pillar: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+    stone: Handle<StandardMaterial>,
+    /// Dark inside of the tunnels
+    opening: Handle<StandardMaterial>,
+}
+
+fn setup_bridge_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(BridgeAssets {
+        block: meshes.add(Cuboid::new(1., 1., 1.)),
+        pillar: meshes.add(Cylinder::new(PILLAR_RADIUS, 1.)),
+        material: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.45, 0.35, 0.25),
+            perceptual_roughness: 0.9,
+            ..default()
+        }),
+        stone: materials.add(StandardMaterial {
+            base_color: Color::srgb(0.55, 0.55, 0.5),
+            perceptual_roughness: 1.,
+            ..default()
+        }),
+        opening: materials.add(StandardMaterial {
+            base_color: Color::BLACK,
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+fn reset_road_tool(mut tool: ResMut<RoadTool>) {
+    *tool = RoadTool::default();
+}
+
+/// Switch the road tool, dropping the part selected in the build menu and the canal tool
+fn toggle_road_tool(
+    mut commands: Commands,
+    actions: Actions,
+    mut tool: ResMut<RoadTool>,
+    mut canal_tool: ResMut<CanalTool>,
+    selected: Query<Entity, With<SelectedBuild>>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) {
+    if !actions.just_pressed(Action::ToggleRoadTool) {
+        return;
+    }
+    tool.active = !tool.active;
+    tool.start = None;
+    if tool.active {
+        for e in &selected {
+            commands.entity(e).despawn();
+        }
+        canal_tool.active = false;
+        toasts.info(localization.get("toast-road-tool-on"));
+    } else {
+        toasts.info(localization.get("toast-road-tool-off"));
+    }
+}
+
+/// Pick the ends of the road on click, and preview it from the first end to the cursor :
+/// roads in gray, bridges in brown, tunnels in purple, and in red when it can't be built
+fn use_road_tool(
+    mut tool: ResMut<RoadTool>,
+    actions: Actions,
+    mut ray_cast: MeshRayCast,
+    camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    window: Single<&Window>,
+    chunks: Query<&IsGround>,
+    map: Res<Map>,
+    roads: Res<RoadGraph>,
+    hover_map: Res<HoverMap>,
+    nodes: Query<(), With<Node>>,
+    mut incoming: EventWriter<IncomingCommand>,
+    mut sounds: EventWriter<PlaySound>,
+    mut gizmos: Gizmos,
+) {
+    if !tool.active {
+        return;
+    }
+    let (camera, camera_transform) = *camera;
+    let hit = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+        .and_then(|ray| cast_to_terrain(&mut ray_cast, ray, &chunks));
+    let Some(hit) = hit else {
+        return;
+    };
+    let mut point = hit.point.xz();
+    if let Some((_, road_point)) = roads.nearest_road_point(point) {
+        if road_point.distance(point) < SNAP_DISTANCE {
+            point = road_point;
+        }
+    }
+    let at = |p: Vec2| Vec3::new(p.x, map.get_height(Vec3::new(p.x, 0., p.y)) + 0.2, p.y);
+    gizmos.sphere(Isometry3d::from_translation(at(point)), 0.5, Color::WHITE);
+
+    let over_ui = hover_map
+        .values()
+        .any(|hits| hits.keys().any(|hit| nodes.contains(*hit)));
+    let clicked = actions.just_pressed(Action::Place) && !over_ui;
+    let Some(start) = tool.start else {
+        if clicked {
+            tool.start = Some(point);
+        }
+        return;
+    };
+    let valid = check_road(&map, start, point).is_ok();
+    for span in plan_road(&map, start, point) {
+        let color = match (valid, span.kind) {
+            (false, _) => bevy::color::palettes::css::RED,
+            (true, RoadKind::Bridge) => bevy::color::palettes::css::SADDLE_BROWN,
+            (true, RoadKind::Tunnel) => bevy::color::palettes::css::REBECCA_PURPLE,
+            (true, _) => bevy::color::palettes::css::DARK_GRAY,
+        };
+        gizmos.line(at(span.from), at(span.to), color);
+    }
+    if clicked {
+        if valid {
+            incoming.write(IncomingCommand {
+                player: 0,
+                command: PlayerCommand::BuildRoad {
+                    from: start,
+                    to: point,
+                },
+            });
+            // the next road goes on from the end of this one
+            tool.start = Some(point);
+        } else {
+            sounds.write(PlaySound(Sound::Invalid));
+        }
+    }
+}
+
+/// Add the roads to the road graph, flatten the terrain under the spans on land and spawn
+/// the bridges and the tunnel portals
+fn build_roads(
+    mut commands: Commands,
+    mut built: EventReader<BuildRoad>,
+    mut roads: ResMut<RoadGraph>,
+    mut terrain_ops: EventWriter<TerrainOp>,
+    map: Res<Map>,
+    sim: Res<Sim>,
+    assets: Res<BridgeAssets>,
+    mut sounds: EventWriter<PlaySound>,
+) {
+    let height = |p: Vec2| map.get_height(Vec3::new(p.x, 0., p.y));
+    for road in built.read() {
+        for span in plan_road(&map, road.from, road.to) {
+            let a = roads.attach(span.from);
+            let b = roads.attach(span.to);
+            roads.edges.push(RoadEdge {
+                a,
+                b,
+                kind: span.kind,
+            });
+            let edge = roads.edges.len() - 1;
+            let (h_from, h_to) = (height(span.from), height(span.to));
+            match span.kind {
+                RoadKind::Bridge => {
+                    let deck = (h_from + DECK_CLEARANCE, h_to + DECK_CLEARANCE);
+                    roads.levels.insert(edge, deck);
+                    spawn_bridge(&mut commands, &assets, &map, span, deck);
+                    continue;
+                }
+                RoadKind::Tunnel => {
+                    roads.levels.insert(edge, (h_from, h_to));
+                    let from = Vec3::new(span.from.x, h_from, span.from.y);
+                    let to = Vec3::new(span.to.x, h_to, span.to.y);
+                    spawn_portal(&mut commands, &assets, from, to - from);
+                    spawn_portal(&mut commands, &assets, to, from - to);
+                    continue;
+                }
+                RoadKind::Road | RoadKind::Driveway => {}
+            }
+            for p in span.flatten_points() {
+                let t = span.from.distance(p) / span.length().max(f32::EPSILON);
+                terrain_ops.write(TerrainOp {
+                    op: PatchOp::Flatten,
+                    center: Vec3::new(p.x, h_from.lerp(h_to, t), p.y),
+                    radius: FLATTEN_RADIUS,
+                    strength: 1.,
+                    tick: sim.tick,
+                });
+            }
+        }
+        sounds.write(PlaySound(Sound::Place));
+    }
+}
+
+/// Spawn the deck of a bridge, sloping between the heights of its ends, and its pillars
+fn spawn_bridge(
+    commands: &mut Commands,
+    assets: &BridgeAssets,
+    map: &Map,
+    span: RoadSpan,
+    (h_from, h_to): (f32, f32),
+) {
+    let from = Vec3::new(span.from.x, h_from, span.from.y);
+    let to = Vec3::new(span.to.x, h_to, span.to.y);
+    let below = Vec3::Y * DECK_THICKNESS / 2.;
+    let deck = Transform::from_translation(from.midpoint(to) - below)
+        .looking_to(to - from, Vec3::Y)
+        .with_scale(Vec3::new(
+            2. * DECK_HALF_WIDTH,
+            DECK_THICKNESS,
+            from.distance(to),
+        ));
+    let pillars = (span.length() / PILLAR_SPACING).ceil() as usize;
+    commands
+        .spawn((
+            Name::new("bridge"),
+            Transform::default(),
+            Visibility::default(),
+            StateScoped(GameState::InGame),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Mesh3d(assets.block.clone()),
+                MeshMaterial3d(assets.material.clone()),
+                deck,
+            ));
+            for i in 1..pillars {
+                let top = from.lerp(to, i as f32 / pillars as f32) - below * 2.;
+                // down into the river bed
+                let bottom = map.get_height(top) - 0.5;
+                let pillar_height = (top.y - bottom).max(0.);
+                parent.spawn((
+                    Mesh3d(assets.pillar.clone()),
+                    MeshMaterial3d(assets.material.clone()),
+                    Transform::from_translation(top.with_y(bottom + pillar_height / 2.))
+                        .with_scale(Vec3::new(1., pillar_height, 1.)),
+                ));
+            }
+        });
+}
+
+/// Spawn the portal of a tunnel at one of its ends, a stone frame around a dark opening
+/// facing away from the tunnel
+fn spawn_portal(commands: &mut Commands, assets: &BridgeAssets, pos: Vec3, inward: Vec3) {
+    let width = 2. * DECK_HALF_WIDTH;
+    let side = Vec3::new(PORTAL_THICKNESS, PORTAL_HEIGHT, PORTAL_THICKNESS);
+    let parts = [
+        (
+            Vec3::new(-(width + PORTAL_THICKNESS) / 2., PORTAL_HEIGHT / 2., 0.),
+            side,
+            &assets.stone,
+        ),
+        (
+            Vec3::new((width + PORTAL_THICKNESS) / 2., PORTAL_HEIGHT / 2., 0.),
+            side,
+            &assets.stone,
+        ),
+        (
+            Vec3::new(0., PORTAL_HEIGHT + PORTAL_THICKNESS / 2., 0.),
+            Vec3::new(
+                width + 2. * PORTAL_THICKNESS,
+                PORTAL_THICKNESS,
+                PORTAL_THICKNESS,
+            ),
+            &assets.stone,
+        ),
+        // slightly inside, so that it doesn't flicker with the frame
+        (
+            Vec3::new(0., PORTAL_HEIGHT / 2., -PORTAL_THICKNESS),
+            Vec3::new(width, PORTAL_HEIGHT, 0.05),
+            &assets.opening,
+        ),
+    ];
+    commands
+        .spawn((
+            Name::new("tunnel portal"),
+            Transform::from_translation(pos).looking_to(inward.with_y(0.), Vec3::Y),
+            Visibility::default(),
+            StateScoped(GameState::InGame),
+        ))
+        .with_children(|parent| {
+            for (offset, scale, material) in parts {
+                parent.spawn((
+                    Mesh3d(assets.block.clone()),
+                    MeshMaterial3d(material.clone()),
+                    Transform::from_translation(offset).with_scale(scale),
+                ));
+            }
+        });
+}
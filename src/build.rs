@@ -1,18 +1,45 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
 use bevy::{
     asset::LoadedFolder,
-    math::NormedVectorSpace,
+    math::{I64Vec2, NormedVectorSpace},
     pbr::{
+        NotShadowCaster,
         decal::{ForwardDecal, ForwardDecalMaterial, ForwardDecalMaterialExt},
         wireframe::{Wireframe, WireframeColor},
     },
+    picking::hover::HoverMap,
     prelude::*,
     render::primitives::Aabb,
 };
+use rhai::Dynamic;
 
 use crate::{
+    audio::{PlaySound, Sound},
+    context_menu::no_context_menu,
+    editor::EditorState,
+    extraction::{RIVERSIDE_REACH, river_beside},
+    input_map::{Action, Actions},
+    localization::Localization,
     map::{BuildingInstance, Chunk, GRID_SQUARE_SIZE, IsGround, Map, PatchOp},
     mapgen::Continent,
-    sim::RhaiScript,
+    menu::GameState,
+    pause_menu::{Pause, Settings},
+    plan::{Planned, PlanningMode},
+    pollution::RIVER_AMOUNT,
+    replication::TerrainOp,
+    script_api::ScriptEvent,
+    shaders::{BuildMaterial, BuildShader, OutlineMaterial, ToonParams},
+    signs::{SIGN_SCALE, SignBillboard, SignLabel},
+    sim::{RhaiScript, Sim},
+    toasts::Toasts,
+    zones::ZoneRules,
 };
 
 /// An id for a building, serve to identify which building corresponds to a mesh.
@@ -23,6 +50,11 @@ pub struct BuildId(pub Handle<Building>);
 #[derive(Component)]
 pub struct SelectedBuild;
 
+/// A part dragged from the build list. Its ghost follows the cursor while the button is
+/// held, and it is placed when released over the world.
+#[derive(Component)]
+pub struct DraggedFromList;
+
 /// Whether a part is resizable.
 #[derive(Component)]
 pub struct Resizable;
@@ -40,26 +72,109 @@ pub struct BuildPlugin;
 
 impl Plugin for BuildPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, (setup_parts, setup_highlight));
+        app.add_systems(Startup, setup_parts);
         app.add_systems(
             Update,
             (
                 spawn_build_from_part_id,
-                build_follow_cursor,
-                place_build,
+                // no placing through the pause menu
+                (
+                    build_follow_cursor,
+                    place_build,
+                    select_world_part.run_if(no_context_menu),
+                )
+                    .run_if(in_state(Pause::Running)),
                 snapping_mode,
-                select_world_part,
                 compute_aabb,
-            ),
+                handle_spawn_requests,
+                finish_pending_placements,
+                finish_restores,
+                toast_constructions,
+                style_parts,
+                restore_parts.after(style_parts),
+                outline_parts.after(style_parts),
+            )
+                .run_if(in_state(GameState::InGame)),
         );
-        app.add_observer(on_add_highlight);
-        app.add_observer(on_remove_highlight);
+        app.add_event::<SpawnBuilding>();
+        app.add_event::<BuildingPlaced>();
+        app.add_event::<BuildingRemoved>();
+        app.insert_resource(GameIds::default());
+        app.init_resource::<OutlineMaterials>();
+        app.add_observer(on_add_instance);
+        app.add_observer(on_remove_instance);
         app.insert_resource(SavedShapes::default());
         app.insert_resource(Snapping::One);
         app.insert_resource(Buildings::default());
+        app.add_systems(OnExit(GameState::InGame), despawn_buildings);
+    }
+}
+
+/// Leaving the game drops the placed buildings, and the one being placed
+fn despawn_buildings(mut commands: Commands, buildings: Query<Entity, With<BuildId>>) {
+    for e in &buildings {
+        commands.entity(e).despawn();
+    }
+}
+
+/// A stable identifier for a placed building, usable from scripts and external tools.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct GameId(pub u64);
+
+/// Generator of unique `GameId`s. Can be cloned and shared with other threads.
+#[derive(Resource, Clone, Default)]
+pub struct GameIds(Arc<AtomicU64>);
+
+impl GameIds {
+    pub fn next(&self) -> GameId {
+        GameId(self.0.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    /// Make sure the next ids come after `id`, given to a building restored from a save
+    pub fn skip_past(&self, id: GameId) {
+        self.0.fetch_max(id.0, Ordering::Relaxed);
     }
 }
 
+/// Request to spawn a building, going through the same pipeline as manual placement.
+#[derive(Event, Clone, Debug)]
+pub struct SpawnBuilding {
+    /// Id given to the building once it is placed
+    pub id: GameId,
+    /// Name of the building, as in its asset file
+    pub name: String,
+    pub pos: Vec2,
+    /// Rotation around the vertical axis, in radians
+    pub rotation: f32,
+}
+
+/// Sent when a building is added to the map
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BuildingPlaced {
+    pub entity: Entity,
+    pub id: GameId,
+    /// Whether the building comes from a loaded save rather than being built
+    pub restored: bool,
+}
+
+/// Sent when a building is removed from the map (including when it is picked up to be moved)
+#[derive(Event, Clone, Copy, Debug)]
+pub struct BuildingRemoved {
+    pub entity: Entity,
+    pub id: GameId,
+}
+
+/// A building spawned from a `SpawnBuilding` request, waiting for its bounding box to be known.
+#[derive(Component)]
+pub struct PendingPlacement {
+    pub pos: Vec2,
+}
+
+/// A building restored from a save at its saved transform, waiting for its bounding box to be
+/// known to be registered in the map.
+#[derive(Component)]
+pub struct PendingRestore;
+
 /// A building (to be modifed with everything needed)
 #[derive(Asset, TypePath, Debug)]
 pub struct Building {
@@ -67,39 +182,142 @@ pub struct Building {
     pub name: String,
     pub size: (u64, u64),
     pub script: Option<Handle<RhaiScript>>,
+    /// Whether a driveway to the nearest road is built with the building
+    pub needs_road: bool,
+    /// Entrance socket, relative to the center of the footprint
+    pub entrance: Vec2,
+    /// Tab of the build menu the building is listed in
+    pub category: String,
+    /// Extra words the build menu search matches
+    pub tags: Vec<String>,
+    pub description: String,
+    /// Resources needed to build it
+    pub cost: BTreeMap<String, f64>,
+    pub placement: Placement,
+    /// Pollution added around it every sim tick
+    pub pollution: f32,
+    /// Residents it can house
+    pub housing: u32,
+    /// Workers it employs
+    pub jobs: u32,
+    /// Research points generated every sim tick with all its jobs filled
+    pub research: f32,
+    /// Technology to research before it can be built
+    pub tech: Option<String>,
+    /// Height the water is raised to behind it, for the dams
+    pub dam: f32,
+    /// Water pumped from the river beside it every sim tick, at most
+    pub water: f32,
+    /// Fish caught in the river beside it every sim tick, at most
+    pub fish: f32,
+    /// Whether fires spread to it
+    pub flammable: bool,
+}
+
+/// Distance from the mouth of a river within which coast buildings can be placed
+const ESTUARY_REACH: f32 = 20.;
+/// Distance past the footprint of a coast building the sea must be within
+const COAST_REACH: f32 = 3.;
+
+/// Where a building can be placed
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, serde::Deserialize)]
+pub enum Placement {
+    #[default]
+    Anywhere,
+    /// On land by the sea, near the mouth of a river
+    Coast,
+    /// Across a river
+    River,
+    /// On land by a river
+    Riverside,
+}
+
+impl Placement {
+    /// Whether a footprint, as a (min, max) rectangle, is a valid place
+    pub fn allows(self, map: &Map, area: (Vec2, Vec2)) -> bool {
+        match self {
+            Placement::Anywhere => true,
+            Placement::Coast => {
+                let center = (area.0 + area.1) / 2.;
+                let at = |p: Vec2| Vec3::new(p.x, 0., p.y);
+                if map.continent.is_sea(at(center))
+                    || !map
+                        .continent
+                        .estuaries()
+                        .any(|estuary| estuary.xz().distance(center) < ESTUARY_REACH)
+                {
+                    return false;
+                }
+                // the sea right past one of the sides of the footprint
+                let reach = (area.1 - area.0).max_element() / 2. + COAST_REACH;
+                (0..16).any(|i| {
+                    let dir = Vec2::from_angle(i as f32 * std::f32::consts::TAU / 16.);
+                    map.continent.is_sea(at(center + dir * reach))
+                })
+            }
+            Placement::River => {
+                let center = (area.0 + area.1) / 2.;
+                let pos = Vec3::new(center.x, 0., center.y);
+                let (x, y) = map.continent.from_world(&pos);
+                !map.continent.is_sea(pos) && map.continent.get_hydro(x, y).amount >= RIVER_AMOUNT
+            }
+            Placement::Riverside => {
+                let center = (area.0 + area.1) / 2.;
+                let pos = Vec3::new(center.x, 0., center.y);
+                let (x, y) = map.continent.from_world(&pos);
+                !map.continent.is_sea(pos)
+                    && map.continent.get_hydro(x, y).amount < RIVER_AMOUNT
+                    && river_beside(&map.continent, area, RIVERSIDE_REACH).is_some()
+            }
+        }
+    }
+
+    /// Localization key of the toast shown when a building is placed where it can't be
+    pub fn toast_key(self) -> &'static str {
+        match self {
+            Placement::Anywhere | Placement::Coast => "toast-needs-coast",
+            Placement::River => "toast-needs-river",
+            Placement::Riverside => "toast-needs-riverside",
+        }
+    }
 }
 
 /// Split between zoning and individual buildings (and maybe fmroe things in the future, e.g. roads)
 #[derive(Debug)]
 pub enum BuildingType {
-    Zone { color: Color },
-    Single { model: Handle<Scene>, scale: f32 },
-    Tool { op: PatchOp, color: Color },
+    Zone {
+        color: Color,
+        rules: ZoneRules,
+    },
+    Single {
+        model: Handle<Scene>,
+        scale: f32,
+        /// Spawned as a scene of its own rather than drawn instanced, to be changed per building
+        unique: bool,
+    },
+    Tool {
+        op: PatchOp,
+        color: Color,
+    },
+    Sign {
+        color: Color,
+        text: String,
+    },
 }
 
 #[derive(Component)]
 pub struct Highlighted;
 
+/// A placed building switched off by the player. Its script doesn't run.
+#[derive(Component)]
+pub struct Disabled;
+
 #[derive(Resource, Default)]
 pub struct Buildings(pub Handle<LoadedFolder>);
 
 #[derive(Resource, Default)]
 pub struct SavedShapes(pub Vec<Handle<Mesh>>);
 
-pub fn setup_highlight(mut commands: Commands) {
-    commands.spawn((
-        SpotLight {
-            color: bevy::color::palettes::css::ORANGE_RED.into(),
-            intensity: 1e9,
-            range: 100.,
-            outer_angle: 0.1,
-            inner_angle: 0.02,
-            ..default()
-        },
-        Transform::from_translation(Vec3::new(0., -10., 0.)),
-        HighlightLight,
-    ));
-}
 /// Generate the parts, that will later serve to generate the buttons.
 pub fn setup_parts(
     mut meshes: ResMut<Assets<Mesh>>,
@@ -139,14 +357,16 @@ struct ToolInstance {
 fn spawn_build_from_part_id(
     mut commands: Commands,
     shapes: Res<SavedShapes>,
-    interaction_query: Query<(Entity, &BuildId), Without<Transform>>,
-    button: Res<ButtonInput<MouseButton>>,
+    interaction_query: Query<(Entity, &BuildId, Has<DraggedFromList>), Without<Transform>>,
+    actions: Actions,
     selected_part_query: Option<Single<Entity, With<SelectedBuild>>>,
     asset_server: Res<AssetServer>,
     mut decal_standard_materials: ResMut<Assets<ForwardDecalMaterial<StandardMaterial>>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
     buildings: Res<Assets<Building>>,
 ) {
-    if button.pressed(MouseButton::Left) {
+    // a part dragged from the list shows up right away, the others once the click is over
+    if actions.pressed(Action::Place) && !interaction_query.iter().any(|(_, _, dragged)| dragged) {
         return;
     }
 
@@ -156,17 +376,17 @@ fn spawn_build_from_part_id(
         };
     }
 
-    for (e, p) in &interaction_query {
+    for (e, p, _) in &interaction_query {
         let part = buildings.get(&p.0).unwrap(); //FIXME
 
         match &part.typ {
-            BuildingType::Single { model, scale } => commands.entity(e).insert((
+            BuildingType::Single { model, scale, .. } => commands.entity(e).insert((
                 SceneRoot(model.clone()),
                 Transform::from_scale(Vec3::splat(*scale)),
                 SelectedBuild,
                 Visibility::Hidden,
             )),
-            BuildingType::Zone { color } => commands.entity(e).insert((
+            BuildingType::Zone { color, .. } => commands.entity(e).insert((
                 Mesh3d(shapes.0[0].clone()),
                 Wireframe,
                 WireframeColor {
@@ -200,6 +420,14 @@ fn spawn_build_from_part_id(
                 SelectedBuild,
                 Visibility::Hidden,
             )),
+            BuildingType::Sign { color, text } => commands.entity(e).insert((
+                Mesh3d(shapes.0[0].clone()),
+                MeshMaterial3d(materials.add(StandardMaterial::from(*color))),
+                Transform::from_scale(SIGN_SCALE),
+                SignLabel(text.clone()),
+                SelectedBuild,
+                Visibility::Hidden,
+            )),
         };
     }
 }
@@ -210,7 +438,13 @@ fn compute_aabb(
     mut commands: Commands,
     children_query: Query<(&Children, &Transform)>,
     aabb_query: Query<(&Aabb, &Transform)>,
-    selected_part_query: Option<Single<(Entity, &Children), (With<SelectedBuild>, Without<Aabb>)>>,
+    parts_query: Query<
+        (Entity, &Children),
+        (
+            Or<(With<SelectedBuild>, With<PendingPlacement>)>,
+            Without<Aabb>,
+        ),
+    >,
 ) {
     fn combine_aabb(x: &mut Aabb, y: &Aabb, offset: Vec3A) {
         *x = Aabb::from_min_max(
@@ -218,8 +452,7 @@ fn compute_aabb(
             x.max().max(y.max() + offset).into(),
         )
     }
-    if let Some(query) = selected_part_query {
-        let (entity, children) = *query;
+    for (entity, children) in &parts_query {
         let mut aabb = Aabb::from_min_max(Vec3::splat(1e10), Vec3::splat(-1e10));
         let mut stack: Vec<(Entity, Vec3)> = children.iter().map(|e| (e, Vec3::ZERO)).collect();
         while let Some((e, position)) = stack.pop() {
@@ -234,14 +467,44 @@ fn compute_aabb(
                 );
             }
         }
+        // the scene might not be fully loaded yet
+        if aabb.half_extents.x < 0. {
+            continue;
+        }
         commands.entity(entity).insert(aabb);
     }
 }
 
 /// Make the selected part follow the cursor
+/// A point of the terrain hit by a ray
+pub struct TerrainHit {
+    pub point: Vec3,
+    pub normal: Vec3,
+    /// Position of the chunk that was hit
+    pub chunk: I64Vec2,
+}
+
+/// Cast a ray on the spawned chunks, ignoring the buildings
+pub fn cast_to_terrain(
+    ray_cast: &mut MeshRayCast,
+    ray: Ray3d,
+    chunks: &Query<&IsGround>,
+) -> Option<TerrainHit> {
+    let filter = |entity: Entity| chunks.contains(entity);
+    let settings = MeshRayCastSettings::default()
+        .always_early_exit()
+        .with_filter(&filter);
+    let (entity, hit) = ray_cast.cast_ray(ray, &settings).first()?;
+    Some(TerrainHit {
+        point: hit.point,
+        normal: hit.normal.normalize(),
+        chunk: chunks.get(*entity).ok()?.0,
+    })
+}
+
 fn build_follow_cursor(
     mut ray_cast: MeshRayCast,
-    camera_query: Single<(&Camera, &GlobalTransform)>,
+    camera_query: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
     windows: Single<&Window>,
     selected_part_query: Option<
         Single<
@@ -251,12 +514,13 @@ fn build_follow_cursor(
                 &Aabb,
                 &mut Visibility,
                 Option<&Resizable>,
+                Has<DraggedFromList>,
             ),
             With<SelectedBuild>,
         >,
     >,
     map: Res<Map>,
-    button: Res<ButtonInput<MouseButton>>,
+    actions: Actions,
     snapping: Res<Snapping>,
     mut place_point: Local<Vec2>,
     chunks: Query<&IsGround>,
@@ -274,21 +538,14 @@ fn build_follow_cursor(
     let Ok(ray) = camera.viewport_to_world(camera_transform, cursor_position) else {
         return;
     };
-    let (_e, mut part_transform, aabb, mut visibility, resizable) = selpart.into_inner();
-    // Cast the ray to get hit to the nearest different object
-
-    let filter = |entity: Entity| chunks.contains(entity);
-    let settings = MeshRayCastSettings::default()
-        .always_early_exit()
-        .with_filter(&filter);
-    let hits = ray_cast.cast_ray(ray, &settings);
+    let (_e, mut part_transform, aabb, mut visibility, resizable, dragged) = selpart.into_inner();
 
-    let (point, _normal) = if let Some((_, hit)) = hits.first() {
+    let point = if let Some(hit) = cast_to_terrain(&mut ray_cast, ray, &chunks) {
         *visibility = Visibility::Visible;
-        (hit.point, hit.normal.normalize())
+        hit.point
     } else {
         *visibility = Visibility::Hidden;
-        (Vec3::ZERO, Vec3::Y)
+        Vec3::ZERO
     };
 
     let point2d = Vec2::new(point.x, point.z);
@@ -308,14 +565,15 @@ fn build_follow_cursor(
         .mul_vec3(Vec3::from(aabb.half_extents) * part_transform.scale)
         .project_onto(Vec3::Y);
     if resizable.is_some()
-        && (button.pressed(MouseButton::Left) || button.just_pressed(MouseButton::Left))
+        && !dragged
+        && (actions.pressed(Action::Place) || actions.just_pressed(Action::Place))
     {
         let scale = point2d - *place_point;
         let scale = scale.abs().max(Vec2::new(0.1, 0.1)) * scale.signum();
         part_transform.scale = Vec3::new(scale.x, 1., scale.y);
         part_transform.translation =
             Vec3::new(place_point.x, 0., place_point.y) + he * part_transform.scale;
-    } else if !button.just_released(MouseButton::Left) {
+    } else if !actions.just_released(Action::Place) {
         *place_point = point2d;
         //part_transform.rotation = Quat::from_rotation_arc(Vec3::Y, normal);
         let center = Vec3::from(aabb.center) * part_transform.scale;
@@ -329,66 +587,396 @@ fn build_follow_cursor(
 fn place_build(
     mut commands: Commands,
     selected_part_query: Option<
-        Single<(Entity, &Transform, Option<&ToolInstance>, &Aabb, &BuildId), With<SelectedBuild>>,
+        Single<
+            (
+                Entity,
+                &Transform,
+                Option<&ToolInstance>,
+                &Aabb,
+                &BuildId,
+                Has<DraggedFromList>,
+            ),
+            With<SelectedBuild>,
+        >,
     >,
     mut map: ResMut<Map>,
     buildings: Res<Assets<Building>>,
-    button: Res<ButtonInput<MouseButton>>,
-    key: Res<ButtonInput<KeyCode>>,
+    actions: Actions,
     mut meshes: ResMut<Assets<Mesh>>,
+    planning: Res<PlanningMode>,
+    mut plan_order: Local<u64>,
+    mut terrain_ops: EventWriter<TerrainOp>,
+    sim: Res<Sim>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+    (hover_map, nodes): (Res<HoverMap>, Query<(), With<Node>>),
+    mut sounds: EventWriter<PlaySound>,
+    editor: Res<State<EditorState>>,
 ) {
-    if button.just_released(MouseButton::Left) {
+    // the editor places the buildings anywhere, researched or not
+    let free = *editor.get() == EditorState::On;
+    if actions.just_released(Action::Place) {
         if let Some(query) = selected_part_query {
-            let (e, transform, tool, aabb, bid) = *query;
-            let (trsl, radius, op) = if let Some(ti) = tool {
-                (transform.translation, ti.radius, ti.op)
-            } else {
-                (
-                    transform.translation
-                        + (Vec3::from(aabb.center) - Vec3::new(0., aabb.half_extents.y - 0.05, 0.))
-                            * transform.scale,
-                    (aabb.half_extents.xz() * transform.scale.xz()).norm() * 2.,
-                    PatchOp::Flatten,
-                )
-            };
-            let chunk_pos_x = (transform.translation.x / Chunk::WORLD_CHUNK_SIZE).floor() as i64;
-            let chunk_pos_z = (transform.translation.z / Chunk::WORLD_CHUNK_SIZE).floor() as i64;
-            let chunk = map.get_chunk_mut(&(chunk_pos_x, chunk_pos_z).into());
-            //TODO too convoluted here. Make separate chunk intersect detection.
-            let add_patches = chunk.patch(&mut *meshes, &trsl, radius, op);
-            for (off_x, off_z) in add_patches {
-                let chunk = map.get_chunk_mut(&(chunk_pos_x + off_x, chunk_pos_z + off_z).into());
-                chunk.patch(&mut *meshes, &trsl, radius, op);
+            let (e, transform, tool, aabb, bid, dragged) = *query;
+            if dragged {
+                commands.entity(e).remove::<DraggedFromList>();
+                // released over the ui, like a click on the button : the part stays selected
+                let over_ui = hover_map
+                    .values()
+                    .any(|hits| hits.keys().any(|hit| nodes.contains(*hit)));
+                if over_ui {
+                    return;
+                }
             }
-            if !(key.pressed(KeyCode::ControlLeft) || key.pressed(KeyCode::ControlRight)) {
-                commands.entity(e).remove::<SelectedBuild>();
+            if tool.is_none() && !map.is_area_free(footprint(transform, aabb)) {
+                warn!("Can't place a building here : the area is occupied");
+                toasts.warning(localization.get("toast-area-occupied"));
+                sounds.write(PlaySound(Sound::Invalid));
+                return;
+            }
+            let placement = buildings
+                .get(&bid.0)
+                .map(|b| b.placement)
+                .unwrap_or_default();
+            if tool.is_none() && !free && !placement.allows(&map, footprint(transform, aabb)) {
+                warn!("Can't place a building here : it must be placed {placement:?}");
+                toasts.warning(localization.get(placement.toast_key()));
+                sounds.write(PlaySound(Sound::Invalid));
+                return;
             }
-            if let Some(building) = buildings.get(&bid.0) {
-                if let BuildingType::Single { .. } = building.typ {
-                    let instance = BuildingInstance {
-                        building: bid.0.clone(),
-                        pos: transform.translation.xz() + aabb.min().xz() * transform.scale.xz(),
-                        half_extents: aabb.half_extents.xz(),
-                        entity: e,
-                    };
-                    map.entities.insert(instance.clone());
-                    commands.entity(e).insert(instance);
+            let locked = buildings.get(&bid.0).filter(|b| {
+                if free {
+                    return false;
                 }
+                let world = sim.script_world.0.lock().unwrap();
+                !world.research.allows(b)
+            });
+            if let Some(tech) = locked.and_then(|b| b.tech.clone()) {
+                warn!("Can't place a building here : {} isn't researched", tech);
+                toasts.warning(localization.get_args("toast-tech-locked", &[("tech", tech)]));
+                sounds.write(PlaySound(Sound::Invalid));
+                return;
             }
+            if let Some(ti) = tool {
+                terrain_ops.write(TerrainOp {
+                    op: ti.op,
+                    center: transform.translation,
+                    radius: ti.radius,
+                    strength: 1.,
+                    tick: sim.tick,
+                });
+            } else if planning.0 {
+                // planned buildings are only ghosts until the plan is committed
+                *plan_order += 1;
+                commands.entity(e).insert(Planned {
+                    order: *plan_order,
+                    aabb: *aabb,
+                });
+            } else {
+                realize_building(
+                    &mut commands,
+                    &mut map,
+                    &mut meshes,
+                    &buildings,
+                    e,
+                    transform,
+                    aabb,
+                    bid,
+                );
+            }
+            if !actions.pressed(Action::KeepPlacing) {
+                commands.entity(e).remove::<SelectedBuild>();
+            }
+        }
+    }
+}
+
+/// Get the footprint of a part on the ground, as a (min, max) rectangle.
+pub fn footprint(transform: &Transform, aabb: &Aabb) -> (Vec2, Vec2) {
+    let center = transform.translation.xz() + aabb.center.xz() * transform.scale.xz();
+    let half_extents = (transform
+        .rotation
+        .mul_vec3(Vec3::from(aabb.half_extents) * transform.scale))
+    .xz()
+    .abs();
+    (center - half_extents, center + half_extents)
+}
+
+/// Turn a placed part into an actual building : flatten the terrain under it and register it in the map.
+pub fn realize_building(
+    commands: &mut Commands,
+    map: &mut Map,
+    meshes: &mut Assets<Mesh>,
+    buildings: &Assets<Building>,
+    e: Entity,
+    transform: &Transform,
+    aabb: &Aabb,
+    bid: &BuildId,
+) {
+    let trsl = transform.translation
+        + (Vec3::from(aabb.center) - Vec3::new(0., aabb.half_extents.y - 0.05, 0.))
+            * transform.scale;
+    let radius = (aabb.half_extents.xz() * transform.scale.xz()).norm() * 2.;
+    // the buildings growing in the zones flatten the ground under them themselves
+    let zone = buildings
+        .get(&bid.0)
+        .is_some_and(|b| matches!(b.typ, BuildingType::Zone { .. }));
+    if !zone {
+        map.patch(meshes, &trsl, radius, PatchOp::Flatten);
+    }
+    register_building(commands, map, buildings, e, transform, aabb, bid);
+}
+
+/// Register a building in the map, leaving the terrain under it as it is.
+pub fn register_building(
+    commands: &mut Commands,
+    map: &mut Map,
+    buildings: &Assets<Building>,
+    e: Entity,
+    transform: &Transform,
+    aabb: &Aabb,
+    bid: &BuildId,
+) {
+    if let Some(building) = buildings.get(&bid.0) {
+        if let BuildingType::Single { .. } | BuildingType::Sign { .. } = building.typ {
+            let instance = BuildingInstance {
+                building: bid.0.clone(),
+                pos: transform.translation.xz() + aabb.min().xz() * transform.scale.xz(),
+                half_extents: aabb.half_extents.xz(),
+                entity: e,
+            };
+            map.entities.insert(instance.clone());
+            commands.entity(e).insert(instance);
         }
     }
 }
 
-fn select_world_part(
+/// Tell the scripts a requested building couldn't be spawned, with a
+/// `building_spawn_failed` event carrying its id
+fn spawn_failed(sim: &Sim, id: GameId) {
+    let mut world = sim.script_world.0.lock().unwrap();
+    world.emitted.push(ScriptEvent {
+        name: "building_spawn_failed".to_string(),
+        payload: Dynamic::from_int(id.0 as i64),
+        tick: sim.tick,
+    });
+}
+
+/// Spawn the buildings requested by `SpawnBuilding` events
+fn handle_spawn_requests(
+    mut commands: Commands,
+    mut requests: EventReader<SpawnBuilding>,
+    mut buildings: ResMut<Assets<Building>>,
+    shapes: Res<SavedShapes>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    sim: Res<Sim>,
+) {
+    for request in requests.read() {
+        let Some(id) = buildings
+            .iter()
+            .find(|(_, b)| b.name == request.name)
+            .map(|(id, _)| id)
+        else {
+            warn!("Can't spawn unknown building {}", request.name);
+            spawn_failed(&sim, request.id);
+            continue;
+        };
+        let handle = buildings.get_strong_handle(id).unwrap();
+        let building = buildings.get(id).unwrap();
+        let rotation = Quat::from_rotation_y(request.rotation);
+        let Some(e) = spawn_building_model(
+            &mut commands,
+            handle,
+            building,
+            rotation,
+            &shapes,
+            &mut materials,
+        ) else {
+            warn!(
+                "Only single buildings and signs can be spawned, not {}",
+                request.name
+            );
+            spawn_failed(&sim, request.id);
+            continue;
+        };
+        commands
+            .entity(e)
+            .insert((request.id, PendingPlacement { pos: request.pos }));
+    }
+}
+
+/// Spawn the model of a building, rotated around the vertical axis, without placing it.
+/// Zones and tools have no model, and nothing is spawned for them.
+pub fn spawn_building_model(
+    commands: &mut Commands,
+    handle: Handle<Building>,
+    building: &Building,
+    rotation: Quat,
+    shapes: &SavedShapes,
+    materials: &mut Assets<StandardMaterial>,
+) -> Option<Entity> {
+    let bundle = (Name::new("building"), BuildId(handle));
+    match &building.typ {
+        BuildingType::Single { model, scale, .. } => Some(
+            commands
+                .spawn((
+                    bundle,
+                    SceneRoot(model.clone()),
+                    Transform::from_scale(Vec3::splat(*scale)).with_rotation(rotation),
+                ))
+                .id(),
+        ),
+        BuildingType::Sign { color, text } => Some(
+            commands
+                .spawn((
+                    bundle,
+                    Mesh3d(shapes.0[0].clone()),
+                    MeshMaterial3d(materials.add(StandardMaterial::from(*color))),
+                    Transform::from_scale(SIGN_SCALE).with_rotation(rotation),
+                    SignLabel(text.clone()),
+                ))
+                .id(),
+        ),
+        BuildingType::Zone { .. } | BuildingType::Tool { .. } => None,
+    }
+}
+
+/// Put the requested buildings on the ground once their bounding box is known, and build them.
+fn finish_pending_placements(
+    mut commands: Commands,
+    mut pending: Query<(
+        Entity,
+        &mut Transform,
+        &Aabb,
+        &BuildId,
+        &GameId,
+        &PendingPlacement,
+    )>,
+    mut map: ResMut<Map>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    buildings: Res<Assets<Building>>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+    sim: Res<Sim>,
+) {
+    for (e, mut transform, aabb, bid, id, pending) in &mut pending {
+        let he_proj = transform
+            .rotation
+            .mul_vec3(Vec3::from(aabb.half_extents) * transform.scale)
+            .project_onto(Vec3::Y);
+        let center = Vec3::from(aabb.center) * transform.scale;
+        let ground = Vec3::new(pending.pos.x, 0., pending.pos.y);
+        transform.translation = ground.with_y(map.get_height(ground)) + he_proj - center;
+
+        if !map.is_area_free(footprint(&transform, aabb)) {
+            warn!(
+                "Can't spawn a building at {} : the area is occupied",
+                pending.pos
+            );
+            let position = [("position", pending.pos.to_string())];
+            toasts.warning(localization.get_args("toast-spawn-area-occupied", &position));
+            spawn_failed(&sim, *id);
+            commands.entity(e).despawn();
+            continue;
+        }
+        realize_building(
+            &mut commands,
+            &mut map,
+            &mut meshes,
+            &buildings,
+            e,
+            &transform,
+            aabb,
+            bid,
+        );
+        commands.entity(e).remove::<PendingPlacement>();
+    }
+}
+
+/// Register the buildings restored from a save once their bounding box is known.
+/// The terrain under them comes with the save, so it isn't flattened again.
+fn finish_restores(
+    mut commands: Commands,
+    restored: Query<(Entity, &Transform, &Aabb, &BuildId), With<PendingRestore>>,
+    mut map: ResMut<Map>,
+    buildings: Res<Assets<Building>>,
+) {
+    for (e, transform, aabb, bid) in &restored {
+        register_building(&mut commands, &mut map, &buildings, e, transform, aabb, bid);
+        commands.entity(e).remove::<PendingRestore>();
+    }
+}
+
+/// Give an id to new building instances, and notify their placement.
+fn on_add_instance(
+    trigger: Trigger<OnAdd, BuildingInstance>,
+    mut commands: Commands,
+    ids: Query<&GameId>,
+    restoring: Query<(), With<PendingRestore>>,
+    game_ids: Res<GameIds>,
+    mut placed: EventWriter<BuildingPlaced>,
+) {
+    let entity = trigger.target();
+    let id = match ids.get(entity) {
+        Ok(id) => *id,
+        Err(_) => {
+            let id = game_ids.next();
+            commands.entity(entity).insert(id);
+            id
+        }
+    };
+    placed.write(BuildingPlaced {
+        entity,
+        id,
+        restored: restoring.contains(entity),
+    });
+}
+
+/// Notify the buildings placed on the map
+fn toast_constructions(
+    mut placed: EventReader<BuildingPlaced>,
+    ids: Query<&BuildId>,
+    buildings: Res<Assets<Building>>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) {
+    for BuildingPlaced {
+        entity, restored, ..
+    } in placed.read()
+    {
+        if *restored {
+            continue;
+        }
+        let Some(building) = ids.get(*entity).ok().and_then(|id| buildings.get(&id.0)) else {
+            continue;
+        };
+        let name = [("name", building.name.clone())];
+        toasts.success(localization.get_args("toast-building-built", &name));
+    }
+}
+
+fn on_remove_instance(
+    trigger: Trigger<OnRemove, BuildingInstance>,
+    ids: Query<&GameId>,
+    mut removed: EventWriter<BuildingRemoved>,
+) {
+    let entity = trigger.target();
+    if let Ok(id) = ids.get(entity) {
+        removed.write(BuildingRemoved { entity, id: *id });
+    }
+}
+
+pub(crate) fn select_world_part(
     mut commands: Commands,
     selected_part_query: Option<Single<Entity, With<SelectedBuild>>>,
     highlighted_part_query: Option<Single<Entity, With<Highlighted>>>,
     buildings: Query<&BuildingInstance>,
     parent_query: Query<&ChildOf>,
+    sign_labels: Query<&SignBillboard>,
     mut ray_cast: MeshRayCast,
-    camera_query: Single<(&Camera, &GlobalTransform)>,
+    camera_query: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
     windows: Single<&Window>,
-    keyboard_input: Res<ButtonInput<MouseButton>>,
+    actions: Actions,
     mut map: ResMut<Map>,
     chunks: Query<&IsGround>,
 ) {
@@ -413,10 +1001,14 @@ fn select_world_part(
             while let Ok(ChildOf(parent)) = parent_query.get(e) {
                 e = *parent;
             }
+            // the label of a sign stands for it
+            if let Ok(label) = sign_labels.get(e) {
+                e = label.sign;
+            }
             //checks if hit is a building
             if let Ok(instance) = buildings.get(e) {
                 //if clicked, select it
-                if keyboard_input.just_released(MouseButton::Left) {
+                if actions.just_released(Action::Place) {
                     highlighted_part_query.map(|e| {
                         commands.entity(*e).remove::<Highlighted>();
                     });
@@ -443,10 +1035,10 @@ fn select_world_part(
 
                 if let Ok(IsGround(chunk_position)) = chunks.get(e) {
                     let pos = hit.point;
-                    let continent_pos_offset = (chunk_position * (Chunk::CHUNK_SIZE as i64 - 1)
+                    let continent_pos_offset = (chunk_position * (Chunk::size() as i64 - 1)
                         + Continent::CONTINENT_SIZE as i64 / 2)
                         .abs()
-                        % ((Continent::CONTINENT_SIZE - Chunk::CHUNK_SIZE) as i64);
+                        % ((Continent::CONTINENT_SIZE - Chunk::size()) as i64);
                     let in_chunk_pos = (pos
                         - map.chunks.get(chunk_position).unwrap().get_world_pos())
                         / GRID_SQUARE_SIZE;
@@ -454,7 +1046,7 @@ fn select_world_part(
                         in_chunk_pos.x.floor() as u32 + continent_pos_offset.x as u32,
                         in_chunk_pos.z.floor() as u32 + continent_pos_offset.y as u32,
                     );
-                    let height = &map.continent[continent_index];
+                    let height = map.continent.point(continent_index.0, continent_index.1);
                     let hydro = map
                         .continent
                         .get_hydro(continent_index.0, continent_index.1);
@@ -464,7 +1056,7 @@ fn select_world_part(
                         .get(&hydro.source)
                         .or(map.continent.to_lake.get(&hydro.source))
                         .map(|i| Continent::h2xy(*i));
-                    if keyboard_input.just_pressed(MouseButton::Left) {
+                    if actions.just_pressed(Action::Place) {
                         println!(
                             "{:?} {} {} - {:?} ---- {:?}",
                             continent_index, height.height, height.grad, hydro, es
@@ -476,38 +1068,221 @@ fn select_world_part(
     }
 }
 
+/// How a part drawn with the build material looks
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum PartLook {
+    /// Only the stylized shading
+    Plain,
+    Highlighted,
+    Selected,
+}
+
+/// A mesh of a part drawn with the build material : glowing when the part is highlighted or
+/// selected, or plain with the stylized shading. Keeps the material it had, to get it back.
 #[derive(Component)]
-pub struct HighlightLight;
-
-fn on_add_highlight(
-    trigger: Trigger<OnAdd, Highlighted>,
-    part_query: Query<(&Transform, &Aabb), With<BuildId>>,
-    mut light_query: Single<
-        (&mut Transform, &mut SpotLight),
-        (With<HighlightLight>, Without<BuildId>),
-    >,
+pub struct StyledMesh {
+    original: Handle<StandardMaterial>,
+    look: PartLook,
+}
+
+const HIGHLIGHT_COLOR: LinearRgba = LinearRgba::new(1., 0.3, 0.1, 0.8);
+const SELECTED_COLOR: LinearRgba = LinearRgba::new(0.2, 0.7, 1., 0.6);
+
+/// Draw the meshes of the highlighted and selected parts with a glowing rim, and all the
+/// parts with the build material when the stylized shading is on.
+/// Scenes spawn their meshes later on, so this runs every frame.
+fn style_parts(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    parts: Query<(Entity, Has<SelectedBuild>, Has<Highlighted>), With<BuildId>>,
+    children: Query<&Children>,
+    meshes: Query<&MeshMaterial3d<StandardMaterial>>,
+    styled_meshes: Query<&StyledMesh>,
+    standard_materials: Res<Assets<StandardMaterial>>,
+    mut build_materials: ResMut<Assets<BuildMaterial>>,
+    mut cache: Local<HashMap<(AssetId<StandardMaterial>, PartLook), Handle<BuildMaterial>>>,
 ) {
-    if let Ok((part, aabb)) = part_query.get(trigger.target()) {
-        let (light_transform, light) = &mut *light_query;
-        let pos = part.translation + Vec3::from(aabb.center) * part.scale;
-        const LIGHT_DISTANCE: f32 = 10.;
-        light_transform.translation = pos + Vec3::Y * LIGHT_DISTANCE;
-        light_transform.look_at(pos, Vec3::Y);
-        light.outer_angle =
-            ((Vec3::from(aabb.half_extents) * part.scale).norm() / LIGHT_DISTANCE).atan();
+    let toon = ToonParams::default();
+    if settings.is_changed() {
+        for handle in cache.values() {
+            if let Some(material) = build_materials.get_mut(handle) {
+                material.extension.toon = toon.uniform(settings.toon);
+            }
+        }
+    }
+    for (part, selected, highlighted) in &parts {
+        let look = match (selected, highlighted) {
+            (true, _) => PartLook::Selected,
+            (_, true) => PartLook::Highlighted,
+            _ if settings.toon => PartLook::Plain,
+            _ => continue,
+        };
+        // the selected part pulses, the highlighted one glows steadily
+        let (color, pulse_speed) = match look {
+            PartLook::Plain => (LinearRgba::NONE, 0.),
+            PartLook::Highlighted => (HIGHLIGHT_COLOR, 0.),
+            PartLook::Selected => (SELECTED_COLOR, 4.),
+        };
+        for e in std::iter::once(part).chain(children.iter_descendants(part)) {
+            let original = match (meshes.get(e), styled_meshes.get(e)) {
+                (Ok(material), _) => material.0.clone(),
+                // highlighted part that got selected, or the other way around
+                (_, Ok(mesh)) if mesh.look != look => mesh.original.clone(),
+                _ => continue,
+            };
+            let Some(base) = standard_materials.get(&original) else {
+                continue;
+            };
+            let material = cache
+                .entry((original.id(), look))
+                .or_insert_with(|| {
+                    build_materials.add(BuildMaterial {
+                        base: base.clone(),
+                        extension: BuildShader {
+                            highlight_color: color,
+                            highlight_params: Vec4::new(pulse_speed, 2., 0., 0.),
+                            toon: toon.uniform(settings.toon),
+                            shadow_color: toon.shadow_color,
+                            rim_color: toon.rim_color,
+                        },
+                    })
+                })
+                .clone();
+            commands
+                .entity(e)
+                .remove::<MeshMaterial3d<StandardMaterial>>()
+                .insert((MeshMaterial3d(material), StyledMesh { original, look }));
+        }
+    }
+}
+
+/// Give their material back to the meshes of parts no longer highlighted or selected,
+/// unless the stylized shading is on
+fn restore_parts(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    meshes: Query<(Entity, &StyledMesh)>,
+    parents: Query<&ChildOf>,
+    parts: Query<(), Or<(With<Highlighted>, With<SelectedBuild>)>>,
+) {
+    if settings.toon {
+        return;
+    }
+    for (e, mesh) in &meshes {
+        let still_highlighted = std::iter::once(e)
+            .chain(parents.iter_ancestors(e))
+            .any(|ancestor| parts.contains(ancestor));
+        if !still_highlighted {
+            commands
+                .entity(e)
+                .remove::<(MeshMaterial3d<BuildMaterial>, StyledMesh)>()
+                .insert(MeshMaterial3d(mesh.original.clone()));
+        }
     }
 }
 
-fn on_remove_highlight(
-    _trigger: Trigger<OnRemove, Highlighted>,
-    mut light_query: Single<&mut Transform, With<HighlightLight>>,
+/// Width of the outlines, as a share of the distance to the camera
+const OUTLINE_WIDTH: f32 = 0.003;
+
+/// Outline materials of the highlighted and the selected parts
+#[derive(Resource)]
+struct OutlineMaterials {
+    highlighted: Handle<OutlineMaterial>,
+    selected: Handle<OutlineMaterial>,
+}
+
+impl FromWorld for OutlineMaterials {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<OutlineMaterial>>();
+        let mut outline = |color: LinearRgba| {
+            materials.add(OutlineMaterial {
+                color,
+                params: Vec4::new(OUTLINE_WIDTH, 0., 0., 0.),
+            })
+        };
+        Self {
+            highlighted: outline(HIGHLIGHT_COLOR),
+            selected: outline(SELECTED_COLOR),
+        }
+    }
+}
+
+/// Outline drawn around a mesh of a part, as its child
+#[derive(Component)]
+struct PartOutline;
+
+/// Outline of a mesh of a part, with the look it was drawn for
+#[derive(Component)]
+struct Outlined {
+    outline: Entity,
+    look: PartLook,
+}
+
+/// Outline the meshes of the highlighted and selected parts, and remove the outlines of the
+/// parts no longer highlighted nor selected. Scenes spawn their meshes later on, so this runs
+/// every frame.
+fn outline_parts(
+    mut commands: Commands,
+    outline_materials: Res<OutlineMaterials>,
+    parts: Query<
+        (Entity, Has<SelectedBuild>),
+        (With<BuildId>, Or<(With<Highlighted>, With<SelectedBuild>)>),
+    >,
+    children: Query<&Children>,
+    meshes: Query<
+        (&Mesh3d, Option<&Outlined>),
+        (
+            Or<(With<MeshMaterial3d<StandardMaterial>>, With<StyledMesh>)>,
+            Without<PartOutline>,
+        ),
+    >,
+    outlined: Query<(Entity, &Outlined)>,
+    parents: Query<&ChildOf>,
 ) {
-    light_query.translation = Vec3::new(0., -10., 0.);
+    for (part, selected) in &parts {
+        let (look, material) = if selected {
+            (PartLook::Selected, &outline_materials.selected)
+        } else {
+            (PartLook::Highlighted, &outline_materials.highlighted)
+        };
+        for e in std::iter::once(part).chain(children.iter_descendants(part)) {
+            let Ok((mesh, outlined)) = meshes.get(e) else {
+                continue;
+            };
+            let outline = match outlined {
+                Some(outlined) if outlined.look == look => continue,
+                Some(outlined) => commands
+                    .entity(outlined.outline)
+                    .insert(MeshMaterial3d(material.clone()))
+                    .id(),
+                None => commands
+                    .spawn((
+                        Name::new("outline"),
+                        PartOutline,
+                        Mesh3d(mesh.0.clone()),
+                        MeshMaterial3d(material.clone()),
+                        NotShadowCaster,
+                        ChildOf(e),
+                    ))
+                    .id(),
+            };
+            commands.entity(e).insert(Outlined { outline, look });
+        }
+    }
+    for (e, outlined) in &outlined {
+        let still_highlighted = std::iter::once(e)
+            .chain(parents.iter_ancestors(e))
+            .any(|ancestor| parts.contains(ancestor));
+        if !still_highlighted {
+            commands.entity(outlined.outline).despawn();
+            commands.entity(e).remove::<Outlined>();
+        }
+    }
 }
 
 /// Change the snapping mode by cycling on pressing S
-fn snapping_mode(mut snapping: ResMut<Snapping>, keyboard_input: Res<ButtonInput<KeyCode>>) {
-    if keyboard_input.just_pressed(KeyCode::KeyS) {
+fn snapping_mode(mut snapping: ResMut<Snapping>, actions: Actions) {
+    if actions.just_pressed(Action::CycleSnapping) {
         *snapping = match &*snapping {
             Snapping::None => Snapping::One,
             Snapping::One => Snapping::Two,
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use bevy::{
     asset::{AssetLoader, LoadContext},
     prelude::*,
@@ -5,8 +7,10 @@ use bevy::{
 use serde::Deserialize;
 
 use crate::{
-    build::{Building, BuildingType},
+    build::{Building, BuildingType, Placement},
     map::PatchOp,
+    versioning::{Migration, Versioned, from_versioned_bytes},
+    zones::ZoneRules,
 };
 
 pub struct BuildAssetPlugin;
@@ -20,9 +24,25 @@ impl Plugin for BuildAssetPlugin {
 
 #[derive(Deserialize)]
 enum BuildingTypFile {
-    Zone { color: LinearRgba },
-    Single { model: String, scale: f32 },
-    Tool { op: PatchOp, color: LinearRgba },
+    Zone {
+        color: LinearRgba,
+        #[serde(default)]
+        rules: ZoneRules,
+    },
+    Single {
+        model: String,
+        scale: f32,
+        #[serde(default)]
+        unique: bool,
+    },
+    Tool {
+        op: PatchOp,
+        color: LinearRgba,
+    },
+    Sign {
+        color: LinearRgba,
+        text: String,
+    },
 }
 #[derive(Deserialize)]
 struct BuildingFile {
@@ -31,6 +51,45 @@ struct BuildingFile {
     typ: BuildingTypFile,
     #[serde(default)]
     script: String,
+    #[serde(default)]
+    needs_road: bool,
+    /// Entrance of the building, relative to the center of its footprint
+    #[serde(default)]
+    entrance: Option<(f32, f32)>,
+    /// Tab of the build menu, by default the type of the building
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    cost: BTreeMap<String, f64>,
+    #[serde(default)]
+    placement: Placement,
+    #[serde(default)]
+    pollution: f32,
+    #[serde(default)]
+    housing: u32,
+    #[serde(default)]
+    jobs: u32,
+    #[serde(default)]
+    research: f32,
+    #[serde(default)]
+    tech: Option<String>,
+    #[serde(default)]
+    dam: f32,
+    #[serde(default)]
+    water: f32,
+    #[serde(default)]
+    fish: f32,
+    #[serde(default)]
+    flammable: bool,
+}
+
+impl Versioned for BuildingFile {
+    const VERSION: u32 = 1;
+    const MIGRATIONS: &'static [Migration] = &[];
 }
 
 #[derive(Default)]
@@ -52,20 +111,37 @@ impl AssetLoader for BuildingLoader {
         let mut bytes = Vec::new();
 
         reader.read_to_end(&mut bytes).await?;
-        let parsed_build_file = ron::de::from_bytes::<BuildingFile>(&bytes)?;
+        let path = load_context.path().display().to_string();
+        let parsed_build_file = from_versioned_bytes::<BuildingFile>(&bytes, &path)?;
 
+        let default_category = match parsed_build_file.typ {
+            BuildingTypFile::Zone { .. } => "Zones",
+            BuildingTypFile::Single { .. } => "Buildings",
+            BuildingTypFile::Tool { .. } => "Tools",
+            BuildingTypFile::Sign { .. } => "Decoration",
+        };
         let typ = match parsed_build_file.typ {
-            BuildingTypFile::Zone { color } => BuildingType::Zone {
+            BuildingTypFile::Zone { color, rules } => BuildingType::Zone {
                 color: color.into(),
+                rules,
             },
-            BuildingTypFile::Single { model, scale } => BuildingType::Single {
+            BuildingTypFile::Single {
+                model,
+                scale,
+                unique,
+            } => BuildingType::Single {
                 model: load_context.load(GltfAssetLabel::Scene(0).from_asset(model)),
-                scale
+                scale,
+                unique,
             },
             BuildingTypFile::Tool { op, color } => BuildingType::Tool {
                 op,
                 color: color.into(),
             },
+            BuildingTypFile::Sign { color, text } => BuildingType::Sign {
+                color: color.into(),
+                text,
+            },
         };
         let script = if parsed_build_file.script.is_empty() {
             None
@@ -78,6 +154,27 @@ impl AssetLoader for BuildingLoader {
             name: parsed_build_file.name,
             size: parsed_build_file.size,
             script,
+            needs_road: parsed_build_file.needs_road,
+            entrance: parsed_build_file
+                .entrance
+                .map(|(x, z)| Vec2::new(x, z))
+                .unwrap_or_default(),
+            category: parsed_build_file
+                .category
+                .unwrap_or_else(|| default_category.to_string()),
+            tags: parsed_build_file.tags,
+            description: parsed_build_file.description,
+            cost: parsed_build_file.cost,
+            placement: parsed_build_file.placement,
+            pollution: parsed_build_file.pollution,
+            housing: parsed_build_file.housing,
+            jobs: parsed_build_file.jobs,
+            research: parsed_build_file.research,
+            tech: parsed_build_file.tech,
+            dam: parsed_build_file.dam,
+            water: parsed_build_file.water,
+            fish: parsed_build_file.fish,
+            flammable: parsed_build_file.flammable,
         })
     }
 
@@ -0,0 +1,381 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bevy::{picking::hover::HoverMap, prelude::*};
+use rhai::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audio::{PlaySound, Sound},
+    bridges::{RoadTool, is_water},
+    build::{SelectedBuild, cast_to_terrain},
+    input_map::{Action, Actions},
+    localization::Localization,
+    map::{GRID_SQUARE_SIZE, IsGround, Map, PatchOp, WaterSurface},
+    mapgen::Continent,
+    menu::GameState,
+    player_commands::{IncomingCommand, PlayerCommand, Rejection},
+    pollution::{CELL_SIZE, Cell, cell_of},
+    replication::TerrainOp,
+    script_api::SharedScriptWorld,
+    sim::Sim,
+    toasts::Toasts,
+};
+
+/// Longest canal dug at once, in world units
+pub const MAX_CANAL_LENGTH: f32 = 100.;
+/// Cost of the canals per world unit, in `bridges::COST_RESOURCE`
+pub const CANAL_COST: f32 = 0.5;
+/// Depth of the canals under the water they connect to
+const CANAL_DEPTH: f32 = 0.5;
+pub const CANAL_HALF_WIDTH: f32 = 1.;
+/// Distance from a canal within which the ground is irrigated, in world units
+const IRRIGATION_RANGE: f32 = 12.;
+/// Distance between the terrain lowerings along the canals
+const DIG_STEP: f32 = 1.;
+
+/// Canal tool (K) : canals are dug between two clicked points, one of them in a river, a lake
+/// or another canal. The terrain along the canal is lowered below the level of the water it
+/// connects to, and it fills up : the hydrology of the continent counts it as water, and the
+/// ground around it is irrigated. Scripts read the irrigation with `irrigation(x, z)` and
+/// the irrigated area with `irrigated_area()`, for the farms.
+pub struct CanalPlugin;
+
+impl Plugin for CanalPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DigCanal>()
+            .insert_resource(CanalTool::default())
+            .add_systems(Startup, setup_canal_material)
+            .add_systems(OnExit(GameState::InGame), reset_canal_tool)
+            .add_systems(
+                Update,
+                (
+                    toggle_canal_tool,
+                    use_canal_tool.after(toggle_canal_tool),
+                    dig_canals,
+                    draw_canal_water.after(dig_canals),
+                )
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// State of the canal tool
+#[derive(Resource, Default)]
+pub struct CanalTool {
+    pub active: bool,
+    /// First end of the canal being drawn
+    pub start: Option<Vec2>,
+}
+
+/// A canal to dig, validated and paid for
+#[derive(Event, Clone, Copy, Debug)]
+pub struct DigCanal {
+    pub from: Vec2,
+    pub to: Vec2,
+}
+
+/// A dug canal
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Canal {
+    pub from: Vec2,
+    pub to: Vec2,
+    /// Height of the water, the one of the water it connects to
+    pub level: f32,
+}
+
+impl Canal {
+    /// Points of the continent grid under the water of the canal
+    pub fn points(&self, continent: &Continent) -> BTreeSet<(u32, u32)> {
+        let dir = (self.to - self.from).normalize_or_zero();
+        let steps = (self.from.distance(self.to) / GRID_SQUARE_SIZE).ceil() as usize;
+        let across = (CANAL_HALF_WIDTH / GRID_SQUARE_SIZE) as i32;
+        let mut points = BTreeSet::new();
+        for i in 0..=steps {
+            let center = self.from + dir * (i as f32 * GRID_SQUARE_SIZE).min(self.length());
+            for j in -across..=across {
+                let p = center + dir.perp() * j as f32 * GRID_SQUARE_SIZE;
+                points.insert(continent.from_world(&Vec3::new(p.x, 0., p.y)));
+            }
+        }
+        points
+    }
+
+    pub fn length(&self) -> f32 {
+        self.from.distance(self.to)
+    }
+
+    fn distance_to(&self, pos: Vec2) -> f32 {
+        let length_squared = (self.to - self.from).length_squared().max(f32::EPSILON);
+        let t = ((pos - self.from).dot(self.to - self.from) / length_squared).clamp(0., 1.);
+        self.from.lerp(self.to, t).distance(pos)
+    }
+}
+
+/// Canals and the irrigation of the ground around them, shared with the scripts. Only the
+/// canals are saved, the irrigation is computed again from them.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Irrigation {
+    pub canals: Vec<Canal>,
+    /// Irrigation of the cells of the pollution grid, between 0 and 1
+    #[serde(skip)]
+    cells: BTreeMap<Cell, f32>,
+}
+
+impl Irrigation {
+    pub fn get(&self, pos: Vec2) -> f32 {
+        self.cells.get(&cell_of(pos)).copied().unwrap_or(0.)
+    }
+
+    /// Area of the irrigated ground, in square world units, weighted by its irrigation
+    pub fn area(&self) -> f32 {
+        self.cells.values().sum::<f32>() * CELL_SIZE * CELL_SIZE
+    }
+
+    fn add(&mut self, canal: Canal) {
+        self.canals.push(canal);
+        self.irrigate(canal);
+    }
+
+    fn irrigate(&mut self, canal: Canal) {
+        let reach = (IRRIGATION_RANGE / CELL_SIZE).ceil() as i32 + 1;
+        let (a, b) = (cell_of(canal.from), cell_of(canal.to));
+        for i in a.0.min(b.0) - reach..=a.0.max(b.0) + reach {
+            for j in a.1.min(b.1) - reach..=a.1.max(b.1) + reach {
+                let center = (Vec2::new(i as f32, j as f32) + 0.5) * CELL_SIZE;
+                let irrigation = 1. - canal.distance_to(center) / IRRIGATION_RANGE;
+                if irrigation > 0. {
+                    let cell = self.cells.entry((i, j)).or_default();
+                    *cell = cell.max(irrigation);
+                }
+            }
+        }
+    }
+
+    /// Compute the irrigation again from the canals, once they are loaded
+    pub fn rebuild(&mut self) {
+        self.cells.clear();
+        for canal in self.canals.clone() {
+            self.irrigate(canal);
+        }
+    }
+}
+
+/// Height of the water a canal between two points connects to : a river, a lake or another
+/// canal at one of its ends, not the sea
+pub fn connected_level(map: &Map, from: Vec2, to: Vec2) -> Option<f32> {
+    [from, to]
+        .into_iter()
+        .find(|p| is_water(map, *p) && !map.continent.is_sea(Vec3::new(p.x, 0., p.y)))
+        .map(|p| map.get_height(Vec3::new(p.x, 0., p.y)))
+}
+
+/// Points along a canal where the terrain is lowered
+pub fn dig_points(from: Vec2, to: Vec2) -> impl Iterator<Item = Vec2> {
+    let steps = (from.distance(to) / DIG_STEP).ceil().max(1.) as usize;
+    (0..=steps).map(move |i| from.lerp(to, i as f32 / steps as f32))
+}
+
+/// Check that a canal can be dug, returning the height of its water
+pub fn check_canal(map: &Map, from: Vec2, to: Vec2) -> Result<f32, Rejection> {
+    let length = from.distance(to);
+    if length > MAX_CANAL_LENGTH {
+        return Err(Rejection::CanalTooLong {
+            length,
+            max: MAX_CANAL_LENGTH,
+        });
+    }
+    connected_level(map, from, to).ok_or(Rejection::CanalNotConnected)
+}
+
+#[derive(Resource)]
+struct CanalMaterial(Handle<StandardMaterial>);
+
+/// Water surface of a canal
+#[derive(Component)]
+struct CanalWater;
+
+fn setup_canal_material(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    // the same as the rivers
+    commands.insert_resource(CanalMaterial(materials.add(StandardMaterial {
+        base_color: bevy::color::palettes::css::ROYAL_BLUE.into(),
+        ..default()
+    })));
+}
+
+fn reset_canal_tool(mut tool: ResMut<CanalTool>) {
+    *tool = CanalTool::default();
+}
+
+/// Switch the canal tool, dropping the part selected in the build menu and the road tool
+fn toggle_canal_tool(
+    mut commands: Commands,
+    actions: Actions,
+    mut tool: ResMut<CanalTool>,
+    mut road_tool: ResMut<RoadTool>,
+    selected: Query<Entity, With<SelectedBuild>>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) {
+    if !actions.just_pressed(Action::ToggleCanalTool) {
+        return;
+    }
+    tool.active = !tool.active;
+    tool.start = None;
+    if tool.active {
+        for e in &selected {
+            commands.entity(e).despawn();
+        }
+        road_tool.active = false;
+        toasts.info(localization.get("toast-canal-tool-on"));
+    } else {
+        toasts.info(localization.get("toast-canal-tool-off"));
+    }
+}
+
+/// Pick the ends of the canal on click, and preview it from the first end to the cursor, in
+/// red when it can't be dug
+fn use_canal_tool(
+    mut tool: ResMut<CanalTool>,
+    actions: Actions,
+    mut ray_cast: MeshRayCast,
+    camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    window: Single<&Window>,
+    chunks: Query<&IsGround>,
+    map: Res<Map>,
+    hover_map: Res<HoverMap>,
+    nodes: Query<(), With<Node>>,
+    mut incoming: EventWriter<IncomingCommand>,
+    mut sounds: EventWriter<PlaySound>,
+    mut gizmos: Gizmos,
+) {
+    if !tool.active {
+        return;
+    }
+    let (camera, camera_transform) = *camera;
+    let hit = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+        .and_then(|ray| cast_to_terrain(&mut ray_cast, ray, &chunks));
+    let Some(hit) = hit else {
+        return;
+    };
+    let point = hit.point.xz();
+    gizmos.sphere(Isometry3d::from_translation(hit.point), 0.5, Color::WHITE);
+
+    let over_ui = hover_map
+        .values()
+        .any(|hits| hits.keys().any(|hit| nodes.contains(*hit)));
+    let clicked = actions.just_pressed(Action::Place) && !over_ui;
+    let Some(start) = tool.start else {
+        if clicked {
+            tool.start = Some(point);
+        }
+        return;
+    };
+    let valid = check_canal(&map, start, point).is_ok();
+    let color = if valid {
+        bevy::color::palettes::css::ROYAL_BLUE
+    } else {
+        bevy::color::palettes::css::RED
+    };
+    let at = |p: Vec2| Vec3::new(p.x, map.get_height(Vec3::new(p.x, 0., p.y)) + 0.2, p.y);
+    gizmos.line(at(start), at(point), color);
+    if clicked {
+        if valid {
+            incoming.write(IncomingCommand {
+                player: 0,
+                command: PlayerCommand::DigCanal {
+                    from: start,
+                    to: point,
+                },
+            });
+            // the next canal goes on from the end of this one, which is now water
+            tool.start = Some(point);
+        } else {
+            sounds.write(PlaySound(Sound::Invalid));
+        }
+    }
+}
+
+/// Lower the terrain along the new canals, and fill them with water
+fn dig_canals(
+    mut dug: EventReader<DigCanal>,
+    mut terrain_ops: EventWriter<TerrainOp>,
+    map: Res<Map>,
+    sim: Res<Sim>,
+    mut sounds: EventWriter<PlaySound>,
+) {
+    for DigCanal { from, to } in dug.read() {
+        let Some(level) = connected_level(&map, *from, *to) else {
+            continue;
+        };
+        let canal = Canal {
+            from: *from,
+            to: *to,
+            level,
+        };
+        let bed = level - CANAL_DEPTH;
+        for p in dig_points(*from, *to) {
+            let ground = map.get_height(Vec3::new(p.x, 0., p.y));
+            terrain_ops.write(TerrainOp {
+                op: PatchOp::Flatten,
+                center: Vec3::new(p.x, ground.min(bed), p.y),
+                radius: CANAL_HALF_WIDTH + GRID_SQUARE_SIZE,
+                strength: 1.,
+                tick: sim.tick,
+            });
+        }
+        sim.script_world.0.lock().unwrap().irrigation.add(canal);
+        sounds.write(PlaySound(Sound::Place));
+    }
+}
+
+/// Spawn the water surface of the canals not drawn yet, and remove them all when the canals
+/// are dropped, like when the sim restarts
+fn draw_canal_water(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    drawn: Query<Entity, With<CanalWater>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material: Res<CanalMaterial>,
+) {
+    let canals = sim.script_world.0.lock().unwrap().irrigation.canals.clone();
+    let mut count = drawn.iter().count();
+    if canals.len() < count {
+        for e in &drawn {
+            commands.entity(e).despawn();
+        }
+        count = 0;
+    }
+    for canal in canals.iter().skip(count) {
+        let from = canal.from.extend(canal.level).xzy();
+        let to = canal.to.extend(canal.level).xzy();
+        commands.spawn((
+            Name::new("canal"),
+            CanalWater,
+            WaterSurface,
+            Mesh3d(meshes.add(Plane3d::new(
+                Vec3::Y,
+                Vec2::new(CANAL_HALF_WIDTH, canal.length() / 2.),
+            ))),
+            MeshMaterial3d(material.0.clone()),
+            Transform::from_translation(from.midpoint(to)).looking_to(to - from, Vec3::Y),
+            StateScoped(GameState::InGame),
+        ));
+    }
+}
+
+/// Register `irrigation(x, z)`, between 0 and 1, and `irrigated_area()`, in square world
+/// units
+pub fn register_canal_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("irrigation", move |x: f64, z: f64| -> f64 {
+        let world = w.0.lock().unwrap();
+        world.irrigation.get(Vec2::new(x as f32, z as f32)) as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("irrigated_area", move || -> f64 {
+        w.0.lock().unwrap().irrigation.area() as f64
+    });
+}
@@ -0,0 +1,170 @@
+use bevy::{
+    prelude::*,
+    window::{MonitorSelection, VideoModeSelection, WindowMode},
+};
+use clap::{Parser, ValueEnum};
+
+use crate::{
+    headless::HeadlessSettings,
+    heightmaps::import_heightmap,
+    mapgen::{WorldGen, WorldPreset, WorldSize},
+    menu::GameState,
+    save_game::begin_load,
+    scenario::ScenarioTracker,
+};
+
+/// Command line arguments of the game
+#[derive(Parser, Debug, Clone, Default)]
+#[command(version, about)]
+pub struct Cli {
+    /// Generate a world from this seed right away, skipping the menus
+    #[arg(long)]
+    pub seed: Option<u32>,
+    /// Preset of the world generated right away
+    #[arg(long, value_enum)]
+    pub preset: Option<WorldPreset>,
+    /// Size of the world generated right away
+    #[arg(long, value_enum)]
+    pub size: Option<WorldSize>,
+    /// Load a saved game right away, skipping the menus
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["seed", "preset", "size"])]
+    pub load: Option<String>,
+    /// Start a new game on a scenario right away, by the asset path of its `.scenario` file
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["seed", "preset", "size", "load"])]
+    pub scenario: Option<String>,
+    /// Start a new game on the terrain of this grayscale PNG or EXR heightmap, imported first
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["load", "scenario"])]
+    pub heightmap: Option<String>,
+    /// With `--heightmap`, draw the rivers where this mask is white
+    #[arg(long, value_name = "PATH", requires = "heightmap")]
+    pub river_mask: Option<String>,
+    #[arg(long, value_enum, default_value_t)]
+    pub window: WindowChoice,
+    /// Run without a window nor rendering, with the sim at full speed
+    #[arg(long)]
+    pub headless: bool,
+    /// With `--headless`, exit once the sim ran this many ticks
+    #[arg(long, requires = "headless")]
+    pub ticks: Option<u64>,
+    /// With `--headless`, write the history of the sim stats to this CSV file on exit
+    #[arg(long, value_name = "PATH", requires = "headless")]
+    pub stats: Option<String>,
+}
+
+/// How the window is shown
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WindowChoice {
+    #[default]
+    Windowed,
+    /// Exclusive fullscreen, in the current video mode of the monitor
+    Fullscreen,
+    /// A borderless window covering the monitor
+    Borderless,
+}
+
+/// What the game starts on
+#[derive(Resource, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Launch {
+    #[default]
+    Menu,
+    /// Generate the world of the `WorldGen` resource
+    NewGame,
+    /// Load the saved game at this path
+    Load(String),
+    /// Start a new game on the scenario at this asset path
+    Scenario(String),
+    /// Import the heightmap at this path, with its river mask, and start a new game on it
+    Heightmap {
+        heights: String,
+        rivers: Option<String>,
+    },
+}
+
+impl Cli {
+    /// The world to generate, from the defaults and the arguments
+    pub fn worldgen(&self) -> WorldGen {
+        let default = WorldGen::default();
+        WorldGen {
+            seed: self.seed.unwrap_or(default.seed),
+            preset: self.preset.unwrap_or(default.preset),
+            size: self.size.unwrap_or(default.size),
+            ..default
+        }
+    }
+
+    pub fn launch(&self) -> Launch {
+        if let Some(path) = &self.load {
+            Launch::Load(path.clone())
+        } else if let Some(path) = &self.scenario {
+            Launch::Scenario(path.clone())
+        } else if let Some(heights) = &self.heightmap {
+            Launch::Heightmap {
+                heights: heights.clone(),
+                rivers: self.river_mask.clone(),
+            }
+        } else if self.headless
+            || self.seed.is_some()
+            || self.preset.is_some()
+            || self.size.is_some()
+        {
+            // there is no menu without a window
+            Launch::NewGame
+        } else {
+            Launch::Menu
+        }
+    }
+
+    pub fn headless_settings(&self) -> HeadlessSettings {
+        HeadlessSettings {
+            max_ticks: self.ticks,
+            stats_path: self.stats.clone(),
+        }
+    }
+
+    /// The primary window
+    pub fn window(&self) -> Window {
+        let mode = match self.window {
+            WindowChoice::Windowed => WindowMode::Windowed,
+            WindowChoice::Fullscreen => {
+                WindowMode::Fullscreen(MonitorSelection::Current, VideoModeSelection::Current)
+            }
+            WindowChoice::Borderless => WindowMode::BorderlessFullscreen(MonitorSelection::Current),
+        };
+        Window { mode, ..default() }
+    }
+}
+
+/// Start the game as asked on the command line, see `Launch`
+pub struct LaunchPlugin;
+
+impl Plugin for LaunchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Launch>().add_systems(Startup, launch);
+    }
+}
+
+fn launch(
+    mut commands: Commands,
+    launch: Res<Launch>,
+    mut worldgen: ResMut<WorldGen>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut tracker: ResMut<ScenarioTracker>,
+    asset_server: Res<AssetServer>,
+) -> Result {
+    match &*launch {
+        Launch::Menu => {}
+        Launch::NewGame => {
+            info!("Starting a new game");
+            next_state.set(GameState::Loading);
+        }
+        Launch::Load(path) => begin_load(&mut commands, &mut worldgen, &mut next_state, path)?,
+        Launch::Scenario(path) => tracker.launch(&asset_server, path),
+        Launch::Heightmap { heights, rivers } => {
+            let id = import_heightmap(heights, rivers.as_deref())?;
+            info!("Starting a new game on the heightmap {heights}, imported as {id:016x}");
+            worldgen.heightmap = Some(id);
+            next_state.set(GameState::Loading);
+        }
+    }
+    Ok(())
+}
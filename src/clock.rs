@@ -0,0 +1,184 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use rhai::{Dynamic, Engine};
+
+use crate::{
+    menu::GameState,
+    script_api::{ScriptEvent, SharedScriptWorld, run_scripts_with_world},
+    sim::{Sim, SimSpeed, sim_running},
+    weather::WEATHER_HOURS,
+};
+
+/// Number of sim ticks in a day, a tick is 36 seconds of game time
+pub const TICKS_PER_DAY: u64 = 2400;
+const MINUTES_PER_DAY: u64 = 24 * 60;
+pub const DAYS_PER_WEEK: u64 = 7;
+/// Hour of the sunrise, the sunset is 12 hours later
+const SUNRISE: f32 = 6.;
+/// Events sent to the scripts at fixed times of the clock : name, period and offset, in
+/// game minutes
+const SCHEDULE: &[(&str, u64, u64)] = &[
+    ("new_day", MINUTES_PER_DAY, 0),
+    // at the end of the last day of the week
+    (
+        "payday",
+        DAYS_PER_WEEK * MINUTES_PER_DAY,
+        (DAYS_PER_WEEK - 1) * MINUTES_PER_DAY + 17 * 60,
+    ),
+    ("weather_change", WEATHER_HOURS * 60, 0),
+];
+
+/// Calendar and clock of the game, following the sim ticks : the time goes by faster with
+/// the sim speed, and stops when it is paused. Things happening at a time of the day, like
+/// the sun moving or the weather changing, go by the clock. The scripts read it with
+/// `clock()`, `time_of_day()` and `day()`, and get the scheduled events of the `SCHEDULE`
+/// like the other events, with `events("payday")`. It is shown in the top bar.
+pub struct ClockPlugin;
+
+impl Plugin for ClockPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(GameClock::default())
+            .add_systems(
+                FixedUpdate,
+                send_scheduled_events
+                    .before(run_scripts_with_world)
+                    .run_if(sim_running),
+            )
+            .add_systems(Update, advance_clock.run_if(in_state(GameState::InGame)));
+    }
+}
+
+/// Time of the game, interpolated between sim ticks
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct GameClock {
+    /// Game minutes since the start
+    pub minutes: f64,
+}
+
+impl GameClock {
+    pub fn from_tick(tick: f64) -> Self {
+        Self {
+            minutes: tick * MINUTES_PER_DAY as f64 / TICKS_PER_DAY as f64,
+        }
+    }
+
+    /// Game minutes at the start of a tick, exactly, for the scheduled events
+    pub fn minutes_at(tick: u64) -> u64 {
+        tick * MINUTES_PER_DAY / TICKS_PER_DAY
+    }
+
+    pub fn day(&self) -> u64 {
+        (self.minutes / MINUTES_PER_DAY as f64) as u64
+    }
+
+    /// Hour in [0, 24)
+    pub fn hour(&self) -> f32 {
+        ((self.minutes % MINUTES_PER_DAY as f64) / 60.) as f32
+    }
+
+    pub fn minute(&self) -> u32 {
+        (self.minutes % 60.) as u32
+    }
+
+    pub fn week(&self) -> u64 {
+        self.day() / DAYS_PER_WEEK
+    }
+
+    /// Day of the week, from 0
+    pub fn weekday(&self) -> u64 {
+        self.day() % DAYS_PER_WEEK
+    }
+
+    /// Hours and minutes, like 09:30
+    pub fn time(&self) -> String {
+        format!("{:02}:{:02}", self.hour() as u32, self.minute())
+    }
+
+    /// Height of the sun, from -1 at midnight to 1 at noon
+    pub fn sun_height(&self) -> f32 {
+        self.sun_angle().sin()
+    }
+
+    /// Angle of the sun above the eastern horizon
+    pub fn sun_angle(&self) -> f32 {
+        (self.hour() - SUNRISE) / 12. * PI
+    }
+
+    /// How much of the daylight there is, 0 at night and 1 in the middle of the day
+    pub fn daylight(&self) -> f32 {
+        ((self.sun_height() + 0.1) / 0.4).clamp(0., 1.)
+    }
+}
+
+pub(crate) fn advance_clock(
+    sim: Res<Sim>,
+    speed: Res<SimSpeed>,
+    fixed: Res<Time<Fixed>>,
+    mut clock: ResMut<GameClock>,
+) {
+    // move smoothly between the ticks, unless the sim is paused
+    let progress = if *speed == SimSpeed::Paused {
+        0.
+    } else {
+        fixed.overstep_fraction_f64()
+    };
+    *clock = GameClock::from_tick(sim.tick as f64 + progress);
+}
+
+/// Send the events of the schedule whose time comes during the tick about to run. The
+/// scripts see them on the next tick, like the events they emit.
+fn send_scheduled_events(sim: Res<Sim>) {
+    if sim.tick == 0 {
+        return;
+    }
+    let before = GameClock::minutes_at(sim.tick - 1);
+    let now = GameClock::minutes_at(sim.tick);
+    let mut world = sim.script_world.0.lock().unwrap();
+    for (name, period, offset) in SCHEDULE {
+        let count = |minutes: u64| (minutes + period - offset) / period;
+        if count(now) == count(before) {
+            continue;
+        }
+        let mut payload = rhai::Map::new();
+        let (day, minutes) = (now / MINUTES_PER_DAY, now % MINUTES_PER_DAY);
+        payload.insert("day".into(), Dynamic::from_int(day as i64));
+        payload.insert("hour".into(), Dynamic::from_int((minutes / 60) as i64));
+        world.emitted.push(ScriptEvent {
+            name: name.to_string(),
+            payload: Dynamic::from_map(payload),
+            tick: sim.tick,
+        });
+    }
+}
+
+/// Register `time_of_day()`, the hour of the current tick in [0, 24), `day()`, and
+/// `clock()`, a map of the day, week, weekday, hour and minute
+pub fn register_clock_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("time_of_day", move || -> f64 {
+        let tick = w.0.lock().unwrap().tick;
+        GameClock::from_tick(tick as f64).hour() as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("day", move || -> i64 {
+        (w.0.lock().unwrap().tick / TICKS_PER_DAY) as i64
+    });
+
+    let w = world.clone();
+    engine.register_fn("clock", move || -> rhai::Map {
+        let clock = GameClock::from_tick(w.0.lock().unwrap().tick as f64);
+        let mut map = rhai::Map::new();
+        for (name, value) in [
+            ("day", clock.day()),
+            ("week", clock.week()),
+            ("weekday", clock.weekday()),
+            ("hour", clock.hour() as u64),
+            ("minute", clock.minute() as u64),
+        ] {
+            map.insert(name.into(), Dynamic::from_int(value as i64));
+        }
+        map
+    });
+}
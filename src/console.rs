@@ -0,0 +1,332 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    input::keyboard::{Key, KeyboardInput},
+    prelude::*,
+};
+
+use crate::{
+    input_map::{Action, Actions, Binding, InputMap},
+    script_errors::ScriptErrors,
+    script_limits::ScriptStats,
+    sim::{Sim, SimSettings},
+    ui::{FontHandle, TextFocus},
+};
+
+/// Number of lines kept in the console output
+const MAX_OUTPUT_LINES: usize = 200;
+/// Number of lines of output shown
+const SHOWN_LINES: usize = 30;
+
+/// Developer console, toggled with the backtick key, evaluating Rhai in the sim scope.
+/// Lines starting with `/` are sent as `ConsoleCommand` events.
+pub struct ConsolePlugin;
+
+impl Plugin for ConsolePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Console::default())
+            .add_event::<ConsoleCommand>()
+            .add_systems(Startup, setup_console)
+            .add_systems(
+                Update,
+                (
+                    toggle_console,
+                    console_input.after(toggle_console),
+                    update_console.after(console_input),
+                ),
+            );
+    }
+}
+
+/// A command typed in the console as `/name arg1 arg2`
+#[derive(Event, Clone, Debug)]
+pub struct ConsoleCommand {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LineKind {
+    Input,
+    Output,
+    Error,
+}
+
+#[derive(Resource, Default)]
+pub struct Console {
+    pub open: bool,
+    input: String,
+    history: Vec<String>,
+    /// Entry of the history being browsed with the arrow keys
+    history_index: Option<usize>,
+    output: VecDeque<(String, LineKind)>,
+}
+
+impl Console {
+    fn push_line(&mut self, text: String, kind: LineKind) {
+        if self.output.len() == MAX_OUTPUT_LINES {
+            self.output.pop_front();
+        }
+        self.output.push_back((text, kind));
+    }
+
+    /// Print a line in the console
+    pub fn print(&mut self, text: impl Into<String>) {
+        self.push_line(text.into(), LineKind::Output);
+    }
+
+    /// Print an error in the console
+    pub fn print_error(&mut self, text: impl Into<String>) {
+        self.push_line(text.into(), LineKind::Error);
+    }
+
+    fn history_back(&mut self) {
+        if self.history.is_empty() {
+            return;
+        }
+        let i = match self.history_index {
+            Some(i) => i.saturating_sub(1),
+            None => self.history.len() - 1,
+        };
+        self.history_index = Some(i);
+        self.input = self.history[i].clone();
+    }
+
+    fn history_forward(&mut self) {
+        let Some(i) = self.history_index else {
+            return;
+        };
+        if i + 1 < self.history.len() {
+            self.history_index = Some(i + 1);
+            self.input = self.history[i + 1].clone();
+        } else {
+            self.history_index = None;
+            self.input.clear();
+        }
+    }
+}
+
+#[derive(Component)]
+struct ConsoleRoot;
+
+#[derive(Component)]
+struct ConsoleOutput;
+
+#[derive(Component)]
+struct ConsoleInputLine;
+
+fn setup_console(mut commands: Commands, font: Res<FontHandle>) {
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 14.,
+        ..default()
+    };
+    commands
+        .spawn((
+            Name::new("console"),
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(0.),
+                left: Val::Px(0.),
+                width: Val::Percent(100.),
+                height: Val::Percent(40.),
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(8.)),
+                ..default()
+            },
+            BackgroundColor(bevy::color::palettes::css::BLACK.with_alpha(0.85).into()),
+            GlobalZIndex(5),
+            Visibility::Hidden,
+            ConsoleRoot,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::End,
+                    flex_grow: 1.,
+                    overflow: Overflow::clip(),
+                    ..default()
+                },
+                ConsoleOutput,
+            ));
+            parent.spawn((Text::new("> "), text_font, Label, ConsoleInputLine));
+        });
+}
+
+/// Open and close the console with the backtick key, or close it with the pause menu key
+fn toggle_console(
+    actions: Actions,
+    mut console: ResMut<Console>,
+    mut text_focus: ResMut<TextFocus>,
+    root: Single<(Entity, &mut Visibility), With<ConsoleRoot>>,
+) {
+    let (root, mut visibility) = root.into_inner();
+    let toggle = actions.just_pressed_while_typing(Action::ToggleConsole);
+    if console.open {
+        if toggle || actions.just_pressed_while_typing(Action::PauseMenu) {
+            console.open = false;
+            if text_focus.0 == Some(root) {
+                text_focus.0 = None;
+            }
+            *visibility = Visibility::Hidden;
+        }
+    } else if toggle && text_focus.0.is_none() {
+        console.open = true;
+        text_focus.0 = Some(root);
+        *visibility = Visibility::Inherited;
+    }
+}
+
+/// Keys of the `data` map at a path
+fn keys_at(map: &rhai::Map, path: &[&str]) -> Option<Vec<String>> {
+    match path.split_first() {
+        None => Some(map.keys().map(|k| k.to_string()).collect()),
+        Some((first, rest)) => keys_at(&*map.get(*first)?.read_lock::<rhai::Map>()?, rest),
+    }
+}
+
+/// Complete the `data` path at the end of the input, as far as the candidates agree.
+/// Returns the candidates if there are several.
+fn complete(input: &mut String, data: &rhai::Map) -> Vec<String> {
+    let start = input
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .map_or(0, |i| i + 1);
+    let word = input[start..].to_string();
+    let mut path: Vec<&str> = word.split('.').collect();
+    let last = path.pop().unwrap_or_default();
+    let keys = match path.split_first() {
+        None => vec!["data".to_string()],
+        Some((&"data", rest)) => keys_at(data, rest).unwrap_or_default(),
+        Some(_) => return Vec::new(),
+    };
+    let mut candidates: Vec<String> = keys.into_iter().filter(|k| k.starts_with(last)).collect();
+    candidates.sort();
+    let Some(first) = candidates.first() else {
+        return Vec::new();
+    };
+    let mut common = first.clone();
+    for candidate in &candidates[1..] {
+        while !candidate.starts_with(&common) {
+            common.pop();
+        }
+    }
+    if common.len() > last.len() {
+        input.push_str(&common[last.len()..]);
+    }
+    if candidates.len() > 1 {
+        candidates
+    } else {
+        Vec::new()
+    }
+}
+
+/// Type in the console. Enter evaluates the line, the arrows browse the history,
+/// and Tab completes the keys of `data`.
+fn console_input(
+    mut events: EventReader<KeyboardInput>,
+    text_focus: Res<TextFocus>,
+    root: Single<Entity, With<ConsoleRoot>>,
+    mut console: ResMut<Console>,
+    mut sim: ResMut<Sim>,
+    settings: Res<SimSettings>,
+    mut errors: ResMut<ScriptErrors>,
+    mut stats: ResMut<ScriptStats>,
+    mut console_commands: EventWriter<ConsoleCommand>,
+    input_map: Res<InputMap>,
+) {
+    // skip the key press that opened the console
+    if text_focus.0 != Some(*root) || text_focus.is_changed() {
+        events.clear();
+        return;
+    }
+    for ev in events.read() {
+        let toggle = Binding::Key(ev.key_code);
+        if !ev.state.is_pressed() || input_map.bindings(Action::ToggleConsole).contains(&toggle) {
+            continue;
+        }
+        match &ev.logical_key {
+            Key::Character(c) => console.input.push_str(c),
+            Key::Space => console.input.push(' '),
+            Key::Backspace => {
+                console.input.pop();
+            }
+            Key::ArrowUp => console.history_back(),
+            Key::ArrowDown => console.history_forward(),
+            Key::Tab => {
+                let Some(data) = sim.data() else {
+                    continue;
+                };
+                let candidates = complete(&mut console.input, data);
+                if !candidates.is_empty() {
+                    console.print(candidates.join("  "));
+                }
+            }
+            Key::Enter => {
+                let input = std::mem::take(&mut console.input).trim().to_string();
+                if input.is_empty() {
+                    continue;
+                }
+                console.history.push(input.clone());
+                console.history_index = None;
+                console.push_line(format!("> {input}"), LineKind::Input);
+                if let Some(command) = input.strip_prefix('/') {
+                    let mut words = command.split_whitespace();
+                    let name = words.next().unwrap_or_default().to_string();
+                    if name == "clear" {
+                        console.output.clear();
+                    } else {
+                        console_commands.write(ConsoleCommand {
+                            name,
+                            args: words.map(String::from).collect(),
+                        });
+                    }
+                    continue;
+                }
+                // changes to the data would be overwritten by the tick running in the background
+                sim.finish_tick(&settings, &mut errors, &mut stats);
+                match sim.eval(&input) {
+                    Ok(value) if value.is_unit() => {}
+                    Ok(value) => console.print(value.to_string()),
+                    Err(e) => console.print_error(e.to_string()),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn update_console(
+    mut commands: Commands,
+    console: Res<Console>,
+    output: Single<Entity, With<ConsoleOutput>>,
+    mut input_line: Single<&mut Text, With<ConsoleInputLine>>,
+    font: Res<FontHandle>,
+) {
+    if !console.is_changed() || !console.open {
+        return;
+    }
+    input_line.0 = format!("> {}_", console.input);
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 14.,
+        ..default()
+    };
+    commands.entity(*output).despawn_related::<Children>();
+    commands.entity(*output).with_children(|parent| {
+        let skip = console.output.len().saturating_sub(SHOWN_LINES);
+        for (text, kind) in console.output.iter().skip(skip) {
+            let color = match kind {
+                LineKind::Input => bevy::color::palettes::css::LIGHT_GRAY.into(),
+                LineKind::Output => Color::WHITE,
+                LineKind::Error => bevy::color::palettes::css::TOMATO.into(),
+            };
+            parent.spawn((
+                Text(text.clone()),
+                text_font.clone(),
+                TextColor(color),
+                Label,
+            ));
+        }
+    });
+}
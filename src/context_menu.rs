@@ -0,0 +1,331 @@
+use bevy::{prelude::*, ui::FocusPolicy};
+
+use crate::{
+    CameraFollow, CameraTarget,
+    build::{BuildId, Building, Disabled, GameId, Highlighted, SelectedBuild},
+    localization::{Localization, LocalizedText},
+    map::{BuildingInstance, Map},
+    pause_menu::Pause,
+    signs::{SignLabel, spawn_sign_field},
+    toasts::Toasts,
+    tooltip::describe,
+    ui::FontHandle,
+};
+
+const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+const HOVERED_BUTTON: Color = Color::srgb(0.3, 0.3, 0.3);
+/// Distance the cursor can move between the press and the release of the right button
+/// for it to be a click, and not a camera orbit
+const CLICK_TOLERANCE: f32 = 4.;
+
+/// Menu opened by right-clicking the highlighted building, listing what can be done with it
+pub struct ContextMenuPlugin;
+
+impl Plugin for ContextMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                open_context_menu,
+                context_menu_buttons,
+                close_building_info,
+                highlight_buttons,
+            )
+                .after(crate::build::select_world_part)
+                .run_if(in_state(Pause::Running)),
+        );
+    }
+}
+
+/// Context menu of a building, anchored at the cursor
+#[derive(Component)]
+struct ContextMenu {
+    target: Entity,
+}
+
+/// Panel describing a building, opened from its context menu
+#[derive(Component)]
+struct BuildingInfo {
+    target: Entity,
+}
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum ContextButton {
+    Move,
+    Delete,
+    Copy,
+    Info,
+    Disable,
+    Follow,
+    CloseInfo,
+}
+
+/// Run condition : no context menu or building info is open.
+/// The world can't be clicked through them.
+pub fn no_context_menu(menus: Query<(), Or<(With<ContextMenu>, With<BuildingInfo>)>>) -> bool {
+    menus.is_empty()
+}
+
+fn spawn_button(
+    parent: &mut ChildSpawnerCommands,
+    button: ContextButton,
+    label: &'static str,
+    font: &TextFont,
+) {
+    parent.spawn((
+        Button,
+        Node {
+            padding: UiRect::axes(Val::Px(10.), Val::Px(4.)),
+            ..default()
+        },
+        BackgroundColor(NORMAL_BUTTON),
+        // let the menu know it is hovered
+        FocusPolicy::Pass,
+        button,
+        children![(
+            LocalizedText::new(label),
+            font.clone(),
+            Label,
+            Pickable::IGNORE
+        )],
+    ));
+}
+
+/// Open the menu on a right click, close it on any click outside of it
+fn open_context_menu(
+    mut commands: Commands,
+    mouse: Res<ButtonInput<MouseButton>>,
+    window: Single<&Window>,
+    mut press_position: Local<Option<Vec2>>,
+    highlighted: Option<
+        Single<(Entity, Has<Disabled>), (With<Highlighted>, With<BuildingInstance>)>,
+    >,
+    menus: Query<(Entity, &Interaction), With<ContextMenu>>,
+    font: Res<FontHandle>,
+) {
+    let cursor = window.cursor_position();
+    if mouse.any_just_pressed([MouseButton::Left, MouseButton::Right]) {
+        for (e, interaction) in &menus {
+            if *interaction == Interaction::None {
+                commands.entity(e).despawn();
+            }
+        }
+    }
+    if mouse.just_pressed(MouseButton::Right) {
+        *press_position = cursor;
+    }
+    if !mouse.just_released(MouseButton::Right) {
+        return;
+    }
+    let (Some(pressed), Some(cursor)) = (press_position.take(), cursor) else {
+        return;
+    };
+    if pressed.distance(cursor) > CLICK_TOLERANCE {
+        return;
+    }
+    let Some(highlighted) = highlighted else {
+        return;
+    };
+    let (target, disabled) = *highlighted;
+    for (e, _) in &menus {
+        commands.entity(e).despawn();
+    }
+    let font = TextFont {
+        font: font.0.clone(),
+        font_size: 16.,
+        ..default()
+    };
+    commands
+        .spawn((
+            Name::new("context menu"),
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Px(cursor.x),
+                top: Val::Px(cursor.y),
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(2.)),
+                row_gap: Val::Px(2.),
+                ..default()
+            },
+            BackgroundColor(Color::BLACK.with_alpha(0.85)),
+            GlobalZIndex(22),
+            Interaction::default(),
+            StateScoped(Pause::Running),
+            ContextMenu { target },
+        ))
+        .with_children(|parent| {
+            spawn_button(parent, ContextButton::Move, "context-move", &font);
+            spawn_button(parent, ContextButton::Copy, "context-copy", &font);
+            let label = if disabled {
+                "context-enable"
+            } else {
+                "context-disable"
+            };
+            spawn_button(parent, ContextButton::Disable, label, &font);
+            spawn_button(parent, ContextButton::Info, "context-info", &font);
+            spawn_button(parent, ContextButton::Follow, "context-follow", &font);
+            spawn_button(parent, ContextButton::Delete, "context-delete", &font);
+        });
+}
+
+/// Buttons act on release, so that the click doesn't reach the building being moved
+fn context_menu_buttons(
+    mut commands: Commands,
+    mouse: Res<ButtonInput<MouseButton>>,
+    buttons: Query<(&Interaction, &ContextButton, &ChildOf)>,
+    menus: Query<(Entity, &ContextMenu, &Node)>,
+    instances: Query<(
+        &BuildingInstance,
+        &BuildId,
+        Option<&GameId>,
+        Has<Disabled>,
+        Has<SignLabel>,
+    )>,
+    camera: Single<Entity, With<CameraTarget>>,
+    buildings: Res<Assets<Building>>,
+    mut map: ResMut<Map>,
+    mut toasts: ResMut<Toasts>,
+    font: Res<FontHandle>,
+    localization: Res<Localization>,
+) {
+    if !mouse.just_released(MouseButton::Left) {
+        return;
+    }
+    let Some((_, button, ChildOf(parent))) = buttons
+        .iter()
+        .find(|(interaction, _, _)| **interaction != Interaction::None)
+    else {
+        return;
+    };
+    let Ok((menu, &ContextMenu { target }, node)) = menus.get(*parent) else {
+        return;
+    };
+    commands.entity(menu).despawn();
+    let Ok((instance, build_id, game_id, disabled, sign)) = instances.get(target) else {
+        return;
+    };
+    let Some(building) = buildings.get(&build_id.0) else {
+        return;
+    };
+    match *button {
+        ContextButton::Move => {
+            commands
+                .entity(target)
+                .remove::<(Highlighted, BuildingInstance)>()
+                .insert(SelectedBuild);
+            map.entities.remove_one(instance.clone());
+        }
+        ContextButton::Delete => {
+            map.entities.remove_one(instance.clone());
+            commands.entity(target).despawn();
+            let name = [("name", building.name.clone())];
+            toasts.info(localization.get_args("toast-building-deleted", &name));
+        }
+        ContextButton::Copy => {
+            commands.spawn((build_id.clone(), Name::new("building")));
+        }
+        ContextButton::Disable => {
+            let name = [("name", building.name.clone())];
+            if disabled {
+                commands.entity(target).remove::<Disabled>();
+                toasts.info(localization.get_args("toast-building-enabled", &name));
+            } else {
+                commands.entity(target).insert(Disabled);
+                toasts.info(localization.get_args("toast-building-disabled", &name));
+            }
+        }
+        ContextButton::Follow => {
+            commands.entity(*camera).insert(CameraFollow(target));
+            let name = [("name", building.name.clone())];
+            toasts.info(localization.get_args("toast-following", &name));
+        }
+        ContextButton::Info => {
+            let mut text = describe(building, &localization);
+            let center = instance.center();
+            let position = format!("{:.1}, {:.1}", center.x, center.y);
+            text.push('\n');
+            text.push_str(&localization.get_args("info-position", &[("position", position)]));
+            if let Some(id) = game_id {
+                text.push('\n');
+                text.push_str(&localization.get_args("info-id", &[("id", id.0.to_string())]));
+            }
+            if disabled {
+                text.push('\n');
+                text.push_str(&localization.get("info-disabled"));
+            }
+            let font = TextFont {
+                font: font.0.clone(),
+                font_size: 14.,
+                ..default()
+            };
+            commands
+                .spawn((
+                    Name::new("building info"),
+                    Node {
+                        position_type: PositionType::Absolute,
+                        left: node.left,
+                        top: node.top,
+                        max_width: Val::Px(300.),
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::End,
+                        padding: UiRect::all(Val::Px(6.)),
+                        row_gap: Val::Px(4.),
+                        ..default()
+                    },
+                    BackgroundColor(Color::BLACK.with_alpha(0.85)),
+                    GlobalZIndex(22),
+                    StateScoped(Pause::Running),
+                    BuildingInfo { target },
+                ))
+                .with_children(|parent| {
+                    parent.spawn((Text(text), font.clone(), Label, Pickable::IGNORE));
+                    if sign {
+                        parent.spawn((
+                            LocalizedText::new("info-sign-text"),
+                            font.clone(),
+                            Label,
+                            Pickable::IGNORE,
+                        ));
+                        spawn_sign_field(parent, target, &font);
+                    }
+                    spawn_button(parent, ContextButton::CloseInfo, "context-close", &font);
+                });
+        }
+        ContextButton::CloseInfo => {}
+    }
+}
+
+/// Close the building info with its button, or when the building is gone
+fn close_building_info(
+    mut commands: Commands,
+    mouse: Res<ButtonInput<MouseButton>>,
+    infos: Query<(Entity, &BuildingInfo, &Children)>,
+    buttons: Query<(&Interaction, &ContextButton)>,
+    instances: Query<(), With<BuildingInstance>>,
+) {
+    for (e, info, children) in &infos {
+        let closed = mouse.just_released(MouseButton::Left)
+            && children.iter().any(|child| {
+                buttons.get(child).is_ok_and(|(interaction, button)| {
+                    *button == ContextButton::CloseInfo && *interaction != Interaction::None
+                })
+            });
+        if closed || !instances.contains(info.target) {
+            commands.entity(e).despawn();
+        }
+    }
+}
+
+fn highlight_buttons(
+    mut buttons: Query<
+        (&Interaction, &mut BackgroundColor),
+        (Changed<Interaction>, With<ContextButton>),
+    >,
+) {
+    for (interaction, mut color) in &mut buttons {
+        color.0 = match interaction {
+            Interaction::None => NORMAL_BUTTON,
+            _ => HOVERED_BUTTON,
+        };
+    }
+}
@@ -0,0 +1,74 @@
+use bevy::prelude::*;
+
+use crate::{
+    build::cast_to_terrain,
+    map::{IsGround, Map},
+    menu::GameState,
+    ui::FontHandle,
+};
+
+/// Small panel describing the terrain under the cursor : position, height, slope,
+/// hydrology flow and chunk.
+pub struct CursorReadoutPlugin;
+
+impl Plugin for CursorReadoutPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_readout)
+            .add_systems(Update, update_readout.run_if(in_state(GameState::InGame)));
+    }
+}
+
+#[derive(Component)]
+struct CursorReadout;
+
+fn setup_readout(mut commands: Commands, font: Res<FontHandle>) {
+    commands.spawn((
+        Name::new("cursor readout"),
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.),
+            bottom: Val::Px(10.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        BackgroundColor(bevy::color::palettes::css::BLACK.with_alpha(0.6).into()),
+        Text::default(),
+        TextFont {
+            font: font.0.clone(),
+            font_size: 12.,
+            ..default()
+        },
+        Label,
+        Pickable::IGNORE,
+        Visibility::Hidden,
+        CursorReadout,
+    ));
+}
+
+fn update_readout(
+    mut ray_cast: MeshRayCast,
+    camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    window: Single<&Window>,
+    chunks: Query<&IsGround>,
+    map: Res<Map>,
+    readout: Single<(&mut Text, &mut Visibility), With<CursorReadout>>,
+) {
+    let (mut text, mut visibility) = readout.into_inner();
+    let (camera, camera_transform) = *camera;
+    let hit = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+        .and_then(|ray| cast_to_terrain(&mut ray_cast, ray, &chunks));
+    let Some(hit) = hit else {
+        *visibility = Visibility::Hidden;
+        return;
+    };
+    *visibility = Visibility::Inherited;
+    let (x, y) = map.continent.from_world(&hit.point);
+    let flow = map.continent.get_hydro(x, y).amount;
+    let slope = hit.normal.angle_between(Vec3::Y).to_degrees();
+    text.0 = format!(
+        "Position : {:.1}, {:.1}\nHeight : {:.2}\nSlope : {:.0}°\nFlow : {:.1}\nChunk : {}, {}",
+        hit.point.x, hit.point.z, hit.point.y, slope, flow, hit.chunk.x, hit.chunk.y
+    );
+}
@@ -0,0 +1,270 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use bevy::{
+    asset::RenderAssetUsages,
+    platform::collections::HashSet,
+    prelude::*,
+    render::mesh::{Indices, PrimitiveTopology},
+};
+use rhai::Engine;
+
+use crate::{
+    build::{BuildId, Building, BuildingPlaced, BuildingRemoved},
+    map::{BuildingInstance, GRID_SQUARE_SIZE, Map, WaterSurface},
+    mapgen::Continent,
+    menu::GameState,
+    pollution::RIVER_AMOUNT,
+    script_api::SharedScriptWorld,
+    sim::Sim,
+};
+
+/// Most points of the grid flooded behind a dam
+const MAX_RESERVOIR_CELLS: usize = 6000;
+/// Share of its flow the river keeps downstream of a dam
+const RELEASED_SHARE: f32 = 0.5;
+/// Most points of the river lowered downstream of a dam
+const MAX_DOWNSTREAM_STEPS: usize = 10000;
+
+/// Dams across the rivers. Buildings with a `dam` height placed across a river hold the
+/// water back : the valley upstream is flooded up to the top of the dam into a reservoir,
+/// drawn as a water surface, and the river downstream keeps only part of its flow. The
+/// hydrology of the continent is changed accordingly, along with the water of the canals, so
+/// the pollution, the land value and the bridges follow the new water. Building scripts read
+/// the flow through their dam with `dam_flow(id)`, to make power from it.
+pub struct DamPlugin;
+
+impl Plugin for DamPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Dams::default())
+            .add_systems(Startup, setup_reservoir_material)
+            .add_systems(OnExit(GameState::InGame), clear_dams)
+            .add_systems(
+                Update,
+                (
+                    add_dams,
+                    remove_dams,
+                    update_hydrology.after(add_dams).after(remove_dams),
+                )
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// A dam and the water it holds
+struct Dam {
+    entity: Entity,
+    /// Point of the grid the dam stands on
+    point: (u32, u32),
+    /// Points of the grid flooded behind it
+    reservoir: Vec<(u32, u32)>,
+    /// Water surface of the reservoir
+    surface: Entity,
+}
+
+/// Placed dams, by game id
+#[derive(Resource, Default)]
+struct Dams(BTreeMap<u64, Dam>);
+
+#[derive(Resource)]
+struct ReservoirMaterial(Handle<StandardMaterial>);
+
+fn setup_reservoir_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // the same as the rivers
+    commands.insert_resource(ReservoirMaterial(materials.add(StandardMaterial {
+        base_color: bevy::color::palettes::css::ROYAL_BLUE.into(),
+        ..default()
+    })));
+}
+
+fn clear_dams(mut dams: ResMut<Dams>) {
+    dams.0.clear();
+}
+
+/// World position of a point of the grid, at the height of the terrain
+fn point_position(map: &Map, (x, y): (u32, u32)) -> Vec3 {
+    let pos = map.continent.to_world(Continent::xy2h(x, y));
+    pos.with_y(map.get_height(pos))
+}
+
+/// Points of the grid upstream of a dam lower than the water level, filled from the dam
+/// across the valley. The water doesn't go past the line of the dam.
+fn flood_reservoir(map: &Map, dam: (u32, u32), level: f32) -> Vec<(u32, u32)> {
+    let Some(down) = map.continent.downstream(dam.0, dam.1) else {
+        return Vec::new();
+    };
+    let flow = Vec2::new(down.0 as f32 - dam.0 as f32, down.1 as f32 - dam.1 as f32);
+    let mut reservoir = Vec::new();
+    let mut seen = HashSet::default();
+    seen.insert(dam);
+    let mut queue = VecDeque::from([dam]);
+    while let Some((x, y)) = queue.pop_front() {
+        for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let (Some(nx), Some(ny)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) else {
+                continue;
+            };
+            let next = (nx, ny);
+            let offset = Vec2::new(nx as f32 - dam.0 as f32, ny as f32 - dam.1 as f32);
+            if nx >= Continent::CONTINENT_SIZE
+                || ny >= Continent::CONTINENT_SIZE
+                || offset.dot(flow) >= 0.
+                || !seen.insert(next)
+            {
+                continue;
+            }
+            let pos = point_position(map, next);
+            if pos.y >= level || map.continent.is_sea(pos) {
+                continue;
+            }
+            reservoir.push(next);
+            if reservoir.len() >= MAX_RESERVOIR_CELLS {
+                return reservoir;
+            }
+            queue.push_back(next);
+        }
+    }
+    reservoir
+}
+
+/// Flat water surface over the points of a reservoir, around `origin`
+fn reservoir_mesh(map: &Map, reservoir: &[(u32, u32)], origin: Vec3) -> Mesh {
+    let half = GRID_SQUARE_SIZE / 2.;
+    let mut positions = Vec::with_capacity(reservoir.len() * 4);
+    let mut indices = Vec::with_capacity(reservoir.len() * 6);
+    for &point in reservoir {
+        let center = map.continent.to_world(Continent::xy2h(point.0, point.1)) - origin;
+        let i = positions.len() as u32;
+        for (dx, dz) in [(-half, -half), (-half, half), (half, half), (half, -half)] {
+            positions.push([center.x + dx, 0., center.z + dz]);
+        }
+        indices.extend([i, i + 1, i + 2, i, i + 2, i + 3]);
+    }
+    let normals = vec![[0., 1., 0.]; positions.len()];
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, normals)
+    .with_inserted_indices(Indices::U32(indices))
+}
+
+/// Flood the reservoirs of the new dams
+fn add_dams(
+    mut commands: Commands,
+    mut placed: EventReader<BuildingPlaced>,
+    mut dams: ResMut<Dams>,
+    map: Res<Map>,
+    instances: Query<(&BuildId, &BuildingInstance)>,
+    buildings: Res<Assets<Building>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material: Res<ReservoirMaterial>,
+) {
+    for BuildingPlaced { entity, id, .. } in placed.read() {
+        let Ok((bid, instance)) = instances.get(*entity) else {
+            continue;
+        };
+        let Some(building) = buildings.get(&bid.0).filter(|b| b.dam > 0.) else {
+            continue;
+        };
+        let center = instance.center();
+        let point = map.continent.from_world(&Vec3::new(center.x, 0., center.y));
+        if map.continent.get_hydro(point.0, point.1).amount < RIVER_AMOUNT {
+            continue;
+        }
+        let origin = point_position(&map, point);
+        let level = origin.y + building.dam;
+        let reservoir = flood_reservoir(&map, point, level);
+        let surface = commands
+            .spawn((
+                Name::new("reservoir"),
+                WaterSurface,
+                Mesh3d(meshes.add(reservoir_mesh(&map, &reservoir, origin))),
+                MeshMaterial3d(material.0.clone()),
+                Transform::from_translation(origin.with_y(level)),
+                StateScoped(GameState::InGame),
+            ))
+            .id();
+        dams.0.insert(
+            id.0,
+            Dam {
+                entity: *entity,
+                point,
+                reservoir,
+                surface,
+            },
+        );
+    }
+}
+
+/// Drain the reservoirs of the removed dams
+fn remove_dams(
+    mut commands: Commands,
+    mut removed: EventReader<BuildingRemoved>,
+    mut dams: ResMut<Dams>,
+) {
+    for BuildingRemoved { entity, id } in removed.read() {
+        if dams.0.get(&id.0).is_some_and(|dam| dam.entity == *entity) {
+            let dam = dams.0.remove(&id.0).unwrap();
+            commands.entity(dam.surface).despawn();
+        }
+    }
+}
+
+/// Apply the dams and the canals to the hydrology of the continent again when they change,
+/// and share the flow through each dam with the scripts
+fn update_hydrology(
+    dams: Res<Dams>,
+    mut map: ResMut<Map>,
+    sim: Res<Sim>,
+    mut canals_applied: Local<usize>,
+) {
+    let mut world = sim.script_world.0.lock().unwrap();
+    let canals = world.irrigation.canals.len();
+    if !dams.is_changed() && canals == *canals_applied {
+        return;
+    }
+    *canals_applied = canals;
+    let continent = &mut map.continent;
+    continent.reset_amounts();
+    let mut flows = BTreeMap::new();
+    for (id, dam) in &dams.0 {
+        let (x, y) = dam.point;
+        let flow = continent.get_hydro(x, y).amount;
+        flows.insert(*id, flow);
+        for &(x, y) in &dam.reservoir {
+            if continent.get_hydro(x, y).amount < RIVER_AMOUNT {
+                continent.set_amount(x, y, RIVER_AMOUNT);
+            }
+        }
+        let mut point = dam.point;
+        for _ in 0..MAX_DOWNSTREAM_STEPS {
+            let Some((x, y)) = continent.downstream(point.0, point.1) else {
+                break;
+            };
+            let amount = continent.get_hydro(x, y).amount;
+            continent.set_amount(x, y, amount * RELEASED_SHARE);
+            point = (x, y);
+        }
+    }
+    // after the dams, so that the canals stay full below them
+    for canal in &world.irrigation.canals {
+        for (x, y) in canal.points(continent) {
+            if continent.get_hydro(x, y).amount < RIVER_AMOUNT {
+                continent.set_amount(x, y, RIVER_AMOUNT);
+            }
+        }
+    }
+    world.dam_flows = flows;
+}
+
+/// Register `dam_flow(id)`, the flow of the river through a dam, 0 for the other buildings
+pub fn register_dam_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("dam_flow", move |id: i64| -> f64 {
+        let world = w.0.lock().unwrap();
+        world.dam_flows.get(&(id as u64)).copied().unwrap_or(0.) as f64
+    });
+}
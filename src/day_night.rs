@@ -0,0 +1,60 @@
+use bevy::{pbr::light_consts::lux, prelude::*};
+
+use crate::{
+    Sun,
+    clock::{GameClock, advance_clock},
+    menu::GameState,
+    weather::Weather,
+};
+
+const DAY_AMBIENT: f32 = 30000.;
+const NIGHT_AMBIENT: f32 = 2000.;
+const DAY_FOG: Color = Color::srgba(0.55, 0.58, 0.72, 0.6);
+const NIGHT_FOG: Color = Color::srgba(0.04, 0.05, 0.1, 0.6);
+/// Fog of the heaviest rain, mixed with the fog of the time of the day
+const RAIN_FOG: Color = Color::srgba(0.35, 0.37, 0.4, 0.85);
+/// Share of the sunlight and of the ambient light hidden by the clouds of the heaviest rain
+const RAIN_SUNLIGHT_LOSS: f32 = 0.7;
+const RAIN_AMBIENT_LOSS: f32 = 0.4;
+
+/// Days and nights following the game clock : the sun moves with the sim speed, and stops
+/// when it is paused.
+pub struct DayNightPlugin;
+
+impl Plugin for DayNightPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            update_lighting
+                .after(advance_clock)
+                .run_if(in_state(GameState::InGame)),
+        );
+    }
+}
+
+/// Move the sun across the sky, and dim the ambient light and the fog at night and in the
+/// rain. The atmosphere follows the direction of the sun by itself.
+fn update_lighting(
+    clock: Res<GameClock>,
+    weather: Res<Weather>,
+    mut sun: Query<(&mut Transform, &mut DirectionalLight), With<Sun>>,
+    mut cameras: Query<(&mut AmbientLight, Option<&mut DistanceFog>), With<Camera3d>>,
+) {
+    let angle = clock.sun_angle();
+    let daylight = clock.daylight();
+    let to_sun = Vec3::new(angle.cos(), angle.sin(), 0.3).normalize();
+    for (mut transform, mut light) in &mut sun {
+        transform.look_to(-to_sun, Vec3::Y);
+        light.illuminance = lux::RAW_SUNLIGHT * daylight * (1. - RAIN_SUNLIGHT_LOSS * weather.rain);
+        light.shadows_enabled = daylight > 0.;
+    }
+    for (mut ambient, fog) in &mut cameras {
+        ambient.brightness =
+            NIGHT_AMBIENT.lerp(DAY_AMBIENT, daylight) * (1. - RAIN_AMBIENT_LOSS * weather.rain);
+        if let Some(mut fog) = fog {
+            let clear = NIGHT_FOG.mix(&DAY_FOG, daylight);
+            // rain fog is darker at night too
+            fog.color = clear.mix(&RAIN_FOG.mix(&NIGHT_FOG, 1. - daylight), weather.rain);
+        }
+    }
+}
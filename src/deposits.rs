@@ -0,0 +1,150 @@
+use bevy::{
+    pbr::decal::{ForwardDecal, ForwardDecalMaterial, ForwardDecalMaterialExt},
+    prelude::*,
+};
+use rhai::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    map::Map, menu::GameState, palettes::HatchImages, pause_menu::Settings,
+    script_api::SharedScriptWorld, sim::Sim,
+};
+
+/// Kinds of deposits, with the color they are drawn in
+pub const DEPOSIT_KINDS: [(&str, Color); 3] = [
+    ("stone", Color::srgb(0.6, 0.6, 0.6)),
+    ("iron", Color::srgb(0.7, 0.35, 0.2)),
+    ("clay", Color::srgb(0.8, 0.6, 0.3)),
+];
+/// Opacity of the deposits drawn on the ground
+const DEPOSIT_OVERLAY: f32 = 0.35;
+/// Height of the box the deposits are projected on the ground from, in world units
+const OVERLAY_HEIGHT: f32 = 40.;
+
+/// Deposits of resources in the ground, painted in the editor and given by the scenarios.
+/// Scripts read the amount of a kind of deposit at a place with `deposit_at(x, z, kind)`.
+/// They are drawn on the ground in the color of their kind.
+pub struct DepositPlugin;
+
+impl Plugin for DepositPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, draw_deposits.run_if(in_state(GameState::InGame)))
+            .add_systems(OnExit(GameState::InGame), clear_deposits);
+    }
+}
+
+/// A round deposit of a resource
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Deposit {
+    /// One of the `DEPOSIT_KINDS`
+    pub kind: String,
+    pub pos: Vec2,
+    pub radius: f32,
+    pub amount: f64,
+}
+
+/// The deposits of the map, shared with the scripts and saved with the sim. Cleared when the
+/// sim restarts, the scenario puts its own back.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Deposits {
+    pub deposits: Vec<Deposit>,
+}
+
+impl Deposits {
+    /// Amount of a kind of deposit at a position, summed over the deposits covering it
+    pub fn amount_at(&self, pos: Vec2, kind: &str) -> f64 {
+        self.deposits
+            .iter()
+            .filter(|d| d.kind == kind && d.pos.distance(pos) <= d.radius)
+            .map(|d| d.amount)
+            .sum()
+    }
+
+    /// Remove the deposits whose center is within `radius` of a position
+    pub fn erase(&mut self, pos: Vec2, radius: f32) {
+        self.deposits.retain(|d| d.pos.distance(pos) > radius);
+    }
+}
+
+/// Color of a kind of deposit, by index in `DEPOSIT_KINDS`, in the palette of the settings
+pub fn deposit_color(i: usize, settings: &Settings) -> Color {
+    settings.palette.color(i, DEPOSIT_KINDS[i].1)
+}
+
+fn clear_deposits(sim: Res<Sim>) {
+    sim.script_world.0.lock().unwrap().deposits = Deposits::default();
+}
+
+/// Deposit drawn on the ground
+#[derive(Component)]
+struct DepositOverlay;
+
+/// Draw the deposits on the ground again when they change, or the palette
+fn draw_deposits(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    map: Res<Map>,
+    drawn: Query<Entity, With<DepositOverlay>>,
+    mut shown: Local<Vec<Deposit>>,
+    mut materials: ResMut<Assets<ForwardDecalMaterial<StandardMaterial>>>,
+    asset_server: Res<AssetServer>,
+    settings: Res<Settings>,
+    hatches: Res<HatchImages>,
+) {
+    let deposits = sim.script_world.0.lock().unwrap().deposits.deposits.clone();
+    if deposits == *shown && !settings.is_changed() {
+        return;
+    }
+    for e in &drawn {
+        commands.entity(e).despawn();
+    }
+    for deposit in &deposits {
+        let Some(i) = DEPOSIT_KINDS
+            .iter()
+            .position(|(kind, _)| *kind == deposit.kind)
+        else {
+            continue;
+        };
+        let color = deposit_color(i, &settings);
+        let (texture, alpha) = if settings.hatch_patterns {
+            (hatches.round(i), (DEPOSIT_OVERLAY * 2.).min(1.))
+        } else {
+            (asset_server.load("img/circle.png"), DEPOSIT_OVERLAY)
+        };
+        let height = map.get_height(deposit.pos.extend(0.).xzy());
+        commands.spawn((
+            Name::new("deposit"),
+            DepositOverlay,
+            ForwardDecal,
+            MeshMaterial3d(materials.add(ForwardDecalMaterial {
+                base: StandardMaterial {
+                    base_color_texture: Some(texture),
+                    base_color: color.with_alpha(alpha),
+                    alpha_mode: AlphaMode::Blend,
+                    ..default()
+                },
+                extension: ForwardDecalMaterialExt {
+                    depth_fade_factor: 1.0,
+                },
+            })),
+            Transform::from_translation(deposit.pos.extend(height).xzy()).with_scale(Vec3::new(
+                deposit.radius * 2.,
+                OVERLAY_HEIGHT,
+                deposit.radius * 2.,
+            )),
+            StateScoped(GameState::InGame),
+        ));
+    }
+    *shown = deposits;
+}
+
+/// Register `deposit_at(x, z, kind)`, the amount of a kind of deposit at a place
+pub fn register_deposit_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("deposit_at", move |x: f64, z: f64, kind: &str| -> f64 {
+        let world = w.0.lock().unwrap();
+        world
+            .deposits
+            .amount_at(Vec2::new(x as f32, z as f32), kind)
+    });
+}
@@ -0,0 +1,106 @@
+use bevy::{
+    diagnostic::{
+        DiagnosticPath, DiagnosticsStore, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin,
+    },
+    prelude::*,
+};
+
+use crate::{
+    input_map::{Action, Actions},
+    map::{CHUNK_COUNT, KDTREE_SIZE},
+    sim::TICK_DURATION,
+    ui::FontHandle,
+};
+
+/// Overlay of performance counters, toggled with F1
+pub struct DiagnosticsOverlayPlugin;
+
+impl Plugin for DiagnosticsOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            FrameTimeDiagnosticsPlugin::default(),
+            EntityCountDiagnosticsPlugin,
+        ))
+        .add_systems(Startup, setup_overlay)
+        .add_systems(
+            Update,
+            (toggle_overlay, update_overlay.after(toggle_overlay)),
+        );
+    }
+}
+
+/// Lines of the overlay : label, diagnostic, and number of decimals
+const LINES: [(&str, DiagnosticPath, usize); 6] = [
+    ("FPS", FrameTimeDiagnosticsPlugin::FPS, 0),
+    ("Frame time", FrameTimeDiagnosticsPlugin::FRAME_TIME, 2),
+    ("Entities", EntityCountDiagnosticsPlugin::ENTITY_COUNT, 0),
+    ("Chunks", CHUNK_COUNT, 0),
+    ("KdTree size", KDTREE_SIZE, 0),
+    ("Sim tick", TICK_DURATION, 2),
+];
+
+#[derive(Component)]
+struct DiagnosticsOverlay;
+
+fn setup_overlay(mut commands: Commands, font: Res<FontHandle>) {
+    commands.spawn((
+        Name::new("diagnostics overlay"),
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.),
+            top: Val::Px(10.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        BackgroundColor(bevy::color::palettes::css::BLACK.with_alpha(0.7).into()),
+        Text::default(),
+        TextFont {
+            font: font.0.clone(),
+            font_size: 12.,
+            ..default()
+        },
+        Label,
+        GlobalZIndex(20),
+        Pickable::IGNORE,
+        Visibility::Hidden,
+        DiagnosticsOverlay,
+    ));
+}
+
+/// Toggle the overlay, and the diagnostics only measured while it is shown
+fn toggle_overlay(
+    actions: Actions,
+    mut overlay: Single<&mut Visibility, With<DiagnosticsOverlay>>,
+    mut diagnostics: ResMut<DiagnosticsStore>,
+) {
+    if actions.just_pressed(Action::ToggleDiagnostics) {
+        overlay.toggle_visible_hidden();
+        if let Some(kdtree_size) = diagnostics.get_mut(&KDTREE_SIZE) {
+            kdtree_size.is_enabled = **overlay != Visibility::Hidden;
+        }
+    }
+}
+
+fn update_overlay(
+    diagnostics: Res<DiagnosticsStore>,
+    overlay: Single<(&mut Text, &Visibility), With<DiagnosticsOverlay>>,
+) {
+    let (mut text, visibility) = overlay.into_inner();
+    if *visibility == Visibility::Hidden {
+        return;
+    }
+    let lines: Vec<String> = LINES
+        .iter()
+        .map(|(label, path, decimals)| {
+            let decimals = *decimals;
+            let Some(diagnostic) = diagnostics.get(path) else {
+                return format!("{label} : -");
+            };
+            match diagnostic.smoothed() {
+                Some(value) => format!("{label} : {value:.decimals$}{}", diagnostic.suffix),
+                None => format!("{label} : -"),
+            }
+        })
+        .collect();
+    text.0 = lines.join("\n");
+}
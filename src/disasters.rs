@@ -0,0 +1,406 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use rhai::{Dynamic, Engine, NativeCallContext};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::{BuildId, Building, GameId},
+    clock::TICKS_PER_DAY,
+    extraction::river_beside,
+    localization::Localization,
+    map::{BuildingInstance, Map, WaterSurface},
+    mapgen::Continent,
+    menu::GameState,
+    particles::{Effect, SpawnEffect},
+    script_api::{ScriptEvent, SharedScriptWorld, run_scripts_with_world},
+    sim::{Sim, sim_running},
+    toasts::Toasts,
+    weather::{WEATHER_HOURS, WeatherKind},
+};
+
+/// Distance from a river within which the buildings are flooded by the storms
+const FLOOD_REACH: f32 = 8.;
+/// Radius of the floods raised by the storms
+const STORM_FLOOD_RADIUS: f32 = 12.;
+/// Rise of the rivers in a storm with the ground fully wet, in world units
+const STORM_RISE: f32 = 1.5;
+/// Damage done every tick to a building under a world unit of water
+const FLOOD_DAMAGE: f32 = 0.002;
+/// Damage done every tick to a building on fire
+const FIRE_DAMAGE: f32 = 0.004;
+/// Ticks a fire burns before dying out
+const FIRE_TICKS: u64 = 300;
+/// Distance within which a fire spreads to the flammable buildings around
+const FIRE_SPREAD_DISTANCE: f32 = 8.;
+/// Chance a fire spreads every tick, without rain
+const FIRE_SPREAD_CHANCE: f64 = 0.005;
+/// Chance a fire is put out every tick under the heaviest rain
+const RAIN_EXTINGUISH_CHANCE: f64 = 0.02;
+/// Time between two bursts of flames over a building on fire, in seconds
+const FLAMES_INTERVAL: f32 = 0.2;
+/// Length of a weather period, in ticks, the time a flood lasts
+const PERIOD_TICKS: u64 = TICKS_PER_DAY * WEATHER_HOURS / 24;
+
+/// Floods and fires. In a storm the rivers rise and flood the low-lying buildings near them,
+/// fires spread between the flammable buildings close to each other until they burn out or
+/// the rain puts them out, and both damage the buildings they reach until they are destroyed.
+/// Scripts start them with `start_fire(x, z, radius)` and `flood(x, z, radius, rise)`, the
+/// scenarios at given ticks, and read their state with `building_damage(id)` and
+/// `on_fire(id)`, or repair a building with `repair(id)`. Damaged buildings smoke, the
+/// burning ones are in flames and the floods are drawn as muddy water.
+pub struct DisasterPlugin;
+
+impl Plugin for DisasterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_flood_material)
+            .add_systems(
+                FixedUpdate,
+                run_disasters
+                    .after(run_scripts_with_world)
+                    .run_if(sim_running),
+            )
+            .add_systems(
+                Update,
+                (show_fires, draw_floods).run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// A disaster started by a script or a scenario
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum Disaster {
+    /// Set fire to the buildings within `radius` of a place
+    Fire { x: f32, z: f32, radius: f32 },
+    /// Raise the water `rise` above the ground within `radius` of a place, for a weather period
+    Flood {
+        x: f32,
+        z: f32,
+        radius: f32,
+        rise: f32,
+    },
+}
+
+/// Water risen over an area
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub struct Flood {
+    pub center: Vec2,
+    pub radius: f32,
+    /// Height of the water
+    pub level: f32,
+    /// Tick the water goes down at
+    pub until: u64,
+}
+
+/// Fires, floods and the damage they did, shared with the scripts and saved with the sim
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Disasters {
+    /// Buildings on fire, by game id, with the ticks they have burnt for
+    pub fires: BTreeMap<u64, u64>,
+    /// Damage of the buildings, by game id, between 0 and 1
+    pub damage: BTreeMap<u64, f32>,
+    pub floods: Vec<Flood>,
+    /// Disasters started since the last tick
+    started: Vec<Disaster>,
+    /// Whether the rivers rose for the current storm
+    storm: bool,
+}
+
+impl Disasters {
+    /// Start a disaster on the next tick
+    pub fn start(&mut self, disaster: Disaster) {
+        self.started.push(disaster);
+    }
+
+    /// Add damage to a building, telling the scripts when it starts being damaged
+    fn damage(&mut self, id: u64, amount: f32, emitted: &mut Vec<ScriptEvent>, tick: u64) {
+        let damage = self.damage.entry(id).or_default();
+        if *damage == 0. {
+            emitted.push(ScriptEvent {
+                name: "building_damaged".to_string(),
+                payload: Dynamic::from_int(id as i64),
+                tick,
+            });
+        }
+        *damage = (*damage + amount).min(1.);
+    }
+}
+
+/// Start the new disasters, raise the rivers in the storms, spread the fires and damage the
+/// buildings, destroying the ones fully damaged
+fn run_disasters(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    mut map: ResMut<Map>,
+    placed: Query<(&GameId, &BuildId, &BuildingInstance)>,
+    buildings: Res<Assets<Building>>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) {
+    let mut guard = sim.script_world.0.lock().unwrap();
+    let world = &mut *guard;
+    let disasters = &mut world.disasters;
+    let tick = sim.tick;
+    let instances: BTreeMap<u64, (&BuildId, &BuildingInstance)> = placed
+        .iter()
+        .map(|(id, bid, instance)| (id.0, (bid, instance)))
+        .collect();
+    let id_of = |e: Entity| placed.get(e).ok().map(|(id, _, _)| id.0);
+    let flammable = |id: u64| {
+        instances
+            .get(&id)
+            .and_then(|(bid, _)| buildings.get(&bid.0))
+            .is_some_and(|b| b.flammable)
+    };
+
+    for disaster in std::mem::take(&mut disasters.started) {
+        match disaster {
+            Disaster::Fire { x, z, radius } => {
+                for instance in map.buildings_in_radius(Vec2::new(x, z), radius) {
+                    if let Some(id) = id_of(instance.entity) {
+                        disasters.fires.entry(id).or_default();
+                    }
+                }
+            }
+            Disaster::Flood { x, z, radius, rise } => {
+                let center = Vec2::new(x, z);
+                disasters.floods.push(Flood {
+                    center,
+                    radius,
+                    level: map.get_height(Vec3::new(x, 0., z)) + rise,
+                    until: tick + PERIOD_TICKS,
+                });
+            }
+        }
+    }
+
+    // the rivers rise once at the start of a storm, by the buildings near them
+    let storm = world.weather.kind == WeatherKind::Storm;
+    if storm && !disasters.storm {
+        let rise = STORM_RISE * world.weather.wetness;
+        for (_, instance) in instances.values() {
+            let center = instance.center();
+            let area = (
+                center - instance.half_extents,
+                center + instance.half_extents,
+            );
+            let Some((x, y)) = river_beside(&map.continent, area, FLOOD_REACH) else {
+                continue;
+            };
+            let river = map.continent.to_world(Continent::xy2h(x, y)).xz();
+            if disasters
+                .floods
+                .iter()
+                .any(|flood| flood.center.distance(river) < flood.radius / 2.)
+            {
+                continue;
+            }
+            disasters.floods.push(Flood {
+                center: river,
+                radius: STORM_FLOOD_RADIUS,
+                level: map.get_height(river.extend(0.).xzy()) + rise,
+                until: tick + PERIOD_TICKS,
+            });
+        }
+    }
+    disasters.storm = storm;
+    disasters.floods.retain(|flood| flood.until > tick);
+
+    let mut emitted = Vec::new();
+    for flood in disasters.floods.clone() {
+        for instance in map.buildings_in_radius(flood.center, flood.radius) {
+            let Some(id) = id_of(instance.entity) else {
+                continue;
+            };
+            let ground = map.get_height(instance.center().extend(0.).xzy());
+            let depth = flood.level - ground;
+            if depth > 0. {
+                disasters.damage(id, FLOOD_DAMAGE * depth.min(1.), &mut emitted, tick);
+            }
+        }
+    }
+
+    let rain = world.weather.kind.rain() as f64;
+    let mut ignited = Vec::new();
+    for (id, burnt) in disasters.fires.iter_mut() {
+        *burnt += 1;
+        let Some((_, instance)) = instances.get(id) else {
+            continue;
+        };
+        if sim.rng.f64() < FIRE_SPREAD_CHANCE * (1. - rain) {
+            let neighbors: Vec<u64> = map
+                .buildings_in_radius(instance.center(), FIRE_SPREAD_DISTANCE)
+                .filter_map(|b| id_of(b.entity))
+                .filter(|other| other != id && flammable(*other))
+                .collect();
+            if !neighbors.is_empty() {
+                let i = (sim.rng.f64() * neighbors.len() as f64) as usize;
+                ignited.push(neighbors[i.min(neighbors.len() - 1)]);
+            }
+        }
+    }
+    let fires: Vec<u64> = disasters.fires.keys().copied().collect();
+    for id in fires {
+        disasters.damage(id, FIRE_DAMAGE, &mut emitted, tick);
+    }
+    disasters
+        .fires
+        .retain(|_, burnt| *burnt < FIRE_TICKS && sim.rng.f64() >= RAIN_EXTINGUISH_CHANCE * rain);
+    for id in ignited {
+        disasters.fires.entry(id).or_default();
+    }
+
+    let destroyed: Vec<u64> = disasters
+        .damage
+        .iter()
+        .filter(|(_, damage)| **damage >= 1.)
+        .map(|(id, _)| *id)
+        .collect();
+    for id in destroyed {
+        disasters.damage.remove(&id);
+        disasters.fires.remove(&id);
+        let Some((bid, instance)) = instances.get(&id) else {
+            continue;
+        };
+        map.entities.remove_one((*instance).clone());
+        commands.entity(instance.entity).despawn();
+        emitted.push(ScriptEvent {
+            name: "building_destroyed".to_string(),
+            payload: Dynamic::from_int(id as i64),
+            tick,
+        });
+        if let Some(building) = buildings.get(&bid.0) {
+            let name = [("name", building.name.clone())];
+            toasts.error(localization.get_args("toast-building-destroyed", &name));
+        }
+    }
+    // the buildings removed by the player don't burn anymore
+    disasters.fires.retain(|id, _| instances.contains_key(id));
+    disasters.damage.retain(|id, _| instances.contains_key(id));
+    world.emitted.extend(emitted);
+}
+
+#[derive(Resource)]
+struct FloodMaterial(Handle<StandardMaterial>);
+
+/// Water of a flood
+#[derive(Component)]
+struct FloodWater;
+
+fn setup_flood_material(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    commands.insert_resource(FloodMaterial(materials.add(StandardMaterial {
+        base_color: Color::srgba(0.35, 0.3, 0.2, 0.7),
+        alpha_mode: AlphaMode::Blend,
+        perceptual_roughness: 0.2,
+        ..default()
+    })));
+}
+
+/// Burst flames over the buildings on fire
+fn show_fires(
+    sim: Res<Sim>,
+    placed: Query<(&GameId, &GlobalTransform), With<BuildingInstance>>,
+    time: Res<Time>,
+    mut timer: Local<Timer>,
+    mut effects: EventWriter<SpawnEffect>,
+) {
+    if timer.duration().is_zero() {
+        *timer = Timer::from_seconds(FLAMES_INTERVAL, TimerMode::Repeating);
+    }
+    if !timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let world = sim.script_world.0.lock().unwrap();
+    for (id, transform) in &placed {
+        if world.disasters.fires.contains_key(&id.0) {
+            effects.write(SpawnEffect {
+                effect: Effect::Flames,
+                pos: transform.translation(),
+                radius: 1.,
+            });
+        }
+    }
+}
+
+/// Draw the water of the floods again when they change
+fn draw_floods(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    drawn: Query<Entity, With<FloodWater>>,
+    mut shown: Local<Vec<Flood>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    material: Res<FloodMaterial>,
+) {
+    let floods = sim.script_world.0.lock().unwrap().disasters.floods.clone();
+    if floods == *shown {
+        return;
+    }
+    for e in &drawn {
+        commands.entity(e).despawn();
+    }
+    for flood in &floods {
+        commands.spawn((
+            Name::new("flood"),
+            FloodWater,
+            WaterSurface,
+            Mesh3d(meshes.add(Circle::new(flood.radius))),
+            MeshMaterial3d(material.0.clone()),
+            Transform::from_translation(flood.center.extend(flood.level).xzy())
+                .with_rotation(Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2)),
+            StateScoped(GameState::InGame),
+        ));
+    }
+    *shown = floods;
+}
+
+/// Register `start_fire(x, z, radius)` and `flood(x, z, radius, rise)`, starting on the next
+/// tick, `building_damage(id)`, between 0 and 1, `on_fire(id)`, and `repair(id)`, clearing the
+/// damage of a building
+pub fn register_disaster_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("start_fire", move |x: f64, z: f64, radius: f64| {
+        w.0.lock().unwrap().disasters.start(Disaster::Fire {
+            x: x as f32,
+            z: z as f32,
+            radius: radius as f32,
+        });
+    });
+
+    let w = world.clone();
+    engine.register_fn("flood", move |x: f64, z: f64, radius: f64, rise: f64| {
+        w.0.lock().unwrap().disasters.start(Disaster::Flood {
+            x: x as f32,
+            z: z as f32,
+            radius: radius as f32,
+            rise: rise as f32,
+        });
+    });
+
+    let w = world.clone();
+    engine.register_fn("building_damage", move |id: i64| -> f64 {
+        let world = w.0.lock().unwrap();
+        world
+            .disasters
+            .damage
+            .get(&(id as u64))
+            .copied()
+            .unwrap_or(0.) as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("on_fire", move |id: i64| -> bool {
+        w.0.lock()
+            .unwrap()
+            .disasters
+            .fires
+            .contains_key(&(id as u64))
+    });
+
+    let w = world.clone();
+    engine.register_fn("repair", move |ctx: NativeCallContext, id: i64| {
+        let mut world = w.0.lock().unwrap();
+        if world.disasters.damage.remove(&(id as u64)).is_some() {
+            world.emit(&ctx, "building_repaired", Dynamic::from_int(id));
+        }
+    });
+}
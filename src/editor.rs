@@ -0,0 +1,755 @@
+use std::{collections::BTreeMap, f32::consts::FRAC_PI_2, path::Path};
+
+use bevy::{
+    ecs::system::SystemParam,
+    input::keyboard::{Key, KeyboardInput},
+    picking::hover::HoverMap,
+    prelude::*,
+};
+
+use crate::{
+    build::{BuildId, Building, SelectedBuild, cast_to_terrain},
+    deposits::{DEPOSIT_KINDS, Deposit, deposit_color},
+    input_map::{Action, Actions},
+    localization::{Localization, LocalizedText},
+    map::{BuildingInstance, IsGround, Map, PatchOp},
+    mapgen::WorldGen,
+    menu::GameState,
+    pause_menu::Settings,
+    replication::TerrainOp,
+    scenario::{Objective, ScenarioBuilding, ScenarioFile},
+    sim::{Sim, SimSpeed},
+    toasts::Toasts,
+    ui::{FontHandle, TextFocus},
+    versioning::Versioned,
+};
+
+/// Directory of the assets, the scenarios are exported to its `scenarios` directory
+const ASSETS_DIR: &str = "assets";
+/// Resources always offered to start with
+const DEFAULT_RESOURCES: [&str; 3] = ["food", "money", "material"];
+const RESOURCE_STEP: f64 = 10.;
+const POPULATION_STEP: f64 = 10.;
+const STOCK_STEP: f64 = 100.;
+const RADIUS_RANGE: (f32, f32) = (1., 50.);
+const RADIUS_STEP: f32 = 2.;
+/// Strength of the terrain brushes held for a second
+const BRUSH_SPEED: f32 = 4.;
+/// Amount of a painted deposit
+const DEPOSIT_AMOUNT: f64 = 1000.;
+const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+const SELECTED_BUTTON: Color = Color::srgb(0.35, 0.75, 0.35);
+
+/// Map and scenario editor, opened from the new game page. The sim is paused, the terrain
+/// brushes raise, lower, flatten and smooth the ground without limits, the deposits of
+/// resources are painted and erased, and the buildings are placed anywhere. A panel sets the
+/// name of the scenario, its starting resources and its objectives, and exports the world
+/// and all this as a scenario package, played with `--scenario`.
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<EditorState>()
+            .insert_resource(Brush::default())
+            .insert_resource(ScenarioDraft::default())
+            .add_systems(
+                OnEnter(GameState::InGame),
+                setup_editor.run_if(in_state(EditorState::On)),
+            )
+            .add_systems(OnExit(GameState::InGame), leave_editor)
+            .add_systems(
+                Update,
+                (
+                    editor_buttons,
+                    focus_name,
+                    edit_name.after(focus_name),
+                    refresh_editor_panel.after(editor_buttons),
+                    paint.after(editor_buttons),
+                )
+                    .run_if(in_state(GameState::InGame))
+                    .run_if(in_state(EditorState::On)),
+            );
+    }
+}
+
+/// Whether the world is edited rather than played
+#[derive(States, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum EditorState {
+    #[default]
+    Off,
+    On,
+}
+
+/// What a click on the terrain does in the editor
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum EditorTool {
+    Terrain(PatchOp),
+    /// Paint a deposit, by index in `DEPOSIT_KINDS`
+    Deposit(usize),
+    /// Erase the deposits
+    Erase,
+}
+
+/// Tool of the editor, and its radius in world units
+#[derive(Resource)]
+struct Brush {
+    tool: Option<EditorTool>,
+    radius: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            tool: None,
+            radius: 5.,
+        }
+    }
+}
+
+/// What an objective of the edited scenario is about
+#[derive(Clone, PartialEq, Debug)]
+enum Goal {
+    Population,
+    /// Stock of a resource
+    Resource(String),
+}
+
+#[derive(Clone, Debug)]
+struct DraftObjective {
+    goal: Goal,
+    target: f64,
+}
+
+impl DraftObjective {
+    fn description(&self) -> String {
+        match &self.goal {
+            Goal::Population => format!("Reach {:.0} inhabitants", self.target),
+            Goal::Resource(resource) => format!("Stock {:.0} {resource}", self.target),
+        }
+    }
+
+    /// Body of its predicate, the progress toward the target
+    fn predicate(&self) -> String {
+        match &self.goal {
+            Goal::Population => format!("this.aggregates.population / {:.1}", self.target),
+            Goal::Resource(resource) => {
+                format!("this.resource.{resource} / {:.1}", self.target)
+            }
+        }
+    }
+}
+
+/// Scenario being edited
+#[derive(Resource, Default)]
+struct ScenarioDraft {
+    name: String,
+    starting_resources: Vec<(String, f64)>,
+    objectives: Vec<DraftObjective>,
+}
+
+impl ScenarioDraft {
+    /// Name of the package, from the name of the scenario
+    fn slug(&self) -> String {
+        let slug: String = self
+            .name
+            .trim()
+            .to_lowercase()
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+        if slug.is_empty() {
+            "scenario".to_string()
+        } else {
+            slug
+        }
+    }
+
+    /// Script defining the predicates of the objectives
+    fn script(&self) -> String {
+        let mut script = format!(
+            "// Predicates of the scenario \"{}\", written by the editor.\n\
+             // They are called with the sim data as `this`, and return the progress toward\n\
+             // the objective between 0 and 1.\n",
+            self.name
+        );
+        for (i, objective) in self.objectives.iter().enumerate() {
+            script += &format!(
+                "\nfn objective_{}() {{\n    {}\n}}\n",
+                i + 1,
+                objective.predicate()
+            );
+        }
+        script
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum EditorButton {
+    Tool(EditorTool),
+    Smaller,
+    Larger,
+    /// Starting resources, by index in the draft
+    ResourceLess(usize),
+    ResourceMore(usize),
+    /// Objectives, by index in the draft
+    TargetLess(usize),
+    TargetMore(usize),
+    RemoveObjective(usize),
+    /// A population objective, or a stock of one of the starting resources
+    AddObjective(Option<usize>),
+    Export,
+}
+
+/// Panel of the editor
+#[derive(Component)]
+struct EditorPanel;
+
+/// Part of the panel listed again when the brush or the draft change
+#[derive(Component)]
+struct EditorSections;
+
+/// Text box of the name of the scenario, focused by clicking it
+#[derive(Component)]
+struct NameBox;
+
+/// Pause the sim, start a draft from the starting resources of the sim and show the panel
+fn setup_editor(
+    mut commands: Commands,
+    mut speed: ResMut<SimSpeed>,
+    sim: Res<Sim>,
+    mut draft: ResMut<ScenarioDraft>,
+    mut brush: ResMut<Brush>,
+    font: Res<FontHandle>,
+) {
+    *speed = SimSpeed::Paused;
+    *brush = Brush::default();
+    let mut resources = sim.starting_resources.clone();
+    for resource in DEFAULT_RESOURCES {
+        resources.entry(resource.to_string()).or_insert(0.);
+    }
+    *draft = ScenarioDraft {
+        name: String::new(),
+        starting_resources: resources.into_iter().collect(),
+        objectives: Vec::new(),
+    };
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 16.,
+        ..default()
+    };
+    commands.spawn((
+        Name::new("editor"),
+        EditorPanel,
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.),
+            top: Val::Px(40.),
+            width: Val::Px(300.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(6.),
+            padding: UiRect::all(Val::Px(8.)),
+            ..default()
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.7)),
+        StateScoped(GameState::InGame),
+        children![
+            (
+                LocalizedText::new("editor-title"),
+                TextFont {
+                    font_size: 24.,
+                    ..text_font.clone()
+                },
+                Label,
+            ),
+            (
+                Node {
+                    column_gap: Val::Px(6.),
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                children![
+                    (LocalizedText::new("editor-name"), text_font.clone(), Label),
+                    (
+                        Button,
+                        Node {
+                            flex_grow: 1.,
+                            padding: UiRect::all(Val::Px(4.)),
+                            ..default()
+                        },
+                        BackgroundColor(Color::BLACK),
+                        NameBox,
+                        children![(Text::default(), text_font.clone(), Label, Pickable::IGNORE)],
+                    ),
+                ],
+            ),
+            (
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(6.),
+                    ..default()
+                },
+                EditorSections,
+            ),
+        ],
+    ));
+}
+
+fn leave_editor(mut next_state: ResMut<NextState<EditorState>>) {
+    next_state.set(EditorState::Off);
+}
+
+fn editor_button(button: EditorButton, label: impl Bundle, text_font: &TextFont) -> impl Bundle {
+    (
+        Button,
+        Node {
+            padding: UiRect::axes(Val::Px(6.), Val::Px(4.)),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        BackgroundColor(NORMAL_BUTTON),
+        button,
+        children![(label, text_font.clone(), Label, Pickable::IGNORE)],
+    )
+}
+
+fn row() -> Node {
+    Node {
+        column_gap: Val::Px(4.),
+        row_gap: Val::Px(4.),
+        flex_wrap: FlexWrap::Wrap,
+        align_items: AlignItems::Center,
+        ..default()
+    }
+}
+
+/// List the tools, the starting resources and the objectives again when they change
+fn refresh_editor_panel(
+    mut commands: Commands,
+    sections: Single<(Entity, Ref<EditorSections>)>,
+    brush: Res<Brush>,
+    draft: Res<ScenarioDraft>,
+    font: Res<FontHandle>,
+) {
+    let (sections, added) = *sections;
+    if !brush.is_changed() && !draft.is_changed() && !added.is_added() {
+        return;
+    }
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 16.,
+        ..default()
+    };
+    let tools = [
+        (EditorTool::Terrain(PatchOp::Up), "editor-raise".into()),
+        (EditorTool::Terrain(PatchOp::Down), "editor-lower".into()),
+        (
+            EditorTool::Terrain(PatchOp::Flatten),
+            "editor-flatten".into(),
+        ),
+        (EditorTool::Terrain(PatchOp::Smooth), "editor-smooth".into()),
+    ]
+    .into_iter()
+    .chain(
+        DEPOSIT_KINDS
+            .iter()
+            .enumerate()
+            .map(|(i, (kind, _))| (EditorTool::Deposit(i), format!("deposit-{kind}"))),
+    )
+    .chain([(EditorTool::Erase, "editor-erase".into())]);
+    commands
+        .entity(sections)
+        .despawn_related::<Children>()
+        .with_children(|parent| {
+            parent.spawn(row()).with_children(|parent| {
+                for (tool, key) in tools {
+                    let color = if brush.tool == Some(tool) {
+                        SELECTED_BUTTON
+                    } else {
+                        NORMAL_BUTTON
+                    };
+                    parent
+                        .spawn(editor_button(
+                            EditorButton::Tool(tool),
+                            LocalizedText::new(key),
+                            &text_font,
+                        ))
+                        .insert(BackgroundColor(color));
+                }
+            });
+            parent.spawn(row()).with_children(|parent| {
+                parent.spawn(editor_button(
+                    EditorButton::Smaller,
+                    Text::new("-"),
+                    &text_font,
+                ));
+                parent.spawn((
+                    LocalizedText::new("editor-radius").with_arg("radius", brush.radius),
+                    text_font.clone(),
+                    Label,
+                ));
+                parent.spawn(editor_button(
+                    EditorButton::Larger,
+                    Text::new("+"),
+                    &text_font,
+                ));
+            });
+            parent.spawn((
+                LocalizedText::new("editor-starting-resources"),
+                text_font.clone(),
+                Label,
+            ));
+            for (i, (resource, amount)) in draft.starting_resources.iter().enumerate() {
+                parent.spawn(row()).with_children(|parent| {
+                    parent.spawn(editor_button(
+                        EditorButton::ResourceLess(i),
+                        Text::new("-"),
+                        &text_font,
+                    ));
+                    parent.spawn((
+                        Text(format!("{resource} : {amount:.0}")),
+                        text_font.clone(),
+                        Label,
+                    ));
+                    parent.spawn(editor_button(
+                        EditorButton::ResourceMore(i),
+                        Text::new("+"),
+                        &text_font,
+                    ));
+                });
+            }
+            parent.spawn((
+                LocalizedText::new("editor-objectives"),
+                text_font.clone(),
+                Label,
+            ));
+            for (i, objective) in draft.objectives.iter().enumerate() {
+                parent.spawn(row()).with_children(|parent| {
+                    parent.spawn(editor_button(
+                        EditorButton::TargetLess(i),
+                        Text::new("-"),
+                        &text_font,
+                    ));
+                    parent.spawn((Text(objective.description()), text_font.clone(), Label));
+                    parent.spawn(editor_button(
+                        EditorButton::TargetMore(i),
+                        Text::new("+"),
+                        &text_font,
+                    ));
+                    parent.spawn(editor_button(
+                        EditorButton::RemoveObjective(i),
+                        Text::new("x"),
+                        &text_font,
+                    ));
+                });
+            }
+            parent.spawn(row()).with_children(|parent| {
+                parent.spawn(editor_button(
+                    EditorButton::AddObjective(None),
+                    LocalizedText::new("editor-add-population"),
+                    &text_font,
+                ));
+                for (i, (resource, _)) in draft.starting_resources.iter().enumerate() {
+                    parent.spawn(editor_button(
+                        EditorButton::AddObjective(Some(i)),
+                        LocalizedText::new("editor-add-stock").with_arg("resource", resource),
+                        &text_font,
+                    ));
+                }
+            });
+            parent.spawn(editor_button(
+                EditorButton::Export,
+                LocalizedText::new("editor-export"),
+                &text_font,
+            ));
+        });
+}
+
+fn editor_buttons(
+    buttons: Query<(&Interaction, &EditorButton), Changed<Interaction>>,
+    mut brush: ResMut<Brush>,
+    mut draft: ResMut<ScenarioDraft>,
+    export: ExportParams,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) -> Result {
+    let Some((_, button)) = buttons
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Pressed)
+    else {
+        return Ok(());
+    };
+    let step = |goal: &Goal| match goal {
+        Goal::Population => POPULATION_STEP,
+        Goal::Resource(_) => STOCK_STEP,
+    };
+    match *button {
+        EditorButton::Tool(tool) => {
+            brush.tool = if brush.tool == Some(tool) {
+                None
+            } else {
+                Some(tool)
+            };
+        }
+        EditorButton::Smaller => {
+            brush.radius = (brush.radius - RADIUS_STEP).max(RADIUS_RANGE.0);
+        }
+        EditorButton::Larger => {
+            brush.radius = (brush.radius + RADIUS_STEP).min(RADIUS_RANGE.1);
+        }
+        EditorButton::ResourceLess(i) => {
+            if let Some((_, amount)) = draft.starting_resources.get_mut(i) {
+                *amount = (*amount - RESOURCE_STEP).max(0.);
+            }
+        }
+        EditorButton::ResourceMore(i) => {
+            if let Some((_, amount)) = draft.starting_resources.get_mut(i) {
+                *amount += RESOURCE_STEP;
+            }
+        }
+        EditorButton::TargetLess(i) => {
+            if let Some(objective) = draft.objectives.get_mut(i) {
+                let step = step(&objective.goal);
+                objective.target = (objective.target - step).max(step);
+            }
+        }
+        EditorButton::TargetMore(i) => {
+            if let Some(objective) = draft.objectives.get_mut(i) {
+                objective.target += step(&objective.goal);
+            }
+        }
+        EditorButton::RemoveObjective(i) => {
+            if i < draft.objectives.len() {
+                draft.objectives.remove(i);
+            }
+        }
+        EditorButton::AddObjective(resource) => {
+            let goal = match resource.and_then(|i| draft.starting_resources.get(i)) {
+                Some((resource, _)) => Goal::Resource(resource.clone()),
+                None => Goal::Population,
+            };
+            let target = step(&goal) * 10.;
+            draft.objectives.push(DraftObjective { goal, target });
+        }
+        EditorButton::Export => {
+            // a scenario without objectives would be won right away
+            if draft.objectives.is_empty() {
+                toasts.warning(localization.get("toast-scenario-no-objectives"));
+                return Ok(());
+            }
+            let path = export_scenario(&draft, &export)?;
+            info!("Scenario exported to {}", path);
+            let path_arg = [("path", path)];
+            toasts.info(localization.get_args("toast-scenario-exported", &path_arg));
+        }
+    }
+    Ok(())
+}
+
+/// Everything exported with the scenario
+#[derive(SystemParam)]
+struct ExportParams<'w, 's> {
+    worldgen: Res<'w, WorldGen>,
+    map: Res<'w, Map>,
+    sim: Res<'w, Sim>,
+    placed: Query<
+        'w,
+        's,
+        (
+            &'static BuildId,
+            &'static BuildingInstance,
+            &'static Transform,
+        ),
+    >,
+    buildings: Res<'w, Assets<Building>>,
+}
+
+/// Write the scenario and its script in their package, and give the asset path of the
+/// scenario
+fn export_scenario(draft: &ScenarioDraft, export: &ExportParams) -> anyhow::Result<String> {
+    let slug = draft.slug();
+    let script_path = format!("scenarios/{slug}/{slug}.rhai");
+    let scenario_path = format!("scenarios/{slug}/{slug}.scenario");
+    let buildings = export
+        .placed
+        .iter()
+        .filter_map(|(bid, instance, transform)| {
+            let building = export.buildings.get(&bid.0)?;
+            Some(ScenarioBuilding {
+                building: building.name.clone(),
+                pos: instance.center(),
+                rotation: transform.rotation.to_euler(EulerRot::YXZ).0,
+            })
+        })
+        .collect();
+    let deposits: Vec<Deposit> = {
+        let world = export.sim.script_world.0.lock().unwrap();
+        world.deposits.deposits.clone()
+    };
+    let name = draft.name.trim();
+    let file = ScenarioFile {
+        version: ScenarioFile::VERSION,
+        name: if name.is_empty() {
+            slug.clone()
+        } else {
+            name.to_string()
+        },
+        description: String::new(),
+        script: script_path.clone(),
+        starting_resources: draft
+            .starting_resources
+            .iter()
+            .cloned()
+            .collect::<BTreeMap<_, _>>(),
+        objectives: draft
+            .objectives
+            .iter()
+            .enumerate()
+            .map(|(i, objective)| Objective {
+                description: objective.description(),
+                predicate: format!("objective_{}", i + 1),
+                reward: BTreeMap::new(),
+            })
+            .collect(),
+        lose_conditions: Vec::new(),
+        tick_limit: None,
+        disasters: Vec::new(),
+        world: Some(*export.worldgen),
+        terrain: export.map.terrain_edits(),
+        buildings,
+        deposits,
+    };
+    let assets = Path::new(ASSETS_DIR);
+    file.write(&assets.join(&scenario_path))?;
+    std::fs::write(assets.join(&script_path), draft.script())?;
+    Ok(scenario_path)
+}
+
+fn focus_name(
+    name_box: Single<(Entity, &Interaction), (Changed<Interaction>, With<NameBox>)>,
+    mut text_focus: ResMut<TextFocus>,
+) {
+    let (entity, interaction) = *name_box;
+    if *interaction == Interaction::Pressed && text_focus.0.is_none() {
+        text_focus.0 = Some(entity);
+    }
+}
+
+/// Type the name of the scenario. Enter or Escape stop the edition.
+fn edit_name(
+    mut events: EventReader<KeyboardInput>,
+    mut text_focus: ResMut<TextFocus>,
+    mut draft: ResMut<ScenarioDraft>,
+    name_box: Single<(Entity, &Children), With<NameBox>>,
+    mut texts: Query<&mut Text>,
+) {
+    let (entity, children) = *name_box;
+    let focused = text_focus.0 == Some(entity);
+    if focused && !text_focus.is_changed() {
+        for ev in events.read() {
+            if !ev.state.is_pressed() {
+                continue;
+            }
+            match &ev.logical_key {
+                Key::Character(c) => draft.name.push_str(c),
+                Key::Space => draft.name.push(' '),
+                Key::Backspace => {
+                    draft.name.pop();
+                }
+                Key::Enter | Key::Escape => {
+                    text_focus.0 = None;
+                    break;
+                }
+                _ => {}
+            }
+        }
+    } else {
+        events.clear();
+    }
+    if !draft.is_changed() && !text_focus.is_changed() {
+        return;
+    }
+    let mut text = draft.name.clone();
+    if text_focus.0 == Some(entity) {
+        text.push('_');
+    }
+    for child in children {
+        if let Ok(mut t) = texts.get_mut(*child) {
+            t.0 = text.clone();
+        }
+    }
+}
+
+/// Use the tool of the brush where the cursor is on the terrain, while the click is held
+fn paint(
+    brush: Res<Brush>,
+    actions: Actions,
+    time: Res<Time>,
+    mut ray_cast: MeshRayCast,
+    camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    window: Single<&Window>,
+    chunks: Query<&IsGround>,
+    hover_map: Res<HoverMap>,
+    nodes: Query<(), With<Node>>,
+    selected: Query<(), With<SelectedBuild>>,
+    sim: Res<Sim>,
+    mut terrain_ops: EventWriter<TerrainOp>,
+    mut gizmos: Gizmos,
+    settings: Res<Settings>,
+) {
+    let Some(tool) = brush.tool else {
+        return;
+    };
+    // a building being placed takes the click
+    if !selected.is_empty() {
+        return;
+    }
+    let (camera, camera_transform) = *camera;
+    let hit = window
+        .cursor_position()
+        .and_then(|cursor| camera.viewport_to_world(camera_transform, cursor).ok())
+        .and_then(|ray| cast_to_terrain(&mut ray_cast, ray, &chunks));
+    let Some(hit) = hit else {
+        return;
+    };
+    let color = match tool {
+        EditorTool::Deposit(i) => deposit_color(i, &settings),
+        _ => Color::WHITE,
+    };
+    gizmos.circle(
+        Isometry3d::new(hit.point, Quat::from_rotation_x(FRAC_PI_2)),
+        brush.radius,
+        color,
+    );
+    let over_ui = hover_map
+        .values()
+        .any(|hits| hits.keys().any(|hit| nodes.contains(*hit)));
+    if over_ui || !actions.pressed(Action::Place) {
+        return;
+    }
+    match tool {
+        EditorTool::Terrain(op) => {
+            terrain_ops.write(TerrainOp {
+                op,
+                center: hit.point,
+                radius: brush.radius,
+                strength: BRUSH_SPEED * time.delta_secs(),
+                tick: sim.tick,
+            });
+        }
+        EditorTool::Deposit(i) => {
+            if actions.just_pressed(Action::Place) {
+                let mut world = sim.script_world.0.lock().unwrap();
+                world.deposits.deposits.push(Deposit {
+                    kind: DEPOSIT_KINDS[i].0.to_string(),
+                    pos: hit.point.xz(),
+                    radius: brush.radius,
+                    amount: DEPOSIT_AMOUNT,
+                });
+            }
+        }
+        EditorTool::Erase => {
+            let mut world = sim.script_world.0.lock().unwrap();
+            world.deposits.erase(hit.point.xz(), brush.radius);
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use rhai::{Dynamic, Engine};
+
+use crate::{
+    build::{BuildId, Building, GameId},
+    map::{BuildingInstance, GRID_SQUARE_SIZE, Map},
+    mapgen::Continent,
+    pollution::RIVER_AMOUNT,
+    script_api::{SharedScriptWorld, run_scripts_with_world},
+    sim::{Sim, sim_running},
+};
+
+/// Distance past the footprint of a riverside building the river must be within
+pub const RIVERSIDE_REACH: f32 = 3.;
+/// Share of the flow of a river that can be pumped every sim tick
+const WATER_SHARE: f32 = 0.05;
+/// Fish that can be caught every sim tick, per unit of flow of a river
+const FISH_PER_FLOW: f32 = 0.002;
+/// Most points of a river followed downstream of a building taking from it
+const MAX_DOWNSTREAM_STEPS: usize = 10000;
+
+/// Water and fish taken from the rivers. Pumps and fisheries, the buildings with `water` or
+/// `fish` placed by a river, take up to these amounts every sim tick, less when they lack
+/// workers. Each point of a river renews a budget of water and fish every tick, following its
+/// flow, and what is taken from it is missing from the points downstream too : the buildings
+/// upstream are served first, and the ones downstream get what is left. Building scripts read
+/// what their building took with `water_pumped(id)` and `fish_caught(id)`, the sim scripts
+/// the totals with `river_harvest()`, and any script what can still be taken at a place with
+/// `water_available(x, z)` and `fish_available(x, z)`.
+pub struct ExtractionPlugin;
+
+impl Plugin for ExtractionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            extract_from_rivers
+                .before(run_scripts_with_world)
+                .run_if(sim_running),
+        );
+    }
+}
+
+/// Water and fish, taken from a river or left in it
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RiverYield {
+    pub water: f32,
+    pub fish: f32,
+}
+
+impl RiverYield {
+    /// What a river flowing with `amount` renews every sim tick
+    fn budget(amount: f32) -> Self {
+        Self {
+            water: amount * WATER_SHARE,
+            fish: amount * FISH_PER_FLOW,
+        }
+    }
+}
+
+/// What was taken from the rivers during the last sim tick, shared with the scripts. It is
+/// computed again every tick, so it isn't saved.
+#[derive(Clone, Default, Debug)]
+pub struct Extraction {
+    /// Taken by each building, by game id
+    pub taken: BTreeMap<u64, RiverYield>,
+    /// Taken upstream of the points of the rivers, and at them
+    drawn: HashMap<(u32, u32), RiverYield>,
+    /// Multiplier of the flow of the rivers during the tick, from the weather
+    boost: f32,
+}
+
+impl Extraction {
+    /// What can still be taken at a point of the grid
+    pub fn available(&self, continent: &Continent, (x, y): (u32, u32)) -> RiverYield {
+        let amount = continent.get_hydro(x, y).amount;
+        if amount < RIVER_AMOUNT {
+            return RiverYield::default();
+        }
+        let budget = RiverYield::budget(amount * self.boost);
+        let drawn = self.drawn.get(&(x, y)).copied().unwrap_or_default();
+        RiverYield {
+            water: (budget.water - drawn.water).max(0.),
+            fish: (budget.fish - drawn.fish).max(0.),
+        }
+    }
+
+    /// Take up to `wanted` at a point of the grid, and from the points downstream
+    fn take(&mut self, continent: &Continent, point: (u32, u32), wanted: RiverYield) -> RiverYield {
+        let available = self.available(continent, point);
+        let taken = RiverYield {
+            water: wanted.water.min(available.water),
+            fish: wanted.fish.min(available.fish),
+        };
+        let mut point = Some(point);
+        for _ in 0..MAX_DOWNSTREAM_STEPS {
+            let Some((x, y)) = point else {
+                break;
+            };
+            let drawn = self.drawn.entry((x, y)).or_default();
+            drawn.water += taken.water;
+            drawn.fish += taken.fish;
+            point = continent.downstream(x, y);
+        }
+        taken
+    }
+
+    /// Totals taken by all the buildings
+    pub fn total(&self) -> RiverYield {
+        self.taken
+            .values()
+            .fold(RiverYield::default(), |sum, taken| RiverYield {
+                water: sum.water + taken.water,
+                fish: sum.fish + taken.fish,
+            })
+    }
+}
+
+/// Point of the biggest river within `reach` of a footprint, as a (min, max) rectangle
+pub fn river_beside(continent: &Continent, area: (Vec2, Vec2), reach: f32) -> Option<(u32, u32)> {
+    let min = area.0 - reach;
+    let steps = ((area.1 + reach - min) / GRID_SQUARE_SIZE).ceil();
+    (0..=steps.x as u32)
+        .flat_map(|i| (0..=steps.y as u32).map(move |j| (i, j)))
+        .map(|(i, j)| {
+            let pos = min + Vec2::new(i as f32, j as f32) * GRID_SQUARE_SIZE;
+            continent.from_world(&Vec3::new(pos.x, 0., pos.y))
+        })
+        .map(|(x, y)| ((x, y), continent.get_hydro(x, y).amount))
+        .filter(|(_, amount)| *amount >= RIVER_AMOUNT)
+        .max_by(|(_, a1), (_, a2)| a1.total_cmp(a2))
+        .map(|(point, _)| point)
+}
+
+/// Share what the pumps and the fisheries want between them, the ones upstream first
+pub(crate) fn extract_from_rivers(
+    sim: Res<Sim>,
+    map: Res<Map>,
+    placed: Query<(&GameId, &BuildId, &BuildingInstance)>,
+    buildings: Res<Assets<Building>>,
+) {
+    let mut world = sim.script_world.0.lock().unwrap();
+    let continent = &map.continent;
+    let mut extractors: Vec<_> = placed
+        .iter()
+        .filter_map(|(id, bid, instance)| {
+            let building = buildings
+                .get(&bid.0)
+                .filter(|b| b.water > 0. || b.fish > 0.)?;
+            let center = instance.center();
+            let area = (
+                center - instance.half_extents,
+                center + instance.half_extents,
+            );
+            let point = river_beside(continent, area, RIVERSIDE_REACH)?;
+            let staffing = if building.jobs > 0 {
+                world.population.staffing.get(&id.0).copied().unwrap_or(0.)
+            } else {
+                1.
+            };
+            let wanted = RiverYield {
+                water: building.water * staffing,
+                fish: building.fish * staffing,
+            };
+            Some((id.0, point, wanted))
+        })
+        .collect();
+    // the rivers grow on their way down, so the smaller the flow the further upstream
+    extractors.sort_by(|(id1, p1, _), (id2, p2, _)| {
+        let amount = |(x, y): (u32, u32)| continent.get_hydro(x, y).amount;
+        amount(*p1).total_cmp(&amount(*p2)).then(id1.cmp(id2))
+    });
+    let mut extraction = Extraction {
+        boost: world.weather.river_boost(),
+        ..default()
+    };
+    for (id, point, wanted) in extractors {
+        let taken = extraction.take(continent, point, wanted);
+        extraction.taken.insert(id, taken);
+    }
+    world.extraction = extraction;
+}
+
+/// Register `water_pumped(id)` and `fish_caught(id)`, taken by a building during the tick,
+/// `river_harvest()`, a map of the water and fish taken by all of them, and
+/// `water_available(x, z)` and `fish_available(x, z)`, what is left in the river at a place
+pub fn register_extraction_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("water_pumped", move |id: i64| -> f64 {
+        let world = w.0.lock().unwrap();
+        let taken = world.extraction.taken.get(&(id as u64));
+        taken.map_or(0., |taken| taken.water) as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("fish_caught", move |id: i64| -> f64 {
+        let world = w.0.lock().unwrap();
+        let taken = world.extraction.taken.get(&(id as u64));
+        taken.map_or(0., |taken| taken.fish) as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("river_harvest", move || -> rhai::Map {
+        let total = w.0.lock().unwrap().extraction.total();
+        let mut map = rhai::Map::new();
+        map.insert("water".into(), Dynamic::from_float(total.water as f64));
+        map.insert("fish".into(), Dynamic::from_float(total.fish as f64));
+        map
+    });
+
+    let w = world.clone();
+    engine.register_fn("water_available", move |x: f64, z: f64| -> f64 {
+        let world = w.0.lock().unwrap();
+        let Some(map) = &world.map else {
+            return 0.;
+        };
+        let point = map.continent.from_world(&Vec3::new(x as f32, 0., z as f32));
+        world.extraction.available(&map.continent, point).water as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("fish_available", move |x: f64, z: f64| -> f64 {
+        let world = w.0.lock().unwrap();
+        let Some(map) = &world.map else {
+            return 0.;
+        };
+        let point = map.continent.from_world(&Vec3::new(x as f32, 0., z as f32));
+        world.extraction.available(&map.continent, point).fish as f64
+    });
+}
@@ -0,0 +1,227 @@
+use bevy::{
+    math::I64Vec2,
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CameraTarget,
+    map::{BuildingInstance, Chunk, GRID_SQUARE_SIZE, IsGround},
+    menu::GameState,
+    shaders::MapMaterial,
+};
+
+/// Distance around the camera target revealed, in world units
+const CAMERA_REVEAL_RADIUS: f32 = 40.;
+/// Distance around a building revealed, in world units
+const BUILDING_REVEAL_RADIUS: f32 = 15.;
+/// Width of the fading edge of a revealed area, in world units
+const REVEAL_EDGE: f32 = 6.;
+/// Distance the camera target moves before revealing again
+const CAMERA_REVEAL_STEP: f32 = 2.;
+pub const EXPLORATION_QUICKSAVE_PATH: &str = "saves/exploration.ron";
+
+/// Unexplored terrain is darkened by the terrain shader. The terrain gets explored around
+/// the camera and around the buildings, and stays explored. The explored cells are saved
+/// along with the sim.
+pub struct FogOfWarPlugin;
+
+impl Plugin for FogOfWarPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Exploration::default())
+            .add_systems(OnExit(GameState::InGame), clear_exploration)
+            .add_systems(
+                Update,
+                (
+                    reveal_around_camera,
+                    reveal_around_buildings,
+                    upload_exploration
+                        .after(reveal_around_camera)
+                        .after(reveal_around_buildings),
+                )
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// How much each grid cell has been explored, from 0 to 255, by chunk.
+/// Chunks missing are unexplored.
+#[derive(Resource, Default)]
+pub struct Exploration {
+    chunks: HashMap<I64Vec2, Vec<u8>>,
+    /// Chunks changed since they were last uploaded
+    dirty: HashSet<I64Vec2>,
+    /// Position of the camera target when the terrain around it was last revealed
+    last_camera_reveal: Option<Vec2>,
+}
+
+/// Explored cells of a chunk, run-length encoded as (value, count)
+#[derive(Serialize, Deserialize)]
+pub struct ExploredChunk {
+    x: i64,
+    z: i64,
+    runs: Vec<(u8, u32)>,
+}
+
+impl Exploration {
+    pub fn is_explored(&self, pos: Vec2) -> bool {
+        Chunk::cells_at(pos).next().is_some_and(|(chunk, cell)| {
+            self.chunks
+                .get(&chunk)
+                .is_some_and(|values| values[cell] > 127)
+        })
+    }
+
+    /// Explore the cells around `center`, with a fading edge
+    pub fn reveal(&mut self, center: Vec2, radius: f32) {
+        let steps = (radius / GRID_SQUARE_SIZE).ceil() as i32;
+        for x in -steps..=steps {
+            for z in -steps..=steps {
+                let offset = Vec2::new(x as f32, z as f32) * GRID_SQUARE_SIZE;
+                let amount = ((radius - offset.length()) / REVEAL_EDGE).clamp(0., 1.);
+                if amount <= 0. {
+                    continue;
+                }
+                let value = (amount * 255.).round() as u8;
+                for (chunk, cell) in Chunk::cells_at(center + offset) {
+                    let values = self
+                        .chunks
+                        .entry(chunk)
+                        .or_insert_with(|| vec![0; Chunk::cell_count()]);
+                    if values[cell] < value {
+                        values[cell] = value;
+                        self.dirty.insert(chunk);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Explored cells of every chunk, to be saved
+    pub fn to_save(&self) -> Vec<ExploredChunk> {
+        self.chunks
+            .iter()
+            .map(|(pos, values)| {
+                let mut runs: Vec<(u8, u32)> = Vec::new();
+                for &value in values {
+                    match runs.last_mut() {
+                        Some((last, count)) if *last == value => *count += 1,
+                        _ => runs.push((value, 1)),
+                    }
+                }
+                ExploredChunk {
+                    x: pos.x,
+                    z: pos.y,
+                    runs,
+                }
+            })
+            .collect()
+    }
+
+    /// Replace the explored cells with saved ones
+    pub fn from_save(&mut self, saved: Vec<ExploredChunk>) -> anyhow::Result<()> {
+        let mut chunks = HashMap::default();
+        for chunk in saved {
+            let values: Vec<u8> = chunk
+                .runs
+                .iter()
+                .flat_map(|&(value, count)| std::iter::repeat_n(value, count as usize))
+                .collect();
+            anyhow::ensure!(
+                values.len() == Chunk::cell_count(),
+                "explored chunk {} {} has {} cells instead of {}",
+                chunk.x,
+                chunk.z,
+                values.len(),
+                Chunk::cell_count()
+            );
+            chunks.insert(I64Vec2::new(chunk.x, chunk.z), values);
+        }
+        // the chunks explored before loading are hidden again
+        self.dirty.extend(self.chunks.keys().copied());
+        self.dirty.extend(chunks.keys().copied());
+        self.chunks = chunks;
+        Ok(())
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, ron::ser::to_string(&self.to_save())?)?;
+        Ok(())
+    }
+
+    /// Load the explored cells. A missing file, from a save made before the exploration was
+    /// saved, leaves the exploration as it is.
+    pub fn load(&mut self, path: &str) -> anyhow::Result<()> {
+        if !std::path::Path::new(path).exists() {
+            return Ok(());
+        }
+        self.from_save(ron::de::from_bytes(&std::fs::read(path)?)?)
+    }
+}
+
+fn clear_exploration(mut exploration: ResMut<Exploration>) {
+    *exploration = Exploration::default();
+}
+
+fn reveal_around_camera(
+    camera: Query<&CameraTarget, (With<Camera>, Changed<CameraTarget>)>,
+    mut exploration: ResMut<Exploration>,
+) {
+    let Ok(target) = camera.single() else {
+        return;
+    };
+    let pos = target.pos.xz();
+    let last = exploration.last_camera_reveal;
+    if last.is_some_and(|last| last.distance(pos) < CAMERA_REVEAL_STEP) {
+        return;
+    }
+    exploration.last_camera_reveal = Some(pos);
+    exploration.reveal(pos, CAMERA_REVEAL_RADIUS);
+}
+
+fn reveal_around_buildings(
+    buildings: Query<&BuildingInstance, Added<BuildingInstance>>,
+    mut exploration: ResMut<Exploration>,
+) {
+    for building in &buildings {
+        exploration.reveal(
+            building.center(),
+            BUILDING_REVEAL_RADIUS + building.half_extents.length(),
+        );
+    }
+}
+
+/// Copy the explored cells to the data textures of the changed chunks, and of the chunks
+/// with a new material
+fn upload_exploration(
+    mut exploration: ResMut<Exploration>,
+    chunks: Query<(&IsGround, Ref<MeshMaterial3d<MapMaterial>>)>,
+    materials: Res<Assets<MapMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let dirty = std::mem::take(&mut exploration.dirty);
+    for (IsGround(pos), material) in &chunks {
+        if !material.is_changed() && !dirty.contains(pos) {
+            continue;
+        }
+        let Some(handle) = materials
+            .get(&material.0)
+            .and_then(|mat| mat.extension.explored.as_ref())
+        else {
+            continue;
+        };
+        let Some(image) = images.get_mut(handle) else {
+            continue;
+        };
+        let values = exploration.chunks.get(pos);
+        image.data = Some(
+            values
+                .cloned()
+                .unwrap_or_else(|| vec![0; Chunk::cell_count()]),
+        );
+    }
+}
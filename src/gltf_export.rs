@@ -0,0 +1,356 @@
+use std::path::Path;
+
+use bevy::{
+    platform::collections::HashMap,
+    prelude::*,
+    render::mesh::{PrimitiveTopology, VertexAttributeValues},
+};
+use serde_json::{Value, json};
+
+use crate::{
+    build::{BuildId, SelectedBuild},
+    console::{Console, ConsoleCommand},
+    localization::Localization,
+    map::{IsGround, WaterSurface},
+    plan::Planned,
+    shaders::OutlineMaterial,
+    toasts::Toasts,
+};
+
+const DEFAULT_EXPORT_PATH: &str = "exports/world.glb";
+/// Color of the terrain in the exported file, as its material only exists in the game
+const TERRAIN_COLOR: Color = Color::srgb(0.35, 0.45, 0.25);
+const WATER_COLOR: Color = Color::srgb(0.25, 0.4, 0.8);
+
+/// Export the loaded chunks, the water and the placed buildings to a single binary glTF file,
+/// from the pause menu or with `/export_gltf [path]`. The meshes of the buildings are shared
+/// between their instances. The materials keep their base color only.
+pub struct GltfExportPlugin;
+
+impl Plugin for GltfExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ExportGltf>()
+            .add_systems(Update, (export_on_command, export_gltf).chain());
+    }
+}
+
+/// Export the scene to a glTF file at this path
+#[derive(Event, Clone, Debug)]
+pub struct ExportGltf {
+    pub path: String,
+}
+
+impl Default for ExportGltf {
+    fn default() -> Self {
+        Self {
+            path: DEFAULT_EXPORT_PATH.to_string(),
+        }
+    }
+}
+
+fn export_on_command(
+    mut commands: EventReader<ConsoleCommand>,
+    mut exports: EventWriter<ExportGltf>,
+) {
+    for command in commands.read() {
+        if command.name != "export_gltf" {
+            continue;
+        }
+        exports.write(match command.args.first() {
+            Some(path) => ExportGltf { path: path.clone() },
+            None => ExportGltf::default(),
+        });
+    }
+}
+
+/// The glTF document being built, and its binary buffer
+#[derive(Default)]
+struct GltfBuilder {
+    bin: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+    meshes: Vec<Value>,
+    materials: Vec<Value>,
+    nodes: Vec<Value>,
+    /// glTF meshes already written, by mesh and material
+    written: HashMap<(AssetId<Mesh>, usize), usize>,
+    /// glTF materials of the building materials
+    building_materials: HashMap<AssetId<StandardMaterial>, usize>,
+}
+
+impl GltfBuilder {
+    /// Add bytes to the buffer, as a buffer view
+    fn view(&mut self, bytes: &[u8], target: u32) -> usize {
+        // the accessors must be aligned on their component size
+        while self.bin.len() % 4 != 0 {
+            self.bin.push(0);
+        }
+        self.views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": bytes.len(),
+            "target": target,
+        }));
+        self.bin.extend_from_slice(bytes);
+        self.views.len() - 1
+    }
+
+    fn vec3_accessor(&mut self, values: &[[f32; 3]], bounds: bool) -> usize {
+        let bytes: Vec<u8> = values
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let view = self.view(&bytes, 34962);
+        let mut accessor = json!({
+            "bufferView": view,
+            "componentType": 5126,
+            "count": values.len(),
+            "type": "VEC3",
+        });
+        // required for the positions
+        if bounds {
+            let (min, max) = values.iter().fold(
+                (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+                |(min, max), v| (min.min(Vec3::from(*v)), max.max(Vec3::from(*v))),
+            );
+            accessor["min"] = json!(min.to_array());
+            accessor["max"] = json!(max.to_array());
+        }
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn material(&mut self, name: &str, color: Color) -> usize {
+        self.materials.push(json!({
+            "name": name,
+            "pbrMetallicRoughness": {
+                "baseColorFactor": color.to_linear().to_f32_array(),
+                "metallicFactor": 0.,
+            },
+        }));
+        self.materials.len() - 1
+    }
+
+    /// glTF mesh of a mesh with a material, written once. `None` for the meshes that aren't
+    /// triangle lists with positions.
+    fn mesh(
+        &mut self,
+        handle: &Handle<Mesh>,
+        material: usize,
+        meshes: &Assets<Mesh>,
+    ) -> Option<usize> {
+        if let Some(id) = self.written.get(&(handle.id(), material)) {
+            return Some(*id);
+        }
+        let mesh = meshes.get(handle)?;
+        if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+            return None;
+        }
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            mesh.attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return None;
+        };
+        let mut attributes = json!({ "POSITION": self.vec3_accessor(positions, true) });
+        if let Some(VertexAttributeValues::Float32x3(normals)) =
+            mesh.attribute(Mesh::ATTRIBUTE_NORMAL)
+        {
+            attributes["NORMAL"] = json!(self.vec3_accessor(normals, false));
+        }
+        let indices: Vec<u32> = match mesh.indices() {
+            Some(indices) => indices.iter().map(|i| i as u32).collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        let bytes: Vec<u8> = indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let view = self.view(&bytes, 34963);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": 5125,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+        self.meshes.push(json!({
+            "primitives": [{
+                "attributes": attributes,
+                "indices": self.accessors.len() - 1,
+                "material": material,
+            }],
+        }));
+        self.written
+            .insert((handle.id(), material), self.meshes.len() - 1);
+        Some(self.meshes.len() - 1)
+    }
+
+    fn node(&mut self, name: &str, mesh: usize, transform: &GlobalTransform) -> usize {
+        let (scale, rotation, translation) = transform.to_scale_rotation_translation();
+        self.nodes.push(json!({
+            "name": name,
+            "mesh": mesh,
+            "translation": translation.to_array(),
+            "rotation": rotation.to_array(),
+            "scale": scale.to_array(),
+        }));
+        self.nodes.len() - 1
+    }
+
+    /// A node grouping others, at the root of the scene
+    fn group(&mut self, name: &str, children: Vec<usize>) -> usize {
+        let mut node = json!({ "name": name });
+        if !children.is_empty() {
+            node["children"] = json!(children);
+        }
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// The whole file, as binary glTF
+    fn glb(self, roots: Vec<usize>) -> anyhow::Result<Vec<u8>> {
+        let mut document = json!({
+            "asset": { "version": "2.0", "generator": "Unnamed factory" },
+            "scene": 0,
+            "scenes": [{ "nodes": roots }],
+            "nodes": self.nodes,
+            "meshes": self.meshes,
+            "materials": self.materials,
+            "accessors": self.accessors,
+            "bufferViews": self.views,
+        });
+        // the arrays of glTF can't be empty
+        if let Some(document) = document.as_object_mut() {
+            document.retain(|_, v| v.as_array().is_none_or(|a| !a.is_empty()));
+        }
+        let mut bin = self.bin;
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+        if !bin.is_empty() {
+            document["buffers"] = json!([{ "byteLength": bin.len() }]);
+        }
+        let mut json = serde_json::to_vec(&document)?;
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let bin_chunk = if bin.is_empty() { 0 } else { 8 + bin.len() };
+        let length = 12 + 8 + json.len() + bin_chunk;
+        let mut glb = Vec::with_capacity(length);
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        if !bin.is_empty() {
+            glb.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+            glb.extend_from_slice(b"BIN\0");
+            glb.extend_from_slice(&bin);
+        }
+        Ok(glb)
+    }
+}
+
+fn export_gltf(
+    mut exports: EventReader<ExportGltf>,
+    chunks: Query<(&Mesh3d, &GlobalTransform, &IsGround)>,
+    water: Query<(&Mesh3d, &GlobalTransform, Option<&Name>), With<WaterSurface>>,
+    parts: Query<
+        (
+            Entity,
+            &Mesh3d,
+            &GlobalTransform,
+            Option<&MeshMaterial3d<StandardMaterial>>,
+        ),
+        (
+            Without<IsGround>,
+            Without<WaterSurface>,
+            Without<MeshMaterial3d<OutlineMaterial>>,
+        ),
+    >,
+    roots: Query<(&BuildId, Has<SelectedBuild>, Has<Planned>)>,
+    parents: Query<&ChildOf>,
+    meshes: Res<Assets<Mesh>>,
+    materials: Res<Assets<StandardMaterial>>,
+    mut console: ResMut<Console>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) {
+    for ExportGltf { path } in exports.read() {
+        let mut gltf = GltfBuilder::default();
+        let terrain_material = gltf.material("terrain", TERRAIN_COLOR);
+        let water_material = gltf.material("water", WATER_COLOR);
+        let plain_material = gltf.material("building", Color::WHITE);
+        let mut terrain = Vec::new();
+        for (mesh, transform, IsGround(pos)) in &chunks {
+            if let Some(mesh) = gltf.mesh(mesh, terrain_material, &meshes) {
+                let name = format!("chunk {} {}", pos.x, pos.y);
+                terrain.push(gltf.node(&name, mesh, transform));
+            }
+        }
+        let mut surfaces = Vec::new();
+        for (mesh, transform, name) in &water {
+            if let Some(mesh) = gltf.mesh(mesh, water_material, &meshes) {
+                let name = name.map_or("water", Name::as_str);
+                surfaces.push(gltf.node(name, mesh, transform));
+            }
+        }
+        let mut buildings = Vec::new();
+        for (e, mesh, transform, material) in &parts {
+            // the meshes of the placed buildings, not of the ghosts
+            let Some(Ok((bid, selected, planned))) = std::iter::once(e)
+                .chain(parents.iter_ancestors(e))
+                .map(|e| roots.get(e))
+                .find(Result::is_ok)
+            else {
+                continue;
+            };
+            if selected || planned {
+                continue;
+            }
+            let material = match material {
+                Some(MeshMaterial3d(handle)) => match gltf.building_materials.get(&handle.id()) {
+                    Some(material) => *material,
+                    None => {
+                        let color = materials.get(handle).map_or(Color::WHITE, |m| m.base_color);
+                        let material = gltf.material("building", color);
+                        gltf.building_materials.insert(handle.id(), material);
+                        material
+                    }
+                },
+                None => plain_material,
+            };
+            if let Some(mesh) = gltf.mesh(mesh, material, &meshes) {
+                let name = bid
+                    .0
+                    .path()
+                    .map_or("building".to_string(), |p| p.to_string());
+                buildings.push(gltf.node(&name, mesh, transform));
+            }
+        }
+        let roots = vec![
+            gltf.group("terrain", terrain),
+            gltf.group("water", surfaces),
+            gltf.group("buildings", buildings),
+        ];
+        let result = gltf.glb(roots).and_then(|glb| {
+            if let Some(parent) = Path::new(path).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, glb)?;
+            Ok(())
+        });
+        match result {
+            Ok(()) => {
+                info!("Scene exported to {path}");
+                let path_arg = [("path", path.clone())];
+                let text = localization.get_args("toast-gltf-exported", &path_arg);
+                console.print(text.clone());
+                toasts.info(text);
+            }
+            Err(e) => {
+                let text = localization.get_args("toast-gltf-failed", &[("error", e.to_string())]);
+                console.print_error(text.clone());
+                toasts.error(text);
+            }
+        }
+    }
+}
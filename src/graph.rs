@@ -0,0 +1,102 @@
+use bevy::prelude::*;
+
+use crate::{localization::Localization, sim::Sim};
+
+/// Line graph of the recent history of a sim stat, drawn with gizmos over a ui node.
+pub struct GraphPlugin;
+
+impl Plugin for GraphPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_gizmo_group::<GraphGizmos>();
+        app.insert_resource(GraphedStat::default());
+        app.add_systems(Startup, setup_graph_gizmos);
+        app.add_systems(Update, (draw_stat_graph, update_graph_label));
+    }
+}
+
+/// Distance from the camera at which the graph lines are drawn
+const GRAPH_DEPTH: f32 = 0.5;
+
+#[derive(Default, Reflect, GizmoConfigGroup)]
+struct GraphGizmos;
+
+/// The stat being graphed : its hashed path and its name
+#[derive(Resource, Default)]
+pub struct GraphedStat(pub Option<(u64, String)>);
+
+/// Ui node over which the graph is drawn
+#[derive(Component)]
+pub struct StatGraph;
+
+#[derive(Component)]
+pub struct StatGraphLabel;
+
+fn setup_graph_gizmos(mut config_store: ResMut<GizmoConfigStore>) {
+    let (config, _) = config_store.config_mut::<GraphGizmos>();
+    // always drawn over the world
+    config.depth_bias = -1.;
+    config.line.width = 2.;
+}
+
+fn draw_stat_graph(
+    sim: Res<Sim>,
+    graphed: Res<GraphedStat>,
+    graphs: Query<(&ComputedNode, &GlobalTransform, &InheritedVisibility), With<StatGraph>>,
+    camera: Single<(&Camera, &GlobalTransform), With<Camera3d>>,
+    mut gizmos: Gizmos<GraphGizmos>,
+) {
+    let Some(samples) = graphed.0.as_ref().and_then(|(id, _)| sim.history(*id)) else {
+        return;
+    };
+    if samples.len() < 2 {
+        return;
+    }
+    let (camera, camera_transform) = *camera;
+    let lo = samples.iter().copied().fold(f64::INFINITY, f64::min);
+    let hi = samples.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = if hi > lo { hi - lo } else { 1. };
+    for (node, transform, visibility) in &graphs {
+        if !visibility.get() {
+            continue;
+        }
+        // ui layout is in physical pixels, the viewport in logical ones
+        let size = node.size() * node.inverse_scale_factor();
+        let min = transform.translation().truncate() * node.inverse_scale_factor() - size / 2.;
+        let points = samples.iter().enumerate().filter_map(|(i, v)| {
+            let x = min.x + size.x * i as f32 / (samples.len() - 1) as f32;
+            let y = min.y + size.y * (1. - ((v - lo) / range) as f32);
+            let ray = camera
+                .viewport_to_world(camera_transform, Vec2::new(x, y))
+                .ok()?;
+            Some(ray.get_point(GRAPH_DEPTH))
+        });
+        gizmos.linestrip(points, bevy::color::palettes::css::LIME);
+    }
+}
+
+fn update_graph_label(
+    sim: Res<Sim>,
+    graphed: Res<GraphedStat>,
+    mut labels: Query<&mut Text, With<StatGraphLabel>>,
+    localization: Res<Localization>,
+) {
+    let Some((id, name)) = &graphed.0 else {
+        return;
+    };
+    let Some(samples) = sim.history(*id) else {
+        return;
+    };
+    let lo = samples.iter().copied().fold(f64::INFINITY, f64::min);
+    let hi = samples.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    for mut text in &mut labels {
+        text.0 = localization.get_args(
+            "graph-label",
+            &[
+                ("name", name.clone()),
+                ("ticks", samples.len().to_string()),
+                ("low", format!("{lo:.2}")),
+                ("high", format!("{hi:.2}")),
+            ],
+        );
+    }
+}
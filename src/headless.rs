@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use bevy::{
+    app::{PluginGroupBuilder, ScheduleRunnerPlugin},
+    prelude::*,
+    render::{
+        RenderPlugin,
+        settings::{RenderCreation, WgpuSettings},
+    },
+    time::TimeUpdateStrategy,
+    window::ExitCondition,
+    winit::WinitPlugin,
+};
+
+use crate::{sim::Sim, stats_export::export_stats};
+
+/// Options of a headless run
+#[derive(Resource, Clone, Debug, Default)]
+pub struct HeadlessSettings {
+    /// Exit once the sim ran this many ticks
+    pub max_ticks: Option<u64>,
+    /// Where the history of the sim stats is written as CSV on exit
+    pub stats_path: Option<String>,
+}
+
+/// Run the game without a window nor rendering, for determinism tests and balance runs.
+/// The frames run as fast as possible with a sim tick each, so the sim runs at full speed.
+/// Buildings can be placed by the scripts or over the remote protocol.
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HeadlessSettings>()
+            .add_plugins(ScheduleRunnerPlugin::run_loop(Duration::ZERO))
+            .add_systems(Update, (follow_tick_rate, exit_after_ticks));
+    }
+}
+
+/// The default plugins, without the window and the GPU
+pub fn headless_default_plugins() -> PluginGroupBuilder {
+    DefaultPlugins
+        .set(WindowPlugin {
+            primary_window: None,
+            exit_condition: ExitCondition::DontExit,
+            close_when_requested: false,
+        })
+        .set(RenderPlugin {
+            render_creation: RenderCreation::Automatic(WgpuSettings {
+                backends: None,
+                ..default()
+            }),
+            ..default()
+        })
+        .disable::<WinitPlugin>()
+}
+
+/// Advance the time by a sim tick every frame, rather than by the real time
+fn follow_tick_rate(
+    time: Res<Time<Fixed>>,
+    mut strategy: ResMut<TimeUpdateStrategy>,
+    mut step: Local<Duration>,
+) {
+    if *step != time.timestep() {
+        *step = time.timestep();
+        *strategy = TimeUpdateStrategy::ManualDuration(*step);
+    }
+}
+
+fn exit_after_ticks(
+    sim: Res<Sim>,
+    settings: Res<HeadlessSettings>,
+    mut exit: EventWriter<AppExit>,
+) -> Result {
+    if settings.max_ticks.is_none_or(|max| sim.tick < max) {
+        return Ok(());
+    }
+    info!("Ran {} sim ticks, exiting", sim.tick);
+    if let Some(path) = &settings.stats_path {
+        export_stats(&sim, path)?;
+        info!("Sim stats written to {}", path);
+    }
+    exit.write(AppExit::Success);
+    Ok(())
+}
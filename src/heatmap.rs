@@ -0,0 +1,179 @@
+use bevy::{
+    math::I64Vec2,
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+
+use crate::{
+    input_map::{Action, Actions},
+    localization::Localization,
+    map::{Chunk, GRID_SQUARE_SIZE, IsGround},
+    menu::GameState,
+    shaders::MapMaterial,
+    toasts::Toasts,
+};
+
+/// Gameplay data drawn over the terrain, like pollution or land value. Systems write the
+/// values of a layer in `Heatmaps`, and the shown layer is copied to the data texture of
+/// the chunks it changed on. H cycles through the layers.
+pub struct HeatmapPlugin;
+
+impl Plugin for HeatmapPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Heatmaps::default())
+            .add_systems(OnExit(GameState::InGame), clear_heatmaps)
+            .add_systems(
+                Update,
+                (cycle_heatmap, upload_heatmaps.after(cycle_heatmap))
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum HeatmapLayer {
+    Pollution,
+    LandValue,
+    PowerCoverage,
+}
+
+impl HeatmapLayer {
+    pub const ALL: [HeatmapLayer; 3] = [
+        HeatmapLayer::Pollution,
+        HeatmapLayer::LandValue,
+        HeatmapLayer::PowerCoverage,
+    ];
+
+    fn key(self) -> &'static str {
+        match self {
+            HeatmapLayer::Pollution => "heatmap-pollution",
+            HeatmapLayer::LandValue => "heatmap-land-value",
+            HeatmapLayer::PowerCoverage => "heatmap-power-coverage",
+        }
+    }
+}
+
+/// Values in [0, 1] of every heatmap layer, on the grid cells of each chunk
+#[derive(Resource, Default)]
+pub struct Heatmaps {
+    /// Layer drawn over the terrain
+    pub shown: Option<HeatmapLayer>,
+    layers: HashMap<HeatmapLayer, HashMap<I64Vec2, Vec<u8>>>,
+    /// Chunks of the layers changed since they were last uploaded
+    dirty: HashSet<(HeatmapLayer, I64Vec2)>,
+}
+
+impl Heatmaps {
+    pub fn get(&self, layer: HeatmapLayer, pos: Vec3) -> f32 {
+        let Some((chunk, cell)) = Chunk::cells_at(pos.xz()).next() else {
+            return 0.;
+        };
+        self.layers
+            .get(&layer)
+            .and_then(|chunks| chunks.get(&chunk))
+            .map_or(0., |values| values[cell] as f32 / 255.)
+    }
+
+    pub fn set(&mut self, layer: HeatmapLayer, pos: Vec3, value: f32) {
+        let value = (value.clamp(0., 1.) * 255.).round() as u8;
+        let chunks = self.layers.entry(layer).or_default();
+        for (chunk, cell) in Chunk::cells_at(pos.xz()) {
+            chunks
+                .entry(chunk)
+                .or_insert_with(|| vec![0; Chunk::cell_count()])[cell] = value;
+            self.dirty.insert((layer, chunk));
+        }
+    }
+
+    /// Add `value` around `center`, fading to nothing at `radius`
+    pub fn paint(&mut self, layer: HeatmapLayer, center: Vec3, radius: f32, value: f32) {
+        let steps = (radius / GRID_SQUARE_SIZE).ceil() as i32;
+        for x in -steps..=steps {
+            for z in -steps..=steps {
+                let offset = Vec3::new(x as f32, 0., z as f32) * GRID_SQUARE_SIZE;
+                let falloff = 1. - offset.length() / radius;
+                if falloff <= 0. {
+                    continue;
+                }
+                let pos = center + offset;
+                let current = self.get(layer, pos);
+                self.set(layer, pos, current + value * falloff);
+            }
+        }
+    }
+
+    /// Reset a layer to 0 everywhere
+    pub fn clear(&mut self, layer: HeatmapLayer) {
+        if let Some(chunks) = self.layers.remove(&layer) {
+            self.dirty
+                .extend(chunks.into_keys().map(|chunk| (layer, chunk)));
+        }
+    }
+}
+
+fn clear_heatmaps(mut heatmaps: ResMut<Heatmaps>) {
+    *heatmaps = Heatmaps::default();
+}
+
+fn cycle_heatmap(
+    actions: Actions,
+    mut heatmaps: ResMut<Heatmaps>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) {
+    if !actions.just_pressed(Action::CycleHeatmap) {
+        return;
+    }
+    heatmaps.shown = match heatmaps.shown {
+        None => Some(HeatmapLayer::ALL[0]),
+        Some(layer) => {
+            let i = HeatmapLayer::ALL
+                .iter()
+                .position(|l| *l == layer)
+                .unwrap_or(0);
+            HeatmapLayer::ALL.get(i + 1).copied()
+        }
+    };
+    let key = heatmaps.shown.map_or("heatmap-none", HeatmapLayer::key);
+    toasts.info(localization.get(key));
+}
+
+/// Copy the shown layer to the data textures of the chunks : all of them when the layer
+/// changes, otherwise only the changed ones and the ones with a new material
+fn upload_heatmaps(
+    mut heatmaps: ResMut<Heatmaps>,
+    chunks: Query<(&IsGround, Ref<MeshMaterial3d<MapMaterial>>)>,
+    materials: Res<Assets<MapMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mut last_shown: Local<Option<HeatmapLayer>>,
+) {
+    let shown_changed = *last_shown != heatmaps.shown;
+    *last_shown = heatmaps.shown;
+    let dirty = std::mem::take(&mut heatmaps.dirty);
+    let Some(layer) = heatmaps.shown else {
+        return;
+    };
+    for (IsGround(pos), material) in &chunks {
+        if !shown_changed && !material.is_changed() && !dirty.contains(&(layer, *pos)) {
+            continue;
+        }
+        let Some(handle) = materials
+            .get(&material.0)
+            .and_then(|mat| mat.extension.heatmap.as_ref())
+        else {
+            continue;
+        };
+        let Some(image) = images.get_mut(handle) else {
+            continue;
+        };
+        let values = heatmaps
+            .layers
+            .get(&layer)
+            .and_then(|chunks| chunks.get(pos));
+        image.data = Some(
+            values
+                .cloned()
+                .unwrap_or_else(|| vec![0; Chunk::cell_count()]),
+        );
+    }
+}
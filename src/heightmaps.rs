@@ -0,0 +1,169 @@
+use std::{
+    hash::{BuildHasher, Hasher},
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    asset::RenderAssetUsages,
+    image::{CompressedImageFormats, ImageSampler, ImageType},
+    math::Vec2,
+    prelude::Image,
+};
+use foldhash::fast::FixedState;
+
+use crate::{
+    map::GRID_SQUARE_SIZE,
+    mapgen::{Continent, TerrainPoint},
+};
+
+/// Directory the imported heightmaps are copied to, one sub directory each, named by the
+/// hash of their files
+pub const HEIGHTMAPS_DIR: &str = "heightmaps";
+/// Share of the range of the heightmaps under the sea
+const SEA_LEVEL: f32 = 0.1;
+/// Value of the river masks from which a point is in a river
+const RIVER_MASK_THRESHOLD: f32 = 0.5;
+/// How much the river masks lower the terrain so that the rivers flow along them, normalized
+const RIVER_MASK_DEPTH: f32 = 0.01;
+
+/// Copy a heightmap, a grayscale PNG or EXR image, and its optional river mask to
+/// `HEIGHTMAPS_DIR`, after checking that they decode. The id it gives goes in
+/// `WorldGen::heightmap`, and identifies the imported world in the saves and the caches.
+pub fn import_heightmap(heights: &str, rivers: Option<&str>) -> anyhow::Result<u64> {
+    let mut hasher = FixedState::default().build_hasher();
+    let heights_bytes = std::fs::read(heights)?;
+    decode(&heights_bytes, heights)?;
+    hasher.write(&heights_bytes);
+    let rivers = match rivers {
+        Some(path) => {
+            let bytes = std::fs::read(path)?;
+            decode(&bytes, path)?;
+            hasher.write(&bytes);
+            Some((bytes, extension(path)?))
+        }
+        None => None,
+    };
+    let id = hasher.finish();
+    let dir = heightmap_dir(id);
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(
+        dir.join(format!("heights.{}", extension(heights)?)),
+        heights_bytes,
+    )?;
+    if let Some((bytes, ext)) = rivers {
+        std::fs::write(dir.join(format!("rivers.{ext}")), bytes)?;
+    }
+    Ok(id)
+}
+
+fn heightmap_dir(id: u64) -> PathBuf {
+    PathBuf::from(HEIGHTMAPS_DIR).join(format!("{id:016x}"))
+}
+
+fn extension(path: &str) -> anyhow::Result<String> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_lowercase());
+    match ext.as_deref() {
+        Some(ext @ ("png" | "exr")) => Ok(ext.to_string()),
+        _ => anyhow::bail!("{path} isn't a PNG nor an EXR image"),
+    }
+}
+
+fn decode(bytes: &[u8], path: &str) -> anyhow::Result<Image> {
+    let image = Image::from_buffer(
+        bytes,
+        ImageType::Extension(&extension(path)?),
+        CompressedImageFormats::NONE,
+        // the values are heights, not colors
+        false,
+        ImageSampler::Default,
+        RenderAssetUsages::MAIN_WORLD,
+    )?;
+    Ok(image)
+}
+
+/// Values of the red channel of an image, resampled on the continent grid, row by row
+fn resample(image: &Image) -> anyhow::Result<Vec<f32>> {
+    let size = Continent::CONTINENT_SIZE;
+    let (width, height) = (image.width(), image.height());
+    let value =
+        |x: u32, y: u32| -> anyhow::Result<f32> { Ok(image.get_color_at(x, y)?.to_linear().red) };
+    let mut values = Vec::with_capacity((size * size) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            // bilinear, from the centers of the pixels
+            let pos = (Vec2::new(x as f32, y as f32) + 0.5) / size as f32
+                * Vec2::new(width as f32, height as f32)
+                - 0.5;
+            let pos = pos.clamp(Vec2::ZERO, Vec2::new(width as f32, height as f32) - 1.);
+            let (x0, y0) = (pos.x.floor() as u32, pos.y.floor() as u32);
+            let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+            let t = pos - Vec2::new(x0 as f32, y0 as f32);
+            let top = value(x0, y0)? * (1. - t.x) + value(x1, y0)? * t.x;
+            let bottom = value(x0, y1)? * (1. - t.x) + value(x1, y1)? * t.x;
+            values.push(top * (1. - t.y) + bottom * t.y);
+        }
+    }
+    Ok(values)
+}
+
+/// Points of the continent grid from an imported heightmap, in the order of the grid. The
+/// lowest value of the heightmap is the sea floor, `SEA_LEVEL` of its range the coast, and the
+/// highest value the top of the mountains. The river mask is dug into the terrain, so that the
+/// rivers flow along it.
+pub fn heightmap_points(id: u64) -> anyhow::Result<Vec<TerrainPoint>> {
+    let dir = heightmap_dir(id);
+    let find = |name: &str| {
+        ["png", "exr"]
+            .into_iter()
+            .map(|ext| dir.join(format!("{name}.{ext}")))
+            .find(|path| path.exists())
+    };
+    let load = |path: PathBuf| -> anyhow::Result<Vec<f32>> {
+        let name = path.display().to_string();
+        resample(&decode(&std::fs::read(&path)?, &name)?)
+    };
+    let Some(heights_path) = find("heights") else {
+        anyhow::bail!("the heightmap {id:016x} isn't in {HEIGHTMAPS_DIR}");
+    };
+    let mut heights = load(heights_path)?;
+    let (min, max) = heights.iter().fold((f32::MAX, f32::MIN), |(min, max), h| {
+        (min.min(*h), max.max(*h))
+    });
+    let range = (max - min).max(f32::EPSILON);
+    let sea = Continent::OCEAN_HEIGHT_LIMIT;
+    for h in &mut heights {
+        let v = (*h - min) / range;
+        *h = if v < SEA_LEVEL {
+            sea * v / SEA_LEVEL
+        } else {
+            sea + (1. - sea) * (v - SEA_LEVEL) / (1. - SEA_LEVEL)
+        };
+    }
+    if let Some(rivers_path) = find("rivers") {
+        for (h, river) in heights.iter_mut().zip(load(rivers_path)?) {
+            if river >= RIVER_MASK_THRESHOLD {
+                *h -= RIVER_MASK_DEPTH;
+            }
+        }
+    }
+    let size = Continent::CONTINENT_SIZE;
+    let at = |x: u32, y: u32| heights[(y.min(size - 1) * size + x.min(size - 1)) as usize];
+    Ok((0..(size * size) as usize)
+        .map(|h| {
+            let (x, y) = Continent::h2xy(h);
+            // the slope down, as the gradient of the noise is given
+            let grad = Vec2::new(
+                at(x + 1, y) - at(x.saturating_sub(1), y),
+                at(x, y + 1) - at(x, y.saturating_sub(1)),
+            ) / (2. * GRID_SQUARE_SIZE);
+            TerrainPoint {
+                height: at(x, y),
+                wetness: 1.,
+                grad: -grad,
+            }
+        })
+        .collect())
+}
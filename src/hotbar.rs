@@ -0,0 +1,218 @@
+use bevy::prelude::*;
+
+use crate::{
+    build::{BuildId, Building},
+    input_map::{Action, Actions},
+    ui::{BuildMenu, FontHandle, PartButton},
+};
+
+/// Actions of the hotbar slots, in order
+const SLOT_ACTIONS: [Action; 10] = [
+    Action::HotbarSlot1,
+    Action::HotbarSlot2,
+    Action::HotbarSlot3,
+    Action::HotbarSlot4,
+    Action::HotbarSlot5,
+    Action::HotbarSlot6,
+    Action::HotbarSlot7,
+    Action::HotbarSlot8,
+    Action::HotbarSlot9,
+    Action::HotbarSlot10,
+];
+
+/// Buildings pinned to the bottom bar, placed with the number keys.
+/// Pin a building by dragging its button on a slot, or by pressing the slot key while
+/// hovering it. Right click a slot to unpin it.
+pub struct HotbarPlugin;
+
+impl Plugin for HotbarPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Hotbar::default())
+            .add_systems(Startup, setup_hotbar)
+            .add_systems(
+                Update,
+                (
+                    hotbar_keys,
+                    unpin_removed,
+                    update_hotbar.after(hotbar_keys).after(unpin_removed),
+                ),
+            );
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Hotbar {
+    pub slots: [Option<BuildId>; 10],
+}
+
+#[derive(Component)]
+struct HotbarSlot(usize);
+
+#[derive(Component)]
+struct HotbarSlotLabel(usize);
+
+fn setup_hotbar(mut commands: Commands, font: Res<FontHandle>) {
+    let font = font.0.clone();
+    commands
+        .spawn((
+            Name::new("hotbar"),
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.),
+                width: Val::Percent(100.),
+                justify_content: JustifyContent::Center,
+                column_gap: Val::Px(4.),
+                ..default()
+            },
+            Pickable::IGNORE,
+        ))
+        .with_children(|parent| {
+            for i in 0..SLOT_ACTIONS.len() {
+                parent
+                    .spawn((
+                        Button,
+                        Node {
+                            width: Val::Px(70.),
+                            height: Val::Px(50.),
+                            flex_direction: FlexDirection::Column,
+                            padding: UiRect::all(Val::Px(3.)),
+                            border: UiRect::all(Val::Px(1.)),
+                            ..default()
+                        },
+                        BackgroundColor(Color::srgb(0.1, 0.1, 0.1).with_alpha(0.8)),
+                        BorderColor(Color::srgb(0.4, 0.4, 0.4)),
+                        HotbarSlot(i),
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            Text(((i + 1) % 10).to_string()),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 12.,
+                                ..default()
+                            },
+                            TextColor(Color::srgb(0.6, 0.6, 0.6)),
+                            Label,
+                            Pickable::IGNORE,
+                        ));
+                        parent.spawn((
+                            Text::default(),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 12.,
+                                ..default()
+                            },
+                            Label,
+                            Pickable::IGNORE,
+                            HotbarSlotLabel(i),
+                        ));
+                    })
+                    .observe(drop_on_slot)
+                    .observe(click_slot);
+            }
+        });
+}
+
+/// Pin the building whose button is dropped on a slot
+fn drop_on_slot(
+    trigger: Trigger<Pointer<DragDrop>>,
+    slots: Query<&HotbarSlot>,
+    buttons: Query<&PartButton>,
+    parents: Query<&ChildOf>,
+    mut hotbar: ResMut<Hotbar>,
+) {
+    let Ok(HotbarSlot(i)) = slots.get(trigger.target()) else {
+        return;
+    };
+    // the drag may start on the text of the button
+    let dropped = trigger.event().dropped;
+    let button = buttons.get(dropped).ok().or_else(|| {
+        let parent = parents.get(dropped).ok()?.parent();
+        buttons.get(parent).ok()
+    });
+    if let Some(button) = button {
+        hotbar.slots[*i] = Some(button.part_id.clone());
+    }
+}
+
+/// Left click places the pinned building, right click unpins it
+fn click_slot(
+    trigger: Trigger<Pointer<Click>>,
+    mut commands: Commands,
+    slots: Query<&HotbarSlot>,
+    mut hotbar: ResMut<Hotbar>,
+) {
+    let Ok(HotbarSlot(i)) = slots.get(trigger.target()) else {
+        return;
+    };
+    match trigger.event().button {
+        PointerButton::Primary => {
+            if let Some(id) = &hotbar.slots[*i] {
+                commands.spawn((id.clone(), Name::new("building")));
+            }
+        }
+        PointerButton::Secondary => hotbar.slots[*i] = None,
+        PointerButton::Middle => {}
+    }
+}
+
+/// Number keys place the pinned building, like clicking its button in the build menu,
+/// or pin the hovered building button
+fn hotbar_keys(
+    mut commands: Commands,
+    actions: Actions,
+    buttons: Query<(&Interaction, &PartButton)>,
+    mut hotbar: ResMut<Hotbar>,
+) {
+    for (i, action) in SLOT_ACTIONS.into_iter().enumerate() {
+        if !actions.just_pressed(action) {
+            continue;
+        }
+        let hovered = buttons
+            .iter()
+            .find(|(interaction, _)| **interaction != Interaction::None);
+        if let Some((_, button)) = hovered {
+            hotbar.slots[i] = Some(button.part_id.clone());
+        } else if let Some(id) = &hotbar.slots[i] {
+            commands.spawn((id.clone(), Name::new("building")));
+        }
+    }
+}
+
+/// Unpin the buildings removed from the build menu, or locked again by a restart
+fn unpin_removed(
+    menu: Res<BuildMenu>,
+    buildings: Res<Assets<Building>>,
+    mut hotbar: ResMut<Hotbar>,
+) {
+    if !menu.is_changed() {
+        return;
+    }
+    for slot in hotbar.slots.iter_mut() {
+        let locked = |id: &BuildId| buildings.get(&id.0).is_some_and(|b| !menu.available(b));
+        if slot
+            .as_ref()
+            .is_some_and(|id| !menu.lists(id.0.id()) || locked(id))
+        {
+            *slot = None;
+        }
+    }
+}
+
+fn update_hotbar(
+    hotbar: Res<Hotbar>,
+    buildings: Res<Assets<Building>>,
+    mut labels: Query<(&mut Text, &HotbarSlotLabel)>,
+) {
+    // the names may be edited
+    if !hotbar.is_changed() && !buildings.is_changed() {
+        return;
+    }
+    for (mut text, HotbarSlotLabel(i)) in &mut labels {
+        text.0 = hotbar.slots[*i]
+            .as_ref()
+            .and_then(|id| buildings.get(&id.0))
+            .map(|b| b.name.clone())
+            .unwrap_or_default();
+    }
+}
@@ -0,0 +1,841 @@
+use std::collections::BTreeMap;
+
+use bevy::{
+    ecs::system::SystemParam,
+    input::mouse::{AccumulatedMouseMotion, AccumulatedMouseScroll},
+    prelude::*,
+    window::PrimaryWindow,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    localization::{Localization, LocalizedText},
+    pause_menu::Settings,
+    ui::{FontHandle, TextFocus},
+};
+
+const INPUT_MAP_PATH: &str = "settings/input.ron";
+/// Orbit speed of the right stick fully tilted, in pixels of mouse motion per second
+const GAMEPAD_ORBIT_SPEED: f32 = 600.;
+/// Zoom speed of the triggers fully pressed, in mouse wheel lines per second
+const GAMEPAD_ZOOM_SPEED: f32 = 8.;
+/// Keys added to the camera panning actions by the WASD setting
+const WASD_BINDINGS: [(Action, KeyCode); 4] = [
+    (Action::CameraForward, KeyCode::KeyW),
+    (Action::CameraLeft, KeyCode::KeyA),
+    (Action::CameraBack, KeyCode::KeyS),
+    (Action::CameraRight, KeyCode::KeyD),
+];
+
+/// Rebindable actions : systems check actions instead of keys, and the controls page
+/// (F10) rebinds them. Bindings are saved to `settings/input.ron`.
+pub struct InputMapPlugin;
+
+impl Plugin for InputMapPlugin {
+    fn build(&self, app: &mut App) {
+        let input_map = match InputMap::load(INPUT_MAP_PATH) {
+            Ok(input_map) => input_map,
+            Err(e) => {
+                info!("Using the default controls ({e})");
+                InputMap::default()
+            }
+        };
+        app.insert_resource(input_map)
+            .insert_resource(Rebinding::default())
+            .add_systems(Startup, setup_controls_page)
+            .add_systems(
+                Update,
+                (
+                    toggle_controls_page,
+                    start_rebinding,
+                    capture_binding.after(start_rebinding),
+                    update_controls_page.after(capture_binding),
+                    center_cursor_on_gamepad,
+                    apply_wasd_panning,
+                ),
+            );
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Action {
+    CameraForward,
+    CameraBack,
+    CameraLeft,
+    CameraRight,
+    OrbitCamera,
+    ToggleWireframe,
+    ToggleBoundingBoxes,
+    CycleSnapping,
+    TogglePlanning,
+    PauseSim,
+    SpeedUp,
+    SlowDown,
+    ResetSim,
+    ToggleSimScreen,
+    Quicksave,
+    Quickload,
+    ToggleScriptStats,
+    SaveReplay,
+    ToggleReplayViewer,
+    ExportStats,
+    ToggleControls,
+    PauseMenu,
+    ToggleDiagnostics,
+    Place,
+    BuildListUp,
+    BuildListDown,
+    ToggleGrid,
+    ToggleContours,
+    CycleHeatmap,
+    RotateCameraLeft,
+    RotateCameraRight,
+    TogglePhotoMode,
+    Screenshot,
+    ToggleResearch,
+    ToggleRoadTool,
+    ToggleCanalTool,
+    ToggleConsole,
+    CommitPlan,
+    DiscardPlan,
+    SaveBlueprint,
+    LoadBlueprint,
+    PreviousBookmark,
+    NextBookmark,
+    KeepPlacing,
+    FlyFast,
+    HotbarSlot1,
+    HotbarSlot2,
+    HotbarSlot3,
+    HotbarSlot4,
+    HotbarSlot5,
+    HotbarSlot6,
+    HotbarSlot7,
+    HotbarSlot8,
+    HotbarSlot9,
+    HotbarSlot10,
+}
+
+impl Action {
+    pub const ALL: [Action; 55] = [
+        Action::CameraForward,
+        Action::CameraBack,
+        Action::CameraLeft,
+        Action::CameraRight,
+        Action::OrbitCamera,
+        Action::ToggleWireframe,
+        Action::ToggleBoundingBoxes,
+        Action::CycleSnapping,
+        Action::TogglePlanning,
+        Action::PauseSim,
+        Action::SpeedUp,
+        Action::SlowDown,
+        Action::ResetSim,
+        Action::ToggleSimScreen,
+        Action::Quicksave,
+        Action::Quickload,
+        Action::ToggleScriptStats,
+        Action::SaveReplay,
+        Action::ToggleReplayViewer,
+        Action::ExportStats,
+        Action::ToggleControls,
+        Action::PauseMenu,
+        Action::ToggleDiagnostics,
+        Action::Place,
+        Action::BuildListUp,
+        Action::BuildListDown,
+        Action::ToggleGrid,
+        Action::ToggleContours,
+        Action::CycleHeatmap,
+        Action::RotateCameraLeft,
+        Action::RotateCameraRight,
+        Action::TogglePhotoMode,
+        Action::Screenshot,
+        Action::ToggleResearch,
+        Action::ToggleRoadTool,
+        Action::ToggleCanalTool,
+        Action::ToggleConsole,
+        Action::CommitPlan,
+        Action::DiscardPlan,
+        Action::SaveBlueprint,
+        Action::LoadBlueprint,
+        Action::PreviousBookmark,
+        Action::NextBookmark,
+        Action::KeepPlacing,
+        Action::FlyFast,
+        Action::HotbarSlot1,
+        Action::HotbarSlot2,
+        Action::HotbarSlot3,
+        Action::HotbarSlot4,
+        Action::HotbarSlot5,
+        Action::HotbarSlot6,
+        Action::HotbarSlot7,
+        Action::HotbarSlot8,
+        Action::HotbarSlot9,
+        Action::HotbarSlot10,
+    ];
+
+    /// Localization key of the label
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::CameraForward => "action-camera-forward",
+            Action::CameraBack => "action-camera-back",
+            Action::CameraLeft => "action-camera-left",
+            Action::CameraRight => "action-camera-right",
+            Action::OrbitCamera => "action-orbit-camera",
+            Action::ToggleWireframe => "action-toggle-wireframe",
+            Action::ToggleBoundingBoxes => "action-toggle-bounding-boxes",
+            Action::CycleSnapping => "action-cycle-snapping",
+            Action::TogglePlanning => "action-toggle-planning",
+            Action::PauseSim => "action-pause-sim",
+            Action::SpeedUp => "action-speed-up",
+            Action::SlowDown => "action-slow-down",
+            Action::ResetSim => "action-reset-sim",
+            Action::ToggleSimScreen => "action-toggle-sim-screen",
+            Action::Quicksave => "action-quicksave",
+            Action::Quickload => "action-quickload",
+            Action::ToggleScriptStats => "action-toggle-script-stats",
+            Action::SaveReplay => "action-save-replay",
+            Action::ToggleReplayViewer => "action-toggle-replay-viewer",
+            Action::ExportStats => "action-export-stats",
+            Action::ToggleControls => "action-toggle-controls",
+            Action::PauseMenu => "action-pause-menu",
+            Action::ToggleDiagnostics => "action-toggle-diagnostics",
+            Action::Place => "action-place",
+            Action::BuildListUp => "action-build-list-up",
+            Action::BuildListDown => "action-build-list-down",
+            Action::ToggleGrid => "action-toggle-grid",
+            Action::ToggleContours => "action-toggle-contours",
+            Action::CycleHeatmap => "action-cycle-heatmap",
+            Action::RotateCameraLeft => "action-rotate-camera-left",
+            Action::RotateCameraRight => "action-rotate-camera-right",
+            Action::TogglePhotoMode => "action-toggle-photo-mode",
+            Action::Screenshot => "action-screenshot",
+            Action::ToggleResearch => "action-toggle-research",
+            Action::ToggleRoadTool => "action-toggle-road-tool",
+            Action::ToggleCanalTool => "action-toggle-canal-tool",
+            Action::ToggleConsole => "action-toggle-console",
+            Action::CommitPlan => "action-commit-plan",
+            Action::DiscardPlan => "action-discard-plan",
+            Action::SaveBlueprint => "action-save-blueprint",
+            Action::LoadBlueprint => "action-load-blueprint",
+            Action::PreviousBookmark => "action-previous-bookmark",
+            Action::NextBookmark => "action-next-bookmark",
+            Action::KeepPlacing => "action-keep-placing",
+            Action::FlyFast => "action-fly-fast",
+            Action::HotbarSlot1 => "action-hotbar-slot-1",
+            Action::HotbarSlot2 => "action-hotbar-slot-2",
+            Action::HotbarSlot3 => "action-hotbar-slot-3",
+            Action::HotbarSlot4 => "action-hotbar-slot-4",
+            Action::HotbarSlot5 => "action-hotbar-slot-5",
+            Action::HotbarSlot6 => "action-hotbar-slot-6",
+            Action::HotbarSlot7 => "action-hotbar-slot-7",
+            Action::HotbarSlot8 => "action-hotbar-slot-8",
+            Action::HotbarSlot9 => "action-hotbar-slot-9",
+            Action::HotbarSlot10 => "action-hotbar-slot-10",
+        }
+    }
+}
+
+/// A key, a mouse button or a gamepad button
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+impl std::fmt::Display for Binding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "{key:?}"),
+            Binding::Mouse(button) => write!(f, "Mouse {button:?}"),
+            Binding::Gamepad(button) => write!(f, "Pad {button:?}"),
+        }
+    }
+}
+
+/// Bindings of every action
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+pub struct InputMap {
+    pub bindings: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use Binding::*;
+        let bindings = [
+            (Action::CameraForward, vec![Key(KeyCode::ArrowUp)]),
+            (Action::CameraBack, vec![Key(KeyCode::ArrowDown)]),
+            (Action::CameraLeft, vec![Key(KeyCode::ArrowLeft)]),
+            (Action::CameraRight, vec![Key(KeyCode::ArrowRight)]),
+            (Action::OrbitCamera, vec![Mouse(MouseButton::Right)]),
+            (Action::ToggleWireframe, vec![Key(KeyCode::F3)]),
+            (Action::ToggleBoundingBoxes, vec![Key(KeyCode::F2)]),
+            (Action::CycleSnapping, vec![Key(KeyCode::KeyS)]),
+            (Action::TogglePlanning, vec![Key(KeyCode::KeyP)]),
+            (Action::PauseSim, vec![Key(KeyCode::Space)]),
+            (
+                Action::SpeedUp,
+                vec![Key(KeyCode::Equal), Key(KeyCode::NumpadAdd)],
+            ),
+            (
+                Action::SlowDown,
+                vec![Key(KeyCode::Minus), Key(KeyCode::NumpadSubtract)],
+            ),
+            (Action::ResetSim, vec![Key(KeyCode::KeyR)]),
+            (Action::ToggleSimScreen, vec![Key(KeyCode::Tab)]),
+            (Action::Quicksave, vec![Key(KeyCode::F5)]),
+            (Action::Quickload, vec![Key(KeyCode::F9)]),
+            (Action::ToggleScriptStats, vec![Key(KeyCode::F4)]),
+            (Action::SaveReplay, vec![Key(KeyCode::F6)]),
+            (Action::ToggleReplayViewer, vec![Key(KeyCode::F7)]),
+            (Action::ExportStats, vec![Key(KeyCode::F8)]),
+            (Action::ToggleControls, vec![Key(KeyCode::F10)]),
+            (
+                Action::PauseMenu,
+                vec![Key(KeyCode::Escape), Gamepad(GamepadButton::Start)],
+            ),
+            (Action::ToggleDiagnostics, vec![Key(KeyCode::F1)]),
+            (
+                Action::Place,
+                vec![Mouse(MouseButton::Left), Gamepad(GamepadButton::South)],
+            ),
+            (Action::BuildListUp, vec![Gamepad(GamepadButton::DPadUp)]),
+            (
+                Action::BuildListDown,
+                vec![Gamepad(GamepadButton::DPadDown)],
+            ),
+            (Action::ToggleGrid, vec![Key(KeyCode::KeyG)]),
+            (Action::ToggleContours, vec![Key(KeyCode::KeyC)]),
+            (Action::CycleHeatmap, vec![Key(KeyCode::KeyH)]),
+            (Action::RotateCameraLeft, vec![Key(KeyCode::KeyQ)]),
+            (Action::RotateCameraRight, vec![Key(KeyCode::KeyE)]),
+            (Action::TogglePhotoMode, vec![Key(KeyCode::F11)]),
+            (Action::Screenshot, vec![Key(KeyCode::F12)]),
+            (Action::ToggleResearch, vec![Key(KeyCode::KeyU)]),
+            (Action::ToggleRoadTool, vec![Key(KeyCode::KeyN)]),
+            (Action::ToggleCanalTool, vec![Key(KeyCode::KeyK)]),
+            (Action::ToggleConsole, vec![Key(KeyCode::Backquote)]),
+            (Action::CommitPlan, vec![Key(KeyCode::Enter)]),
+            (Action::DiscardPlan, vec![Key(KeyCode::Backspace)]),
+            (Action::SaveBlueprint, vec![Key(KeyCode::KeyB)]),
+            (Action::LoadBlueprint, vec![Key(KeyCode::KeyV)]),
+            (Action::PreviousBookmark, vec![Key(KeyCode::BracketLeft)]),
+            (Action::NextBookmark, vec![Key(KeyCode::BracketRight)]),
+            (
+                Action::KeepPlacing,
+                vec![Key(KeyCode::ControlLeft), Key(KeyCode::ControlRight)],
+            ),
+            (
+                Action::FlyFast,
+                vec![Key(KeyCode::ShiftLeft), Key(KeyCode::ShiftRight)],
+            ),
+            (Action::HotbarSlot1, vec![Key(KeyCode::Digit1)]),
+            (Action::HotbarSlot2, vec![Key(KeyCode::Digit2)]),
+            (Action::HotbarSlot3, vec![Key(KeyCode::Digit3)]),
+            (Action::HotbarSlot4, vec![Key(KeyCode::Digit4)]),
+            (Action::HotbarSlot5, vec![Key(KeyCode::Digit5)]),
+            (Action::HotbarSlot6, vec![Key(KeyCode::Digit6)]),
+            (Action::HotbarSlot7, vec![Key(KeyCode::Digit7)]),
+            (Action::HotbarSlot8, vec![Key(KeyCode::Digit8)]),
+            (Action::HotbarSlot9, vec![Key(KeyCode::Digit9)]),
+            (Action::HotbarSlot10, vec![Key(KeyCode::Digit0)]),
+        ];
+        Self {
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+impl InputMap {
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings
+            .get(&action)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Other actions bound to the same key or button
+    pub fn conflicts(&self, action: Action, binding: Binding) -> Vec<Action> {
+        self.bindings
+            .iter()
+            .filter(|(other, bindings)| **other != action && bindings.contains(&binding))
+            .map(|(other, _)| *other)
+            .collect()
+    }
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let saved: SavedInputMap = ron::de::from_bytes(&std::fs::read(path)?)?;
+        let mut input_map = InputMap {
+            bindings: BTreeMap::new(),
+        };
+        for (ActionName(name), bindings) in saved.bindings {
+            // actions removed since the file was saved are dropped
+            match ron::from_str::<Action>(&name) {
+                Ok(action) => {
+                    input_map.bindings.insert(action, bindings);
+                }
+                Err(_) => warn!("Dropping the bindings of the unknown action {name}"),
+            }
+        }
+        // actions added since the file was saved get their default bindings
+        for (action, bindings) in InputMap::default().bindings {
+            input_map.bindings.entry(action).or_insert(bindings);
+        }
+        Ok(input_map)
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(
+            path,
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?,
+        )?;
+        Ok(())
+    }
+}
+
+/// An input map as saved, where the actions are read by name
+#[derive(Deserialize)]
+struct SavedInputMap {
+    bindings: BTreeMap<ActionName, Vec<Binding>>,
+}
+
+/// Name of a saved action, which may no longer exist
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct ActionName(String);
+
+impl<'de> Deserialize<'de> for ActionName {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NameVisitor;
+
+        impl serde::de::Visitor<'_> for NameVisitor {
+            type Value = ActionName;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("the name of an action")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, name: &str) -> Result<ActionName, E> {
+                Ok(ActionName(name.to_string()))
+            }
+        }
+
+        deserializer.deserialize_identifier(NameVisitor)
+    }
+}
+
+/// Check the state of the actions. Keys are ignored while some text is being typed.
+/// Every connected gamepad can trigger the actions.
+#[derive(SystemParam)]
+pub struct Actions<'w, 's> {
+    input_map: Res<'w, InputMap>,
+    keys: Res<'w, ButtonInput<KeyCode>>,
+    mouse: Res<'w, ButtonInput<MouseButton>>,
+    gamepads: Query<'w, 's, &'static Gamepad>,
+    text_focus: Res<'w, TextFocus>,
+}
+
+impl Actions<'_, '_> {
+    fn check(
+        &self,
+        action: Action,
+        key: impl Fn(&ButtonInput<KeyCode>, KeyCode) -> bool,
+        mouse: impl Fn(&ButtonInput<MouseButton>, MouseButton) -> bool,
+        gamepad: impl Fn(&ButtonInput<GamepadButton>, GamepadButton) -> bool,
+    ) -> bool {
+        self.check_keys(action, false, key, mouse, gamepad)
+    }
+
+    fn check_keys(
+        &self,
+        action: Action,
+        typing: bool,
+        key: impl Fn(&ButtonInput<KeyCode>, KeyCode) -> bool,
+        mouse: impl Fn(&ButtonInput<MouseButton>, MouseButton) -> bool,
+        gamepad: impl Fn(&ButtonInput<GamepadButton>, GamepadButton) -> bool,
+    ) -> bool {
+        self.input_map
+            .bindings(action)
+            .iter()
+            .any(|binding| match binding {
+                Binding::Key(k) => (typing || self.text_focus.0.is_none()) && key(&self.keys, *k),
+                Binding::Mouse(b) => mouse(&self.mouse, *b),
+                Binding::Gamepad(b) => self.gamepads.iter().any(|pad| gamepad(pad.digital(), *b)),
+            })
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.check(
+            action,
+            ButtonInput::pressed,
+            ButtonInput::pressed,
+            ButtonInput::pressed,
+        )
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.check(
+            action,
+            ButtonInput::just_pressed,
+            ButtonInput::just_pressed,
+            ButtonInput::just_pressed,
+        )
+    }
+
+    /// Like `just_pressed`, but also while some text is being typed, for the actions that
+    /// stop the typing
+    pub fn just_pressed_while_typing(&self, action: Action) -> bool {
+        self.check_keys(
+            action,
+            true,
+            ButtonInput::just_pressed,
+            ButtonInput::just_pressed,
+            ButtonInput::just_pressed,
+        )
+    }
+
+    pub fn just_released(&self, action: Action) -> bool {
+        self.check(
+            action,
+            ButtonInput::just_released,
+            ButtonInput::just_released,
+            ButtonInput::just_released,
+        )
+    }
+
+    /// Sum of the left sticks of the gamepads
+    pub fn left_stick(&self) -> Vec2 {
+        self.gamepads.iter().map(Gamepad::left_stick).sum()
+    }
+
+    /// Sum of the right sticks of the gamepads
+    pub fn right_stick(&self) -> Vec2 {
+        self.gamepads.iter().map(Gamepad::right_stick).sum()
+    }
+
+    /// Analog value of a gamepad button, like the triggers, between 0 and 1
+    pub fn analog(&self, button: GamepadButton) -> f32 {
+        self.gamepads
+            .iter()
+            .filter_map(|gamepad| gamepad.get(button))
+            .fold(0., f32::max)
+    }
+}
+
+/// Camera controls, from the mouse and keyboard actions and the gamepad sticks and triggers.
+#[derive(SystemParam)]
+pub struct CameraInput<'w, 's> {
+    actions: Actions<'w, 's>,
+    mouse_motion: Res<'w, AccumulatedMouseMotion>,
+    mouse_scroll: Res<'w, AccumulatedMouseScroll>,
+    time: Res<'w, Time>,
+}
+
+impl CameraInput<'_, '_> {
+    /// Rotation of the camera this frame, in pixels of mouse motion
+    pub fn orbit(&self) -> Vec2 {
+        let mut delta = self.actions.right_stick() * Vec2::new(1., -1.);
+        delta *= GAMEPAD_ORBIT_SPEED * self.time.delta_secs();
+        if self.actions.pressed(Action::OrbitCamera) {
+            delta += self.mouse_motion.delta;
+        }
+        delta
+    }
+
+    /// Direction the camera target moves in, relative to the camera : x is right, z is back
+    pub fn pan(&self) -> Vec3 {
+        let stick = self.actions.left_stick();
+        let mut movement = Vec3::new(stick.x, 0., -stick.y);
+        if self.actions.pressed(Action::CameraBack) {
+            movement += Vec3::Z;
+        }
+        if self.actions.pressed(Action::CameraForward) {
+            movement -= Vec3::Z;
+        }
+        if self.actions.pressed(Action::CameraLeft) {
+            movement -= Vec3::X;
+        }
+        if self.actions.pressed(Action::CameraRight) {
+            movement += Vec3::X;
+        }
+        movement
+    }
+
+    /// Direction the camera turns around its target : 1 to the left, -1 to the right
+    pub fn rotation(&self) -> f32 {
+        let mut rotation = 0.;
+        if self.actions.pressed(Action::RotateCameraLeft) {
+            rotation += 1.;
+        }
+        if self.actions.pressed(Action::RotateCameraRight) {
+            rotation -= 1.;
+        }
+        rotation
+    }
+
+    /// Zoom this frame, in mouse wheel lines. Positive zooms out.
+    pub fn zoom(&self) -> f32 {
+        let triggers = self.actions.analog(GamepadButton::LeftTrigger2)
+            - self.actions.analog(GamepadButton::RightTrigger2);
+        triggers * GAMEPAD_ZOOM_SPEED * self.time.delta_secs() - self.mouse_scroll.delta.y
+    }
+}
+
+/// Add the WASD keys to the camera panning, next to the arrows, when the setting is on.
+/// Other actions bound to these keys lose them while it is on, and get their default
+/// bindings back if they are left without any once it is off.
+fn apply_wasd_panning(
+    settings: Res<Settings>,
+    mut input_map: ResMut<InputMap>,
+    mut applied: Local<Option<bool>>,
+) -> Result {
+    if !settings.is_changed() || *applied == Some(settings.wasd_panning) {
+        return Ok(());
+    }
+    // the bindings loaded at startup already follow the setting, unless they were reset
+    let first = applied.is_none();
+    *applied = Some(settings.wasd_panning);
+    if first && !settings.wasd_panning {
+        return Ok(());
+    }
+    let defaults = InputMap::default();
+    for (action, key) in WASD_BINDINGS {
+        let binding = Binding::Key(key);
+        if settings.wasd_panning {
+            for other in input_map.conflicts(action, binding) {
+                if let Some(bindings) = input_map.bindings.get_mut(&other) {
+                    bindings.retain(|b| *b != binding);
+                }
+            }
+            let bindings = input_map.bindings.entry(action).or_default();
+            if !bindings.contains(&binding) {
+                bindings.push(binding);
+            }
+        } else if let Some(bindings) = input_map.bindings.get_mut(&action) {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+    if !settings.wasd_panning {
+        for (action, bindings) in input_map.bindings.iter_mut() {
+            if bindings.is_empty() {
+                *bindings = defaults.bindings(*action).to_vec();
+            }
+        }
+    }
+    input_map.save(INPUT_MAP_PATH)?;
+    Ok(())
+}
+
+/// The gamepad has no cursor : keep the mouse one at the center of the window when the
+/// gamepad is used, so that placing and selecting happen there.
+fn center_cursor_on_gamepad(
+    actions: Actions,
+    gamepads: Query<&Gamepad>,
+    mut window: Single<&mut Window, With<PrimaryWindow>>,
+) {
+    let used = actions.left_stick() != Vec2::ZERO
+        || actions.right_stick() != Vec2::ZERO
+        || gamepads
+            .iter()
+            .any(|gamepad| gamepad.get_just_pressed().next().is_some());
+    if !used {
+        return;
+    }
+    let center = window.size() / 2.;
+    if window.cursor_position() != Some(center) {
+        window.set_cursor_position(Some(center));
+    }
+}
+
+/// The action waiting for a new binding on the controls page
+#[derive(Resource, Default)]
+struct Rebinding(Option<Action>);
+
+#[derive(Component)]
+struct ControlsPage;
+
+#[derive(Component)]
+struct RebindButton(Action);
+
+fn setup_controls_page(mut commands: Commands) {
+    commands.spawn((
+        Name::new("controls"),
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(30.),
+            top: Val::Percent(10.),
+            width: Val::Percent(40.),
+            max_height: Val::Percent(80.),
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(10.)),
+            row_gap: Val::Px(3.),
+            overflow: Overflow::scroll_y(),
+            ..default()
+        },
+        BackgroundColor(bevy::color::palettes::css::BLACK.with_alpha(0.9).into()),
+        GlobalZIndex(10),
+        Visibility::Hidden,
+        ControlsPage,
+    ));
+}
+
+fn toggle_controls_page(
+    actions: Actions,
+    rebinding: Res<Rebinding>,
+    mut page: Single<&mut Visibility, With<ControlsPage>>,
+) {
+    if rebinding.0.is_none() && actions.just_pressed(Action::ToggleControls) {
+        page.toggle_visible_hidden();
+    }
+}
+
+/// Wait for a new binding after clicking the binding of an action
+fn start_rebinding(
+    buttons: Query<(&Interaction, &RebindButton), Changed<Interaction>>,
+    page: Single<Entity, With<ControlsPage>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut text_focus: ResMut<TextFocus>,
+) {
+    for (interaction, RebindButton(action)) in &buttons {
+        if *interaction == Interaction::Pressed && text_focus.0.is_none() {
+            rebinding.0 = Some(*action);
+            // keep the other actions from triggering while choosing the key
+            text_focus.0 = Some(*page);
+        }
+    }
+}
+
+/// Bind the next key, mouse button or gamepad button pressed. Escape cancels.
+fn capture_binding(
+    mut rebinding: ResMut<Rebinding>,
+    mut text_focus: ResMut<TextFocus>,
+    mut input_map: ResMut<InputMap>,
+    keys: Res<ButtonInput<KeyCode>>,
+    mouse: Res<ButtonInput<MouseButton>>,
+    gamepads: Query<&Gamepad>,
+) -> Result {
+    let Some(action) = rebinding.0 else {
+        return Ok(());
+    };
+    // the click that started the rebinding
+    if rebinding.is_changed() {
+        return Ok(());
+    }
+    let binding = if keys.just_pressed(KeyCode::Escape) {
+        None
+    } else if let Some(key) = keys.get_just_pressed().next() {
+        Some(Binding::Key(*key))
+    } else if let Some(button) = mouse.get_just_pressed().next() {
+        Some(Binding::Mouse(*button))
+    } else if let Some(button) = gamepads
+        .iter()
+        .find_map(|gamepad| gamepad.get_just_pressed().next())
+    {
+        Some(Binding::Gamepad(*button))
+    } else {
+        return Ok(());
+    };
+    rebinding.0 = None;
+    text_focus.0 = None;
+    if let Some(binding) = binding {
+        input_map.bindings.insert(action, vec![binding]);
+        input_map.save(INPUT_MAP_PATH)?;
+    }
+    Ok(())
+}
+
+/// List the actions with their bindings, marking the conflicting ones
+fn update_controls_page(
+    mut commands: Commands,
+    input_map: Res<InputMap>,
+    rebinding: Res<Rebinding>,
+    page: Single<(Entity, &Visibility), With<ControlsPage>>,
+    font: Res<FontHandle>,
+    localization: Res<Localization>,
+    mut shown: Local<bool>,
+) {
+    let (page, visibility) = *page;
+    let visible = *visibility != Visibility::Hidden;
+    if visible == *shown
+        && !input_map.is_changed()
+        && !rebinding.is_changed()
+        && !localization.is_changed()
+    {
+        return;
+    }
+    *shown = visible;
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 14.,
+        ..default()
+    };
+    commands.entity(page).despawn_related::<Children>();
+    commands.entity(page).with_children(|parent| {
+        parent.spawn((
+            LocalizedText::new("controls-header"),
+            text_font.clone(),
+            Label,
+        ));
+        for action in Action::ALL {
+            let bindings = input_map.bindings(action);
+            let conflicts: Vec<_> = bindings
+                .iter()
+                .flat_map(|binding| input_map.conflicts(action, *binding))
+                .collect();
+            let binding_text = if rebinding.0 == Some(action) {
+                localization.get("controls-press-key")
+            } else if bindings.is_empty() {
+                localization.get("controls-unbound")
+            } else {
+                bindings
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" / ")
+            };
+            let mut label = localization.get(action.label());
+            if !conflicts.is_empty() {
+                let names: Vec<_> = conflicts
+                    .iter()
+                    .map(|a| localization.get(a.label()))
+                    .collect();
+                label = localization.get_args(
+                    "controls-conflicts",
+                    &[("action", label), ("actions", names.join(", "))],
+                );
+            }
+            let color = if conflicts.is_empty() {
+                Color::WHITE
+            } else {
+                bevy::color::palettes::css::TOMATO.into()
+            };
+            parent
+                .spawn(Node {
+                    justify_content: JustifyContent::SpaceBetween,
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((Text(label), text_font.clone(), TextColor(color), Label));
+                    parent.spawn((
+                        Button,
+                        Node {
+                            padding: UiRect::horizontal(Val::Px(6.)),
+                            ..default()
+                        },
+                        BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                        RebindButton(action),
+                        children![(
+                            Text(binding_text),
+                            text_font.clone(),
+                            Label,
+                            Pickable::IGNORE,
+                        )],
+                    ));
+                });
+        }
+    });
+}
@@ -0,0 +1,136 @@
+use bevy::{
+    animation::AnimationPlayer, platform::collections::HashMap, prelude::*,
+    render::mesh::skinning::SkinnedMesh, scene::scene_spawner,
+};
+
+use crate::build::{BuildId, Building, BuildingType};
+
+/// Single buildings are drawn with the meshes and materials of their model shared between all
+/// of them, rather than with a copy of the whole scene each. The draws of a mesh with a
+/// material are batched into one instanced draw, with the transforms of the instances in a
+/// buffer, so hundreds of identical buildings cost a few draw calls. Animated, skinned or lit
+/// models, and the buildings marked `unique`, are still spawned as scenes.
+pub struct InstancingPlugin;
+
+impl Plugin for InstancingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(InstancedModels::default())
+            .add_systems(Update, extract_instanced_models)
+            // before the scenes are spawned, so that the instanced ones never are
+            .add_systems(SpawnScene, instance_models.before(scene_spawner));
+    }
+}
+
+/// A mesh of a model, with its transform relative to the root of the model
+#[derive(Clone)]
+struct ModelPart {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+    transform: Transform,
+}
+
+/// Parts of the models of the buildings, by scene. `None` for the scenes that can't be
+/// instanced.
+#[derive(Resource, Default)]
+struct InstancedModels(HashMap<AssetId<Scene>, Option<Vec<ModelPart>>>);
+
+/// Take the parts of the models of the buildings once their scene is loaded, again when it
+/// is reloaded
+fn extract_instanced_models(
+    mut models: ResMut<InstancedModels>,
+    mut events: EventReader<AssetEvent<Scene>>,
+    buildings: Res<Assets<Building>>,
+    scenes: Res<Assets<Scene>>,
+) {
+    for event in events.read() {
+        if let AssetEvent::Modified { id } | AssetEvent::Removed { id } = event {
+            models.0.remove(id);
+        }
+    }
+    for (_, building) in buildings.iter() {
+        let BuildingType::Single { model, .. } = &building.typ else {
+            continue;
+        };
+        if models.0.contains_key(&model.id()) {
+            continue;
+        }
+        let Some(scene) = scenes.get(model) else {
+            continue;
+        };
+        let parts = model_parts(&scene.world);
+        if parts.is_none() {
+            info!(
+                "The model {:?} is animated or lit, it is spawned as a scene",
+                model.path()
+            );
+        }
+        models.0.insert(model.id(), parts);
+    }
+}
+
+/// Meshes of a scene with their transform from its root, or `None` if some of it would be
+/// lost by drawing only the meshes
+fn model_parts(world: &World) -> Option<Vec<ModelPart>> {
+    let mut parts = Vec::new();
+    for entity in world.iter_entities() {
+        if entity.contains::<AnimationPlayer>()
+            || entity.contains::<SkinnedMesh>()
+            || entity.contains::<PointLight>()
+            || entity.contains::<SpotLight>()
+            || entity.contains::<DirectionalLight>()
+        {
+            return None;
+        }
+        let Some(mesh) = entity.get::<Mesh3d>() else {
+            continue;
+        };
+        let material = entity.get::<MeshMaterial3d<StandardMaterial>>()?;
+        // the transforms of the nodes above the mesh, up to the root
+        let mut transform = entity.get::<Transform>().copied().unwrap_or_default();
+        let mut parent = entity.get::<ChildOf>().map(ChildOf::parent);
+        while let Some(e) = parent {
+            let node = world.get::<Transform>(e).copied().unwrap_or_default();
+            transform = node * transform;
+            parent = world.get::<ChildOf>(e).map(ChildOf::parent);
+        }
+        parts.push(ModelPart {
+            mesh: mesh.0.clone(),
+            material: material.0.clone(),
+            transform,
+        });
+    }
+    Some(parts)
+}
+
+/// Replace the scene of the single buildings by the shared parts of their model
+fn instance_models(
+    mut commands: Commands,
+    roots: Query<(Entity, &SceneRoot, &BuildId), Changed<SceneRoot>>,
+    models: Res<InstancedModels>,
+    buildings: Res<Assets<Building>>,
+) {
+    for (e, root, bid) in &roots {
+        let unique = buildings.get(&bid.0).is_some_and(|building| {
+            matches!(building.typ, BuildingType::Single { unique: true, .. })
+        });
+        let Some(Some(parts)) = models.0.get(&root.0.id()) else {
+            continue;
+        };
+        if unique {
+            continue;
+        }
+        commands
+            .entity(e)
+            .remove::<SceneRoot>()
+            .with_children(|parent| {
+                for part in parts {
+                    parent.spawn((
+                        Name::new("instanced part"),
+                        Mesh3d(part.mesh.clone()),
+                        MeshMaterial3d(part.material.clone()),
+                        part.transform,
+                    ));
+                }
+            });
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use rhai::Engine;
+
+use crate::{
+    CameraTarget,
+    build::{BuildId, Building},
+    heatmap::{HeatmapLayer, Heatmaps},
+    map::{BuildingInstance, GRID_SQUARE_SIZE, Map},
+    menu::GameState,
+    pollution::{CELL_SIZE, Cell, PollutionGrid, RIVER_AMOUNT, cell_of, spread_pollution},
+    replication::TerrainOp,
+    script_api::SharedScriptWorld,
+    sim::{Sim, sim_running},
+};
+
+/// Distance within which water raises the value of the land, in world units
+const WATER_RANGE: f32 = 12.;
+/// Distance between the points searched for water, in world units
+const WATER_SEARCH_STEP: f32 = 2.;
+/// Slope, in height per world unit, from which the land is worthless to build on
+const MAX_SLOPE: f32 = 1.;
+/// Distance within which a service raises the value of the land, in world units
+const SERVICE_RADIUS: f32 = 30.;
+/// Tag of the buildings serving their surroundings
+const SERVICE_TAG: &str = "service";
+/// Pollution removing all the value of the land
+const POLLUTION_FULL: f32 = 20.;
+/// Weight of each part of the value of the land
+const WATER_WEIGHT: f32 = 0.3;
+const FLATNESS_WEIGHT: f32 = 0.3;
+const SERVICE_WEIGHT: f32 = 0.4;
+/// Cells around the buildings whose value is kept for the scripts
+const BUILDING_REACH: i32 = 2;
+/// Cells around the camera target drawn by the heatmap
+const VIEW_REACH: i32 = 10;
+/// Sim ticks between two updates of the value of the land
+const REFRESH_TICKS: u64 = 20;
+/// Zones grow that much faster on the most valuable land than on worthless land
+const MAX_GROWTH_SPEED: f32 = 3.;
+
+/// Desirability of the land, between 0 and 1, on the cells of the pollution grid. Water
+/// nearby, flat ground and services (buildings tagged "service") raise it, pollution lowers
+/// it. It is updated around the buildings every few ticks for the scripts, which read it with
+/// `land_value(x, z)` and `growth_speed(x, z)`, and drawn around the camera by the land value
+/// heatmap.
+pub struct LandValuePlugin;
+
+impl Plugin for LandValuePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TerrainValues::default())
+            .add_systems(OnExit(GameState::InGame), clear_terrain_values)
+            .add_systems(
+                FixedUpdate,
+                value_around_buildings
+                    .after(spread_pollution)
+                    .run_if(sim_running),
+            )
+            .add_systems(
+                Update,
+                (
+                    forget_changed_terrain,
+                    paint_land_value_heatmap.after(forget_changed_terrain),
+                )
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// Value of the land around the buildings, shared with the scripts
+#[derive(Default, Clone, Debug)]
+pub struct LandValueGrid {
+    cells: BTreeMap<Cell, f32>,
+}
+
+impl LandValueGrid {
+    pub fn get(&self, pos: Vec2) -> f32 {
+        self.cells.get(&cell_of(pos)).copied().unwrap_or(0.)
+    }
+
+    /// Multiplier of the speed zones grow at
+    pub fn growth_speed(&self, pos: Vec2) -> f32 {
+        1f32.lerp(MAX_GROWTH_SPEED, self.get(pos))
+    }
+}
+
+/// Part of the value of the land coming from the terrain, by cell, which only changes with
+/// the terrain
+#[derive(Resource, Default)]
+struct TerrainValues(HashMap<Cell, f32>);
+
+impl TerrainValues {
+    fn get(&mut self, map: &Map, cell: Cell) -> f32 {
+        *self
+            .0
+            .entry(cell)
+            .or_insert_with(|| terrain_value(map, cell))
+    }
+}
+
+fn clear_terrain_values(mut values: ResMut<TerrainValues>) {
+    values.0.clear();
+}
+
+fn cell_center(cell: Cell) -> Vec2 {
+    (Vec2::new(cell.0 as f32, cell.1 as f32) + 0.5) * CELL_SIZE
+}
+
+/// Value of a cell from the water around it and its flatness, 0 under the sea
+fn terrain_value(map: &Map, cell: Cell) -> f32 {
+    let center = cell_center(cell);
+    let at = |p: Vec2| Vec3::new(p.x, 0., p.y);
+    if map.continent.is_sea(at(center)) {
+        return 0.;
+    }
+    let steps = (WATER_RANGE / WATER_SEARCH_STEP) as i32;
+    let mut water_distance = f32::INFINITY;
+    for i in -steps..=steps {
+        for j in -steps..=steps {
+            let offset = Vec2::new(i as f32, j as f32) * WATER_SEARCH_STEP;
+            let pos = at(center + offset);
+            let (x, y) = map.continent.from_world(&pos);
+            if map.continent.is_sea(pos) || map.continent.get_hydro(x, y).amount >= RIVER_AMOUNT {
+                water_distance = water_distance.min(offset.length());
+            }
+        }
+    }
+    let water = (1. - water_distance / WATER_RANGE).max(0.);
+    let h = |offset: Vec2| map.get_height(at(center + offset));
+    let slope = Vec2::new(
+        h(Vec2::X * CELL_SIZE / 2.) - h(Vec2::NEG_X * CELL_SIZE / 2.),
+        h(Vec2::Y * CELL_SIZE / 2.) - h(Vec2::NEG_Y * CELL_SIZE / 2.),
+    )
+    .length()
+        / CELL_SIZE;
+    let flatness = (1. - slope / MAX_SLOPE).max(0.);
+    WATER_WEIGHT * water + FLATNESS_WEIGHT * flatness
+}
+
+/// Value of a cell, adding the services around it and removing its pollution
+fn land_value(
+    terrain: &mut TerrainValues,
+    map: &Map,
+    pollution: &PollutionGrid,
+    services: &[Vec2],
+    cell: Cell,
+) -> f32 {
+    let center = cell_center(cell);
+    let served: f32 = services
+        .iter()
+        .map(|service| (1. - service.distance(center) / SERVICE_RADIUS).max(0.))
+        .sum();
+    let polluted = (pollution.get(center) / POLLUTION_FULL).min(1.);
+    let value = terrain.get(map, cell) + SERVICE_WEIGHT * served.min(1.);
+    (value * (1. - polluted)).clamp(0., 1.)
+}
+
+/// Positions of the buildings serving their surroundings, and of every building
+fn placed_buildings(
+    placed: &Query<(&BuildingInstance, &BuildId)>,
+    buildings: &Assets<Building>,
+) -> (Vec<Vec2>, Vec<Vec2>) {
+    let services = placed
+        .iter()
+        .filter(|(_, bid)| {
+            buildings
+                .get(&bid.0)
+                .is_some_and(|b| b.tags.iter().any(|tag| tag == SERVICE_TAG))
+        })
+        .map(|(instance, _)| instance.center())
+        .collect();
+    let all = placed
+        .iter()
+        .map(|(instance, _)| instance.center())
+        .collect();
+    (services, all)
+}
+
+fn cells_around(center: Cell, reach: i32) -> impl Iterator<Item = Cell> {
+    (-reach..=reach).flat_map(move |i| (-reach..=reach).map(move |j| (center.0 + i, center.1 + j)))
+}
+
+/// Update the value of the land around the buildings for the scripts
+pub(crate) fn value_around_buildings(
+    sim: Res<Sim>,
+    map: Res<Map>,
+    mut terrain: ResMut<TerrainValues>,
+    placed: Query<(&BuildingInstance, &BuildId)>,
+    buildings: Res<Assets<Building>>,
+) {
+    let mut world = sim.script_world.0.lock().unwrap();
+    if world.tick % REFRESH_TICKS != 0 {
+        return;
+    }
+    let (services, all) = placed_buildings(&placed, &buildings);
+    let mut cells = BTreeMap::new();
+    for pos in all {
+        for cell in cells_around(cell_of(pos), BUILDING_REACH) {
+            if !cells.contains_key(&cell) {
+                let value = land_value(&mut terrain, &map, &world.pollution, &services, cell);
+                cells.insert(cell, value);
+            }
+        }
+    }
+    world.land_value.cells = cells;
+}
+
+/// The terrain changed under the terrain operations
+fn forget_changed_terrain(mut ops: EventReader<TerrainOp>, mut terrain: ResMut<TerrainValues>) {
+    for op in ops.read() {
+        let reach = (op.radius / CELL_SIZE).ceil() as i32 + 1;
+        for cell in cells_around(cell_of(op.center.xz()), reach) {
+            terrain.0.remove(&cell);
+        }
+    }
+}
+
+/// Draw the value of the land around the camera target while its heatmap is shown, every
+/// few ticks or when the camera target moved to another cell
+fn paint_land_value_heatmap(
+    sim: Res<Sim>,
+    map: Res<Map>,
+    mut terrain: ResMut<TerrainValues>,
+    mut heatmaps: ResMut<Heatmaps>,
+    camera: Query<&CameraTarget, With<Camera>>,
+    placed: Query<(&BuildingInstance, &BuildId)>,
+    buildings: Res<Assets<Building>>,
+    mut painted: Local<Option<(u64, Cell)>>,
+) {
+    if heatmaps.shown != Some(HeatmapLayer::LandValue) {
+        *painted = None;
+        return;
+    }
+    let Ok(target) = camera.single() else {
+        return;
+    };
+    let center = cell_of(target.pos.xz());
+    if painted.is_some_and(|(tick, cell)| cell == center && sim.tick < tick + REFRESH_TICKS) {
+        return;
+    }
+    *painted = Some((sim.tick, center));
+    let (services, _) = placed_buildings(&placed, &buildings);
+    let pollution = sim.script_world.0.lock().unwrap().pollution.clone();
+    heatmaps.clear(HeatmapLayer::LandValue);
+    let steps = (CELL_SIZE / GRID_SQUARE_SIZE) as i32;
+    for cell in cells_around(center, VIEW_REACH) {
+        let value = land_value(&mut terrain, &map, &pollution, &services, cell);
+        let min = Vec2::new(cell.0 as f32, cell.1 as f32) * CELL_SIZE;
+        for i in 0..steps {
+            for j in 0..steps {
+                let pos = min + Vec2::new(i as f32, j as f32) * GRID_SQUARE_SIZE;
+                heatmaps.set(HeatmapLayer::LandValue, Vec3::new(pos.x, 0., pos.y), value);
+            }
+        }
+    }
+}
+
+/// Register `land_value(x, z)`, between 0 and 1, and `growth_speed(x, z)`, the multiplier
+/// of the speed zones grow at, near the buildings
+pub fn register_land_value_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("land_value", move |x: f64, z: f64| -> f64 {
+        let world = w.0.lock().unwrap();
+        world.land_value.get(Vec2::new(x as f32, z as f32)) as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("growth_speed", move |x: f64, z: f64| -> f64 {
+        let world = w.0.lock().unwrap();
+        world.land_value.growth_speed(Vec2::new(x as f32, z as f32)) as f64
+    });
+}
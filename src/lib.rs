@@ -0,0 +1,626 @@
+pub mod audio;
+pub mod bridges;
+pub mod build;
+pub mod build_asset;
+pub mod canals;
+pub mod cli;
+pub mod clock;
+pub mod console;
+pub mod context_menu;
+pub mod cursor_readout;
+pub mod dams;
+pub mod day_night;
+pub mod deposits;
+pub mod diagnostics_overlay;
+pub mod disasters;
+pub mod editor;
+pub mod extraction;
+pub mod fog_of_war;
+pub mod gltf_export;
+pub mod graph;
+pub mod headless;
+pub mod heatmap;
+pub mod heightmaps;
+pub mod hotbar;
+pub mod input_map;
+pub mod instancing;
+pub mod land_value;
+pub mod localization;
+pub mod logistics;
+pub mod map;
+pub mod menu;
+pub mod music;
+pub mod palettes;
+pub mod particles;
+pub mod pause_menu;
+pub mod photo_mode;
+pub mod plan;
+pub mod player_commands;
+pub mod pollution;
+pub mod population;
+pub mod puddles;
+pub mod remote;
+pub mod replay;
+pub mod replication;
+pub mod research;
+pub mod roads;
+pub mod save_browser;
+pub mod save_game;
+pub mod scenario;
+pub mod script_errors;
+pub mod script_limits;
+pub mod script_ui;
+pub mod shaders;
+pub mod signs;
+pub mod sim;
+pub mod sim_rng;
+pub mod start_location;
+pub mod stats_export;
+pub mod terrain_overlay;
+pub mod toasts;
+pub mod tooltip;
+pub mod trade;
+pub mod top_bar;
+pub mod ui;
+pub mod vehicles;
+pub mod versioning;
+pub mod weather;
+pub mod wind;
+pub mod zones;
+pub mod mapgen;
+pub mod script_api;
+
+use std::{
+    f32::consts::{FRAC_PI_2, PI},
+    ops::Range,
+};
+
+use bevy::{
+    color::palettes, core_pipeline::{
+        bloom::Bloom,
+        experimental::taa::{TemporalAntiAliasPlugin, TemporalAntiAliasing},
+        prepass::DepthPrepass,
+    }, pbr::{
+        light_consts::lux, wireframe::{WireframeConfig, WireframePlugin}, Atmosphere
+    }, picking::hover::HoverMap, prelude::*, remote::{http::RemoteHttpPlugin, RemotePlugin}, render::{camera::Exposure, primitives::Aabb},
+    window::PrimaryWindow,
+};
+use audio::SoundPlugin;
+use bridges::BridgePlugin;
+use build::{BuildPlugin, cast_to_terrain};
+use build_asset::BuildAssetPlugin;
+use canals::CanalPlugin;
+use cli::{Cli, LaunchPlugin};
+use clock::ClockPlugin;
+use console::ConsolePlugin;
+use context_menu::ContextMenuPlugin;
+use cursor_readout::CursorReadoutPlugin;
+use dams::DamPlugin;
+use day_night::DayNightPlugin;
+use deposits::DepositPlugin;
+use diagnostics_overlay::DiagnosticsOverlayPlugin;
+use disasters::DisasterPlugin;
+use editor::EditorPlugin;
+use extraction::ExtractionPlugin;
+use fog_of_war::FogOfWarPlugin;
+use gltf_export::GltfExportPlugin;
+use graph::GraphPlugin;
+use headless::{HeadlessPlugin, headless_default_plugins};
+use heatmap::HeatmapPlugin;
+use hotbar::HotbarPlugin;
+use input_map::{Action, Actions, CameraInput, InputMapPlugin};
+use instancing::InstancingPlugin;
+use land_value::LandValuePlugin;
+use localization::LocalizationPlugin;
+use logistics::LogisticsPlugin;
+use map::{IsGround, Map, MapPlugin};
+use menu::MenuPlugin;
+use music::MusicPlugin;
+use palettes::PalettePlugin;
+use particles::ParticlePlugin;
+use pause_menu::{Pause, PauseMenuPlugin, Settings};
+use photo_mode::{PhotoMode, PhotoModePlugin};
+use plan::PlanPlugin;
+use player_commands::PlayerCommandPlugin;
+use pollution::PollutionPlugin;
+use population::PopulationPlugin;
+use puddles::PuddlePlugin;
+use remote::GameRemotePlugin;
+use replay::ReplayPlugin;
+use replication::ReplicationPlugin;
+use research::ResearchPlugin;
+use roads::RoadPlugin;
+use save_browser::SaveBrowserPlugin;
+use save_game::SaveGamePlugin;
+use scenario::ScenarioPlugin;
+use script_errors::ScriptErrorPlugin;
+use script_limits::ScriptLimitsPlugin;
+use script_ui::ScriptUiPlugin;
+use shaders::ShadersPlugin;
+use signs::SignPlugin;
+use sim::SimPlugin;
+use start_location::StartLocationPlugin;
+use stats_export::StatsExportPlugin;
+use terrain_overlay::TerrainOverlayPlugin;
+use toasts::ToastPlugin;
+use tooltip::TooltipPlugin;
+use trade::TradePlugin;
+use top_bar::TopBarPlugin;
+use ui::UiPlugin;
+use vehicles::VehiclePlugin;
+use weather::WeatherPlugin;
+use wind::WindPlugin;
+use zones::ZonePlugin;
+
+use crate::build::BuildId;
+
+/// Build the app with every plugin of the game, and run it as asked on the command line
+pub fn run(cli: Cli) {
+    let headless = cli.headless;
+    let mut app = App::new();
+    if headless {
+        app.add_plugins(headless_default_plugins().set(ImagePlugin::default_nearest()))
+            .insert_resource(cli.headless_settings())
+            .add_plugins(HeadlessPlugin);
+    } else {
+        app.add_plugins(
+            DefaultPlugins
+                .set(ImagePlugin::default_nearest())
+                .set(WindowPlugin {
+                    primary_window: Some(cli.window()),
+                    ..default()
+                }),
+        );
+    }
+    app.add_plugins((WireframePlugin::default(), TemporalAntiAliasPlugin))
+        .add_plugins(RemotePlugin::default())
+        .add_plugins(RemoteHttpPlugin::default())
+        .add_plugins(GameRemotePlugin)
+        .insert_resource(CameraSettings::default())
+        .add_systems(Startup, (setup_3d,))
+        .add_plugins((
+            BuildPlugin,
+            UiPlugin,
+            MapPlugin,
+            ShadersPlugin,
+            BuildAssetPlugin,
+            InputMapPlugin,
+            MenuPlugin,
+            PauseMenuPlugin,
+            ToastPlugin,
+            LocalizationPlugin,
+        ))
+        .add_plugins((
+            SimPlugin,
+            PlanPlugin,
+            SignPlugin,
+            PuddlePlugin,
+            ReplicationPlugin,
+            ScriptErrorPlugin,
+            PlayerCommandPlugin,
+            ReplayPlugin,
+            GraphPlugin,
+            RoadPlugin,
+            ScriptLimitsPlugin,
+            ScenarioPlugin,
+            ConsolePlugin,
+            StatsExportPlugin,
+        ))
+        .add_plugins((
+            DayNightPlugin,
+            HeatmapPlugin,
+            FogOfWarPlugin,
+            SaveGamePlugin,
+            InstancingPlugin,
+            LaunchPlugin,
+            SoundPlugin,
+            ParticlePlugin,
+            WeatherPlugin,
+            WindPlugin,
+            VehiclePlugin,
+            LogisticsPlugin,
+            TradePlugin,
+            PollutionPlugin,
+            LandValuePlugin,
+        ))
+        .add_plugins((
+            PopulationPlugin,
+            ResearchPlugin,
+            ClockPlugin,
+            BridgePlugin,
+            DamPlugin,
+            CanalPlugin,
+            ExtractionPlugin,
+            DisasterPlugin,
+            ZonePlugin,
+            DepositPlugin,
+            EditorPlugin,
+            GltfExportPlugin,
+            PalettePlugin,
+        ))
+        .insert_resource(cli.worldgen())
+        .insert_resource(cli.launch());
+    // the plugins only showing things or reacting to the player
+    if !headless {
+        app.add_plugins((
+            HotbarPlugin,
+            TooltipPlugin,
+            CursorReadoutPlugin,
+            DiagnosticsOverlayPlugin,
+            ContextMenuPlugin,
+            ScriptUiPlugin,
+            TopBarPlugin,
+            TerrainOverlayPlugin,
+            PhotoModePlugin,
+            SaveBrowserPlugin,
+            MusicPlugin,
+            StartLocationPlugin,
+        ))
+        .add_systems(
+            Update,
+            (
+                toggle_wireframe,
+                orbit
+                    .run_if(in_state(Pause::Running))
+                    .run_if(in_state(PhotoMode::Off)),
+                toggle_bounding_box,
+            ),
+        );
+    }
+
+    app.run();
+}
+
+/// Settings for the orientable camera
+#[derive(Debug, Resource)]
+struct CameraSettings {
+    pub orbit_distance: Range<f32>,
+    pub pitch_speed: f32,
+    // Clamp pitch to this range
+    pub pitch_range: Range<f32>,
+    pub yaw_speed: f32,
+    /// Speed of the rotation with the keyboard, in radians per second
+    pub rotation_speed: f32,
+    pub zoom_speed: f32,
+    pub pan_speed: f32,
+    /// Speed at which the camera catches up with its target, higher is snappier
+    pub pan_smoothing: f32,
+    pub zoom_smoothing: f32,
+    pub rotation_smoothing: f32,
+    /// Distance to the border of the window, in pixels, from which the cursor pans
+    pub edge_pan_zone: f32,
+    /// Panning speed with the cursor on the border, relative to `pan_speed`
+    pub edge_pan_speed: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        // Limiting pitch stops some unexpected rotation past 90° up or down.
+        let pitch_limit = FRAC_PI_2 - 0.01;
+        Self {
+            // These values are completely arbitrary, chosen because they seem to produce
+            // "sensible" results for this example. Adjust as required.
+            orbit_distance: 1.0..100.0,
+            pitch_speed: 0.003,
+            pitch_range: -pitch_limit..pitch_limit,
+            yaw_speed: 0.004,
+            rotation_speed: 1.5,
+            zoom_speed: 0.05,
+            pan_speed: 3.,
+            pan_smoothing: 12.,
+            zoom_smoothing: 10.,
+            rotation_smoothing: 15.,
+            edge_pan_zone: 10.,
+            edge_pan_speed: 1.,
+        }
+    }
+}
+
+#[derive(Component)]
+struct Sun;
+
+/// Setup the 3D environnement. Mostly a placeholder.
+fn setup_3d(
+    mut commands: Commands,
+    //mut materials: ResMut<Assets<StandardMaterial>>, mut meshes: ResMut<Assets<Mesh>>
+) {
+    commands.spawn((
+        Name::new("Sun"),
+        DirectionalLight {
+            shadows_enabled: true,
+            illuminance: lux::RAW_SUNLIGHT,
+            shadow_depth_bias: 0.05,
+            ..default()
+        },
+        Transform {
+            translation: Vec3::new(0.0, 10.0, 0.0),
+            rotation: Quat::from_rotation_x(-PI / 4.),
+            ..default()
+        },
+        Sun,
+    ));
+
+    // //ground plane
+    // commands.spawn((
+    //     Mesh3d(meshes.add(Plane3d::default().mesh().size(500.0, 500.0).subdivisions(100))),
+    //     MeshMaterial3d(materials.add(Color::from(bevy::color::palettes::css::SILVER))),
+    //     Transform::from_scale(Vec3::splat(44.0)).with_translation(Vec3::new(0.,0., 0.)).with_rotation(Quat::from_axis_angle(Vec3::Z, 0.))
+    // ));
+
+    let camera_transform = Transform::from_xyz(20.0, 20., 20.0).looking_at(Vec3::ZERO, Vec3::Y);
+    commands.spawn((
+        Name::new("3d camera"),
+        Camera3d::default(),
+        IsDefaultUiCamera,
+        CameraTarget {
+            pos: Vec3::default(),
+            distance: 10.,
+            rotation: camera_transform.rotation,
+        },
+        CameraMotion {
+            pos: Vec3::default(),
+            distance: 10.,
+            visible_distance: 10.,
+        },
+        Projection::Perspective(PerspectiveProjection {
+            fov: PI / 3.,
+            ..Default::default()
+        }),
+        Camera {
+            hdr: true,
+            ..default()
+        },
+        Bloom::NATURAL,
+        Exposure::SUNLIGHT,
+        AmbientLight {
+            color: palettes::css::MIDNIGHT_BLUE.lighter(0.1).into(),
+            brightness: 30000.,
+            ..default()
+        },
+        DepthPrepass,
+        Msaa::Off,
+        TemporalAntiAliasing::default(),
+        camera_transform,
+        Atmosphere::EARTH,
+        DistanceFog {
+            color: Color::srgba(0.55, 0.58, 0.72, 0.6),
+            directional_light_color: Color::srgba(1.0, 0.95, 0.85, 0.5),
+            directional_light_exponent: 50.0,
+            falloff: FogFalloff::from_visibility_colors(
+                300.0, // distance in world units up to which objects retain visibility (>= 5% contrast)
+                Color::srgb(0.796, 0.914, 0.929), // atmospheric extinction color (after light is lost due to absorption by atmospheric particles)
+                Color::srgb(0.8, 0.844, 1.0), // atmospheric inscattering color (light gained due to scattering from the sun)
+            ),
+        }
+        //DistanceFog::default()
+        //ScreenSpaceAmbientOcclusion::default()
+    ));
+}
+
+/// Toggle wireframe on pressing space, for debugging purposes
+fn toggle_wireframe(
+    mut wireframe_config: ResMut<WireframeConfig>,
+    actions: Actions,
+) {
+    if actions.just_pressed(Action::ToggleWireframe) {
+        wireframe_config.global = !wireframe_config.global;
+    }
+}
+#[derive(Default)]
+struct BoundingBoxConfig(pub bool);
+
+fn toggle_bounding_box(
+    mut bb_config: Local<BoundingBoxConfig>,
+    actions: Actions,
+    aabb_query: Query<(&Aabb, &GlobalTransform), With<BuildId>>,
+    mut gizmos: Gizmos,
+) {
+    if actions.just_pressed(Action::ToggleBoundingBoxes) {
+        bb_config.0 = !bb_config.0;
+    }
+    if bb_config.0 {
+        for (aabb, transform) in aabb_query {
+            gizmos.cuboid(
+                Transform::from_translation(
+                    Vec3::from(aabb.center) * transform.scale() + transform.translation(),
+                )
+                .with_scale(
+                    transform
+                        .rotation()
+                        .mul_vec3(Vec3::from(aabb.half_extents) * transform.scale() * 2.),
+                ),
+                bevy::color::palettes::css::ORANGE_RED,
+            );
+        }
+    }
+}
+
+/// Where the camera is going : the inputs move the target, and the camera follows it smoothly
+#[derive(Component)]
+pub struct CameraTarget {
+    pos: Vec3,
+    distance: f32,
+    rotation: Quat,
+}
+
+/// Makes the camera target follow an entity, until the player pans
+#[derive(Component)]
+pub struct CameraFollow(pub Entity);
+
+/// Where the camera orbits around now, on its way to the `CameraTarget`.
+/// Its rotation is the one of its transform.
+#[derive(Component)]
+struct CameraMotion {
+    pos: Vec3,
+    distance: f32,
+    /// Distance the camera is actually at, closer than `distance` when terrain hides the target
+    visible_distance: f32,
+}
+
+/// Step of the search for terrain between the target and the camera, in world units
+const OCCLUSION_STEP: f32 = 0.5;
+/// Height the line of sight keeps above the terrain at the camera, less closer to the target,
+/// which is on the ground
+const OCCLUSION_CLEARANCE: f32 = 0.5;
+
+/// Orbiting camera handling
+fn orbit(
+    mut commands: Commands,
+    mut camera: Single<(
+        Entity,
+        &mut Transform,
+        &mut CameraTarget,
+        &mut CameraMotion,
+        &Camera,
+        &GlobalTransform,
+        Option<&CameraFollow>,
+    )>,
+    followed: Query<&GlobalTransform, Without<Camera>>,
+    camera_settings: Res<CameraSettings>,
+    settings: Res<Settings>,
+    input: CameraInput,
+    window: Single<&Window, With<PrimaryWindow>>,
+    hover_map: Res<HoverMap>,
+    nodes: Query<(), With<Node>>,
+    mut ray_cast: MeshRayCast,
+    chunks: Query<&IsGround>,
+    map: Res<Map>,
+    time: Res<Time>,
+) {
+    let (camera_entity, camera_transform, camera_target, motion, camera_view, global_transform, follow) =
+        &mut *camera;
+    let delta = input.orbit();
+    if delta != Vec2::ZERO {
+        // Mouse motion is one of the few inputs that should not be multiplied by delta time,
+        // as we are already receiving the full movement since the last frame was rendered. Multiplying
+        // by delta time here would make the movement slower that it should be.
+        // The gamepad orbit is already scaled by delta time.
+        let delta_pitch = -delta.y * camera_settings.pitch_speed;
+        let delta_yaw = -delta.x * camera_settings.yaw_speed;
+
+        // Obtain the existing pitch, yaw, and roll values from the target.
+        let (yaw, pitch, roll) = camera_target.rotation.to_euler(EulerRot::YXZ);
+
+        // Establish the new yaw and pitch, preventing the pitch value from exceeding our limits.
+        let pitch = (pitch + delta_pitch).clamp(
+            camera_settings.pitch_range.start,
+            camera_settings.pitch_range.end,
+        );
+        let yaw = yaw + delta_yaw;
+        camera_target.rotation = Quat::from_euler(EulerRot::YXZ, yaw, pitch, roll);
+    }
+    let rotation = input.rotation();
+    if rotation != 0. {
+        let yaw = rotation * camera_settings.rotation_speed * time.delta_secs();
+        camera_target.rotation = Quat::from_rotation_y(yaw) * camera_target.rotation;
+    }
+
+    // Adjust the translation to maintain the correct orientation toward the orbit target at the desired orbit distance.
+
+    // Move the target if needed
+    let mut movement = input.pan();
+    // not while the cursor is on the panels near the border
+    let over_ui = hover_map
+        .values()
+        .any(|hits| hits.keys().any(|hit| nodes.contains(*hit)));
+    if settings.edge_pan && !over_ui {
+        movement += edge_pan(&window, &camera_settings);
+    }
+
+    // Follow the entity until the player pans, or it is gone
+    if let Some(CameraFollow(entity)) = follow {
+        match followed.get(*entity) {
+            Ok(transform) if movement == Vec3::ZERO => camera_target.pos = transform.translation(),
+            _ => {
+                commands.entity(*camera_entity).remove::<CameraFollow>();
+            }
+        }
+    }
+
+    movement *= time.delta_secs() * camera_settings.pan_speed * camera_target.distance;
+
+    camera_target.pos += camera_target.rotation.mul_vec3(movement);
+
+    let delta_scroll = input.zoom();
+    if delta_scroll != 0. {
+        let previous_distance = camera_target.distance;
+        camera_target.distance += delta_scroll * camera_settings.zoom_speed * camera_target.distance;
+        camera_target.distance = camera_target.distance.clamp(
+            camera_settings.orbit_distance.start,
+            camera_settings.orbit_distance.end,
+        );
+        // Zoom toward the point under the cursor : scaling the camera around it keeps it in place on screen
+        let hit = window
+            .cursor_position()
+            .and_then(|cursor| camera_view.viewport_to_world(global_transform, cursor).ok())
+            .and_then(|ray| cast_to_terrain(&mut ray_cast, ray, &chunks));
+        if let Some(hit) = hit {
+            let scale = camera_target.distance / previous_distance;
+            camera_target.pos = hit.point + (camera_target.pos - hit.point) * scale;
+        }
+    }
+
+    let height =  map.get_height(camera_target.pos);
+    camera_target.pos.y = height;
+
+    // Ease the camera toward its target
+    let dt = time.delta_secs();
+    motion
+        .pos
+        .smooth_nudge(&camera_target.pos, camera_settings.pan_smoothing, dt);
+    motion
+        .distance
+        .smooth_nudge(&camera_target.distance, camera_settings.zoom_smoothing, dt);
+    camera_transform.rotation.smooth_nudge(
+        &camera_target.rotation,
+        camera_settings.rotation_smoothing,
+        dt,
+    );
+
+    // Pull the camera in front of the terrain hiding the target at once, and move it back out smoothly
+    let back = -camera_transform.forward().as_vec3();
+    let clear_distance = unoccluded_distance(&map, motion.pos, back, motion.distance)
+        .max(camera_settings.orbit_distance.start);
+    if clear_distance < motion.visible_distance {
+        motion.visible_distance = clear_distance;
+    } else {
+        motion
+            .visible_distance
+            .smooth_nudge(&clear_distance, camera_settings.zoom_smoothing, dt);
+    }
+    camera_transform.translation = motion.pos + back * motion.visible_distance;
+
+    camera_transform.translation.y = camera_transform
+        .translation
+        .y
+        .max(map.get_height(camera_transform.translation) + 1.)
+}
+
+/// Distance from the target, along the direction to the camera, before the terrain gets in
+/// the way of the line of sight
+fn unoccluded_distance(map: &Map, target: Vec3, direction: Vec3, distance: f32) -> f32 {
+    let steps = (distance / OCCLUSION_STEP).ceil() as u32;
+    for i in 1..=steps {
+        let along = (i as f32 * OCCLUSION_STEP).min(distance);
+        let point = target + direction * along;
+        let clearance = OCCLUSION_CLEARANCE * along / distance;
+        if map.get_height(point) + clearance > point.y {
+            return along - OCCLUSION_STEP;
+        }
+    }
+    distance
+}
+
+/// Panning from the cursor near the border of the window, stronger closer to the border
+fn edge_pan(window: &Window, camera_settings: &CameraSettings) -> Vec3 {
+    let Some(cursor) = window.cursor_position().filter(|_| window.focused) else {
+        return Vec3::ZERO;
+    };
+    let zone = camera_settings.edge_pan_zone.max(1.);
+    // how far the cursor is in the zone of each border, from 0 to 1
+    let depth = |distance: f32| ((zone - distance) / zone).clamp(0., 1.);
+    let size = window.size();
+    let x = depth(size.x - cursor.x) - depth(cursor.x);
+    let z = depth(size.y - cursor.y) - depth(cursor.y);
+    Vec3::new(x, 0., z) * camera_settings.edge_pan_speed
+}
@@ -0,0 +1,213 @@
+use std::{borrow::Cow, sync::Arc};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext},
+    prelude::*,
+};
+use fluent_bundle::{FluentArgs, FluentResource, concurrent::FluentBundle};
+use unic_langid::LanguageIdentifier;
+
+use crate::pause_menu::Settings;
+
+/// Languages that can be chosen in the settings : identifier, and name in the language itself
+pub const LANGUAGES: [(&str, &str); 2] = [("en-US", "English"), ("fr", "Français")];
+/// Used for the keys missing from the chosen language. Embedded, so that it is there
+/// before the assets are loaded.
+const FALLBACK: &str = include_str!("../assets/locales/en-US.ftl");
+
+type Bundle = FluentBundle<Arc<FluentResource>>;
+
+/// Translation of the UI strings, with fluent files in `assets/locales`.
+/// Text spawned with a `LocalizedText` follows the language chosen in the settings.
+pub struct LocalizationPlugin;
+
+impl Plugin for LocalizationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Locale>()
+            .init_asset_loader::<LocaleLoader>()
+            .insert_resource(Localization::default())
+            .add_systems(
+                Update,
+                (
+                    select_language,
+                    build_bundle.after(select_language),
+                    update_localized_texts.after(build_bundle),
+                ),
+            );
+    }
+}
+
+/// A fluent file, translating the UI in one language
+#[derive(Asset, TypePath)]
+pub struct Locale(Arc<FluentResource>);
+
+#[derive(Default)]
+pub struct LocaleLoader;
+
+impl AssetLoader for LocaleLoader {
+    type Asset = Locale;
+
+    type Settings = ();
+
+    type Error = anyhow::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn bevy::asset::io::Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut text = String::new();
+        reader.read_to_string(&mut text).await?;
+        let resource = FluentResource::try_new(text)
+            .map_err(|(_, errors)| anyhow::anyhow!("Invalid locale file : {errors:?}"))?;
+        Ok(Locale(Arc::new(resource)))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ftl"]
+    }
+}
+
+fn new_bundle(language: &str, resource: Arc<FluentResource>) -> Bundle {
+    let id: LanguageIdentifier = language.parse().unwrap_or_default();
+    let mut bundle = FluentBundle::new_concurrent(vec![id]);
+    // the isolation marks around the arguments are not in the font
+    bundle.set_use_isolating(false);
+    if let Err(errors) = bundle.add_resource(resource) {
+        warn!("Conflicting keys in the {language} locale : {errors:?}");
+    }
+    bundle
+}
+
+/// The chosen language and its translations
+#[derive(Resource)]
+pub struct Localization {
+    language: String,
+    handle: Option<Handle<Locale>>,
+    bundle: Option<Bundle>,
+    fallback: Bundle,
+}
+
+impl Default for Localization {
+    fn default() -> Self {
+        let resource = FluentResource::try_new(FALLBACK.to_string())
+            .expect("The embedded english locale is invalid");
+        Self {
+            language: String::new(),
+            handle: None,
+            bundle: None,
+            fallback: new_bundle(LANGUAGES[0].0, Arc::new(resource)),
+        }
+    }
+}
+
+impl Localization {
+    /// The translation of `key`, or the key itself if no language has it
+    pub fn get(&self, key: &str) -> String {
+        self.format(key, None).unwrap_or_else(|| key.to_string())
+    }
+
+    /// The translation of `key`, if a language has it
+    pub fn try_get(&self, key: &str) -> Option<String> {
+        self.format(key, None)
+    }
+
+    pub fn get_args(&self, key: &str, args: &[(&str, String)]) -> String {
+        let mut fluent_args = FluentArgs::new();
+        for (name, value) in args {
+            fluent_args.set(*name, value.clone());
+        }
+        self.format(key, Some(&fluent_args))
+            .unwrap_or_else(|| key.to_string())
+    }
+
+    fn format(&self, key: &str, args: Option<&FluentArgs>) -> Option<String> {
+        for bundle in self.bundle.iter().chain(std::iter::once(&self.fallback)) {
+            let Some(pattern) = bundle.get_message(key).and_then(|m| m.value()) else {
+                continue;
+            };
+            let mut errors = Vec::new();
+            let text = bundle.format_pattern(pattern, args, &mut errors);
+            if !errors.is_empty() {
+                warn!("Errors formatting the text {key} : {errors:?}");
+            }
+            return Some(text.into_owned());
+        }
+        None
+    }
+}
+
+/// Text translated in the chosen language. Its `Text` is kept up to date.
+#[derive(Component, Clone, Debug)]
+#[require(Text)]
+pub struct LocalizedText {
+    pub key: Cow<'static, str>,
+    pub args: Vec<(&'static str, String)>,
+}
+
+impl LocalizedText {
+    pub fn new(key: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            key: key.into(),
+            args: Vec::new(),
+        }
+    }
+
+    pub fn with_arg(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.args.push((name, value.to_string()));
+        self
+    }
+}
+
+/// Load the locale of the language chosen in the settings
+fn select_language(
+    settings: Res<Settings>,
+    mut localization: ResMut<Localization>,
+    asset_server: Res<AssetServer>,
+) {
+    if localization.language == settings.language {
+        return;
+    }
+    localization.language = settings.language.clone();
+    localization.bundle = None;
+    localization.handle = Some(asset_server.load(format!("locales/{}.ftl", settings.language)));
+}
+
+/// Build the bundle of the chosen language once its file is loaded, and on every change
+fn build_bundle(
+    mut events: EventReader<AssetEvent<Locale>>,
+    locales: Res<Assets<Locale>>,
+    mut localization: ResMut<Localization>,
+) {
+    let Some(handle) = localization.handle.clone() else {
+        events.clear();
+        return;
+    };
+    for event in events.read() {
+        match event {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }
+                if *id == handle.id() =>
+            {
+                let Some(locale) = locales.get(*id) else {
+                    continue;
+                };
+                let bundle = new_bundle(&localization.language, locale.0.clone());
+                localization.bundle = Some(bundle);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn update_localized_texts(
+    localization: Res<Localization>,
+    mut texts: Query<(Ref<LocalizedText>, &mut Text)>,
+) {
+    for (localized, mut text) in &mut texts {
+        if !localization.is_changed() && !localized.is_changed() {
+            continue;
+        }
+        text.0 = localization.get_args(&localized.key, &localized.args);
+    }
+}
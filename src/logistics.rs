@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use rhai::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::GameId,
+    map::BuildingInstance,
+    roads::RoadGraph,
+    script_api::{SharedScriptWorld, run_scripts_with_world},
+    sim::{Sim, sim_running},
+    vehicles::DispatchVehicle,
+};
+
+/// Most goods carried by a shipment
+const SHIPMENT_CAPACITY: f64 = 10.;
+/// Distance the goods travel per sim tick, in world units
+const FREIGHT_SPEED: f32 = 2.;
+/// Ticks spent loading and unloading a shipment
+const HANDLING_TICKS: u64 = 5;
+/// Cost of going off the roads, as a multiplier of the straight distance
+const OFFROAD_FACTOR: f32 = 3.;
+/// Most goods waiting to be picked up at a building, by resource. Producers can't offer
+/// more until shipments take some away.
+const MAX_OUTGOING: f64 = 50.;
+
+/// Freight between the buildings. The building scripts `offer` the goods they produce and
+/// `request` the stock of goods they want to keep, and each tick the requests are matched
+/// with the nearest offers. The shipments take the time to travel the road distance, or a
+/// longer straight distance off the roads, and the delivered goods are used with `take`.
+/// When the deliveries fall behind, `take` gets less than asked and the outgoing goods pile
+/// up until `offer` refuses them, so the scripts stall their production.
+pub struct LogisticsPlugin;
+
+impl Plugin for LogisticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            match_freight
+                .after(run_scripts_with_world)
+                .run_if(sim_running),
+        );
+    }
+}
+
+/// Goods on their way between two buildings
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Shipment {
+    pub from: u64,
+    pub to: u64,
+    pub resource: String,
+    pub amount: f64,
+    pub arrival: u64,
+}
+
+/// Freight state shared with the scripts, by building game id and resource. Ordered maps,
+/// so that the matching doesn't depend on hashing and replays ship the same goods.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Freight {
+    /// Goods waiting to be picked up
+    pub outgoing: BTreeMap<(u64, String), f64>,
+    /// Stock of goods the buildings want to keep
+    pub requested: BTreeMap<(u64, String), f64>,
+    /// Goods delivered and not used yet
+    pub stock: BTreeMap<(u64, String), f64>,
+    pub shipments: Vec<Shipment>,
+}
+
+impl Freight {
+    fn in_transit(&self, to: u64, resource: &str) -> f64 {
+        self.shipments
+            .iter()
+            .filter(|s| s.to == to && s.resource == resource)
+            .map(|s| s.amount)
+            .sum()
+    }
+
+    /// Goods requested and neither delivered nor on their way, over every building
+    pub fn backlog(&self) -> f64 {
+        self.requested
+            .iter()
+            .map(|((id, resource), want)| {
+                let have = self.stock.get(&(*id, resource.clone())).unwrap_or(&0.);
+                (want - have - self.in_transit(*id, resource)).max(0.)
+            })
+            .sum()
+    }
+
+    /// Unload the shipments arrived by `tick`
+    fn deliver(&mut self, tick: u64) {
+        let (arrived, travelling): (Vec<_>, Vec<_>) = std::mem::take(&mut self.shipments)
+            .into_iter()
+            .partition(|s| s.arrival <= tick);
+        self.shipments = travelling;
+        for shipment in arrived {
+            *self
+                .stock
+                .entry((shipment.to, shipment.resource))
+                .or_default() += shipment.amount;
+        }
+    }
+
+    /// Ship goods to the requests from the nearest offers. `distance` is the travel
+    /// distance between two buildings, `None` if one of them is gone.
+    fn assign(
+        &mut self,
+        tick: u64,
+        mut distance: impl FnMut(u64, u64) -> Option<f32>,
+    ) -> Vec<Shipment> {
+        let mut new = Vec::new();
+        let requests: Vec<_> = self.requested.clone().into_iter().collect();
+        for ((to, resource), want) in requests {
+            let have = self
+                .stock
+                .get(&(to, resource.clone()))
+                .copied()
+                .unwrap_or(0.);
+            let mut missing = want - have - self.in_transit(to, &resource);
+            while missing > 0. {
+                let nearest = self
+                    .outgoing
+                    .iter()
+                    .filter(|((from, r), amount)| *r == resource && *from != to && **amount > 0.)
+                    .filter_map(|((from, _), _)| Some((*from, distance(*from, to)?)))
+                    .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2));
+                let Some((from, d)) = nearest else {
+                    break;
+                };
+                let outgoing = self.outgoing.get_mut(&(from, resource.clone())).unwrap();
+                let amount = missing.min(*outgoing).min(SHIPMENT_CAPACITY);
+                *outgoing -= amount;
+                missing -= amount;
+                let shipment = Shipment {
+                    from,
+                    to,
+                    resource: resource.clone(),
+                    amount,
+                    arrival: tick + HANDLING_TICKS + (d / FREIGHT_SPEED).ceil() as u64,
+                };
+                self.shipments.push(shipment.clone());
+                new.push(shipment);
+            }
+        }
+        new
+    }
+}
+
+/// Deliver the shipments arrived, then match the requests of this tick with the offers,
+/// sending a vehicle along the road for the shipments between connected buildings
+fn match_freight(
+    sim: Res<Sim>,
+    roads: Res<RoadGraph>,
+    buildings: Query<(Entity, &GameId, &BuildingInstance)>,
+    mut dispatches: EventWriter<DispatchVehicle>,
+) {
+    let placed: HashMap<u64, (Entity, Vec2)> = buildings
+        .iter()
+        .map(|(e, id, instance)| (id.0, (e, instance.center())))
+        .collect();
+    let distance = |from: u64, to: u64| {
+        let (&(a, a_pos), &(b, b_pos)) = (placed.get(&from)?, placed.get(&to)?);
+        let road = roads.path_length(a, b);
+        Some(road.unwrap_or(a_pos.distance(b_pos) * OFFROAD_FACTOR))
+    };
+    let mut world = sim.script_world.0.lock().unwrap();
+    let tick = world.tick;
+    world.freight.deliver(tick);
+    for shipment in world.freight.assign(tick, distance) {
+        let (Some(&(from, _)), Some(&(to, _))) =
+            (placed.get(&shipment.from), placed.get(&shipment.to))
+        else {
+            continue;
+        };
+        if roads.path_between(from, to).is_some() {
+            dispatches.write(DispatchVehicle { from, to });
+        }
+    }
+}
+
+/// Register the freight functions of the building scripts, taking the id of the building :
+/// `offer(id, resource, amount)`, returning the amount accepted, `request(id, resource,
+/// amount)`, setting the stock wanted, `take(id, resource, amount)`, returning the amount
+/// taken from the stock, `stock(id, resource)`, and `freight_backlog()`.
+pub fn register_freight_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn(
+        "offer",
+        move |id: i64, resource: &str, amount: f64| -> f64 {
+            let mut world = w.0.lock().unwrap();
+            let outgoing = world
+                .freight
+                .outgoing
+                .entry((id as u64, resource.to_string()))
+                .or_default();
+            let accepted = amount.clamp(0., (MAX_OUTGOING - *outgoing).max(0.));
+            *outgoing += accepted;
+            accepted
+        },
+    );
+
+    let w = world.clone();
+    engine.register_fn("request", move |id: i64, resource: &str, amount: f64| {
+        let mut world = w.0.lock().unwrap();
+        let key = (id as u64, resource.to_string());
+        if amount > 0. {
+            world.freight.requested.insert(key, amount);
+        } else {
+            world.freight.requested.remove(&key);
+        }
+    });
+
+    let w = world.clone();
+    engine.register_fn("take", move |id: i64, resource: &str, amount: f64| -> f64 {
+        let mut world = w.0.lock().unwrap();
+        let Some(stock) = world
+            .freight
+            .stock
+            .get_mut(&(id as u64, resource.to_string()))
+        else {
+            return 0.;
+        };
+        let taken = amount.clamp(0., *stock);
+        *stock -= taken;
+        taken
+    });
+
+    let w = world.clone();
+    engine.register_fn("stock", move |id: i64, resource: &str| -> f64 {
+        let world = w.0.lock().unwrap();
+        world
+            .freight
+            .stock
+            .get(&(id as u64, resource.to_string()))
+            .copied()
+            .unwrap_or(0.)
+    });
+
+    let w = world.clone();
+    engine.register_fn("freight_backlog", move || -> f64 {
+        w.0.lock().unwrap().freight.backlog()
+    });
+}
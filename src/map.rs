@@ -1,31 +1,121 @@
+use std::{
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
 use bevy::{
     asset::RenderAssetUsages,
-    math::{I64Vec2, NormedVectorSpace},
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    image::ImageSampler,
+    math::{Affine3A, I64Vec2, NormedVectorSpace},
     platform::collections::HashMap,
     prelude::*,
-    render::mesh::{Indices, PrimitiveTopology, VertexAttributeValues},
+    render::{
+        mesh::{Indices, PrimitiveTopology, VertexAttributeValues},
+        primitives::{Aabb, Frustum},
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+    tasks::{
+        AsyncComputeTaskPool, Task,
+        futures_lite::future::{block_on, poll_once},
+    },
 };
 use kdtree_collisions::{KdTree, KdValue};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{CameraTarget, build::Building, mapgen::Continent, shaders::MapMaterial};
-pub struct MapPlugin {
-    pub seed: u128,
-}
+use crate::{
+    build::Building,
+    mapgen::{Continent, DEFAULT_CHUNK_SIZE, WorldGen},
+    menu::GameState,
+    pause_menu::Settings,
+    shaders::MapMaterial,
+};
+
+/// The terrain. The continent is generated from the `WorldGen` resource when entering
+/// `GameState::Loading`, and the chunks in view of the camera are streamed in once in game.
+pub struct MapPlugin;
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(Map {
-            material: Handle::default(),
-            chunks: HashMap::new(),
-            entities: KdTree::default(),
-            continent: Continent::new_and_generate(self.seed as u32),
-        });
-        app.add_systems(Update, (spawn_chunk, display_rivers));
-        app.add_systems(Startup, setup_map);
+        app.init_resource::<WorldGen>();
+        app.register_diagnostic(Diagnostic::new(CHUNK_COUNT));
+        let mut kdtree_size = Diagnostic::new(KDTREE_SIZE);
+        // counting the tree is slow, it's only done while the diagnostics overlay is shown
+        kdtree_size.is_enabled = false;
+        app.register_diagnostic(kdtree_size);
+        app.add_systems(OnEnter(GameState::Loading), start_generation);
+        app.add_systems(
+            Update,
+            finish_generation.run_if(in_state(GameState::Loading)),
+        );
+        app.add_systems(OnEnter(GameState::InGame), setup_map);
+        app.add_systems(OnExit(GameState::InGame), unload_map);
+        app.add_systems(
+            Update,
+            (
+                stream_chunks,
+                display_rivers,
+                seed_map_material,
+                shade_map_material,
+                sync_chunk_materials
+                    .after(seed_map_material)
+                    .after(shade_map_material),
+                measure_map,
+            )
+                .run_if(in_state(GameState::InGame)),
+        );
     }
 }
 
+/// Number of chunks loaded
+pub const CHUNK_COUNT: DiagnosticPath = DiagnosticPath::const_new("map/chunks");
+/// Number of building instances in the kd-tree
+pub const KDTREE_SIZE: DiagnosticPath = DiagnosticPath::const_new("map/kdtree_size");
+
+fn measure_map(mut diagnostics: Diagnostics, map: Res<Map>) {
+    diagnostics.add_measurement(&CHUNK_COUNT, || map.chunks.len() as f64);
+    // the values of the tree intersecting the whole plane, so all of them
+    diagnostics.add_measurement(&KDTREE_SIZE, || {
+        map.entities
+            .query_rect(f32::MIN, f32::MAX, f32::MIN, f32::MAX)
+            .count() as f64
+    });
+}
+
+/// The continent being generated in the background
+#[derive(Resource)]
+struct Generating(Task<Map>);
+
+fn start_generation(mut commands: Commands, worldgen: Res<WorldGen>, settings: Res<Settings>) {
+    let worldgen = *worldgen;
+    let lazy = settings.low_memory_terrain;
+    info!("Generating the world {:?}", worldgen);
+    let task = AsyncComputeTaskPool::get().spawn(async move { Map::new(worldgen, lazy) });
+    commands.insert_resource(Generating(task));
+}
+
+/// The chunk and river entities are scoped to the game, only the map is left to drop
+fn unload_map(mut commands: Commands) {
+    commands.remove_resource::<Map>();
+}
+
+fn finish_generation(
+    mut commands: Commands,
+    mut generating: ResMut<Generating>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let Some(map) = block_on(poll_once(&mut generating.0)) else {
+        return;
+    };
+    commands.insert_resource(map);
+    commands.remove_resource::<Generating>();
+    next_state.set(GameState::InGame);
+}
+
 pub const GRID_SQUARE_SIZE: f32 = 0.5;
+/// Version of the world generation, to bump whenever it changes the generated terrain
+pub const WORLDGEN_VERSION: u32 = 1;
+const CHUNK_CACHE_DIR: &str = "cache/chunks";
 /// An instance of a specific building at a position
 /// Might contain other instance-specific stats in the future (damage, etc)
 #[derive(PartialEq, Clone, Component)]
@@ -36,6 +126,13 @@ pub struct BuildingInstance {
     pub entity: Entity,
 }
 
+impl BuildingInstance {
+    /// Center of the building footprint, in world coordinates.
+    pub fn center(&self) -> Vec2 {
+        self.pos + self.half_extents
+    }
+}
+
 impl KdValue for BuildingInstance {
     type Position = f32;
 
@@ -56,7 +153,7 @@ impl KdValue for BuildingInstance {
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PatchOp {
     Up,
     Down,
@@ -67,18 +164,40 @@ pub enum PatchOp {
 #[derive(Component)]
 pub struct ChunkMarker(pub I64Vec2);
 
+/// Heights of a chunk differing from the generated terrain, as (grid index, normalized height)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChunkEdits {
+    pub x: i64,
+    pub z: i64,
+    pub cells: Vec<(u32, f32)>,
+}
+
 /// A chunk, containing terrain data
 pub struct Chunk {
     grid: Vec<f32>,
     hydro: Vec<f32>,
     chunk_position: I64Vec2,
     cached_mesh: Option<Handle<Mesh>>,
+    /// Copy of the map material with the data textures of this chunk,
+    /// made once the map material is loaded
+    material: Option<Handle<MapMaterial>>,
     spawned: bool,
+    /// Heights of the cells of the neighbors just outside the borders, in the order of
+    /// `Chunk::apron_sides`, so that the normals on the borders match the neighbors
+    apron: [Vec<f32>; 4],
 }
 
+/// Number of grid cells along a side of a chunk. Set from the `WorldGen` when generating
+/// the map, as it is global to the world but needed where the map isn't at hand.
+static CHUNK_SIZE: AtomicU32 = AtomicU32::new(DEFAULT_CHUNK_SIZE);
+
+/// Momentum of the water giving half of the full flow speed in the flow map
+const HALF_FLOW_MOMENTUM: f32 = 0.01;
+
 impl Chunk {
-    pub const CHUNK_SIZE: u32 = 256;
-    pub const WORLD_CHUNK_SIZE: f32 = (Self::CHUNK_SIZE as f32 - 1.) * GRID_SQUARE_SIZE;
+    /// Chunk sizes allowed in the `WorldGen`, in cells. Smaller than the continent, which
+    /// the chunks are taken from.
+    pub const SIZE_RANGE: RangeInclusive<u32> = 16..=1024;
     pub const SCALE_Y: f32 = 100.;
 
     // fn get_noise(seed: u32) -> NoiseT {
@@ -168,33 +287,277 @@ impl Chunk {
     //     }
     // }
 
-    /// get a dummy terrain chunk for testing purpose
-    fn new_and_generate(pos: &I64Vec2, continent: &Continent) -> Self {
+    /// Number of grid cells along a side of a chunk
+    pub fn size() -> u32 {
+        CHUNK_SIZE.load(Ordering::Relaxed)
+    }
+
+    /// Length of a side of a chunk, in world units. The cells on the border of a chunk are
+    /// shared with its neighbors.
+    pub fn world_size() -> f32 {
+        (Self::size() as f32 - 1.) * GRID_SQUARE_SIZE
+    }
+
+    /// Number of grid cells of a chunk
+    pub fn cell_count() -> usize {
+        (Self::size() as usize).pow(2)
+    }
+
+    /// Set the chunk size of the world about to be generated, clamped to `SIZE_RANGE`
+    fn set_size(size: u32) {
+        let clamped = size.clamp(*Self::SIZE_RANGE.start(), *Self::SIZE_RANGE.end());
+        if clamped != size {
+            warn!("Chunk size {} out of range, using {}", size, clamped);
+        }
+        CHUNK_SIZE.store(clamped, Ordering::Relaxed);
+    }
+
+    /// Generate a chunk from the continent, without the disk cache
+    pub fn new_and_generate(pos: &I64Vec2, continent: &Continent) -> Self {
         let mut chunk = Self {
-            grid: Vec::with_capacity((Self::CHUNK_SIZE * Self::CHUNK_SIZE) as usize),
-            hydro: Vec::with_capacity((Self::CHUNK_SIZE * Self::CHUNK_SIZE) as usize),
+            grid: Vec::with_capacity((Self::size() * Self::size()) as usize),
+            hydro: Vec::with_capacity((Self::size() * Self::size()) as usize),
             chunk_position: pos.clone(),
             cached_mesh: None,
+            material: None,
             spawned: false,
+            apron: Default::default(),
         };
         chunk.generate(continent);
         chunk
     }
 
-    fn generate(&mut self, continent: &Continent) {
-        let world_pos = (self.chunk_position * (Self::CHUNK_SIZE as i64 - 1)
-            + Continent::CONTINENT_SIZE as i64 / 2)
+    /// Load the chunk from the disk cache, or generate it and cache it.
+    fn load_or_generate(pos: &I64Vec2, continent: &Continent, worldgen: &WorldGen) -> Self {
+        let _span = info_span!("chunk_load_or_generate").entered();
+        let path = Self::cache_path(worldgen, pos);
+        if let Some(mut chunk) = Self::load_cached(&path, pos) {
+            chunk.generate_apron(continent);
+            return chunk;
+        }
+        let chunk = Self::new_and_generate(pos, continent);
+        if let Err(e) = chunk.save_cache(&path) {
+            warn!("Couldn't cache chunk {} : {}", pos, e);
+        }
+        chunk
+    }
+
+    /// Path of the cached grids of a chunk. The worldgen version is part of the key,
+    /// so changing the generation invalidates the cache.
+    fn cache_path(worldgen: &WorldGen, pos: &I64Vec2) -> PathBuf {
+        PathBuf::from(CHUNK_CACHE_DIR).join(format!(
+            "{}_{}_{}_{}.chunk",
+            WORLDGEN_VERSION,
+            worldgen.key(),
+            pos.x,
+            pos.y
+        ))
+    }
+
+    fn load_cached(path: &Path, pos: &I64Vec2) -> Option<Self> {
+        let bytes = std::fs::read(path).ok()?;
+        let len = (Self::size() * Self::size()) as usize;
+        if bytes.len() != 2 * len * 4 {
+            return None;
+        }
+        let mut values = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        Some(Self {
+            grid: values.by_ref().take(len).collect(),
+            hydro: values.collect(),
+            chunk_position: *pos,
+            cached_mesh: None,
+            material: None,
+            spawned: false,
+            apron: Default::default(),
+        })
+    }
+
+    fn save_cache(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let bytes: Vec<u8> = self
+            .grid
+            .iter()
+            .chain(&self.hydro)
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        std::fs::write(path, bytes)
+    }
+
+    /// Position of the origin of the chunk at `pos` in the continent grid
+    fn continent_offset(pos: &I64Vec2) -> (u32, u32) {
+        let world_pos = (*pos * (Self::size() as i64 - 1) + Continent::CONTINENT_SIZE as i64 / 2)
             .abs()
-            % ((Continent::CONTINENT_SIZE - Self::CHUNK_SIZE) as i64);
+            % ((Continent::CONTINENT_SIZE - Self::size()) as i64);
+        (world_pos.x as u32, world_pos.y as u32)
+    }
+
+    fn generate(&mut self, continent: &Continent) {
+        let world_pos = Self::continent_offset(&self.chunk_position);
         self.grid.clear();
-        for x in 0..Self::CHUNK_SIZE {
-            for z in 0..Self::CHUNK_SIZE {
-                let pos = (x + world_pos.x as u32, z + world_pos.y as u32);
-                let sample: f32 = continent[pos].height;
+        self.hydro.clear();
+        for x in 0..Self::size() {
+            for z in 0..Self::size() {
+                let pos = (x + world_pos.0, z + world_pos.1);
+                let sample: f32 = continent.height(pos.0, pos.1);
                 self.grid.push(sample);
                 self.hydro.push(continent.get_hydro(pos.0, pos.1).amount);
             }
         }
+        self.generate_apron(continent);
+    }
+
+    /// Neighbors of a chunk, in the order of its apron, with the row of their cells next to
+    /// its border. The cells on the borders are shared, so it is their second row.
+    fn apron_sides() -> [(I64Vec2, u32); 4] {
+        let last = Self::size() - 2;
+        [
+            (I64Vec2::NEG_X, last),
+            (I64Vec2::X, 1),
+            (I64Vec2::NEG_Y, last),
+            (I64Vec2::Y, 1),
+        ]
+    }
+
+    /// Cell of a chunk in a row along its border with a neighbor at `offset`
+    fn apron_cell(offset: I64Vec2, row: u32, i: u32) -> (u32, u32) {
+        if offset.x != 0 { (row, i) } else { (i, row) }
+    }
+
+    /// Take the apron from the generated terrain of the neighbors
+    fn generate_apron(&mut self, continent: &Continent) {
+        let chunk_position = self.chunk_position;
+        self.apron = Self::apron_sides().map(|(offset, row)| {
+            let (offset_x, offset_z) = Self::continent_offset(&(chunk_position + offset));
+            (0..Self::size())
+                .map(|i| {
+                    let (x, z) = Self::apron_cell(offset, row, i);
+                    continent.height(x + offset_x, z + offset_z)
+                })
+                .collect()
+        });
+    }
+
+    /// Height of a cell, normalized, looking into the apron for the cells just outside
+    fn height_or_apron(&self, x: i32, z: i32) -> f32 {
+        let size = Self::size() as i32;
+        match (x, z) {
+            (-1, z) => self.apron[0][z as usize],
+            (x, z) if x == size => self.apron[1][z as usize],
+            (x, -1) => self.apron[2][x as usize],
+            (x, z) if z == size => self.apron[3][x as usize],
+            (x, z) => self.grid[Self::get_index(x, z)],
+        }
+    }
+
+    /// Normals of the vertices of the mesh, from the slopes between the cells around them.
+    /// The apron gives the slopes on the borders, so that they match the neighbors.
+    fn normals(&self) -> Vec<[f32; 3]> {
+        let size = Self::size() as i32;
+        let mut normals = Vec::with_capacity(Self::cell_count());
+        for x in 0..size {
+            for z in 0..size {
+                let dx = self.height_or_apron(x + 1, z) - self.height_or_apron(x - 1, z);
+                let dz = self.height_or_apron(x, z + 1) - self.height_or_apron(x, z - 1);
+                let normal = Vec3::new(
+                    -dx * Self::SCALE_Y,
+                    2. * GRID_SQUARE_SIZE,
+                    -dz * Self::SCALE_Y,
+                );
+                normals.push(normal.normalize().to_array());
+            }
+        }
+        normals
+    }
+
+    /// Flow of the water on the cells of the chunk, for the terrain shader :
+    /// rg is its direction and b its speed. Rows go along z.
+    fn make_flow_map(&self, continent: &Continent) -> Image {
+        let (offset_x, offset_z) = Self::continent_offset(&self.chunk_position);
+        let to_byte = |v: f32| (v.clamp(0., 1.) * 255.).round() as u8;
+        let mut data = Vec::with_capacity((Self::size().pow(2) * 4) as usize);
+        for z in 0..Self::size() {
+            for x in 0..Self::size() {
+                let momentum = continent.get_hydro(x + offset_x, z + offset_z).momentum;
+                let direction = momentum.normalize_or_zero() * 0.5 + 0.5;
+                let speed = momentum.length() / (momentum.length() + HALF_FLOW_MOMENTUM);
+                data.extend([to_byte(direction.x), to_byte(direction.y), to_byte(speed), 255]);
+            }
+        }
+        let mut image = Image::new(
+            Extent3d {
+                width: Self::size(),
+                height: Self::size(),
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            data,
+            TextureFormat::Rgba8Unorm,
+            RenderAssetUsages::RENDER_WORLD,
+        );
+        image.sampler = ImageSampler::linear();
+        image
+    }
+
+    /// Chunks and cells of their data textures at a world position. Cells on the border of
+    /// a chunk are shared with its neighbors, and are returned for all of them.
+    pub fn cells_at(pos: Vec2) -> impl Iterator<Item = (I64Vec2, usize)> {
+        let chunk = (pos / Self::world_size()).floor().as_i64vec2();
+        [I64Vec2::ZERO, I64Vec2::X, I64Vec2::Y, I64Vec2::ONE]
+            .into_iter()
+            .filter_map(move |offset| {
+                let chunk = chunk - offset;
+                let origin = chunk.as_vec2() * Self::world_size();
+                let cell = ((pos - origin) / GRID_SQUARE_SIZE).round();
+                let size = Self::size() as f32;
+                (cell.x >= 0. && cell.y >= 0. && cell.x < size && cell.y < size)
+                    .then(|| (chunk, Self::get_index(cell.y as i32, cell.x as i32)))
+            })
+    }
+
+    /// Single channel data texture for the cells of a chunk, filled with 0.
+    /// Kept in the main world to be updated by the game.
+    pub fn blank_data_texture() -> Image {
+        let mut image = Image::new(
+            Extent3d {
+                width: Self::size(),
+                height: Self::size(),
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![0; Self::size().pow(2) as usize],
+            TextureFormat::R8Unorm,
+            RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+        );
+        image.sampler = ImageSampler::linear();
+        image
+    }
+
+    /// Height of the terrain at a grid cell of the chunk, in world units.
+    pub fn cell_height(&self, x: u32, z: u32) -> f32 {
+        self.grid[Chunk::get_index(x as i32, z as i32)] * Self::SCALE_Y
+    }
+
+    /// Heights of the grid of the chunk, normalized
+    pub fn heights(&self) -> &[f32] {
+        &self.grid
+    }
+
+    /// Bounds of any chunk, from its origin. Its heights aren't known before generating it,
+    /// so they are generous.
+    pub fn bounds() -> Aabb {
+        Aabb::from_min_max(
+            Vec3::new(0., -0.5 * Self::SCALE_Y, 0.),
+            Vec3::new(Self::world_size(), 1.5 * Self::SCALE_Y, Self::world_size()),
+        )
+    }
+
+    /// Whether the chunk has been spawned in the world
+    pub fn is_spawned(&self) -> bool {
+        self.spawned
     }
 
     /// Get the in-world position of the origin of the chunk.
@@ -203,19 +566,20 @@ impl Chunk {
             self.chunk_position.x as f32,
             0.,
             self.chunk_position.y as f32,
-        ) * Self::WORLD_CHUNK_SIZE
+        ) * Self::world_size()
     }
 
     /// Generates the mesh for a chunk.
     // TODO: a way to regenerate mesh on terrain change
-    fn make_mesh(&self) -> Mesh {
-        let mut vertex_positions = Vec::with_capacity(Self::CHUNK_SIZE.pow(2) as usize);
-        let mut uv = Vec::with_capacity(Self::CHUNK_SIZE.pow(2) as usize);
-        let mut indices = Vec::with_capacity(((Self::CHUNK_SIZE - 1).pow(2) * 6) as usize);
+    pub fn make_mesh(&self) -> Mesh {
+        let _span = info_span!("chunk_mesh").entered();
+        let mut vertex_positions = Vec::with_capacity(Self::size().pow(2) as usize);
+        let mut uv = Vec::with_capacity(Self::size().pow(2) as usize);
+        let mut indices = Vec::with_capacity(((Self::size() - 1).pow(2) * 6) as usize);
         let offset = 0.;
         for (i, sq) in self.grid.iter().enumerate() {
-            let x = GRID_SQUARE_SIZE * (i as u32 / Self::CHUNK_SIZE) as f32;
-            let z = GRID_SQUARE_SIZE * (i as u32 % Self::CHUNK_SIZE) as f32;
+            let x = GRID_SQUARE_SIZE * (i as u32 / Self::size()) as f32;
+            let z = GRID_SQUARE_SIZE * (i as u32 % Self::size()) as f32;
             vertex_positions.push([x + offset, sq * Self::SCALE_Y, z + offset]);
             let uv_x = 1.3 * (*sq) - 0.35;
             let uv_y = self.hydro[i];
@@ -223,11 +587,10 @@ impl Chunk {
             uv.push([uv_x, uv_y]);
         }
         //println!("");
-        for x in 1..Self::CHUNK_SIZE as u16 {
-            for z in 1..Self::CHUNK_SIZE as u16 {
-                fn id(x: u16, z: u16) -> u16 {
-                    z + x * Chunk::CHUNK_SIZE as u16
-                }
+        let size = Self::size();
+        let id = |x: u32, z: u32| z + x * size;
+        for x in 1..size {
+            for z in 1..size {
                 //top top left triangle
                 indices.extend(&[id(x, z), id(x, z - 1), id(x - 1, z - 1)]);
                 //top left left triangle
@@ -241,8 +604,8 @@ impl Chunk {
         )
         .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, vertex_positions)
         .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uv)
-        .with_inserted_indices(Indices::U16(indices))
-        .with_computed_smooth_normals()
+        .with_inserted_indices(Indices::U32(indices))
+        .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals())
     }
 
     /// Get a handle to the mesh of the chunk, generating it on the fly if necessary.
@@ -262,7 +625,7 @@ impl Chunk {
     }
 
     pub fn get_index(x: i32, y: i32) -> usize {
-        x as usize * Chunk::CHUNK_SIZE as usize + y as usize
+        x as usize * Chunk::size() as usize + y as usize
     }
     pub fn patch(
         &mut self,
@@ -270,6 +633,7 @@ impl Chunk {
         pos: &Vec3,
         radius: f32,
         operation: PatchOp,
+        strength: f32,
     ) -> Vec<(i64, i64)> {
         let mesh = self.get_mesh_mut(meshes);
 
@@ -301,7 +665,7 @@ impl Chunk {
                 if x_min <= 0 && y_min <= 0 {
                     ret.push((-1, -1));
                 }
-                if x_max >= Self::CHUNK_SIZE as i32 - 1 && y_max >= Self::CHUNK_SIZE as i32 - 1 {
+                if x_max >= Self::size() as i32 - 1 && y_max >= Self::size() as i32 - 1 {
                     ret.push((1, 1));
                 }
                 if x_min <= 0 {
@@ -312,13 +676,13 @@ impl Chunk {
                     ret.push((0, -1));
                     y_min = 0;
                 }
-                if x_max >= Self::CHUNK_SIZE as i32 - 1 {
+                if x_max >= Self::size() as i32 - 1 {
                     ret.push((1, 0));
-                    x_max = Self::CHUNK_SIZE as i32 - 1;
+                    x_max = Self::size() as i32 - 1;
                 }
-                if y_max >= Self::CHUNK_SIZE as i32 - 1 {
+                if y_max >= Self::size() as i32 - 1 {
                     ret.push((0, 1));
-                    y_max = Self::CHUNK_SIZE as i32 - 1;
+                    y_max = Self::size() as i32 - 1;
                 }
 
                 match operation {
@@ -333,7 +697,8 @@ impl Chunk {
                                 let dist = (local_pos - Vec2::new(x as f32, y as f32)).norm();
                                 if dist <= radius {
                                     let index = Chunk::get_index(x, y);
-                                    let delta = 0.1 * (1. - (dist / radius).powi(4)) * sign;
+                                    let delta =
+                                        0.1 * strength * (1. - (dist / radius).powi(4)) * sign;
                                     vertex[index][1] += delta * Self::SCALE_Y;
                                     self.grid[index] += delta;
                                     uvs[index][0] += delta;
@@ -346,9 +711,9 @@ impl Chunk {
                             for y in y_min..=y_max {
                                 let dist = (local_pos - Vec2::new(x as f32, y as f32)).norm();
                                 if dist <= radius {
-                                    let index =
-                                        x as usize * Chunk::CHUNK_SIZE as usize + y as usize;
-                                    let ratio = (dist / radius).powi(6);
+                                    let index = x as usize * Chunk::size() as usize + y as usize;
+                                    let ratio =
+                                        1. - (1. - (dist / radius).powi(6)) * strength.min(1.);
                                     let height = ratio * vertex[index][1] + (1. - ratio) * pos.y;
                                     vertex[index][1] = height;
                                     self.grid[index] = height / Self::SCALE_Y;
@@ -357,11 +722,39 @@ impl Chunk {
                             }
                         }
                     }
-                    PatchOp::Smooth => todo!(),
+                    PatchOp::Smooth => {
+                        // averaged from the heights before the patch, so that the order of the
+                        // points doesn't matter
+                        let before = self.grid.clone();
+                        let last = Self::size() as i32 - 1;
+                        for x in x_min..=x_max {
+                            for y in y_min..=y_max {
+                                let dist = (local_pos - Vec2::new(x as f32, y as f32)).norm();
+                                if dist <= radius {
+                                    let mut sum = 0.;
+                                    let mut count = 0.;
+                                    for nx in (x - 1).max(0)..=(x + 1).min(last) {
+                                        for ny in (y - 1).max(0)..=(y + 1).min(last) {
+                                            sum += before[Chunk::get_index(nx, ny)];
+                                            count += 1.;
+                                        }
+                                    }
+                                    let index = Chunk::get_index(x, y);
+                                    let ratio =
+                                        (1. - (dist / radius).powi(4)) * strength.min(1.);
+                                    let height =
+                                        before[index] + (sum / count - before[index]) * ratio;
+                                    vertex[index][1] = height * Self::SCALE_Y;
+                                    self.grid[index] = height;
+                                    uvs[index][0] = height;
+                                }
+                            }
+                        }
+                    }
                 }
             }
         }
-        mesh.compute_smooth_normals();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, self.normals());
         ret
     }
 }
@@ -369,6 +762,7 @@ impl Chunk {
 /// The whole map. Contains chunks, and a kd-tree of building instances in the map.
 #[derive(Resource)]
 pub struct Map {
+    pub worldgen: WorldGen,
     material: Handle<MapMaterial>,
     pub chunks: HashMap<I64Vec2, Chunk>,
     pub entities: KdTree<BuildingInstance, 10>,
@@ -376,18 +770,190 @@ pub struct Map {
 }
 
 impl Map {
+    /// Generate the continent. Slow, so better done in the background.
+    /// `lazy` samples the heights when needed, see `Continent::new_and_generate`.
+    pub fn new(worldgen: WorldGen, lazy: bool) -> Self {
+        Chunk::set_size(worldgen.chunk_size);
+        Self {
+            worldgen,
+            material: Handle::default(),
+            chunks: HashMap::new(),
+            entities: KdTree::default(),
+            continent: Continent::new_and_generate(&worldgen, lazy),
+        }
+    }
+
+    /// Material shared by every chunk of the terrain
+    pub fn material(&self) -> &Handle<MapMaterial> {
+        &self.material
+    }
+
     /// Get a mutable reference to a chunk (and make/ load it if it doesnt already exists)
     pub fn get_chunk_mut<'a>(&'a mut self, pos: &I64Vec2) -> &'a mut Chunk {
         //Apparently it's the best way to insert an element if it doesnt already exists, and get a mut ref to the result.
         self.chunks
             .raw_entry_mut()
             .from_key(pos)
-            .or_insert_with(|| (pos.clone(), Chunk::new_and_generate(pos, &self.continent)))
+            .or_insert_with(|| {
+                (
+                    pos.clone(),
+                    Chunk::load_or_generate(pos, &self.continent, &self.worldgen),
+                )
+            })
             .1
     }
 
+    /// Regenerate the terrain of every chunk from the continent, dropping all the edits.
+    pub fn reset_terrain(&mut self, meshes: &mut Assets<Mesh>) {
+        for chunk in self.chunks.values_mut() {
+            chunk.generate(&self.continent);
+            if let Some(mesh) = chunk.cached_mesh.as_ref().and_then(|h| meshes.get_mut(h)) {
+                *mesh = chunk.make_mesh();
+            }
+        }
+    }
+
+    /// Heights of every chunk edited since it was generated
+    pub fn terrain_edits(&self) -> Vec<ChunkEdits> {
+        let mut edits: Vec<ChunkEdits> = self
+            .chunks
+            .iter()
+            .filter_map(|(pos, chunk)| {
+                let generated = Chunk::new_and_generate(pos, &self.continent);
+                let cells: Vec<(u32, f32)> = chunk
+                    .grid
+                    .iter()
+                    .zip(&generated.grid)
+                    .enumerate()
+                    .filter(|(_, (height, generated))| height != generated)
+                    .map(|(i, (height, _))| (i as u32, *height))
+                    .collect();
+                (!cells.is_empty()).then_some(ChunkEdits {
+                    x: pos.x,
+                    z: pos.y,
+                    cells,
+                })
+            })
+            .collect();
+        edits.sort_by_key(|edits| (edits.x, edits.z));
+        edits
+    }
+
+    /// Put edited heights back on the generated terrain, generating the chunks if needed.
+    pub fn apply_terrain_edits(&mut self, meshes: &mut Assets<Mesh>, edits: &[ChunkEdits]) {
+        for edits in edits {
+            let chunk = self.get_chunk_mut(&I64Vec2::new(edits.x, edits.z));
+            for &(cell, height) in &edits.cells {
+                if let Some(h) = chunk.grid.get_mut(cell as usize) {
+                    *h = height;
+                }
+            }
+            if let Some(mesh) = chunk.cached_mesh.as_ref().and_then(|h| meshes.get_mut(h)) {
+                *mesh = chunk.make_mesh();
+            }
+        }
+        for edits in edits {
+            self.sync_aprons_around(meshes, I64Vec2::new(edits.x, edits.z));
+        }
+    }
+
+    /// Apply a terrain operation around `pos`, on every chunk it overlaps.
+    pub fn patch(&mut self, meshes: &mut Assets<Mesh>, pos: &Vec3, radius: f32, op: PatchOp) {
+        self.patch_with_strength(meshes, pos, radius, op, 1.);
+    }
+
+    /// Apply a terrain operation, scaling its effect by `strength`.
+    pub fn patch_with_strength(
+        &mut self,
+        meshes: &mut Assets<Mesh>,
+        pos: &Vec3,
+        radius: f32,
+        op: PatchOp,
+        strength: f32,
+    ) {
+        let _span = info_span!("terrain_patch").entered();
+        let chunk_pos_x = (pos.x / Chunk::world_size()).floor() as i64;
+        let chunk_pos_z = (pos.z / Chunk::world_size()).floor() as i64;
+        let chunk = self.get_chunk_mut(&(chunk_pos_x, chunk_pos_z).into());
+        //TODO too convoluted here. Make separate chunk intersect detection.
+        let add_patches = chunk.patch(meshes, pos, radius, op, strength);
+        for &(off_x, off_z) in &add_patches {
+            let chunk = self.get_chunk_mut(&(chunk_pos_x + off_x, chunk_pos_z + off_z).into());
+            chunk.patch(meshes, pos, radius, op, strength);
+        }
+        for (off_x, off_z) in add_patches.into_iter().chain([(0, 0)]) {
+            self.sync_aprons_around(
+                meshes,
+                I64Vec2::new(chunk_pos_x + off_x, chunk_pos_z + off_z),
+            );
+        }
+    }
+
+    /// Take the apron of the chunk at `pos` from its neighbors, where they exist, and update
+    /// the normals of its mesh if it changed.
+    fn sync_apron(&mut self, meshes: &mut Assets<Mesh>, pos: I64Vec2) {
+        let Some(chunk) = self.chunks.get(&pos) else {
+            return;
+        };
+        let mut apron = chunk.apron.clone();
+        for (side, (offset, row)) in Chunk::apron_sides().into_iter().enumerate() {
+            let Some(neighbor) = self.chunks.get(&(pos + offset)) else {
+                continue;
+            };
+            apron[side] = (0..Chunk::size())
+                .map(|i| {
+                    let (x, z) = Chunk::apron_cell(offset, row, i);
+                    neighbor.grid[Chunk::get_index(x as i32, z as i32)]
+                })
+                .collect();
+        }
+        let Some(chunk) = self.chunks.get_mut(&pos) else {
+            return;
+        };
+        if chunk.apron == apron {
+            return;
+        }
+        chunk.apron = apron;
+        if let Some(mesh) = chunk.cached_mesh.as_ref().and_then(|h| meshes.get_mut(h)) {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, chunk.normals());
+        }
+    }
+
+    /// Sync the aprons of the chunk at `pos` and of its neighbors, after it changed
+    pub fn sync_aprons_around(&mut self, meshes: &mut Assets<Mesh>, pos: I64Vec2) {
+        self.sync_apron(meshes, pos);
+        for (offset, _) in Chunk::apron_sides() {
+            self.sync_apron(meshes, pos + offset);
+        }
+    }
+
+    /// Whether no building intersects the given (min, max) rectangle
+    pub fn is_area_free(&self, (min, max): (Vec2, Vec2)) -> bool {
+        let _span = info_span!("kdtree_query").entered();
+        self.entities
+            .query_rect(min.x, max.x, min.y, max.y)
+            .next()
+            .is_none()
+    }
+
+    /// Iterate over the building instances whose footprint center is within `radius` of `center`.
+    pub fn buildings_in_radius(
+        &self,
+        center: Vec2,
+        radius: f32,
+    ) -> impl Iterator<Item = &BuildingInstance> {
+        self.entities
+            .query_rect(
+                center.x - radius,
+                center.x + radius,
+                center.y - radius,
+                center.y + radius,
+            )
+            .filter(move |b| b.center().distance(center) <= radius)
+    }
+
     pub fn get_height(&self, pos: Vec3) -> f32 {
-        let chunk_pos = (pos / Chunk::WORLD_CHUNK_SIZE).floor();
+        let chunk_pos = (pos / Chunk::world_size()).floor();
         let chunk_pos = I64Vec2::new(chunk_pos.x as i64, chunk_pos.z as i64);
         let chunk = self.chunks.get(&chunk_pos);
         if let Some(chunk) = chunk {
@@ -447,6 +1013,114 @@ pub fn display_rivers(map: ResMut<Map>, mut gizmos: Gizmos) {
     }
 }
 
+/// Switch the stylized shading of the terrain material with the settings. Checked every frame,
+/// to apply it again after the material is reloaded.
+pub fn shade_map_material(
+    settings: Res<Settings>,
+    map: Res<Map>,
+    mut materials: ResMut<Assets<MapMaterial>>,
+) {
+    let id = map.material.id();
+    let on = if settings.toon { 1. } else { 0. };
+    // only touch the material when needed, as it is sent again to the gpu
+    let applied = materials
+        .get(id)
+        .is_none_or(|mat| mat.extension.toon.x == on);
+    if applied {
+        return;
+    }
+    if let Some(mat) = materials.get_mut(id) {
+        mat.extension.toon.x = on;
+    }
+}
+
+/// Give the world seed to the terrain material, so that the macro variation differs between worlds,
+/// along with the chunk size of the world.
+/// The material file is hot reloaded : the chunks share its handle, so they all pick up the
+/// new version, and it gets the seed again.
+pub fn seed_map_material(
+    mut events: EventReader<AssetEvent<MapMaterial>>,
+    mut materials: ResMut<Assets<MapMaterial>>,
+    map: Res<Map>,
+) {
+    let mut ids: Vec<_> = events
+        .read()
+        .filter_map(|ev| match ev {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+    // the material may have been loaded for a previous world
+    if map.is_added() {
+        ids.push(map.material.id());
+    }
+    // keep the seed small so it stays precise as a f32 in the shader
+    let seed = (map.worldgen.seed % 1024) as f32;
+    let chunk = Vec4::new(Chunk::world_size(), Chunk::size() as f32, 0., 0.);
+    for id in ids {
+        // setting the seed is a modification too, only do it when needed to not loop
+        let seeded = materials.get(id).is_none_or(|mat| {
+            mat.extension.macro_variation.w == seed && mat.extension.chunk == chunk
+        });
+        if seeded {
+            continue;
+        }
+        if let Some(mat) = materials.get_mut(id) {
+            mat.extension.macro_variation.w = seed;
+            mat.extension.chunk = chunk;
+        }
+    }
+}
+
+/// Give every chunk its own copy of the map material, with the data textures of the chunk.
+/// The copies follow the changes of the map material : hot reload, seed and overlays.
+pub fn sync_chunk_materials(
+    mut events: EventReader<AssetEvent<MapMaterial>>,
+    mut materials: ResMut<Assets<MapMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    mut map: ResMut<Map>,
+    mut chunks: Query<(&IsGround, &mut MeshMaterial3d<MapMaterial>)>,
+) {
+    let template_id = map.material.id();
+    let changed = events.read().any(|ev| match ev {
+        AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => {
+            *id == template_id
+        }
+        _ => false,
+    });
+    let Some(template) = materials.get(template_id).cloned() else {
+        return;
+    };
+    let map = &mut *map;
+    for (IsGround(pos), mut material) in &mut chunks {
+        let Some(chunk) = map.chunks.get_mut(pos) else {
+            continue;
+        };
+        match &chunk.material {
+            Some(handle) if material.0 == *handle => {
+                if !changed {
+                    continue;
+                }
+                if let Some(mat) = materials.get_mut(handle) {
+                    let mut updated = template.clone();
+                    updated.extension.keep_chunk_data(&mat.extension);
+                    *mat = updated;
+                }
+            }
+            Some(handle) => material.0 = handle.clone(),
+            None => {
+                let mut mat = template.clone();
+                mat.extension.flow_map = Some(images.add(chunk.make_flow_map(&map.continent)));
+                mat.extension.heatmap = Some(images.add(Chunk::blank_data_texture()));
+                mat.extension.explored = Some(images.add(Chunk::blank_data_texture()));
+                let handle = materials.add(mat);
+                chunk.material = Some(handle.clone());
+                material.0 = handle;
+            }
+        }
+    }
+}
+
 pub fn setup_map(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
@@ -475,10 +1149,12 @@ pub fn setup_map(
             }
             commands.spawn((
                 Name::new("River"),
+                WaterSurface,
                 Mesh3d(rmesh.get_handle(&mut *meshes)),
                 MeshMaterial3d(rivermat.clone()),
                 Transform::from_translation(origin.clone()),
-                aabb.clone()
+                aabb.clone(),
+                StateScoped(GameState::InGame),
             ));
         }
     }
@@ -493,80 +1169,96 @@ pub fn setup_map(
         ),
         MeshMaterial3d(bottomplanemat),
         Transform::from_xyz(0., 0., 0.),
+        StateScoped(GameState::InGame),
     ));
 }
 #[derive(Component)]
 pub struct IsGround(pub I64Vec2);
 
-/// Handles the spawning of chunks when the camera is close enough. (Currently only spawns the chunk the camera is on)
-pub fn spawn_chunk(
+/// Surface of water drawn over the terrain : rivers, canals, reservoirs and floods
+#[derive(Component)]
+pub struct WaterSurface;
+
+/// Distance from the camera up to which the chunks are streamed in, in world units
+const STREAM_DISTANCE: f32 = 600.;
+/// Distance from the camera beyond which the chunks are despawned, further than
+/// `STREAM_DISTANCE` so that the chunks at the limit aren't spawned and despawned in turn
+const EVICT_DISTANCE: f32 = 800.;
+/// Chunks spawned per frame at most, as generating one is slow
+const CHUNKS_PER_FRAME: usize = 2;
+
+/// Spawn the chunks in the view of the camera, up to `STREAM_DISTANCE`. The chunks covering
+/// the most of the screen come first, a few per frame. The chunks further than
+/// `EVICT_DISTANCE` are despawned, and their mesh and material freed. Their heights stay in the
+/// map.
+pub fn stream_chunks(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<MapMaterial>>,
     mut map: ResMut<Map>,
-    camera: Query<&CameraTarget, (With<Camera>, Changed<CameraTarget>)>,
-) -> Result {
-    let camera_transform = camera.single()?;
-    let camera_chunk_pos = camera_transform.pos / Chunk::WORLD_CHUNK_SIZE;
+    camera: Single<(&GlobalTransform, &Frustum), With<Camera3d>>,
+    spawned: Query<(Entity, &IsGround)>,
+) {
+    let (camera_transform, frustum) = *camera;
+    let eye = camera_transform.translation();
+    let bounds = Chunk::bounds();
+    let radius = bounds.half_extents.length();
+    for (entity, IsGround(chunk_pos)) in &spawned {
+        let origin = Vec3::new(chunk_pos.x as f32, 0., chunk_pos.y as f32) * Chunk::world_size();
+        if eye.distance(origin + Vec3::from(bounds.center)) - radius <= EVICT_DISTANCE {
+            continue;
+        }
+        commands.entity(entity).despawn();
+        if let Some(chunk) = map.chunks.get_mut(chunk_pos) {
+            chunk.spawned = false;
+            if let Some(mesh) = chunk.cached_mesh.take() {
+                meshes.remove(&mesh);
+            }
+            // made again when it is spawned, with its data textures
+            if let Some(material) = chunk.material.take() {
+                materials.remove(&material);
+            }
+        }
+    }
+    let reach = (STREAM_DISTANCE / Chunk::world_size()).ceil() as i64 + 1;
+    let camera_chunk_pos = (eye.xz() / Chunk::world_size()).floor().as_i64vec2();
+    let mut candidates = Vec::new();
+    for x in -reach..=reach {
+        for z in -reach..=reach {
+            let chunk_pos = camera_chunk_pos + I64Vec2::new(x, z);
+            if map.chunks.get(&chunk_pos).is_some_and(Chunk::is_spawned) {
+                continue;
+            }
+            let origin =
+                Vec3::new(chunk_pos.x as f32, 0., chunk_pos.y as f32) * Chunk::world_size();
+            let distance = eye.distance(origin + Vec3::from(bounds.center));
+            if distance - radius > STREAM_DISTANCE
+                || !frustum.intersects_obb(&bounds, &Affine3A::from_translation(origin), true, true)
+            {
+                continue;
+            }
+            // share of the view taken by the bounding sphere of the chunk, up to a constant
+            let coverage = (radius / distance.max(radius)).powi(2);
+            candidates.push((coverage, chunk_pos));
+        }
+    }
+    candidates.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
     let mat = map.material.clone();
-    for (x, z) in [-2., -1., 0., 1.]
-        .into_iter()
-        .map(|x| [-2., -1., 0., 1.].into_iter().map(move |z| (x, z)))
-        .flatten()
-    {
-        let chunk_pos = I64Vec2::new(
-            (camera_chunk_pos.x + x) as i64,
-            (camera_chunk_pos.z + z) as i64,
-        );
+    for (_, chunk_pos) in candidates.into_iter().take(CHUNKS_PER_FRAME) {
+        map.get_chunk_mut(&chunk_pos);
+        // the neighbors may have been edited since they were generated
+        map.sync_aprons_around(&mut meshes, chunk_pos);
         let chunk = map.get_chunk_mut(&chunk_pos);
-        if !chunk.spawned {
-            chunk.spawned = true;
-            let mesh = chunk.get_mesh(&mut *meshes);
-            let mut entity = commands.spawn((
-                Name::new(format!("chunk {} {}", chunk_pos.x, chunk_pos.y)),
-                Mesh3d(mesh),
-                MeshMaterial3d(mat.clone()),
-                Transform::from_translation(chunk.get_world_pos()),
-                IsGround(chunk_pos),
-            ));
-
-            // for build in map.entities.query_rect(
-            //     chunk_pos.x,
-            //     chunk_pos.x + Chunk::CHUNK_SIZE as i64,
-            //     chunk_pos.y,
-            //     chunk_pos.y + Chunk::CHUNK_SIZE as i64,
-            // ) {
-            //     let pos = Vec3::new(
-            //         (build.grid_pos.x - chunk_pos.x) as f32 * GRID_SQUARE_SIZE,
-            //         0.,
-            //         (build.grid_pos.y - chunk_pos.y) as f32 * GRID_SQUARE_SIZE,
-            //     );
-            //     match &build.building.typ {
-            //         BuildingType::Single { model } => {
-            //             entity.with_child((
-            //                 Mesh3d(model.mesh.clone()),
-            //                 MeshMaterial3d(build.building.material.clone()),
-            //                 Transform::from_translation(pos),
-            //             ));
-            //         }
-            //         BuildingType::Zone { color } => {
-            //             entity.with_child((
-            //                 // TODO : mesh for zone
-            //                 Wireframe,
-            //                 WireframeColor {
-            //                     color: color.clone(),
-            //                 },
-            //                 Transform::from_translation(pos).with_scale(Vec3::new(
-            //                     build.size.x as f32 * GRID_SQUARE_SIZE,
-            //                     0.1,
-            //                     build.size.y as f32 * GRID_SQUARE_SIZE,
-            //                 )),
-            //             ));
-            //         }
-            //         _ => {}
-            //     };
-            // }
-        }
-    }
-
-    Ok(())
+        chunk.spawned = true;
+        let mesh = chunk.get_mesh(&mut *meshes);
+        commands.spawn((
+            Name::new(format!("chunk {} {}", chunk_pos.x, chunk_pos.y)),
+            Mesh3d(mesh),
+            MeshMaterial3d(mat.clone()),
+            Transform::from_translation(chunk.get_world_pos()),
+            IsGround(chunk_pos),
+            StateScoped(GameState::InGame),
+        ));
+    }
 }
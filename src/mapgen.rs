@@ -1,8 +1,9 @@
 use bevy::{
-    asset::{Assets, Handle, RenderAssetUsages}, ecs::system::ResMut, log::{info, warn}, math::{
+    asset::{Assets, Handle, RenderAssetUsages}, ecs::{resource::Resource, system::ResMut}, log::{info, info_span, warn}, math::{
         cubic_splines::{CubicGenerator, CubicHermite, LinearSpline}, curve::CurveExt, NormedVectorSpace, Vec2, Vec3, Vec3Swizzles
     }, platform::collections::{HashMap, HashSet}, render::{mesh::{Indices, Mesh, MeshAabb, PrimitiveTopology}, primitives::Aabb}
 };
+use clap::ValueEnum;
 use fast_hilbert;
 use kdtree_collisions::{KdTree, KdValue};
 use noiz::{
@@ -21,12 +22,13 @@ use noiz::{
 };
 use rand::SeedableRng;
 use rand_distr::{Distribution, num_traits::Float};
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, BTreeSet},
     f32::consts::PI,
-    ops::{Index, IndexMut},
 };
 
+use crate::heightmaps::heightmap_points;
 use crate::map::{Chunk, GRID_SQUARE_SIZE};
 
 type NoiseT = Noise<(
@@ -77,6 +79,7 @@ type FlatnessNoiseT = (
     Offset<(Constant<f32>, WithGradientOf<Vec2>)>,
     Scaled<f32>,
 );
+#[derive(Clone)]
 pub struct TerrainPoint {
     pub height: f32,
     pub wetness: f32,
@@ -122,16 +125,127 @@ impl MeshOrHandle {
     }
 }
 
+/// How much of the continent grid the land can cover
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum WorldSize {
+    Small,
+    Medium,
+    #[default]
+    Large,
+}
+
+impl WorldSize {
+    pub const ALL: [Self; 3] = [Self::Small, Self::Medium, Self::Large];
+
+    /// Radius of the land, as a fraction of the half size of the continent
+    fn land_radius(self) -> f32 {
+        match self {
+            Self::Small => 0.5,
+            Self::Medium => 0.75,
+            Self::Large => 1.,
+        }
+    }
+}
+
+/// Tuning of the terrain noise
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum WorldPreset {
+    #[default]
+    Continent,
+    /// Smaller and lower landmasses, with more sea between them
+    Archipelago,
+    /// Wide and high landmasses
+    Highlands,
+}
+
+impl WorldPreset {
+    pub const ALL: [Self; 3] = [Self::Continent, Self::Archipelago, Self::Highlands];
+
+    fn frequency(self) -> f32 {
+        match self {
+            Self::Continent => 0.04,
+            Self::Archipelago => 0.06,
+            Self::Highlands => 0.03,
+        }
+    }
+
+    fn height_scale(self) -> f32 {
+        match self {
+            Self::Continent => 1.,
+            Self::Archipelago => 0.92,
+            Self::Highlands => 1.15,
+        }
+    }
+}
+
+/// Number of grid cells along a side of a chunk, unless the `WorldGen` says otherwise
+pub const DEFAULT_CHUNK_SIZE: u32 = 256;
+
+/// Everything the generated terrain depends on
+#[derive(Resource, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WorldGen {
+    pub seed: u32,
+    pub size: WorldSize,
+    pub preset: WorldPreset,
+    /// Number of grid cells along a side of a chunk, within `Chunk::SIZE_RANGE`. The chunks
+    /// are laid out on the continent by their size, so it changes the terrain too.
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: u32,
+    /// Id of the imported heightmap the terrain comes from, in place of the noise
+    #[serde(default)]
+    pub heightmap: Option<u64>,
+}
+
+impl Default for WorldGen {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            size: WorldSize::default(),
+            preset: WorldPreset::default(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            heightmap: None,
+        }
+    }
+}
+
+fn default_chunk_size() -> u32 {
+    DEFAULT_CHUNK_SIZE
+}
+
+impl WorldGen {
+    /// Identifies the generated terrain, e.g. in cache file names
+    pub fn key(&self) -> String {
+        let key = format!(
+            "{}_{:?}_{:?}_{}",
+            self.seed, self.size, self.preset, self.chunk_size
+        );
+        match self.heightmap {
+            Some(id) => format!("{key}_{id:016x}"),
+            None => key,
+        }
+    }
+}
+
+/// How much the terrain is lowered under the rivers, normalized
+const RIVER_BED_DEPTH: f32 = 0.001;
+
 pub struct Continent {
+    /// Heights of the whole continent grid. Dropped once generated when the heights are
+    /// sampled on demand.
     points: Vec<TerrainPoint>,
     hydrology: Vec<Hydrologypoint>,
     height_noise: NoiseT,
     offset: Vec2,
+    worldgen: WorldGen,
+    /// Points of the grid lowered under the rivers, kept when the heights are sampled
+    river_beds: HashSet<usize>,
     pub river_paths: Vec<(CubicHermite<Vec3>, LinearSpline<Vec2>)>,
     pub river_meshes: Vec<(Vec3, Option<Aabb>, MeshOrHandle)>,
     pub lakes: Vec<usize>,
     pub to_sea: BTreeMap<usize, usize>,
     pub to_lake: BTreeMap<usize, usize>,
+    /// Generated amounts of water of the points changed since, by the dams
+    changed_amounts: BTreeMap<usize, f32>,
 }
 
 impl Continent {
@@ -140,7 +254,11 @@ impl Continent {
     pub const OCEAN_HEIGHT_LIMIT: f32 = 0.534;
     const TILES_PER_POINT: u32 = 30;
 
-    pub fn new_and_generate(seed: u32) -> Self {
+    /// Generate the continent. When `lazy`, the heights are sampled again from the noise
+    /// when needed, rather than kept for the whole grid : slower, but a lot lighter. The
+    /// heights of imported heightmaps are always kept.
+    pub fn new_and_generate(worldgen: &WorldGen, lazy: bool) -> Self {
+        let lazy = lazy && worldgen.heightmap.is_none();
         let mut new = Self {
             points: Vec::with_capacity(1 << (2 * Self::CONTINENT_SIZE_PO2)),
             hydrology: vec![
@@ -150,19 +268,32 @@ impl Continent {
                 };
                 1 << (2 * Self::CONTINENT_SIZE_PO2)
             ],
-            height_noise: Self::get_noise(seed),
+            height_noise: Self::get_noise(worldgen.seed, worldgen.preset.frequency()),
             offset: Vec2::new(0., 0.),
+            worldgen: *worldgen,
+            river_beds: HashSet::default(),
             river_paths: Vec::default(),
             river_meshes: Vec::default(),
             lakes: Vec::default(),
             to_sea: BTreeMap::default(),
             to_lake: BTreeMap::default(),
+            changed_amounts: BTreeMap::default(),
         };
         new.generate();
+        if lazy {
+            new.points = Vec::new();
+        } else {
+            new.river_beds = HashSet::default();
+        }
         new
     }
 
-    fn get_noise(seed: u32) -> NoiseT {
+    /// Whether the heights are sampled when needed rather than stored
+    pub fn is_lazy(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    fn get_noise(seed: u32, frequency: f32) -> NoiseT {
         Noise {
             noise: (
                 LayeredNoise::new(
@@ -228,32 +359,76 @@ impl Continent {
                 SNormToUNorm::default(),
             ),
             seed: NoiseRng(seed),
-            frequency: 0.04,
+            frequency,
         }
     }
 
     fn generate(&mut self) {
+        let span = info_span!("continent_heights").entered();
+        if let Some(id) = self.worldgen.heightmap {
+            match heightmap_points(id) {
+                Ok(points) => {
+                    self.points = points;
+                    drop(span);
+                    self.make_hydrology_map();
+                    return;
+                }
+                Err(e) => warn!("Couldn't load the heightmap {id:016x}, using the noise : {e}"),
+            }
+        }
         for i in 0..(1 << (Self::CONTINENT_SIZE_PO2 * 2)) {
-            let pos: (u32, u32) = fast_hilbert::h2xy(i, Self::CONTINENT_SIZE_PO2);
-            let offset = (1 << (Self::CONTINENT_SIZE_PO2 - 1)) as f32;
-            let edge_mult = 1.
-                - ((Vec2::new(pos.0 as f32, pos.1 as f32) - offset).abs() / offset)
-                    .powf(8.)
-                    .norm();
-            let pos = self.offset + Vec2::new(pos.0 as f32, pos.1 as f32) * GRID_SQUARE_SIZE;
-            let sample: WithGradient<f32, Vec2> = self.height_noise.sample(pos);
-            self.points.push(TerrainPoint {
-                height: sample.value * edge_mult,
-                wetness: 1.,
-                grad: -sample.gradient,
-            })
+            let point = self.sample_point(fast_hilbert::h2xy(i, Self::CONTINENT_SIZE_PO2));
+            self.points.push(point);
         }
+        drop(span);
         self.make_hydrology_map();
     }
+
+    /// Height and slope of the terrain at a point of the grid, from the noise
+    fn sample_point(&self, pos: (u32, u32)) -> TerrainPoint {
+        let radius = self.worldgen.size.land_radius();
+        let offset = (1 << (Self::CONTINENT_SIZE_PO2 - 1)) as f32;
+        // past the land radius, the sea floor is as deep as the corners of a large world
+        let edge_mult = (1.
+            - ((Vec2::new(pos.0 as f32, pos.1 as f32) - offset).abs() / (offset * radius))
+                .powf(8.)
+                .norm())
+        .max(1. - std::f32::consts::SQRT_2);
+        let pos = self.offset + Vec2::new(pos.0 as f32, pos.1 as f32) * GRID_SQUARE_SIZE;
+        let sample: WithGradient<f32, Vec2> = self.height_noise.sample(pos);
+        TerrainPoint {
+            height: sample.value * edge_mult * self.worldgen.preset.height_scale(),
+            wetness: 1.,
+            grad: -sample.gradient,
+        }
+    }
+
+    /// Point of the grid, stored or sampled
+    pub fn point(&self, x: u32, y: u32) -> TerrainPoint {
+        let h = Self::xy2h(x, y);
+        if let Some(point) = self.points.get(h) {
+            return point.clone();
+        }
+        let mut point = self.sample_point((x, y));
+        if self.river_beds.contains(&h) {
+            point.height -= RIVER_BED_DEPTH;
+        }
+        point
+    }
+
+    /// Height of a point of the grid, normalized
+    pub fn height(&self, x: u32, y: u32) -> f32 {
+        match self.points.get(Self::xy2h(x, y)) {
+            Some(point) => point.height,
+            None => self.point(x, y).height,
+        }
+    }
     //handle everything river and lake related
     fn make_hydrology_map(&mut self) {
+        let _span = info_span!("hydrology").entered();
         const HEIGHT_THRESHOLD: f32 = 0.05;
         //get sources
+        let span = info_span!("hydrology_flow").entered();
         for x in 1u32..((1 << Self::CONTINENT_SIZE_PO2) - 1) {
             for y in 1..((1 << Self::CONTINENT_SIZE_PO2) - 1) {
                 let id = Self::xy2h(x, y);
@@ -286,6 +461,7 @@ impl Continent {
                 }
             }
         }
+        drop(span);
         info!("Generating map...");
         info!("Finding sources");
         //find sources
@@ -302,6 +478,7 @@ impl Continent {
         const SEP_SLOPE_ANGLE: f32 = PI / 2.;
         let mut chosen_sources: BTreeSet<usize> = BTreeSet::default();
         let mut tree: KdTree<U32Value, 10> = KdTree::default();
+        let span = info_span!("river_sources").entered();
         for s in sources {
             let (x, y): (u32, u32) = fast_hilbert::h2xy(s as u64, Self::CONTINENT_SIZE_PO2);
 
@@ -326,14 +503,17 @@ impl Continent {
 
             //dbg!("plop");
         }
+        drop(span);
         let mut forks = BTreeMap::default();
         let mut to_sea = BTreeMap::default();
         let mut to_lake = BTreeMap::default();
         info!("Generate river paths");
         //make paths
+        let span = info_span!("river_paths").entered();
         for s in chosen_sources.iter() {
             self.go_through_path(*s, &mut estuaries, &mut forks, &mut to_sea, &mut to_lake);
         }
+        drop(span);
         self.lakes = forks
             .iter()
             .filter_map(|(s1, s2)| {
@@ -346,9 +526,11 @@ impl Continent {
             .collect();
         info!("Propagate water");
         //Reverse order for amounts
+        let span = info_span!("river_amounts").entered();
         for s in chosen_sources.iter().rev() {
             self.propagate_amount(*s);
         }
+        drop(span);
 
         info!("Group estuaries");
         let estuary_groups = self.make_estuary_groups(estuaries, &forks);
@@ -368,6 +550,7 @@ impl Continent {
 
     //patch the terrain and create meshes for rivers
     fn patch_for_rivers(&mut self) {
+        let _span = info_span!("patch_for_rivers").entered();
 
         const RANGE_DIVIDE: f32 = 20.;
         let mut in_river = HashSet::new();
@@ -443,9 +626,10 @@ impl Continent {
             self.river_meshes.push((spos, aabb, MeshOrHandle::new(mesh)));
 
         }
-        for h in in_river {
-            self.points[h].height -= 0.001;
+        for &h in &in_river {
+            self.points[h].height -= RIVER_BED_DEPTH;
         }
+        self.river_beds = in_river;
     }
     //gets the height of a point in the continent
     pub fn get_height(&self, pos: Vec3) -> f32 {
@@ -458,10 +642,10 @@ impl Continent {
 
         let floor = xy.floor();
         let fract = xy.fract();
-        let h00 = self.points[Self::xy2h(floor.x as u32, floor.y as u32)].height;
-        let h01 = self.points[Self::xy2h(floor.x as u32, floor.y as u32 + 1)].height;
-        let h10 = self.points[Self::xy2h(floor.x as u32 + 1, floor.y as u32)].height;
-        let h11 = self.points[Self::xy2h(floor.x as u32 + 1, floor.y as u32 + 1)].height;
+        let h00 = self.height(floor.x as u32, floor.y as u32);
+        let h01 = self.height(floor.x as u32, floor.y as u32 + 1);
+        let h10 = self.height(floor.x as u32 + 1, floor.y as u32);
+        let h11 = self.height(floor.x as u32 + 1, floor.y as u32 + 1);
         (h00 * (1. - fract.x) * (1. - fract.y)
             + h01 * (1. - fract.x) * fract.y
             + h10 * fract.x * (1. - fract.y)
@@ -470,6 +654,7 @@ impl Continent {
     }
     //Creates the curves for rivers
     fn make_curves(&mut self, sources: &BTreeSet<usize>) {
+        let _span = info_span!("make_curves").entered();
         let dist = rand_distr::Normal::new(0., 0.5).unwrap();
         let mut rng = rand::rngs::StdRng::seed_from_u64(self.height_noise.seed.0 as u64);
 
@@ -557,12 +742,12 @@ impl Continent {
     //Convert an index to world point
     pub fn to_world(&self, p: usize) -> Vec3 {
         let (x, y) = Self::h2xy(p);
+        let h = self.height(x, y) * Chunk::SCALE_Y + 1.;
         let (x, y) = (
             x as i32 - Self::CONTINENT_SIZE as i32 / 2,
             y as i32 - Self::CONTINENT_SIZE as i32 / 2,
         );
         let (x, y) = (x as f32 * GRID_SQUARE_SIZE, y as f32 * GRID_SQUARE_SIZE);
-        let h = self.points[p].height * Chunk::SCALE_Y + 1.;
         Vec3::new(x, h, y)
     }
     //Convert world point to index
@@ -584,6 +769,7 @@ impl Continent {
         forks: &mut BTreeMap<usize, usize>,
         sources: &mut BTreeSet<usize>,
     ) {
+        let _span = info_span!("fork_estuaries").entered();
         const RIVER_UNMERGE_RADIUS: f32 = 25.;
 
         for (main, others) in estuary_groups {
@@ -652,6 +838,7 @@ impl Continent {
         estuaries: Vec<(u32, u32)>,
         forks: &BTreeMap<usize, usize>,
     ) -> BTreeMap<(u32, u32), Vec<(u32, u32)>> {
+        let _span = info_span!("make_estuary_groups").entered();
         //make groups of estuaries
         const ESTUARY_MERGE_RADIUS: u32 = 20;
         let mut estuary_groups: BTreeMap<(u32, u32), Vec<(u32, u32)>> = BTreeMap::default();
@@ -796,10 +983,46 @@ impl Continent {
         estuaries.push((x, y));
     }
 
+    /// World positions of the mouths of the rivers into the sea
+    pub fn estuaries(&self) -> impl Iterator<Item = Vec3> + '_ {
+        self.to_sea
+            .values()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|&p| self.to_world(p))
+    }
+
+    /// Whether the continent is under the sea at a world position
+    pub fn is_sea(&self, pos: Vec3) -> bool {
+        let (x, y) = self.from_world(&pos);
+        self.height(x, y) <= Self::OCEAN_HEIGHT_LIMIT
+    }
+
+    /// Next point down the river through a point of the grid, `None` off the rivers
+    pub fn downstream(&self, x: u32, y: u32) -> Option<(u32, u32)> {
+        let next = self.get_hydro(x, y).next;
+        (next != 0).then(|| Self::h2xy(next))
+    }
+
     pub fn get_hydro(&self, x: u32, y: u32) -> &Hydrologypoint {
         let id: u64 = fast_hilbert::xy2h(x, y, Self::CONTINENT_SIZE_PO2);
         &self.hydrology[id as usize]
     }
+
+    /// Change the amount of water through a point of the grid, keeping the generated one
+    pub fn set_amount(&mut self, x: u32, y: u32, amount: f32) {
+        let id = Self::xy2h(x, y);
+        let generated = self.hydrology[id].amount;
+        self.changed_amounts.entry(id).or_insert(generated);
+        self.hydrology[id].amount = amount;
+    }
+
+    /// Restore the generated amounts of water changed with `set_amount`
+    pub fn reset_amounts(&mut self) {
+        for (id, amount) in std::mem::take(&mut self.changed_amounts) {
+            self.hydrology[id].amount = amount;
+        }
+    }
 }
 
 #[derive(Clone, Default, PartialEq)]
@@ -829,18 +1052,3 @@ impl KdValue for U32Value {
         (self.y + self.he).min(Continent::CONTINENT_SIZE)
     }
 }
-
-impl Index<(u32, u32)> for Continent {
-    type Output = TerrainPoint;
-
-    fn index(&self, index: (u32, u32)) -> &Self::Output {
-        &self.points[fast_hilbert::xy2h::<u32>(index.0, index.1, Self::CONTINENT_SIZE_PO2) as usize]
-    }
-}
-
-impl IndexMut<(u32, u32)> for Continent {
-    fn index_mut(&mut self, index: (u32, u32)) -> &mut Self::Output {
-        &mut self.points
-            [fast_hilbert::xy2h::<u32>(index.0, index.1, Self::CONTINENT_SIZE_PO2) as usize]
-    }
-}
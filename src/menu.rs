@@ -0,0 +1,356 @@
+use bevy::{
+    input::keyboard::{Key, KeyboardInput},
+    prelude::*,
+};
+
+use crate::{
+    editor::EditorState,
+    localization::LocalizedText,
+    mapgen::{WorldGen, WorldPreset, WorldSize},
+    ui::{FontHandle, TextFocus},
+};
+
+const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+const SELECTED_BUTTON: Color = Color::srgb(0.35, 0.75, 0.35);
+
+/// Top level state of the app. The world only exists from `InGame` on.
+#[derive(States, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum GameState {
+    #[default]
+    MainMenu,
+    /// The world is being generated
+    Loading,
+    InGame,
+}
+
+/// Page of the main menu being shown
+#[derive(SubStates, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[source(GameState = GameState::MainMenu)]
+enum MenuPage {
+    #[default]
+    Main,
+    NewGame,
+}
+
+/// Main menu, and the new game screen choosing the `WorldGen` of the world to generate
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<GameState>()
+            .add_sub_state::<MenuPage>()
+            .enable_state_scoped_entities::<GameState>()
+            .enable_state_scoped_entities::<MenuPage>()
+            .add_systems(OnEnter(MenuPage::Main), setup_main_page)
+            .add_systems(OnEnter(MenuPage::NewGame), setup_new_game_page)
+            .add_systems(OnEnter(GameState::Loading), setup_loading_screen)
+            .add_systems(
+                Update,
+                (
+                    menu_buttons,
+                    focus_seed,
+                    edit_seed.after(focus_seed),
+                    update_new_game_page.after(menu_buttons).after(edit_seed),
+                )
+                    .run_if(in_state(GameState::MainMenu)),
+            );
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum MenuButton {
+    NewGame,
+    Quit,
+    Back,
+    Start,
+    /// Generate the world to edit it
+    Editor,
+    RandomSeed,
+    Size(WorldSize),
+    Preset(WorldPreset),
+}
+
+impl MenuButton {
+    fn label(self) -> LocalizedText {
+        let key = match self {
+            MenuButton::NewGame => "menu-new-game",
+            MenuButton::Quit => "menu-quit",
+            MenuButton::Back => "menu-back",
+            MenuButton::Start => "menu-start",
+            MenuButton::Editor => "menu-editor",
+            MenuButton::RandomSeed => "menu-random-seed",
+            MenuButton::Size(WorldSize::Small) => "world-size-small",
+            MenuButton::Size(WorldSize::Medium) => "world-size-medium",
+            MenuButton::Size(WorldSize::Large) => "world-size-large",
+            MenuButton::Preset(WorldPreset::Continent) => "world-preset-continent",
+            MenuButton::Preset(WorldPreset::Archipelago) => "world-preset-archipelago",
+            MenuButton::Preset(WorldPreset::Highlands) => "world-preset-highlands",
+        };
+        LocalizedText::new(key)
+    }
+}
+
+/// Text box of the seed, focused by clicking it
+#[derive(Component)]
+struct SeedBox;
+
+/// Full screen background of the menu pages, covering the HUD
+fn menu_root(name: &'static str) -> impl Bundle {
+    (
+        Name::new(name),
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            row_gap: Val::Px(10.),
+            ..default()
+        },
+        BackgroundColor(Color::srgb(0.05, 0.06, 0.08)),
+        GlobalZIndex(30),
+    )
+}
+
+fn spawn_button(parent: &mut ChildSpawnerCommands, button: MenuButton, text_font: &TextFont) {
+    parent.spawn((
+        Button,
+        Node {
+            padding: UiRect::all(Val::Px(8.)),
+            min_width: Val::Px(120.),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        BackgroundColor(NORMAL_BUTTON),
+        button,
+        children![(button.label(), text_font.clone(), Label, Pickable::IGNORE)],
+    ));
+}
+
+fn setup_main_page(mut commands: Commands, font: Res<FontHandle>) {
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 20.,
+        ..default()
+    };
+    commands
+        .spawn((menu_root("main menu"), StateScoped(MenuPage::Main)))
+        .with_children(|parent| {
+            parent.spawn((
+                LocalizedText::new("menu-title"),
+                TextFont {
+                    font_size: 48.,
+                    ..text_font.clone()
+                },
+                Label,
+            ));
+            spawn_button(parent, MenuButton::NewGame, &text_font);
+            spawn_button(parent, MenuButton::Quit, &text_font);
+        });
+}
+
+fn setup_new_game_page(
+    mut commands: Commands,
+    font: Res<FontHandle>,
+    mut worldgen: ResMut<WorldGen>,
+) {
+    worldgen.seed = rand::random();
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 20.,
+        ..default()
+    };
+    let row = Node {
+        column_gap: Val::Px(6.),
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    commands
+        .spawn((menu_root("new game"), StateScoped(MenuPage::NewGame)))
+        .with_children(|parent| {
+            parent.spawn((
+                LocalizedText::new("menu-new-game"),
+                TextFont {
+                    font_size: 36.,
+                    ..text_font.clone()
+                },
+                Label,
+            ));
+            parent.spawn(row.clone()).with_children(|parent| {
+                parent.spawn((LocalizedText::new("menu-seed"), text_font.clone(), Label));
+                parent.spawn((
+                    Button,
+                    Node {
+                        padding: UiRect::all(Val::Px(8.)),
+                        min_width: Val::Px(160.),
+                        ..default()
+                    },
+                    BackgroundColor(Color::BLACK),
+                    SeedBox,
+                    children![(Text::default(), text_font.clone(), Label, Pickable::IGNORE)],
+                ));
+                spawn_button(parent, MenuButton::RandomSeed, &text_font);
+            });
+            parent.spawn(row.clone()).with_children(|parent| {
+                parent.spawn((
+                    LocalizedText::new("menu-world-size"),
+                    text_font.clone(),
+                    Label,
+                ));
+                for size in WorldSize::ALL {
+                    spawn_button(parent, MenuButton::Size(size), &text_font);
+                }
+            });
+            parent.spawn(row.clone()).with_children(|parent| {
+                parent.spawn((LocalizedText::new("menu-terrain"), text_font.clone(), Label));
+                for preset in WorldPreset::ALL {
+                    spawn_button(parent, MenuButton::Preset(preset), &text_font);
+                }
+            });
+            parent.spawn(row).with_children(|parent| {
+                spawn_button(parent, MenuButton::Back, &text_font);
+                spawn_button(parent, MenuButton::Editor, &text_font);
+                spawn_button(parent, MenuButton::Start, &text_font);
+            });
+        });
+}
+
+fn setup_loading_screen(mut commands: Commands, font: Res<FontHandle>) {
+    commands
+        .spawn((menu_root("loading screen"), StateScoped(GameState::Loading)))
+        .with_children(|parent| {
+            parent.spawn((
+                LocalizedText::new("menu-generating"),
+                TextFont {
+                    font: font.0.clone(),
+                    font_size: 28.,
+                    ..default()
+                },
+                Label,
+            ));
+        });
+}
+
+fn menu_buttons(
+    buttons: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut worldgen: ResMut<WorldGen>,
+    mut next_page: ResMut<NextState<MenuPage>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut next_editor: ResMut<NextState<EditorState>>,
+    mut text_focus: ResMut<TextFocus>,
+    seed_box: Option<Single<Entity, With<SeedBox>>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let Some((_, button)) = buttons
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Pressed)
+    else {
+        return;
+    };
+    match *button {
+        MenuButton::NewGame => next_page.set(MenuPage::NewGame),
+        MenuButton::Quit => {
+            exit.write(AppExit::Success);
+        }
+        MenuButton::Back => next_page.set(MenuPage::Main),
+        MenuButton::Start => next_state.set(GameState::Loading),
+        MenuButton::Editor => {
+            next_editor.set(EditorState::On);
+            next_state.set(GameState::Loading);
+        }
+        MenuButton::RandomSeed => worldgen.seed = rand::random(),
+        MenuButton::Size(size) => worldgen.size = size,
+        MenuButton::Preset(preset) => worldgen.preset = preset,
+    }
+    // the seed box may be despawned with its page
+    if seed_box.is_some_and(|e| text_focus.0 == Some(*e)) {
+        text_focus.0 = None;
+    }
+}
+
+fn focus_seed(
+    seed_box: Single<(Entity, &Interaction), (Changed<Interaction>, With<SeedBox>)>,
+    mut text_focus: ResMut<TextFocus>,
+) {
+    let (entity, interaction) = *seed_box;
+    if *interaction == Interaction::Pressed && text_focus.0.is_none() {
+        text_focus.0 = Some(entity);
+    }
+}
+
+/// Type the seed. Only digits are accepted, Enter or Escape stop the edition.
+fn edit_seed(
+    mut events: EventReader<KeyboardInput>,
+    mut text_focus: ResMut<TextFocus>,
+    mut worldgen: ResMut<WorldGen>,
+    seed_box: Option<Single<Entity, With<SeedBox>>>,
+) {
+    let focused = seed_box.is_some_and(|e| text_focus.0 == Some(*e));
+    if !focused || text_focus.is_changed() {
+        events.clear();
+        return;
+    }
+    for ev in events.read() {
+        if !ev.state.is_pressed() {
+            continue;
+        }
+        match &ev.logical_key {
+            Key::Character(c) => {
+                if let Some(digit) = c.chars().next().and_then(|c| c.to_digit(10)) {
+                    if let Some(seed) = worldgen
+                        .seed
+                        .checked_mul(10)
+                        .and_then(|s| s.checked_add(digit))
+                    {
+                        worldgen.seed = seed;
+                    }
+                }
+            }
+            Key::Backspace => worldgen.seed /= 10,
+            Key::Enter | Key::Escape => {
+                text_focus.0 = None;
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Show the seed, and highlight the chosen options
+fn update_new_game_page(
+    worldgen: Res<WorldGen>,
+    text_focus: Res<TextFocus>,
+    seed_box: Option<Single<(Entity, &Children, Ref<SeedBox>)>>,
+    mut texts: Query<&mut Text>,
+    mut buttons: Query<(&MenuButton, &mut BackgroundColor)>,
+) {
+    let Some(seed_box) = seed_box else {
+        return;
+    };
+    let (entity, children, seed_ref) = seed_box.into_inner();
+    if !worldgen.is_changed() && !text_focus.is_changed() && !seed_ref.is_added() {
+        return;
+    }
+    let mut seed = worldgen.seed.to_string();
+    if text_focus.0 == Some(entity) {
+        seed.push('_');
+    }
+    for child in children {
+        if let Ok(mut text) = texts.get_mut(*child) {
+            text.0 = seed.clone();
+        }
+    }
+    for (button, mut color) in &mut buttons {
+        let selected = match *button {
+            MenuButton::Size(size) => size == worldgen.size,
+            MenuButton::Preset(preset) => preset == worldgen.preset,
+            _ => false,
+        };
+        color.0 = if selected {
+            SELECTED_BUTTON
+        } else {
+            NORMAL_BUTTON
+        };
+    }
+}
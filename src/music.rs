@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use bevy::{asset::LoadedFolder, audio::Volume, prelude::*};
+use rand::seq::SliceRandom;
+
+use crate::{
+    audio::SoundCategory, build::BuildingPlaced, clock::GameClock, menu::GameState,
+    pause_menu::Settings,
+};
+
+/// Time for a track to fade in or out, in seconds
+const CROSSFADE: f32 = 3.;
+/// Buildings placed within `SPREE_WINDOW` making a building spree
+const SPREE_BUILDINGS: usize = 3;
+/// Time a placed building counts towards a spree
+const SPREE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Music from `assets/music` : the tracks of `music/calm` play in the menus, at night and
+/// while not much is built, the ones of `music/active` during building sprees. The tracks
+/// of a playlist are shuffled, and the playlists crossfade when the mood changes.
+pub struct MusicPlugin;
+
+impl Plugin for MusicPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Spree::default())
+            .add_systems(Startup, load_music)
+            .add_systems(
+                Update,
+                (
+                    collect_tracks,
+                    count_spree,
+                    choose_mood.after(count_spree),
+                    play_music.after(collect_tracks).after(choose_mood),
+                    fade_music.after(play_music),
+                ),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Mood {
+    #[default]
+    Calm,
+    Active,
+}
+
+/// A shuffled playlist
+#[derive(Default)]
+struct Playlist {
+    tracks: Vec<Handle<AudioSource>>,
+    /// Indices of the tracks left to play before shuffling again
+    queue: Vec<usize>,
+}
+
+impl Playlist {
+    fn next(&mut self) -> Option<Handle<AudioSource>> {
+        if self.queue.is_empty() {
+            self.queue = (0..self.tracks.len()).collect();
+            self.queue.shuffle(&mut rand::rng());
+        }
+        let i = self.queue.pop()?;
+        Some(self.tracks[i].clone())
+    }
+}
+
+#[derive(Resource)]
+struct Music {
+    folder: Handle<LoadedFolder>,
+    calm: Playlist,
+    active: Playlist,
+    mood: Mood,
+}
+
+impl Music {
+    fn playlist(&mut self, mood: Mood) -> &mut Playlist {
+        match mood {
+            Mood::Calm => &mut self.calm,
+            Mood::Active => &mut self.active,
+        }
+    }
+}
+
+/// When the last buildings were placed
+#[derive(Resource, Default)]
+struct Spree(Vec<Duration>);
+
+/// A playing track, fading in while its mood is the current one, and out after
+#[derive(Component)]
+struct MusicTrack {
+    mood: Mood,
+    /// Loudness from the crossfade, between 0 and 1
+    fade: f32,
+}
+
+fn load_music(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(Music {
+        folder: asset_server.load_folder("music"),
+        calm: default(),
+        active: default(),
+        mood: default(),
+    });
+}
+
+/// Sort the tracks of the music folder into the playlists, again when it changes
+fn collect_tracks(
+    mut events: EventReader<AssetEvent<LoadedFolder>>,
+    folders: Res<Assets<LoadedFolder>>,
+    mut music: ResMut<Music>,
+) {
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event
+        else {
+            continue;
+        };
+        if *id != music.folder.id() {
+            continue;
+        }
+        let Some(folder) = folders.get(*id) else {
+            continue;
+        };
+        let (mut calm, mut active) = (Vec::new(), Vec::new());
+        for handle in &folder.handles {
+            let Ok(track) = handle.clone().try_typed::<AudioSource>() else {
+                continue;
+            };
+            let is_active = track
+                .path()
+                .is_some_and(|path| path.path().starts_with("music/active"));
+            if is_active {
+                active.push(track);
+            } else {
+                calm.push(track);
+            }
+        }
+        info!(
+            "Loaded {} calm and {} active tracks",
+            calm.len(),
+            active.len()
+        );
+        music.calm = Playlist {
+            tracks: calm,
+            queue: Vec::new(),
+        };
+        music.active = Playlist {
+            tracks: active,
+            queue: Vec::new(),
+        };
+    }
+}
+
+fn count_spree(
+    mut placed: EventReader<BuildingPlaced>,
+    mut spree: ResMut<Spree>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed();
+    for _ in placed.read().filter(|placed| !placed.restored) {
+        spree.0.push(now);
+    }
+    spree.0.retain(|at| now - *at < SPREE_WINDOW);
+}
+
+fn choose_mood(
+    mut music: ResMut<Music>,
+    state: Res<State<GameState>>,
+    spree: Res<Spree>,
+    clock: Res<GameClock>,
+) {
+    let mood = if *state.get() == GameState::InGame
+        && clock.daylight() > 0.
+        && spree.0.len() >= SPREE_BUILDINGS
+    {
+        Mood::Active
+    } else {
+        Mood::Calm
+    };
+    if music.mood != mood {
+        music.mood = mood;
+    }
+}
+
+/// Start the next track of the current mood when none is playing
+fn play_music(mut commands: Commands, mut music: ResMut<Music>, tracks: Query<&MusicTrack>) {
+    let mood = music.mood;
+    if tracks.iter().any(|track| track.mood == mood) {
+        return;
+    }
+    // a mood without tracks plays the other playlist
+    let Some(handle) = music.playlist(mood).next().or_else(|| music.calm.next()) else {
+        return;
+    };
+    commands.spawn((
+        Name::new("music"),
+        AudioPlayer(handle),
+        PlaybackSettings::DESPAWN.with_volume(Volume::Linear(0.)),
+        MusicTrack { mood, fade: 0. },
+        SoundCategory::Music,
+    ));
+}
+
+/// Fade the tracks of the current mood in and the others out, and stop them once silent
+fn fade_music(
+    mut commands: Commands,
+    music: Res<Music>,
+    settings: Res<Settings>,
+    time: Res<Time<Real>>,
+    mut tracks: Query<(Entity, &mut MusicTrack, &mut AudioSink)>,
+) {
+    let volume = SoundCategory::Music.volume(&settings);
+    let step = time.delta_secs() / CROSSFADE;
+    for (e, mut track, mut sink) in &mut tracks {
+        if track.mood == music.mood {
+            track.fade = (track.fade + step).min(1.);
+        } else {
+            track.fade -= step;
+            if track.fade <= 0. {
+                commands.entity(e).despawn();
+                continue;
+            }
+        }
+        sink.set_volume(Volume::Linear(track.fade * volume));
+    }
+}
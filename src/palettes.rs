@@ -0,0 +1,232 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    image::{ImageAddressMode, ImageSampler, ImageSamplerDescriptor},
+    pbr::wireframe::WireframeColor,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::{BuildId, Building, BuildingType},
+    pause_menu::Settings,
+    zones::zone_index,
+};
+
+/// Size of the tiled hatch images, in pixels
+const HATCH_SIZE: u32 = 32;
+/// Size of the round hatch images, in pixels
+const ROUND_HATCH_SIZE: u32 = 128;
+/// Pixels between two lines of a hatch
+const HATCH_PERIOD: i32 = 8;
+/// World units covered by a tile of hatch on the ground
+pub const HATCH_TILE: f32 = 4.;
+/// Number of hatch patterns, cycled through past it
+const HATCH_COUNT: usize = 6;
+
+/// Colors of the things told apart by their color, chosen in the settings: zones, deposits and
+/// the heatmap. The colorblind palettes replace the colors of the zones and the deposits by
+/// colors kept distinct for their kind of colorblindness, and the heatmap ramp by one growing
+/// in brightness. Hatch patterns can be drawn over them too, each zone or deposit having its
+/// own.
+pub struct PalettePlugin;
+
+impl Plugin for PalettePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<HatchImages>()
+            .add_systems(Update, color_zone_wireframes);
+    }
+}
+
+/// Set of colors, for the colorblind players
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Palette {
+    /// The colors of the assets
+    #[default]
+    Default,
+    /// For deuteranopia and protanopia, from Okabe and Ito
+    RedGreen,
+    /// For tritanopia
+    BlueYellow,
+}
+
+impl Palette {
+    pub const ALL: [Palette; 3] = [Palette::Default, Palette::RedGreen, Palette::BlueYellow];
+
+    /// Localization key of the name
+    pub fn key(self) -> &'static str {
+        match self {
+            Palette::Default => "palette-default",
+            Palette::RedGreen => "palette-red-green",
+            Palette::BlueYellow => "palette-blue-yellow",
+        }
+    }
+
+    pub fn next(self) -> Palette {
+        let i = Self::ALL.iter().position(|p| *p == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    fn colors(self) -> &'static [Color] {
+        match self {
+            Palette::Default => &[],
+            Palette::RedGreen => &[
+                Color::srgb(0.9, 0.62, 0.),
+                Color::srgb(0.34, 0.71, 0.91),
+                Color::srgb(0., 0.62, 0.45),
+                Color::srgb(0.94, 0.89, 0.26),
+                Color::srgb(0., 0.45, 0.7),
+                Color::srgb(0.84, 0.37, 0.),
+                Color::srgb(0.8, 0.47, 0.65),
+            ],
+            Palette::BlueYellow => &[
+                Color::srgb(0.85, 0.11, 0.38),
+                Color::srgb(0., 0.3, 0.25),
+                Color::srgb(0.88, 0.88, 0.88),
+                Color::srgb(1., 0.54, 0.4),
+                Color::srgb(0.36, 0.25, 0.22),
+            ],
+        }
+    }
+
+    /// Color of the `i`th thing of a set, in place of its default color
+    pub fn color(self, i: usize, default: Color) -> Color {
+        let colors = self.colors();
+        if colors.is_empty() {
+            default
+        } else {
+            colors[i % colors.len()].with_alpha(default.alpha())
+        }
+    }
+
+    /// Ramp of the heatmap in the terrain shader
+    pub fn heat_ramp(self) -> f32 {
+        match self {
+            Palette::Default => 0.,
+            Palette::RedGreen => 1.,
+            Palette::BlueYellow => 2.,
+        }
+    }
+}
+
+/// Hatch patterns, by index : tiled ones for the zones, round ones for the deposits. The lines
+/// are dark and the gaps are white, to be tinted by the color of the material.
+#[derive(Resource)]
+pub struct HatchImages {
+    tiled: Vec<Handle<Image>>,
+    round: Vec<Handle<Image>>,
+}
+
+impl HatchImages {
+    pub fn tiled(&self, i: usize) -> Handle<Image> {
+        self.tiled[i % self.tiled.len()].clone()
+    }
+
+    pub fn round(&self, i: usize) -> Handle<Image> {
+        self.round[i % self.round.len()].clone()
+    }
+}
+
+impl FromWorld for HatchImages {
+    fn from_world(world: &mut World) -> Self {
+        let mut images = world.resource_mut::<Assets<Image>>();
+        Self {
+            tiled: (0..HATCH_COUNT)
+                .map(|i| images.add(hatch_image(i, HATCH_SIZE, false)))
+                .collect(),
+            round: (0..HATCH_COUNT)
+                .map(|i| images.add(hatch_image(i, ROUND_HATCH_SIZE, true)))
+                .collect(),
+        }
+    }
+}
+
+/// Whether a pixel is on a line of a hatch pattern
+fn on_hatch(pattern: usize, x: i32, y: i32) -> bool {
+    let p = HATCH_PERIOD;
+    let line = |v: i32| v.rem_euclid(p) < 2;
+    match pattern {
+        0 => line(x + y),
+        1 => line(y),
+        2 => {
+            let d = IVec2::new(x.rem_euclid(p), y.rem_euclid(p)) - p / 2;
+            d.length_squared() <= (p / 4) * (p / 4)
+        }
+        3 => line(x + y) || line(x - y),
+        4 => line(x),
+        _ => line(x - y),
+    }
+}
+
+fn hatch_image(pattern: usize, size: u32, round: bool) -> Image {
+    let mut data = Vec::with_capacity((size * size * 4) as usize);
+    let center = size as f32 / 2.;
+    for y in 0..size {
+        for x in 0..size {
+            let inside = !round
+                || Vec2::new(x as f32 + 0.5, y as f32 + 0.5).distance(Vec2::splat(center))
+                    <= center;
+            let texel = match (inside, on_hatch(pattern, x as i32, y as i32)) {
+                (false, _) => [0, 0, 0, 0],
+                (true, true) => [40, 40, 40, 255],
+                (true, false) => [255, 255, 255, 150],
+            };
+            data.extend_from_slice(&texel);
+        }
+    }
+    let mut image = Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        // the UI swatches measure it
+        RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        ..ImageSamplerDescriptor::linear()
+    });
+    image
+}
+
+/// Color of a zone in a palette, `None` for the other buildings
+pub fn zone_color(
+    buildings: &Assets<Building>,
+    building: &Building,
+    palette: Palette,
+) -> Option<Color> {
+    match &building.typ {
+        BuildingType::Zone { color, .. } => {
+            Some(palette.color(zone_index(buildings, &building.name), *color))
+        }
+        _ => None,
+    }
+}
+
+/// Color the outlines of the zones being placed or planned with the palette
+fn color_zone_wireframes(
+    settings: Res<Settings>,
+    buildings: Res<Assets<Building>>,
+    mut wireframes: Query<(&BuildId, &mut WireframeColor)>,
+    added: Query<(), Added<WireframeColor>>,
+) {
+    if !settings.is_changed() && added.is_empty() {
+        return;
+    }
+    for (bid, mut wireframe) in &mut wireframes {
+        let Some(color) = buildings
+            .get(&bid.0)
+            .and_then(|building| zone_color(&buildings, building, settings.palette))
+        else {
+            continue;
+        };
+        if wireframe.color != color {
+            wireframe.color = color;
+        }
+    }
+}
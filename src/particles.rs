@@ -0,0 +1,310 @@
+use bevy::prelude::*;
+
+use crate::{
+    build::{BuildingPlaced, GameId},
+    menu::GameState,
+    replication::TerrainOp,
+    script_api::ScriptEvent,
+    wind::Wind,
+};
+
+/// Most particles alive at once, the effects past it are dropped
+const MAX_PARTICLES: usize = 600;
+/// Time between two smoke puffs of a damaged building, in seconds
+const SMOKE_INTERVAL: f32 = 0.4;
+/// Script events marking a building as damaged or repaired, with its id as payload
+const DAMAGED_EVENT: &str = "building_damaged";
+const REPAIRED_EVENT: &str = "building_repaired";
+
+/// A light particle layer : dust when the terrain tools are used, sparks when a building is
+/// built, and smoke over the buildings the scripts report as damaged with
+/// `emit("building_damaged", id)`, until `emit("building_repaired", id)`. The particles
+/// drift with the wind. Other modules spawn effects with `SpawnEffect`.
+pub struct ParticlePlugin;
+
+impl Plugin for ParticlePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SpawnEffect>()
+            .add_systems(Startup, setup_particle_assets)
+            .add_systems(
+                Update,
+                (
+                    (
+                        dust_on_terrain_ops,
+                        sparks_on_construction,
+                        mark_damaged,
+                        emit_smoke.after(mark_damaged),
+                    )
+                        .before(spawn_effects),
+                    spawn_effects,
+                    update_particles,
+                )
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Effect {
+    Dust,
+    Sparks,
+    Smoke,
+    Flames,
+}
+
+/// Request to spawn an effect, spread over `radius`
+#[derive(Event, Clone, Copy, Debug)]
+pub struct SpawnEffect {
+    pub effect: Effect,
+    pub pos: Vec3,
+    pub radius: f32,
+}
+
+/// How the particles of an effect look and move
+struct EffectParams {
+    count: usize,
+    lifetime: f32,
+    /// Horizontal and vertical speed of the particles at their start
+    speed: Vec2,
+    gravity: f32,
+    /// Share of the speed lost per second
+    drag: f32,
+    /// Scale of the particles at their start and their end
+    scale: (f32, f32),
+    /// Share of the speed of the wind the particles drift with
+    drift: f32,
+}
+
+impl Effect {
+    fn params(self) -> EffectParams {
+        match self {
+            Effect::Dust => EffectParams {
+                count: 24,
+                lifetime: 1.2,
+                speed: Vec2::new(1.5, 1.),
+                gravity: 0.5,
+                drag: 1.5,
+                scale: (0.25, 0.6),
+                drift: 0.4,
+            },
+            Effect::Sparks => EffectParams {
+                count: 16,
+                lifetime: 0.6,
+                speed: Vec2::new(2., 3.),
+                gravity: 6.,
+                drag: 0.5,
+                scale: (0.08, 0.02),
+                drift: 0.1,
+            },
+            Effect::Smoke => EffectParams {
+                count: 1,
+                lifetime: 3.,
+                speed: Vec2::new(0.1, 0.8),
+                gravity: -0.1,
+                drag: 0.3,
+                scale: (0.3, 1.2),
+                drift: 0.6,
+            },
+            Effect::Flames => EffectParams {
+                count: 6,
+                lifetime: 0.8,
+                speed: Vec2::new(0.3, 2.),
+                gravity: -1.,
+                drag: 0.8,
+                scale: (0.35, 0.05),
+                drift: 0.3,
+            },
+        }
+    }
+}
+
+#[derive(Resource)]
+struct ParticleAssets {
+    mesh: Handle<Mesh>,
+    dust: Handle<StandardMaterial>,
+    sparks: Handle<StandardMaterial>,
+    smoke: Handle<StandardMaterial>,
+    flames: Handle<StandardMaterial>,
+}
+
+#[derive(Component)]
+struct Particle {
+    velocity: Vec3,
+    age: f32,
+    lifetime: f32,
+    gravity: f32,
+    drag: f32,
+    scale: (f32, f32),
+    drift: f32,
+}
+
+/// A building smoking, with the time until its next puff
+#[derive(Component)]
+struct Damaged(Timer);
+
+fn setup_particle_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let material = |color: Color, unlit: bool| StandardMaterial {
+        base_color: color,
+        alpha_mode: AlphaMode::Blend,
+        unlit,
+        ..default()
+    };
+    commands.insert_resource(ParticleAssets {
+        mesh: meshes.add(Sphere::new(1.).mesh().ico(1).unwrap()),
+        dust: materials.add(material(Color::srgba(0.55, 0.45, 0.35, 0.5), false)),
+        sparks: materials.add(StandardMaterial {
+            emissive: LinearRgba::rgb(8., 4., 1.),
+            ..material(Color::srgb(1., 0.7, 0.3), true)
+        }),
+        smoke: materials.add(material(Color::srgba(0.25, 0.25, 0.25, 0.4), false)),
+        flames: materials.add(StandardMaterial {
+            emissive: LinearRgba::rgb(10., 3., 0.5),
+            ..material(Color::srgba(1., 0.45, 0.1, 0.8), true)
+        }),
+    });
+}
+
+fn dust_on_terrain_ops(mut ops: EventReader<TerrainOp>, mut effects: EventWriter<SpawnEffect>) {
+    for op in ops.read() {
+        effects.write(SpawnEffect {
+            effect: Effect::Dust,
+            pos: op.center,
+            radius: op.radius,
+        });
+    }
+}
+
+fn sparks_on_construction(
+    mut placed: EventReader<BuildingPlaced>,
+    transforms: Query<&GlobalTransform>,
+    mut effects: EventWriter<SpawnEffect>,
+) {
+    for placed in placed.read().filter(|placed| !placed.restored) {
+        let Ok(transform) = transforms.get(placed.entity) else {
+            continue;
+        };
+        effects.write(SpawnEffect {
+            effect: Effect::Sparks,
+            pos: transform.translation(),
+            radius: 1.,
+        });
+    }
+}
+
+/// Start and stop the smoke of the buildings on the script events
+fn mark_damaged(
+    mut commands: Commands,
+    mut events: EventReader<ScriptEvent>,
+    buildings: Query<(Entity, &GameId)>,
+) {
+    for event in events.read() {
+        let damaged = match event.name.as_str() {
+            DAMAGED_EVENT => true,
+            REPAIRED_EVENT => false,
+            _ => continue,
+        };
+        let Ok(id) = event.payload.as_int() else {
+            warn!("The {} event needs the id of the building", event.name);
+            continue;
+        };
+        let Some((e, _)) = buildings.iter().find(|(_, gid)| gid.0 == id as u64) else {
+            continue;
+        };
+        if damaged {
+            commands.entity(e).insert(Damaged(Timer::from_seconds(
+                SMOKE_INTERVAL,
+                TimerMode::Repeating,
+            )));
+        } else {
+            commands.entity(e).remove::<Damaged>();
+        }
+    }
+}
+
+fn emit_smoke(
+    mut damaged: Query<(&mut Damaged, &GlobalTransform)>,
+    time: Res<Time>,
+    mut effects: EventWriter<SpawnEffect>,
+) {
+    for (mut damaged, transform) in &mut damaged {
+        if damaged.0.tick(time.delta()).just_finished() {
+            effects.write(SpawnEffect {
+                effect: Effect::Smoke,
+                pos: transform.translation(),
+                radius: 0.5,
+            });
+        }
+    }
+}
+
+fn spawn_effects(
+    mut commands: Commands,
+    mut requests: EventReader<SpawnEffect>,
+    assets: Res<ParticleAssets>,
+    particles: Query<(), With<Particle>>,
+) {
+    let mut alive = particles.iter().count();
+    for request in requests.read() {
+        let params = request.effect.params();
+        if alive + params.count > MAX_PARTICLES {
+            continue;
+        }
+        alive += params.count;
+        let material = match request.effect {
+            Effect::Dust => &assets.dust,
+            Effect::Sparks => &assets.sparks,
+            Effect::Smoke => &assets.smoke,
+            Effect::Flames => &assets.flames,
+        };
+        for _ in 0..params.count {
+            let angle = rand::random_range(0.0..std::f32::consts::TAU);
+            let direction = Vec2::from_angle(angle);
+            let offset = direction * rand::random_range(0.0..=request.radius);
+            let speed = params.speed * rand::random_range(0.5..=1.);
+            commands.spawn((
+                Name::new("particle"),
+                Mesh3d(assets.mesh.clone()),
+                MeshMaterial3d(material.clone()),
+                Transform::from_translation(request.pos + Vec3::new(offset.x, 0., offset.y))
+                    .with_scale(Vec3::splat(params.scale.0)),
+                Particle {
+                    velocity: Vec3::new(direction.x * speed.x, speed.y, direction.y * speed.x),
+                    age: 0.,
+                    lifetime: params.lifetime * rand::random_range(0.75..=1.),
+                    gravity: params.gravity,
+                    drag: params.drag,
+                    scale: params.scale,
+                    drift: params.drift,
+                },
+                StateScoped(GameState::InGame),
+            ));
+        }
+    }
+}
+
+/// Move the particles, carried by the wind, and despawn them at the end of their life
+fn update_particles(
+    mut commands: Commands,
+    mut particles: Query<(Entity, &mut Particle, &mut Transform)>,
+    wind: Res<Wind>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_secs();
+    for (e, mut particle, mut transform) in &mut particles {
+        particle.age += dt;
+        if particle.age >= particle.lifetime {
+            commands.entity(e).despawn();
+            continue;
+        }
+        particle.velocity.y -= particle.gravity * dt;
+        let drag = (1. - particle.drag * dt).max(0.);
+        particle.velocity *= drag;
+        transform.translation += (particle.velocity + wind.velocity * particle.drift) * dt;
+        let t = particle.age / particle.lifetime;
+        transform.scale = Vec3::splat(particle.scale.0.lerp(particle.scale.1, t));
+    }
+}
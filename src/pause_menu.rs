@@ -0,0 +1,546 @@
+use bevy::{
+    core_pipeline::experimental::taa::TemporalAntiAliasing, pbr::wireframe::WireframeConfig,
+    prelude::*,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gltf_export::ExportGltf,
+    input_map::{Action, Actions},
+    localization::{LANGUAGES, Localization, LocalizedText},
+    menu::GameState,
+    palettes::Palette,
+    ui::{FontHandle, TextFocus},
+};
+
+const SETTINGS_PATH: &str = "settings/settings.ron";
+const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+
+/// Whether the game is paused. Only exists in game.
+#[derive(SubStates, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[source(GameState = GameState::InGame)]
+pub enum Pause {
+    #[default]
+    Running,
+    Paused,
+}
+
+/// Page of the pause menu being shown
+#[derive(SubStates, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[source(Pause = Pause::Paused)]
+pub enum PausePage {
+    #[default]
+    Main,
+    Settings,
+    /// The list of saves, see `save_browser`
+    Saves,
+}
+
+/// Pause menu toggled with Escape, pausing the sim. Its settings page edits the `Settings`,
+/// saved to `settings/settings.ron`.
+pub struct PauseMenuPlugin;
+
+impl Plugin for PauseMenuPlugin {
+    fn build(&self, app: &mut App) {
+        let settings = match Settings::load(SETTINGS_PATH) {
+            Ok(settings) => settings,
+            Err(e) => {
+                info!("Using the default settings ({e})");
+                Settings::default()
+            }
+        };
+        app.insert_resource(settings)
+            .add_sub_state::<Pause>()
+            .add_sub_state::<PausePage>()
+            .enable_state_scoped_entities::<Pause>()
+            .enable_state_scoped_entities::<PausePage>()
+            .add_systems(OnEnter(PausePage::Main), setup_pause_page)
+            .add_systems(OnEnter(PausePage::Settings), setup_settings_page)
+            .add_systems(
+                Update,
+                (
+                    toggle_pause.run_if(in_state(GameState::InGame)),
+                    pause_buttons.run_if(in_state(Pause::Paused)),
+                    apply_settings,
+                    update_setting_values.after(pause_buttons),
+                ),
+            );
+    }
+}
+
+/// Player settings
+#[derive(Resource, Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Settings {
+    /// Identifier of the language of the UI, one of `LANGUAGES`
+    pub language: String,
+    pub taa: bool,
+    pub fog: bool,
+    /// Stylized shading of the terrain and the buildings, instead of the realistic one
+    pub toon: bool,
+    /// Pan the camera with WASD too, taking these keys from the other actions
+    pub wasd_panning: bool,
+    /// Pan the camera when the cursor is near the border of the window
+    pub edge_pan: bool,
+    /// Sample the terrain heights when needed rather than keeping the whole continent in
+    /// memory. Applied to the next world generated.
+    pub low_memory_terrain: bool,
+    /// Volumes, between 0 and 1. The volume of each category is scaled by the master volume.
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub effects_volume: f32,
+    pub ui_volume: f32,
+    pub ambient_volume: f32,
+    pub ui_scale: f32,
+    /// Colors of the zones, the deposits and the heatmap, for the colorblind players
+    pub palette: Palette,
+    /// Draw a hatch pattern over the zones and the deposits, telling them apart without colors
+    pub hatch_patterns: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            language: LANGUAGES[0].0.to_string(),
+            taa: true,
+            fog: true,
+            toon: false,
+            wasd_panning: false,
+            edge_pan: false,
+            low_memory_terrain: false,
+            master_volume: 1.,
+            music_volume: 0.8,
+            effects_volume: 0.8,
+            ui_volume: 0.6,
+            ambient_volume: 0.6,
+            ui_scale: 1.,
+            palette: Palette::Default,
+            hatch_patterns: false,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        Ok(ron::de::from_bytes(&std::fs::read(path)?)?)
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(
+            path,
+            ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?,
+        )?;
+        Ok(())
+    }
+}
+
+/// A line of the settings page
+#[derive(Clone, Copy, PartialEq)]
+enum Setting {
+    Language,
+    Wireframe,
+    Taa,
+    Fog,
+    Toon,
+    WasdPanning,
+    EdgePan,
+    LowMemoryTerrain,
+    Palette,
+    HatchPatterns,
+    MasterVolume,
+    MusicVolume,
+    EffectsVolume,
+    UiVolume,
+    AmbientVolume,
+    UiScale,
+}
+
+impl Setting {
+    const TOGGLES: [Setting; 10] = [
+        Setting::Language,
+        Setting::Wireframe,
+        Setting::Taa,
+        Setting::Fog,
+        Setting::Toon,
+        Setting::WasdPanning,
+        Setting::EdgePan,
+        Setting::LowMemoryTerrain,
+        Setting::Palette,
+        Setting::HatchPatterns,
+    ];
+    const SLIDERS: [Setting; 6] = [
+        Setting::MasterVolume,
+        Setting::MusicVolume,
+        Setting::EffectsVolume,
+        Setting::UiVolume,
+        Setting::AmbientVolume,
+        Setting::UiScale,
+    ];
+
+    /// Localization key of the label
+    fn label(self) -> &'static str {
+        match self {
+            Setting::Language => "settings-language",
+            Setting::Wireframe => "settings-wireframe",
+            Setting::Taa => "settings-taa",
+            Setting::Fog => "settings-fog",
+            Setting::Toon => "settings-toon",
+            Setting::WasdPanning => "settings-wasd-panning",
+            Setting::EdgePan => "settings-edge-pan",
+            Setting::LowMemoryTerrain => "settings-low-memory-terrain",
+            Setting::Palette => "settings-palette",
+            Setting::HatchPatterns => "settings-hatch-patterns",
+            Setting::MasterVolume => "settings-master-volume",
+            Setting::MusicVolume => "settings-music-volume",
+            Setting::EffectsVolume => "settings-effects-volume",
+            Setting::UiVolume => "settings-ui-volume",
+            Setting::AmbientVolume => "settings-ambient-volume",
+            Setting::UiScale => "settings-ui-scale",
+        }
+    }
+
+    fn value(
+        self,
+        settings: &Settings,
+        wireframe: &WireframeConfig,
+        localization: &Localization,
+    ) -> String {
+        let on_off = |on: bool| localization.get(if on { "settings-on" } else { "settings-off" });
+        let percent = |v: f32| format!("{:.0}%", v * 100.);
+        match self {
+            Setting::Language => LANGUAGES
+                .iter()
+                .find(|(id, _)| *id == settings.language)
+                .map_or(settings.language.clone(), |(_, name)| name.to_string()),
+            Setting::Wireframe => on_off(wireframe.global),
+            Setting::Taa => on_off(settings.taa),
+            Setting::Fog => on_off(settings.fog),
+            Setting::Toon => on_off(settings.toon),
+            Setting::WasdPanning => on_off(settings.wasd_panning),
+            Setting::EdgePan => on_off(settings.edge_pan),
+            Setting::LowMemoryTerrain => on_off(settings.low_memory_terrain),
+            Setting::Palette => localization.get(settings.palette.key()),
+            Setting::HatchPatterns => on_off(settings.hatch_patterns),
+            Setting::MasterVolume => percent(settings.master_volume),
+            Setting::MusicVolume => percent(settings.music_volume),
+            Setting::EffectsVolume => percent(settings.effects_volume),
+            Setting::UiVolume => percent(settings.ui_volume),
+            Setting::AmbientVolume => percent(settings.ambient_volume),
+            Setting::UiScale => percent(settings.ui_scale),
+        }
+    }
+
+    fn toggle(self, settings: &mut Settings, wireframe: &mut WireframeConfig) {
+        match self {
+            Setting::Language => {
+                let current = LANGUAGES
+                    .iter()
+                    .position(|(id, _)| *id == settings.language);
+                let next = current.map_or(0, |i| (i + 1) % LANGUAGES.len());
+                settings.language = LANGUAGES[next].0.to_string();
+            }
+            Setting::Wireframe => wireframe.global = !wireframe.global,
+            Setting::Taa => settings.taa = !settings.taa,
+            Setting::Fog => settings.fog = !settings.fog,
+            Setting::Toon => settings.toon = !settings.toon,
+            Setting::WasdPanning => settings.wasd_panning = !settings.wasd_panning,
+            Setting::EdgePan => settings.edge_pan = !settings.edge_pan,
+            Setting::LowMemoryTerrain => settings.low_memory_terrain = !settings.low_memory_terrain,
+            Setting::Palette => settings.palette = settings.palette.next(),
+            Setting::HatchPatterns => settings.hatch_patterns = !settings.hatch_patterns,
+            _ => {}
+        }
+    }
+
+    /// Move a slider up or down a step
+    fn step(self, settings: &mut Settings, up: bool) {
+        let (value, range) = match self {
+            Setting::MasterVolume => (&mut settings.master_volume, 0.0..=1.),
+            Setting::MusicVolume => (&mut settings.music_volume, 0.0..=1.),
+            Setting::EffectsVolume => (&mut settings.effects_volume, 0.0..=1.),
+            Setting::UiVolume => (&mut settings.ui_volume, 0.0..=1.),
+            Setting::AmbientVolume => (&mut settings.ambient_volume, 0.0..=1.),
+            Setting::UiScale => (&mut settings.ui_scale, 0.5..=2.),
+            _ => return,
+        };
+        let step = if up { 0.1 } else { -0.1 };
+        // round to the step, so that repeated steps don't drift
+        *value = ((*value + step) * 10.).round() / 10.;
+        *value = value.clamp(*range.start(), *range.end());
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum PauseButton {
+    Resume,
+    Settings,
+    Saves,
+    ExportGltf,
+    QuitToMenu,
+    Back,
+    Toggle(Setting),
+    Step(Setting, bool),
+}
+
+/// Text showing the value of a setting
+#[derive(Component)]
+struct SettingValue(Setting);
+
+fn pause_root(name: &'static str) -> impl Bundle {
+    (
+        Name::new(name),
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            row_gap: Val::Px(10.),
+            ..default()
+        },
+        BackgroundColor(bevy::color::palettes::css::BLACK.with_alpha(0.7).into()),
+        GlobalZIndex(25),
+    )
+}
+
+fn spawn_button(
+    parent: &mut ChildSpawnerCommands,
+    button: PauseButton,
+    label: LocalizedText,
+    text_font: &TextFont,
+) {
+    parent.spawn((
+        Button,
+        Node {
+            padding: UiRect::all(Val::Px(8.)),
+            min_width: Val::Px(40.),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        BackgroundColor(NORMAL_BUTTON),
+        button,
+        children![(label, text_font.clone(), Label, Pickable::IGNORE)],
+    ));
+}
+
+fn setup_pause_page(mut commands: Commands, font: Res<FontHandle>) {
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 20.,
+        ..default()
+    };
+    commands
+        .spawn((pause_root("pause menu"), StateScoped(PausePage::Main)))
+        .with_children(|parent| {
+            parent.spawn((
+                LocalizedText::new("pause-title"),
+                TextFont {
+                    font_size: 40.,
+                    ..text_font.clone()
+                },
+                Label,
+            ));
+            for (button, label) in [
+                (PauseButton::Resume, "pause-resume"),
+                (PauseButton::Settings, "pause-settings"),
+                (PauseButton::Saves, "pause-saves"),
+                (PauseButton::ExportGltf, "pause-export-gltf"),
+                (PauseButton::QuitToMenu, "pause-quit-to-menu"),
+            ] {
+                spawn_button(parent, button, LocalizedText::new(label), &text_font);
+            }
+        });
+}
+
+fn setup_settings_page(mut commands: Commands, font: Res<FontHandle>) {
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 20.,
+        ..default()
+    };
+    let row = Node {
+        width: Val::Px(360.),
+        column_gap: Val::Px(6.),
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let label = |setting: Setting| {
+        (
+            Node {
+                flex_grow: 1.,
+                ..default()
+            },
+            LocalizedText::new(setting.label()),
+            text_font.clone(),
+            Label,
+        )
+    };
+    commands
+        .spawn((pause_root("settings"), StateScoped(PausePage::Settings)))
+        .with_children(|parent| {
+            parent.spawn((
+                LocalizedText::new("settings-title"),
+                TextFont {
+                    font_size: 40.,
+                    ..text_font.clone()
+                },
+                Label,
+            ));
+            for setting in Setting::TOGGLES {
+                parent.spawn(row.clone()).with_children(|parent| {
+                    parent.spawn(label(setting));
+                    parent.spawn((
+                        Button,
+                        Node {
+                            padding: UiRect::all(Val::Px(8.)),
+                            min_width: Val::Px(70.),
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        BackgroundColor(NORMAL_BUTTON),
+                        PauseButton::Toggle(setting),
+                        children![(
+                            Text::default(),
+                            text_font.clone(),
+                            Label,
+                            Pickable::IGNORE,
+                            SettingValue(setting)
+                        )],
+                    ));
+                });
+            }
+            for setting in Setting::SLIDERS {
+                parent.spawn(row.clone()).with_children(|parent| {
+                    parent.spawn(label(setting));
+                    let minus = LocalizedText::new("settings-decrease");
+                    spawn_button(parent, PauseButton::Step(setting, false), minus, &text_font);
+                    parent.spawn((
+                        Node {
+                            width: Val::Px(60.),
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        Text::default(),
+                        text_font.clone(),
+                        Label,
+                        SettingValue(setting),
+                    ));
+                    let plus = LocalizedText::new("settings-increase");
+                    spawn_button(parent, PauseButton::Step(setting, true), plus, &text_font);
+                });
+            }
+            let back = LocalizedText::new("settings-back");
+            spawn_button(parent, PauseButton::Back, back, &text_font);
+        });
+}
+
+/// Open or close the pause menu with Escape. The other pages go back to the pause menu.
+fn toggle_pause(
+    actions: Actions,
+    text_focus: Res<TextFocus>,
+    pause: Res<State<Pause>>,
+    page: Option<Res<State<PausePage>>>,
+    mut next_pause: ResMut<NextState<Pause>>,
+    mut next_page: ResMut<NextState<PausePage>>,
+) {
+    // the same Escape may have just closed the console or a text box
+    if text_focus.is_changed() || !actions.just_pressed(Action::PauseMenu) {
+        return;
+    }
+    match (pause.get(), page.as_deref().map(State::get)) {
+        (Pause::Paused, Some(PausePage::Settings | PausePage::Saves)) => {
+            next_page.set(PausePage::Main)
+        }
+        (Pause::Paused, _) => next_pause.set(Pause::Running),
+        (Pause::Running, _) => next_pause.set(Pause::Paused),
+    }
+}
+
+fn pause_buttons(
+    buttons: Query<(&Interaction, &PauseButton), Changed<Interaction>>,
+    mut next_pause: ResMut<NextState<Pause>>,
+    mut next_page: ResMut<NextState<PausePage>>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut settings: ResMut<Settings>,
+    mut wireframe: ResMut<WireframeConfig>,
+    mut exports: EventWriter<ExportGltf>,
+) -> Result {
+    let Some((_, button)) = buttons
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Pressed)
+    else {
+        return Ok(());
+    };
+    match *button {
+        PauseButton::Resume => next_pause.set(Pause::Running),
+        PauseButton::Settings => next_page.set(PausePage::Settings),
+        PauseButton::Back => next_page.set(PausePage::Main),
+        PauseButton::Saves => next_page.set(PausePage::Saves),
+        PauseButton::ExportGltf => {
+            exports.write(ExportGltf::default());
+        }
+        PauseButton::QuitToMenu => next_state.set(GameState::MainMenu),
+        PauseButton::Toggle(setting) => {
+            setting.toggle(&mut settings, &mut wireframe);
+            settings.save(SETTINGS_PATH)?;
+        }
+        PauseButton::Step(setting, up) => {
+            setting.step(&mut settings, up);
+            settings.save(SETTINGS_PATH)?;
+        }
+    }
+    Ok(())
+}
+
+/// Apply the graphics settings to the camera, and the UI scale
+fn apply_settings(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    mut ui_scale: ResMut<UiScale>,
+    camera: Single<(Entity, Option<&DistanceFog>, Has<TemporalAntiAliasing>), With<Camera3d>>,
+    mut removed_fog: Local<Option<DistanceFog>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    ui_scale.0 = settings.ui_scale;
+    let (camera, fog, has_taa) = *camera;
+    if settings.taa && !has_taa {
+        commands
+            .entity(camera)
+            .insert(TemporalAntiAliasing::default());
+    } else if !settings.taa && has_taa {
+        commands.entity(camera).remove::<TemporalAntiAliasing>();
+    }
+    // keep the fog parameters around to restore them
+    match (settings.fog, fog) {
+        (false, Some(fog)) => {
+            *removed_fog = Some(fog.clone());
+            commands.entity(camera).remove::<DistanceFog>();
+        }
+        (true, None) => {
+            if let Some(fog) = removed_fog.take() {
+                commands.entity(camera).insert(fog);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn update_setting_values(
+    settings: Res<Settings>,
+    wireframe: Res<WireframeConfig>,
+    localization: Res<Localization>,
+    mut values: Query<(Ref<SettingValue>, &mut Text)>,
+) {
+    let changed = settings.is_changed() || wireframe.is_changed() || localization.is_changed();
+    for (value, mut text) in &mut values {
+        if changed || value.is_added() {
+            text.0 = value.0.value(&settings, &wireframe, &localization);
+        }
+    }
+}
@@ -0,0 +1,358 @@
+use bevy::{
+    input::mouse::AccumulatedMouseMotion,
+    prelude::*,
+    render::{
+        camera::Exposure,
+        view::screenshot::{Screenshot, save_to_disk},
+    },
+};
+
+use crate::{
+    input_map::{Action, Actions},
+    localization::{Localization, LocalizedText},
+    pause_menu::Pause,
+    toasts::Toasts,
+    ui::FontHandle,
+};
+
+const SCREENSHOT_DIR: &str = "screenshots";
+/// Speed of the free-fly camera, in world units per second
+const FLY_SPEED: f32 = 10.;
+/// Speed with the sprint key held
+const FAST_FLY_SPEED: f32 = 40.;
+const LOOK_SPEED: f32 = 0.003;
+const FOV_STEP: f32 = 5.;
+const FOV_RANGE: std::ops::RangeInclusive<f32> = 20.0..=120.0;
+const EXPOSURE_STEP: f32 = 0.5;
+const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+
+/// Photo mode, toggled with F11 : the camera leaves its target and flies freely (the camera
+/// actions, and the right mouse button to look around), the UI is hidden but for a small panel with the
+/// field of view and the exposure, and F12 saves a screenshot in `screenshots/`.
+/// Pausing the game leaves it.
+pub struct PhotoModePlugin;
+
+impl Plugin for PhotoModePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_sub_state::<PhotoMode>()
+            .enable_state_scoped_entities::<PhotoMode>()
+            .insert_resource(Capture::default())
+            .add_systems(OnEnter(PhotoMode::On), enter_photo_mode)
+            .add_systems(OnExit(PhotoMode::On), exit_photo_mode)
+            .add_systems(
+                Update,
+                (
+                    toggle_photo_mode.run_if(in_state(Pause::Running)),
+                    (hide_ui, fly_camera, photo_buttons, update_photo_values)
+                        .run_if(in_state(PhotoMode::On)),
+                    take_screenshot,
+                ),
+            );
+    }
+}
+
+/// Whether the photo mode is on. Only exists while the game is running.
+#[derive(SubStates, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[source(Pause = Pause::Running)]
+pub enum PhotoMode {
+    #[default]
+    Off,
+    On,
+}
+
+/// UI root hidden by the photo mode, with its display before. The display is used rather
+/// than the visibility, that some systems update every frame.
+#[derive(Component)]
+struct HiddenForPhoto(Display);
+
+/// Panel of the photo mode, left visible
+#[derive(Component)]
+struct PhotoPanel;
+
+/// Field of view and exposure of the camera before the photo mode, given back when leaving it
+#[derive(Resource)]
+struct CameraBeforePhoto {
+    fov: f32,
+    exposure: f32,
+}
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum PhotoButton {
+    Fov(bool),
+    Exposure(bool),
+}
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum PhotoValue {
+    Fov,
+    Exposure,
+}
+
+/// Screenshot being taken : frames left before the panel is shown again. The screenshot is
+/// taken in the middle, so that the panel is hidden.
+#[derive(Resource, Default)]
+struct Capture {
+    frames_left: u8,
+}
+
+fn toggle_photo_mode(
+    actions: Actions,
+    state: Res<State<PhotoMode>>,
+    mut next_state: ResMut<NextState<PhotoMode>>,
+) {
+    if actions.just_pressed(Action::TogglePhotoMode) {
+        next_state.set(match state.get() {
+            PhotoMode::Off => PhotoMode::On,
+            PhotoMode::On => PhotoMode::Off,
+        });
+    }
+}
+
+fn enter_photo_mode(
+    mut commands: Commands,
+    camera: Single<(&Projection, &Exposure), With<Camera3d>>,
+    font: Res<FontHandle>,
+) {
+    let (projection, exposure) = *camera;
+    let fov = match projection {
+        Projection::Perspective(perspective) => perspective.fov,
+        _ => 0.,
+    };
+    commands.insert_resource(CameraBeforePhoto {
+        fov,
+        exposure: exposure.ev100,
+    });
+    let font = TextFont {
+        font: font.0.clone(),
+        font_size: 14.,
+        ..default()
+    };
+    let row = |value: PhotoValue, label: &'static str, button: fn(bool) -> PhotoButton| {
+        (
+            Node {
+                column_gap: Val::Px(6.),
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            children![
+                (
+                    Node {
+                        flex_grow: 1.,
+                        ..default()
+                    },
+                    LocalizedText::new(label),
+                    font.clone(),
+                    Label,
+                ),
+                photo_button(button(false), "-", &font),
+                (Text::default(), font.clone(), Label, value),
+                photo_button(button(true), "+", &font),
+            ],
+        )
+    };
+    commands.spawn((
+        Name::new("photo panel"),
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.),
+            bottom: Val::Px(10.),
+            width: Val::Px(240.),
+            flex_direction: FlexDirection::Column,
+            padding: UiRect::all(Val::Px(6.)),
+            row_gap: Val::Px(4.),
+            ..default()
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.6)),
+        StateScoped(PhotoMode::On),
+        PhotoPanel,
+        children![
+            (LocalizedText::new("photo-hint"), font.clone(), Label),
+            row(PhotoValue::Fov, "photo-fov", PhotoButton::Fov),
+            row(
+                PhotoValue::Exposure,
+                "photo-exposure",
+                PhotoButton::Exposure
+            ),
+        ],
+    ));
+}
+
+fn photo_button(button: PhotoButton, text: &'static str, font: &TextFont) -> impl Bundle {
+    (
+        Button,
+        Node {
+            padding: UiRect::axes(Val::Px(8.), Val::Px(2.)),
+            ..default()
+        },
+        BackgroundColor(NORMAL_BUTTON),
+        button,
+        children![(Text::new(text), font.clone(), Pickable::IGNORE)],
+    )
+}
+
+/// Show the UI again, and give its field of view and exposure back to the camera.
+/// It goes back to orbiting its target by itself.
+fn exit_photo_mode(
+    mut commands: Commands,
+    mut hidden: Query<(Entity, &mut Node, &HiddenForPhoto)>,
+    camera: Single<(&mut Projection, &mut Exposure), With<Camera3d>>,
+    before: Option<Res<CameraBeforePhoto>>,
+) {
+    for (e, mut node, HiddenForPhoto(previous)) in &mut hidden {
+        node.display = *previous;
+        commands.entity(e).remove::<HiddenForPhoto>();
+    }
+    let Some(before) = before else {
+        return;
+    };
+    let (mut projection, mut exposure) = camera.into_inner();
+    if let Projection::Perspective(perspective) = &mut *projection {
+        perspective.fov = before.fov;
+    }
+    exposure.ev100 = before.exposure;
+    commands.remove_resource::<CameraBeforePhoto>();
+}
+
+/// Hide the UI roots, including the ones spawned while in photo mode, like toasts
+fn hide_ui(
+    mut commands: Commands,
+    mut roots: Query<
+        (Entity, &mut Node),
+        (
+            Without<ChildOf>,
+            Without<PhotoPanel>,
+            Without<HiddenForPhoto>,
+        ),
+    >,
+) {
+    for (e, mut node) in &mut roots {
+        commands.entity(e).insert(HiddenForPhoto(node.display));
+        node.display = Display::None;
+    }
+}
+
+/// Fly with the camera actions, in the direction the camera looks, and look around
+/// like when orbiting
+fn fly_camera(
+    mut camera: Single<&mut Transform, With<Camera3d>>,
+    actions: Actions,
+    mouse_motion: Res<AccumulatedMouseMotion>,
+    time: Res<Time>,
+) {
+    if actions.pressed(Action::OrbitCamera) {
+        let delta = mouse_motion.delta * LOOK_SPEED;
+        let (yaw, pitch, _) = camera.rotation.to_euler(EulerRot::YXZ);
+        let pitch = (pitch - delta.y).clamp(-1.54, 1.54);
+        camera.rotation = Quat::from_euler(EulerRot::YXZ, yaw - delta.x, pitch, 0.);
+    }
+    let mut movement = Vec3::ZERO;
+    for (action, direction) in [
+        (Action::CameraForward, Vec3::NEG_Z),
+        (Action::CameraBack, Vec3::Z),
+        (Action::CameraLeft, Vec3::NEG_X),
+        (Action::CameraRight, Vec3::X),
+    ] {
+        if actions.pressed(action) {
+            movement += direction;
+        }
+    }
+    let speed = if actions.pressed(Action::FlyFast) {
+        FAST_FLY_SPEED
+    } else {
+        FLY_SPEED
+    };
+    let movement = camera.rotation * movement.normalize_or_zero();
+    camera.translation += movement * speed * time.delta_secs();
+}
+
+fn photo_buttons(
+    buttons: Query<(&Interaction, &PhotoButton), Changed<Interaction>>,
+    camera: Single<(&mut Projection, &mut Exposure), With<Camera3d>>,
+) {
+    let (mut projection, mut exposure) = camera.into_inner();
+    for (interaction, button) in &buttons {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match *button {
+            PhotoButton::Fov(up) => {
+                if let Projection::Perspective(perspective) = &mut *projection {
+                    let step = if up { FOV_STEP } else { -FOV_STEP };
+                    let fov = (perspective.fov.to_degrees() + step)
+                        .clamp(*FOV_RANGE.start(), *FOV_RANGE.end());
+                    perspective.fov = fov.to_radians();
+                }
+            }
+            PhotoButton::Exposure(up) => {
+                exposure.ev100 += if up { EXPOSURE_STEP } else { -EXPOSURE_STEP };
+            }
+        }
+    }
+}
+
+fn update_photo_values(
+    camera: Single<(Ref<Projection>, Ref<Exposure>), With<Camera3d>>,
+    mut values: Query<(Ref<PhotoValue>, &mut Text)>,
+) {
+    let (projection, exposure) = *camera;
+    let changed = projection.is_changed() || exposure.is_changed();
+    for (value, mut text) in &mut values {
+        if !changed && !value.is_added() {
+            continue;
+        }
+        text.0 = match *value {
+            PhotoValue::Fov => match &*projection {
+                Projection::Perspective(perspective) => {
+                    format!("{:.0}°", perspective.fov.to_degrees())
+                }
+                _ => String::new(),
+            },
+            PhotoValue::Exposure => format!("{:.1} EV", exposure.ev100),
+        };
+    }
+}
+
+/// Save a screenshot on pressing F12. In photo mode the panel is hidden for it, and the
+/// toast telling where it is saved waits for it to be taken.
+fn take_screenshot(
+    mut commands: Commands,
+    actions: Actions,
+    mut capture: ResMut<Capture>,
+    mut panel: Query<&mut Visibility, With<PhotoPanel>>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+    mut saved_path: Local<String>,
+) -> Result {
+    if capture.frames_left == 0 {
+        if !actions.just_pressed(Action::Screenshot) {
+            return Ok(());
+        }
+        capture.frames_left = 3;
+        for mut visibility in &mut panel {
+            *visibility = Visibility::Hidden;
+        }
+        return Ok(());
+    }
+    capture.frames_left -= 1;
+    match capture.frames_left {
+        2 => {
+            std::fs::create_dir_all(SCREENSHOT_DIR)?;
+            let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+            let path = format!("{SCREENSHOT_DIR}/screenshot-{}.png", time.as_millis());
+            info!("Screenshot saved to {}", path);
+            commands
+                .spawn(Screenshot::primary_window())
+                .observe(save_to_disk(path.clone()));
+            *saved_path = path;
+        }
+        0 => {
+            for mut visibility in &mut panel {
+                *visibility = Visibility::Inherited;
+            }
+            let path = std::mem::take(&mut *saved_path);
+            toasts.info(localization.get_args("toast-screenshot-saved", &[("path", path)]));
+        }
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,305 @@
+use bevy::{
+    pbr::wireframe::{Wireframe, WireframeColor},
+    prelude::*,
+    render::primitives::Aabb,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CameraTarget,
+    build::{BuildId, Building, BuildingType, SavedShapes, realize_building},
+    input_map::{Action, Actions},
+    localization::Localization,
+    map::Map,
+    menu::GameState,
+    signs::SignLabel,
+    toasts::Toasts,
+};
+
+/// Planning mode : placed buildings become ghosts, that cost nothing and don't run scripts,
+/// until the plan is committed.
+pub struct PlanPlugin;
+
+impl Plugin for PlanPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(PlanningMode(false));
+        app.add_systems(
+            Update,
+            (
+                toggle_planning,
+                draw_planned,
+                commit_plan,
+                discard_plan,
+                save_blueprint,
+                load_blueprint,
+                place_blueprint
+                    .run_if(resource_exists::<PendingBlueprint>)
+                    .after(load_blueprint),
+            )
+                .run_if(in_state(GameState::InGame)),
+        );
+    }
+}
+
+const BLUEPRINT_PATH: &str = "blueprints/blueprint.ron";
+
+/// Whether placements create planned buildings instead of real ones
+#[derive(Resource)]
+pub struct PlanningMode(pub bool);
+
+/// A building that is planned but not built yet
+#[derive(Component)]
+pub struct Planned {
+    /// Order in which the building was planned
+    pub order: u64,
+    pub aabb: Aabb,
+}
+
+/// A set of planned buildings, positioned relatively to their center
+#[derive(Serialize, Deserialize, Default)]
+pub struct Blueprint {
+    pub buildings: Vec<BlueprintEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BlueprintEntry {
+    /// Asset path of the building
+    pub building: String,
+    pub offset: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+    pub aabb_center: [f32; 3],
+    pub aabb_half_extents: [f32; 3],
+    /// Text of the sign, for sign buildings
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// A blueprint being placed, waiting for its buildings to be loaded
+#[derive(Resource)]
+struct PendingBlueprint {
+    blueprint: Blueprint,
+    handles: Vec<Handle<Building>>,
+    /// Camera target when the blueprint was loaded, the blueprint is centered on it
+    anchor: Vec3,
+}
+
+/// Toggle planning mode on pressing P
+fn toggle_planning(mut planning: ResMut<PlanningMode>, actions: Actions) {
+    if actions.just_pressed(Action::TogglePlanning) {
+        planning.0 = !planning.0;
+        info!("Planning mode : {}", planning.0);
+    }
+}
+
+/// Draw the outline of planned buildings
+fn draw_planned(planned: Query<(&Planned, &Transform)>, mut gizmos: Gizmos) {
+    for (planned, transform) in &planned {
+        gizmos.cuboid(
+            Transform::from_translation(
+                transform.translation + Vec3::from(planned.aabb.center) * transform.scale,
+            )
+            .with_rotation(transform.rotation)
+            .with_scale(Vec3::from(planned.aabb.half_extents) * transform.scale * 2.),
+            bevy::color::palettes::css::AQUA,
+        );
+    }
+}
+
+/// Rank of a building type in the construction order of a plan.
+fn construction_rank(typ: &BuildingType) -> u8 {
+    match typ {
+        BuildingType::Tool { .. } => 0,
+        BuildingType::Zone { .. } => 1,
+        BuildingType::Single { .. } | BuildingType::Sign { .. } => 2,
+    }
+}
+
+/// Build every planned building on pressing Enter, terrain and zones first.
+fn commit_plan(
+    mut commands: Commands,
+    actions: Actions,
+    planned: Query<(Entity, &Transform, &Planned, &BuildId)>,
+    mut map: ResMut<Map>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    buildings: Res<Assets<Building>>,
+) {
+    if !actions.just_pressed(Action::CommitPlan) {
+        return;
+    }
+    let mut plan: Vec<_> = planned.iter().collect();
+    plan.sort_by_key(|(_, _, planned, bid)| {
+        let rank = buildings
+            .get(&bid.0)
+            .map(|b| construction_rank(&b.typ))
+            .unwrap_or(u8::MAX);
+        (rank, planned.order)
+    });
+    for (e, transform, planned, bid) in plan {
+        realize_building(
+            &mut commands,
+            &mut map,
+            &mut meshes,
+            &buildings,
+            e,
+            transform,
+            &planned.aabb,
+            bid,
+        );
+        commands.entity(e).remove::<Planned>();
+    }
+}
+
+/// Remove every planned building on pressing Backspace
+fn discard_plan(mut commands: Commands, actions: Actions, planned: Query<Entity, With<Planned>>) {
+    if actions.just_pressed(Action::DiscardPlan) {
+        for e in &planned {
+            commands.entity(e).despawn();
+        }
+    }
+}
+
+/// Save the current plan as a blueprint on pressing B
+fn save_blueprint(
+    actions: Actions,
+    planned: Query<(&Transform, &Planned, &BuildId, Option<&SignLabel>)>,
+    asset_server: Res<AssetServer>,
+) -> Result {
+    if !actions.just_pressed(Action::SaveBlueprint) || planned.is_empty() {
+        return Ok(());
+    }
+    let center = planned
+        .iter()
+        .map(|(transform, _, _, _)| transform.translation)
+        .sum::<Vec3>()
+        / planned.iter().count() as f32;
+    let mut blueprint = Blueprint::default();
+    let mut plan: Vec<_> = planned.iter().collect();
+    plan.sort_by_key(|(_, planned, _, _)| planned.order);
+    for (transform, planned, bid, label) in plan {
+        let Some(path) = asset_server.get_path(bid.0.id()) else {
+            continue;
+        };
+        blueprint.buildings.push(BlueprintEntry {
+            building: path.to_string(),
+            offset: (transform.translation - center).to_array(),
+            rotation: transform.rotation.to_array(),
+            scale: transform.scale.to_array(),
+            aabb_center: planned.aabb.center.to_array(),
+            aabb_half_extents: planned.aabb.half_extents.to_array(),
+            label: label.map(|l| l.0.clone()),
+        });
+    }
+    std::fs::create_dir_all("blueprints")?;
+    std::fs::write(
+        BLUEPRINT_PATH,
+        ron::ser::to_string_pretty(&blueprint, ron::ser::PrettyConfig::default())?,
+    )?;
+    info!("Blueprint saved to {}", BLUEPRINT_PATH);
+    Ok(())
+}
+
+/// Load the saved blueprint on pressing V, to be placed around the camera target once its
+/// buildings are loaded
+fn load_blueprint(
+    mut commands: Commands,
+    actions: Actions,
+    asset_server: Res<AssetServer>,
+    camera_target: Single<&CameraTarget>,
+) -> Result {
+    if !actions.just_pressed(Action::LoadBlueprint) {
+        return Ok(());
+    }
+    let blueprint: Blueprint = ron::de::from_bytes(&std::fs::read(BLUEPRINT_PATH)?)?;
+    let handles = blueprint
+        .buildings
+        .iter()
+        .map(|entry| asset_server.load(&entry.building))
+        .collect();
+    commands.insert_resource(PendingBlueprint {
+        blueprint,
+        handles,
+        anchor: camera_target.pos,
+    });
+    Ok(())
+}
+
+/// Place the loaded blueprint as a plan once its buildings are loaded. The entries whose
+/// building can't be loaded are left out, and reported.
+fn place_blueprint(
+    mut commands: Commands,
+    pending: Res<PendingBlueprint>,
+    asset_server: Res<AssetServer>,
+    buildings: Res<Assets<Building>>,
+    shapes: Res<SavedShapes>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    map: Res<Map>,
+    planned: Query<&Planned>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) {
+    if pending
+        .handles
+        .iter()
+        .any(|handle| asset_server.load_state(handle).is_loading())
+    {
+        return;
+    }
+    commands.remove_resource::<PendingBlueprint>();
+    let mut order = planned.iter().map(|p| p.order).max().unwrap_or(0);
+    let mut missing = Vec::new();
+    for (entry, handle) in pending.blueprint.buildings.iter().zip(&pending.handles) {
+        let Some(building) = buildings.get(handle) else {
+            warn!("Unknown building {} in the blueprint", entry.building);
+            missing.push(entry.building.as_str());
+            continue;
+        };
+        let mut translation = pending.anchor + Vec3::from_array(entry.offset);
+        translation.y += map.get_height(translation) - pending.anchor.y;
+        let transform = Transform {
+            translation,
+            rotation: Quat::from_array(entry.rotation),
+            scale: Vec3::from_array(entry.scale),
+        };
+        let aabb = Aabb {
+            center: entry.aabb_center.into(),
+            half_extents: entry.aabb_half_extents.into(),
+        };
+        order += 1;
+        let mut entity = commands.spawn((
+            Name::new("building"),
+            BuildId(handle.clone()),
+            transform,
+            aabb,
+            Planned { order, aabb },
+        ));
+        match &building.typ {
+            BuildingType::Single { model, .. } => {
+                entity.insert(SceneRoot(model.clone()));
+            }
+            BuildingType::Zone { color, .. } => {
+                entity.insert((
+                    Mesh3d(shapes.0[0].clone()),
+                    Wireframe,
+                    WireframeColor { color: *color },
+                ));
+            }
+            BuildingType::Sign { color, text } => {
+                entity.insert((
+                    Mesh3d(shapes.0[0].clone()),
+                    MeshMaterial3d(materials.add(StandardMaterial::from(*color))),
+                    SignLabel(entry.label.clone().unwrap_or_else(|| text.clone())),
+                ));
+            }
+            BuildingType::Tool { .. } => {
+                entity.despawn();
+            }
+        }
+    }
+    if !missing.is_empty() {
+        missing.sort_unstable();
+        missing.dedup();
+        let args = [("buildings", missing.join(", "))];
+        toasts.warning(localization.get_args("toast-blueprint-missing", &args));
+    }
+}
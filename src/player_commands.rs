@@ -0,0 +1,448 @@
+use std::fmt;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bridges::{BuildRoad, COST_RESOURCE, check_road, road_cost},
+    build::{Building, GameIds, Placement, SpawnBuilding},
+    canals::{CANAL_COST, CANAL_HALF_WIDTH, DigCanal, check_canal, dig_points},
+    localization::Localization,
+    map::Map,
+    menu::GameState,
+    replication::TerrainOp,
+    research::{TechTree, TechTreeHandle},
+    roads::RoadKind,
+    sim::Sim,
+    toasts::Toasts,
+};
+
+/// Validation of the commands sent by players, on the authoritative side.
+/// Accepted commands are applied, rejected ones are answered with a reason.
+pub struct PlayerCommandPlugin;
+
+impl Plugin for PlayerCommandPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<IncomingCommand>();
+        app.add_event::<CommandRejected>();
+        app.insert_resource(ProtectedAreas::default());
+        app.add_systems(
+            Update,
+            (validate_commands, log_rejections).run_if(in_state(GameState::InGame)),
+        );
+    }
+}
+
+/// An action requested by a player
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum PlayerCommand {
+    Place {
+        /// Name of the building, as in its asset file
+        building: String,
+        pos: Vec2,
+        rotation: f32,
+    },
+    Terraform(TerrainOp),
+    Purchase {
+        /// Name of the resource paid with, in the sim data
+        resource: String,
+        cost: f64,
+    },
+    /// Research a technology of the tech tree
+    Research {
+        tech: String,
+    },
+    /// Build a straight road, with bridges over the water and tunnels under the mountains it
+    /// crosses
+    BuildRoad {
+        from: Vec2,
+        to: Vec2,
+    },
+    /// Dig a straight canal from a river, a lake or another canal
+    DigCanal {
+        from: Vec2,
+        to: Vec2,
+    },
+}
+
+/// A command received from a player
+#[derive(Event, Clone, Debug)]
+pub struct IncomingCommand {
+    pub player: u32,
+    pub command: PlayerCommand,
+}
+
+/// Why a command was refused
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Rejection {
+    UnknownBuilding(String),
+    Occupied,
+    Protected,
+    Placement(Placement),
+    /// The technology of the building, or one required by the technology, isn't researched
+    Locked(String),
+    AlreadyResearched(String),
+    /// The technology isn't in the tech tree
+    UnknownTech(String),
+    RoadTooLong {
+        length: f32,
+        max: f32,
+    },
+    /// A road starts or ends in the water
+    RoadInWater,
+    BridgeTooLong {
+        length: f32,
+        max: f32,
+    },
+    TunnelTooLong {
+        length: f32,
+        max: f32,
+    },
+    CanalTooLong {
+        length: f32,
+        max: f32,
+    },
+    /// A canal doesn't start nor end in a river, a lake or another canal
+    CanalNotConnected,
+    NotEnoughResources {
+        resource: String,
+        needed: f64,
+        available: f64,
+    },
+    /// A terrain operation the terrain can't apply
+    InvalidTerraform,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::UnknownBuilding(name) => write!(f, "unknown building {name}"),
+            Rejection::Occupied => write!(f, "the area is occupied"),
+            Rejection::Protected => write!(f, "the area is protected"),
+            Rejection::Placement(Placement::Coast) => {
+                write!(f, "it must be on the coast, near the mouth of a river")
+            }
+            Rejection::Placement(Placement::River) => write!(f, "it must be across a river"),
+            Rejection::Placement(Placement::Riverside) => write!(f, "it must be by a river"),
+            Rejection::Placement(placement) => write!(f, "it can't be placed {placement:?}"),
+            Rejection::Locked(tech) => write!(f, "{tech} must be researched first"),
+            Rejection::AlreadyResearched(tech) => write!(f, "{tech} is already researched"),
+            Rejection::UnknownTech(tech) => write!(f, "unknown technology {tech}"),
+            Rejection::RoadTooLong { length, max } => {
+                write!(f, "the road is too long ({length:.0} / {max:.0})")
+            }
+            Rejection::RoadInWater => write!(f, "roads must start and end on land"),
+            Rejection::BridgeTooLong { length, max } => {
+                write!(f, "the bridge is too long ({length:.0} / {max:.0})")
+            }
+            Rejection::TunnelTooLong { length, max } => {
+                write!(f, "the tunnel is too long ({length:.0} / {max:.0})")
+            }
+            Rejection::CanalTooLong { length, max } => {
+                write!(f, "the canal is too long ({length:.0} / {max:.0})")
+            }
+            Rejection::CanalNotConnected => {
+                write!(
+                    f,
+                    "canals must start or end in a river, a lake or another canal"
+                )
+            }
+            Rejection::NotEnoughResources {
+                resource,
+                needed,
+                available,
+            } => write!(f, "not enough {resource} ({available:.1} / {needed:.1})"),
+            Rejection::InvalidTerraform => write!(f, "the terrain can't be changed this way"),
+        }
+    }
+}
+
+impl Rejection {
+    /// The reason shown to the player, in the chosen language
+    pub fn localized(&self, localization: &Localization) -> String {
+        let length_args = |length: &f32, max: &f32| {
+            [
+                ("length", format!("{length:.0}")),
+                ("max", format!("{max:.0}")),
+            ]
+        };
+        match self {
+            Rejection::UnknownBuilding(name) => {
+                localization.get_args("rejection-unknown-building", &[("name", name.clone())])
+            }
+            Rejection::Occupied => localization.get("rejection-occupied"),
+            Rejection::Protected => localization.get("rejection-protected"),
+            Rejection::Placement(placement) => localization.get(placement.toast_key()),
+            Rejection::Locked(tech) => {
+                localization.get_args("rejection-locked", &[("tech", tech.clone())])
+            }
+            Rejection::AlreadyResearched(tech) => {
+                localization.get_args("rejection-already-researched", &[("tech", tech.clone())])
+            }
+            Rejection::UnknownTech(tech) => {
+                localization.get_args("rejection-unknown-tech", &[("tech", tech.clone())])
+            }
+            Rejection::RoadTooLong { length, max } => {
+                localization.get_args("rejection-road-too-long", &length_args(length, max))
+            }
+            Rejection::RoadInWater => localization.get("rejection-road-in-water"),
+            Rejection::BridgeTooLong { length, max } => {
+                localization.get_args("rejection-bridge-too-long", &length_args(length, max))
+            }
+            Rejection::TunnelTooLong { length, max } => {
+                localization.get_args("rejection-tunnel-too-long", &length_args(length, max))
+            }
+            Rejection::CanalTooLong { length, max } => {
+                localization.get_args("rejection-canal-too-long", &length_args(length, max))
+            }
+            Rejection::CanalNotConnected => localization.get("rejection-canal-not-connected"),
+            Rejection::NotEnoughResources {
+                resource,
+                needed,
+                available,
+            } => localization.get_args(
+                "rejection-not-enough-resources",
+                &[
+                    ("resource", resource.clone()),
+                    ("needed", format!("{needed:.1}")),
+                    ("available", format!("{available:.1}")),
+                ],
+            ),
+            Rejection::InvalidTerraform => localization.get("rejection-invalid-terraform"),
+        }
+    }
+}
+
+/// Sent back to the player whose command was refused
+#[derive(Event, Clone, Debug)]
+pub struct CommandRejected {
+    pub player: u32,
+    pub command: PlayerCommand,
+    pub reason: Rejection,
+}
+
+/// Areas of the map where players can't build nor terraform
+#[derive(Resource, Default)]
+pub struct ProtectedAreas(pub Vec<Rect>);
+
+impl ProtectedAreas {
+    fn is_protected(&self, area: Rect) -> bool {
+        self.0.iter().any(|r| !r.intersect(area).is_empty())
+    }
+}
+
+impl PlayerCommand {
+    /// Check the command against occupancy, resources, protected areas and the tech tree,
+    /// `None` while it loads
+    pub fn validate(
+        &self,
+        map: &Map,
+        buildings: &Assets<Building>,
+        protected: &ProtectedAreas,
+        sim: &Sim,
+        tech_tree: Option<&TechTree>,
+    ) -> Result<(), Rejection> {
+        match self {
+            PlayerCommand::Place { building, pos, .. } => {
+                let Some((_, b)) = buildings.iter().find(|(_, b)| &b.name == building) else {
+                    return Err(Rejection::UnknownBuilding(building.clone()));
+                };
+                let half_size = Vec2::new(b.size.0 as f32, b.size.1 as f32) / 2.;
+                if protected.is_protected(Rect::from_center_half_size(*pos, half_size)) {
+                    return Err(Rejection::Protected);
+                }
+                if !map.is_area_free((pos - half_size, pos + half_size)) {
+                    return Err(Rejection::Occupied);
+                }
+                if !b.placement.allows(map, (pos - half_size, pos + half_size)) {
+                    return Err(Rejection::Placement(b.placement));
+                }
+                if !sim.script_world.0.lock().unwrap().research.allows(b) {
+                    return Err(Rejection::Locked(b.tech.clone().unwrap_or_default()));
+                }
+                for (resource, needed) in &b.cost {
+                    let available = sim.resource_amount(resource);
+                    if available < *needed {
+                        return Err(Rejection::NotEnoughResources {
+                            resource: resource.clone(),
+                            needed: *needed,
+                            available,
+                        });
+                    }
+                }
+                Ok(())
+            }
+            PlayerCommand::Terraform(op) => {
+                if !op.is_valid() {
+                    return Err(Rejection::InvalidTerraform);
+                }
+                let area = Rect::from_center_half_size(op.center.xz(), Vec2::splat(op.radius));
+                if protected.is_protected(area) {
+                    return Err(Rejection::Protected);
+                }
+                Ok(())
+            }
+            PlayerCommand::Purchase { resource, cost } => {
+                let available = sim.resource_amount(resource);
+                if available < *cost {
+                    return Err(Rejection::NotEnoughResources {
+                        resource: resource.clone(),
+                        needed: *cost,
+                        available,
+                    });
+                }
+                Ok(())
+            }
+            PlayerCommand::Research { tech } => {
+                let world = sim.script_world.0.lock().unwrap();
+                if world.research.unlocked.contains(tech) {
+                    return Err(Rejection::AlreadyResearched(tech.clone()));
+                }
+                let Some((tree, t)) = tech_tree.and_then(|tree| Some((tree, tree.get(tech)?)))
+                else {
+                    return Err(Rejection::UnknownTech(tech.clone()));
+                };
+                if !tree.is_available(&world.research, tech) {
+                    let missing = t
+                        .requires
+                        .iter()
+                        .find(|required| !world.research.unlocked.contains(*required));
+                    return Err(Rejection::Locked(missing.cloned().unwrap_or_default()));
+                }
+                Ok(())
+            }
+            PlayerCommand::BuildRoad { from, to } => {
+                let spans = check_road(map, *from, *to)?;
+                let flattened = spans
+                    .iter()
+                    .filter(|span| span.kind == RoadKind::Road)
+                    .flat_map(|span| span.flatten_points());
+                for p in flattened {
+                    if protected.is_protected(Rect::from_center_half_size(p, Vec2::ONE)) {
+                        return Err(Rejection::Protected);
+                    }
+                }
+                let cost = road_cost(&spans);
+                let available = sim.resource_amount(COST_RESOURCE);
+                if available < cost {
+                    return Err(Rejection::NotEnoughResources {
+                        resource: COST_RESOURCE.to_string(),
+                        needed: cost,
+                        available,
+                    });
+                }
+                Ok(())
+            }
+            PlayerCommand::DigCanal { from, to } => {
+                check_canal(map, *from, *to)?;
+                for p in dig_points(*from, *to) {
+                    let area = Rect::from_center_half_size(p, Vec2::splat(CANAL_HALF_WIDTH));
+                    if protected.is_protected(area) {
+                        return Err(Rejection::Protected);
+                    }
+                }
+                let cost = (from.distance(*to) * CANAL_COST) as f64;
+                let available = sim.resource_amount(COST_RESOURCE);
+                if available < cost {
+                    return Err(Rejection::NotEnoughResources {
+                        resource: COST_RESOURCE.to_string(),
+                        needed: cost,
+                        available,
+                    });
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Apply the valid incoming commands, and answer the others with a rejection
+fn validate_commands(
+    mut incoming: EventReader<IncomingCommand>,
+    mut rejected: EventWriter<CommandRejected>,
+    mut spawn: EventWriter<SpawnBuilding>,
+    mut terrain_ops: EventWriter<TerrainOp>,
+    mut roads: EventWriter<BuildRoad>,
+    mut canals: EventWriter<DigCanal>,
+    map: Res<Map>,
+    buildings: Res<Assets<Building>>,
+    protected: Res<ProtectedAreas>,
+    game_ids: Res<GameIds>,
+    mut sim: ResMut<Sim>,
+    tree: Res<TechTreeHandle>,
+    trees: Res<Assets<TechTree>>,
+) {
+    let tech_tree = trees.get(&tree.0);
+    for IncomingCommand { player, command } in incoming.read() {
+        if let Err(reason) = command.validate(&map, &buildings, &protected, &sim, tech_tree) {
+            rejected.write(CommandRejected {
+                player: *player,
+                command: command.clone(),
+                reason,
+            });
+            continue;
+        }
+        match command {
+            PlayerCommand::Place {
+                building,
+                pos,
+                rotation,
+            } => {
+                if let Some((_, b)) = buildings.iter().find(|(_, b)| &b.name == building) {
+                    for (resource, cost) in &b.cost {
+                        sim.spend_resource(resource, *cost);
+                    }
+                }
+                spawn.write(SpawnBuilding {
+                    id: game_ids.next(),
+                    name: building.clone(),
+                    pos: *pos,
+                    rotation: *rotation,
+                });
+            }
+            PlayerCommand::Terraform(op) => {
+                terrain_ops.write(*op);
+            }
+            PlayerCommand::Purchase { resource, cost } => {
+                sim.spend_resource(resource, *cost);
+            }
+            PlayerCommand::Research { tech } => {
+                let mut world = sim.script_world.0.lock().unwrap();
+                // the points put into the previous technology are lost
+                if world.research.current.as_ref() != Some(tech) {
+                    world.research.current = Some(tech.clone());
+                    world.research.progress = 0.;
+                }
+            }
+            PlayerCommand::BuildRoad { from, to } => {
+                let cost = road_cost(&check_road(&map, *from, *to).unwrap_or_default());
+                sim.spend_resource(COST_RESOURCE, cost);
+                roads.write(BuildRoad {
+                    from: *from,
+                    to: *to,
+                });
+            }
+            PlayerCommand::DigCanal { from, to } => {
+                sim.spend_resource(COST_RESOURCE, (from.distance(*to) * CANAL_COST) as f64);
+                canals.write(DigCanal {
+                    from: *from,
+                    to: *to,
+                });
+            }
+        }
+    }
+}
+
+fn log_rejections(
+    mut rejected: EventReader<CommandRejected>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) {
+    for rejection in rejected.read() {
+        warn!(
+            "Command of player {} rejected : {}",
+            rejection.player, rejection.reason
+        );
+        toasts.warning(rejection.reason.localized(&localization));
+    }
+}
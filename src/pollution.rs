@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use rhai::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::{BuildId, Building},
+    heatmap::{HeatmapLayer, Heatmaps},
+    map::{BuildingInstance, GRID_SQUARE_SIZE, Map},
+    mapgen::Continent,
+    menu::GameState,
+    script_api::{SharedScriptWorld, run_scripts_with_world},
+    sim::{Sim, sim_running},
+};
+
+/// Size of the cells of the pollution grid, in world units
+pub const CELL_SIZE: f32 = 8.;
+/// Share of the pollution of a cell lost every tick
+const DECAY: f32 = 0.02;
+/// Share of the pollution of a cell spreading to its 4 neighbors every tick
+const DIFFUSION: f32 = 0.1;
+/// Share of the pollution of a cell on a river carried to the next cell downstream every tick
+const RIVER_CARRY: f32 = 0.3;
+/// Amount of water from which a point of the hydrology is a river
+pub const RIVER_AMOUNT: f32 = 20.;
+/// Cells with less pollution are clean
+const MIN_POLLUTION: f32 = 0.01;
+/// Pollution drawn with the full color of the heatmap
+const HEATMAP_FULL: f32 = 20.;
+/// Sim ticks between two repaints of the heatmap
+const HEATMAP_REFRESH_TICKS: u64 = 10;
+
+/// A coarse grid of pollution over the map. Polluting buildings add to it every sim tick,
+/// and scripts with `pollute(x, z, amount)`. It spreads to the neighboring cells, decays,
+/// and is carried down the rivers. Scripts read it with `pollution(x, z)`, and the mean
+/// pollution under the buildings with `pollution_exposure()`. It is drawn by the pollution
+/// heatmap.
+pub struct PollutionPlugin;
+
+impl Plugin for PollutionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            spread_pollution
+                .after(run_scripts_with_world)
+                .run_if(sim_running),
+        )
+        .add_systems(
+            Update,
+            paint_pollution_heatmap.run_if(in_state(GameState::InGame)),
+        );
+    }
+}
+
+pub type Cell = (i32, i32);
+
+/// Cell of the grid a world position is in
+pub fn cell_of(pos: Vec2) -> Cell {
+    let cell = (pos / CELL_SIZE).floor();
+    (cell.x as i32, cell.y as i32)
+}
+
+/// Pollution by cell, shared with the scripts and saved with the sim
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct PollutionGrid {
+    /// Pollution of the cells, missing when clean
+    cells: BTreeMap<Cell, f32>,
+    /// Mean pollution under the buildings
+    pub exposure: f32,
+    /// Cell the river through each cell flows to, found when first needed
+    #[serde(skip)]
+    downstream: HashMap<Cell, Option<Cell>>,
+}
+
+impl PollutionGrid {
+    pub fn get(&self, pos: Vec2) -> f32 {
+        self.cells.get(&cell_of(pos)).copied().unwrap_or(0.)
+    }
+
+    pub fn add(&mut self, pos: Vec2, amount: f32) {
+        *self.cells.entry(cell_of(pos)).or_default() += amount;
+    }
+
+    /// Decay, spread and carry the pollution down the rivers for a tick
+    fn step(&mut self, continent: &Continent) {
+        let mut next = BTreeMap::new();
+        for (cell, value) in std::mem::take(&mut self.cells) {
+            let kept = value * (1. - DECAY);
+            let spread = kept * DIFFUSION;
+            let downstream = *self
+                .downstream
+                .entry(cell)
+                .or_insert_with(|| find_downstream(continent, cell));
+            let carried = downstream.map_or(0., |_| kept * RIVER_CARRY);
+            *next.entry(cell).or_default() += kept - spread - carried;
+            for (dx, dz) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                *next.entry((cell.0 + dx, cell.1 + dz)).or_default() += spread / 4.;
+            }
+            if let Some(down) = downstream {
+                *next.entry(down).or_default() += carried;
+            }
+        }
+        next.retain(|_, value| *value > MIN_POLLUTION);
+        self.cells = next;
+    }
+}
+
+/// Cell the biggest river through a cell flows to, following it downstream
+fn find_downstream(continent: &Continent, cell: Cell) -> Option<Cell> {
+    let min = Vec2::new(cell.0 as f32, cell.1 as f32) * CELL_SIZE;
+    let steps = (CELL_SIZE / GRID_SQUARE_SIZE) as i32;
+    let (mut point, _) = (0..steps)
+        .flat_map(|i| (0..steps).map(move |j| (i, j)))
+        .map(|(i, j)| {
+            let pos = min + Vec2::new(i as f32, j as f32) * GRID_SQUARE_SIZE;
+            continent.from_world(&Vec3::new(pos.x, 0., pos.y))
+        })
+        .map(|(x, y)| ((x, y), continent.get_hydro(x, y).amount))
+        .filter(|(_, amount)| *amount >= RIVER_AMOUNT)
+        .max_by(|(_, a1), (_, a2)| a1.total_cmp(a2))?;
+    // a river crosses the cell in less than twice its size, unless it winds
+    for _ in 0..steps * 4 {
+        point = continent.downstream(point.0, point.1)?;
+        let down = cell_of(continent.to_world(Continent::xy2h(point.0, point.1)).xz());
+        if down != cell {
+            return Some(down);
+        }
+    }
+    None
+}
+
+pub(crate) fn spread_pollution(
+    sim: Res<Sim>,
+    map: Res<Map>,
+    placed: Query<(&BuildingInstance, &BuildId)>,
+    buildings: Res<Assets<Building>>,
+) {
+    let mut world = sim.script_world.0.lock().unwrap();
+    let grid = &mut world.pollution;
+    for (instance, bid) in &placed {
+        let Some(building) = buildings.get(&bid.0) else {
+            continue;
+        };
+        if building.pollution > 0. {
+            grid.add(instance.center(), building.pollution);
+        }
+    }
+    grid.step(&map.continent);
+    let count = placed.iter().count();
+    grid.exposure = if count == 0 {
+        0.
+    } else {
+        placed
+            .iter()
+            .map(|(instance, _)| grid.get(instance.center()))
+            .sum::<f32>()
+            / count as f32
+    };
+}
+
+/// Draw the pollution on its heatmap layer while it is shown, every few ticks
+fn paint_pollution_heatmap(
+    sim: Res<Sim>,
+    mut heatmaps: ResMut<Heatmaps>,
+    mut painted_tick: Local<Option<u64>>,
+) {
+    if heatmaps.shown != Some(HeatmapLayer::Pollution) {
+        *painted_tick = None;
+        return;
+    }
+    if painted_tick.is_some_and(|tick| sim.tick < tick + HEATMAP_REFRESH_TICKS) {
+        return;
+    }
+    *painted_tick = Some(sim.tick);
+    let cells = sim.script_world.0.lock().unwrap().pollution.cells.clone();
+    heatmaps.clear(HeatmapLayer::Pollution);
+    let steps = (CELL_SIZE / GRID_SQUARE_SIZE) as i32;
+    for ((x, z), value) in cells {
+        let min = Vec2::new(x as f32, z as f32) * CELL_SIZE;
+        for i in 0..steps {
+            for j in 0..steps {
+                let pos = min + Vec2::new(i as f32, j as f32) * GRID_SQUARE_SIZE;
+                heatmaps.set(
+                    HeatmapLayer::Pollution,
+                    Vec3::new(pos.x, 0., pos.y),
+                    value / HEATMAP_FULL,
+                );
+            }
+        }
+    }
+}
+
+/// Register `pollution(x, z)`, `pollute(x, z, amount)` and `pollution_exposure()`, the
+/// mean pollution under the buildings
+pub fn register_pollution_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("pollution", move |x: f64, z: f64| -> f64 {
+        let world = w.0.lock().unwrap();
+        world.pollution.get(Vec2::new(x as f32, z as f32)) as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("pollute", move |x: f64, z: f64, amount: f64| {
+        let mut world = w.0.lock().unwrap();
+        world
+            .pollution
+            .add(Vec2::new(x as f32, z as f32), amount.max(0.) as f32);
+    });
+
+    let w = world.clone();
+    engine.register_fn("pollution_exposure", move || -> f64 {
+        w.0.lock().unwrap().pollution.exposure as f64
+    });
+}
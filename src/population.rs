@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use rhai::{Dynamic, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::{BuildId, Building, GameId},
+    land_value::value_around_buildings,
+    map::BuildingInstance,
+    roads::RoadGraph,
+    script_api::SharedScriptWorld,
+    sim::{Sim, sim_running},
+};
+
+/// Sim ticks between two updates of the residents and the jobs
+const POPULATION_TICKS: u64 = 20;
+/// Share of the free housing filled per update by perfectly happy residents
+const MOVE_IN_RATE: f32 = 0.1;
+/// Share of the residents who work
+const WORKING_SHARE: f32 = 0.6;
+/// Longest road distance residents commute to work, in world units
+const MAX_COMMUTE: f32 = 120.;
+/// Pollution making the residents fully unhappy
+const POLLUTION_FULL: f32 = 20.;
+
+/// Residents of the homes and the workers of the workplaces. Buildings house up to their
+/// `housing` residents, who move in faster where they are happy, and offer `jobs`. The
+/// residents work at the nearest workplaces with open jobs within `MAX_COMMUTE` by road.
+/// Building scripts read the residents of a home with `residents(id)` and the share of the
+/// jobs of a workplace filled with `staffing(id)`, to slow down when it lacks workers. The
+/// sim scripts get the totals with `population_stats()`.
+pub struct PopulationPlugin;
+
+impl Plugin for PopulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            update_population
+                .after(value_around_buildings)
+                .run_if(sim_running),
+        );
+    }
+}
+
+/// Totals over the map
+#[derive(Default, Clone, Copy, Debug)]
+pub struct PopulationStats {
+    pub residents: f32,
+    pub housing: f32,
+    pub workforce: f32,
+    pub jobs: f32,
+    pub employed: f32,
+    /// Mean happiness of the residents, between 0 and 1
+    pub happiness: f32,
+}
+
+impl PopulationStats {
+    pub fn unemployment(&self) -> f32 {
+        if self.workforce > 0. {
+            1. - self.employed / self.workforce
+        } else {
+            0.
+        }
+    }
+}
+
+/// Residents and workers, shared with the scripts. Only the residents are saved, the rest
+/// is computed again from them.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Population {
+    /// Residents of each home, by game id
+    pub residents: BTreeMap<u64, f32>,
+    /// Share of the jobs of each workplace filled, by game id
+    #[serde(skip)]
+    pub staffing: BTreeMap<u64, f32>,
+    #[serde(skip)]
+    pub stats: PopulationStats,
+}
+
+struct Home {
+    id: u64,
+    entity: Entity,
+    pos: Vec2,
+    housing: f32,
+}
+
+struct Workplace {
+    id: u64,
+    entity: Entity,
+    jobs: f32,
+    filled: f32,
+}
+
+pub(crate) fn update_population(
+    sim: Res<Sim>,
+    roads: Res<RoadGraph>,
+    placed: Query<(Entity, &GameId, &BuildId, &BuildingInstance)>,
+    buildings: Res<Assets<Building>>,
+) {
+    let mut world = sim.script_world.0.lock().unwrap();
+    if world.tick % POPULATION_TICKS != 0 {
+        return;
+    }
+    let mut homes = Vec::new();
+    let mut workplaces = Vec::new();
+    for (entity, id, bid, instance) in &placed {
+        let Some(building) = buildings.get(&bid.0) else {
+            continue;
+        };
+        if building.housing > 0 {
+            homes.push(Home {
+                id: id.0,
+                entity,
+                pos: instance.center(),
+                housing: building.housing as f32,
+            });
+        }
+        if building.jobs > 0 {
+            workplaces.push(Workplace {
+                id: id.0,
+                entity,
+                jobs: building.jobs as f32,
+                filled: 0.,
+            });
+        }
+    }
+    // in the order they were built, so that replays give the jobs the same way
+    homes.sort_by_key(|home| home.id);
+    workplaces.sort_by_key(|workplace| workplace.id);
+
+    let mut stats = PopulationStats::default();
+    let mut residents = BTreeMap::new();
+    let mut weighted_happiness = 0.;
+    for home in &homes {
+        let pos = home.pos;
+        let current = world
+            .population
+            .residents
+            .get(&home.id)
+            .copied()
+            .unwrap_or(0.);
+        let polluted = (world.pollution.get(pos) / POLLUTION_FULL).min(1.);
+        let mut happiness = (0.5 + 0.5 * world.land_value.get(pos)) * (1. - polluted);
+
+        // the workers go to the nearest workplaces with open jobs
+        let workers = current * WORKING_SHARE;
+        let mut reachable: Vec<_> = workplaces
+            .iter()
+            .enumerate()
+            .filter_map(|(i, w)| Some((i, roads.path_length(home.entity, w.entity)?)))
+            .filter(|(_, distance)| *distance <= MAX_COMMUTE)
+            .collect();
+        reachable.sort_by(|(_, d1), (_, d2)| d1.total_cmp(d2));
+        let mut left = workers;
+        let mut commute = 0.;
+        for (i, distance) in reachable {
+            let workplace = &mut workplaces[i];
+            let hired = left.min(workplace.jobs - workplace.filled);
+            workplace.filled += hired;
+            left -= hired;
+            commute += hired * distance;
+            if left <= 0. {
+                break;
+            }
+        }
+        let employed = workers - left;
+        if employed > 0. {
+            // long commutes make the residents unhappy
+            happiness *= 1. - 0.5 * commute / employed / MAX_COMMUTE;
+        }
+
+        let next = current + (home.housing - current) * MOVE_IN_RATE * happiness;
+        let next = next.min(home.housing);
+        residents.insert(home.id, next);
+        stats.residents += next;
+        stats.housing += home.housing;
+        stats.workforce += workers;
+        stats.employed += employed;
+        weighted_happiness += happiness * next;
+    }
+    stats.happiness = if stats.residents > 0. {
+        weighted_happiness / stats.residents
+    } else {
+        0.
+    };
+    stats.jobs = workplaces.iter().map(|w| w.jobs).sum();
+    world.population.staffing = workplaces
+        .iter()
+        .map(|w| (w.id, w.filled / w.jobs))
+        .collect();
+    world.population.residents = residents;
+    world.population.stats = stats;
+}
+
+/// Register `residents(id)`, `staffing(id)`, between 0 and 1, and `population_stats()`, a map
+/// of the totals : residents, housing, workforce, jobs, employed, unemployment and happiness
+pub fn register_population_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("residents", move |id: i64| -> f64 {
+        let world = w.0.lock().unwrap();
+        world
+            .population
+            .residents
+            .get(&(id as u64))
+            .copied()
+            .unwrap_or(0.) as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("staffing", move |id: i64| -> f64 {
+        let world = w.0.lock().unwrap();
+        world
+            .population
+            .staffing
+            .get(&(id as u64))
+            .copied()
+            .unwrap_or(0.) as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("population_stats", move || -> rhai::Map {
+        let stats = w.0.lock().unwrap().population.stats;
+        let mut map = rhai::Map::new();
+        for (name, value) in [
+            ("residents", stats.residents),
+            ("housing", stats.housing),
+            ("workforce", stats.workforce),
+            ("jobs", stats.jobs),
+            ("employed", stats.employed),
+            ("unemployment", stats.unemployment()),
+            ("happiness", stats.happiness),
+        ] {
+            map.insert(name.into(), Dynamic::from_float(value as f64));
+        }
+        map
+    });
+}
@@ -0,0 +1,201 @@
+use bevy::{math::I64Vec2, platform::collections::HashMap, prelude::*};
+
+use crate::{
+    map::{Chunk, GRID_SQUARE_SIZE, Map},
+    mapgen::Continent,
+    menu::GameState,
+    sim::sim_running,
+};
+
+/// Ephemeral ponds filling the small depressions of loaded chunks when it rains.
+pub struct PuddlePlugin;
+
+impl Plugin for PuddlePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Rainfall(0.));
+        app.insert_resource(Puddles::default());
+        app.add_systems(Startup, setup_puddles);
+        app.add_systems(FixedUpdate, fill_basins.run_if(sim_running));
+        app.add_systems(
+            Update,
+            (find_basins, draw_puddles.after(find_basins)).run_if(in_state(GameState::InGame)),
+        );
+        app.add_systems(OnExit(GameState::InGame), clear_puddles);
+    }
+}
+
+fn clear_puddles(mut commands: Commands, mut puddles: ResMut<Puddles>) {
+    for basin in puddles.basins.drain().flat_map(|(_, basins)| basins) {
+        if let Some(e) = basin.entity {
+            commands.entity(e).despawn();
+        }
+    }
+}
+
+/// Intensity of the rain, between 0 and 1. Driven by the weather.
+#[derive(Resource)]
+pub struct Rainfall(pub f32);
+
+/// Spacing, in grid cells, of the samples used to find basins
+const BASIN_SAMPLE_STEP: u32 = 4;
+/// Radius of a basin, in samples
+const BASIN_RADIUS: i32 = 2;
+/// Maximum depth of a puddle, in world units
+const MAX_PUDDLE_DEPTH: f32 = 1.;
+/// Water height gained per tick under full rain
+const FILL_RATE: f32 = 0.01;
+/// Water height lost per tick
+const DRAIN_RATE: f32 = 0.002;
+
+/// A small depression of the terrain that can hold water
+pub struct Basin {
+    pub center: Vec2,
+    pub radius: f32,
+    /// Height of the lowest point
+    pub bottom: f32,
+    /// Height at which the basin overflows
+    pub spill: f32,
+    /// Current height of the water above the bottom
+    pub water: f32,
+    entity: Option<Entity>,
+}
+
+impl Basin {
+    /// Current water level, in world height
+    pub fn level(&self) -> f32 {
+        self.bottom + self.water
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Puddles {
+    pub basins: HashMap<I64Vec2, Vec<Basin>>,
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+impl Puddles {
+    /// Whether there is water at this position. Agents should avoid these cells.
+    pub fn is_flooded(&self, pos: Vec2) -> bool {
+        self.basins.values().flatten().any(|b| {
+            b.water > 0. && b.center.distance(pos) < b.radius * (b.water / (b.spill - b.bottom))
+        })
+    }
+}
+
+fn setup_puddles(
+    mut puddles: ResMut<Puddles>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    puddles.mesh = meshes.add(Circle::new(1.));
+    puddles.material = materials.add(StandardMaterial {
+        base_color: bevy::color::palettes::css::STEEL_BLUE.with_alpha(0.7).into(),
+        alpha_mode: AlphaMode::Blend,
+        perceptual_roughness: 0.1,
+        ..default()
+    });
+}
+
+/// Find the basins of newly spawned chunks, on a coarse sampling of the terrain. The basins of
+/// the despawned chunks are forgotten, and their puddles despawned.
+fn find_basins(mut commands: Commands, map: Res<Map>, mut puddles: ResMut<Puddles>) {
+    puddles.basins.retain(|pos, basins| {
+        if map.chunks.get(pos).is_some_and(Chunk::is_spawned) {
+            return true;
+        }
+        for e in basins.iter_mut().filter_map(|b| b.entity.take()) {
+            commands.entity(e).despawn();
+        }
+        false
+    });
+    let samples = (Chunk::size() / BASIN_SAMPLE_STEP) as i32;
+    for (pos, chunk) in map.chunks.iter() {
+        if !chunk.is_spawned() || puddles.basins.contains_key(pos) {
+            continue;
+        }
+        let height = |x: i32, z: i32| {
+            chunk.cell_height(x as u32 * BASIN_SAMPLE_STEP, z as u32 * BASIN_SAMPLE_STEP)
+        };
+        let mut basins = Vec::new();
+        for x in BASIN_RADIUS..samples - BASIN_RADIUS {
+            for z in BASIN_RADIUS..samples - BASIN_RADIUS {
+                let bottom = height(x, z);
+                if bottom < Continent::OCEAN_HEIGHT_LIMIT * Chunk::SCALE_Y {
+                    continue;
+                }
+                let is_min = (-1..=1)
+                    .flat_map(|dx| (-1..=1).map(move |dz| (dx, dz)))
+                    .filter(|d| *d != (0, 0))
+                    .all(|(dx, dz)| height(x + dx, z + dz) > bottom);
+                if !is_min {
+                    continue;
+                }
+                // the basin overflows at the lowest point of its rim
+                let spill = (-BASIN_RADIUS..=BASIN_RADIUS)
+                    .flat_map(|dx| (-BASIN_RADIUS..=BASIN_RADIUS).map(move |dz| (dx, dz)))
+                    .filter(|(dx, dz)| dx.abs() == BASIN_RADIUS || dz.abs() == BASIN_RADIUS)
+                    .map(|(dx, dz)| height(x + dx, z + dz))
+                    .fold(f32::INFINITY, f32::min);
+                let spill = spill.min(bottom + MAX_PUDDLE_DEPTH);
+                let world_pos = chunk.get_world_pos().xz()
+                    + Vec2::new(x as f32, z as f32) * (BASIN_SAMPLE_STEP as f32 * GRID_SQUARE_SIZE);
+                basins.push(Basin {
+                    center: world_pos,
+                    radius: BASIN_RADIUS as f32 * BASIN_SAMPLE_STEP as f32 * GRID_SQUARE_SIZE,
+                    bottom,
+                    spill,
+                    water: 0.,
+                    entity: None,
+                });
+            }
+        }
+        puddles.basins.insert(*pos, basins);
+    }
+}
+
+/// Fill the basins with rain, and drain them over time.
+fn fill_basins(rainfall: Res<Rainfall>, mut puddles: ResMut<Puddles>) {
+    for basin in puddles.basins.values_mut().flatten() {
+        basin.water = (basin.water + rainfall.0 * FILL_RATE - DRAIN_RATE)
+            .clamp(0., basin.spill - basin.bottom);
+    }
+}
+
+/// Spawn, resize and despawn the puddle meshes following the water in the basins.
+fn draw_puddles(mut commands: Commands, mut puddles: ResMut<Puddles>) {
+    let Puddles {
+        basins,
+        mesh,
+        material,
+    } = &mut *puddles;
+    for basin in basins.values_mut().flatten() {
+        if basin.water <= 0. {
+            if let Some(e) = basin.entity.take() {
+                commands.entity(e).despawn();
+            }
+            continue;
+        }
+        let fill = basin.water / (basin.spill - basin.bottom);
+        let transform = Transform::from_xyz(basin.center.x, basin.level(), basin.center.y)
+            .with_rotation(Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2))
+            .with_scale(Vec3::splat(basin.radius * fill.sqrt()));
+        match basin.entity {
+            Some(e) => {
+                commands.entity(e).insert(transform);
+            }
+            None => {
+                basin.entity = Some(
+                    commands
+                        .spawn((
+                            Name::new("puddle"),
+                            Mesh3d(mesh.clone()),
+                            MeshMaterial3d(material.clone()),
+                            transform,
+                        ))
+                        .id(),
+                );
+            }
+        }
+    }
+}
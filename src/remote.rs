@@ -0,0 +1,173 @@
+use bevy::{
+    prelude::*,
+    remote::{BrpError, BrpResult, RemoteMethodSystemId, RemoteMethods, error_codes},
+};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::{Value, json};
+
+use crate::{
+    build::{Building, GameId},
+    map::{BuildingInstance, Chunk, Map},
+    menu::GameState,
+    player_commands::{IncomingCommand, PlayerCommand, ProtectedAreas},
+    research::{TechTree, TechTreeHandle},
+    sim::Sim,
+};
+
+/// Player id of the commands sent over the remote protocol
+const REMOTE_PLAYER: u32 = 0;
+
+/// Game specific methods of the Bevy Remote Protocol, so that external tools can inspect
+/// and drive the game over HTTP :
+/// - `game/get_height` `{x, z}` : height of the terrain
+/// - `game/list_buildings` : every placed building
+/// - `game/sim_values` `{prefix?}` : value of the sim stats, by dotted path
+/// - `game/place_building` `{building, x, z, rotation?}` : place a building, as a player would
+///
+/// Must be added after the `RemotePlugin`.
+pub struct GameRemotePlugin;
+
+impl Plugin for GameRemotePlugin {
+    fn build(&self, app: &mut App) {
+        let world = app.world_mut();
+        let methods = [
+            ("game/get_height", world.register_system(get_height)),
+            ("game/list_buildings", world.register_system(list_buildings)),
+            ("game/sim_values", world.register_system(sim_values)),
+            ("game/place_building", world.register_system(place_building)),
+        ];
+        let mut remote_methods = world.resource_mut::<RemoteMethods>();
+        for (name, system) in methods {
+            remote_methods.insert(name, RemoteMethodSystemId::Instant(system));
+        }
+    }
+}
+
+/// Deserialize the parameters of a request
+fn parse_params<T: DeserializeOwned>(params: Option<Value>) -> Result<T, BrpError> {
+    serde_json::from_value(params.unwrap_or(Value::Null)).map_err(|e| BrpError {
+        code: error_codes::INVALID_PARAMS,
+        message: e.to_string(),
+        data: None,
+    })
+}
+
+fn not_in_game() -> BrpError {
+    BrpError {
+        code: error_codes::INTERNAL_ERROR,
+        message: "no game is running".to_string(),
+        data: None,
+    }
+}
+
+#[derive(Deserialize)]
+struct HeightParams {
+    x: f32,
+    z: f32,
+}
+
+/// Height of the terrain, from its chunk if it is loaded, else from the continent
+fn get_height(In(params): In<Option<Value>>, map: Option<Res<Map>>) -> BrpResult {
+    let HeightParams { x, z } = parse_params(params)?;
+    let map = map.ok_or_else(not_in_game)?;
+    let pos = Vec3::new(x, 0., z);
+    let chunk = (pos.xz() / Chunk::world_size()).floor().as_i64vec2();
+    let height = if map.chunks.contains_key(&chunk) {
+        map.get_height(pos)
+    } else {
+        map.continent.get_height(pos)
+    };
+    Ok(json!(height))
+}
+
+fn list_buildings(
+    In(_): In<Option<Value>>,
+    instances: Query<(&BuildingInstance, Option<&GameId>)>,
+    buildings: Res<Assets<Building>>,
+) -> BrpResult {
+    let list: Vec<Value> = instances
+        .iter()
+        .map(|(instance, id)| {
+            let center = instance.center();
+            json!({
+                "id": id.map(|id| id.0),
+                "entity": instance.entity.to_bits(),
+                "building": buildings.get(&instance.building).map(|b| b.name.as_str()),
+                "x": center.x,
+                "z": center.y,
+                "half_extents": [instance.half_extents.x, instance.half_extents.y],
+            })
+        })
+        .collect();
+    Ok(Value::Array(list))
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SimValuesParams {
+    /// Only the stats whose path starts with it
+    prefix: String,
+}
+
+fn sim_values(In(params): In<Option<Value>>, sim: Res<Sim>) -> BrpResult {
+    let SimValuesParams { prefix } = match params {
+        Some(params) => parse_params(Some(params))?,
+        None => default(),
+    };
+    let values: serde_json::Map<String, Value> = sim
+        .stat_names()
+        .filter(|(_, name)| name.starts_with(&prefix))
+        .filter_map(|(id, name)| Some((name.to_string(), json!(sim.stat_value(id)?))))
+        .collect();
+    Ok(Value::Object(values))
+}
+
+#[derive(Deserialize)]
+struct PlaceParams {
+    building: String,
+    x: f32,
+    z: f32,
+    #[serde(default)]
+    rotation: f32,
+}
+
+/// Validate the placement, and send it as a command of the remote player
+fn place_building(
+    In(params): In<Option<Value>>,
+    state: Res<State<GameState>>,
+    map: Option<Res<Map>>,
+    buildings: Res<Assets<Building>>,
+    protected: Res<ProtectedAreas>,
+    sim: Res<Sim>,
+    tree: Res<TechTreeHandle>,
+    trees: Res<Assets<TechTree>>,
+    mut incoming: EventWriter<IncomingCommand>,
+) -> BrpResult {
+    let PlaceParams {
+        building,
+        x,
+        z,
+        rotation,
+    } = parse_params(params)?;
+    let map = map.ok_or_else(not_in_game)?;
+    if *state.get() != GameState::InGame {
+        return Err(not_in_game());
+    }
+    let command = PlayerCommand::Place {
+        building,
+        pos: Vec2::new(x, z),
+        rotation,
+    };
+    if let Err(reason) = command.validate(&map, &buildings, &protected, &sim, trees.get(&tree.0)) {
+        return Err(BrpError {
+            code: error_codes::INVALID_PARAMS,
+            message: reason.to_string(),
+            data: None,
+        });
+    }
+    incoming.write(IncomingCommand {
+        player: REMOTE_PLAYER,
+        command,
+    });
+    Ok(Value::Null)
+}
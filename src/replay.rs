@@ -0,0 +1,444 @@
+use bevy::{prelude::*, render::primitives::Aabb, ui::RelativeCursorPosition};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::{BuildId, Building, BuildingPlaced},
+    input_map::{Action, Actions},
+    map::{BuildingInstance, Map},
+    mapgen::{Continent, WorldGen, WorldPreset, WorldSize},
+    menu::GameState,
+    player_commands::{IncomingCommand, PlayerCommand},
+    replication::TerrainOp,
+    script_api::run_scripts_with_world,
+    sim::{Sim, SimSettings},
+    ui::FontHandle,
+};
+
+/// Recording of the session, and a viewer replaying a recorded session on a timeline.
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Bookmark>();
+        app.insert_resource(ReplayRecorder::default());
+        app.add_systems(
+            Update,
+            (
+                record_session,
+                save_replay,
+                toggle_replay_viewer,
+                scrub_timeline,
+                jump_to_bookmark,
+                update_timeline,
+            )
+                .run_if(in_state(GameState::InGame)),
+        );
+        app.add_systems(FixedUpdate, play_replay.after(run_scripts_with_world));
+        app.add_systems(OnExit(GameState::InGame), stop_recording);
+    }
+}
+
+/// Leaving the game ends the recording, and the replay being watched
+fn stop_recording(
+    mut commands: Commands,
+    mut recorder: ResMut<ReplayRecorder>,
+    timeline: Option<Single<Entity, With<Timeline>>>,
+) {
+    recorder.replay = Replay::default();
+    commands.remove_resource::<ReplayViewer>();
+    if let Some(timeline) = timeline {
+        commands.entity(*timeline).despawn();
+    }
+}
+
+const REPLAY_PATH: &str = "saves/replay.ron";
+/// Tick rate used to fast forward to the scrubbed tick
+const CATCH_UP_TICK_RATE: f64 = 1000.;
+
+/// A recorded session : the world generation settings and every command applied, in order
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Replay {
+    pub seed: u32,
+    #[serde(default)]
+    pub size: WorldSize,
+    #[serde(default)]
+    pub preset: WorldPreset,
+    /// Id of the imported heightmap of the world, if any
+    #[serde(default)]
+    pub heightmap: Option<u64>,
+    pub commands: Vec<RecordedCommand>,
+    pub bookmarks: Vec<Bookmark>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RecordedCommand {
+    pub tick: u64,
+    pub command: PlayerCommand,
+}
+
+/// A notable moment of a session, shown on the replay timeline.
+/// Send it as an event to bookmark the current tick.
+#[derive(Event, Serialize, Deserialize, Clone, Debug)]
+pub struct Bookmark {
+    pub tick: u64,
+    pub label: String,
+}
+
+#[derive(Resource, Default)]
+pub struct ReplayRecorder {
+    pub replay: Replay,
+}
+
+/// The replay being watched. While it exists, the session isn't recorded.
+#[derive(Resource)]
+pub struct ReplayViewer {
+    pub replay: Replay,
+    /// Index of the next command to apply
+    next: usize,
+    /// Tick to fast forward to, with the tick rate to restore once it is reached
+    catch_up: Option<(u64, f64)>,
+}
+
+impl ReplayViewer {
+    fn last_tick(&self) -> u64 {
+        let last_command = self.replay.commands.last().map_or(0, |c| c.tick);
+        let last_bookmark = self.replay.bookmarks.iter().map(|b| b.tick).max();
+        last_command.max(last_bookmark.unwrap_or(0)).max(1)
+    }
+}
+
+/// Record the placements, terraforming and other player commands of the session
+fn record_session(
+    mut recorder: ResMut<ReplayRecorder>,
+    viewer: Option<Res<ReplayViewer>>,
+    sim: Res<Sim>,
+    map: Res<Map>,
+    mut placed: EventReader<BuildingPlaced>,
+    mut terrain_ops: EventReader<TerrainOp>,
+    mut incoming: EventReader<IncomingCommand>,
+    mut bookmarks: EventReader<Bookmark>,
+    placed_buildings: Query<(&BuildId, &Transform, &Aabb)>,
+    buildings: Res<Assets<Building>>,
+) {
+    if viewer.is_some() {
+        placed.clear();
+        terrain_ops.clear();
+        incoming.clear();
+        bookmarks.clear();
+        return;
+    }
+    let replay = &mut recorder.replay;
+    replay.seed = map.worldgen.seed;
+    replay.size = map.worldgen.size;
+    replay.preset = map.worldgen.preset;
+    replay.heightmap = map.worldgen.heightmap;
+    let tick = sim.tick;
+    for BuildingPlaced { entity, .. } in placed.read() {
+        let Ok((bid, transform, aabb)) = placed_buildings.get(*entity) else {
+            continue;
+        };
+        let Some(building) = buildings.get(&bid.0) else {
+            continue;
+        };
+        if replay.bookmarks.is_empty() {
+            replay.bookmarks.push(Bookmark {
+                tick,
+                label: "First building".to_string(),
+            });
+        }
+        let center = Vec3::from(aabb.center) * transform.scale;
+        replay.commands.push(RecordedCommand {
+            tick,
+            command: PlayerCommand::Place {
+                building: building.name.clone(),
+                pos: transform.translation.xz() + center.xz(),
+                rotation: transform.rotation.to_euler(EulerRot::YXZ).0,
+            },
+        });
+    }
+    for op in terrain_ops.read() {
+        replay.commands.push(RecordedCommand {
+            tick: op.tick,
+            command: PlayerCommand::Terraform(*op),
+        });
+    }
+    for IncomingCommand { command, .. } in incoming.read() {
+        // placements and terraforming are recorded once applied
+        if let PlayerCommand::Purchase { .. }
+        | PlayerCommand::Research { .. }
+        | PlayerCommand::BuildRoad { .. }
+        | PlayerCommand::DigCanal { .. } = command
+        {
+            replay.commands.push(RecordedCommand {
+                tick,
+                command: command.clone(),
+            });
+        }
+    }
+    replay.bookmarks.extend(bookmarks.read().cloned());
+}
+
+/// Save the recorded session on pressing F6
+fn save_replay(recorder: Res<ReplayRecorder>, actions: Actions) -> Result {
+    if !actions.just_pressed(Action::SaveReplay) {
+        return Ok(());
+    }
+    std::fs::create_dir_all("saves")?;
+    std::fs::write(
+        REPLAY_PATH,
+        ron::ser::to_string_pretty(&recorder.replay, ron::ser::PrettyConfig::default())?,
+    )?;
+    info!("Replay saved to {}", REPLAY_PATH);
+    Ok(())
+}
+
+#[derive(Component)]
+struct Timeline;
+
+#[derive(Component)]
+struct TimelineFill;
+
+#[derive(Component)]
+struct TimelineLabel;
+
+/// Start watching the saved replay on pressing F7, or stop watching it
+fn toggle_replay_viewer(
+    mut commands: Commands,
+    actions: Actions,
+    viewer: Option<Res<ReplayViewer>>,
+    timeline: Option<Single<Entity, With<Timeline>>>,
+    font: Res<FontHandle>,
+) -> Result {
+    if !actions.just_pressed(Action::ToggleReplayViewer) {
+        return Ok(());
+    }
+    if viewer.is_some() {
+        commands.remove_resource::<ReplayViewer>();
+        if let Some(timeline) = timeline {
+            commands.entity(*timeline).despawn();
+        }
+        info!("Stopped watching the replay");
+        return Ok(());
+    }
+    let replay: Replay = ron::de::from_bytes(&std::fs::read(REPLAY_PATH)?)?;
+    let viewer = ReplayViewer {
+        replay,
+        next: 0,
+        catch_up: None,
+    };
+    let last_tick = viewer.last_tick();
+    commands
+        .spawn((
+            Name::new("replay timeline"),
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(20.),
+                width: Val::Percent(60.),
+                bottom: Val::Px(20.),
+                height: Val::Px(14.),
+                ..default()
+            },
+            BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+            Button,
+            RelativeCursorPosition::default(),
+            Timeline,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Node {
+                    width: Val::Percent(0.),
+                    height: Val::Percent(100.),
+                    ..default()
+                },
+                BackgroundColor(Color::srgb(0.35, 0.75, 0.35)),
+                Pickable::IGNORE,
+                TimelineFill,
+            ));
+            for bookmark in &viewer.replay.bookmarks {
+                parent.spawn((
+                    Node {
+                        position_type: PositionType::Absolute,
+                        left: Val::Percent(100. * bookmark.tick as f32 / last_tick as f32),
+                        width: Val::Px(3.),
+                        height: Val::Percent(100.),
+                        ..default()
+                    },
+                    BackgroundColor(bevy::color::palettes::css::GOLD.into()),
+                    Pickable::IGNORE,
+                ));
+            }
+            parent.spawn((
+                Node {
+                    position_type: PositionType::Absolute,
+                    bottom: Val::Px(16.),
+                    ..default()
+                },
+                Text::default(),
+                TextFont {
+                    font: font.0.clone(),
+                    font_size: 14.,
+                    ..default()
+                },
+                Pickable::IGNORE,
+                TimelineLabel,
+            ));
+        });
+    commands.insert_resource(viewer);
+    commands.run_system_cached(restart_world);
+    info!("Watching the replay from {}", REPLAY_PATH);
+    Ok(())
+}
+
+/// Bring the world back to its initial state : generated terrain, no buildings, fresh sim.
+fn restart_world(
+    mut commands: Commands,
+    mut map: ResMut<Map>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut sim: ResMut<Sim>,
+    mut viewer: ResMut<ReplayViewer>,
+    instances: Query<(Entity, &BuildingInstance)>,
+) {
+    let worldgen = WorldGen {
+        seed: viewer.replay.seed,
+        size: viewer.replay.size,
+        preset: viewer.replay.preset,
+        heightmap: viewer.replay.heightmap,
+        // the chunks in place keep their size
+        chunk_size: map.worldgen.chunk_size,
+    };
+    if map.worldgen != worldgen {
+        warn!(
+            "The replay was recorded on the world {:?}, regenerating the continent",
+            worldgen
+        );
+        map.worldgen = worldgen;
+        map.continent = Continent::new_and_generate(&worldgen, map.continent.is_lazy());
+    }
+    for (e, instance) in &instances {
+        map.entities.remove_one(instance.clone());
+        commands.entity(e).despawn();
+    }
+    map.reset_terrain(&mut meshes);
+    sim.restart();
+    viewer.next = 0;
+}
+
+/// Feed the recorded commands to the world when their tick comes
+fn play_replay(
+    viewer: Option<ResMut<ReplayViewer>>,
+    sim: Res<Sim>,
+    mut commands: EventWriter<IncomingCommand>,
+    mut settings: ResMut<SimSettings>,
+) {
+    let Some(mut viewer) = viewer else {
+        return;
+    };
+    while let Some(recorded) = viewer.replay.commands.get(viewer.next) {
+        if recorded.tick > sim.tick {
+            break;
+        }
+        commands.write(IncomingCommand {
+            player: 0,
+            command: recorded.command.clone(),
+        });
+        viewer.next += 1;
+    }
+    if let Some((target, tick_rate)) = viewer.catch_up {
+        if sim.tick >= target {
+            settings.tick_rate = tick_rate;
+            viewer.catch_up = None;
+        }
+    }
+}
+
+/// Fast forward to a tick. Going back in time replays the session from the start.
+fn seek(
+    commands: &mut Commands,
+    viewer: &mut ReplayViewer,
+    settings: &mut SimSettings,
+    sim: &Sim,
+    target: u64,
+) {
+    if target < sim.tick {
+        commands.run_system_cached(restart_world);
+    }
+    let tick_rate = viewer.catch_up.map_or(settings.tick_rate, |(_, rate)| rate);
+    viewer.catch_up = Some((target, tick_rate));
+    settings.tick_rate = CATCH_UP_TICK_RATE;
+}
+
+/// Click on the timeline to jump to a tick
+fn scrub_timeline(
+    mut commands: Commands,
+    timeline: Option<Single<(&Interaction, &RelativeCursorPosition), With<Timeline>>>,
+    viewer: Option<ResMut<ReplayViewer>>,
+    mut settings: ResMut<SimSettings>,
+    sim: Res<Sim>,
+) {
+    let (Some(timeline), Some(mut viewer)) = (timeline, viewer) else {
+        return;
+    };
+    let (interaction, cursor) = *timeline;
+    if *interaction != Interaction::Pressed {
+        return;
+    }
+    let Some(cursor) = cursor.normalized else {
+        return;
+    };
+    // the cursor position is relative to the center of the node
+    let ratio = (cursor.x + 0.5).clamp(0., 1.);
+    let target = (ratio as f64 * viewer.last_tick() as f64) as u64;
+    seek(&mut commands, &mut viewer, &mut settings, &sim, target);
+}
+
+/// Jump to the previous or next bookmark with [ and ]
+fn jump_to_bookmark(
+    mut commands: Commands,
+    actions: Actions,
+    viewer: Option<ResMut<ReplayViewer>>,
+    mut settings: ResMut<SimSettings>,
+    sim: Res<Sim>,
+) {
+    let Some(mut viewer) = viewer else {
+        return;
+    };
+    let target = if actions.just_pressed(Action::PreviousBookmark) {
+        viewer
+            .replay
+            .bookmarks
+            .iter()
+            .map(|b| b.tick)
+            .filter(|t| *t < sim.tick)
+            .max()
+            .unwrap_or(0)
+    } else if actions.just_pressed(Action::NextBookmark) {
+        let Some(next) = viewer
+            .replay
+            .bookmarks
+            .iter()
+            .map(|b| b.tick)
+            .filter(|t| *t > sim.tick)
+            .min()
+        else {
+            return;
+        };
+        next
+    } else {
+        return;
+    };
+    seek(&mut commands, &mut viewer, &mut settings, &sim, target);
+}
+
+fn update_timeline(
+    viewer: Option<Res<ReplayViewer>>,
+    sim: Res<Sim>,
+    mut fill: Single<&mut Node, With<TimelineFill>>,
+    mut label: Single<&mut Text, With<TimelineLabel>>,
+) {
+    let Some(viewer) = viewer else {
+        return;
+    };
+    let last_tick = viewer.last_tick();
+    fill.width = Val::Percent(100. * (sim.tick.min(last_tick) as f32 / last_tick as f32));
+    label.0 = format!("Replay : tick {} / {}", sim.tick, last_tick);
+}
@@ -0,0 +1,152 @@
+use std::hash::{BuildHasher, Hasher};
+
+use bevy::{math::I64Vec2, prelude::*};
+use foldhash::fast::FixedState;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    map::{Map, PatchOp},
+    menu::GameState,
+    sim::Sim,
+};
+
+/// Multiplayer foundation : terrain edits go through compact op records, applied in the same
+/// order on every client, and the terrain is checksummed periodically to detect divergence.
+pub struct ReplicationPlugin;
+
+impl Plugin for ReplicationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TerrainOp>();
+        app.add_event::<TerrainChecksum>();
+        app.add_event::<RemoteTerrainChecksum>();
+        app.insert_resource(TerrainOpLog::default());
+        app.add_systems(
+            Update,
+            (
+                apply_terrain_ops,
+                checksum_terrain.after(apply_terrain_ops),
+                check_divergence.after(checksum_terrain),
+            )
+                .run_if(in_state(GameState::InGame)),
+        );
+        app.add_systems(OnExit(GameState::InGame), clear_op_log);
+    }
+}
+
+fn clear_op_log(mut log: ResMut<TerrainOpLog>) {
+    *log = TerrainOpLog::default();
+}
+
+/// Number of sim ticks between two terrain checksums
+const CHECKSUM_INTERVAL: u64 = 100;
+/// Number of checksums kept to compare with late remote ones
+const CHECKSUM_HISTORY: usize = 16;
+
+/// A terraform operation, as sent over the network
+#[derive(Event, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TerrainOp {
+    pub op: PatchOp,
+    pub center: Vec3,
+    pub radius: f32,
+    pub strength: f32,
+    /// Sim tick at which the operation was made
+    pub tick: u64,
+}
+
+impl TerrainOp {
+    /// Whether the terrain can apply the operation : a positive radius, a finite strength and
+    /// center
+    pub fn is_valid(&self) -> bool {
+        self.radius.is_finite()
+            && self.radius > 0.
+            && self.strength.is_finite()
+            && self.center.is_finite()
+    }
+}
+
+/// Checksum of the terrain heights at a sim tick, to send to the other clients
+#[derive(Event, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TerrainChecksum {
+    pub tick: u64,
+    pub checksum: u64,
+}
+
+/// Checksum received from another client
+#[derive(Event, Clone, Copy, Debug)]
+pub struct RemoteTerrainChecksum(pub TerrainChecksum);
+
+/// Terrain operations applied so far, and the recent checksums
+#[derive(Resource, Default)]
+pub struct TerrainOpLog {
+    pub applied: Vec<TerrainOp>,
+    pub checksums: Vec<TerrainChecksum>,
+}
+
+/// Apply the terrain operations of the frame, ordered by tick so that every client
+/// ends up with the same heights.
+fn apply_terrain_ops(
+    mut ops: EventReader<TerrainOp>,
+    mut map: ResMut<Map>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut log: ResMut<TerrainOpLog>,
+) {
+    let mut ops: Vec<_> = ops.read().copied().collect();
+    // stable sort : operations of the same tick keep their emission order
+    ops.sort_by_key(|op| op.tick);
+    for op in ops {
+        map.patch_with_strength(&mut meshes, &op.center, op.radius, op.op, op.strength);
+        log.applied.push(op);
+    }
+}
+
+/// Hash the heights of every generated chunk, in a deterministic order.
+pub fn terrain_checksum(map: &Map) -> u64 {
+    let mut chunks: Vec<(&I64Vec2, _)> = map.chunks.iter().collect();
+    chunks.sort_by_key(|(pos, _)| (pos.x, pos.y));
+    let mut h = FixedState::default().build_hasher();
+    for (pos, chunk) in chunks {
+        h.write_i64(pos.x);
+        h.write_i64(pos.y);
+        for height in chunk.heights() {
+            h.write_u32(height.to_bits());
+        }
+    }
+    h.finish()
+}
+
+/// Checksum the terrain every `CHECKSUM_INTERVAL` ticks
+fn checksum_terrain(
+    sim: Res<Sim>,
+    map: Res<Map>,
+    mut log: ResMut<TerrainOpLog>,
+    mut checksums: EventWriter<TerrainChecksum>,
+    mut last_tick: Local<u64>,
+) {
+    if sim.tick == *last_tick || sim.tick % CHECKSUM_INTERVAL != 0 {
+        return;
+    }
+    *last_tick = sim.tick;
+    let checksum = TerrainChecksum {
+        tick: sim.tick,
+        checksum: terrain_checksum(&map),
+    };
+    log.checksums.push(checksum);
+    if log.checksums.len() > CHECKSUM_HISTORY {
+        log.checksums.remove(0);
+    }
+    checksums.write(checksum);
+}
+
+/// Compare the checksums received from other clients with ours
+fn check_divergence(mut remote: EventReader<RemoteTerrainChecksum>, log: Res<TerrainOpLog>) {
+    for RemoteTerrainChecksum(remote) in remote.read() {
+        if let Some(local) = log.checksums.iter().find(|c| c.tick == remote.tick) {
+            if local.checksum != remote.checksum {
+                error!(
+                    "Terrain diverged at tick {} : local {:x}, remote {:x}",
+                    remote.tick, local.checksum, remote.checksum
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::BTreeSet;
+
+use bevy::{
+    asset::{AssetLoader, LoadContext},
+    prelude::*,
+};
+use rhai::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::{BuildId, Building, GameId},
+    input_map::{Action, Actions},
+    localization::{Localization, LocalizedText},
+    player_commands::{IncomingCommand, PlayerCommand},
+    population::update_population,
+    script_api::SharedScriptWorld,
+    sim::{Sim, sim_running},
+    toasts::Toasts,
+    ui::{BuildMenu, FontHandle},
+    versioning::{Migration, Versioned, from_versioned_bytes},
+};
+
+const TECH_TREE: &str = "research/tech_tree.techs";
+
+/// Research of the technologies of the tech tree. Labs (buildings with `research` points)
+/// generate points every sim tick, as much as their jobs are filled, toward the technology
+/// picked on the research screen. Buildings with a `tech` are locked in the build menu until
+/// it is researched. Scripts check the technologies with `tech_unlocked(id)`.
+pub struct ResearchPlugin;
+
+impl Plugin for ResearchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<TechTree>()
+            .init_asset_loader::<TechTreeLoader>()
+            .add_event::<TechUnlocked>()
+            .insert_resource(TechTreeHandle::default())
+            .add_systems(Startup, (load_tech_tree, setup_research_screen))
+            .add_systems(
+                FixedUpdate,
+                advance_research
+                    .after(update_population)
+                    .run_if(sim_running),
+            )
+            .add_systems(
+                Update,
+                (
+                    toast_unlocks,
+                    unlock_build_menu,
+                    toggle_research_screen,
+                    update_research_screen.after(toggle_research_screen),
+                    pick_research,
+                ),
+            );
+    }
+}
+
+/// A technology of the tech tree
+#[derive(Deserialize, Clone, Debug)]
+pub struct Tech {
+    /// Name the buildings and the scripts refer to it with
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Research points needed
+    pub cost: f64,
+    /// Technologies to research before this one
+    #[serde(default)]
+    pub requires: Vec<String>,
+}
+
+#[derive(Asset, TypePath, Debug)]
+pub struct TechTree {
+    pub techs: Vec<Tech>,
+}
+
+impl TechTree {
+    pub fn get(&self, id: &str) -> Option<&Tech> {
+        self.techs.iter().find(|tech| tech.id == id)
+    }
+
+    /// Whether the technology can be researched : not yet, and after its requirements
+    pub fn is_available(&self, research: &Research, id: &str) -> bool {
+        self.get(id).is_some_and(|tech| {
+            !research.unlocked.contains(id)
+                && tech
+                    .requires
+                    .iter()
+                    .all(|required| research.unlocked.contains(required))
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct TechTreeFile {
+    techs: Vec<Tech>,
+}
+
+impl Versioned for TechTreeFile {
+    const VERSION: u32 = 1;
+    const MIGRATIONS: &'static [Migration] = &[];
+}
+
+#[derive(Default)]
+pub struct TechTreeLoader;
+
+impl AssetLoader for TechTreeLoader {
+    type Asset = TechTree;
+
+    type Settings = ();
+
+    type Error = anyhow::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn bevy::asset::io::Reader,
+        _settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let path = load_context.path().display().to_string();
+        let file = from_versioned_bytes::<TechTreeFile>(&bytes, &path)?;
+        for tech in &file.techs {
+            for required in &tech.requires {
+                anyhow::ensure!(
+                    file.techs.iter().any(|t| t.id == *required),
+                    "{} requires the unknown tech {}",
+                    tech.id,
+                    required
+                );
+            }
+        }
+        Ok(TechTree { techs: file.techs })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["techs"]
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct TechTreeHandle(pub Handle<TechTree>);
+
+fn load_tech_tree(mut tree: ResMut<TechTreeHandle>, asset_server: Res<AssetServer>) {
+    tree.0 = asset_server.load(TECH_TREE);
+}
+
+/// Progress of the research, shared with the scripts and saved with the sim
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Research {
+    pub unlocked: BTreeSet<String>,
+    /// Technology being researched
+    pub current: Option<String>,
+    /// Points put into the current technology
+    pub progress: f64,
+    /// Points generated by the labs at the last tick
+    #[serde(skip)]
+    pub rate: f64,
+}
+
+impl Research {
+    /// Whether the technology of the building, if any, is researched
+    pub fn allows(&self, building: &Building) -> bool {
+        building
+            .tech
+            .as_ref()
+            .is_none_or(|tech| self.unlocked.contains(tech))
+    }
+}
+
+/// Sent when a technology is researched
+#[derive(Event, Clone, Debug)]
+pub struct TechUnlocked(pub String);
+
+fn advance_research(
+    sim: Res<Sim>,
+    labs: Query<(&GameId, &BuildId)>,
+    buildings: Res<Assets<Building>>,
+    tree: Res<TechTreeHandle>,
+    trees: Res<Assets<TechTree>>,
+    mut unlocks: EventWriter<TechUnlocked>,
+) {
+    let mut world = sim.script_world.0.lock().unwrap();
+    let rate = labs
+        .iter()
+        .filter_map(|(id, bid)| Some((id, buildings.get(&bid.0)?)))
+        .filter(|(_, building)| building.research > 0.)
+        .map(|(id, building)| {
+            // labs without workers don't research
+            let staffing = if building.jobs > 0 {
+                world.population.staffing.get(&id.0).copied().unwrap_or(0.)
+            } else {
+                1.
+            };
+            (building.research * staffing) as f64
+        })
+        .sum();
+    let research = &mut world.research;
+    research.rate = rate;
+    let Some(tree) = trees.get(&tree.0) else {
+        return;
+    };
+    let Some(current) = research.current.clone() else {
+        return;
+    };
+    if !tree.is_available(research, &current) {
+        research.current = None;
+        research.progress = 0.;
+        return;
+    }
+    research.progress += rate;
+    if research.progress >= tree.get(&current).unwrap().cost {
+        research.unlocked.insert(current.clone());
+        research.current = None;
+        research.progress = 0.;
+        unlocks.write(TechUnlocked(current));
+    }
+}
+
+fn toast_unlocks(
+    mut unlocks: EventReader<TechUnlocked>,
+    mut toasts: ResMut<Toasts>,
+    tree: Res<TechTreeHandle>,
+    trees: Res<Assets<TechTree>>,
+    localization: Res<Localization>,
+) {
+    for TechUnlocked(id) in unlocks.read() {
+        let name = trees
+            .get(&tree.0)
+            .and_then(|tree| tree.get(id))
+            .map_or(id.clone(), |tech| tech.name.clone());
+        info!("Researched {}", name);
+        toasts.achievement(localization.get_args("toast-tech-unlocked", &[("tech", name)]));
+    }
+}
+
+/// Show the buildings of the researched technologies in the build menu
+fn unlock_build_menu(sim: Res<Sim>, mut menu: ResMut<BuildMenu>) {
+    let world = sim.script_world.0.lock().unwrap();
+    // only touch the menu when it changes, it is rebuilt on every change
+    if *menu.unlocked() != world.research.unlocked {
+        menu.set_unlocked(world.research.unlocked.clone());
+    }
+}
+
+#[derive(Component)]
+struct ResearchScreen;
+
+/// Pick the technology to research
+#[derive(Component)]
+struct TechButton(String);
+
+fn setup_research_screen(mut commands: Commands) {
+    commands.spawn((
+        Name::new("research screen"),
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Percent(25.),
+            top: Val::Percent(10.),
+            width: Val::Percent(50.),
+            max_height: Val::Percent(80.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(6.),
+            padding: UiRect::all(Val::Px(10.)),
+            overflow: Overflow::scroll_y(),
+            ..default()
+        },
+        BackgroundColor(bevy::color::palettes::css::BLACK.with_alpha(0.8).into()),
+        GlobalZIndex(5),
+        Visibility::Hidden,
+        ResearchScreen,
+    ));
+}
+
+fn toggle_research_screen(
+    actions: Actions,
+    mut screen: Single<&mut Visibility, With<ResearchScreen>>,
+) {
+    if actions.just_pressed(Action::ToggleResearch) {
+        screen.toggle_visible_hidden();
+    }
+}
+
+/// List the technologies with their state while the screen is shown. It is rebuilt when
+/// the research moves on by a percent.
+fn update_research_screen(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    screen: Single<(Entity, &Visibility), With<ResearchScreen>>,
+    tree: Res<TechTreeHandle>,
+    trees: Res<Assets<TechTree>>,
+    buildings: Res<Assets<Building>>,
+    font: Res<FontHandle>,
+    mut shown: Local<Option<(BTreeSet<String>, Option<String>, i64, i64)>>,
+) {
+    let (entity, visibility) = *screen;
+    if *visibility == Visibility::Hidden {
+        *shown = None;
+        return;
+    }
+    let Some(tree) = trees.get(&tree.0) else {
+        return;
+    };
+    let research = sim.script_world.0.lock().unwrap().research.clone();
+    let percent = |tech: &Tech| (research.progress / tech.cost * 100.) as i64;
+    let current_percent = research
+        .current
+        .as_ref()
+        .and_then(|id| tree.get(id))
+        .map_or(0, percent);
+    let state = (
+        research.unlocked.clone(),
+        research.current.clone(),
+        current_percent,
+        (research.rate * 100.).round() as i64,
+    );
+    if shown.as_ref() == Some(&state) && !trees.is_changed() {
+        return;
+    }
+    *shown = Some(state);
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 16.,
+        ..default()
+    };
+    let small_font = TextFont {
+        font_size: 12.,
+        ..text_font.clone()
+    };
+    commands.entity(entity).despawn_related::<Children>();
+    commands.entity(entity).with_children(|parent| {
+        parent.spawn((
+            LocalizedText::new("research-title"),
+            TextFont {
+                font_size: 24.,
+                ..text_font.clone()
+            },
+            Label,
+        ));
+        parent.spawn((
+            LocalizedText::new("research-rate").with_arg("rate", format!("{:.2}", research.rate)),
+            small_font.clone(),
+            Label,
+        ));
+        for tech in &tree.techs {
+            let unlocked = research.unlocked.contains(&tech.id);
+            let researching = research.current.as_ref() == Some(&tech.id);
+            let (status, color) = if unlocked {
+                (
+                    LocalizedText::new("research-done"),
+                    bevy::color::palettes::css::LIGHT_GREEN.into(),
+                )
+            } else if researching {
+                (
+                    LocalizedText::new("research-progress").with_arg("progress", current_percent),
+                    bevy::color::palettes::css::GOLD.into(),
+                )
+            } else if tree.is_available(&research, &tech.id) {
+                (
+                    LocalizedText::new("research-cost").with_arg("cost", tech.cost),
+                    Color::WHITE,
+                )
+            } else {
+                let missing: Vec<_> = tech
+                    .requires
+                    .iter()
+                    .filter(|id| !research.unlocked.contains(*id))
+                    .map(|id| tree.get(id).map_or(id.as_str(), |t| t.name.as_str()))
+                    .collect();
+                (
+                    LocalizedText::new("research-requires").with_arg("techs", missing.join(", ")),
+                    Color::srgb(0.5, 0.5, 0.5),
+                )
+            };
+            let unlocks: Vec<_> = buildings
+                .iter()
+                .filter(|(_, b)| b.tech.as_ref() == Some(&tech.id))
+                .map(|(_, b)| b.name.clone())
+                .collect();
+            let mut row = parent.spawn((
+                Node {
+                    flex_direction: FlexDirection::Column,
+                    padding: UiRect::all(Val::Px(6.)),
+                    border: UiRect::all(Val::Px(2.)),
+                    ..default()
+                },
+                BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                BorderColor(if researching { color } else { Color::BLACK }),
+            ));
+            // only the technologies that can be researched are buttons
+            if !unlocked && !researching && tree.is_available(&research, &tech.id) {
+                row.insert((Button, TechButton(tech.id.clone())));
+            }
+            row.with_children(|parent| {
+                parent.spawn((
+                    Text(tech.name.clone()),
+                    text_font.clone(),
+                    TextColor(color),
+                    Label,
+                    Pickable::IGNORE,
+                ));
+                parent.spawn((
+                    status,
+                    small_font.clone(),
+                    TextColor(color),
+                    Label,
+                    Pickable::IGNORE,
+                ));
+                if !tech.description.is_empty() {
+                    parent.spawn((
+                        Text(tech.description.clone()),
+                        small_font.clone(),
+                        Label,
+                        Pickable::IGNORE,
+                    ));
+                }
+                if !unlocks.is_empty() {
+                    parent.spawn((
+                        LocalizedText::new("research-unlocks")
+                            .with_arg("buildings", unlocks.join(", ")),
+                        small_font.clone(),
+                        Label,
+                        Pickable::IGNORE,
+                    ));
+                }
+            });
+        }
+    });
+}
+
+/// Research the clicked technology, as a player command so that replays and the other
+/// players research it too
+fn pick_research(
+    buttons: Query<(&Interaction, &TechButton), Changed<Interaction>>,
+    mut incoming: EventWriter<IncomingCommand>,
+) {
+    for (interaction, TechButton(tech)) in &buttons {
+        if *interaction == Interaction::Pressed {
+            incoming.write(IncomingCommand {
+                player: 0,
+                command: PlayerCommand::Research { tech: tech.clone() },
+            });
+        }
+    }
+}
+
+/// Register `tech_unlocked(id)`
+pub fn register_research_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("tech_unlocked", move |id: &str| -> bool {
+        w.0.lock().unwrap().research.unlocked.contains(id)
+    });
+}
@@ -0,0 +1,259 @@
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::{math::FloatOrd, platform::collections::HashMap, prelude::*};
+
+use crate::{
+    build::{BuildId, Building, BuildingPlaced, BuildingRemoved},
+    map::{BuildingInstance, Map},
+    menu::GameState,
+};
+
+/// Road graph, and driveways connecting new buildings to it.
+pub struct RoadPlugin;
+
+impl Plugin for RoadPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(RoadGraph::default());
+        app.add_systems(
+            Update,
+            (connect_to_roads, forget_entrances, draw_roads).run_if(in_state(GameState::InGame)),
+        );
+        app.add_systems(OnExit(GameState::InGame), clear_roads);
+    }
+}
+
+fn clear_roads(mut roads: ResMut<RoadGraph>) {
+    *roads = RoadGraph::default();
+}
+
+/// Driveways are only built to roads closer than this
+const MAX_DRIVEWAY_LENGTH: f32 = 15.;
+/// Ends of segments closer than this are joined into the same node
+const NODE_MERGE_DISTANCE: f32 = 0.01;
+/// Half the width of the bridge decks and the tunnels, in world units
+pub const DECK_HALF_WIDTH: f32 = 1.;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RoadKind {
+    Road,
+    Driveway,
+    /// A road over the water, on a deck above the terrain
+    Bridge,
+    /// A road through a mountain, under the terrain
+    Tunnel,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RoadEdge {
+    pub a: usize,
+    pub b: usize,
+    pub kind: RoadKind,
+}
+
+/// Graph of the roads, that vehicles travel on
+#[derive(Resource, Default)]
+pub struct RoadGraph {
+    pub nodes: Vec<Vec2>,
+    pub edges: Vec<RoadEdge>,
+    /// Node at the end of the driveway of each connected building
+    pub entrances: HashMap<Entity, usize>,
+    /// Height of the road at both ends of the bridge and tunnel edges, by edge
+    pub levels: HashMap<usize, (f32, f32)>,
+}
+
+impl RoadGraph {
+    pub fn add_node(&mut self, pos: Vec2) -> usize {
+        self.nodes.push(pos);
+        self.nodes.len() - 1
+    }
+
+    /// Node at a position, or a new one
+    fn node_at(&mut self, pos: Vec2) -> usize {
+        self.nodes
+            .iter()
+            .position(|node| node.distance(pos) < NODE_MERGE_DISTANCE)
+            .unwrap_or_else(|| self.add_node(pos))
+    }
+
+    /// Add a segment, joined to the nodes already at its ends
+    pub fn add_segment(&mut self, from: Vec2, to: Vec2, kind: RoadKind) -> (usize, usize) {
+        let a = self.node_at(from);
+        let b = self.node_at(to);
+        self.edges.push(RoadEdge { a, b, kind });
+        (a, b)
+    }
+
+    /// Node at a position : one already there, or a new one splitting the road passing
+    /// there, so that new roads join the ones they start or end on
+    pub fn attach(&mut self, pos: Vec2) -> usize {
+        if let Some(node) = self
+            .nodes
+            .iter()
+            .position(|node| node.distance(pos) < NODE_MERGE_DISTANCE)
+        {
+            return node;
+        }
+        match self.nearest_road_point(pos) {
+            Some((edge, point)) if point.distance(pos) < NODE_MERGE_DISTANCE => {
+                self.split_edge(edge, pos)
+            }
+            _ => self.add_node(pos),
+        }
+    }
+
+    /// Height of the road at a position where it leaves the terrain, on a bridge deck or in a
+    /// tunnel. The water under the bridges can be crossed there.
+    pub fn road_height(&self, pos: Vec2) -> Option<f32> {
+        self.levels.iter().find_map(|(edge, (h_a, h_b))| {
+            let edge = self.edges[*edge];
+            let (a, b) = (self.nodes[edge.a], self.nodes[edge.b]);
+            let t = (pos - a).dot(b - a) / (b - a).length_squared().max(f32::EPSILON);
+            let on_deck = (0. ..=1.).contains(&t) && a.lerp(b, t).distance(pos) < DECK_HALF_WIDTH;
+            on_deck.then(|| h_a.lerp(*h_b, t))
+        })
+    }
+
+    /// Nearest point on a road (driveways excluded), with the edge it is on
+    pub fn nearest_road_point(&self, pos: Vec2) -> Option<(usize, Vec2)> {
+        self.edges
+            .iter()
+            .enumerate()
+            .filter(|(_, edge)| edge.kind == RoadKind::Road)
+            .map(|(i, edge)| {
+                let (a, b) = (self.nodes[edge.a], self.nodes[edge.b]);
+                let t = ((pos - a).dot(b - a) / (b - a).length_squared().max(f32::EPSILON))
+                    .clamp(0., 1.);
+                (i, a.lerp(b, t))
+            })
+            .min_by(|(_, p1), (_, p2)| p1.distance(pos).total_cmp(&p2.distance(pos)))
+    }
+
+    /// Split an edge at a point, returning the node created there
+    pub fn split_edge(&mut self, edge: usize, at: Vec2) -> usize {
+        let node = self.add_node(at);
+        let RoadEdge { b, kind, .. } = self.edges[edge];
+        self.edges[edge].b = node;
+        self.edges.push(RoadEdge { a: node, b, kind });
+        node
+    }
+
+    /// Shortest path between two nodes with A*, as the nodes along it from `from` to `to`
+    pub fn find_path(&self, from: usize, to: usize) -> Option<Vec<usize>> {
+        let mut neighbors = vec![Vec::new(); self.nodes.len()];
+        for edge in &self.edges {
+            neighbors[edge.a].push(edge.b);
+            neighbors[edge.b].push(edge.a);
+        }
+        let estimate = |node: usize| self.nodes[node].distance(self.nodes[to]);
+        let mut cost = vec![f32::INFINITY; self.nodes.len()];
+        let mut came_from = vec![None; self.nodes.len()];
+        let mut open = BinaryHeap::new();
+        cost[from] = 0.;
+        open.push(Reverse((FloatOrd(estimate(from)), from)));
+        while let Some(Reverse((_, node))) = open.pop() {
+            if node == to {
+                let mut path = vec![to];
+                while let Some(previous) = came_from[*path.last().unwrap()] {
+                    path.push(previous);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for &next in &neighbors[node] {
+                let c = cost[node] + self.nodes[node].distance(self.nodes[next]);
+                if c < cost[next] {
+                    cost[next] = c;
+                    came_from[next] = Some(node);
+                    open.push(Reverse((FloatOrd(c + estimate(next)), next)));
+                }
+            }
+        }
+        None
+    }
+
+    /// Shortest path between the entrances of two buildings, as the positions along it
+    pub fn path_between(&self, from: Entity, to: Entity) -> Option<Vec<Vec2>> {
+        let path = self.find_path(*self.entrances.get(&from)?, *self.entrances.get(&to)?)?;
+        Some(path.into_iter().map(|node| self.nodes[node]).collect())
+    }
+
+    /// Length of the shortest path between the entrances of two buildings
+    pub fn path_length(&self, from: Entity, to: Entity) -> Option<f32> {
+        let path = self.path_between(from, to)?;
+        Some(path.windows(2).map(|pair| pair[0].distance(pair[1])).sum())
+    }
+}
+
+/// Build a driveway from the entrance of the buildings that need a road to the nearest road,
+/// when they are placed, or when new roads may reach the ones still without a driveway
+fn connect_to_roads(
+    mut placed: EventReader<BuildingPlaced>,
+    mut roads: ResMut<RoadGraph>,
+    buildings: Res<Assets<Building>>,
+    placed_buildings: Query<(Entity, &BuildId, &Transform, &BuildingInstance)>,
+) {
+    let mut waiting: Vec<Entity> = placed.read().map(|placed| placed.entity).collect();
+    if roads.is_changed() {
+        waiting.extend(
+            placed_buildings
+                .iter()
+                .map(|(entity, ..)| entity)
+                .filter(|entity| !roads.entrances.contains_key(entity)),
+        );
+    }
+    for entity in waiting {
+        let Ok((_, bid, transform, instance)) = placed_buildings.get(entity) else {
+            continue;
+        };
+        if roads.entrances.contains_key(&entity) {
+            continue;
+        }
+        let Some(building) = buildings.get(&bid.0).filter(|b| b.needs_road) else {
+            continue;
+        };
+        let entrance = instance.center()
+            + (transform.rotation * Vec3::new(building.entrance.x, 0., building.entrance.y)).xz();
+        let Some((edge, road_point)) = roads.nearest_road_point(entrance) else {
+            continue;
+        };
+        if road_point.distance(entrance) > MAX_DRIVEWAY_LENGTH {
+            continue;
+        }
+        let road_node = roads.split_edge(edge, road_point);
+        let entrance_node = roads.add_node(entrance);
+        roads.edges.push(RoadEdge {
+            a: road_node,
+            b: entrance_node,
+            kind: RoadKind::Driveway,
+        });
+        roads.entrances.insert(entity, entrance_node);
+    }
+}
+
+/// Removed buildings can't be driven to anymore. Their driveway stays.
+fn forget_entrances(mut removed: EventReader<BuildingRemoved>, mut roads: ResMut<RoadGraph>) {
+    for BuildingRemoved { entity, .. } in removed.read() {
+        roads.entrances.remove(entity);
+    }
+}
+
+fn draw_roads(roads: Res<RoadGraph>, map: Res<Map>, mut gizmos: Gizmos) {
+    for (i, edge) in roads.edges.iter().enumerate() {
+        let [mut a, mut b] = [roads.nodes[edge.a], roads.nodes[edge.b]].map(|p| {
+            let p = Vec3::new(p.x, 0., p.y);
+            p.with_y(map.get_height(p) + 0.1)
+        });
+        if let Some((h_a, h_b)) = roads.levels.get(&i) {
+            a.y = h_a + 0.1;
+            b.y = h_b + 0.1;
+        }
+        let color = match edge.kind {
+            RoadKind::Road => bevy::color::palettes::css::DARK_GRAY,
+            RoadKind::Driveway => bevy::color::palettes::css::TAN,
+            RoadKind::Bridge => bevy::color::palettes::css::SADDLE_BROWN,
+            // hidden under the mountains
+            RoadKind::Tunnel => continue,
+        };
+        gizmos.line(a, b, color);
+    }
+}
@@ -0,0 +1,407 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::{
+    asset::RenderAssetUsages,
+    image::{CompressedImageFormats, ImageSampler, ImageType},
+    prelude::*,
+};
+
+use crate::{
+    localization::{Localization, LocalizedText},
+    pause_menu::PausePage,
+    save_game::{
+        GameSaved, LoadGameRequest, SaveEntry, SaveGameRequest, delete_save, list_saves, save_path,
+        thumbnail_path,
+    },
+    toasts::Toasts,
+    ui::FontHandle,
+};
+
+const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+const DANGER_BUTTON: Color = Color::srgb(0.45, 0.12, 0.12);
+const THUMBNAIL_DISPLAY_SIZE: f32 = 96.;
+
+/// Saves page of the pause menu : the saves with when they were made, the time played, the
+/// seed of their world and a thumbnail of the map. A new save can be made, and the saves
+/// loaded, overwritten or deleted, the last two after a confirmation.
+pub struct SaveBrowserPlugin;
+
+impl Plugin for SaveBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(PausePage::Saves), setup_saves_page)
+            .add_systems(
+                Update,
+                (browser_buttons, refresh_save_list.after(browser_buttons))
+                    .run_if(in_state(PausePage::Saves)),
+            );
+    }
+}
+
+/// Container of the rows of the saves, listed again when `dirty`
+#[derive(Component)]
+struct SaveList {
+    saves: Vec<SaveEntry>,
+    dirty: bool,
+}
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum BrowserButton {
+    New,
+    /// Buttons of a save, by index in the `SaveList`
+    Load(usize),
+    Overwrite(usize),
+    Delete(usize),
+    Confirm,
+    Cancel,
+    Back,
+}
+
+/// Dialog asking to confirm overwriting or deleting a save
+#[derive(Component)]
+struct ConfirmDialog(Confirmation);
+
+#[derive(Clone)]
+enum Confirmation {
+    Overwrite(String),
+    Delete(String),
+}
+
+fn text_font(font: &FontHandle) -> TextFont {
+    TextFont {
+        font: font.0.clone(),
+        font_size: 20.,
+        ..default()
+    }
+}
+
+fn browser_button(
+    button: BrowserButton,
+    label: &'static str,
+    color: Color,
+    text_font: &TextFont,
+) -> impl Bundle {
+    (
+        Button,
+        Node {
+            padding: UiRect::all(Val::Px(8.)),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        BackgroundColor(color),
+        button,
+        children![(
+            LocalizedText::new(label),
+            text_font.clone(),
+            Label,
+            Pickable::IGNORE
+        )],
+    )
+}
+
+fn setup_saves_page(mut commands: Commands, font: Res<FontHandle>) {
+    let text_font = text_font(&font);
+    commands.spawn((
+        Name::new("saves"),
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            row_gap: Val::Px(10.),
+            ..default()
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.7)),
+        GlobalZIndex(25),
+        StateScoped(PausePage::Saves),
+        children![
+            (
+                LocalizedText::new("saves-title"),
+                TextFont {
+                    font_size: 40.,
+                    ..text_font.clone()
+                },
+                Label,
+            ),
+            browser_button(BrowserButton::New, "saves-new", NORMAL_BUTTON, &text_font),
+            (
+                Node {
+                    width: Val::Px(640.),
+                    max_height: Val::Percent(60.),
+                    flex_direction: FlexDirection::Column,
+                    row_gap: Val::Px(6.),
+                    overflow: Overflow::scroll_y(),
+                    ..default()
+                },
+                SaveList {
+                    saves: Vec::new(),
+                    dirty: true,
+                },
+            ),
+            browser_button(BrowserButton::Back, "saves-back", NORMAL_BUTTON, &text_font),
+        ],
+    ));
+}
+
+/// List the saves again when the page opens, and after a save is written or deleted
+fn refresh_save_list(
+    mut commands: Commands,
+    list: Single<(Entity, &mut SaveList)>,
+    mut saved: EventReader<GameSaved>,
+    mut images: ResMut<Assets<Image>>,
+    font: Res<FontHandle>,
+) {
+    let (list_entity, mut list) = list.into_inner();
+    if saved.read().count() == 0 && !list.dirty {
+        return;
+    }
+    list.dirty = false;
+    list.saves = list_saves();
+    let text_font = text_font(&font);
+    let small_font = TextFont {
+        font_size: 16.,
+        ..text_font.clone()
+    };
+    commands
+        .entity(list_entity)
+        .despawn_related::<Children>()
+        .with_children(|parent| {
+            if list.saves.is_empty() {
+                parent.spawn((LocalizedText::new("saves-empty"), text_font.clone(), Label));
+            }
+            for (i, save) in list.saves.iter().enumerate() {
+                let thumbnail = load_thumbnail(&save.path)
+                    .map(|image| ImageNode::new(images.add(image)))
+                    .unwrap_or_default();
+                let details = LocalizedText::new("saves-details")
+                    .with_arg("seed", save.meta.worldgen.seed)
+                    .with_arg("time", format_play_time(save.meta.play_time));
+                parent.spawn((
+                    Node {
+                        column_gap: Val::Px(10.),
+                        align_items: AlignItems::Center,
+                        padding: UiRect::all(Val::Px(4.)),
+                        ..default()
+                    },
+                    BackgroundColor(Color::BLACK.with_alpha(0.4)),
+                    children![
+                        (
+                            Node {
+                                width: Val::Px(THUMBNAIL_DISPLAY_SIZE),
+                                height: Val::Px(THUMBNAIL_DISPLAY_SIZE),
+                                ..default()
+                            },
+                            thumbnail,
+                        ),
+                        (
+                            Node {
+                                flex_grow: 1.,
+                                flex_direction: FlexDirection::Column,
+                                ..default()
+                            },
+                            children![
+                                (
+                                    Text::new(format_timestamp(save.meta.saved_at)),
+                                    text_font.clone(),
+                                    Label,
+                                ),
+                                (details, small_font.clone(), Label),
+                            ],
+                        ),
+                        browser_button(
+                            BrowserButton::Load(i),
+                            "saves-load",
+                            NORMAL_BUTTON,
+                            &text_font
+                        ),
+                        browser_button(
+                            BrowserButton::Overwrite(i),
+                            "saves-overwrite",
+                            NORMAL_BUTTON,
+                            &text_font
+                        ),
+                        browser_button(
+                            BrowserButton::Delete(i),
+                            "saves-delete",
+                            DANGER_BUTTON,
+                            &text_font
+                        ),
+                    ],
+                ));
+            }
+        });
+}
+
+fn load_thumbnail(path: &str) -> Option<Image> {
+    let bytes = std::fs::read(thumbnail_path(path)).ok()?;
+    Image::from_buffer(
+        &bytes,
+        ImageType::Extension("png"),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+    .inspect_err(|e| warn!("Invalid thumbnail for {} : {}", path, e))
+    .ok()
+}
+
+fn browser_buttons(
+    mut commands: Commands,
+    buttons: Query<(&Interaction, &BrowserButton), Changed<Interaction>>,
+    dialogs: Query<(Entity, &ConfirmDialog)>,
+    mut list: Single<&mut SaveList>,
+    mut save_requests: EventWriter<SaveGameRequest>,
+    mut load_requests: EventWriter<LoadGameRequest>,
+    mut next_page: ResMut<NextState<PausePage>>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+    font: Res<FontHandle>,
+) -> Result {
+    let Some((_, button)) = buttons
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Pressed)
+    else {
+        return Ok(());
+    };
+    match *button {
+        BrowserButton::New => {
+            let time = SystemTime::now().duration_since(UNIX_EPOCH)?;
+            let path = save_path(&format!("save-{}", time.as_millis()));
+            save_requests.write(SaveGameRequest { path });
+        }
+        BrowserButton::Load(i) => {
+            if let Some(save) = list.saves.get(i) {
+                load_requests.write(LoadGameRequest {
+                    path: save.path.clone(),
+                });
+            }
+        }
+        BrowserButton::Overwrite(i) | BrowserButton::Delete(i) => {
+            let Some(save) = list.saves.get(i) else {
+                return Ok(());
+            };
+            let (confirmation, key) = match *button {
+                BrowserButton::Overwrite(_) => (
+                    Confirmation::Overwrite(save.path.clone()),
+                    "saves-confirm-overwrite",
+                ),
+                _ => (
+                    Confirmation::Delete(save.path.clone()),
+                    "saves-confirm-delete",
+                ),
+            };
+            for (dialog, _) in &dialogs {
+                commands.entity(dialog).despawn();
+            }
+            let question =
+                LocalizedText::new(key).with_arg("date", format_timestamp(save.meta.saved_at));
+            spawn_confirm_dialog(&mut commands, confirmation, question, &font);
+        }
+        BrowserButton::Confirm => {
+            for (dialog, ConfirmDialog(confirmation)) in &dialogs {
+                match confirmation {
+                    Confirmation::Overwrite(path) => {
+                        save_requests.write(SaveGameRequest { path: path.clone() });
+                    }
+                    Confirmation::Delete(path) => {
+                        delete_save(path)?;
+                        info!("Save {} deleted", path);
+                        toasts.info(localization.get("toast-save-deleted"));
+                        list.dirty = true;
+                    }
+                }
+                commands.entity(dialog).despawn();
+            }
+        }
+        BrowserButton::Cancel => {
+            for (dialog, _) in &dialogs {
+                commands.entity(dialog).despawn();
+            }
+        }
+        BrowserButton::Back => next_page.set(PausePage::Main),
+    }
+    Ok(())
+}
+
+fn spawn_confirm_dialog(
+    commands: &mut Commands,
+    confirmation: Confirmation,
+    question: LocalizedText,
+    font: &FontHandle,
+) {
+    let text_font = text_font(font);
+    commands.spawn((
+        Name::new("confirm dialog"),
+        Node {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.),
+            height: Val::Percent(100.),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        BackgroundColor(Color::BLACK.with_alpha(0.5)),
+        GlobalZIndex(26),
+        StateScoped(PausePage::Saves),
+        ConfirmDialog(confirmation),
+        children![(
+            Node {
+                flex_direction: FlexDirection::Column,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(10.),
+                padding: UiRect::all(Val::Px(16.)),
+                ..default()
+            },
+            BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
+            children![
+                (question, text_font.clone(), Label),
+                (
+                    Node {
+                        column_gap: Val::Px(10.),
+                        ..default()
+                    },
+                    children![
+                        browser_button(
+                            BrowserButton::Confirm,
+                            "saves-yes",
+                            DANGER_BUTTON,
+                            &text_font
+                        ),
+                        browser_button(
+                            BrowserButton::Cancel,
+                            "saves-no",
+                            NORMAL_BUTTON,
+                            &text_font
+                        ),
+                    ],
+                ),
+            ],
+        )],
+    ));
+}
+
+/// Date and time of a unix timestamp, in UTC, as "YYYY-MM-DD HH:MM"
+fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let (hour, minute) = (secs % 86400 / 3600, secs % 3600 / 60);
+    // civil date from the number of days since the epoch, by Howard Hinnant
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{year}-{month:02}-{day:02} {hour:02}:{minute:02}")
+}
+
+/// Time played, as hours and minutes
+fn format_play_time(secs: f64) -> String {
+    let minutes = (secs / 60.) as u64;
+    format!("{}h{:02}", minutes / 60, minutes % 60)
+}
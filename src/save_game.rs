@@ -0,0 +1,491 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    CameraTarget,
+    build::{
+        BuildId, Building, Disabled, GameId, GameIds, PendingRestore, SavedShapes,
+        spawn_building_model,
+    },
+    fog_of_war::{Exploration, ExploredChunk},
+    localization::Localization,
+    map::{BuildingInstance, Chunk, ChunkEdits, Map, WORLDGEN_VERSION},
+    mapgen::{Continent, WorldGen},
+    menu::GameState,
+    pause_menu::Pause,
+    signs::SignLabel,
+    sim::{Sim, SimSave, SimSettings},
+    toasts::Toasts,
+    versioning::{Migration, Versioned, field_mut, from_versioned_bytes},
+};
+
+/// Version of the save format, to bump with a migration whenever it changes
+pub const SAVE_VERSION: u32 = 2;
+/// Directory of the saves. Each save comes with a summary in a `.meta.ron` file and a
+/// thumbnail in a `.png` file.
+pub const SAVES_DIR: &str = "saves/games";
+/// Size of the thumbnails, in pixels
+const THUMBNAIL_SIZE: u32 = 128;
+/// World units covered by a pixel of the thumbnails
+const THUMBNAIL_SCALE: f32 = 2.;
+const THUMBNAIL_WATER: Vec3 = Vec3::new(0.16, 0.35, 0.6);
+const THUMBNAIL_LOWLAND: Vec3 = Vec3::new(0.33, 0.55, 0.25);
+const THUMBNAIL_HIGHLAND: Vec3 = Vec3::new(0.62, 0.55, 0.45);
+const THUMBNAIL_BUILDING: Vec3 = Vec3::new(0.95, 0.92, 0.85);
+
+/// Saving and loading the whole game : the world it was generated from, the terrain edits,
+/// the buildings, the sim and the camera. Loading goes back through `GameState::Loading` to
+/// generate the world of the save, and restores the rest once in game.
+pub struct SaveGamePlugin;
+
+impl Plugin for SaveGamePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveGameRequest>()
+            .add_event::<LoadGameRequest>()
+            .add_event::<GameSaved>()
+            .insert_resource(PlayTime::default())
+            .add_systems(OnExit(GameState::InGame), reset_play_time)
+            .add_systems(Update, count_play_time.run_if(in_state(Pause::Running)))
+            .add_systems(
+                Update,
+                (
+                    save_game,
+                    load_game,
+                    // a save loaded this frame waits for the world it asked for
+                    restore_game
+                        .run_if(resource_exists::<PendingLoad>)
+                        .before(load_game),
+                )
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// Request to save the game to a file
+#[derive(Event, Clone, Debug)]
+pub struct SaveGameRequest {
+    pub path: String,
+}
+
+/// Request to load the game saved in a file
+#[derive(Event, Clone, Debug)]
+pub struct LoadGameRequest {
+    pub path: String,
+}
+
+/// Sent once a save is written
+#[derive(Event, Clone, Debug)]
+pub struct GameSaved {
+    pub path: String,
+}
+
+/// Time played in the current game, saved with it
+#[derive(Resource, Default)]
+pub struct PlayTime(pub Duration);
+
+/// Everything needed to get a game back
+#[derive(Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    /// Version of the world generation the terrain edits were made on
+    pub worldgen_version: u32,
+    pub worldgen: WorldGen,
+    pub terrain: Vec<ChunkEdits>,
+    pub buildings: Vec<SavedBuilding>,
+    pub sim: SimSave,
+    pub exploration: Vec<ExploredChunk>,
+    pub camera: SavedCamera,
+    /// Time played, in seconds
+    #[serde(default)]
+    pub play_time: f64,
+}
+
+/// Summary of a save, shown in the list of saves without reading the whole save
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SaveMeta {
+    /// When the game was saved, in seconds since the unix epoch
+    pub saved_at: u64,
+    /// Time played, in seconds
+    pub play_time: f64,
+    pub worldgen: WorldGen,
+}
+
+/// A save found in `SAVES_DIR`
+#[derive(Clone)]
+pub struct SaveEntry {
+    pub path: String,
+    pub meta: SaveMeta,
+}
+
+/// A placed building. Its storage is saved with the sim, under its id.
+#[derive(Serialize, Deserialize)]
+pub struct SavedBuilding {
+    pub id: u64,
+    /// Path of the building asset
+    pub building: String,
+    pub transform: Transform,
+    #[serde(default)]
+    pub disabled: bool,
+    /// Text of a sign
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// Where the camera was looking
+#[derive(Serialize, Deserialize)]
+pub struct SavedCamera {
+    pub pos: Vec3,
+    pub distance: f32,
+    pub rotation: Quat,
+}
+
+impl SaveGame {
+    pub fn write(&self, path: &str) -> anyhow::Result<()> {
+        if let Some(parent) = Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, ron::ser::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Read a save, migrating it from the older versions
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let save: SaveGame = from_versioned_bytes(&std::fs::read(path)?, path)?;
+        if save.worldgen_version != WORLDGEN_VERSION {
+            warn!(
+                "{} was saved with the world generation {}, the terrain edits may not fit",
+                path, save.worldgen_version
+            );
+        }
+        Ok(save)
+    }
+}
+
+impl Versioned for SaveGame {
+    const VERSION: u32 = SAVE_VERSION;
+    const MIGRATIONS: &'static [Migration] = &[Migration {
+        from: 1,
+        description: "estimate the play time from the sim ticks",
+        apply: |save| {
+            if field_mut(save, "play_time").is_some() {
+                return Ok(());
+            }
+            let tick: u64 = field_mut(save, "sim")
+                .and_then(|sim| field_mut(sim, "tick"))
+                .map(|tick| tick.clone().into_rust())
+                .transpose()?
+                .unwrap_or(0);
+            let play_time = tick as f64 / SimSettings::default().tick_rate;
+            if let ron::Value::Map(fields) = save {
+                fields.insert(
+                    ron::Value::String("play_time".to_string()),
+                    ron::Value::Number(play_time.into()),
+                );
+            }
+            Ok(())
+        },
+    }];
+}
+
+/// Path of the save named `name`
+pub fn save_path(name: &str) -> String {
+    format!("{SAVES_DIR}/{name}.ron")
+}
+
+fn meta_path(path: &str) -> PathBuf {
+    Path::new(path).with_extension("meta.ron")
+}
+
+pub fn thumbnail_path(path: &str) -> PathBuf {
+    Path::new(path).with_extension("png")
+}
+
+/// Saves of `SAVES_DIR`, the most recent first
+pub fn list_saves() -> Vec<SaveEntry> {
+    let Ok(dir) = std::fs::read_dir(SAVES_DIR) else {
+        return Vec::new();
+    };
+    let mut saves: Vec<SaveEntry> = dir
+        .filter_map(|entry| {
+            let file = entry.ok()?.path();
+            let name = file.file_name()?.to_str()?.strip_suffix(".meta.ron")?;
+            let meta = std::fs::read(&file)
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| Ok(ron::de::from_bytes::<SaveMeta>(&bytes)?));
+            match meta {
+                Ok(meta) => Some(SaveEntry {
+                    path: save_path(name),
+                    meta,
+                }),
+                Err(e) => {
+                    warn!("Couldn't read the save summary {} : {}", file.display(), e);
+                    None
+                }
+            }
+        })
+        .collect();
+    saves.sort_by_key(|save| std::cmp::Reverse(save.meta.saved_at));
+    saves
+}
+
+/// Delete a save, with its summary and its thumbnail
+pub fn delete_save(path: &str) -> anyhow::Result<()> {
+    std::fs::remove_file(path)?;
+    for extra in [meta_path(path), thumbnail_path(path)] {
+        if extra.exists() {
+            std::fs::remove_file(extra)?;
+        }
+    }
+    Ok(())
+}
+
+/// Top view of the terrain around `center` : the water, the land shaded by its height, the
+/// buildings, and the unexplored terrain darkened
+fn thumbnail(map: &Map, exploration: &Exploration, center: Vec2) -> Image {
+    let half_size = Vec2::splat(THUMBNAIL_SIZE as f32 / 2.);
+    let mut data = Vec::with_capacity((THUMBNAIL_SIZE.pow(2) * 4) as usize);
+    for y in 0..THUMBNAIL_SIZE {
+        for x in 0..THUMBNAIL_SIZE {
+            let pos = center + (Vec2::new(x as f32, y as f32) - half_size) * THUMBNAIL_SCALE;
+            let chunk = (pos / Chunk::world_size()).floor().as_i64vec2();
+            let mut color = if !map.chunks.contains_key(&chunk) {
+                Vec3::ZERO
+            } else if !map.is_area_free((pos, pos + THUMBNAIL_SCALE)) {
+                THUMBNAIL_BUILDING
+            } else {
+                terrain_color(map.get_height(Vec3::new(pos.x, 0., pos.y)) / Chunk::SCALE_Y)
+            };
+            if !exploration.is_explored(pos) {
+                color *= 0.35;
+            }
+            let [r, g, b] = color
+                .to_array()
+                .map(|c| (c.clamp(0., 1.) * 255.).round() as u8);
+            data.extend([r, g, b, 255]);
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: THUMBNAIL_SIZE,
+            height: THUMBNAIL_SIZE,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::MAIN_WORLD,
+    )
+}
+
+/// Color of the terrain seen from above at a normalized height : the water, or the land
+/// shaded by its height
+pub(crate) fn terrain_color(height: f32) -> Vec3 {
+    let sea = Continent::OCEAN_HEIGHT_LIMIT;
+    if height < sea {
+        THUMBNAIL_WATER
+    } else {
+        let t = ((height - sea) / (1. - sea)).clamp(0., 1.);
+        THUMBNAIL_LOWLAND.lerp(THUMBNAIL_HIGHLAND, t)
+    }
+}
+
+fn count_play_time(time: Res<Time>, mut play_time: ResMut<PlayTime>) {
+    play_time.0 += time.delta();
+}
+
+fn reset_play_time(mut play_time: ResMut<PlayTime>) {
+    *play_time = PlayTime::default();
+}
+
+/// Save read from its file, waiting for its world to be generated and for the assets of
+/// its buildings to be loaded
+#[derive(Resource)]
+pub(crate) struct PendingLoad(Option<SaveGame>);
+
+/// Write the save, with its summary and its thumbnail
+fn save_game(
+    mut requests: EventReader<SaveGameRequest>,
+    mut saved: EventWriter<GameSaved>,
+    play_time: Res<PlayTime>,
+    map: Res<Map>,
+    sim: Res<Sim>,
+    exploration: Res<Exploration>,
+    camera: Single<&CameraTarget>,
+    buildings: Query<
+        (
+            &GameId,
+            &BuildId,
+            &Transform,
+            Has<Disabled>,
+            Option<&SignLabel>,
+        ),
+        With<BuildingInstance>,
+    >,
+    asset_server: Res<AssetServer>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) -> Result {
+    for SaveGameRequest { path } in requests.read() {
+        let mut saved_buildings = Vec::new();
+        for (id, bid, transform, disabled, label) in &buildings {
+            let Some(asset_path) = asset_server.get_path(bid.0.id()) else {
+                warn!("Building {} has no asset path, it isn't saved", id.0);
+                continue;
+            };
+            saved_buildings.push(SavedBuilding {
+                id: id.0,
+                building: asset_path.to_string(),
+                transform: *transform,
+                disabled,
+                label: label.map(|l| l.0.clone()),
+            });
+        }
+        saved_buildings.sort_by_key(|building| building.id);
+        let save = SaveGame {
+            version: SAVE_VERSION,
+            worldgen_version: WORLDGEN_VERSION,
+            worldgen: map.worldgen,
+            terrain: map.terrain_edits(),
+            buildings: saved_buildings,
+            sim: sim.to_save()?,
+            exploration: exploration.to_save(),
+            camera: SavedCamera {
+                pos: camera.pos,
+                distance: camera.distance,
+                rotation: camera.rotation,
+            },
+            play_time: play_time.0.as_secs_f64(),
+        };
+        save.write(path)?;
+        let meta = SaveMeta {
+            saved_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            play_time: save.play_time,
+            worldgen: save.worldgen,
+        };
+        std::fs::write(meta_path(path), ron::ser::to_string(&meta)?)?;
+        thumbnail(&map, &exploration, camera.pos.xz())
+            .try_into_dynamic()?
+            .save(thumbnail_path(path))?;
+        info!("Game saved to {}", path);
+        saved.write(GameSaved { path: path.clone() });
+        toasts.info(localization.get("toast-game-saved"));
+    }
+    Ok(())
+}
+
+/// Read the save, and leave the game to generate the world of the save
+fn load_game(
+    mut commands: Commands,
+    mut requests: EventReader<LoadGameRequest>,
+    mut worldgen: ResMut<WorldGen>,
+    mut next_state: ResMut<NextState<GameState>>,
+) -> Result {
+    let Some(LoadGameRequest { path }) = requests.read().last() else {
+        return Ok(());
+    };
+    begin_load(&mut commands, &mut worldgen, &mut next_state, path)?;
+    Ok(())
+}
+
+/// Read a save, and go to `GameState::Loading` to generate its world. The rest of the save
+/// is restored once in game.
+pub fn begin_load(
+    commands: &mut Commands,
+    worldgen: &mut WorldGen,
+    next_state: &mut NextState<GameState>,
+    path: &str,
+) -> anyhow::Result<()> {
+    let save = SaveGame::read(path)?;
+    info!("Loading the game from {}", path);
+    *worldgen = save.worldgen;
+    commands.insert_resource(PendingLoad(Some(save)));
+    next_state.set(GameState::Loading);
+    Ok(())
+}
+
+/// Put the terrain edits, the sim, the exploration and the camera of the save back, and
+/// spawn its buildings at their saved transform, once their assets are loaded
+fn restore_game(
+    mut commands: Commands,
+    mut pending: ResMut<PendingLoad>,
+    asset_server: Res<AssetServer>,
+    building_assets: Res<Assets<Building>>,
+    mut map: ResMut<Map>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut sim: ResMut<Sim>,
+    mut exploration: ResMut<Exploration>,
+    mut camera: Single<&mut CameraTarget>,
+    game_ids: Res<GameIds>,
+    shapes: Res<SavedShapes>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut play_time: ResMut<PlayTime>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) -> Result {
+    let Some(save) = pending.0.as_ref() else {
+        return Ok(());
+    };
+    let handles: Vec<Handle<Building>> = save
+        .buildings
+        .iter()
+        .map(|building| asset_server.load(&building.building))
+        .collect();
+    if handles
+        .iter()
+        .any(|handle| asset_server.load_state(handle).is_loading())
+    {
+        return Ok(());
+    }
+    let Some(save) = pending.0.take() else {
+        return Ok(());
+    };
+    commands.remove_resource::<PendingLoad>();
+
+    map.apply_terrain_edits(&mut meshes, &save.terrain);
+    // the tick running in the background is dropped, as the sim changes generation
+    sim.from_save(save.sim);
+    exploration.from_save(save.exploration)?;
+    camera.pos = save.camera.pos;
+    camera.distance = save.camera.distance;
+    camera.rotation = save.camera.rotation;
+    play_time.0 = Duration::from_secs_f64(save.play_time);
+    for (saved, handle) in save.buildings.iter().zip(handles) {
+        let Some(building) = building_assets.get(&handle) else {
+            warn!("Can't restore the unknown building {}", saved.building);
+            continue;
+        };
+        let Some(e) = spawn_building_model(
+            &mut commands,
+            handle.clone(),
+            building,
+            Quat::IDENTITY,
+            &shapes,
+            &mut materials,
+        ) else {
+            warn!("Can't restore {}, it has no model", saved.building);
+            continue;
+        };
+        let id = GameId(saved.id);
+        game_ids.skip_past(id);
+        let mut entity = commands.entity(e);
+        entity.insert((id, saved.transform, PendingRestore));
+        if saved.disabled {
+            entity.insert(Disabled);
+        }
+        if let Some(label) = &saved.label {
+            entity.insert(SignLabel(label.clone()));
+        }
+    }
+    info!("Game loaded");
+    toasts.info(localization.get("toast-game-loaded"));
+    Ok(())
+}
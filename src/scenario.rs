@@ -0,0 +1,633 @@
+use std::{collections::BTreeMap, path::Path};
+
+use bevy::{
+    asset::{AssetLoader, LoadContext},
+    prelude::*,
+};
+use rhai::Dynamic;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::{GameIds, SpawnBuilding},
+    deposits::Deposit,
+    disasters::Disaster,
+    localization::{Localization, LocalizedText},
+    map::{ChunkEdits, Map},
+    mapgen::WorldGen,
+    menu::GameState,
+    save_game::PendingLoad,
+    script_errors::{ScriptErrors, script_name},
+    sim::{RhaiScript, Sim, SimSpeed},
+    toasts::Toasts,
+    ui::FontHandle,
+    versioning::{Migration, Versioned, from_versioned_bytes},
+};
+
+const DEFAULT_SCENARIO: &str = "scenarios/first_village.scenario";
+
+/// Scenarios : starting conditions, objectives checked by script predicates, and their rewards.
+/// A scenario may come with its own world : the world to generate, the terrain edits, the
+/// buildings and the deposits, set up when a new game starts on it.
+pub struct ScenarioPlugin;
+
+impl Plugin for ScenarioPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Scenario>()
+            .init_asset_loader::<ScenarioLoader>()
+            .insert_resource(ScenarioTracker::default())
+            .add_systems(Startup, (load_scenario, setup_objective_panel))
+            .add_systems(
+                Update,
+                (
+                    start_scenario,
+                    track_objectives.after(start_scenario),
+                    update_objective_panel.after(track_objectives),
+                    show_end_screen.after(track_objectives),
+                    end_screen_buttons,
+                ),
+            )
+            .add_systems(
+                Update,
+                launch_scenario.run_if(in_state(GameState::MainMenu)),
+            )
+            .add_systems(
+                OnEnter(GameState::InGame),
+                setup_scenario_world.run_if(not(resource_exists::<PendingLoad>)),
+            );
+    }
+}
+
+/// An objective of a scenario
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Objective {
+    pub description: String,
+    /// Function of the scenario script, called with the sim data as `this`.
+    /// It returns a bool, or the progress toward the objective between 0 and 1.
+    pub predicate: String,
+    /// Resources added when the objective is completed
+    #[serde(default)]
+    pub reward: BTreeMap<String, f64>,
+}
+
+/// A predicate of the scenario script ending the scenario in a defeat when true
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoseCondition {
+    pub description: String,
+    pub predicate: String,
+}
+
+#[derive(Asset, TypePath, Debug)]
+pub struct Scenario {
+    pub name: String,
+    pub description: String,
+    /// Script defining the predicates
+    pub script: Handle<RhaiScript>,
+    /// Resources set in `data.resource` when the sim starts
+    pub starting_resources: BTreeMap<String, f64>,
+    pub objectives: Vec<Objective>,
+    pub lose_conditions: Vec<LoseCondition>,
+    /// The scenario is lost if the objectives aren't completed after this many ticks
+    pub tick_limit: Option<u64>,
+    /// Disasters striking at given ticks
+    pub disasters: Vec<ScheduledDisaster>,
+    /// World the scenario is played on, the one chosen in the menu if `None`
+    pub world: Option<WorldGen>,
+    /// Heights of the chunks edited on the world
+    pub terrain: Vec<ChunkEdits>,
+    pub buildings: Vec<ScenarioBuilding>,
+    pub deposits: Vec<Deposit>,
+}
+
+/// A disaster of a scenario, starting at a tick
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct ScheduledDisaster {
+    pub tick: u64,
+    pub disaster: Disaster,
+}
+
+/// A building standing when the scenario starts
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScenarioBuilding {
+    /// Name of the building, as in its asset file
+    pub building: String,
+    /// Center of its footprint
+    pub pos: Vec2,
+    pub rotation: f32,
+}
+
+/// A scenario as written in its `.scenario` file
+#[derive(Serialize, Deserialize)]
+pub struct ScenarioFile {
+    #[serde(default)]
+    pub version: u32,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Asset path of the script
+    pub script: String,
+    #[serde(default)]
+    pub starting_resources: BTreeMap<String, f64>,
+    pub objectives: Vec<Objective>,
+    #[serde(default)]
+    pub lose_conditions: Vec<LoseCondition>,
+    #[serde(default)]
+    pub tick_limit: Option<u64>,
+    #[serde(default)]
+    pub disasters: Vec<ScheduledDisaster>,
+    #[serde(default)]
+    pub world: Option<WorldGen>,
+    #[serde(default)]
+    pub terrain: Vec<ChunkEdits>,
+    #[serde(default)]
+    pub buildings: Vec<ScenarioBuilding>,
+    #[serde(default)]
+    pub deposits: Vec<Deposit>,
+}
+
+impl ScenarioFile {
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let pretty = ron::ser::PrettyConfig::default();
+        std::fs::write(path, ron::ser::to_string_pretty(self, pretty)?)?;
+        Ok(())
+    }
+}
+
+impl Versioned for ScenarioFile {
+    const VERSION: u32 = 2;
+    // the scenarios of the version 1 are played on the world chosen in the menu
+    const MIGRATIONS: &'static [Migration] = &[];
+}
+
+#[derive(Default)]
+pub struct ScenarioLoader;
+
+impl AssetLoader for ScenarioLoader {
+    type Asset = Scenario;
+
+    type Settings = ();
+
+    type Error = anyhow::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn bevy::asset::io::Reader,
+        _settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let path = load_context.path().display().to_string();
+        let file = from_versioned_bytes::<ScenarioFile>(&bytes, &path)?;
+        Ok(Scenario {
+            name: file.name,
+            description: file.description,
+            script: load_context.load(file.script),
+            starting_resources: file.starting_resources,
+            objectives: file.objectives,
+            lose_conditions: file.lose_conditions,
+            tick_limit: file.tick_limit,
+            disasters: file.disasters,
+            world: file.world,
+            terrain: file.terrain,
+            buildings: file.buildings,
+            deposits: file.deposits,
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["scenario"]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ScenarioOutcome {
+    #[default]
+    Running,
+    Won,
+    Lost,
+}
+
+/// State of the scenario being played
+#[derive(Resource, Default)]
+pub struct ScenarioTracker {
+    pub scenario: Option<Handle<Scenario>>,
+    /// Progress of each objective, between 0 and 1
+    pub progress: Vec<f64>,
+    pub outcome: ScenarioOutcome,
+    /// Why the scenario was lost
+    pub reason: Option<String>,
+    /// Generation of the sim data the scenario was started on
+    generation: u64,
+    last_tick: u64,
+    /// A new game starts on the scenario once it is loaded
+    launching: bool,
+}
+
+impl ScenarioTracker {
+    /// Load a scenario, and start a new game on its world once loaded
+    pub fn launch(&mut self, asset_server: &AssetServer, path: &str) {
+        info!("Launching the scenario {}", path);
+        self.scenario = Some(asset_server.load(path.to_string()));
+        self.launching = true;
+    }
+}
+
+fn load_scenario(mut tracker: ResMut<ScenarioTracker>, asset_server: Res<AssetServer>) {
+    // a scenario may already be launched from the command line
+    if tracker.scenario.is_none() {
+        tracker.scenario = Some(asset_server.load(DEFAULT_SCENARIO));
+    }
+}
+
+/// Generate the world of the scenario being launched, once it is loaded
+fn launch_scenario(
+    mut tracker: ResMut<ScenarioTracker>,
+    scenarios: Res<Assets<Scenario>>,
+    asset_server: Res<AssetServer>,
+    mut worldgen: ResMut<WorldGen>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if !tracker.launching {
+        return;
+    }
+    let Some(handle) = tracker.scenario.clone() else {
+        tracker.launching = false;
+        return;
+    };
+    if asset_server.load_state(&handle).is_failed() {
+        warn!(
+            "Can't launch the scenario {:?}, it failed to load",
+            handle.path()
+        );
+        tracker.launching = false;
+        return;
+    }
+    let Some(scenario) = scenarios.get(&handle) else {
+        return;
+    };
+    if let Some(world) = scenario.world {
+        *worldgen = world;
+    }
+    tracker.launching = false;
+    next_state.set(GameState::Loading);
+}
+
+/// Set up the world of the scenario on a new game : its terrain edits, its buildings and its
+/// deposits. They are left out on another world than the scenario's.
+fn setup_scenario_world(
+    tracker: Res<ScenarioTracker>,
+    scenarios: Res<Assets<Scenario>>,
+    worldgen: Res<WorldGen>,
+    mut map: ResMut<Map>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    sim: Res<Sim>,
+    game_ids: Res<GameIds>,
+    mut spawns: EventWriter<SpawnBuilding>,
+) {
+    let Some(scenario) = tracker.scenario.as_ref().and_then(|s| scenarios.get(s)) else {
+        return;
+    };
+    if scenario.world.is_some_and(|world| world != *worldgen) {
+        return;
+    }
+    map.apply_terrain_edits(&mut meshes, &scenario.terrain);
+    for building in &scenario.buildings {
+        spawns.write(SpawnBuilding {
+            id: game_ids.next(),
+            name: building.building.clone(),
+            pos: building.pos,
+            rotation: building.rotation,
+        });
+    }
+    let mut world = sim.script_world.0.lock().unwrap();
+    world.deposits.deposits = scenario.deposits.clone();
+}
+
+/// Apply the starting conditions of the scenario, restarting the sim if they changed,
+/// and start tracking the objectives again when the sim restarts, with the deposits of the
+/// scenario back.
+fn start_scenario(
+    mut events: EventReader<AssetEvent<Scenario>>,
+    scenarios: Res<Assets<Scenario>>,
+    mut tracker: ResMut<ScenarioTracker>,
+    mut sim: ResMut<Sim>,
+    worldgen: Option<Res<WorldGen>>,
+) {
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event
+        else {
+            continue;
+        };
+        if tracker.scenario.as_ref().is_none_or(|s| s.id() != *id) {
+            continue;
+        }
+        let Some(scenario) = scenarios.get(*id) else {
+            continue;
+        };
+        info!("Scenario {} : {}", scenario.name, scenario.description);
+        if sim.starting_resources != scenario.starting_resources {
+            sim.starting_resources = scenario.starting_resources.clone();
+            sim.restart();
+        }
+    }
+    // a loaded save keeps the progress, a restarted sim starts over
+    if sim.is_initialized() && sim.generation() != tracker.generation {
+        tracker.generation = sim.generation();
+        // the disasters before a loaded save already struck
+        tracker.last_tick = sim.tick;
+        if sim.tick == 0 {
+            tracker.progress.clear();
+            tracker.outcome = ScenarioOutcome::Running;
+            tracker.reason = None;
+            let scenario = tracker.scenario.as_ref().and_then(|s| scenarios.get(s));
+            if let (Some(scenario), Some(worldgen)) = (scenario, worldgen) {
+                // the restart cleared them
+                if scenario.world.is_none_or(|world| world == *worldgen) {
+                    let mut world = sim.script_world.0.lock().unwrap();
+                    world.deposits.deposits = scenario.deposits.clone();
+                }
+            }
+        }
+    }
+}
+
+/// Progress given by a predicate : a bool, or a number between 0 and 1
+fn predicate_progress(value: Dynamic) -> f64 {
+    if let Ok(done) = value.as_bool() {
+        return if done { 1. } else { 0. };
+    }
+    value
+        .as_float()
+        .or_else(|_| value.as_int().map(|i| i as f64))
+        .map(|p| p.clamp(0., 1.))
+        .unwrap_or(0.)
+}
+
+/// Evaluate the objectives and lose conditions once per sim tick, and give the rewards
+fn track_objectives(
+    mut tracker: ResMut<ScenarioTracker>,
+    mut sim: ResMut<Sim>,
+    scenarios: Res<Assets<Scenario>>,
+    scripts: Res<Assets<RhaiScript>>,
+    mut errors: ResMut<ScriptErrors>,
+    mut speed: ResMut<SimSpeed>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) {
+    if !sim.is_initialized()
+        || sim.tick == tracker.last_tick
+        || tracker.outcome != ScenarioOutcome::Running
+    {
+        return;
+    }
+    let previous_tick = std::mem::replace(&mut tracker.last_tick, sim.tick);
+    let Some(scenario) = tracker.scenario.as_ref().and_then(|s| scenarios.get(s)) else {
+        return;
+    };
+    for scheduled in &scenario.disasters {
+        if scheduled.tick > previous_tick && scheduled.tick <= sim.tick {
+            let mut world = sim.script_world.0.lock().unwrap();
+            world.disasters.start(scheduled.disaster);
+        }
+    }
+    if errors.has_error(&script_name(&scenario.script)) {
+        return;
+    }
+    tracker.progress.resize(scenario.objectives.len(), 0.);
+    for (i, objective) in scenario.objectives.iter().enumerate() {
+        if tracker.progress[i] >= 1. {
+            continue;
+        }
+        match sim.eval_with_data(&scripts, &scenario.script, &objective.predicate) {
+            Ok(Some(value)) => {
+                let progress = predicate_progress(value);
+                if progress >= 1. {
+                    info!("Objective completed : {}", objective.description);
+                    let objective_arg = [("objective", objective.description.clone())];
+                    toasts.achievement(
+                        localization.get_args("toast-objective-completed", &objective_arg),
+                    );
+                    for (resource, amount) in &objective.reward {
+                        sim.grant_resource(resource, *amount);
+                    }
+                }
+                tracker.progress[i] = progress;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                errors.push(e);
+                return;
+            }
+        }
+    }
+    if tracker.progress.iter().all(|p| *p >= 1.) {
+        tracker.outcome = ScenarioOutcome::Won;
+    } else {
+        for condition in &scenario.lose_conditions {
+            match sim.eval_with_data(&scripts, &scenario.script, &condition.predicate) {
+                Ok(Some(value)) if predicate_progress(value) >= 1. => {
+                    tracker.outcome = ScenarioOutcome::Lost;
+                    tracker.reason = Some(condition.description.clone());
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    errors.push(e);
+                    return;
+                }
+            }
+        }
+        if tracker.outcome == ScenarioOutcome::Running
+            && scenario.tick_limit.is_some_and(|limit| sim.tick > limit)
+        {
+            tracker.outcome = ScenarioOutcome::Lost;
+            tracker.reason = Some(localization.get("scenario-time-up"));
+        }
+    }
+    if tracker.outcome != ScenarioOutcome::Running {
+        *speed = SimSpeed::Paused;
+    }
+}
+
+#[derive(Component)]
+struct ObjectivePanel;
+
+fn setup_objective_panel(mut commands: Commands) {
+    commands.spawn((
+        Name::new("objectives"),
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.),
+            top: Val::Px(40.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(4.),
+            ..default()
+        },
+        ObjectivePanel,
+    ));
+}
+
+/// List the objectives of the scenario, with their progress
+fn update_objective_panel(
+    mut commands: Commands,
+    tracker: Res<ScenarioTracker>,
+    scenarios: Res<Assets<Scenario>>,
+    panel: Single<Entity, With<ObjectivePanel>>,
+    font: Res<FontHandle>,
+) {
+    if !tracker.is_changed() {
+        return;
+    }
+    let Some(scenario) = tracker.scenario.as_ref().and_then(|s| scenarios.get(s)) else {
+        return;
+    };
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 14.,
+        ..default()
+    };
+    commands.entity(*panel).despawn_related::<Children>();
+    commands.entity(*panel).with_children(|parent| {
+        parent.spawn((Text(scenario.name.clone()), text_font.clone(), Label));
+        for (i, objective) in scenario.objectives.iter().enumerate() {
+            let progress = tracker.progress.get(i).copied().unwrap_or(0.);
+            let color = if progress >= 1. {
+                bevy::color::palettes::css::LIGHT_GREEN.into()
+            } else {
+                Color::WHITE
+            };
+            parent.spawn((
+                Text(format!(
+                    "{} : {:.0}%",
+                    objective.description,
+                    progress * 100.
+                )),
+                text_font.clone(),
+                TextColor(color),
+                Label,
+            ));
+        }
+        if let Some(limit) = scenario.tick_limit {
+            parent.spawn((
+                LocalizedText::new("scenario-ticks-left")
+                    .with_arg("ticks", limit.saturating_sub(tracker.last_tick)),
+                text_font.clone(),
+                Label,
+            ));
+        }
+    });
+}
+
+#[derive(Component)]
+struct EndScreen;
+
+#[derive(Component, Clone, Copy)]
+enum EndScreenButton {
+    /// Keep playing after winning
+    Continue,
+    Restart,
+}
+
+/// Show the win or lose screen when the scenario ends
+fn show_end_screen(
+    mut commands: Commands,
+    tracker: Res<ScenarioTracker>,
+    screen: Option<Single<Entity, With<EndScreen>>>,
+    font: Res<FontHandle>,
+    mut shown: Local<ScenarioOutcome>,
+) {
+    if tracker.outcome == *shown {
+        return;
+    }
+    *shown = tracker.outcome;
+    if let Some(screen) = screen {
+        commands.entity(*screen).despawn();
+    }
+    let (title, buttons): (_, &[_]) = match tracker.outcome {
+        ScenarioOutcome::Running => return,
+        ScenarioOutcome::Won => (
+            "scenario-victory",
+            &[EndScreenButton::Continue, EndScreenButton::Restart],
+        ),
+        ScenarioOutcome::Lost => ("scenario-defeat", &[EndScreenButton::Restart]),
+    };
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 20.,
+        ..default()
+    };
+    commands
+        .spawn((
+            Name::new("scenario end screen"),
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(10.),
+                ..default()
+            },
+            BackgroundColor(bevy::color::palettes::css::BLACK.with_alpha(0.7).into()),
+            GlobalZIndex(10),
+            EndScreen,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                LocalizedText::new(title),
+                TextFont {
+                    font_size: 48.,
+                    ..text_font.clone()
+                },
+                Label,
+            ));
+            if let Some(reason) = &tracker.reason {
+                parent.spawn((Text(reason.clone()), text_font.clone(), Label));
+            }
+            for button in buttons {
+                let label = match button {
+                    EndScreenButton::Continue => "scenario-continue",
+                    EndScreenButton::Restart => "scenario-restart",
+                };
+                parent
+                    .spawn((
+                        Button,
+                        Node {
+                            padding: UiRect::all(Val::Px(8.)),
+                            ..default()
+                        },
+                        BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                        *button,
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((LocalizedText::new(label), text_font.clone(), Label));
+                    });
+            }
+        });
+}
+
+fn end_screen_buttons(
+    mut commands: Commands,
+    buttons: Query<(&Interaction, &EndScreenButton), Changed<Interaction>>,
+    screen: Option<Single<Entity, With<EndScreen>>>,
+    mut sim: ResMut<Sim>,
+    mut speed: ResMut<SimSpeed>,
+) {
+    let Some((_, button)) = buttons
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Pressed)
+    else {
+        return;
+    };
+    if let EndScreenButton::Restart = button {
+        sim.restart();
+    }
+    if let Some(screen) = screen {
+        commands.entity(*screen).despawn();
+    }
+    *speed = SimSpeed::Normal;
+}
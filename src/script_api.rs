@@ -0,0 +1,394 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use bevy::{
+    platform::collections::{HashMap, HashSet},
+    prelude::*,
+};
+use rhai::{Dynamic, Engine, NativeCallContext};
+
+use crate::{
+    build::{Building, BuildingType, GameIds, SpawnBuilding},
+    canals::Irrigation,
+    deposits::Deposits,
+    disasters::Disasters,
+    extraction::Extraction,
+    land_value::LandValueGrid,
+    logistics::Freight,
+    map::{Map, PatchOp},
+    pollution::PollutionGrid,
+    population::Population,
+    replication::TerrainOp,
+    research::Research,
+    sim::{BuildingStorage, Sim, run_building_scripts, run_rhai},
+    trade::Market,
+    weather::WeatherState,
+    wind::WindState,
+    zones::Zoning,
+};
+
+/// World data lent to the script engine while the sim scripts run.
+/// The map is moved in before the scripts run, and moved back to the world right after.
+#[derive(Default)]
+pub struct ScriptWorld {
+    pub map: Option<Map>,
+    pub building_names: HashMap<AssetId<Building>, String>,
+    /// Names of the buildings scripts can spawn : the single buildings and the signs
+    pub spawnable: HashSet<String>,
+    /// Terrain operations requested by scripts, sent as `TerrainOp` events after the scripts ran.
+    pub patches: Vec<TerrainOp>,
+    pub game_ids: GameIds,
+    /// Buildings requested by scripts, sent as `SpawnBuilding` events after the scripts ran.
+    pub spawn_requests: Vec<SpawnBuilding>,
+    /// Sim tick being run
+    pub tick: u64,
+    /// Events emitted by scripts during this tick
+    pub emitted: Vec<ScriptEvent>,
+    /// Events emitted during the previous tick, readable by scripts with `events()`
+    pub received: Vec<ScriptEvent>,
+    /// Storage of every placed building, by game id
+    pub storages: HashMap<u64, BuildingStorage>,
+    /// Widgets declared by the sim scripts, by key
+    pub ui: rhai::Map,
+    /// Weather of the current period
+    pub weather: WeatherState,
+    /// Wind of the current tick
+    pub wind: WindState,
+    /// Goods offered, requested, travelling and delivered between the buildings
+    pub freight: Freight,
+    /// Prices and orders of the trade with the ships
+    pub market: Market,
+    /// Pollution over the map
+    pub pollution: PollutionGrid,
+    /// Value of the land around the buildings
+    pub land_value: LandValueGrid,
+    /// Residents and workers of the buildings
+    pub population: Population,
+    /// Technologies researched and the research under way
+    pub research: Research,
+    /// Flow of the rivers through the dams, by game id
+    pub dam_flows: BTreeMap<u64, f32>,
+    /// Canals and the ground they irrigate
+    pub irrigation: Irrigation,
+    /// Water and fish taken from the rivers during the tick
+    pub extraction: Extraction,
+    /// Fires, floods and the damage they did
+    pub disasters: Disasters,
+    /// Areas zoned by the player
+    pub zoning: Zoning,
+    /// Deposits of resources in the ground
+    pub deposits: Deposits,
+    /// Asked for by the tick running in the background, sent once it completes
+    pub background: BackgroundOutput,
+}
+
+/// Buildings and events asked for by the sim scripts running in the background
+#[derive(Default)]
+pub struct BackgroundOutput {
+    pub spawn_requests: Vec<SpawnBuilding>,
+    pub emitted: Vec<ScriptEvent>,
+}
+
+impl ScriptWorld {
+    /// Emit an event from a host function, stamped with the tick of the script calling it
+    pub fn emit(&mut self, ctx: &NativeCallContext, name: &str, payload: Dynamic) {
+        let (tick, emitted) = match background_tick(ctx) {
+            Some(tick) => (tick, &mut self.background.emitted),
+            None => (self.tick, &mut self.emitted),
+        };
+        emitted.push(ScriptEvent {
+            name: name.to_string(),
+            payload,
+            tick,
+        });
+    }
+
+    /// Request a building from a host function
+    pub fn request_spawn(&mut self, ctx: &NativeCallContext, request: SpawnBuilding) {
+        match background_tick(ctx) {
+            Some(_) => self.background.spawn_requests.push(request),
+            None => self.spawn_requests.push(request),
+        }
+    }
+
+    /// Send what the tick running in the background asked for with the rest, once it completed
+    pub fn collect_background(&mut self) {
+        let background = std::mem::take(&mut self.background);
+        self.spawn_requests.extend(background.spawn_requests);
+        self.emitted.extend(background.emitted);
+    }
+}
+
+/// Tick running in the background a host function is called from, `None` on the main thread
+fn background_tick(ctx: &NativeCallContext) -> Option<u64> {
+    ctx.tag()
+        .and_then(|tag| tag.as_int().ok())
+        .map(|tick| tick as u64)
+}
+
+/// An event emitted by a script with `emit(name, payload)`.
+/// Scripts see the events of the previous tick, Rust systems can read them as bevy events.
+#[derive(Event, Clone, Debug)]
+pub struct ScriptEvent {
+    pub name: String,
+    pub payload: Dynamic,
+    pub tick: u64,
+}
+
+impl ScriptEvent {
+    fn to_rhai(&self) -> Dynamic {
+        let mut event = rhai::Map::new();
+        event.insert("name".into(), self.name.clone().into());
+        event.insert("payload".into(), self.payload.clone());
+        event.insert("tick".into(), (self.tick as i64).into());
+        Dynamic::from_map(event)
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SharedScriptWorld(pub Arc<Mutex<ScriptWorld>>);
+
+/// Host functions reading the map, only lent to the scripts running on the main thread
+pub const MAP_FUNCTIONS: [&str; 4] = [
+    "get_height",
+    "get_flow",
+    "buildings_in_radius",
+    "patch_terrain",
+];
+
+/// Register the map related host functions on the engine.
+pub fn register_map_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("get_height", move |x: f64, z: f64| -> f64 {
+        let world = w.0.lock().unwrap();
+        world
+            .map
+            .as_ref()
+            .map(|map| map.get_height(Vec3::new(x as f32, 0., z as f32)) as f64)
+            .unwrap_or(f64::NAN)
+    });
+
+    let w = world.clone();
+    engine.register_fn("get_flow", move |x: f64, z: f64| -> f64 {
+        let world = w.0.lock().unwrap();
+        world
+            .map
+            .as_ref()
+            .map(|map| {
+                let (x, y) = map
+                    .continent
+                    .from_world(&Vec3::new(x as f32, 0., z as f32));
+                // the rivers swell after the rain
+                (map.continent.get_hydro(x, y).amount * world.weather.river_boost()) as f64
+            })
+            .unwrap_or(f64::NAN)
+    });
+
+    let w = world.clone();
+    engine.register_fn(
+        "buildings_in_radius",
+        move |x: f64, z: f64, r: f64| -> rhai::Array {
+            let world = w.0.lock().unwrap();
+            let Some(map) = &world.map else {
+                return rhai::Array::new();
+            };
+            map.buildings_in_radius(Vec2::new(x as f32, z as f32), r as f32)
+                .map(|b| {
+                    let mut building = rhai::Map::new();
+                    let name = world
+                        .building_names
+                        .get(&b.building.id())
+                        .cloned()
+                        .unwrap_or_default();
+                    building.insert("name".into(), name.into());
+                    building.insert("x".into(), (b.center().x as f64).into());
+                    building.insert("z".into(), (b.center().y as f64).into());
+                    building.insert("id".into(), (b.entity.to_bits() as i64).into());
+                    Dynamic::from_map(building)
+                })
+                .collect()
+        },
+    );
+
+    let w = world.clone();
+    engine.register_fn(
+        "patch_terrain",
+        move |x: f64, z: f64, r: f64, op: &str| -> bool {
+            let op = match op {
+                "up" => PatchOp::Up,
+                "down" => PatchOp::Down,
+                "flatten" => PatchOp::Flatten,
+                _ => return false,
+            };
+            let mut world = w.0.lock().unwrap();
+            let Some(map) = &world.map else {
+                return false;
+            };
+            let pos = Vec3::new(x as f32, 0., z as f32);
+            let center = pos.with_y(map.get_height(pos));
+            let tick = world.tick;
+            world.patches.push(TerrainOp {
+                op,
+                center,
+                radius: r as f32,
+                strength: 1.,
+                tick,
+            });
+            true
+        },
+    );
+}
+
+/// Register the host functions acting on buildings.
+/// `spawn_building` returns -1 for the buildings that can't be spawned, and the id of the
+/// others. A `building_spawn_failed` event carrying the id is emitted when the building
+/// can't be placed there in the end.
+pub fn register_building_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn(
+        "spawn_building",
+        move |ctx: NativeCallContext, name: &str, x: f64, z: f64, rotation: f64| -> i64 {
+            let mut world = w.0.lock().unwrap();
+            if !world.spawnable.contains(name) {
+                return -1;
+            }
+            let id = world.game_ids.next();
+            let request = SpawnBuilding {
+                id,
+                name: name.to_string(),
+                pos: Vec2::new(x as f32, z as f32),
+                rotation: rotation as f32,
+            };
+            world.request_spawn(&ctx, request);
+            id.0 as i64
+        },
+    );
+
+    let w = world.clone();
+    engine.register_fn("building_storage", move |id: i64| -> Dynamic {
+        let world = w.0.lock().unwrap();
+        world
+            .storages
+            .get(&(id as u64))
+            .cloned()
+            .map(Dynamic::from)
+            .unwrap_or(Dynamic::UNIT)
+    });
+}
+
+/// Register `emit` and `events`, the event bus between scripts and bevy.
+pub fn register_event_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn(
+        "emit",
+        move |ctx: NativeCallContext, name: &str, payload: Dynamic| {
+            w.0.lock().unwrap().emit(&ctx, name, payload);
+        },
+    );
+
+    let w = world.clone();
+    engine.register_fn("emit", move |ctx: NativeCallContext, name: &str| {
+        w.0.lock().unwrap().emit(&ctx, name, Dynamic::UNIT);
+    });
+
+    let w = world.clone();
+    engine.register_fn("events", move || -> rhai::Array {
+        let world = w.0.lock().unwrap();
+        world.received.iter().map(ScriptEvent::to_rhai).collect()
+    });
+
+    let w = world.clone();
+    engine.register_fn("events", move |name: &str| -> rhai::Array {
+        let world = w.0.lock().unwrap();
+        world
+            .received
+            .iter()
+            .filter(|e| e.name == name)
+            .map(ScriptEvent::to_rhai)
+            .collect()
+    });
+}
+
+/// Register `widget` and `remove_widget`, declaring the widgets shown by the script ui,
+/// and `current_tick`.
+pub fn register_ui_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("widget", move |key: &str, definition: rhai::Map| {
+        let mut world = w.0.lock().unwrap();
+        world.ui.insert(key.into(), Dynamic::from_map(definition));
+    });
+
+    let w = world.clone();
+    engine.register_fn("remove_widget", move |key: &str| {
+        let mut world = w.0.lock().unwrap();
+        world.ui.remove(key);
+    });
+
+    let w = world.clone();
+    engine.register_fn("current_tick", move || -> i64 {
+        w.0.lock().unwrap().tick as i64
+    });
+}
+
+/// Lend the map to the script engine, run the sim scripts, then take the map back
+/// and send the terrain operations, buildings and events the scripts asked for. Those of the
+/// tick running in the background are sent once it completes.
+pub fn run_scripts_with_world(world: &mut World) -> Result {
+    let shared = world.resource::<Sim>().script_world.clone();
+    let map = world.remove_resource::<Map>().ok_or("map missing")?;
+    let buildings = world.resource::<Assets<Building>>();
+    let building_names = buildings
+        .iter()
+        .map(|(id, b)| (id, b.name.clone()))
+        .collect();
+    let spawnable = buildings
+        .iter()
+        .filter(|(_, b)| {
+            matches!(
+                b.typ,
+                BuildingType::Single { .. } | BuildingType::Sign { .. }
+            )
+        })
+        .map(|(_, b)| b.name.clone())
+        .collect();
+    {
+        let mut script_world = shared.0.lock().unwrap();
+        script_world.map = Some(map);
+        script_world.building_names = building_names;
+        script_world.spawnable = spawnable;
+        script_world.game_ids = world.resource::<GameIds>().clone();
+        script_world.tick = world.resource::<Sim>().tick;
+    }
+
+    let run_result = world.run_system_cached(run_rhai);
+    let building_result = world.run_system_cached(run_building_scripts);
+
+    let (map, patches, spawn_requests, emitted) = {
+        let mut script_world = shared.0.lock().unwrap();
+        let emitted = std::mem::take(&mut script_world.emitted);
+        // the events of this tick are seen by the scripts on the next one
+        script_world.received = emitted.clone();
+        (
+            script_world.map.take(),
+            std::mem::take(&mut script_world.patches),
+            std::mem::take(&mut script_world.spawn_requests),
+            emitted,
+        )
+    };
+    for request in spawn_requests {
+        world.send_event(request);
+    }
+    for event in emitted {
+        world.send_event(event);
+    }
+    world.insert_resource(map.ok_or("map lost by the script engine")?);
+    for patch in patches {
+        world.send_event(patch);
+    }
+
+    run_result??;
+    building_result??;
+    Ok(())
+}
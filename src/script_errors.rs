@@ -0,0 +1,199 @@
+use bevy::prelude::*;
+use rhai::EvalAltResult;
+
+use crate::{
+    localization::{Localization, LocalizedText},
+    sim::RhaiScript,
+    toasts::Toasts,
+    ui::FontHandle,
+};
+
+/// Errors raised by the scripts, displayed in a panel until the user retries.
+pub struct ScriptErrorPlugin;
+
+impl Plugin for ScriptErrorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ScriptErrors::default());
+        app.add_systems(
+            Update,
+            (update_error_panel, retry_scripts, toast_new_errors),
+        );
+    }
+}
+
+/// An error raised by a script
+#[derive(Clone, Debug)]
+pub struct ScriptError {
+    /// Asset path of the script
+    pub script: String,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// The offending line of the script
+    pub source_line: Option<String>,
+}
+
+impl ScriptError {
+    pub fn new(script: String, text: &str, err: &EvalAltResult) -> Self {
+        let pos = err.position();
+        Self {
+            script,
+            message: err.to_string(),
+            line: pos.line(),
+            column: pos.position(),
+            source_line: pos
+                .line()
+                .and_then(|l| text.lines().nth(l.saturating_sub(1)))
+                .map(|l| l.trim().to_string()),
+        }
+    }
+}
+
+/// Scripts in error are not run until the errors are cleared with the retry button.
+#[derive(Resource, Default)]
+pub struct ScriptErrors(pub Vec<ScriptError>);
+
+impl ScriptErrors {
+    /// Add an error, unless the same one is already pending for the script
+    pub fn push(&mut self, error: ScriptError) {
+        if self
+            .0
+            .iter()
+            .any(|e| e.script == error.script && e.message == error.message)
+        {
+            return;
+        }
+        error!("Error in script {} : {}", error.script, error.message);
+        self.0.push(error);
+    }
+
+    /// Whether this script has an error pending
+    pub fn has_error(&self, script: &str) -> bool {
+        self.0.iter().any(|e| e.script == script)
+    }
+}
+
+/// Name of a script, as shown in errors
+pub fn script_name(script: &Handle<RhaiScript>) -> String {
+    script
+        .path()
+        .map(|p| p.to_string())
+        .unwrap_or_else(|| format!("{:?}", script.id()))
+}
+
+/// Notify the errors raised since the last frame
+fn toast_new_errors(
+    errors: Res<ScriptErrors>,
+    mut toasts: ResMut<Toasts>,
+    mut seen: Local<usize>,
+    localization: Res<Localization>,
+) {
+    if !errors.is_changed() {
+        return;
+    }
+    // retrying clears the errors
+    for error in errors.0.iter().skip((*seen).min(errors.0.len())) {
+        let args = [
+            ("script", error.script.clone()),
+            ("message", error.message.clone()),
+        ];
+        toasts.error(localization.get_args("toast-script-error", &args));
+    }
+    *seen = errors.0.len();
+}
+
+#[derive(Component)]
+struct ErrorPanel;
+
+#[derive(Component)]
+struct RetryButton;
+
+/// Rebuild the error panel when the errors change
+fn update_error_panel(
+    mut commands: Commands,
+    errors: Res<ScriptErrors>,
+    panel: Option<Single<Entity, With<ErrorPanel>>>,
+    font: Res<FontHandle>,
+) {
+    if !errors.is_changed() {
+        return;
+    }
+    if let Some(panel) = panel {
+        commands.entity(*panel).despawn();
+    }
+    if errors.0.is_empty() {
+        return;
+    }
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 14.,
+        ..default()
+    };
+    commands
+        .spawn((
+            Name::new("script errors"),
+            Node {
+                position_type: PositionType::Absolute,
+                left: Val::Percent(20.),
+                bottom: Val::Px(10.),
+                max_width: Val::Percent(60.),
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(10.)),
+                row_gap: Val::Px(5.),
+                ..default()
+            },
+            BackgroundColor(bevy::color::palettes::css::DARK_RED.with_alpha(0.9).into()),
+            ErrorPanel,
+        ))
+        .with_children(|parent| {
+            for error in &errors.0 {
+                let location = match (error.line, error.column) {
+                    (Some(line), Some(column)) => format!(" (line {line}, column {column})"),
+                    (Some(line), None) => format!(" (line {line})"),
+                    _ => String::new(),
+                };
+                parent.spawn((
+                    Text(format!("{}{} : {}", error.script, location, error.message)),
+                    text_font.clone(),
+                    Label,
+                ));
+                if let Some(source_line) = &error.source_line {
+                    parent.spawn((
+                        Text(format!("> {source_line}")),
+                        text_font.clone(),
+                        TextColor(bevy::color::palettes::css::LIGHT_YELLOW.into()),
+                        Label,
+                    ));
+                }
+            }
+            parent
+                .spawn((
+                    Button,
+                    Node {
+                        align_self: AlignSelf::Start,
+                        padding: UiRect::all(Val::Px(5.)),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                    RetryButton,
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        LocalizedText::new("script-errors-retry"),
+                        text_font.clone(),
+                        Label,
+                    ));
+                });
+        });
+}
+
+/// Clear the errors so the scripts run again. Scripts are hot reloaded on save, so the fixed
+/// version is the one that runs.
+fn retry_scripts(
+    mut errors: ResMut<ScriptErrors>,
+    buttons: Query<&Interaction, (Changed<Interaction>, With<RetryButton>)>,
+) {
+    if buttons.iter().any(|i| *i == Interaction::Pressed) {
+        errors.0.clear();
+    }
+}
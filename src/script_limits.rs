@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use bevy::{platform::collections::HashMap, prelude::*};
+use rhai::Engine;
+
+use crate::{
+    input_map::{Action, Actions},
+    sim::Sim,
+    ui::FontHandle,
+};
+
+/// Limits on what scripts can do, so a runaway mod script can't freeze or exhaust the game,
+/// and a panel with the time spent in each script.
+pub struct ScriptLimitsPlugin;
+
+impl Plugin for ScriptLimitsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ScriptLimits::default());
+        app.insert_resource(ScriptStats::default());
+        app.add_systems(Startup, setup_stats_panel);
+        app.add_systems(
+            Update,
+            (apply_script_limits, toggle_stats_panel, update_stats_panel),
+        );
+    }
+}
+
+/// Configurable limits of the script engine. Limits are per script run, so per tick.
+#[derive(Resource, Clone, Debug)]
+pub struct ScriptLimits {
+    pub max_operations: u64,
+    pub max_call_levels: usize,
+    pub max_string_size: usize,
+    pub max_array_size: usize,
+    pub max_map_size: usize,
+    /// Whether scripts can `import` other script files
+    pub allow_modules: bool,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_operations: 1_000_000,
+            max_call_levels: 64,
+            max_string_size: 64 * 1024,
+            max_array_size: 100_000,
+            max_map_size: 100_000,
+            allow_modules: false,
+        }
+    }
+}
+
+impl ScriptLimits {
+    pub fn apply(&self, engine: &mut Engine) {
+        engine
+            .set_max_operations(self.max_operations)
+            .set_max_call_levels(self.max_call_levels)
+            .set_max_string_size(self.max_string_size)
+            .set_max_array_size(self.max_array_size)
+            .set_max_map_size(self.max_map_size);
+        if self.allow_modules {
+            engine.set_module_resolver(rhai::module_resolvers::FileModuleResolver::new());
+        } else {
+            engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+        }
+    }
+}
+
+/// Execution time of a script
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ScriptStat {
+    pub runs: u64,
+    pub last: Duration,
+    /// Moving average of the run time
+    pub average: Duration,
+    pub max: Duration,
+}
+
+/// Execution time of every script, by name
+#[derive(Resource, Default)]
+pub struct ScriptStats(pub HashMap<String, ScriptStat>);
+
+impl ScriptStats {
+    pub fn record(&mut self, script: &str, time: Duration) {
+        let stat = self.0.entry(script.to_string()).or_default();
+        stat.runs += 1;
+        stat.last = time;
+        stat.max = stat.max.max(time);
+        stat.average = if stat.runs == 1 {
+            time
+        } else {
+            stat.average.mul_f64(0.9) + time.mul_f64(0.1)
+        };
+    }
+}
+
+/// Apply the limits when they change. The engine can't change while a tick runs
+/// in the background, so this is retried until it succeeds.
+fn apply_script_limits(limits: Res<ScriptLimits>, mut sim: ResMut<Sim>, mut pending: Local<bool>) {
+    if limits.is_changed() {
+        *pending = true;
+    }
+    if *pending && sim.configure_engine(|engine| limits.apply(engine)) {
+        *pending = false;
+    }
+}
+
+#[derive(Component)]
+struct StatsPanel;
+
+fn setup_stats_panel(mut commands: Commands, font: Res<FontHandle>) {
+    commands.spawn((
+        Name::new("script stats"),
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.),
+            top: Val::Px(40.),
+            padding: UiRect::all(Val::Px(5.)),
+            ..default()
+        },
+        BackgroundColor(bevy::color::palettes::css::BLACK.with_alpha(0.7).into()),
+        Text::default(),
+        TextFont {
+            font: font.0.clone(),
+            font_size: 14.,
+            ..default()
+        },
+        Label,
+        Visibility::Hidden,
+        StatsPanel,
+    ));
+}
+
+/// Show the script stats on pressing F4
+fn toggle_stats_panel(actions: Actions, mut panel: Single<&mut Visibility, With<StatsPanel>>) {
+    if actions.just_pressed(Action::ToggleScriptStats) {
+        panel.toggle_visible_hidden();
+    }
+}
+
+fn update_stats_panel(
+    stats: Res<ScriptStats>,
+    panel: Single<(&mut Text, &Visibility), With<StatsPanel>>,
+) {
+    let (mut text, visibility) = panel.into_inner();
+    if *visibility == Visibility::Hidden || !stats.is_changed() {
+        return;
+    }
+    let mut lines: Vec<_> = stats
+        .0
+        .iter()
+        .map(|(name, stat)| {
+            format!(
+                "{name} : {:.2}ms avg, {:.2}ms max ({} runs)",
+                stat.average.as_secs_f64() * 1000.,
+                stat.max.as_secs_f64() * 1000.,
+                stat.runs
+            )
+        })
+        .collect();
+    lines.sort();
+    text.0 = lines.join("\n");
+}
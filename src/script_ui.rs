@@ -0,0 +1,240 @@
+use bevy::prelude::*;
+use rhai::Dynamic;
+
+use crate::{
+    script_errors::ScriptErrors,
+    sim::{RhaiScript, Sim},
+    ui::FontHandle,
+};
+
+/// Widgets declared by the sim scripts with `widget(key, definition)` :
+/// `widget("food", #{ type: "label", text: "Food" })`,
+/// `widget("fame", #{ type: "progress", label: "Fame", value: 0.5, max: 1.0 })`,
+/// `widget("feast", #{ type: "button", text: "Feast", callback: "on_feast" })`.
+/// Button callbacks are functions of a sim script, called with the sim data as `this`.
+pub struct ScriptUiPlugin;
+
+impl Plugin for ScriptUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_script_ui);
+        app.add_systems(
+            Update,
+            (
+                rebuild_script_ui,
+                update_script_ui.after(rebuild_script_ui),
+                press_script_buttons,
+            ),
+        );
+    }
+}
+
+/// Kind of a widget, and the key it was declared with
+#[derive(Clone, PartialEq, Debug)]
+enum WidgetKind {
+    Label,
+    Progress,
+    Button,
+}
+
+#[derive(Component)]
+struct ScriptUiRoot;
+
+#[derive(Component)]
+struct ScriptWidget(String);
+
+#[derive(Component)]
+struct ProgressFill;
+
+#[derive(Component)]
+struct ScriptButton;
+
+fn field_str(widget: &rhai::Map, name: &str) -> String {
+    widget.get(name).map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn field_f64(widget: &rhai::Map, name: &str, default: f64) -> f64 {
+    widget
+        .get(name)
+        .and_then(|v| {
+            v.as_float()
+                .ok()
+                .or_else(|| v.as_int().ok().map(|i| i as f64))
+        })
+        .unwrap_or(default)
+}
+
+fn widget_kind(widget: &Dynamic) -> Option<(rhai::Map, WidgetKind)> {
+    let widget = widget.clone().try_cast::<rhai::Map>()?;
+    let kind = match field_str(&widget, "type").as_str() {
+        "label" => WidgetKind::Label,
+        "progress" => WidgetKind::Progress,
+        "button" => WidgetKind::Button,
+        _ => return None,
+    };
+    Some((widget, kind))
+}
+
+fn setup_script_ui(mut commands: Commands) {
+    commands.spawn((
+        Name::new("script ui"),
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(10.),
+            top: Val::Px(40.),
+            flex_direction: FlexDirection::Column,
+            row_gap: Val::Px(4.),
+            ..default()
+        },
+        ScriptUiRoot,
+    ));
+}
+
+/// Respawn the widgets when the scripts add, remove or change the type of one
+fn rebuild_script_ui(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    root: Single<Entity, With<ScriptUiRoot>>,
+    font: Res<FontHandle>,
+    mut layout: Local<Vec<(String, WidgetKind)>>,
+) {
+    let ui = sim.ui();
+    let new_layout: Vec<_> = ui
+        .iter()
+        .filter_map(|(key, w)| Some((key.to_string(), widget_kind(w)?.1)))
+        .collect();
+    if *layout == new_layout {
+        return;
+    }
+    *layout = new_layout;
+    commands.entity(*root).despawn_related::<Children>();
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 16.,
+        ..default()
+    };
+    commands.entity(*root).with_children(|parent| {
+        for (key, kind) in layout.iter() {
+            let widget = ScriptWidget(key.clone());
+            match kind {
+                WidgetKind::Label => {
+                    parent.spawn((Text::default(), text_font.clone(), Label, widget));
+                }
+                WidgetKind::Progress => {
+                    parent
+                        .spawn(Node {
+                            flex_direction: FlexDirection::Column,
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            parent.spawn((
+                                Text::default(),
+                                text_font.clone(),
+                                Label,
+                                ScriptWidget(key.clone()),
+                            ));
+                            parent
+                                .spawn((
+                                    Node {
+                                        width: Val::Px(150.),
+                                        height: Val::Px(8.),
+                                        ..default()
+                                    },
+                                    BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn((
+                                        Node {
+                                            height: Val::Percent(100.),
+                                            ..default()
+                                        },
+                                        BackgroundColor(Color::srgb(0.35, 0.75, 0.35)),
+                                        ProgressFill,
+                                        widget,
+                                    ));
+                                });
+                        });
+                }
+                WidgetKind::Button => {
+                    parent
+                        .spawn((
+                            Button,
+                            Node {
+                                align_self: AlignSelf::Start,
+                                padding: UiRect::all(Val::Px(5.)),
+                                ..default()
+                            },
+                            BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                            ScriptButton,
+                            ScriptWidget(key.clone()),
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn((Text::default(), text_font.clone(), Label, widget));
+                        });
+                }
+            }
+        }
+    });
+}
+
+/// Keep the texts and progress bars in sync with the declared widgets
+fn update_script_ui(
+    sim: Res<Sim>,
+    mut texts: Query<(&ScriptWidget, &mut Text)>,
+    mut fills: Query<(&ScriptWidget, &mut Node), With<ProgressFill>>,
+) {
+    let ui = sim.ui();
+    for (ScriptWidget(key), mut text) in &mut texts {
+        let Some((widget, kind)) = ui.get(key.as_str()).and_then(widget_kind) else {
+            continue;
+        };
+        let new_text = match kind {
+            WidgetKind::Label | WidgetKind::Button => field_str(&widget, "text"),
+            WidgetKind::Progress => format!(
+                "{} : {:.1} / {:.1}",
+                field_str(&widget, "label"),
+                field_f64(&widget, "value", 0.),
+                field_f64(&widget, "max", 1.)
+            ),
+        };
+        if text.0 != new_text {
+            text.0 = new_text;
+        }
+    }
+    for (ScriptWidget(key), mut node) in &mut fills {
+        let Some((widget, _)) = ui.get(key.as_str()).and_then(widget_kind) else {
+            continue;
+        };
+        let max = field_f64(&widget, "max", 1.);
+        let ratio = if max > 0. {
+            (field_f64(&widget, "value", 0.) / max).clamp(0., 1.)
+        } else {
+            0.
+        };
+        node.width = Val::Percent(100. * ratio as f32);
+    }
+}
+
+/// Call the callback of the pressed script buttons
+fn press_script_buttons(
+    mut sim: ResMut<Sim>,
+    scripts: Res<Assets<RhaiScript>>,
+    mut errors: ResMut<ScriptErrors>,
+    buttons: Query<(&Interaction, &ScriptWidget), (Changed<Interaction>, With<ScriptButton>)>,
+) {
+    for (interaction, ScriptWidget(key)) in &buttons {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let Some(callback) = sim
+            .ui()
+            .get(key.as_str())
+            .and_then(widget_kind)
+            .map(|(widget, _)| field_str(&widget, "callback"))
+        else {
+            continue;
+        };
+        if let Err(e) = sim.call_with_data(&scripts, &callback) {
+            errors.push(e);
+        }
+    }
+}
@@ -1,23 +1,35 @@
 use bevy::{
     asset::{AssetLoader, LoadContext},
-    pbr::{ExtendedMaterial, MaterialExtension},
+    image::{ImageAddressMode, ImageLoaderSettings, ImageSampler, ImageSamplerDescriptor},
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialPipeline, MaterialPipelineKey},
     prelude::*,
-    render::render_resource::*,
+    render::{mesh::MeshVertexBufferLayoutRef, render_resource::*},
 };
 use serde::{Deserialize, Deserializer};
 
+use crate::map::Chunk;
+
 pub struct ShadersPlugin;
 impl Plugin for ShadersPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((
             MaterialPlugin::<MapMaterial>::default(),
-            //MaterialPlugin::<BuildMaterial>::default(),
+            MaterialPlugin::<WaterMaterial>::default(),
+            MaterialPlugin::<BuildMaterial>::default(),
+            // the outlines are only drawn in the main pass, their depth would hide the parts
+            MaterialPlugin::<OutlineMaterial> {
+                prepass_enabled: false,
+                shadows_enabled: false,
+                ..default()
+            },
         ));
         app.init_asset_loader::<MapMaterialLoader>();
+        app.init_asset_loader::<WaterMaterialLoader>();
     }
 }
 
 const MAP_SHADER_ASSET_PATH: &str = "shaders/map_material.wgsl";
+const WATER_SHADER_ASSET_PATH: &str = "shaders/water_material.wgsl";
 
 #[derive(Asset, AsBindGroup, PartialEq, Debug, Clone, Component, Reflect)]
 #[reflect(PartialEq)]
@@ -32,6 +44,74 @@ pub struct TerrainShader {
     pub snow_color: LinearRgba,
     #[uniform(104)]
     pub sand_color: LinearRgba,
+    /// Large scale color variation : x is the hue strength, y the brightness strength,
+    /// z the scale of the variation in world units and w the world seed.
+    #[uniform(105)]
+    pub macro_variation: Vec4,
+    /// Normalized heights where the ground changes : x is the shore, y where the sand ends,
+    /// z where the rock starts and w where the snow starts.
+    #[uniform(106)]
+    pub heights: Vec4,
+    /// x is the slope (1 - normal.y) from which the ground is rock, y the width of the
+    /// transitions, z the width of the transition to the rock, and w the size of the textures
+    /// in world units. w is 0 without `splat_textures` : the flat colors are used.
+    #[uniform(107)]
+    pub layers: Vec4,
+    /// Grass, rock, sand and snow layers
+    #[texture(108, dimension = "2d_array")]
+    #[sampler(109)]
+    pub splat_textures: Option<Handle<Image>>,
+    /// Overlays : x is the spacing of the grid (0 hides it), y the number of squares between
+    /// brighter lines, z the height between contour lines (0 hides them) and w the opacity
+    /// of the heatmap (0 hides it).
+    /// Set by the game, not by the material file.
+    #[uniform(110)]
+    pub overlay: Vec4,
+    /// Flow of the water on the chunk : rg is its direction and b its speed.
+    /// Each chunk has its own, the material loaded from the file has none.
+    #[texture(111)]
+    #[sampler(112)]
+    pub flow_map: Option<Handle<Image>>,
+    /// Ripples along the rivers : x is their speed, y their size in world units, z their
+    /// strength and w the hydro amount from which the ground is a river.
+    #[uniform(113)]
+    pub river: Vec4,
+    /// x is the size of a chunk in world units, y in cells, to find the texels of the chunk data
+    #[uniform(114)]
+    pub chunk: Vec4,
+    /// Values of the shown heatmap layer on the chunk
+    #[texture(115)]
+    #[sampler(116)]
+    pub heatmap: Option<Handle<Image>>,
+    /// How much each cell of the chunk has been explored, from 0 to 1
+    #[texture(117)]
+    #[sampler(118)]
+    pub explored: Option<Handle<Image>>,
+    /// Look of the unexplored terrain : rgb is the color it fades to, a how much (0 shows
+    /// the whole map).
+    #[uniform(119)]
+    pub fog_of_war: LinearRgba,
+    /// Stylized shading : x is 1 when it is on, set by the game from the settings,
+    /// y the number of light bands, z the strength of the rim light and w its sharpness.
+    #[uniform(120)]
+    pub toon: Vec4,
+    /// Tint of the darkest band of the stylized shading, its alpha is the strength
+    #[uniform(121)]
+    pub shadow_color: LinearRgba,
+    #[uniform(122)]
+    pub rim_color: LinearRgba,
+    /// Colorblind settings : x is the ramp of the heatmap, y 1 to hatch it. Set by the game.
+    #[uniform(123)]
+    pub palette: Vec4,
+}
+
+impl TerrainShader {
+    /// Keep the data textures of a chunk when its material is replaced
+    pub fn keep_chunk_data(&mut self, chunk: &TerrainShader) {
+        self.flow_map = chunk.flow_map.clone();
+        self.heatmap = chunk.heatmap.clone();
+        self.explored = chunk.explored.clone();
+    }
 }
 
 impl MaterialExtension for TerrainShader {
@@ -44,25 +124,113 @@ impl MaterialExtension for TerrainShader {
     }
 }
 
-// const BUILD_SHADER_ASSET_PATH: &str = "shaders/extended_material.wgsl";
+/// Animated water, for oceans and rivers. Its color goes from shallow to deep with the
+/// depth of the water, read from the depth prepass, and it foams along the shores.
+#[derive(Asset, AsBindGroup, PartialEq, Debug, Clone, Component, Reflect)]
+#[reflect(PartialEq)]
+pub struct WaterShader {
+    #[uniform(100)]
+    pub shallow_color: LinearRgba,
+    #[uniform(101)]
+    pub deep_color: LinearRgba,
+    #[uniform(102)]
+    pub foam_color: LinearRgba,
+    /// x is the scale of the waves in world units, y the strength of their normals,
+    /// z the width of the foam and w the depth at which the water gets the deep color.
+    #[uniform(103)]
+    pub waves: Vec4,
+    /// Scrolling of the two layers of normals, in world units per second
+    #[uniform(104)]
+    pub scroll: Vec4,
+    /// Whether `normal_map` is set. Without it the waves are procedural.
+    #[uniform(105)]
+    pub has_normal_map: u32,
+    #[texture(106)]
+    #[sampler(107)]
+    pub normal_map: Option<Handle<Image>>,
+}
 
-// #[derive(Asset, AsBindGroup, PartialEq, Debug, Clone, Component, Reflect)]
-// #[reflect(PartialEq)]
-// pub struct BuildShader {
-//     //The color modification when a part is selected
-//     #[uniform(101)]
-//     pub highlight_color: LinearRgba,
-// }
+impl MaterialExtension for WaterShader {
+    fn fragment_shader() -> ShaderRef {
+        WATER_SHADER_ASSET_PATH.into()
+    }
 
-// impl MaterialExtension for BuildShader {
-//     fn fragment_shader() -> ShaderRef {
-//         BUILD_SHADER_ASSET_PATH.into()
-//     }
+    fn deferred_fragment_shader() -> ShaderRef {
+        WATER_SHADER_ASSET_PATH.into()
+    }
+}
 
-//     fn deferred_fragment_shader() -> ShaderRef {
-//         BUILD_SHADER_ASSET_PATH.into()
-//     }
-// }
+const BUILD_SHADER_ASSET_PATH: &str = "shaders/build_material.wgsl";
+
+/// Glow of the highlighted and selected parts, on top of their own material, and the
+/// stylized shading of the parts
+#[derive(Asset, AsBindGroup, PartialEq, Debug, Clone, Component, Reflect)]
+#[reflect(PartialEq)]
+pub struct BuildShader {
+    /// Color of the rim glow, its alpha is the strength
+    #[uniform(100)]
+    pub highlight_color: LinearRgba,
+    /// x is the speed of the pulse (0 for a steady glow), y the sharpness of the rim
+    #[uniform(101)]
+    pub highlight_params: Vec4,
+    /// Stylized shading, as for the terrain
+    #[uniform(102)]
+    pub toon: Vec4,
+    #[uniform(103)]
+    pub shadow_color: LinearRgba,
+    #[uniform(104)]
+    pub rim_color: LinearRgba,
+}
+
+impl MaterialExtension for BuildShader {
+    fn fragment_shader() -> ShaderRef {
+        BUILD_SHADER_ASSET_PATH.into()
+    }
+
+    fn deferred_fragment_shader() -> ShaderRef {
+        BUILD_SHADER_ASSET_PATH.into()
+    }
+}
+
+const OUTLINE_SHADER_ASSET_PATH: &str = "shaders/outline.wgsl";
+
+/// Outline of the highlighted and selected parts : their mesh again, grown along its normals
+/// and drawn flat with only its back faces, so that it shows around the part whatever the
+/// lighting and the shadows
+#[derive(Asset, AsBindGroup, PartialEq, Debug, Clone, Reflect)]
+#[reflect(PartialEq)]
+pub struct OutlineMaterial {
+    #[uniform(0)]
+    pub color: LinearRgba,
+    /// x is the width of the outline, as a share of the distance to the camera
+    #[uniform(1)]
+    pub params: Vec4,
+}
+
+impl Material for OutlineMaterial {
+    fn vertex_shader() -> ShaderRef {
+        OUTLINE_SHADER_ASSET_PATH.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        OUTLINE_SHADER_ASSET_PATH.into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let vertex_layout = layout.0.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+        ])?;
+        descriptor.vertex.buffers = vec![vertex_layout];
+        descriptor.primitive.cull_mode = Some(Face::Front);
+        Ok(())
+    }
+}
 
 fn deser_color<'de, D>(deserializer: D) -> Result<LinearRgba, D::Error>
 where D: Deserializer<'de> {
@@ -71,7 +239,8 @@ where D: Deserializer<'de> {
 }
 
 pub type MapMaterial = ExtendedMaterial<StandardMaterial, TerrainShader>;
-//pub type BuildMaterial = ExtendedMaterial<StandardMaterial, BuildShader>;
+pub type WaterMaterial = ExtendedMaterial<StandardMaterial, WaterShader>;
+pub type BuildMaterial = ExtendedMaterial<StandardMaterial, BuildShader>;
 
 #[derive(Deserialize)]
 #[serde(default)]
@@ -150,6 +319,222 @@ pub struct MapMaterialParams {
     pub snow_color: LinearRgba,
     #[serde(deserialize_with = "deser_color")]
     pub sand_color: LinearRgba,
+    #[serde(default)]
+    pub macro_variation: MacroVariationParams,
+    #[serde(default)]
+    pub thresholds: TerrainThresholds,
+    #[serde(default)]
+    pub splatting: Option<SplatParams>,
+    #[serde(default)]
+    pub river: RiverParams,
+    #[serde(default)]
+    pub fog_of_war: FogOfWarParams,
+    #[serde(default)]
+    pub toon: ToonParams,
+}
+
+/// Stylized shading, used instead of the realistic one when chosen in the settings :
+/// the light is cut in bands, and a rim of light outlines the shapes.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ToonParams {
+    pub bands: f32,
+    /// Tint of the darkest band, its alpha is the strength
+    #[serde(deserialize_with = "deser_color")]
+    pub shadow_color: LinearRgba,
+    #[serde(deserialize_with = "deser_color")]
+    pub rim_color: LinearRgba,
+    pub rim_strength: f32,
+    pub rim_sharpness: f32,
+}
+
+impl Default for ToonParams {
+    fn default() -> Self {
+        Self {
+            bands: 3.,
+            shadow_color: Srgba::hex("3b3f6699").unwrap().into(),
+            rim_color: LinearRgba::WHITE,
+            rim_strength: 0.3,
+            rim_sharpness: 4.,
+        }
+    }
+}
+
+impl ToonParams {
+    /// Uniform of the shading, with x telling whether it is on
+    pub fn uniform(&self, on: bool) -> Vec4 {
+        Vec4::new(
+            if on { 1. } else { 0. },
+            self.bands,
+            self.rim_strength,
+            self.rim_sharpness,
+        )
+    }
+}
+
+/// Unexplored terrain, desaturated and faded to a color
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FogOfWarParams {
+    #[serde(deserialize_with = "deser_color")]
+    pub color: LinearRgba,
+    /// How much the unexplored terrain fades to the color, 0 disables the fog
+    pub strength: f32,
+}
+
+impl Default for FogOfWarParams {
+    fn default() -> Self {
+        Self {
+            color: Srgba::rgb_u8(0x1a, 0x1d, 0x26).into(),
+            strength: 0.85,
+        }
+    }
+}
+
+/// Ripples moving along the rivers, with the speed of the flow
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct RiverParams {
+    pub speed: f32,
+    /// Size of the ripples in world units
+    pub ripple_scale: f32,
+    pub strength: f32,
+    /// Hydro amount from which the ground is a river
+    pub min_amount: f32,
+}
+
+impl Default for RiverParams {
+    fn default() -> Self {
+        Self {
+            speed: 0.5,
+            ripple_scale: 1.5,
+            strength: 0.2,
+            min_amount: 20.,
+        }
+    }
+}
+
+/// Textures of the terrain, picked by height and slope. Cliffs use a triplanar projection.
+#[derive(Deserialize)]
+pub struct SplatParams {
+    /// Image with the grass, rock, sand and snow textures stacked vertically, in this order
+    pub textures: String,
+    /// Size of the textures in world units
+    #[serde(default = "default_splat_scale")]
+    pub scale: f32,
+}
+
+fn default_splat_scale() -> f32 {
+    8.
+}
+
+/// Normalized heights and slope where the ground changes, for the colors and the textures
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct TerrainThresholds {
+    /// Under it is the ocean floor
+    pub shore_height: f32,
+    pub sand_height: f32,
+    pub rock_height: f32,
+    pub snow_height: f32,
+    /// Slope (1 - normal.y) from which the ground is rock, whatever its height
+    pub rock_slope: f32,
+    /// Width of the transitions between layers
+    pub blend: f32,
+    /// Width of the transition from the grass to the rock, usually wider
+    pub rock_blend: f32,
+}
+
+impl Default for TerrainThresholds {
+    fn default() -> Self {
+        Self {
+            shore_height: 0.3425,
+            sand_height: 0.3475,
+            rock_height: 0.47,
+            snow_height: 0.55,
+            rock_slope: 0.4,
+            blend: 0.0025,
+            rock_blend: 0.05,
+        }
+    }
+}
+
+/// Strength of the macro-variation noise that breaks the repetition of large terrain areas.
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct MacroVariationParams {
+    pub hue_strength: f32,
+    pub brightness_strength: f32,
+    pub scale: f32,
+}
+
+impl Default for MacroVariationParams {
+    fn default() -> Self {
+        Self {
+            hue_strength: 0.05,
+            brightness_strength: 0.1,
+            scale: 300.,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct WaterMaterialParams {
+    #[serde(default)]
+    pub pbr: StandardMaterialParams,
+    #[serde(deserialize_with = "deser_color")]
+    pub shallow_color: LinearRgba,
+    #[serde(deserialize_with = "deser_color")]
+    pub deep_color: LinearRgba,
+    #[serde(deserialize_with = "deser_color")]
+    pub foam_color: LinearRgba,
+    /// Tangent space normal map of the waves, tiled over the water
+    #[serde(default)]
+    pub normal_map: Option<String>,
+    #[serde(default)]
+    pub waves: WaveParams,
+    #[serde(default)]
+    pub shore: ShoreParams,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct WaveParams {
+    /// Size of the waves in world units
+    pub scale: f32,
+    pub strength: f32,
+    /// Scrolling of the first layer of normals, in world units per second
+    pub first_scroll: (f32, f32),
+    pub second_scroll: (f32, f32),
+}
+
+impl Default for WaveParams {
+    fn default() -> Self {
+        Self {
+            scale: 6.,
+            strength: 0.4,
+            first_scroll: (0.3, 0.1),
+            second_scroll: (-0.15, 0.25),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct ShoreParams {
+    /// Depth of water under which it foams
+    pub foam_width: f32,
+    /// Depth of water from which it has the deep color
+    pub deep_depth: f32,
+}
+
+impl Default for ShoreParams {
+    fn default() -> Self {
+        Self {
+            foam_width: 0.3,
+            deep_depth: 4.,
+        }
+    }
 }
 
 // #[derive(Deserialize)]
@@ -161,6 +546,37 @@ pub struct MapMaterialParams {
 //     pub highlight_color: LinearRgba,
 // }
 
+/// Number of layers of the terrain texture array
+const SPLAT_LAYERS: u32 = 4;
+
+/// Load the stacked terrain textures as a tiled texture array
+async fn load_splat_textures(
+    load_context: &mut LoadContext<'_>,
+    path: &str,
+) -> anyhow::Result<Handle<Image>> {
+    let mut image = load_context
+        .loader()
+        .with_settings(|settings: &mut ImageLoaderSettings| {
+            settings.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+                address_mode_u: ImageAddressMode::Repeat,
+                address_mode_v: ImageAddressMode::Repeat,
+                ..ImageSamplerDescriptor::linear()
+            });
+        })
+        .immediate()
+        .load::<Image>(path)
+        .await?
+        .take();
+    if image.height() % SPLAT_LAYERS != 0 {
+        anyhow::bail!(
+            "The terrain textures {path} should be {SPLAT_LAYERS} textures of the same size \
+             stacked vertically"
+        );
+    }
+    image.reinterpret_stacked_2d_as_array(SPLAT_LAYERS);
+    Ok(load_context.add_labeled_asset("splat_textures".to_string(), image))
+}
+
 #[derive(Default)]
 pub struct MapMaterialLoader;
 
@@ -182,12 +598,50 @@ impl AssetLoader for MapMaterialLoader {
         reader.read_to_end(&mut bytes).await?;
         let mat_params = ron::de::from_bytes::<MapMaterialParams>(&bytes)?;
         let base = mat_params.pbr.to_mat(load_context);
+        let (splat_textures, splat_scale) = match mat_params.splatting {
+            Some(splat) => (
+                Some(load_splat_textures(load_context, &splat.textures).await?),
+                splat.scale,
+            ),
+            None => (None, 0.),
+        };
+        let t = mat_params.thresholds;
         let extension = TerrainShader {
             grass_color: mat_params.grass_color,
             ocean_color: mat_params.ocean_color,
             mountain_color: mat_params.mountain_color,
             snow_color: mat_params.snow_color,
             sand_color: mat_params.sand_color,
+            // The seed is filled in by the map once the material is loaded
+            macro_variation: Vec4::new(
+                mat_params.macro_variation.hue_strength,
+                mat_params.macro_variation.brightness_strength,
+                mat_params.macro_variation.scale,
+                0.,
+            ),
+            heights: Vec4::new(t.shore_height, t.sand_height, t.rock_height, t.snow_height),
+            layers: Vec4::new(t.rock_slope, t.blend, t.rock_blend, splat_scale),
+            splat_textures,
+            overlay: Vec4::ZERO,
+            flow_map: None,
+            river: Vec4::new(
+                mat_params.river.speed,
+                mat_params.river.ripple_scale,
+                mat_params.river.strength,
+                mat_params.river.min_amount,
+            ),
+            chunk: Vec4::new(Chunk::world_size(), Chunk::size() as f32, 0., 0.),
+            heatmap: None,
+            explored: None,
+            fog_of_war: mat_params
+                .fog_of_war
+                .color
+                .with_alpha(mat_params.fog_of_war.strength),
+            // switched on by the game from the settings
+            toon: mat_params.toon.uniform(false),
+            shadow_color: mat_params.toon.shadow_color,
+            rim_color: mat_params.toon.rim_color,
+            palette: Vec4::ZERO,
         };
         Ok(MapMaterial {base, extension})
     }
@@ -197,6 +651,64 @@ impl AssetLoader for MapMaterialLoader {
     }
 }
 
+#[derive(Default)]
+pub struct WaterMaterialLoader;
+
+impl AssetLoader for WaterMaterialLoader {
+    type Asset = WaterMaterial;
+
+    type Settings = ();
+
+    type Error = anyhow::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn bevy::asset::io::Reader,
+        _settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+
+        reader.read_to_end(&mut bytes).await?;
+        let mat_params = ron::de::from_bytes::<WaterMaterialParams>(&bytes)?;
+        let base = mat_params.pbr.to_mat(load_context);
+        // The normal map is tiled, and holds directions rather than colors
+        let normal_map = mat_params.normal_map.map(|path| {
+            load_context
+                .loader()
+                .with_settings(|settings: &mut ImageLoaderSettings| {
+                    settings.is_srgb = false;
+                    settings.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+                        address_mode_u: ImageAddressMode::Repeat,
+                        address_mode_v: ImageAddressMode::Repeat,
+                        ..ImageSamplerDescriptor::linear()
+                    });
+                })
+                .load(path)
+        });
+        let (waves, shore) = (mat_params.waves, mat_params.shore);
+        let extension = WaterShader {
+            shallow_color: mat_params.shallow_color,
+            deep_color: mat_params.deep_color,
+            foam_color: mat_params.foam_color,
+            waves: Vec4::new(waves.scale, waves.strength, shore.foam_width, shore.deep_depth),
+            scroll: Vec4::new(
+                waves.first_scroll.0,
+                waves.first_scroll.1,
+                waves.second_scroll.0,
+                waves.second_scroll.1,
+            ),
+            has_normal_map: normal_map.is_some() as u32,
+            normal_map,
+        };
+        Ok(WaterMaterial {base, extension})
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["watermat"]
+    }
+}
+
 
 // #[derive(Default)]
 // pub struct BuildMaterialLoader;
@@ -0,0 +1,327 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    input::keyboard::{Key, KeyboardInput},
+    pbr::NotShadowCaster,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+    },
+};
+
+use crate::ui::{FontHandle, TextFocus};
+
+/// Signs : props displaying a player-editable text above them, edited from their info panel
+pub struct SignPlugin;
+
+impl Plugin for SignPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                spawn_sign_labels,
+                update_sign_labels,
+                focus_sign_fields,
+                edit_sign.after(focus_sign_fields),
+                update_sign_fields.after(edit_sign),
+            ),
+        );
+    }
+}
+
+/// Scale of the sign post mesh
+pub const SIGN_SCALE: Vec3 = Vec3::new(0.2, 1.5, 1.);
+/// Distance at which labels start fading out
+const LABEL_FADE_START: f32 = 30.;
+/// Distance at which labels are fully hidden
+const LABEL_FADE_END: f32 = 60.;
+/// Size of the texture a label is drawn to, in pixels
+const LABEL_TEXTURE_SIZE: UVec2 = UVec2::new(256, 64);
+/// Width of a label in world units, its height follows the texture
+const LABEL_WIDTH: f32 = 3.;
+/// Frames a label camera draws after a change, for the text to be laid out
+const REDRAW_FRAMES: u8 = 3;
+
+/// The text displayed by a sign
+#[derive(Component, Clone, Debug)]
+pub struct SignLabel(pub String);
+
+/// Label of a sign in the world : a ui camera draws its text to a texture, shown on a quad
+/// above the sign turned toward the camera. The camera only runs when the text changes.
+#[derive(Component)]
+pub struct SignBillboard {
+    pub sign: Entity,
+    camera: Entity,
+    root: Entity,
+    text: Entity,
+    material: Handle<StandardMaterial>,
+    alpha: f32,
+    redraw: u8,
+}
+
+/// Field of the info panel of a sign, editing its text when clicked
+#[derive(Component)]
+pub struct SignTextField {
+    sign: Entity,
+}
+
+fn label_height() -> f32 {
+    LABEL_WIDTH * LABEL_TEXTURE_SIZE.y as f32 / LABEL_TEXTURE_SIZE.x as f32
+}
+
+fn spawn_sign_labels(
+    mut commands: Commands,
+    signs: Query<(Entity, &SignLabel), Added<SignLabel>>,
+    font: Res<FontHandle>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut quad: Local<Option<Handle<Mesh>>>,
+) {
+    for (sign, label) in &signs {
+        let size = Extent3d {
+            width: LABEL_TEXTURE_SIZE.x,
+            height: LABEL_TEXTURE_SIZE.y,
+            ..default()
+        };
+        let mut image = Image::new_fill(
+            size,
+            TextureDimension::D2,
+            &[0; 4],
+            TextureFormat::Bgra8UnormSrgb,
+            RenderAssetUsages::default(),
+        );
+        image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+            | TextureUsages::COPY_DST
+            | TextureUsages::RENDER_ATTACHMENT;
+        let image = images.add(image);
+        let camera = commands
+            .spawn((
+                Name::new("sign label camera"),
+                Camera2d,
+                Camera {
+                    order: -1,
+                    target: RenderTarget::Image(image.clone().into()),
+                    clear_color: ClearColorConfig::Custom(Color::NONE),
+                    ..default()
+                },
+            ))
+            .id();
+        let text = commands
+            .spawn((
+                Text(label.0.clone()),
+                TextFont {
+                    font: font.0.clone(),
+                    font_size: 36.,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+                Node {
+                    padding: UiRect::axes(Val::Px(8.), Val::Px(2.)),
+                    ..default()
+                },
+                BackgroundColor(Color::BLACK.with_alpha(0.5)),
+            ))
+            .id();
+        let root = commands
+            .spawn((
+                Name::new("sign label text"),
+                Node {
+                    width: Val::Percent(100.),
+                    height: Val::Percent(100.),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    overflow: Overflow::clip(),
+                    ..default()
+                },
+                UiTargetCamera(camera),
+            ))
+            .add_child(text)
+            .id();
+        let material = materials.add(StandardMaterial {
+            base_color_texture: Some(image),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            cull_mode: None,
+            ..default()
+        });
+        let quad = quad
+            .get_or_insert_with(|| meshes.add(Rectangle::new(LABEL_WIDTH, label_height())))
+            .clone();
+        commands.spawn((
+            Name::new("sign label"),
+            Mesh3d(quad),
+            MeshMaterial3d(material.clone()),
+            Transform::default(),
+            Visibility::Hidden,
+            NotShadowCaster,
+            SignBillboard {
+                sign,
+                camera,
+                root,
+                text,
+                material,
+                alpha: 1.,
+                redraw: REDRAW_FRAMES,
+            },
+        ));
+    }
+}
+
+/// Keep the labels above their sign and turned toward the camera, fade them with the
+/// distance, and redraw their text when it changes.
+fn update_sign_labels(
+    mut commands: Commands,
+    mut labels: Query<(Entity, &mut SignBillboard, &mut Transform, &mut Visibility)>,
+    signs: Query<(&GlobalTransform, &InheritedVisibility, Ref<SignLabel>)>,
+    mut texts: Query<&mut Text>,
+    mut cameras: Query<&mut Camera, Without<Camera3d>>,
+    view: Single<&GlobalTransform, With<Camera3d>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    fonts: Res<Assets<Font>>,
+    font: Res<FontHandle>,
+) {
+    let view = view.compute_transform();
+    for (e, mut billboard, mut transform, mut visibility) in &mut labels {
+        let Ok((sign_transform, sign_visibility, label)) = signs.get(billboard.sign) else {
+            commands.entity(billboard.root).despawn();
+            commands.entity(billboard.camera).despawn();
+            commands.entity(e).despawn();
+            continue;
+        };
+        if label.is_changed() {
+            if let Ok(mut text) = texts.get_mut(billboard.text) {
+                text.0 = label.0.clone();
+            }
+            billboard.redraw = REDRAW_FRAMES;
+        }
+        let drawing = billboard.redraw > 0;
+        // the text isn't laid out before its font is loaded
+        if drawing && fonts.contains(&font.0) {
+            billboard.redraw -= 1;
+        }
+        if let Ok(mut camera) = cameras.get_mut(billboard.camera) {
+            if camera.is_active != drawing {
+                camera.is_active = drawing;
+            }
+        }
+        let anchor = sign_transform.translation() + Vec3::Y * (SIGN_SCALE.y + label_height());
+        let distance = anchor.distance(view.translation);
+        let shown = sign_visibility.get() && distance < LABEL_FADE_END && !label.0.is_empty();
+        visibility.set_if_neq(if shown {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        });
+        if !shown {
+            continue;
+        }
+        transform.translation = anchor;
+        transform.rotation = view.rotation;
+        let alpha = 1.
+            - ((distance - LABEL_FADE_START) / (LABEL_FADE_END - LABEL_FADE_START)).clamp(0., 1.);
+        if (alpha - billboard.alpha).abs() > 0.01 {
+            billboard.alpha = alpha;
+            if let Some(material) = materials.get_mut(&billboard.material) {
+                material.base_color = Color::WHITE.with_alpha(alpha);
+            }
+        }
+    }
+}
+
+/// Spawn the field editing the text of a sign, in its info panel
+pub fn spawn_sign_field(parent: &mut ChildSpawnerCommands, sign: Entity, font: &TextFont) {
+    parent.spawn((
+        Button,
+        Node {
+            align_self: AlignSelf::Stretch,
+            padding: UiRect::all(Val::Px(4.)),
+            ..default()
+        },
+        BackgroundColor(Color::srgb(0.2, 0.2, 0.2)),
+        SignTextField { sign },
+        children![(Text::default(), font.clone(), Label, Pickable::IGNORE)],
+    ));
+}
+
+/// Start editing a sign on clicking its field
+fn focus_sign_fields(
+    fields: Query<(&Interaction, &SignTextField), Changed<Interaction>>,
+    mut text_focus: ResMut<TextFocus>,
+) {
+    for (interaction, field) in &fields {
+        if *interaction == Interaction::Pressed && text_focus.0.is_none() {
+            text_focus.0 = Some(field.sign);
+        }
+    }
+}
+
+/// Type into the sign being edited. Enter or Escape stop the edition.
+fn edit_sign(
+    mut events: EventReader<KeyboardInput>,
+    mut text_focus: ResMut<TextFocus>,
+    mut signs: Query<&mut SignLabel>,
+) {
+    let Some(focused) = text_focus.0 else {
+        events.clear();
+        return;
+    };
+    // skip the key press that started the edition
+    if text_focus.is_changed() {
+        events.clear();
+        return;
+    }
+    let Ok(mut label) = signs.get_mut(focused) else {
+        return;
+    };
+    for ev in events.read() {
+        if !ev.state.is_pressed() {
+            continue;
+        }
+        match &ev.logical_key {
+            Key::Character(c) => label.0.push_str(c),
+            Key::Space => label.0.push(' '),
+            Key::Backspace => {
+                label.0.pop();
+            }
+            Key::Enter | Key::Escape => {
+                text_focus.0 = None;
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Show the text of the signs in their fields, with a cursor while it is edited.
+/// Closing the field stops the edition.
+fn update_sign_fields(
+    fields: Query<(Ref<SignTextField>, &Children)>,
+    signs: Query<Ref<SignLabel>>,
+    mut texts: Query<&mut Text>,
+    mut text_focus: ResMut<TextFocus>,
+) {
+    if let Some(focused) = text_focus.0 {
+        if signs.contains(focused) && !fields.iter().any(|(field, _)| field.sign == focused) {
+            text_focus.0 = None;
+        }
+    }
+    for (field, children) in &fields {
+        let Ok(label) = signs.get(field.sign) else {
+            continue;
+        };
+        if !field.is_added() && !label.is_changed() && !text_focus.is_changed() {
+            continue;
+        }
+        let text = if text_focus.0 == Some(field.sign) {
+            format!("{}_", label.0)
+        } else {
+            label.0.clone()
+        };
+        for child in children {
+            if let Ok(mut t) = texts.get_mut(*child) {
+                t.0 = text.clone();
+            }
+        }
+    }
+}
@@ -1,17 +1,56 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext, LoadedFolder};
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
 use bevy::ecs::relationship::RelatedSpawnerCommands;
-use bevy::platform::collections::HashMap;
+use bevy::platform::collections::{HashMap, HashSet};
+use bevy::platform::time::Instant;
 use bevy::prelude::*;
+use bevy::tasks::futures_lite::future::{block_on, poll_once};
+use bevy::tasks::{AsyncComputeTaskPool, Task};
 use foldhash::fast::FixedState;
 use rhai::Scope;
-use rhai::{Engine, ImmutableString};
+use rhai::{AST, CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, ImmutableString};
+use serde::{Deserialize, Serialize};
+
+use crate::build::{Building, BuildingPlaced, BuildingRemoved, Disabled, GameId};
+use crate::canals::{Irrigation, register_canal_api};
+use crate::clock::register_clock_api;
+use crate::dams::register_dam_api;
+use crate::deposits::{Deposits, register_deposit_api};
+use crate::disasters::{Disasters, register_disaster_api};
+use crate::extraction::register_extraction_api;
+use crate::fog_of_war::{EXPLORATION_QUICKSAVE_PATH, Exploration};
+use crate::graph::{GraphedStat, StatGraph, StatGraphLabel};
+use crate::input_map::{Action, Actions};
+use crate::land_value::register_land_value_api;
+use crate::localization::LocalizedText;
+use crate::logistics::{Freight, register_freight_api};
+use crate::map::BuildingInstance;
+use crate::menu::GameState;
+use crate::pause_menu::Pause;
+use crate::pollution::{PollutionGrid, register_pollution_api};
+use crate::population::{Population, register_population_api};
+use crate::research::{Research, register_research_api};
+use crate::script_api::{
+    MAP_FUNCTIONS, ScriptEvent, SharedScriptWorld, register_building_api, register_event_api,
+    register_map_api, register_ui_api, run_scripts_with_world,
+};
+use crate::script_errors::{ScriptError, ScriptErrors, script_name};
+use crate::script_limits::{ScriptLimits, ScriptStats};
+use crate::sim_rng::{SimRng, register_rng_api, seed_sim_rng};
+use crate::trade::{Market, register_trade_api};
+use crate::ui::FontHandle;
+use crate::weather::register_weather_api;
+use crate::wind::register_wind_api;
+use crate::zones::Zoning;
 
 #[derive(Asset, TypePath, Debug)]
 pub struct RhaiScript {
     text: String,
-    ast: Option<rhai::AST>,
 }
 
 #[derive(Default)]
@@ -34,10 +73,7 @@ impl AssetLoader for RhaiScriptLoader {
 
         reader.read_to_string(&mut buf).await?;
 
-        Ok(RhaiScript {
-            text: buf,
-            ast: None,
-        })
+        Ok(RhaiScript { text: buf })
     }
 
     fn extensions(&self) -> &[&str] {
@@ -47,91 +83,1250 @@ impl AssetLoader for RhaiScriptLoader {
 
 #[derive(Resource)]
 pub struct Sim {
-    init: Handle<RhaiScript>,
-    run: Handle<RhaiScript>,
+    /// The `scripts` folder, every script outside of `scripts/buildings` is a sim script
+    folder: Handle<LoadedFolder>,
+    /// Sim scripts, sorted by path. Their hooks are called in this order.
+    scripts: Vec<Handle<RhaiScript>>,
+    /// Building hooks to call at the start of the next tick, with their argument
+    pending_hooks: Vec<(&'static str, Dynamic)>,
+    /// Resources added to `data.resource` at the start of the next tick
+    granted: Vec<(String, f64)>,
+    /// Resources set in `data.resource` after the `on_init` hooks ran, by the scenario
+    pub starting_resources: BTreeMap<String, f64>,
     initialized: bool,
     scope: rhai::Scope<'static>, //dynamic storing a boxed sim_data
-    engine: Engine,
+    engine: Arc<Engine>,
+    /// Compiled scripts, by asset. A script that fails to compile keeps its last good AST.
+    asts: HashMap<AssetId<RhaiScript>, AST>,
+    /// Tick of the sim scripts currently computed in the background
+    running: Option<RunningTick>,
     values: HashMap<u64, f64>,
+    /// Stats whose value changed the last time the values were read from the data
+    changed_stats: HashSet<u64>,
+    /// Hash of the paths of every stat, changes when stats are added or removed
+    layout: u64,
+    /// Last `HISTORY_LEN` values of each stat, one sample per tick
+    history: HashMap<u64, VecDeque<f64>>,
+    /// Tick of each sample of the history. All stats are sampled together, so the last
+    /// samples of a stat match the last ticks, even for stats that appeared later.
+    sample_ticks: VecDeque<u64>,
+    /// Dotted path of each stat in the sim data
+    stat_names: HashMap<u64, String>,
+    /// Number of simulation ticks run so far.
+    pub tick: u64,
+    /// Incremented each time the sim data is reset by the `on_init` hooks.
+    generation: u64,
+    /// Time taken by the scripts during the last tick
+    last_tick_duration: Duration,
+    pub(crate) script_world: SharedScriptWorld,
+    pub(crate) rng: SimRng,
 }
 
 impl Default for Sim {
     fn default() -> Self {
-        let engine = Engine::new();
+        let mut engine = Engine::new();
+        let script_world = SharedScriptWorld::default();
+        let rng = SimRng::default();
+        register_storage(&mut engine);
+        register_map_api(&mut engine, &script_world);
+        register_building_api(&mut engine, &script_world);
+        register_event_api(&mut engine, &script_world);
+        register_rng_api(&mut engine, &rng);
+        register_ui_api(&mut engine, &script_world);
+        register_clock_api(&mut engine, &script_world);
+        register_weather_api(&mut engine, &script_world);
+        register_wind_api(&mut engine, &script_world);
+        register_freight_api(&mut engine, &script_world);
+        register_trade_api(&mut engine, &script_world);
+        register_pollution_api(&mut engine, &script_world);
+        register_land_value_api(&mut engine, &script_world);
+        register_population_api(&mut engine, &script_world);
+        register_research_api(&mut engine, &script_world);
+        register_dam_api(&mut engine, &script_world);
+        register_canal_api(&mut engine, &script_world);
+        register_extraction_api(&mut engine, &script_world);
+        register_disaster_api(&mut engine, &script_world);
+        register_deposit_api(&mut engine, &script_world);
+        ScriptLimits::default().apply(&mut engine);
         let mut scope = Scope::new();
         scope.push("data", rhai::Map::new());
         Self {
-            init: Default::default(),
-            run: Default::default(),
+            folder: Default::default(),
+            scripts: Vec::new(),
+            pending_hooks: Vec::new(),
+            granted: Vec::new(),
+            starting_resources: BTreeMap::new(),
             scope,
             initialized: false,
-            engine,
+            engine: Arc::new(engine),
+            asts: default(),
+            running: None,
             values: default(),
+            changed_stats: default(),
+            layout: 0,
+            history: default(),
+            sample_ticks: default(),
+            stat_names: default(),
+            tick: 0,
+            generation: 0,
+            last_tick_duration: Duration::ZERO,
+            script_world,
+            rng,
+        }
+    }
+}
+
+/// A compiled sim script, as sent to the background tick
+#[derive(Clone)]
+struct SimModule {
+    name: String,
+    text: String,
+    ast: AST,
+}
+
+impl SimModule {
+    fn has_hook(&self, hook: &str, arity: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == hook && f.params.len() == arity)
+    }
+
+    /// Whether the script calls the map functions, which only work on the main thread
+    fn uses_map(&self) -> bool {
+        MAP_FUNCTIONS.iter().any(|f| self.text.contains(f))
+    }
+
+    /// Call a function of the script with the sim data bound to `this`. `background` is the
+    /// tick running in the background the call belongs to, if any.
+    fn call(
+        &self,
+        engine: &Engine,
+        hook: &str,
+        data: &mut Dynamic,
+        args: impl FuncArgs,
+        background: Option<u64>,
+    ) -> std::result::Result<(), ScriptError> {
+        let mut options = CallFnOptions::new().eval_ast(false).bind_this_ptr(data);
+        if let Some(tick) = background {
+            options = options.with_tag(tick as i64);
+        }
+        engine
+            .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, hook, args)
+            .map(|_| ())
+            .map_err(|e| ScriptError::new(self.name.clone(), &self.text, &e))
+    }
+}
+
+/// The outcome of a background tick, with the time taken by each script
+struct TickResult {
+    data: std::result::Result<Dynamic, ScriptError>,
+    times: Vec<(String, Duration)>,
+}
+
+/// A tick of the sim scripts computed on a background thread, on a copy of the sim data.
+/// Changes made to the sim data while it runs are overwritten when it completes. The map
+/// isn't lent to it, so the ticks of scripts calling the map functions run on the main thread.
+/// The events and buildings it asks for are kept apart until it completes.
+struct RunningTick {
+    task: Task<TickResult>,
+    started: Instant,
+    /// Generation of the data the tick started from, to drop it if the sim was reset or loaded
+    generation: u64,
+    /// Whether the watchdog already reported this tick
+    reported: bool,
+}
+
+/// Serialized state of the simulation
+#[derive(Serialize, Deserialize)]
+pub struct SimSave {
+    pub tick: u64,
+    pub data: Dynamic,
+    /// Storage of the placed buildings, by game id
+    #[serde(default)]
+    pub storages: BTreeMap<u64, rhai::Map>,
+    /// Seed of the sim rng, and the number of values drawn from it
+    #[serde(default)]
+    pub rng: (u64, u64),
+    /// Freight between the buildings
+    #[serde(default)]
+    pub freight: Freight,
+    /// Prices and orders of the trade with the ships
+    #[serde(default)]
+    pub market: Market,
+    /// Pollution over the map
+    #[serde(default)]
+    pub pollution: PollutionGrid,
+    /// Residents of the homes
+    #[serde(default)]
+    pub population: Population,
+    /// Technologies researched
+    #[serde(default)]
+    pub research: Research,
+    /// Canals dug
+    #[serde(default)]
+    pub irrigation: Irrigation,
+    /// Fires, floods and damaged buildings
+    #[serde(default)]
+    pub disasters: Disasters,
+    /// Zoned areas
+    #[serde(default)]
+    pub zoning: Zoning,
+    /// Deposits of resources in the ground
+    #[serde(default)]
+    pub deposits: Deposits,
+}
+
+impl Sim {
+    /// Get a serializable snapshot of the simulation state
+    pub fn to_save(&self) -> anyhow::Result<SimSave> {
+        let data = self
+            .scope
+            .get("data")
+            .ok_or(anyhow::anyhow!("sim data missing"))?;
+        let script_world = self.script_world.0.lock().unwrap();
+        let storages = script_world
+            .storages
+            .iter()
+            .map(|(id, storage)| (*id, storage.0.read().unwrap().clone()))
+            .collect();
+        Ok(SimSave {
+            tick: self.tick,
+            data: data.clone(),
+            storages,
+            rng: self.rng.state(),
+            freight: script_world.freight.clone(),
+            market: script_world.market.clone(),
+            pollution: script_world.pollution.clone(),
+            population: script_world.population.clone(),
+            research: script_world.research.clone(),
+            irrigation: script_world.irrigation.clone(),
+            disasters: script_world.disasters.clone(),
+            zoning: script_world.zoning.clone(),
+            deposits: script_world.deposits.clone(),
+        })
+    }
+
+    /// Restore the simulation state from a snapshot
+    pub fn from_save(&mut self, save: SimSave) {
+        self.scope.set_value("data", save.data);
+        self.tick = save.tick;
+        self.rng.restore(save.rng.0, save.rng.1);
+        let mut script_world = self.script_world.0.lock().unwrap();
+        for (id, saved) in save.storages {
+            // replace in place, so that the buildings keep sharing their storage with the sim
+            *script_world
+                .storages
+                .entry(id)
+                .or_default()
+                .0
+                .write()
+                .unwrap() = saved;
         }
+        script_world.freight = save.freight;
+        script_world.market = save.market;
+        script_world.pollution = save.pollution;
+        script_world.population = save.population;
+        script_world.research = save.research;
+        script_world.irrigation = save.irrigation;
+        script_world.irrigation.rebuild();
+        script_world.disasters = save.disasters;
+        script_world.zoning = save.zoning;
+        script_world.deposits = save.deposits;
+        self.initialized = true;
+        // the structure of the data may have changed
+        self.generation += 1;
+    }
+
+    /// Recent values of a stat, oldest first
+    pub fn history(&self, stat: u64) -> Option<&VecDeque<f64>> {
+        self.history.get(&stat)
+    }
+
+    /// Ticks at which the history was sampled, oldest first
+    pub fn sample_ticks(&self) -> &VecDeque<u64> {
+        &self.sample_ticks
     }
+
+    /// Current value of a stat, by dotted path
+    pub fn value(&self, path: &str) -> Option<f64> {
+        let (id, _) = self.stat_names.iter().find(|(_, name)| *name == path)?;
+        self.values.get(id).copied()
+    }
+
+    /// Current value of a stat
+    pub fn stat_value(&self, stat: u64) -> Option<f64> {
+        self.values.get(&stat).copied()
+    }
+
+    /// Dotted path of a stat
+    pub fn stat_name(&self, stat: u64) -> Option<&str> {
+        self.stat_names.get(&stat).map(String::as_str)
+    }
+
+    /// Id and dotted path of every stat
+    pub fn stat_names(&self) -> impl Iterator<Item = (u64, &str)> {
+        self.stat_names
+            .iter()
+            .map(|(id, name)| (*id, name.as_str()))
+    }
+
+    /// AST of a script, compiled when it was loaded or modified. A script that never compiled
+    /// is compiled again, to report its error.
+    fn compiled(
+        &self,
+        handle: &Handle<RhaiScript>,
+        sc: &RhaiScript,
+    ) -> std::result::Result<AST, ScriptError> {
+        match self.asts.get(&handle.id()) {
+            Some(ast) => Ok(ast.clone()),
+            None => self
+                .engine
+                .compile(&sc.text)
+                .map_err(|e| ScriptError::new(script_name(handle), &sc.text, &e.into())),
+        }
+    }
+
+    /// Widgets declared by the scripts with `widget(key, definition)`
+    pub fn ui(&self) -> rhai::Map {
+        self.script_world.0.lock().unwrap().ui.clone()
+    }
+
+    /// Call a function of the first sim script defining it, with the sim data bound to `this`
+    pub fn call_with_data(
+        &mut self,
+        scripts: &Assets<RhaiScript>,
+        name: &str,
+    ) -> std::result::Result<(), ScriptError> {
+        let Some(module) = self.scripts.iter().find_map(|handle| {
+            let sc = scripts.get(handle)?;
+            let module = SimModule {
+                name: script_name(handle),
+                text: sc.text.clone(),
+                ast: self.asts.get(&handle.id())?.clone(),
+            };
+            module.has_hook(name, 0).then_some(module)
+        }) else {
+            return Ok(());
+        };
+        let Some(data) = self.scope.get_mut("data") else {
+            return Ok(());
+        };
+        let mut data = std::mem::take(data);
+        let result = module.call(&self.engine, name, &mut data, (), None);
+        self.scope.set_value("data", data);
+        result
+    }
+
+    /// Call a function of any script with the sim data bound to `this`, and return its result.
+    /// Returns `None` if the script isn't loaded yet or doesn't define the function.
+    pub fn eval_with_data(
+        &mut self,
+        scripts: &Assets<RhaiScript>,
+        script: &Handle<RhaiScript>,
+        name: &str,
+    ) -> std::result::Result<Option<Dynamic>, ScriptError> {
+        let Some(sc) = scripts.get(script) else {
+            return Ok(None);
+        };
+        let ast = self.compiled(script, sc)?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.is_empty())
+        {
+            return Ok(None);
+        }
+        let Some(data) = self.scope.get_mut("data") else {
+            return Ok(None);
+        };
+        let mut data = std::mem::take(data);
+        let result = self.engine.call_fn_with_options::<Dynamic>(
+            CallFnOptions::new()
+                .eval_ast(false)
+                .bind_this_ptr(&mut data),
+            &mut Scope::new(),
+            &ast,
+            name,
+            (),
+        );
+        self.scope.set_value("data", data);
+        result
+            .map(Some)
+            .map_err(|e| ScriptError::new(script_name(script), &sc.text, &e))
+    }
+
+    /// Apply the result of a background tick started on the given generation of the data
+    fn apply_tick(
+        &mut self,
+        generation: u64,
+        result: TickResult,
+        settings: &SimSettings,
+        errors: &mut ScriptErrors,
+        stats: &mut ScriptStats,
+    ) {
+        self.last_tick_duration = result.times.iter().map(|(_, time)| *time).sum();
+        for (name, time) in &result.times {
+            stats.record(name, *time);
+            if *time > settings.tick_budget {
+                warn!(
+                    "Script {} exceeded its tick budget of {:?}",
+                    name, settings.tick_budget
+                );
+            }
+        }
+        let mut script_world = self.script_world.0.lock().unwrap();
+        match result.data {
+            Ok(data) if generation == self.generation => {
+                script_world.collect_background();
+                drop(script_world);
+                self.scope.set_value("data", data);
+                self.tick += 1;
+            }
+            // the sim was reset or loaded since
+            Ok(_) => script_world.background = Default::default(),
+            Err(e) => {
+                script_world.background = Default::default();
+                errors.push(e);
+            }
+        }
+    }
+
+    /// Wait for the tick running in the background and apply it, so that changes made
+    /// to the data right after aren't overwritten
+    pub fn finish_tick(
+        &mut self,
+        settings: &SimSettings,
+        errors: &mut ScriptErrors,
+        stats: &mut ScriptStats,
+    ) {
+        if let Some(running) = self.running.take() {
+            let result = block_on(running.task);
+            self.apply_tick(running.generation, result, settings, errors, stats);
+        }
+    }
+
+    /// Evaluate an expression in the sim scope, where the sim data is `data`
+    pub fn eval(&mut self, code: &str) -> std::result::Result<Dynamic, Box<EvalAltResult>> {
+        self.engine
+            .eval_with_scope::<Dynamic>(&mut self.scope, code)
+    }
+
+    /// The sim data
+    pub fn data(&self) -> Option<&rhai::Map> {
+        self.scope.get_value_ref("data")
+    }
+
+    /// Whether the `on_init` hooks ran
+    pub fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    /// Incremented each time the sim data is reset or loaded
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn last_tick_duration(&self) -> Duration {
+        self.last_tick_duration
+    }
+
+    /// Change the settings of the script engine. Returns false if the engine is in use by
+    /// a tick running in the background.
+    pub fn configure_engine(&mut self, configure: impl FnOnce(&mut Engine)) -> bool {
+        match Arc::get_mut(&mut self.engine) {
+            Some(engine) => {
+                configure(engine);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Restart the simulation from scratch : the `on_init` hooks run again on the next tick
+    pub fn restart(&mut self) {
+        self.initialized = false;
+        self.tick = 0;
+        self.pending_hooks.clear();
+        self.granted.clear();
+        // a tick still running in the background belongs to the previous game
+        self.generation += 1;
+        let mut script_world = self.script_world.0.lock().unwrap();
+        script_world.storages.clear();
+        script_world.freight = Default::default();
+        script_world.market = Default::default();
+        script_world.pollution = Default::default();
+        script_world.population = Default::default();
+        script_world.research = Default::default();
+        script_world.irrigation = Default::default();
+        script_world.disasters = Default::default();
+        script_world.zoning = Default::default();
+        script_world.deposits = Default::default();
+        script_world.background = Default::default();
+    }
+
+    /// Amount of a resource in `data.resource`, 0 if it doesn't exist
+    pub fn resource_amount(&self, resource: &str) -> f64 {
+        self.scope
+            .get_value_ref::<rhai::Map>("data")
+            .and_then(|data| {
+                data.get("resource")?
+                    .read_lock::<rhai::Map>()?
+                    .get(resource)
+                    .cloned()
+            })
+            .and_then(|amount| amount.as_float().ok())
+            .unwrap_or(0.)
+    }
+
+    /// Set a resource in `data.resource`, creating it if needed
+    fn set_resource(&mut self, resource: &str, amount: f64) {
+        let Some(data) = self.scope.get_value_mut::<rhai::Map>("data") else {
+            return;
+        };
+        let resources = data
+            .entry("resource".into())
+            .or_insert_with(|| rhai::Map::new().into());
+        if let Some(mut resources) = resources.write_lock::<rhai::Map>() {
+            resources.insert(resource.into(), amount.into());
+        }
+    }
+
+    /// Add an amount of a resource to `data.resource` at the start of the next tick,
+    /// so that it isn't overwritten by the tick running in the background
+    pub fn grant_resource(&mut self, resource: &str, amount: f64) {
+        self.granted.push((resource.to_string(), amount));
+    }
+
+    /// Remove an amount of a resource from `data.resource`
+    pub fn spend_resource(&mut self, resource: &str, amount: f64) {
+        let Some(data) = self.scope.get_value_mut::<rhai::Map>("data") else {
+            return;
+        };
+        let Some(mut resources) = data
+            .get_mut("resource")
+            .and_then(|r| r.write_lock::<rhai::Map>())
+        else {
+            return;
+        };
+        if let Some(value) = resources.get_mut(resource) {
+            if let Ok(current) = value.as_float() {
+                *value = (current - amount).into();
+            }
+        }
+    }
+
+    /// Save the simulation state to a RON file
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let text = ron::ser::to_string_pretty(&self.to_save()?, ron::ser::PrettyConfig::default())?;
+        if let Some(parent) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Load the simulation state from a RON file
+    pub fn load(&mut self, path: &str) -> anyhow::Result<()> {
+        let save: SimSave = ron::de::from_bytes(&std::fs::read(path)?)?;
+        self.from_save(save);
+        Ok(())
+    }
+}
+
+pub const SIM_QUICKSAVE_PATH: &str = "saves/sim.ron";
+
+/// Quicksave the sim state and the explored terrain on F5, and quickload them on F9
+fn quicksave_sim(
+    mut sim: ResMut<Sim>,
+    mut exploration: ResMut<Exploration>,
+    actions: Actions,
+) -> Result {
+    if actions.just_pressed(Action::Quicksave) {
+        sim.save(SIM_QUICKSAVE_PATH)?;
+        exploration.save(EXPLORATION_QUICKSAVE_PATH)?;
+        info!("Sim saved to {}", SIM_QUICKSAVE_PATH);
+    }
+    if actions.just_pressed(Action::Quickload) {
+        sim.load(SIM_QUICKSAVE_PATH)?;
+        exploration.load(EXPLORATION_QUICKSAVE_PATH)?;
+        info!("Sim loaded from {}", SIM_QUICKSAVE_PATH);
+    }
+    Ok(())
+}
+
+/// Settings of the simulation
+#[derive(Resource)]
+pub struct SimSettings {
+    /// Number of sim ticks per second
+    pub tick_rate: f64,
+    /// Time a tick of a sim script may take before the watchdog reports it
+    pub tick_budget: Duration,
+}
+
+impl Default for SimSettings {
+    fn default() -> Self {
+        Self {
+            tick_rate: 10.,
+            tick_budget: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Speed multiplier of the simulation
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SimSpeed {
+    Paused,
+    #[default]
+    Normal,
+    Fast,
+    Faster,
+}
+
+impl SimSpeed {
+    pub fn multiplier(self) -> f64 {
+        match self {
+            SimSpeed::Paused => 0.,
+            SimSpeed::Normal => 1.,
+            SimSpeed::Fast => 2.,
+            SimSpeed::Faster => 4.,
+        }
+    }
+
+    fn faster(self) -> Self {
+        match self {
+            SimSpeed::Paused => SimSpeed::Normal,
+            SimSpeed::Normal => SimSpeed::Fast,
+            SimSpeed::Fast | SimSpeed::Faster => SimSpeed::Faster,
+        }
+    }
+
+    fn slower(self) -> Self {
+        match self {
+            SimSpeed::Faster => SimSpeed::Fast,
+            SimSpeed::Fast => SimSpeed::Normal,
+            SimSpeed::Normal | SimSpeed::Paused => SimSpeed::Paused,
+        }
+    }
+}
+
+/// Run condition for everything that should stop when the sim is paused
+/// The pause state only exists in game
+pub fn sim_running(speed: Res<SimSpeed>, pause: Option<Res<State<Pause>>>) -> bool {
+    pause.is_some_and(|pause| *pause.get() == Pause::Running) && *speed != SimSpeed::Paused
 }
 
+/// Time taken by the sim scripts during a tick, in milliseconds
+pub const TICK_DURATION: DiagnosticPath = DiagnosticPath::const_new("sim/tick_duration");
+
 pub struct SimPlugin;
 impl Plugin for SimPlugin {
     fn build(&self, app: &mut App) {
         app.init_asset::<RhaiScript>();
         app.init_asset_loader::<RhaiScriptLoader>();
-        app.insert_resource(Sim::default());
-        app.add_systems(Startup, (init_rhai,));
+        let sim = Sim::default();
+        app.insert_resource(sim.rng.clone());
+        app.insert_resource(sim);
+        app.insert_resource(SimSettings::default());
+        app.insert_resource(SimSpeed::default());
+        app.add_event::<ScriptEvent>();
+        app.register_diagnostic(Diagnostic::new(TICK_DURATION).with_suffix("ms"));
+        app.add_systems(Startup, (load_sim_scripts, setup_speed_widget));
+        app.add_systems(OnEnter(GameState::InGame), seed_sim_rng);
+        app.add_systems(OnExit(GameState::InGame), restart_sim);
+        // The sim runs at a fixed rate, independently of the frame rate
+        app.add_systems(FixedUpdate, run_scripts_with_world.run_if(sim_running));
         app.add_systems(
             Update,
             (
-                run_rhai,
+                change_sim_speed,
+                apply_tick_rate.after(change_sim_speed),
+                update_speed_widget.after(change_sim_speed),
+                reset_sim,
+                reload_scripts,
+                collect_sim_scripts,
+                queue_building_hooks.run_if(in_state(GameState::InGame)),
+                sync_storages,
+                log_script_events,
+                quicksave_sim,
                 toggle_sim_screen,
-                make_sim_ui.after(run_rhai),
-                get_values.after(run_rhai),
+                make_sim_ui.after(get_values),
+                select_graphed_stat,
+                get_values,
                 update_ui.after(make_sim_ui).after(get_values),
+                measure_tick_duration,
             ),
         );
     }
 }
 
-fn init_rhai(mut sim: ResMut<Sim>, asset_server: Res<AssetServer>) {
-    sim.init = asset_server.load("scripts/init.rhai");
-    sim.run = asset_server.load("scripts/run.rhai");
+/// Leaving the game drops the sim state
+fn restart_sim(mut sim: ResMut<Sim>) {
+    sim.restart();
+}
+
+fn measure_tick_duration(mut diagnostics: Diagnostics, sim: Res<Sim>, mut last_tick: Local<u64>) {
+    if sim.tick == *last_tick {
+        return;
+    }
+    *last_tick = sim.tick;
+    diagnostics.add_measurement(&TICK_DURATION, || {
+        sim.last_tick_duration().as_secs_f64() * 1000.
+    });
+}
+
+fn apply_tick_rate(
+    settings: Res<SimSettings>,
+    speed: Res<SimSpeed>,
+    mut time: ResMut<Time<Fixed>>,
+) {
+    // a paused sim doesn't run at all, so keep the previous timestep
+    if (settings.is_changed() || speed.is_changed()) && *speed != SimSpeed::Paused {
+        time.set_timestep_hz(settings.tick_rate * speed.multiplier());
+    }
+}
+
+/// Space pauses the sim, + and - change its speed
+fn change_sim_speed(mut speed: ResMut<SimSpeed>, mut previous: Local<SimSpeed>, actions: Actions) {
+    if actions.just_pressed(Action::PauseSim) {
+        if *speed == SimSpeed::Paused {
+            *speed = *previous;
+        } else {
+            *previous = *speed;
+            *speed = SimSpeed::Paused;
+        }
+    }
+    if actions.just_pressed(Action::SpeedUp) {
+        *speed = speed.faster();
+    }
+    if actions.just_pressed(Action::SlowDown) {
+        *speed = speed.slower();
+    }
+}
+
+#[derive(Component)]
+struct SpeedWidget;
+
+fn setup_speed_widget(mut commands: Commands, font: Res<FontHandle>) {
+    commands.spawn((
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.),
+            top: Val::Px(10.),
+            ..default()
+        },
+        Text::default(),
+        TextFont {
+            font: font.0.clone(),
+            ..default()
+        },
+        Label,
+        SpeedWidget,
+    ));
+}
+
+fn update_speed_widget(speed: Res<SimSpeed>, mut widget: Single<&mut Text, With<SpeedWidget>>) {
+    if speed.is_changed() {
+        widget.0 = match *speed {
+            SimSpeed::Paused => "Paused".to_string(),
+            speed => format!("Speed x{}", speed.multiplier()),
+        };
+    }
+}
+
+/// Rerun the `on_init` hooks on the next tick when pressing R
+fn reset_sim(mut sim: ResMut<Sim>, actions: Actions) {
+    if actions.just_pressed(Action::ResetSim) {
+        sim.initialized = false;
+    }
+}
+
+/// Recompile scripts when their file changes, keeping the sim data.
+/// If the new version doesn't compile, the previous one keeps running.
+/// Compile the scripts when they are loaded or modified. The scripts are only read, so that
+/// compiling them doesn't mark them as modified again.
+fn reload_scripts(
+    mut events: EventReader<AssetEvent<RhaiScript>>,
+    mut sim: ResMut<Sim>,
+    scripts: Res<Assets<RhaiScript>>,
+    mut errors: ResMut<ScriptErrors>,
+    asset_server: Res<AssetServer>,
+) {
+    for event in events.read() {
+        let (AssetEvent::Added { id } | AssetEvent::Modified { id }) = event else {
+            continue;
+        };
+        let Some(sc) = scripts.get(*id) else {
+            continue;
+        };
+        match sim.engine.compile(&sc.text) {
+            Ok(ast) => {
+                if matches!(event, AssetEvent::Modified { .. }) {
+                    info!("Reloaded script {:?}", id);
+                }
+                sim.asts.insert(*id, ast);
+            }
+            Err(e) => {
+                let name = asset_server
+                    .get_path(*id)
+                    .map(|p| p.to_string())
+                    .unwrap_or_else(|| format!("{:?}", id));
+                errors.push(ScriptError::new(name, &sc.text, &e.into()));
+            }
+        }
+    }
+}
+
+fn log_script_events(mut events: EventReader<ScriptEvent>) {
+    for event in events.read() {
+        debug!("Script event {} : {:?}", event.name, event.payload);
+    }
+}
+
+fn load_sim_scripts(mut sim: ResMut<Sim>, asset_server: Res<AssetServer>) {
+    sim.folder = asset_server.load_folder("scripts");
+}
+
+/// Update the list of sim scripts when the scripts folder is loaded or a script is added to it
+fn collect_sim_scripts(
+    mut events: EventReader<AssetEvent<LoadedFolder>>,
+    folders: Res<Assets<LoadedFolder>>,
+    mut sim: ResMut<Sim>,
+) {
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event
+        else {
+            continue;
+        };
+        if *id != sim.folder.id() {
+            continue;
+        }
+        let Some(folder) = folders.get(*id) else {
+            continue;
+        };
+        let mut scripts: Vec<Handle<RhaiScript>> = folder
+            .handles
+            .iter()
+            .filter(|handle| {
+                // building scripts are run by their building
+                handle
+                    .path()
+                    .is_none_or(|path| !path.path().starts_with("scripts/buildings"))
+            })
+            .filter_map(|handle| handle.clone().try_typed().ok())
+            .collect();
+        scripts.sort_by_key(script_name);
+        info!("Loaded {} sim scripts", scripts.len());
+        sim.scripts = scripts;
+    }
 }
 
-fn run_rhai(
+/// Queue the `on_building_placed(building)` and `on_building_removed(building)` hooks
+fn queue_building_hooks(
     mut sim: ResMut<Sim>,
-    input: Res<ButtonInput<KeyCode>>,
-    mut scripts: ResMut<Assets<RhaiScript>>,
+    mut placed: EventReader<BuildingPlaced>,
+    mut removed: EventReader<BuildingRemoved>,
+    instances: Query<&BuildingInstance>,
+    buildings: Res<Assets<Building>>,
+) {
+    for BuildingPlaced {
+        entity,
+        id,
+        restored,
+    } in placed.read()
+    {
+        // the sim state of restored buildings comes with the save
+        if *restored {
+            continue;
+        }
+        let Ok(instance) = instances.get(*entity) else {
+            continue;
+        };
+        let center = instance.center();
+        let mut building = rhai::Map::new();
+        let name = buildings
+            .get(&instance.building)
+            .map(|b| b.name.clone())
+            .unwrap_or_default();
+        building.insert("name".into(), name.into());
+        building.insert("id".into(), (id.0 as i64).into());
+        building.insert("x".into(), (center.x as f64).into());
+        building.insert("z".into(), (center.y as f64).into());
+        sim.pending_hooks
+            .push(("on_building_placed", Dynamic::from_map(building)));
+    }
+    for BuildingRemoved { id, .. } in removed.read() {
+        // the building is already gone, only its id is known
+        let mut building = rhai::Map::new();
+        building.insert("id".into(), (id.0 as i64).into());
+        sim.pending_hooks
+            .push(("on_building_removed", Dynamic::from_map(building)));
+    }
+}
+
+/// Compile the sim scripts that need it. Returns `None` if a script isn't loaded yet
+/// or has errors, in which case the sim is halted until it's fixed.
+fn sim_modules(
+    sim: &Sim,
+    scripts: &Assets<RhaiScript>,
+    errors: &mut ScriptErrors,
+) -> Option<Vec<SimModule>> {
+    let mut modules = Vec::with_capacity(sim.scripts.len());
+    for handle in &sim.scripts {
+        let name = script_name(handle);
+        if errors.has_error(&name) {
+            return None;
+        }
+        // read only, as changing the scripts would compile them again
+        let sc = scripts.get(handle)?;
+        match sim.compiled(handle, sc) {
+            Ok(ast) => modules.push(SimModule {
+                name,
+                text: sc.text.clone(),
+                ast,
+            }),
+            Err(e) => {
+                errors.push(e);
+                return None;
+            }
+        }
+    }
+    Some(modules)
+}
+
+/// Run the lifecycle hooks of the sim scripts. Each script is an independent module,
+/// sharing only the sim data, bound to `this` in the hooks :
+/// - `on_init()` when the sim starts or restarts
+/// - `on_building_placed(building)` and `on_building_removed(building)` at the start of the next tick
+/// - `on_tick()` every tick
+pub(crate) fn run_rhai(
+    mut sim: ResMut<Sim>,
+    scripts: Res<Assets<RhaiScript>>,
+    settings: Res<SimSettings>,
+    mut errors: ResMut<ScriptErrors>,
+    mut stats: ResMut<ScriptStats>,
 ) -> Result {
-    //todo better error handling
+    // collect the tick running in the background, if it's done
+    if let Some(running) = &mut sim.running {
+        let Some(result) = block_on(poll_once(&mut running.task)) else {
+            if !running.reported && running.started.elapsed() > settings.tick_budget {
+                running.reported = true;
+                warn!(
+                    "The sim scripts exceeded their tick budget of {:?}",
+                    settings.tick_budget
+                );
+            }
+            // the next tick starts once this one is done
+            return Ok(());
+        };
+        let running = sim.running.take().unwrap();
+        sim.apply_tick(
+            running.generation,
+            result,
+            &settings,
+            &mut errors,
+            &mut stats,
+        );
+    }
+    // the sim is halted until the errors are fixed
+    let Some(modules) = sim_modules(&sim, &scripts, &mut errors) else {
+        return Ok(());
+    };
+    if modules.is_empty() {
+        // wait for the scripts folder to be loaded
+        return Ok(());
+    }
     //Initialize simulation
-    if !sim.initialized || input.just_pressed(KeyCode::KeyR) {
-        info!("Init script");
+    if !sim.initialized {
+        info!("Init scripts");
+        sim.rng.reset();
+        sim.script_world.0.lock().unwrap().ui.clear();
         //reset sim data
-        *sim.scope.get_mut("data").ok_or("critical failure")? = rhai::Map::new().into();
-        if let Some(sc) = scripts.get_mut(&sim.init) {
-            let Sim { engine, scope, .. } = &mut *sim;
-            engine.run_with_scope(scope, &*sc.text)?;
+        let mut data = Dynamic::from_map(rhai::Map::new());
+        for module in modules.iter().filter(|m| m.has_hook("on_init", 0)) {
+            let start = Instant::now();
+            let result = module.call(&sim.engine, "on_init", &mut data, (), None);
+            stats.record(&module.name, start.elapsed());
+            if let Err(e) = result {
+                errors.push(e);
+                return Ok(());
+            }
+        }
+        sim.scope.set_value("data", data);
+        for (resource, amount) in sim.starting_resources.clone() {
+            sim.set_resource(&resource, amount);
         }
         sim.initialized = true;
+        sim.generation += 1;
     }
-    if let Some(sc) = scripts.get_mut(&sim.run) {
-        if sc.ast.is_none() {
-            sc.ast = Some(sim.engine.compile_with_scope(&sim.scope, &sc.text)?);
-        }
+    for (resource, amount) in std::mem::take(&mut sim.granted) {
+        let current = sim.resource_amount(&resource);
+        sim.set_resource(&resource, current + amount);
+    }
+
+    // the tick runs on a copy of the data, swapped in when it completes
+    let data = sim.scope.get("data").cloned().ok_or("sim data missing")?;
+    let hooks = std::mem::take(&mut sim.pending_hooks);
+    let engine = sim.engine.clone();
+    let tick = sim.tick;
+    sim.script_world.0.lock().unwrap().tick = tick;
+    if modules.iter().any(SimModule::uses_map) {
+        // the map is only lent to the scripts while they run on the main thread
+        let result = run_tick(&modules, &engine, hooks, data, None);
+        let generation = sim.generation;
+        sim.apply_tick(generation, result, &settings, &mut errors, &mut stats);
+        return Ok(());
+    }
+    let task = AsyncComputeTaskPool::get()
+        .spawn(async move { run_tick(&modules, &engine, hooks, data, Some(tick)) });
+    sim.running = Some(RunningTick {
+        task,
+        started: Instant::now(),
+        generation: sim.generation,
+        reported: false,
+    });
 
-        if let Some(ast) = &sc.ast {
-            if input.pressed(KeyCode::Enter) {
-                let Sim { engine, scope, .. } = &mut *sim;
+    Ok(())
+}
 
-                engine.run_ast_with_scope(scope, ast)?;
+/// Run the pending hooks then `on_tick` of the sim scripts on the data. `background` is the
+/// tick when it runs in the background.
+fn run_tick(
+    modules: &[SimModule],
+    engine: &Engine,
+    hooks: Vec<(&'static str, Dynamic)>,
+    mut data: Dynamic,
+    background: Option<u64>,
+) -> TickResult {
+    let _span = info_span!("sim_tick").entered();
+    let mut times: Vec<(String, Duration)> = Vec::new();
+    for (hook, building) in hooks {
+        for module in modules.iter().filter(|m| m.has_hook(hook, 1)) {
+            let args = (building.clone(),);
+            if let Err(e) = module.call(engine, hook, &mut data, args, background) {
+                return TickResult {
+                    data: Err(e),
+                    times,
+                };
             }
         }
     }
+    for module in modules.iter().filter(|m| m.has_hook("on_tick", 0)) {
+        let _span = info_span!("sim_script", script = %module.name).entered();
+        let start = Instant::now();
+        let result = module.call(engine, "on_tick", &mut data, (), background);
+        times.push((module.name.clone(), start.elapsed()));
+        if let Err(e) = result {
+            return TickResult {
+                data: Err(e),
+                times,
+            };
+        }
+    }
+    TickResult {
+        data: Ok(data),
+        times,
+    }
+}
+
+/// Key/value storage of a placed building, shared with its script as `ctx.storage`.
+#[derive(Component, Clone, Default, Debug)]
+pub struct BuildingStorage(pub Arc<RwLock<rhai::Map>>);
+
+fn register_storage(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<BuildingStorage>("Storage")
+        .register_indexer_get(|storage: &mut BuildingStorage, key: &str| -> Dynamic {
+            storage
+                .0
+                .read()
+                .unwrap()
+                .get(key)
+                .cloned()
+                .unwrap_or(Dynamic::UNIT)
+        })
+        .register_indexer_set(|storage: &mut BuildingStorage, key: &str, value: Dynamic| {
+            storage.0.write().unwrap().insert(key.into(), value);
+        })
+        .register_fn(
+            "get",
+            |storage: &mut BuildingStorage, key: &str| -> Dynamic {
+                storage
+                    .0
+                    .read()
+                    .unwrap()
+                    .get(key)
+                    .cloned()
+                    .unwrap_or(Dynamic::UNIT)
+            },
+        )
+        .register_fn(
+            "set",
+            |storage: &mut BuildingStorage, key: &str, value: Dynamic| {
+                storage.0.write().unwrap().insert(key.into(), value);
+            },
+        )
+        .register_fn("has", |storage: &mut BuildingStorage, key: &str| -> bool {
+            storage.0.read().unwrap().contains_key(key)
+        })
+        .register_fn(
+            "remove",
+            |storage: &mut BuildingStorage, key: &str| -> Dynamic {
+                storage
+                    .0
+                    .write()
+                    .unwrap()
+                    .remove(key)
+                    .unwrap_or(Dynamic::UNIT)
+            },
+        )
+        .register_fn("keys", |storage: &mut BuildingStorage| -> rhai::Array {
+            storage
+                .0
+                .read()
+                .unwrap()
+                .keys()
+                .map(|k| k.to_string().into())
+                .collect()
+        });
+}
+
+/// Give a storage to every placed building, and keep the sim's view of the storages up to date.
+/// Storages restored from a save are given to the building with the matching game id.
+fn sync_storages(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    buildings: Query<(Entity, &GameId, Option<&BuildingStorage>), With<BuildingInstance>>,
+    mut removed: EventReader<BuildingRemoved>,
+) {
+    let mut script_world = sim.script_world.0.lock().unwrap();
+    for BuildingRemoved { id, .. } in removed.read() {
+        script_world.storages.remove(&id.0);
+    }
+    for (e, id, storage) in &buildings {
+        let shared = script_world
+            .storages
+            .entry(id.0)
+            .or_insert_with(|| storage.cloned().unwrap_or_default());
+        if storage.is_none_or(|s| !Arc::ptr_eq(&s.0, &shared.0)) {
+            commands.entity(e).insert(shared.clone());
+        }
+    }
+}
+
+/// Radius in which other buildings are reported as neighbors to building scripts.
+const NEIGHBOR_RADIUS: f32 = 20.;
+
+/// Call the `update(ctx)` function of every placed building that has a script, once per sim tick.
+pub(crate) fn run_building_scripts(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    mut last_tick: Local<u64>,
+    buildings: Res<Assets<Building>>,
+    scripts: Res<Assets<RhaiScript>>,
+    instances: Query<
+        (
+            Entity,
+            &BuildingInstance,
+            Option<&BuildingStorage>,
+            Option<&GameId>,
+        ),
+        Without<Disabled>,
+    >,
+    mut errors: ResMut<ScriptErrors>,
+    mut stats: ResMut<ScriptStats>,
+) -> Result {
+    if sim.tick == *last_tick {
+        return Ok(());
+    }
+    *last_tick = sim.tick;
 
+    for (e, instance, storage, id) in &instances {
+        let Some(building) = buildings.get(&instance.building) else {
+            continue;
+        };
+        let Some(handle) = &building.script else {
+            continue;
+        };
+        let name = script_name(handle);
+        if errors.has_error(&name) {
+            continue;
+        }
+        let Some(sc) = scripts.get(handle) else {
+            continue;
+        };
+        let ast = match sim.compiled(handle, sc) {
+            Ok(ast) => ast,
+            Err(e) => {
+                errors.push(e);
+                continue;
+            }
+        };
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "update" && f.params.len() == 1)
+        {
+            continue;
+        }
+
+        let storage = match storage {
+            Some(storage) => storage.clone(),
+            None => {
+                let storage = BuildingStorage::default();
+                commands.entity(e).insert(storage.clone());
+                storage
+            }
+        };
+
+        let center = instance.center();
+        // the map is lent to the script engine during the tick
+        let neighbors: rhai::Array = {
+            let script_world = sim.script_world.0.lock().unwrap();
+            let Some(map) = &script_world.map else {
+                continue;
+            };
+            map.buildings_in_radius(center, NEIGHBOR_RADIUS)
+                .filter(|other| other.entity != e)
+                .map(|other| {
+                    let mut neighbor = rhai::Map::new();
+                    let name = buildings
+                        .get(&other.building)
+                        .map(|b| b.name.clone())
+                        .unwrap_or_default();
+                    neighbor.insert("name".into(), name.into());
+                    neighbor.insert("x".into(), (other.center().x as f64).into());
+                    neighbor.insert("z".into(), (other.center().y as f64).into());
+                    neighbor.into()
+                })
+                .collect()
+        };
+
+        let mut ctx = rhai::Map::new();
+        ctx.insert("name".into(), building.name.clone().into());
+        ctx.insert("id".into(), id.map_or(-1, |id| id.0 as i64).into());
+        ctx.insert("x".into(), (center.x as f64).into());
+        ctx.insert("z".into(), (center.y as f64).into());
+        ctx.insert("tick".into(), (sim.tick as i64).into());
+        ctx.insert("storage".into(), Dynamic::from(storage));
+        ctx.insert("neighbors".into(), neighbors.into());
+
+        let start = Instant::now();
+        let result = sim.engine.call_fn_with_options::<Dynamic>(
+            CallFnOptions::new().eval_ast(false),
+            &mut Scope::new(),
+            &ast,
+            "update",
+            (ctx,),
+        );
+        stats.record(&name, start.elapsed());
+        if let Err(e) = result {
+            errors.push(ScriptError::new(name, &sc.text, &e));
+        }
+    }
     Ok(())
 }
 
+/// A stat of the sim screen : its id and name
 #[derive(Component)]
-struct Stat(u64, ImmutableString);
+pub(crate) struct Stat(pub(crate) u64, ImmutableString);
 
 fn spawn_on(
     parent: &mut RelatedSpawnerCommands<ChildOf>,
@@ -182,6 +1377,7 @@ fn spawn_on(
                     ..default()
                 },
                 Label,
+                Button,
                 Stat(h.finish(), name.clone().into()),
             ));
         }
@@ -194,15 +1390,17 @@ struct MainNode;
 fn make_sim_ui(
     mut commands: Commands,
     sim: Res<Sim>,
-    asset_server: Res<AssetServer>,
+    font: Res<FontHandle>,
     main_node_query: Option<Single<Entity, With<MainNode>>>,
-    input: Res<ButtonInput<KeyCode>>,
+    mut built_layout: Local<u64>,
 ) {
-    if sim.initialized && (main_node_query.is_none() || input.just_pressed(KeyCode::KeyR)) {
+    // a reset keeps the same stats, only their values change
+    if sim.initialized && (main_node_query.is_none() || *built_layout != sim.layout) {
+        *built_layout = sim.layout;
         if let Some(e) = main_node_query {
             commands.entity(*e).despawn();
         }
-        let font = asset_server.load("fonts/FiraSans-Bold.ttf");
+        let font = font.0.clone();
         let data: &rhai::Map = sim.scope.get_value_ref("data").unwrap();
         commands
             .spawn((
@@ -226,15 +1424,47 @@ fn make_sim_ui(
             .with_children(|parent| {
                 let mut path = vec![];
                 spawn_on(parent, data, &font, &mut path);
+                parent
+                    .spawn((
+                        Node {
+                            width: Val::Percent(90.),
+                            height: Val::Px(150.),
+                            margin: UiRect::all(Val::Px(10.)),
+                            ..default()
+                        },
+                        BackgroundColor(Color::srgb(0.1, 0.1, 0.1)),
+                        StatGraph,
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn((
+                            LocalizedText::new("graph-hint"),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 14.,
+                                ..default()
+                            },
+                            Label,
+                            StatGraphLabel,
+                        ));
+                    });
             });
     }
 }
 
-fn toggle_sim_screen(
-    keyboard: Res<ButtonInput<KeyCode>>,
-    main_node: Query<&mut Visibility, With<MainNode>>,
+/// Graph the clicked stat
+fn select_graphed_stat(
+    stats: Query<(&Interaction, &Stat), Changed<Interaction>>,
+    mut graphed: ResMut<GraphedStat>,
 ) {
-    if keyboard.just_pressed(KeyCode::Tab) {
+    for (interaction, Stat(id, name)) in &stats {
+        if *interaction == Interaction::Pressed {
+            graphed.0 = Some((*id, name.to_string()));
+        }
+    }
+}
+
+fn toggle_sim_screen(actions: Actions, main_node: Query<&mut Visibility, With<MainNode>>) {
+    if actions.just_pressed(Action::ToggleSimScreen) {
         for mut visibility in main_node {
             visibility.toggle_visible_hidden();
         }
@@ -243,31 +1473,92 @@ fn toggle_sim_screen(
 
 fn get_values_rec(
     values: &mut HashMap<u64, f64>,
+    changed: &mut HashSet<u64>,
+    names: &mut HashMap<u64, String>,
+    layout: &mut impl Hasher,
     data: &rhai::Map,
     path: &mut Vec<rhai::ImmutableString>,
 ) {
     for (name, v) in data.iter() {
         path.push(name.into());
-        if let Some(map) = v.clone().try_cast::<rhai::Map>() {
-            get_values_rec(values, &map, path);
-        } else if let Some(f) = v.clone().try_cast::<f64>() {
+        if let Some(map) = v.read_lock::<rhai::Map>() {
+            get_values_rec(values, changed, names, layout, &map, path);
+        } else if let Ok(f) = v.as_float() {
             let mut h = FixedState::default().build_hasher();
             path.hash(&mut h);
-            values.insert(h.finish(), f);
+            let id = h.finish();
+            id.hash(layout);
+            if values.insert(id, f) != Some(f) {
+                changed.insert(id);
+            }
+            names.entry(id).or_insert_with(|| path.join("."));
         }
         path.pop();
     }
 }
 
-fn get_values(mut sim: ResMut<Sim>) {
-    let Sim { scope, values, .. } = &mut *sim;
+/// Number of samples kept in the history of each stat
+pub const HISTORY_LEN: usize = 200;
+
+/// Read the stats from the sim data when it may have changed, and sample their history
+/// once per tick. The sim is only marked as changed for the UI when a stat changed.
+fn get_values(mut sim: ResMut<Sim>, mut last_tick: Local<u64>) {
+    if !sim.is_changed() {
+        return;
+    }
+    let Sim {
+        scope,
+        values,
+        changed_stats,
+        layout,
+        history,
+        sample_ticks,
+        stat_names,
+        tick,
+        ..
+    } = sim.bypass_change_detection();
     let data: &rhai::Map = scope.get_value_ref("data").unwrap();
     let mut path = Vec::new();
-    get_values_rec(values, data, &mut path);
+    let mut layout_hash = FixedState::default().build_hasher();
+    changed_stats.clear();
+    get_values_rec(
+        values,
+        changed_stats,
+        stat_names,
+        &mut layout_hash,
+        data,
+        &mut path,
+    );
+    let layout_hash = layout_hash.finish();
+    let any_changed = *layout != layout_hash || !changed_stats.is_empty();
+    *layout = layout_hash;
+    if *tick != *last_tick {
+        *last_tick = *tick;
+        if sample_ticks.len() == HISTORY_LEN {
+            sample_ticks.pop_front();
+        }
+        sample_ticks.push_back(*tick);
+        for (id, value) in values.iter() {
+            let samples = history.entry(*id).or_default();
+            if samples.len() == HISTORY_LEN {
+                samples.pop_front();
+            }
+            samples.push_back(*value);
+        }
+    }
+    if any_changed {
+        sim.set_changed();
+    }
 }
 
-fn update_ui(sim: Res<Sim>, mut stat_query: Query<(&mut Text, &Stat)>) {
-    for (mut text, Stat(id, name)) in &mut stat_query {
+/// Rewrite the text of the stats that changed, and of the ones just spawned
+fn update_ui(sim: Res<Sim>, mut stat_query: Query<(&mut Text, Ref<Stat>)>) {
+    let changed = sim.is_changed();
+    for (mut text, stat) in &mut stat_query {
+        let Stat(id, name) = &*stat;
+        if !stat.is_added() && !(changed && sim.changed_stats.contains(id)) {
+            continue;
+        }
         text.0 = format!(
             "{} : {:.2}",
             name,
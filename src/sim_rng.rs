@@ -0,0 +1,89 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rhai::Engine;
+
+use crate::map::Map;
+
+/// Seeded random number generator shared by the scripts and the sim systems,
+/// so that replays and multiplayer sessions stay deterministic.
+#[derive(Resource, Clone)]
+pub struct SimRng(Arc<Mutex<RngState>>);
+
+struct RngState {
+    seed: u64,
+    rng: StdRng,
+    /// Number of values drawn since seeding, to restore the state from a save
+    draws: u64,
+}
+
+impl Default for SimRng {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(RngState {
+            seed: 0,
+            rng: StdRng::seed_from_u64(0),
+            draws: 0,
+        })))
+    }
+}
+
+impl SimRng {
+    /// Seed the generator and restart its sequence
+    pub fn set_seed(&self, seed: u64) {
+        let mut state = self.0.lock().unwrap();
+        state.seed = seed;
+        state.rng = StdRng::seed_from_u64(seed);
+        state.draws = 0;
+    }
+
+    /// Restart the sequence from the seed
+    pub fn reset(&self) {
+        let seed = self.0.lock().unwrap().seed;
+        self.set_seed(seed);
+    }
+
+    /// Seed and number of values drawn, enough to restore the state
+    pub fn state(&self) -> (u64, u64) {
+        let state = self.0.lock().unwrap();
+        (state.seed, state.draws)
+    }
+
+    pub fn restore(&self, seed: u64, draws: u64) {
+        self.set_seed(seed);
+        for _ in 0..draws {
+            self.f64();
+        }
+    }
+
+    /// Uniform value in [0, 1)
+    pub fn f64(&self) -> f64 {
+        let mut state = self.0.lock().unwrap();
+        state.draws += 1;
+        state.rng.random()
+    }
+
+    /// Uniform value in [min, max)
+    pub fn range(&self, min: f64, max: f64) -> f64 {
+        min + (max - min) * self.f64()
+    }
+}
+
+/// Register `rand()` and `rand_range(a, b)` on the engine
+pub fn register_rng_api(engine: &mut Engine, rng: &SimRng) {
+    let r = rng.clone();
+    engine.register_fn("rand", move || -> f64 { r.f64() });
+    let r = rng.clone();
+    engine.register_fn("rand_range", move |min: f64, max: f64| -> f64 {
+        r.range(min, max)
+    });
+    let r = rng.clone();
+    engine.register_fn("rand_range", move |min: i64, max: i64| -> i64 {
+        r.range(min as f64, max as f64).floor() as i64
+    });
+}
+
+/// Seed the generator from the world seed
+pub fn seed_sim_rng(rng: Res<SimRng>, map: Res<Map>) {
+    rng.set_seed(map.worldgen.seed as u64);
+}
@@ -0,0 +1,377 @@
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+
+use crate::{
+    CameraTarget,
+    extraction::river_beside,
+    localization::{Localization, LocalizedText},
+    map::{Chunk, GRID_SQUARE_SIZE, Map},
+    mapgen::Continent,
+    menu::GameState,
+    pollution::RIVER_AMOUNT,
+    save_game::{PendingLoad, terrain_color},
+    ui::FontHandle,
+};
+
+/// Spots offered to start at
+const CANDIDATES: usize = 5;
+/// Points of the grid between two spots analyzed
+const CANDIDATE_STEP: u32 = 32;
+/// Half size of the area analyzed around a spot, in points of the grid
+const SITE_RADIUS: i32 = 16;
+/// Points of the grid between two heights sampled around a spot
+const SITE_STEP: i32 = 4;
+/// Difference of heights over a spot from which it counts as not flat at all, in world units
+const MAX_SPREAD: f32 = 6.;
+/// Distance past the spot a river must be within to count, in world units
+const RIVER_REACH: f32 = 6.;
+/// Highest land counted as fertile, normalized above the sea
+const FERTILE_HEIGHT: f32 = 0.15;
+const FLAT_WEIGHT: f32 = 2.;
+const RIVER_WEIGHT: f32 = 1.5;
+const FERTILE_WEIGHT: f32 = 1.;
+const COAST_WEIGHT: f32 = 0.5;
+/// Closest two spots offered can be, in world units
+const MIN_SPACING: f32 = 100.;
+/// Points of the grid covered by a pixel of the map
+const MAP_SCALE: u32 = 8;
+const MAP_DISPLAY_SIZE: f32 = 512.;
+const MARKER_SIZE: f32 = 24.;
+const RIVER_COLOR: Vec3 = Vec3::new(0.25, 0.5, 0.8);
+const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
+const MARKER_COLOR: Color = Color::srgb(0.9, 0.7, 0.2);
+
+/// Where to start a new game. Once the world is generated, the continent is analyzed for the
+/// best spots to start at : flat land by a river, with fertile lowland and the sea around.
+/// The camera starts at the best one, and the others are shown as markers on a map of the
+/// continent, to pick from.
+pub struct StartLocationPlugin;
+
+impl Plugin for StartLocationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(GameState::InGame),
+            (find_start_spots, show_start_map.after(find_start_spots))
+                .run_if(not(resource_exists::<PendingLoad>)),
+        )
+        .add_systems(
+            Update,
+            start_map_buttons.run_if(resource_exists::<StartSpots>),
+        )
+        .add_systems(OnExit(GameState::InGame), clear_start_spots);
+    }
+}
+
+/// A spot to start at
+#[derive(Clone, Debug)]
+pub struct StartSpot {
+    pub pos: Vec3,
+    pub score: f32,
+    /// Between 0 and 1
+    pub flatness: f32,
+    pub river: bool,
+    pub coast: bool,
+}
+
+/// The spots offered to start at, from the best
+#[derive(Resource, Default, Debug)]
+pub struct StartSpots(pub Vec<StartSpot>);
+
+/// Map of the continent with the spots to start at
+#[derive(Component)]
+struct StartMap;
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum StartButton {
+    /// Marker of a spot, by index in the `StartSpots`
+    Spot(usize),
+    Best,
+}
+
+/// Score a spot of the continent, `None` in the sea
+fn analyze(continent: &Continent, (x, y): (u32, u32)) -> Option<StartSpot> {
+    let sea = Continent::OCEAN_HEIGHT_LIMIT;
+    if continent.height(x, y) <= sea {
+        return None;
+    }
+    let last = Continent::CONTINENT_SIZE as i32 - 1;
+    let (mut lowest, mut highest) = (f32::MAX, f32::MIN);
+    let (mut land, mut fertile, mut coast) = (0, 0, false);
+    for i in (-SITE_RADIUS..=SITE_RADIUS).step_by(SITE_STEP as usize) {
+        for j in (-SITE_RADIUS..=SITE_RADIUS).step_by(SITE_STEP as usize) {
+            let (sx, sy) = (
+                (x as i32 + i).clamp(0, last) as u32,
+                (y as i32 + j).clamp(0, last) as u32,
+            );
+            let height = continent.height(sx, sy);
+            if height <= sea {
+                coast = true;
+                continue;
+            }
+            land += 1;
+            if (height - sea) / (1. - sea) < FERTILE_HEIGHT {
+                fertile += 1;
+            }
+            lowest = lowest.min(height);
+            highest = highest.max(height);
+        }
+    }
+    let flatness = (1. - (highest - lowest) * Chunk::SCALE_Y / MAX_SPREAD).max(0.);
+    let center = continent.to_world(Continent::xy2h(x, y));
+    let half_size = Vec2::splat(SITE_RADIUS as f32 * GRID_SQUARE_SIZE);
+    let river = river_beside(
+        continent,
+        (center.xz() - half_size, center.xz() + half_size),
+        RIVER_REACH,
+    )
+    .is_some();
+    let score = FLAT_WEIGHT * flatness
+        + if river { RIVER_WEIGHT } else { 0. }
+        + FERTILE_WEIGHT * fertile as f32 / land.max(1) as f32
+        + if coast { COAST_WEIGHT } else { 0. };
+    Some(StartSpot {
+        pos: center,
+        score,
+        flatness,
+        river,
+        coast,
+    })
+}
+
+/// Best spots of the continent to start at, spaced apart
+fn best_spots(continent: &Continent) -> Vec<StartSpot> {
+    let steps = Continent::CONTINENT_SIZE / CANDIDATE_STEP;
+    let mut analyzed: Vec<_> = (0..steps)
+        .flat_map(|i| (0..steps).map(move |j| (i, j)))
+        .filter_map(|(i, j)| {
+            let offset = CANDIDATE_STEP / 2;
+            analyze(
+                continent,
+                (i * CANDIDATE_STEP + offset, j * CANDIDATE_STEP + offset),
+            )
+        })
+        .collect();
+    analyzed.sort_by(|s1, s2| s2.score.total_cmp(&s1.score));
+    let mut spots: Vec<StartSpot> = Vec::new();
+    for spot in analyzed {
+        if spots
+            .iter()
+            .all(|s| s.pos.xz().distance(spot.pos.xz()) >= MIN_SPACING)
+        {
+            spots.push(spot);
+        }
+        if spots.len() == CANDIDATES {
+            break;
+        }
+    }
+    spots
+}
+
+/// Analyze the continent of a new game, and start the camera at the best spot
+fn find_start_spots(mut commands: Commands, map: Res<Map>, mut camera: Single<&mut CameraTarget>) {
+    let spots = best_spots(&map.continent);
+    info!("Start spots : {:?}", spots);
+    if let Some(best) = spots.first() {
+        camera.pos = best.pos;
+    }
+    commands.insert_resource(StartSpots(spots));
+}
+
+fn clear_start_spots(mut commands: Commands) {
+    commands.remove_resource::<StartSpots>();
+}
+
+/// Top view of the whole continent, with its rivers
+fn continent_image(continent: &Continent) -> Image {
+    let size = Continent::CONTINENT_SIZE / MAP_SCALE;
+    let mut data = Vec::with_capacity((size.pow(2) * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let (x, y) = (x * MAP_SCALE, y * MAP_SCALE);
+            let color = if continent.get_hydro(x, y).amount >= RIVER_AMOUNT {
+                RIVER_COLOR
+            } else {
+                terrain_color(continent.height(x, y))
+            };
+            let [r, g, b] = color
+                .to_array()
+                .map(|c| (c.clamp(0., 1.) * 255.).round() as u8);
+            data.extend([r, g, b, 255]);
+        }
+    }
+    Image::new(
+        Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+/// Show the map of the continent, with a marker on each spot numbered from the best
+fn show_start_map(
+    mut commands: Commands,
+    map: Res<Map>,
+    spots: Res<StartSpots>,
+    mut images: ResMut<Assets<Image>>,
+    font: Res<FontHandle>,
+    localization: Res<Localization>,
+) {
+    if spots.0.is_empty() {
+        return;
+    }
+    let text_font = TextFont {
+        font: font.0.clone(),
+        font_size: 20.,
+        ..default()
+    };
+    let continent = &map.continent;
+    let image = images.add(continent_image(continent));
+    let markers: Vec<_> = spots
+        .0
+        .iter()
+        .enumerate()
+        .map(|(i, spot)| {
+            let (x, y) = continent.from_world(&spot.pos);
+            let percent = |p: u32| Val::Percent(p as f32 * 100. / Continent::CONTINENT_SIZE as f32);
+            (
+                Button,
+                Node {
+                    position_type: PositionType::Absolute,
+                    left: percent(x),
+                    top: percent(y),
+                    width: Val::Px(MARKER_SIZE),
+                    height: Val::Px(MARKER_SIZE),
+                    margin: UiRect::all(Val::Px(-MARKER_SIZE / 2.)),
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                BorderRadius::MAX,
+                BackgroundColor(MARKER_COLOR),
+                StartButton::Spot(i),
+                children![(
+                    Text::new((i + 1).to_string()),
+                    TextFont {
+                        font_size: 16.,
+                        ..text_font.clone()
+                    },
+                    TextColor(Color::BLACK),
+                    Label,
+                    Pickable::IGNORE,
+                )],
+            )
+        })
+        .collect();
+    commands
+        .spawn((
+            Name::new("start map"),
+            StartMap,
+            Node {
+                position_type: PositionType::Absolute,
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                row_gap: Val::Px(10.),
+                ..default()
+            },
+            BackgroundColor(Color::BLACK.with_alpha(0.7)),
+            GlobalZIndex(20),
+            StateScoped(GameState::InGame),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                LocalizedText::new("start-title"),
+                TextFont {
+                    font_size: 40.,
+                    ..text_font.clone()
+                },
+                Label,
+            ));
+            parent.spawn((LocalizedText::new("start-hint"), text_font.clone(), Label));
+            parent
+                .spawn((
+                    Node {
+                        width: Val::Px(MAP_DISPLAY_SIZE),
+                        height: Val::Px(MAP_DISPLAY_SIZE),
+                        ..default()
+                    },
+                    ImageNode::new(image),
+                ))
+                .with_children(|map| {
+                    for marker in markers {
+                        map.spawn(marker);
+                    }
+                });
+            for (i, spot) in spots.0.iter().enumerate() {
+                let water = match (spot.river, spot.coast) {
+                    (true, true) => "start-water-both",
+                    (true, false) => "start-water-river",
+                    (false, true) => "start-water-coast",
+                    (false, false) => "start-water-none",
+                };
+                parent.spawn((
+                    LocalizedText::new("start-spot")
+                        .with_arg("rank", i + 1)
+                        .with_arg("flatness", (spot.flatness * 100.).round())
+                        .with_arg("water", localization.get(water)),
+                    TextFont {
+                        font_size: 16.,
+                        ..text_font.clone()
+                    },
+                    Label,
+                ));
+            }
+            parent.spawn((
+                Button,
+                Node {
+                    padding: UiRect::all(Val::Px(8.)),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                BackgroundColor(NORMAL_BUTTON),
+                StartButton::Best,
+                children![(
+                    LocalizedText::new("start-best"),
+                    text_font.clone(),
+                    Label,
+                    Pickable::IGNORE
+                )],
+            ));
+        });
+}
+
+/// Start at the spot picked, and close the map
+fn start_map_buttons(
+    mut commands: Commands,
+    buttons: Query<(&Interaction, &StartButton), Changed<Interaction>>,
+    start_map: Query<Entity, With<StartMap>>,
+    spots: Res<StartSpots>,
+    mut camera: Single<&mut CameraTarget>,
+) {
+    let Some((_, button)) = buttons
+        .iter()
+        .find(|(interaction, _)| **interaction == Interaction::Pressed)
+    else {
+        return;
+    };
+    let spot = match *button {
+        StartButton::Spot(i) => spots.0.get(i),
+        StartButton::Best => spots.0.first(),
+    };
+    if let Some(spot) = spot {
+        camera.pos = spot.pos;
+    }
+    for e in &start_map {
+        commands.entity(e).despawn();
+    }
+}
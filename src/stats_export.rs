@@ -0,0 +1,104 @@
+use std::{fmt::Write, path::Path};
+
+use bevy::prelude::*;
+
+use crate::{
+    console::{Console, ConsoleCommand},
+    input_map::{Action, Actions},
+    localization::Localization,
+    sim::Sim,
+};
+
+const DEFAULT_EXPORT_PATH: &str = "exports/stats.csv";
+
+/// Export the history of the sim stats to CSV, on pressing F8 or with `/export_stats [path]`
+pub struct StatsExportPlugin;
+
+impl Plugin for StatsExportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (export_on_key, export_on_command));
+    }
+}
+
+/// Quote a CSV field if needed
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// The stat history as CSV : a row per sampled tick, a column per stat.
+/// Stats that appeared after the first sampled tick have empty cells before.
+pub fn stats_csv(sim: &Sim) -> String {
+    let mut stats: Vec<_> = sim.stat_names().collect();
+    stats.sort_by_key(|(_, name)| *name);
+    let mut csv = String::from("tick");
+    for (_, name) in &stats {
+        csv.push(',');
+        csv.push_str(&csv_field(name));
+    }
+    csv.push('\n');
+    let ticks = sim.sample_ticks();
+    for (row, tick) in ticks.iter().enumerate() {
+        write!(csv, "{tick}").unwrap();
+        for (id, _) in &stats {
+            csv.push(',');
+            let Some(samples) = sim.history(*id) else {
+                continue;
+            };
+            // the histories end on the last sampled tick
+            let Some(i) = (row + samples.len()).checked_sub(ticks.len()) else {
+                continue;
+            };
+            if let Some(value) = samples.get(i) {
+                write!(csv, "{value}").unwrap();
+            }
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+pub fn export_stats(sim: &Sim, path: &str) -> anyhow::Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, stats_csv(sim))?;
+    Ok(())
+}
+
+fn export_on_key(actions: Actions, sim: Res<Sim>) -> Result {
+    if !actions.just_pressed(Action::ExportStats) {
+        return Ok(());
+    }
+    export_stats(&sim, DEFAULT_EXPORT_PATH)?;
+    info!("Stats exported to {}", DEFAULT_EXPORT_PATH);
+    Ok(())
+}
+
+fn export_on_command(
+    mut commands: EventReader<ConsoleCommand>,
+    mut console: ResMut<Console>,
+    sim: Res<Sim>,
+    localization: Res<Localization>,
+) {
+    for command in commands.read() {
+        if command.name != "export_stats" {
+            continue;
+        }
+        let path = command
+            .args
+            .first()
+            .map_or(DEFAULT_EXPORT_PATH, |p| p.as_str());
+        match export_stats(&sim, path) {
+            Ok(()) => console.print(
+                localization.get_args("console-stats-exported", &[("path", path.to_string())]),
+            ),
+            Err(e) => console.print_error(
+                localization.get_args("console-export-failed", &[("error", e.to_string())]),
+            ),
+        }
+    }
+}
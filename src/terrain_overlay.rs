@@ -0,0 +1,98 @@
+use bevy::prelude::*;
+
+use crate::{
+    build::{SelectedBuild, Snapping},
+    heatmap::Heatmaps,
+    input_map::{Action, Actions},
+    map::{GRID_SQUARE_SIZE, Map},
+    menu::GameState,
+    pause_menu::Settings,
+    shaders::MapMaterial,
+};
+
+/// Height between two contour lines, in world units
+const CONTOUR_SPACING: f32 = 1.;
+/// Opacity of the heatmap over the terrain colors
+const HEATMAP_OPACITY: f32 = 0.6;
+
+/// Grid and height contour lines drawn by the terrain shader. The grid shows up while
+/// placing a building, with brighter lines at the snapping multiples, or always once
+/// toggled with G. C toggles the contours.
+pub struct TerrainOverlayPlugin;
+
+impl Plugin for TerrainOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TerrainOverlay::default()).add_systems(
+            Update,
+            (toggle_overlay, update_overlay.after(toggle_overlay))
+                .run_if(in_state(GameState::InGame)),
+        );
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct TerrainOverlay {
+    /// Show the grid even when no building is being placed
+    pub grid: bool,
+    pub contours: bool,
+}
+
+fn toggle_overlay(actions: Actions, mut overlay: ResMut<TerrainOverlay>) {
+    if actions.just_pressed(Action::ToggleGrid) {
+        overlay.grid = !overlay.grid;
+    }
+    if actions.just_pressed(Action::ToggleContours) {
+        overlay.contours = !overlay.contours;
+    }
+}
+
+/// Give the overlay and the palette of the heatmap to the terrain material. Also done after
+/// the material is reloaded.
+fn update_overlay(
+    overlay: Res<TerrainOverlay>,
+    snapping: Res<Snapping>,
+    selected: Query<(), With<SelectedBuild>>,
+    heatmaps: Res<Heatmaps>,
+    map: Res<Map>,
+    mut materials: ResMut<Assets<MapMaterial>>,
+    settings: Res<Settings>,
+) {
+    let grid = overlay.grid || !selected.is_empty();
+    let major = match *snapping {
+        Snapping::None | Snapping::One => 1.,
+        Snapping::Two => 2.,
+        Snapping::Four => 4.,
+    };
+    let wanted = Vec4::new(
+        if grid { GRID_SQUARE_SIZE } else { 0. },
+        major,
+        if overlay.contours {
+            CONTOUR_SPACING
+        } else {
+            0.
+        },
+        if heatmaps.shown.is_some() {
+            HEATMAP_OPACITY
+        } else {
+            0.
+        },
+    );
+    let palette = Vec4::new(
+        settings.palette.heat_ramp(),
+        if settings.hatch_patterns { 1. } else { 0. },
+        0.,
+        0.,
+    );
+    let id = map.material().id();
+    // only touch the material when needed, as it is sent again to the gpu
+    if materials
+        .get(id)
+        .is_none_or(|mat| mat.extension.overlay == wanted && mat.extension.palette == palette)
+    {
+        return;
+    }
+    if let Some(mat) = materials.get_mut(id) {
+        mat.extension.overlay = wanted;
+        mat.extension.palette = palette;
+    }
+}
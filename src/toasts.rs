@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+
+use bevy::{color::palettes::css, prelude::*};
+
+use crate::ui::FontHandle;
+
+/// Time a toast stays on screen, in seconds
+const TOAST_DURATION: f32 = 4.;
+/// Time the toast takes to fade out at the end of its duration, in seconds
+const FADE_DURATION: f32 = 1.;
+/// Toasts shown at once. The others wait in the queue.
+const MAX_SHOWN: usize = 5;
+
+/// Short notifications stacked in the corner of the screen, fading out after a few seconds.
+/// Push them to the `Toasts` resource.
+pub struct ToastPlugin;
+
+impl Plugin for ToastPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Toasts::default())
+            .add_systems(Startup, setup_toasts)
+            .add_systems(Update, (show_toasts, fade_toasts.after(show_toasts)));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ToastKind {
+    Info,
+    Success,
+    Warning,
+    Error,
+    Achievement,
+}
+
+impl ToastKind {
+    fn color(self) -> Color {
+        match self {
+            ToastKind::Info => css::DARK_SLATE_GRAY.into(),
+            ToastKind::Success => css::DARK_GREEN.into(),
+            ToastKind::Warning => css::DARK_GOLDENROD.into(),
+            ToastKind::Error => css::DARK_RED.into(),
+            ToastKind::Achievement => css::INDIGO.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Toast {
+    kind: ToastKind,
+    text: String,
+    /// Times the toast was pushed while it waited in the queue
+    count: u32,
+}
+
+/// Toasts waiting to be shown
+#[derive(Resource, Default)]
+pub struct Toasts {
+    queue: VecDeque<Toast>,
+}
+
+impl Toasts {
+    /// Queue a toast. A toast identical to one already waiting is merged with it.
+    pub fn push(&mut self, kind: ToastKind, text: impl Into<String>) {
+        let text = text.into();
+        if let Some(queued) = self
+            .queue
+            .iter_mut()
+            .find(|t| t.kind == kind && t.text == text)
+        {
+            queued.count += 1;
+            return;
+        }
+        self.queue.push_back(Toast {
+            kind,
+            text,
+            count: 1,
+        });
+    }
+
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(ToastKind::Info, text);
+    }
+
+    pub fn success(&mut self, text: impl Into<String>) {
+        self.push(ToastKind::Success, text);
+    }
+
+    pub fn warning(&mut self, text: impl Into<String>) {
+        self.push(ToastKind::Warning, text);
+    }
+
+    pub fn error(&mut self, text: impl Into<String>) {
+        self.push(ToastKind::Error, text);
+    }
+
+    pub fn achievement(&mut self, text: impl Into<String>) {
+        self.push(ToastKind::Achievement, text);
+    }
+}
+
+#[derive(Component)]
+struct ToastList;
+
+/// A toast on screen. Repeated toasts are merged, counting the repetitions.
+#[derive(Component)]
+struct ToastNode {
+    kind: ToastKind,
+    text: String,
+    count: u32,
+    age: f32,
+}
+
+impl ToastNode {
+    fn label(&self) -> String {
+        if self.count > 1 {
+            format!("{} (x{})", self.text, self.count)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+fn setup_toasts(mut commands: Commands) {
+    commands.spawn((
+        Name::new("toasts"),
+        Node {
+            position_type: PositionType::Absolute,
+            right: Val::Px(10.),
+            bottom: Val::Px(80.),
+            max_width: Val::Px(320.),
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::End,
+            row_gap: Val::Px(4.),
+            ..default()
+        },
+        GlobalZIndex(15),
+        Pickable::IGNORE,
+        ToastList,
+    ));
+}
+
+/// Spawn the queued toasts, or bump the count of the identical toast already shown
+fn show_toasts(
+    mut commands: Commands,
+    mut toasts: ResMut<Toasts>,
+    list: Single<Entity, With<ToastList>>,
+    mut shown: Query<(&mut ToastNode, &Children)>,
+    mut texts: Query<&mut Text>,
+    font: Res<FontHandle>,
+) {
+    let mut count = shown.iter().count();
+    while let Some(toast) = toasts.queue.front() {
+        if let Some((mut node, children)) = shown
+            .iter_mut()
+            .find(|(node, _)| node.kind == toast.kind && node.text == toast.text)
+        {
+            node.count += toast.count;
+            node.age = 0.;
+            for child in children {
+                if let Ok(mut text) = texts.get_mut(*child) {
+                    text.0 = node.label();
+                }
+            }
+            toasts.queue.pop_front();
+            continue;
+        }
+        if count >= MAX_SHOWN {
+            break;
+        }
+        let Some(toast) = toasts.queue.pop_front() else {
+            break;
+        };
+        count += 1;
+        let node = ToastNode {
+            kind: toast.kind,
+            text: toast.text,
+            count: toast.count,
+            age: 0.,
+        };
+        let toast_entity = commands
+            .spawn((
+                Node {
+                    padding: UiRect::all(Val::Px(8.)),
+                    ..default()
+                },
+                BackgroundColor(toast.kind.color().with_alpha(0.9)),
+                Pickable::IGNORE,
+                children![(
+                    Text(node.label()),
+                    TextFont {
+                        font: font.0.clone(),
+                        font_size: 14.,
+                        ..default()
+                    },
+                    Label,
+                    Pickable::IGNORE,
+                )],
+                node,
+            ))
+            .id();
+        commands.entity(*list).add_child(toast_entity);
+    }
+}
+
+/// Age the toasts, fading them out at the end of their life
+fn fade_toasts(
+    mut commands: Commands,
+    time: Res<Time<Real>>,
+    mut toasts: Query<(Entity, &mut ToastNode, &mut BackgroundColor, &Children)>,
+    mut text_colors: Query<&mut TextColor>,
+) {
+    for (e, mut node, mut background, children) in &mut toasts {
+        node.age += time.delta_secs();
+        if node.age >= TOAST_DURATION {
+            commands.entity(e).despawn();
+            continue;
+        }
+        let alpha = ((TOAST_DURATION - node.age) / FADE_DURATION).min(1.);
+        background.0.set_alpha(0.9 * alpha);
+        for child in children {
+            if let Ok(mut color) = text_colors.get_mut(*child) {
+                color.0.set_alpha(alpha);
+            }
+        }
+    }
+}
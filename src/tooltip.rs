@@ -0,0 +1,131 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    build::{Building, BuildingType},
+    localization::Localization,
+    ui::{FontHandle, PartButton},
+};
+
+/// Distance between the cursor and the tooltip
+const CURSOR_OFFSET: f32 = 16.;
+
+/// Tooltip describing the build menu entry under the cursor
+pub struct TooltipPlugin;
+
+impl Plugin for TooltipPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_tooltip).add_systems(
+            Update,
+            (update_tooltip, place_tooltip.after(update_tooltip)),
+        );
+    }
+}
+
+#[derive(Component)]
+struct Tooltip;
+
+fn setup_tooltip(mut commands: Commands, font: Res<FontHandle>) {
+    commands.spawn((
+        Name::new("tooltip"),
+        Node {
+            position_type: PositionType::Absolute,
+            max_width: Val::Px(300.),
+            padding: UiRect::all(Val::Px(6.)),
+            ..default()
+        },
+        BackgroundColor(bevy::color::palettes::css::BLACK.with_alpha(0.85).into()),
+        Text::default(),
+        TextFont {
+            font: font.0.clone(),
+            font_size: 14.,
+            ..default()
+        },
+        Label,
+        GlobalZIndex(20),
+        Pickable::IGNORE,
+        Visibility::Hidden,
+        Tooltip,
+    ));
+}
+
+pub(crate) fn describe(building: &Building, localization: &Localization) -> String {
+    let typ = localization.get(match building.typ {
+        BuildingType::Zone { .. } => "building-type-zone",
+        BuildingType::Single { .. } => "building-type-single",
+        BuildingType::Tool { .. } => "building-type-tool",
+        BuildingType::Sign { .. } => "building-type-sign",
+    });
+    let cost = if building.cost.is_empty() {
+        localization.get("building-cost-free")
+    } else {
+        building
+            .cost
+            .iter()
+            .map(|(resource, amount)| format!("{amount} {resource}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let size = format!("{} x {}", building.size.0, building.size.1);
+    let mut text = format!(
+        "{}\n{typ}, {size}\n{}",
+        building.name,
+        localization.get_args("building-cost", &[("cost", cost)])
+    );
+    if !building.description.is_empty() {
+        text.push('\n');
+        text.push_str(&building.description);
+    }
+    text
+}
+
+/// Fill the tooltip with the hovered building
+fn update_tooltip(
+    buttons: Query<(&Interaction, &PartButton)>,
+    buildings: Res<Assets<Building>>,
+    tooltip: Single<(&mut Text, &mut Visibility), With<Tooltip>>,
+    mut shown: Local<Option<AssetId<Building>>>,
+    localization: Res<Localization>,
+) {
+    let (mut text, mut visibility) = tooltip.into_inner();
+    let hovered = buttons
+        .iter()
+        .find(|(interaction, _)| **interaction != Interaction::None)
+        .map(|(_, button)| button.part_id.0.id());
+    if hovered == *shown && !localization.is_changed() {
+        return;
+    }
+    *shown = hovered;
+    match hovered.and_then(|id| buildings.get(id)) {
+        Some(building) => {
+            text.0 = describe(building, &localization);
+            *visibility = Visibility::Inherited;
+        }
+        None => *visibility = Visibility::Hidden,
+    }
+}
+
+/// Keep the tooltip next to the cursor, flipping it to the other side near the window edges
+fn place_tooltip(
+    window: Single<&Window, With<PrimaryWindow>>,
+    tooltip: Single<(&mut Node, &ComputedNode, &Visibility), With<Tooltip>>,
+) {
+    let (mut node, computed, visibility) = tooltip.into_inner();
+    if *visibility == Visibility::Hidden {
+        return;
+    }
+    let Some(cursor) = window.cursor_position() else {
+        return;
+    };
+    // the layout is in physical pixels, the cursor in logical ones
+    let size = computed.size() * computed.inverse_scale_factor();
+    let mut pos = cursor + CURSOR_OFFSET;
+    if pos.x + size.x > window.width() {
+        pos.x = cursor.x - CURSOR_OFFSET - size.x;
+    }
+    if pos.y + size.y > window.height() {
+        pos.y = cursor.y - CURSOR_OFFSET - size.y;
+    }
+    let pos = pos.max(Vec2::ZERO);
+    node.left = Val::Px(pos.x);
+    node.top = Val::Px(pos.y);
+}
@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+
+use crate::{
+    clock::GameClock,
+    localization::Localization,
+    menu::GameState,
+    sim::{Sim, Stat},
+    ui::FontHandle,
+};
+
+const NORMAL_COLOR: Color = Color::WHITE;
+const WARNING_COLOR: Color = Color::srgb(1., 0.39, 0.28);
+
+/// Bar at the top of the screen with the game clock and the main sim stats, updated every
+/// tick.
+/// Right click a stat in the sim screen to pin it to the bar, or to unpin it.
+pub struct TopBarPlugin;
+
+impl Plugin for TopBarPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(TopBar::default())
+            .add_systems(OnEnter(GameState::InGame), setup_top_bar)
+            .add_systems(
+                Update,
+                (pin_stats, update_top_bar.after(pin_stats)).run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// Dotted paths of the stats shown in the top bar
+#[derive(Resource)]
+pub struct TopBar {
+    pub stats: Vec<String>,
+}
+
+impl Default for TopBar {
+    fn default() -> Self {
+        Self {
+            stats: [
+                "resource.money",
+                "resource.food",
+                "resource.material",
+                "aggregates.population",
+                "stat.science",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Component)]
+struct TopBarNode;
+
+fn setup_top_bar(mut commands: Commands) {
+    commands.spawn((
+        Name::new("top bar"),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(0.),
+            width: Val::Percent(100.),
+            justify_content: JustifyContent::Center,
+            ..default()
+        },
+        Pickable::IGNORE,
+        StateScoped(GameState::InGame),
+        children![(
+            Node {
+                padding: UiRect::axes(Val::Px(12.), Val::Px(4.)),
+                column_gap: Val::Px(20.),
+                ..default()
+            },
+            BackgroundColor(Color::BLACK.with_alpha(0.7)),
+            Pickable::IGNORE,
+            TopBarNode,
+        )],
+    ));
+}
+
+/// Pin or unpin the stat of the sim screen under the cursor on a right click
+fn pin_stats(
+    mouse: Res<ButtonInput<MouseButton>>,
+    stats: Query<(&Interaction, &Stat)>,
+    sim: Res<Sim>,
+    mut top_bar: ResMut<TopBar>,
+) {
+    if !mouse.just_pressed(MouseButton::Right) {
+        return;
+    }
+    for (interaction, stat) in &stats {
+        if *interaction == Interaction::None {
+            continue;
+        }
+        let Some(path) = sim.stat_name(stat.0) else {
+            continue;
+        };
+        if let Some(i) = top_bar.stats.iter().position(|pinned| pinned == path) {
+            top_bar.stats.remove(i);
+        } else {
+            top_bar.stats.push(path.to_string());
+        }
+    }
+}
+
+/// Respawn the clock and the stats of the bar after each tick. A stat turns red when it, or its rate
+/// (the stat with the same name prefixed by `d`), is negative.
+fn update_top_bar(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    top_bar: Res<TopBar>,
+    clock: Res<GameClock>,
+    localization: Res<Localization>,
+    node: Option<Single<Entity, With<TopBarNode>>>,
+    font: Res<FontHandle>,
+    mut last_tick: Local<Option<u64>>,
+) {
+    let Some(node) = node else {
+        return;
+    };
+    if *last_tick == Some(sim.tick) && !top_bar.is_changed() && !localization.is_changed() {
+        return;
+    }
+    *last_tick = Some(sim.tick);
+    let font = TextFont {
+        font: font.0.clone(),
+        font_size: 16.,
+        ..default()
+    };
+    commands
+        .entity(*node)
+        .despawn_related::<Children>()
+        .with_children(|parent| {
+            let time = localization.get_args(
+                "top-bar-clock",
+                &[
+                    ("day", (clock.day() + 1).to_string()),
+                    ("time", clock.time()),
+                ],
+            );
+            parent.spawn((
+                Text(time),
+                font.clone(),
+                TextColor(NORMAL_COLOR),
+                Label,
+                Pickable::IGNORE,
+            ));
+            for path in &top_bar.stats {
+                let Some(value) = sim.value(path) else {
+                    continue;
+                };
+                let rate = path
+                    .rsplit_once('.')
+                    .and_then(|(parent, name)| sim.value(&format!("{parent}.d{name}")));
+                let label = localization
+                    .try_get(&format!("stat-{}", path.replace('.', "-")))
+                    .unwrap_or_else(|| path.clone());
+                let mut text = format!("{label} : {value:.1}");
+                if let Some(rate) = rate {
+                    text.push_str(&format!(" ({rate:+.1})"));
+                }
+                let warning = value < 0. || rate.is_some_and(|rate| rate < 0.);
+                parent.spawn((
+                    Text(text),
+                    font.clone(),
+                    TextColor(if warning { WARNING_COLOR } else { NORMAL_COLOR }),
+                    Label,
+                    Pickable::IGNORE,
+                ));
+            }
+        });
+}
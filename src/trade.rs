@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+use rhai::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::{BuildId, Building, GameId},
+    localization::Localization,
+    map::BuildingInstance,
+    script_api::{SharedScriptWorld, run_scripts_with_world},
+    sim::{Sim, sim_running},
+    toasts::Toasts,
+};
+
+/// Number of sim ticks between two ships at each harbor
+const SHIP_INTERVAL: u64 = 300;
+/// Most of each resource a ship trades
+const SHIP_CAPACITY: f64 = 50.;
+/// Change of the price per unit traded, as a share of the price
+const PRICE_IMPACT: f64 = 0.004;
+/// Share of the gap to the base price closed between two ships
+const PRICE_RECOVERY: f64 = 0.3;
+/// Lowest price, as a share of the base price
+const MIN_PRICE: f64 = 0.2;
+/// Buying costs that much more than selling
+const SPREAD: f64 = 0.2;
+/// Resource the goods are paid with
+const CURRENCY: &str = "money";
+/// Price of the resources when the market is calm, in money per unit
+const BASE_PRICES: &[(&str, f64)] = &[("food", 1.), ("material", 2.)];
+const DEFAULT_BASE_PRICE: f64 = 1.5;
+/// Tag of the buildings ships dock at
+const HARBOR_TAG: &str = "harbor";
+
+/// Trade with the world beyond the map. Every `SHIP_INTERVAL` ticks, a ship docks at each
+/// harbor and follows the standing orders the scripts set with `trade_order(resource,
+/// amount)` : buying the amount, or selling it when negative. Trading moves the prices, which
+/// drift back to their base between the ships. Scripts read them with `trade_price`.
+pub struct TradePlugin;
+
+impl Plugin for TradePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ShipDocked>()
+            .add_systems(
+                FixedUpdate,
+                dock_ships.after(run_scripts_with_world).run_if(sim_running),
+            )
+            .add_systems(Update, toast_trades);
+    }
+}
+
+fn base_price(resource: &str) -> f64 {
+    BASE_PRICES
+        .iter()
+        .find(|(r, _)| *r == resource)
+        .map_or(DEFAULT_BASE_PRICE, |(_, price)| *price)
+}
+
+/// Prices and standing orders, shared with the scripts
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Market {
+    /// Price of the resources traded so far, in money per unit
+    pub prices: BTreeMap<String, f64>,
+    /// Amount to buy from each ship, negative to sell, by resource
+    pub orders: BTreeMap<String, f64>,
+}
+
+impl Market {
+    /// Price the ships pay for a resource. Buying it from them costs `SPREAD` more.
+    pub fn price(&self, resource: &str) -> f64 {
+        self.prices
+            .get(resource)
+            .copied()
+            .unwrap_or_else(|| base_price(resource))
+    }
+
+    fn recover(&mut self) {
+        for (resource, price) in &mut self.prices {
+            *price += (base_price(resource) - *price) * PRICE_RECOVERY;
+        }
+    }
+}
+
+/// Sent when a ship traded at a harbor, with the amount of each resource bought (negative
+/// when sold) and the money paid for it (negative when earned)
+#[derive(Event, Clone, Debug)]
+pub struct ShipDocked {
+    pub harbor: Entity,
+    pub trades: Vec<(String, f64, f64)>,
+}
+
+fn dock_ships(
+    mut sim: ResMut<Sim>,
+    harbors: Query<(Entity, &BuildId, &GameId), With<BuildingInstance>>,
+    buildings: Res<Assets<Building>>,
+    mut docked: EventWriter<ShipDocked>,
+) {
+    let shared = sim.script_world.clone();
+    let mut world = shared.0.lock().unwrap();
+    if world.tick == 0 || world.tick % SHIP_INTERVAL != 0 {
+        return;
+    }
+    let mut harbors: Vec<_> = harbors
+        .iter()
+        .filter(|(_, bid, _)| {
+            buildings
+                .get(&bid.0)
+                .is_some_and(|b| b.tags.iter().any(|tag| tag == HARBOR_TAG))
+        })
+        .collect();
+    // in the order they were built, so that replays trade the same way
+    harbors.sort_by_key(|(_, _, id)| id.0);
+    let market = &mut world.market;
+    market.recover();
+    for (harbor, _, _) in harbors {
+        let mut trades = Vec::new();
+        for (resource, &order) in &market.orders {
+            let price = market.price(resource);
+            let (amount, paid, new_price) = if order > 0. {
+                let unit = price * (1. + SPREAD);
+                let affordable = (sim.resource_amount(CURRENCY) / unit).max(0.);
+                let amount = order.min(SHIP_CAPACITY).min(affordable);
+                (amount, amount * unit, price * (1. + PRICE_IMPACT * amount))
+            } else {
+                let available = sim.resource_amount(resource).max(0.);
+                let amount = (-order).min(SHIP_CAPACITY).min(available);
+                let new_price = price * (1. - PRICE_IMPACT * amount);
+                let floor = base_price(resource) * MIN_PRICE;
+                (-amount, -amount * price, new_price.max(floor))
+            };
+            if amount == 0. {
+                continue;
+            }
+            if amount > 0. {
+                sim.spend_resource(CURRENCY, paid);
+                sim.grant_resource(resource, amount);
+            } else {
+                sim.spend_resource(resource, -amount);
+                sim.grant_resource(CURRENCY, -paid);
+            }
+            trades.push((resource.clone(), amount, paid, new_price));
+        }
+        for (resource, _, _, new_price) in &trades {
+            market.prices.insert(resource.clone(), *new_price);
+        }
+        docked.write(ShipDocked {
+            harbor,
+            trades: trades
+                .into_iter()
+                .map(|(resource, amount, paid, _)| (resource, amount, paid))
+                .collect(),
+        });
+    }
+}
+
+fn toast_trades(
+    mut docked: EventReader<ShipDocked>,
+    mut toasts: ResMut<Toasts>,
+    localization: Res<Localization>,
+) {
+    for ShipDocked { trades, .. } in docked.read() {
+        if trades.is_empty() {
+            continue;
+        }
+        let balance: f64 = -trades.iter().map(|(_, _, paid)| paid).sum::<f64>();
+        toasts.info(localization.get_args(
+            "toast-ship-docked",
+            &[("balance", format!("{balance:+.1}"))],
+        ));
+    }
+}
+
+/// Register `trade_order(resource, amount)`, setting the amount to buy from each ship,
+/// negative to sell, and `trade_price(resource)`
+pub fn register_trade_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("trade_order", move |resource: &str, amount: f64| {
+        let mut world = w.0.lock().unwrap();
+        if amount == 0. {
+            world.market.orders.remove(resource);
+        } else {
+            world.market.orders.insert(resource.to_string(), amount);
+        }
+    });
+
+    let w = world.clone();
+    engine.register_fn("trade_price", move |resource: &str| -> f64 {
+        w.0.lock().unwrap().market.price(resource)
+    });
+}
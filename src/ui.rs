@@ -1,32 +1,65 @@
+use std::{collections::BTreeSet, ops::Range};
+
 use bevy::{
     color::palettes::basic::*,
-    input::mouse::{MouseScrollUnit, MouseWheel},
+    input::{
+        keyboard::{Key, KeyboardInput},
+        mouse::{MouseScrollUnit, MouseWheel},
+    },
+    asset::LoadedFolder,
     picking::hover::HoverMap,
     prelude::*,
 };
 
-use crate::build::{BuildId, Building, setup_parts};
+use crate::build::{BuildId, Building, Buildings, DraggedFromList, SelectedBuild, setup_parts};
+use crate::input_map::{Action, Actions};
+use crate::localization::{Localization, LocalizedText};
+use crate::palettes::{HatchImages, zone_color};
+use crate::pause_menu::Settings;
+use crate::zones::zone_index;
 pub struct UiPlugin;
 
 impl Plugin for UiPlugin {
     fn build(&self, app: &mut App) {
         //setup ui needs the parts list first
         app.add_systems(Startup, setup_ui.after(setup_parts));
-        app.add_systems(Update, (update_scroll_position, button_system, update_building_list));
-        app.insert_resource(FontHandle::default());
+        app.add_systems(
+            Update,
+            (
+                update_scroll_position,
+                button_system,
+                update_building_list,
+                select_tab,
+                focus_search,
+                edit_search.after(focus_search),
+                rebuild_tabs.after(update_building_list),
+                filter_building_list
+                    .after(update_building_list)
+                    .after(select_tab)
+                    .after(edit_search),
+                show_building_rows.after(filter_building_list),
+                gamepad_build_list.after(show_building_rows),
+            ),
+        );
+        app.init_resource::<FontHandle>();
+        app.insert_resource(BuildMenu::default());
+        app.insert_resource(TextFocus::default());
     }
 }
 
 const FONT_SIZE: f32 = 20.;
 const LINE_HEIGHT: f32 = 21.;
+/// Height of a row of the building list
+const ROW_HEIGHT: f32 = 2. * LINE_HEIGHT;
+/// Rows of the building list spawned above and below the ones in view
+const EXTRA_ROWS: usize = 2;
 
 #[derive(Component)]
 pub struct PartButton {
-    part_id: BuildId,
+    pub part_id: BuildId,
 }
 
-fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>, mut font: ResMut<FontHandle>) {
-    font.0 = asset_server.load("fonts/FiraSans-Bold.ttf");
+fn setup_ui(mut commands: Commands, font: Res<FontHandle>, localization: Res<Localization>) {
     // root node
     commands
         .spawn(Node {
@@ -61,15 +94,38 @@ fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>, mut font: Re
                             BackgroundColor(Color::srgb(0.10, 0.10, 0.10)),
                         ))
                         .with_children(|parent| {
-                            // Title
+                            // Category tabs
                             parent.spawn((
-                                Text::new("Vertically Scrolling List"),
-                                TextFont {
-                                    font: font.0.clone(),
-                                    font_size: FONT_SIZE,
+                                Node {
+                                    flex_wrap: FlexWrap::Wrap,
+                                    align_self: AlignSelf::Stretch,
+                                    column_gap: Val::Px(2.),
+                                    row_gap: Val::Px(2.),
                                     ..default()
                                 },
-                                Label,
+                                CategoryTabs,
+                            ));
+                            // Search box
+                            parent.spawn((
+                                Button,
+                                Node {
+                                    align_self: AlignSelf::Stretch,
+                                    padding: UiRect::all(Val::Px(4.)),
+                                    margin: UiRect::vertical(Val::Px(4.)),
+                                    ..default()
+                                },
+                                BackgroundColor(Color::srgb(0.2, 0.2, 0.2)),
+                                SearchBox,
+                                children![(
+                                    Text::new(localization.get("build-search")),
+                                    TextFont {
+                                        font: font.0.clone(),
+                                        font_size: FONT_SIZE * 0.8,
+                                        ..default()
+                                    },
+                                    Label,
+                                    Pickable::IGNORE,
+                                )],
                             ));
                             // Scrolling list
                             parent.spawn((
@@ -80,66 +136,455 @@ fn setup_ui(mut commands: Commands, asset_server: Res<AssetServer>, mut font: Re
                                     overflow: Overflow::scroll_y(), // n.b.
                                     ..default()
                                 },
-                                BuildingList,
+                                BuildingList::default(),
                             ));
                         });
                 });
         });
 }
+/// The scrolling list of the build menu. Only the rows in view are spawned, between two
+/// spacers standing for the others.
+#[derive(Component, Default)]
+pub struct BuildingList {
+    /// Buildings matching the tab and the search, in order
+    listed: Vec<Handle<Building>>,
+    /// Rows spawned, `None` to respawn them
+    shown: Option<Range<usize>>,
+}
+
+/// Index of a row of the building list
 #[derive(Component)]
-pub struct BuildingList;
+struct ListRow(usize);
 
-#[derive(Resource, Default)]
+/// The font of the ui, loaded with the plugin so that every system can use it
+#[derive(Resource)]
 pub struct FontHandle(pub Handle<Font>);
 
+impl FromWorld for FontHandle {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        Self(asset_server.load("fonts/FiraSans-Bold.ttf"))
+    }
+}
+
+/// The entity currently receiving keyboard text input, if any.
+/// Keyboard shortcuts should be ignored while it is set.
+#[derive(Resource, Default)]
+pub struct TextFocus(pub Option<Entity>);
+
+/// State of the build menu : buildings known, and how they're filtered
+#[derive(Resource, Default)]
+pub struct BuildMenu {
+    /// Loaded buildings, sorted by category and name
+    buildings: Vec<Handle<Building>>,
+    /// Selected tab, `None` for all the categories
+    category: Option<String>,
+    search: String,
+    /// Technologies researched, the buildings of the others are locked
+    unlocked: BTreeSet<String>,
+}
+
+impl BuildMenu {
+    /// Whether the building is in the menu, and not removed from the buildings folder
+    pub fn lists(&self, id: AssetId<Building>) -> bool {
+        self.buildings.iter().any(|h| h.id() == id)
+    }
+
+    pub fn unlocked(&self) -> &BTreeSet<String> {
+        &self.unlocked
+    }
+
+    pub fn set_unlocked(&mut self, unlocked: BTreeSet<String>) {
+        self.unlocked = unlocked;
+    }
+
+    /// Whether the technology of the building, if any, is researched
+    pub fn available(&self, building: &Building) -> bool {
+        building
+            .tech
+            .as_ref()
+            .is_none_or(|tech| self.unlocked.contains(tech))
+    }
+
+    fn matches(&self, building: &Building) -> bool {
+        if self
+            .category
+            .as_ref()
+            .is_some_and(|c| *c != building.category)
+        {
+            return false;
+        }
+        let search = self.search.to_lowercase();
+        building.name.to_lowercase().contains(&search)
+            || building
+                .tags
+                .iter()
+                .any(|tag| tag.to_lowercase().contains(&search))
+    }
+}
+
+#[derive(Component)]
+struct CategoryTabs;
+
+/// A tab of the build menu, `None` for all the categories
+#[derive(Component)]
+struct CategoryTab(Option<String>);
+
+#[derive(Component)]
+struct SearchBox;
+
+/// Keep the list of buildings of the build menu up to date.
+/// Files removed or renamed in the buildings folder drop their building from the list,
+/// and edited buildings update their button in place when they keep their place in the list.
 pub fn update_building_list(
-    mut commands: Commands,
     mut events: EventReader<AssetEvent<Building>>,
+    mut folder_events: EventReader<AssetEvent<LoadedFolder>>,
+    folders: Res<Assets<LoadedFolder>>,
+    buildings_folder: Res<Buildings>,
     mut buildings: ResMut<Assets<Building>>,
-    list_query: Single<Entity, With<BuildingList>>,
-    font: Res<FontHandle>,
+    mut menu: ResMut<BuildMenu>,
+    part_buttons: Query<(&PartButton, &Children)>,
+    mut labels: Query<&mut LocalizedText>,
+    list: Single<&BuildingList>,
 ) {
+    let mut changed = false;
+    let mut modified = Vec::new();
     for ev in events.read() {
-        if let AssetEvent::LoadedWithDependencies { id } = ev {
-            commands.entity(*list_query).with_children(|parent| {
-                // List items
-                let building_handle = buildings.get_strong_handle(*id).unwrap();
-                let building = buildings.get(*id).unwrap();
-                parent
-                    .spawn((
-                        Button,
-                        Node {
-                            min_height: Val::Px(2. * LINE_HEIGHT),
-                            max_height: Val::Px(2. * LINE_HEIGHT),
-                            border: UiRect::all(Val::Px(5.0)),
+        match ev {
+            AssetEvent::LoadedWithDependencies { id } => {
+                if !menu.buildings.iter().any(|h| h.id() == *id) {
+                    let handle = buildings.get_strong_handle(*id).unwrap();
+                    menu.buildings.push(handle);
+                }
+                changed = true;
+            }
+            AssetEvent::Modified { id } => modified.push(*id),
+            AssetEvent::Removed { id } => {
+                menu.buildings.retain(|h| h.id() != *id);
+                changed = true;
+            }
+            _ => {}
+        }
+    }
+    // the folder is reloaded when a file is added, removed or renamed. The menu holds the
+    // handles, so the buildings gone from the folder are never removed otherwise.
+    for ev in folder_events.read() {
+        let AssetEvent::Modified { id } = ev else {
+            continue;
+        };
+        if *id != buildings_folder.0.id() {
+            continue;
+        }
+        let Some(folder) = folders.get(*id) else {
+            continue;
+        };
+        let before = menu.buildings.len();
+        menu.buildings.retain(|h| folder.handles.iter().any(|f| f.id() == h.id().untyped()));
+        changed |= menu.buildings.len() != before;
+    }
+    if !changed && modified.is_empty() {
+        return;
+    }
+    let mut list = menu.buildings.clone();
+    list.sort_by_cached_key(|h| {
+        buildings
+            .get(h)
+            .map(|b| (b.category.clone(), b.name.clone()))
+            .unwrap_or_default()
+    });
+    // an edited building that appears or disappears from the filtered list needs a rebuild
+    let listed_changed = modified.iter().any(|id| {
+        let listed = list.listed.iter().any(|h| h.id() == *id);
+        let matches = buildings.get(*id).is_some_and(|b| menu.matches(b));
+        listed != matches
+    });
+    if changed || listed_changed || list != menu.buildings {
+        menu.buildings = list;
+        return;
+    }
+    for (button, children) in &part_buttons {
+        let Some(building) = modified
+            .iter()
+            .find(|id| button.part_id.0.id() == **id)
+            .and_then(|id| buildings.get(*id))
+        else {
+            continue;
+        };
+        for child in children {
+            if let Ok(mut label) = labels.get_mut(*child) {
+                *label = LocalizedText::new("build-list-item").with_arg("name", &building.name);
+            }
+        }
+    }
+}
+
+/// Respawn the category tabs when the categories change
+fn rebuild_tabs(
+    mut commands: Commands,
+    menu: Res<BuildMenu>,
+    buildings: Res<Assets<Building>>,
+    tabs: Single<Entity, With<CategoryTabs>>,
+    font: Res<FontHandle>,
+    mut shown: Local<Vec<String>>,
+) {
+    // edited buildings may change category without changing the list
+    if !menu.is_changed() && !buildings.is_changed() {
+        return;
+    }
+    let mut categories: Vec<String> = menu
+        .buildings
+        .iter()
+        .filter_map(|h| Some(buildings.get(h)?.category.clone()))
+        .collect();
+    categories.dedup();
+    if *shown == categories {
+        return;
+    }
+    *shown = categories;
+    commands.entity(*tabs).despawn_related::<Children>();
+    commands.entity(*tabs).with_children(|parent| {
+        let tabs = std::iter::once(None).chain(shown.iter().cloned().map(Some));
+        for category in tabs {
+            parent.spawn((
+                Button,
+                Node {
+                    padding: UiRect::all(Val::Px(3.)),
+                    ..default()
+                },
+                BackgroundColor(NORMAL_BUTTON),
+                children![(
+                    // categories are their own key, so that the ones without a translation show as is
+                    LocalizedText::new(category.clone().unwrap_or_else(|| "build-category-all".to_string())),
+                    TextFont {
+                        font: font.0.clone(),
+                        font_size: FONT_SIZE * 0.7,
+                        ..default()
+                    },
+                    Label,
+                    Pickable::IGNORE,
+                )],
+                CategoryTab(category),
+            ));
+        }
+    });
+}
+
+/// Select the clicked tab, and highlight the selected one
+fn select_tab(
+    mut menu: ResMut<BuildMenu>,
+    mut tabs: Query<(Ref<Interaction>, &CategoryTab, &mut BackgroundColor)>,
+) {
+    for (interaction, tab, _) in &tabs {
+        if interaction.is_changed() && *interaction == Interaction::Pressed {
+            menu.category = tab.0.clone();
+        }
+    }
+    for (_, tab, mut color) in &mut tabs {
+        color.0 = if tab.0 == menu.category {
+            PRESSED_BUTTON
+        } else {
+            NORMAL_BUTTON
+        };
+    }
+}
+
+/// Type in the search box after clicking it
+fn focus_search(
+    search_box: Single<(Entity, &Interaction), (Changed<Interaction>, With<SearchBox>)>,
+    mut text_focus: ResMut<TextFocus>,
+) {
+    let (entity, interaction) = *search_box;
+    if *interaction == Interaction::Pressed && text_focus.0.is_none() {
+        text_focus.0 = Some(entity);
+    }
+}
+
+/// Edit the search. Enter or Escape stop the edition.
+fn edit_search(
+    mut events: EventReader<KeyboardInput>,
+    mut text_focus: ResMut<TextFocus>,
+    mut menu: ResMut<BuildMenu>,
+    search_box: Single<(Entity, &Children), With<SearchBox>>,
+    mut texts: Query<&mut Text>,
+    localization: Res<Localization>,
+) {
+    let (entity, children) = *search_box;
+    let focused = text_focus.0 == Some(entity);
+    if focused && !text_focus.is_changed() {
+        for ev in events.read() {
+            if !ev.state.is_pressed() {
+                continue;
+            }
+            match &ev.logical_key {
+                Key::Character(c) => menu.search.push_str(c),
+                Key::Space => menu.search.push(' '),
+                Key::Backspace => {
+                    menu.search.pop();
+                }
+                Key::Enter | Key::Escape => {
+                    text_focus.0 = None;
+                    break;
+                }
+                _ => {}
+            }
+        }
+    } else {
+        events.clear();
+    }
+    if !menu.is_changed() && !text_focus.is_changed() && !localization.is_changed() {
+        return;
+    }
+    let focused = text_focus.0 == Some(entity);
+    let text = match (menu.search.is_empty(), focused) {
+        (true, false) => localization.get("build-search"),
+        (_, true) => format!("{}_", menu.search),
+        (false, false) => menu.search.clone(),
+    };
+    for child in children {
+        if let Ok(mut t) = texts.get_mut(*child) {
+            t.0 = text.clone();
+        }
+    }
+}
+
+/// List the buildings matching the tab and the search, and respawn the rows
+fn filter_building_list(
+    menu: Res<BuildMenu>,
+    buildings: Res<Assets<Building>>,
+    settings: Res<Settings>,
+    mut list: Single<&mut BuildingList>,
+) {
+    if !menu.is_changed() && !settings.is_changed() {
+        return;
+    }
+    list.listed = menu
+        .buildings
+        .iter()
+        .filter(|h| buildings.get(*h).is_some_and(|b| menu.matches(b)))
+        .cloned()
+        .collect();
+    list.shown = None;
+}
+
+/// Spawn the rows of the building list in view, when it scrolls or changes.
+/// The locked buildings are listed greyed out, without a button. The zones get a swatch
+/// of their color, and hatch.
+fn show_building_rows(
+    mut commands: Commands,
+    menu: Res<BuildMenu>,
+    buildings: Res<Assets<Building>>,
+    list: Single<(Entity, &mut BuildingList, &ScrollPosition, &ComputedNode)>,
+    font: Res<FontHandle>,
+    settings: Res<Settings>,
+    hatches: Res<HatchImages>,
+) {
+    let (list_entity, mut list, scroll, node) = list.into_inner();
+    let height = node.size().y * node.inverse_scale_factor();
+    let first = ((scroll.offset_y / ROW_HEIGHT) as usize).saturating_sub(EXTRA_ROWS);
+    let last = ((scroll.offset_y + height) / ROW_HEIGHT).ceil() as usize + EXTRA_ROWS;
+    let range = first.min(list.listed.len())..last.min(list.listed.len());
+    if list.shown.as_ref() == Some(&range) {
+        return;
+    }
+    list.shown = Some(range.clone());
+    let spacer = |rows: usize| {
+        (
+            Node {
+                min_height: Val::Px(rows as f32 * ROW_HEIGHT),
+                ..default()
+            },
+            Pickable::IGNORE,
+        )
+    };
+    commands.entity(list_entity).despawn_related::<Children>();
+    commands.entity(list_entity).with_children(|parent| {
+        parent.spawn(spacer(range.start));
+        for i in range.clone() {
+            let building_handle = &list.listed[i];
+            let Some(building) = buildings.get(building_handle) else {
+                parent.spawn(spacer(1));
+                continue;
+            };
+            if !menu.available(building) {
+                parent.spawn((
+                    Node {
+                        min_height: Val::Px(ROW_HEIGHT),
+                        max_height: Val::Px(ROW_HEIGHT),
+                        border: UiRect::all(Val::Px(5.0)),
+                        ..default()
+                    },
+                    ListRow(i),
+                    children![(
+                        LocalizedText::new("build-list-locked")
+                            .with_arg("name", &building.name)
+                            .with_arg("tech", building.tech.clone().unwrap_or_default()),
+                        TextFont {
+                            font: font.0.clone(),
+                            font_size: FONT_SIZE * 0.8,
                             ..default()
                         },
-                        Pickable {
+                        TextColor(Color::srgb(0.5, 0.5, 0.5)),
+                        Label,
+                    )],
+                ));
+                continue;
+            }
+            // List items
+            parent
+                .spawn((
+                    Button,
+                    Node {
+                        min_height: Val::Px(ROW_HEIGHT),
+                        max_height: Val::Px(ROW_HEIGHT),
+                        border: UiRect::all(Val::Px(5.0)),
+                        ..default()
+                    },
+                    Pickable {
+                        should_block_lower: false,
+                        ..default()
+                    },
+                    PartButton {
+                        part_id: BuildId(building_handle.clone()),
+                    },
+                    ListRow(i),
+                ))
+                .with_children(|parent| {
+                    if let Some(color) = zone_color(&buildings, building, settings.palette) {
+                        let mut swatch = parent.spawn((
+                            Node {
+                                width: Val::Px(FONT_SIZE),
+                                height: Val::Px(FONT_SIZE),
+                                margin: UiRect::right(Val::Px(6.)),
+                                align_self: AlignSelf::Center,
+                                ..default()
+                            },
+                            Pickable::IGNORE,
+                        ));
+                        if settings.hatch_patterns {
+                            let i = zone_index(&buildings, &building.name);
+                            let image = ImageNode::new(hatches.tiled(i));
+                            swatch.insert(image.with_color(color.with_alpha(1.)));
+                        } else {
+                            swatch.insert(BackgroundColor(color.with_alpha(1.)));
+                        }
+                    }
+                    parent
+                        .spawn((
+                            LocalizedText::new("build-list-item").with_arg("name", &building.name),
+                            TextFont {
+                                font: font.0.clone(),
+                                ..default()
+                            },
+                            Label,
+                        ))
+                        .insert(Pickable {
                             should_block_lower: false,
                             ..default()
-                        },
-                        PartButton {
-                            part_id: BuildId(building_handle),
-                        },
-                    ))
-                    .with_children(|parent| {
-                        parent
-                            .spawn((
-                                Text(format!("Item {:}", building.name)),
-                                TextFont {
-                                    font: font.0.clone(),
-                                    ..default()
-                                },
-                                Label,
-                            ))
-                            .insert(Pickable {
-                                should_block_lower: false,
-                                ..default()
-                            });
-                    });
-            });
+                        });
+                });
         }
-    }
+        parent.spawn(spacer(list.listed.len() - range.end));
+    });
 }
 
 /// Updates the scroll position of scrollable nodes in response to mouse input
@@ -172,6 +617,64 @@ const NORMAL_BUTTON: Color = Color::srgb(0.15, 0.15, 0.15);
 const HOVERED_BUTTON: Color = Color::srgb(0.25, 0.25, 0.25);
 const PRESSED_BUTTON: Color = Color::srgb(0.35, 0.75, 0.35);
 
+/// Browse the build list with the gamepad. The focused button gets a white border,
+/// and the place action picks it. The locked buildings are skipped.
+fn gamepad_build_list(
+    mut commands: Commands,
+    actions: Actions,
+    mouse: Res<ButtonInput<MouseButton>>,
+    mut focus: Local<Option<usize>>,
+    list: Single<(&BuildingList, &mut ScrollPosition)>,
+    menu: Res<BuildMenu>,
+    buildings: Res<Assets<Building>>,
+    mut buttons: Query<(&ListRow, &mut BorderColor), With<PartButton>>,
+    new_rows: Query<(), Added<ListRow>>,
+    selected: Query<(), With<SelectedBuild>>,
+) {
+    let (list, mut scroll) = list.into_inner();
+    let available = |i: &usize| {
+        list.listed
+            .get(*i)
+            .and_then(|h| buildings.get(h))
+            .is_some_and(|b| menu.available(b))
+    };
+    let previous = *focus;
+    // the mouse takes over
+    if mouse.get_just_pressed().next().is_some() {
+        *focus = None;
+    }
+    if actions.just_pressed(Action::BuildListDown) {
+        let start = focus.map_or(0, |i| i + 1);
+        *focus = (start..list.listed.len()).find(available).or(*focus);
+    }
+    if actions.just_pressed(Action::BuildListUp) {
+        let end = focus.unwrap_or(list.listed.len());
+        *focus = (0..end).rev().find(available).or(*focus);
+    }
+    let picked = focus.is_some() && actions.just_pressed(Action::Place) && selected.is_empty();
+    if picked {
+        if let Some(handle) = focus.and_then(|i| list.listed.get(i)) {
+            commands.spawn((BuildId(handle.clone()), Name::new("building")));
+        }
+        *focus = None;
+    }
+    *focus = focus.filter(available);
+    if *focus == previous && new_rows.is_empty() {
+        return;
+    }
+    for (ListRow(i), mut border_color) in &mut buttons {
+        border_color.0 = if Some(*i) == *focus {
+            Color::WHITE
+        } else {
+            Color::BLACK
+        };
+    }
+    // keep the focused button in view
+    if let Some(i) = focus.filter(|_| *focus != previous) {
+        scroll.offset_y = (i as f32 - 2.).max(0.) * ROW_HEIGHT;
+    }
+}
+
 /// Change the button appearance when it is pressed.
 fn button_system(
     mut commands: Commands,
@@ -190,7 +693,11 @@ fn button_system(
             Interaction::Pressed => {
                 *color = PRESSED_BUTTON.into();
                 border_color.0 = RED.into();
-                commands.spawn((part_button.part_id.clone(), Name::new("building")));
+                commands.spawn((
+                    part_button.part_id.clone(),
+                    Name::new("building"),
+                    DraggedFromList,
+                ));
             }
             Interaction::Hovered => {
                 *color = HOVERED_BUTTON.into();
@@ -0,0 +1,218 @@
+use bevy::prelude::*;
+
+use crate::{build::GameId, map::Map, menu::GameState, roads::RoadGraph, script_api::ScriptEvent};
+
+/// Most vehicles on the roads at once, the dispatches past it are dropped
+const MAX_VEHICLES: usize = 200;
+/// Speed of the vehicles on a free road, in world units per second
+const VEHICLE_SPEED: f32 = 6.;
+/// Distance from the middle of the road to the lane the vehicles drive on
+const LANE_OFFSET: f32 = 0.4;
+/// Distance to the vehicle ahead under which a vehicle slows down
+const SAFE_DISTANCE: f32 = 2.5;
+/// Share of its speed a vehicle keeps behind another, so that crossings never lock up
+const MIN_SPEED_FACTOR: f32 = 0.15;
+/// Script event sending a vehicle between two buildings, with `#{ from: id, to: id }` as
+/// payload
+const HAUL_EVENT: &str = "haul";
+
+/// Vehicles driving along the roads between the entrances of two buildings, on the path
+/// found with A* over the road graph. They spawn at the building they leave, despawn at
+/// the one they reach, and slow down behind each other. Other modules send them with
+/// `DispatchVehicle`, scripts with `emit("haul", #{ from: id, to: id })`.
+pub struct VehiclePlugin;
+
+impl Plugin for VehiclePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DispatchVehicle>()
+            .add_event::<VehicleArrived>()
+            .add_systems(Startup, setup_vehicle_assets)
+            .add_systems(
+                Update,
+                (
+                    dispatch_on_script_events.before(spawn_vehicles),
+                    spawn_vehicles,
+                    drive_vehicles.after(spawn_vehicles),
+                )
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// Request to send a vehicle from a building to another
+#[derive(Event, Clone, Copy, Debug)]
+pub struct DispatchVehicle {
+    pub from: Entity,
+    pub to: Entity,
+}
+
+/// Sent when a vehicle reached the building it was sent to
+#[derive(Event, Clone, Copy, Debug)]
+pub struct VehicleArrived {
+    pub from: Entity,
+    pub to: Entity,
+}
+
+#[derive(Component)]
+pub struct Vehicle {
+    pub from: Entity,
+    pub to: Entity,
+}
+
+/// Positions to drive through, in order
+#[derive(Component)]
+pub struct FollowPath {
+    pub points: Vec<Vec2>,
+    /// Index of the point driven to
+    pub next: usize,
+    /// Position on the middle of the road
+    pub pos: Vec2,
+}
+
+#[derive(Resource)]
+struct VehicleAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+fn setup_vehicle_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(VehicleAssets {
+        mesh: meshes.add(Cuboid::new(0.9, 0.5, 0.45)),
+        material: materials.add(Color::srgb(0.75, 0.35, 0.2)),
+    });
+}
+
+fn dispatch_on_script_events(
+    mut events: EventReader<ScriptEvent>,
+    buildings: Query<(Entity, &GameId)>,
+    mut dispatches: EventWriter<DispatchVehicle>,
+) {
+    for event in events.read().filter(|event| event.name == HAUL_EVENT) {
+        let ends = event
+            .payload
+            .clone()
+            .try_cast::<rhai::Map>()
+            .and_then(|payload| {
+                let id = |key: &str| payload.get(key)?.as_int().ok();
+                Some((id("from")?, id("to")?))
+            });
+        let Some((from, to)) = ends else {
+            warn!(
+                "The {} event needs the ids of the buildings `from` and `to`",
+                HAUL_EVENT
+            );
+            continue;
+        };
+        let entity = |id: i64| {
+            buildings
+                .iter()
+                .find(|(_, gid)| gid.0 == id as u64)
+                .map(|(e, _)| e)
+        };
+        if let (Some(from), Some(to)) = (entity(from), entity(to)) {
+            dispatches.write(DispatchVehicle { from, to });
+        }
+    }
+}
+
+fn spawn_vehicles(
+    mut commands: Commands,
+    mut dispatches: EventReader<DispatchVehicle>,
+    roads: Res<RoadGraph>,
+    assets: Res<VehicleAssets>,
+    vehicles: Query<(), With<Vehicle>>,
+) {
+    let mut count = vehicles.iter().count();
+    for &DispatchVehicle { from, to } in dispatches.read() {
+        if count >= MAX_VEHICLES {
+            break;
+        }
+        let Some(points) = roads.path_between(from, to) else {
+            debug!("No road between {from} and {to}");
+            continue;
+        };
+        count += 1;
+        let start = Vec3::new(points[0].x, 0., points[0].y);
+        commands.spawn((
+            Name::new("vehicle"),
+            Vehicle { from, to },
+            FollowPath {
+                pos: points[0],
+                points,
+                next: 1,
+            },
+            Mesh3d(assets.mesh.clone()),
+            MeshMaterial3d(assets.material.clone()),
+            Transform::from_translation(start),
+            StateScoped(GameState::InGame),
+        ));
+    }
+}
+
+/// Move the vehicles along their path, slowing down behind the vehicles ahead on their
+/// lane, and despawn them at the end of their path
+fn drive_vehicles(
+    mut commands: Commands,
+    mut vehicles: Query<(Entity, &Vehicle, &mut FollowPath, &mut Transform)>,
+    map: Res<Map>,
+    roads: Res<RoadGraph>,
+    mut arrived: EventWriter<VehicleArrived>,
+    time: Res<Time>,
+) {
+    // positions and directions before moving, so that the order of the vehicles doesn't matter
+    let lanes: Vec<(Entity, Vec2, Vec2)> = vehicles
+        .iter()
+        .filter_map(|(e, _, path, _)| {
+            let dir = (*path.points.get(path.next)? - path.pos).normalize_or_zero();
+            Some((e, path.pos, dir))
+        })
+        .collect();
+    for (e, vehicle, mut path, mut transform) in &mut vehicles {
+        let Some(&target) = path.points.get(path.next) else {
+            arrived.write(VehicleArrived {
+                from: vehicle.from,
+                to: vehicle.to,
+            });
+            commands.entity(e).despawn();
+            continue;
+        };
+        let dir = (target - path.pos).normalize_or_zero();
+        let factor = lanes
+            .iter()
+            .filter(|(other, _, other_dir)| *other != e && other_dir.dot(dir) > 0.)
+            .filter_map(|(_, other_pos, _)| {
+                let offset = *other_pos - path.pos;
+                let ahead = offset.dot(dir);
+                let side = offset.perp_dot(dir).abs();
+                (ahead > 0. && side < LANE_OFFSET).then_some(ahead / SAFE_DISTANCE)
+            })
+            .fold(1., f32::min)
+            .clamp(MIN_SPEED_FACTOR, 1.);
+        let mut step = VEHICLE_SPEED * factor * time.delta_secs();
+        // move through the points reached during this frame
+        while let Some(&target) = path.points.get(path.next) {
+            let distance = path.pos.distance(target);
+            if distance > step {
+                path.pos += (target - path.pos) / distance * step;
+                break;
+            }
+            step -= distance;
+            path.pos = target;
+            path.next += 1;
+        }
+        // driving on the right of the road
+        let lane = path.pos + dir.perp() * LANE_OFFSET;
+        let pos = Vec3::new(lane.x, 0., lane.y);
+        let ground = roads
+            .road_height(lane)
+            .unwrap_or_else(|| map.get_height(pos));
+        transform.translation = pos.with_y(ground + 0.25);
+        if dir != Vec2::ZERO {
+            transform.rotation = Quat::from_rotation_y(-dir.to_angle());
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+
+/// Version of the files written before their format had a version
+pub const FIRST_VERSION: u32 = 1;
+
+/// A change of a versioned format : the file read from the version `from` is brought up to
+/// the next version by `apply`, as a generic RON value, before it is deserialized into the
+/// current format. So renamed, removed and retyped fields can be migrated. Fields added by a
+/// change can rather have serde defaults.
+pub struct Migration {
+    pub from: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut ron::Value) -> anyhow::Result<()>,
+}
+
+/// A RON file format starting with a `version` field. Files of a newer version than
+/// `VERSION` are refused, and the ones of an older version go through the `MIGRATIONS`
+/// from their version on.
+pub trait Versioned: DeserializeOwned + Sized {
+    /// Version of the format written now
+    const VERSION: u32;
+    /// Migrations from the older versions, in order
+    const MIGRATIONS: &'static [Migration];
+}
+
+/// Version of a RON file, from its `version` field
+pub fn read_version(bytes: &[u8]) -> anyhow::Result<u32> {
+    // a generic value, so that the rest of the file doesn't need to match the current format
+    let value: ron::Value = ron::de::from_bytes(bytes)?;
+    version_of(&value)
+}
+
+fn version_of(value: &ron::Value) -> anyhow::Result<u32> {
+    let ron::Value::Map(fields) = value else {
+        anyhow::bail!("expected a struct");
+    };
+    match fields.get(&ron::Value::String("version".to_string())) {
+        Some(version) => Ok(version.clone().into_rust()?),
+        None => Ok(FIRST_VERSION),
+    }
+}
+
+/// Field of a struct read as a generic RON value, for the migrations
+pub fn field_mut<'a>(value: &'a mut ron::Value, name: &str) -> Option<&'a mut ron::Value> {
+    let ron::Value::Map(fields) = value else {
+        return None;
+    };
+    fields.get_mut(&ron::Value::String(name.to_string()))
+}
+
+/// Rename a field of a struct read as a generic RON value, for the migrations
+pub fn rename_field(value: &mut ron::Value, from: &str, to: &str) -> anyhow::Result<()> {
+    let ron::Value::Map(fields) = value else {
+        anyhow::bail!("expected a struct");
+    };
+    if let Some(field) = fields.remove(&ron::Value::String(from.to_string())) {
+        fields.insert(ron::Value::String(to.to_string()), field);
+    }
+    Ok(())
+}
+
+/// Deserialize a versioned file, migrating it to the current version. `name` is used in
+/// the logs and the errors.
+pub fn from_versioned_bytes<T: Versioned>(bytes: &[u8], name: &str) -> anyhow::Result<T> {
+    let mut value: ron::Value = ron::de::from_bytes(bytes)?;
+    let version = version_of(&value)?;
+    anyhow::ensure!(
+        version <= T::VERSION,
+        "{} has the version {}, newer than the supported {}",
+        name,
+        version,
+        T::VERSION
+    );
+    if version == T::VERSION {
+        // read from the file, the generic value loses the enums
+        return Ok(ron::de::from_bytes(bytes)?);
+    }
+    for migration in T::MIGRATIONS.iter().filter(|m| m.from >= version) {
+        info!(
+            "Migrating {} from the version {} : {}",
+            name, migration.from, migration.description
+        );
+        (migration.apply)(&mut value)?;
+    }
+    Ok(value.into_rust()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Renamed {
+        version: u32,
+        name: String,
+        #[serde(default)]
+        count: u32,
+    }
+
+    impl Versioned for Renamed {
+        const VERSION: u32 = 2;
+        const MIGRATIONS: &'static [Migration] = &[Migration {
+            from: 1,
+            description: "`title` is renamed `name`",
+            apply: |value| rename_field(value, "title", "name"),
+        }];
+    }
+
+    #[test]
+    fn migrates_a_renamed_field() {
+        let bytes = include_bytes!("../tests/fixtures/renamed_field_v1.ron");
+        let data: Renamed = from_versioned_bytes(bytes, "renamed_field_v1.ron").unwrap();
+        assert_eq!(
+            data,
+            Renamed {
+                version: 1,
+                name: "Old title".to_string(),
+                count: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let bytes = b"(version: 3, name: \"Future\")";
+        assert!(from_versioned_bytes::<Renamed>(bytes, "future.ron").is_err());
+    }
+}
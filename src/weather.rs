@@ -0,0 +1,282 @@
+use std::hash::{BuildHasher, Hash, Hasher};
+
+use bevy::prelude::*;
+use foldhash::fast::FixedState;
+use rhai::Engine;
+
+use crate::{
+    CameraTarget, clock::GameClock, map::Map, menu::GameState, script_api::SharedScriptWorld,
+    sim::Sim, wind::Wind,
+};
+
+/// Game hours the weather lasts before it may change, a quarter of a day
+pub const WEATHER_HOURS: u64 = 6;
+/// Share of the wetness of the ground left after a period
+const WETNESS_DECAY: f32 = 0.5;
+/// Increase of the flow of the rivers with the ground fully wet
+const RIVER_RAIN_BOOST: f32 = 1.;
+/// Change of the rain shown per second, so that it fades in and out
+const RAIN_FADE_SPEED: f32 = 0.2;
+/// Raindrops falling around the camera target in the heaviest rain
+const MAX_DROPS: usize = 400;
+/// Raindrops started per frame at most, so that the rain starts gradually
+const DROPS_PER_FRAME: usize = 20;
+/// Half size of the area around the camera target the rain falls on, in world units
+const RAIN_AREA: f32 = 25.;
+/// Height above the camera target the raindrops start from, in world units
+const RAIN_HEIGHT: f32 = 20.;
+/// Falling speed of the raindrops, in world units per second
+const RAIN_SPEED: f32 = 25.;
+
+/// Weather following the game clock : every `WEATHER_HOURS`, it may change between clear,
+/// rain and storm. It depends only on the seed of the world and the tick, so replays and
+/// loaded saves get the same weather. Rain darkens the light, thickens the fog and wets the
+/// ground, which raises the flow of the rivers seen by the scripts with `get_flow`. Scripts
+/// read it with `weather()`, `wetness()` and `river_boost()`.
+pub struct WeatherPlugin;
+
+impl Plugin for WeatherPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Weather::default())
+            .add_systems(Startup, setup_rain_assets)
+            .add_systems(OnExit(GameState::InGame), clear_weather)
+            .add_systems(
+                Update,
+                (
+                    advance_weather,
+                    fade_rain.after(advance_weather),
+                    (spawn_raindrops, fall_raindrops).after(fade_rain),
+                )
+                    .run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum WeatherKind {
+    #[default]
+    Clear,
+    Rain,
+    Storm,
+}
+
+impl WeatherKind {
+    /// How much it rains, between 0 and 1
+    pub fn rain(self) -> f32 {
+        match self {
+            WeatherKind::Clear => 0.,
+            WeatherKind::Rain => 0.6,
+            WeatherKind::Storm => 1.,
+        }
+    }
+
+    /// Weather of the next period, from a random roll in [0, 1)
+    fn next(self, roll: f32) -> Self {
+        let (clear, rain) = match self {
+            WeatherKind::Clear => (0.75, 0.95),
+            WeatherKind::Rain => (0.4, 0.85),
+            WeatherKind::Storm => (0.1, 0.7),
+        };
+        if roll < clear {
+            WeatherKind::Clear
+        } else if roll < rain {
+            WeatherKind::Rain
+        } else {
+            WeatherKind::Storm
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WeatherKind::Clear => "clear",
+            WeatherKind::Rain => "rain",
+            WeatherKind::Storm => "storm",
+        }
+    }
+}
+
+/// The weather of a period of the sim
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct WeatherState {
+    pub kind: WeatherKind,
+    /// How wet the ground is from the recent rain, between 0 and 1
+    pub wetness: f32,
+}
+
+impl WeatherState {
+    /// Weather of a period, going through the periods before it
+    pub fn at_period(seed: u32, period: u64) -> Self {
+        (1..=period).fold(Self::default(), |state, p| state.next(seed, p))
+    }
+
+    /// Weather of the period after this one
+    fn next(self, seed: u32, period: u64) -> Self {
+        let mut h = FixedState::default().build_hasher();
+        (seed, period).hash(&mut h);
+        let roll = (h.finish() >> 40) as f32 / (1u64 << 24) as f32;
+        let kind = self.kind.next(roll);
+        Self {
+            kind,
+            wetness: self.wetness * WETNESS_DECAY + kind.rain() * (1. - WETNESS_DECAY),
+        }
+    }
+
+    /// Multiplier of the flow of the rivers from the wetness of the ground
+    pub fn river_boost(&self) -> f32 {
+        1. + RIVER_RAIN_BOOST * self.wetness
+    }
+}
+
+/// Current weather
+#[derive(Resource, Default)]
+pub struct Weather {
+    pub state: WeatherState,
+    /// Rain shown, fading towards the rain of the current weather, between 0 and 1
+    pub rain: f32,
+    /// Period and seed the state was computed for
+    period: u64,
+    seed: u32,
+}
+
+fn clear_weather(mut weather: ResMut<Weather>) {
+    *weather = Weather::default();
+}
+
+/// Follow the sim ticks, stepping to the next period or computing the weather again after
+/// a jump, like loading a save
+fn advance_weather(
+    sim: Res<Sim>,
+    map: Res<Map>,
+    mut weather: ResMut<Weather>,
+    mut script_weather: Local<WeatherState>,
+) {
+    let period = GameClock::minutes_at(sim.tick) / (WEATHER_HOURS * 60);
+    let seed = map.worldgen.seed;
+    if period != weather.period || seed != weather.seed {
+        weather.state = if period == weather.period + 1 && seed == weather.seed {
+            weather.state.next(seed, period)
+        } else {
+            WeatherState::at_period(seed, period)
+        };
+        weather.period = period;
+        weather.seed = seed;
+    }
+    if *script_weather != weather.state {
+        *script_weather = weather.state;
+        sim.script_world.0.lock().unwrap().weather = weather.state;
+    }
+}
+
+fn fade_rain(mut weather: ResMut<Weather>, time: Res<Time>) {
+    let target = weather.state.kind.rain();
+    let step = RAIN_FADE_SPEED * time.delta_secs();
+    let rain = weather.rain + (target - weather.rain).clamp(-step, step);
+    if rain != weather.rain {
+        weather.rain = rain;
+    }
+}
+
+/// Register `weather()`, one of "clear", "rain" or "storm", `wetness()` of the ground
+/// between 0 and 1, and `river_boost()`, the multiplier of the flow of the rivers
+pub fn register_weather_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("weather", move || -> String {
+        w.0.lock().unwrap().weather.kind.name().to_string()
+    });
+
+    let w = world.clone();
+    engine.register_fn("wetness", move || -> f64 {
+        w.0.lock().unwrap().weather.wetness as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("river_boost", move || -> f64 {
+        w.0.lock().unwrap().weather.river_boost() as f64
+    });
+}
+
+#[derive(Resource)]
+struct RainAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+#[derive(Component)]
+struct Raindrop;
+
+fn setup_rain_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.insert_resource(RainAssets {
+        mesh: meshes.add(Cuboid::new(0.02, 0.6, 0.02)),
+        material: materials.add(StandardMaterial {
+            base_color: Color::srgba(0.7, 0.75, 0.85, 0.35),
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..default()
+        }),
+    });
+}
+
+/// Start raindrops above the camera target, as many as the rain asks for
+fn spawn_raindrops(
+    mut commands: Commands,
+    weather: Res<Weather>,
+    assets: Res<RainAssets>,
+    camera: Single<&CameraTarget, With<Camera>>,
+    drops: Query<(), With<Raindrop>>,
+) {
+    let wanted = (MAX_DROPS as f32 * weather.rain) as usize;
+    let missing = wanted.saturating_sub(drops.iter().count());
+    for _ in 0..missing.min(DROPS_PER_FRAME) {
+        let offset = Vec3::new(
+            rand::random_range(-RAIN_AREA..RAIN_AREA),
+            RAIN_HEIGHT * rand::random_range(0.5..=1.),
+            rand::random_range(-RAIN_AREA..RAIN_AREA),
+        );
+        commands.spawn((
+            Name::new("raindrop"),
+            Raindrop,
+            Mesh3d(assets.mesh.clone()),
+            MeshMaterial3d(assets.material.clone()),
+            Transform::from_translation(camera.pos + offset),
+            StateScoped(GameState::InGame),
+        ));
+    }
+}
+
+/// Move the raindrops down, slanted by the wind, and put them back up once they
+/// reach the ground, or remove them if the rain weakened
+fn fall_raindrops(
+    mut commands: Commands,
+    weather: Res<Weather>,
+    wind: Res<Wind>,
+    map: Res<Map>,
+    camera: Single<&CameraTarget, With<Camera>>,
+    mut drops: Query<(Entity, &mut Transform), With<Raindrop>>,
+    time: Res<Time>,
+) {
+    let velocity = Vec3::NEG_Y * RAIN_SPEED + wind.velocity;
+    let wanted = (MAX_DROPS as f32 * weather.rain) as usize;
+    let mut count = drops.iter().count();
+    for (e, mut transform) in &mut drops {
+        transform.translation += velocity * time.delta_secs();
+        transform.rotation = Quat::from_rotation_arc(Vec3::NEG_Y, velocity.normalize());
+        if transform.translation.y > map.get_height(transform.translation) {
+            continue;
+        }
+        if count > wanted {
+            commands.entity(e).despawn();
+            count -= 1;
+            continue;
+        }
+        transform.translation = camera.pos
+            + Vec3::new(
+                rand::random_range(-RAIN_AREA..RAIN_AREA),
+                RAIN_HEIGHT,
+                rand::random_range(-RAIN_AREA..RAIN_AREA),
+            );
+    }
+}
@@ -0,0 +1,145 @@
+use std::hash::{BuildHasher, Hash, Hasher};
+
+use bevy::prelude::*;
+use foldhash::fast::FixedState;
+use rhai::Engine;
+
+use crate::{
+    map::Map,
+    menu::GameState,
+    script_api::SharedScriptWorld,
+    sim::Sim,
+    weather::{Weather, WeatherKind},
+};
+
+/// Number of sim ticks between two random values of the noise of the direction
+const DIRECTION_KNOT: u64 = 1200;
+/// Number of sim ticks between two random values of the noise of the strength
+const STRENGTH_KNOT: u64 = 300;
+/// Largest angle between the wind and the prevailing wind, in radians
+const DIRECTION_SWING: f32 = std::f32::consts::PI;
+/// Strength added by the rain and the storms
+const RAIN_GUST: f32 = 0.1;
+const STORM_GUST: f32 = 0.4;
+/// Speed of the wind at full strength, in world units per second
+pub const MAX_WIND_SPEED: f32 = 8.;
+/// Change of the wind shown per second, so that the gusts of the weather ease in
+const WIND_EASE_SPEED: f32 = 1.5;
+
+/// Wind blowing over the whole world. Its direction and strength follow a smooth noise of
+/// the sim ticks, seeded by the world, with gusts during rain and storms, so replays and
+/// loaded saves get the same wind. Scripts read it with `wind_strength()` and
+/// `wind_direction()`, and the particles and the rain drift with it.
+pub struct WindPlugin;
+
+impl Plugin for WindPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Wind::default())
+            .add_systems(OnExit(GameState::InGame), clear_wind)
+            .add_systems(
+                Update,
+                (advance_wind, ease_wind.after(advance_wind)).run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+/// The wind at a sim tick
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct WindState {
+    /// Angle the wind blows towards, in radians from the x axis towards the z axis
+    pub direction: f32,
+    /// Between 0 and 1
+    pub strength: f32,
+}
+
+impl WindState {
+    pub fn at_tick(seed: u32, tick: u64, weather: WeatherKind) -> Self {
+        let gust = match weather {
+            WeatherKind::Clear => 0.,
+            WeatherKind::Rain => RAIN_GUST,
+            WeatherKind::Storm => STORM_GUST,
+        };
+        Self {
+            // swinging around the prevailing wind of the world
+            direction: std::f32::consts::TAU * smooth_noise(seed, 0, 0, 1)
+                + DIRECTION_SWING * (2. * smooth_noise(seed, 1, tick, DIRECTION_KNOT) - 1.),
+            strength: (0.15 + 0.6 * smooth_noise(seed, 2, tick, STRENGTH_KNOT) + gust).min(1.),
+        }
+    }
+
+    /// Horizontal direction the wind blows towards
+    pub fn direction_vec(&self) -> Vec2 {
+        Vec2::from_angle(self.direction)
+    }
+
+    /// Velocity of the air, in world units per second
+    pub fn velocity(&self) -> Vec3 {
+        let d = self.direction_vec() * self.strength * MAX_WIND_SPEED;
+        Vec3::new(d.x, 0., d.y)
+    }
+}
+
+/// Value noise between 0 and 1 : random values every `knot` ticks, smoothly interpolated
+fn smooth_noise(seed: u32, salt: u32, tick: u64, knot: u64) -> f32 {
+    let value = |k: u64| {
+        let mut h = FixedState::default().build_hasher();
+        (seed, salt, k).hash(&mut h);
+        (h.finish() >> 40) as f32 / (1u64 << 24) as f32
+    };
+    let k = tick / knot;
+    let t = (tick % knot) as f32 / knot as f32;
+    let t = t * t * (3. - 2. * t);
+    value(k).lerp(value(k + 1), t)
+}
+
+/// Current wind
+#[derive(Resource, Default)]
+pub struct Wind {
+    pub state: WindState,
+    /// Velocity of the air shown, easing towards the one of the current state
+    pub velocity: Vec3,
+}
+
+fn clear_wind(mut wind: ResMut<Wind>) {
+    *wind = Wind::default();
+}
+
+fn advance_wind(
+    sim: Res<Sim>,
+    map: Res<Map>,
+    weather: Res<Weather>,
+    mut wind: ResMut<Wind>,
+    mut script_wind: Local<WindState>,
+) {
+    let state = WindState::at_tick(map.worldgen.seed, sim.tick, weather.state.kind);
+    if state != wind.state {
+        wind.state = state;
+    }
+    if *script_wind != state {
+        *script_wind = state;
+        sim.script_world.0.lock().unwrap().wind = state;
+    }
+}
+
+fn ease_wind(mut wind: ResMut<Wind>, time: Res<Time>) {
+    let target = wind.state.velocity();
+    let step = WIND_EASE_SPEED * MAX_WIND_SPEED * time.delta_secs();
+    let velocity = wind.velocity + (target - wind.velocity).clamp_length_max(step);
+    if velocity != wind.velocity {
+        wind.velocity = velocity;
+    }
+}
+
+/// Register `wind_strength()`, between 0 and 1, and `wind_direction()`, the angle the wind
+/// blows towards in radians
+pub fn register_wind_api(engine: &mut Engine, world: &SharedScriptWorld) {
+    let w = world.clone();
+    engine.register_fn("wind_strength", move || -> f64 {
+        w.0.lock().unwrap().wind.strength as f64
+    });
+
+    let w = world.clone();
+    engine.register_fn("wind_direction", move || -> f64 {
+        w.0.lock().unwrap().wind.direction as f64
+    });
+}
@@ -0,0 +1,297 @@
+use bevy::{
+    math::Affine2,
+    pbr::decal::{ForwardDecal, ForwardDecalMaterial, ForwardDecalMaterialExt},
+    prelude::*,
+    render::primitives::Aabb,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build::{BuildId, Building, BuildingType, GameIds, SelectedBuild, SpawnBuilding, footprint},
+    canals::Irrigation,
+    extraction::river_beside,
+    land_value::value_around_buildings,
+    map::Map,
+    menu::GameState,
+    palettes::{HATCH_TILE, HatchImages},
+    pause_menu::Settings,
+    plan::Planned,
+    sim::{Sim, sim_running},
+};
+
+/// Sim ticks between two growth attempts in each zone
+const GROWTH_TICKS: u64 = 20;
+/// Chance a zone grows a building at each attempt, on worthless land
+const GROWTH_CHANCE: f64 = 0.2;
+/// Distance from a river within which the ground is wet, in world units
+const WET_REACH: f32 = 6.;
+/// Height of the box the zones are projected on the ground from, in world units
+const OVERLAY_HEIGHT: f32 = 40.;
+
+/// Zones drawn by the player, where buildings grow by themselves. Zone assets (residential,
+/// industrial, farmland) give their growth rules in their `.bconf` : the slope and the
+/// wetness of the ground the buildings grow on, and density tiers, each a pool of buildings
+/// growing once the value of the land reaches it. Every few ticks, each zone may grow a
+/// building of its tier at a random free spot, faster on valuable land. The zones are drawn on
+/// the ground in their color.
+pub struct ZonePlugin;
+
+impl Plugin for ZonePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            FixedUpdate,
+            grow_zones.after(value_around_buildings).run_if(sim_running),
+        )
+        .add_systems(
+            Update,
+            (register_zones, draw_zones.after(register_zones)).run_if(in_state(GameState::InGame)),
+        );
+    }
+}
+
+/// What grows in a zone, and where
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ZoneRules {
+    /// Steepest slope the buildings grow on, in height per world unit
+    pub max_slope: f32,
+    /// Driest and wettest ground the buildings grow on, between 0 and 1
+    pub wetness: (f32, f32),
+    /// Density tiers, from the sparsest
+    pub tiers: Vec<ZoneTier>,
+    /// Opacity of the zone drawn on the ground
+    pub overlay: f32,
+}
+
+impl Default for ZoneRules {
+    fn default() -> Self {
+        Self {
+            max_slope: 0.5,
+            wetness: (0., 1.),
+            tiers: Vec::new(),
+            overlay: 0.3,
+        }
+    }
+}
+
+/// Buildings growing in a zone from a value of the land
+#[derive(Deserialize, Clone, Debug)]
+pub struct ZoneTier {
+    /// Value of the land from which the tier grows, between 0 and 1
+    #[serde(default)]
+    pub min_value: f32,
+    /// Names of the buildings growing, as in their asset files
+    pub buildings: Vec<String>,
+}
+
+impl ZoneRules {
+    /// Densest tier the value of the land reaches
+    fn tier(&self, value: f32) -> Option<&ZoneTier> {
+        self.tiers.iter().rev().find(|tier| tier.min_value <= value)
+    }
+}
+
+/// An area zoned by the player
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ZoneArea {
+    /// Name of the zone, as in its asset file
+    pub zone: String,
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+/// The zoned areas, shared with the scripts and saved with the sim
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct Zoning {
+    pub zones: Vec<ZoneArea>,
+}
+
+/// Index of a zone among the zone assets, sorted by name, for its color in the palettes and
+/// its hatch pattern
+pub fn zone_index(buildings: &Assets<Building>, name: &str) -> usize {
+    buildings
+        .iter()
+        .filter(|(_, b)| matches!(b.typ, BuildingType::Zone { .. }) && b.name.as_str() < name)
+        .count()
+}
+
+/// Wetness of the ground, between 0 and 1 : wet by the rivers, or as irrigated by the canals
+fn ground_wetness(map: &Map, irrigation: &Irrigation, pos: Vec2) -> f32 {
+    if river_beside(&map.continent, (pos, pos), WET_REACH).is_some() {
+        1.
+    } else {
+        irrigation.get(pos)
+    }
+}
+
+/// Slope of the ground over a footprint, in height per world unit
+fn slope(map: &Map, (min, max): (Vec2, Vec2)) -> f32 {
+    let h = |x: f32, z: f32| map.get_height(Vec3::new(x, 0., z));
+    let size = (max - min).max(Vec2::splat(0.1));
+    let center = (min + max) / 2.;
+    Vec2::new(
+        (h(max.x, center.y) - h(min.x, center.y)) / size.x,
+        (h(center.x, max.y) - h(center.x, min.y)) / size.y,
+    )
+    .length()
+}
+
+/// Record the zones placed by the player, in place of their outline
+fn register_zones(
+    mut commands: Commands,
+    placed: Query<
+        (Entity, &BuildId, &Transform, &Aabb),
+        (Without<SelectedBuild>, Without<Planned>),
+    >,
+    buildings: Res<Assets<Building>>,
+    sim: Res<Sim>,
+) {
+    for (e, bid, transform, aabb) in &placed {
+        let Some(building) = buildings.get(&bid.0) else {
+            continue;
+        };
+        if !matches!(building.typ, BuildingType::Zone { .. }) {
+            continue;
+        }
+        let (min, max) = footprint(transform, aabb);
+        let mut world = sim.script_world.0.lock().unwrap();
+        world.zoning.zones.push(ZoneArea {
+            zone: building.name.clone(),
+            min,
+            max,
+        });
+        commands.entity(e).despawn();
+    }
+}
+
+/// Grow a building in the zones now and then, where the ground suits them
+fn grow_zones(
+    sim: Res<Sim>,
+    map: Res<Map>,
+    buildings: Res<Assets<Building>>,
+    game_ids: Res<GameIds>,
+    mut spawns: EventWriter<SpawnBuilding>,
+) {
+    if sim.tick % GROWTH_TICKS != 0 {
+        return;
+    }
+    let world = sim.script_world.0.lock().unwrap();
+    let by_name = |name: &str| buildings.iter().map(|(_, b)| b).find(|b| b.name == name);
+    for area in &world.zoning.zones {
+        let Some(BuildingType::Zone { rules, .. }) = by_name(&area.zone).map(|b| &b.typ) else {
+            continue;
+        };
+        let speed = world.land_value.growth_speed((area.min + area.max) / 2.);
+        if sim.rng.f64() >= GROWTH_CHANCE * speed as f64 {
+            continue;
+        }
+        let size = area.max - area.min;
+        let pos = area.min + size * Vec2::new(sim.rng.f64() as f32, sim.rng.f64() as f32);
+        let Some(tier) = rules.tier(world.land_value.get(pos)) else {
+            continue;
+        };
+        if tier.buildings.is_empty() {
+            continue;
+        }
+        let i = (sim.rng.f64() * tier.buildings.len() as f64) as usize;
+        let name = &tier.buildings[i.min(tier.buildings.len() - 1)];
+        let Some(grown) = by_name(name) else {
+            warn!("Zone {} grows the unknown building {name}", area.zone);
+            continue;
+        };
+        let half_size = Vec2::new(grown.size.0 as f32, grown.size.1 as f32) / 2.;
+        // kept inside the zone
+        let pos = pos.clamp(
+            area.min + half_size,
+            (area.max - half_size).max(area.min + half_size),
+        );
+        let footprint = (pos - half_size, pos + half_size);
+        let wetness = ground_wetness(&map, &world.irrigation, pos);
+        if footprint.0.cmplt(area.min).any()
+            || footprint.1.cmpgt(area.max).any()
+            || map.continent.is_sea(pos.extend(0.).xzy())
+            || !map.is_area_free(footprint)
+            || slope(&map, footprint) > rules.max_slope
+            || !(rules.wetness.0..=rules.wetness.1).contains(&wetness)
+            || !grown.placement.allows(&map, footprint)
+        {
+            continue;
+        }
+        spawns.write(SpawnBuilding {
+            id: game_ids.next(),
+            name: name.clone(),
+            pos,
+            rotation: 0.,
+        });
+    }
+}
+
+/// Zone drawn on the ground
+#[derive(Component)]
+struct ZoneOverlay;
+
+/// Draw the zones on the ground again when they change, or the palette
+fn draw_zones(
+    mut commands: Commands,
+    sim: Res<Sim>,
+    map: Res<Map>,
+    drawn: Query<Entity, With<ZoneOverlay>>,
+    mut shown: Local<Vec<ZoneArea>>,
+    buildings: Res<Assets<Building>>,
+    mut materials: ResMut<Assets<ForwardDecalMaterial<StandardMaterial>>>,
+    settings: Res<Settings>,
+    hatches: Res<HatchImages>,
+) {
+    let zones = sim.script_world.0.lock().unwrap().zoning.zones.clone();
+    if zones == *shown && !settings.is_changed() {
+        return;
+    }
+    for e in &drawn {
+        commands.entity(e).despawn();
+    }
+    for area in &zones {
+        let Some((color, rules)) = buildings.iter().find_map(|(_, b)| match &b.typ {
+            BuildingType::Zone { color, rules } if b.name == area.zone => Some((*color, rules)),
+            _ => None,
+        }) else {
+            continue;
+        };
+        let center = (area.min + area.max) / 2.;
+        let size = area.max - area.min;
+        let height = map.get_height(center.extend(0.).xzy());
+        let i = zone_index(&buildings, &area.zone);
+        let color = settings.palette.color(i, color).with_alpha(rules.overlay);
+        // the hatch is tiled over the zone, and stands out more than the flat color
+        let (texture, color, uv_transform) = if settings.hatch_patterns {
+            let tiles = Affine2::from_scale(size / HATCH_TILE);
+            let alpha = (rules.overlay * 2.).min(1.);
+            (Some(hatches.tiled(i)), color.with_alpha(alpha), tiles)
+        } else {
+            (None, color, Affine2::IDENTITY)
+        };
+        commands.spawn((
+            Name::new("zone"),
+            ZoneOverlay,
+            ForwardDecal,
+            MeshMaterial3d(materials.add(ForwardDecalMaterial {
+                base: StandardMaterial {
+                    base_color: color,
+                    base_color_texture: texture,
+                    uv_transform,
+                    alpha_mode: AlphaMode::Blend,
+                    ..default()
+                },
+                extension: ForwardDecalMaterialExt {
+                    depth_fade_factor: 1.0,
+                },
+            })),
+            Transform::from_translation(center.extend(height).xzy()).with_scale(Vec3::new(
+                size.x,
+                OVERLAY_HEIGHT,
+                size.y,
+            )),
+            StateScoped(GameState::InGame),
+        ));
+    }
+    *shown = zones;
+}